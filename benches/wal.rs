@@ -0,0 +1,194 @@
+//! Throughput and latency of the WAL, run with `cargo bench`
+//!
+//! Every benchmark runs once per payload size, 64 bytes and 1KB by default, or the sizes listed
+//! in `WALCRAFT_BENCH_PAYLOADS`, e.g. `WALCRAFT_BENCH_PAYLOADS=16,4096 cargo bench`. Each
+//! iteration writes to a fresh WAL under `./tmp/bench/` and waits for its logs to be flushed, so
+//! the numbers include the writer thread and the file system.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::time::{Duration, Instant};
+use walcraft::{Wal, WalBuilder};
+
+// logs written by an iteration
+const LOGS: usize = 10_000;
+
+// logs of a batch in `batch_write`
+const BATCH: usize = 100;
+
+// logs in the WAL reopened by `recovery`
+const RECOVERED: usize = 100_000;
+
+// storage of a WAL, large enough to never drop logs during an iteration
+const CAPACITY: usize = 1 << 30;
+
+fn payload_sizes() -> Vec<usize> {
+    std::env::var("WALCRAFT_BENCH_PAYLOADS")
+        .ok()
+        .map(|sizes| {
+            sizes
+                .split(',')
+                .map(|size| size.trim().parse().expect("payload size in bytes"))
+                .collect()
+        })
+        .unwrap_or_else(|| vec![64, 1024])
+}
+
+// empty WAL of byte payloads, in a directory of its own
+fn fresh_wal(name: &str) -> Wal<Vec<u8>> {
+    let dir = format!("./tmp/bench/{}/", name);
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    WalBuilder::new(&dir, CAPACITY).build().unwrap()
+}
+
+fn single_thread(c: &mut Criterion) {
+    let mut group = c.benchmark_group("write");
+    for size in payload_sizes() {
+        group.throughput(Throughput::Bytes((LOGS * size) as u64));
+        group.bench_with_input(
+            BenchmarkId::new("single_thread", size),
+            &size,
+            |b, &size| {
+                b.iter_custom(|iters| {
+                    let mut elapsed = Duration::ZERO;
+                    for _ in 0..iters {
+                        let wal = fresh_wal("single_thread");
+                        let start = Instant::now();
+                        for _ in 0..LOGS {
+                            wal.try_write(&vec![7u8; size]).unwrap();
+                        }
+                        wal.flush().unwrap();
+                        elapsed += start.elapsed();
+                    }
+                    elapsed
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+fn contention(c: &mut Criterion) {
+    let mut group = c.benchmark_group("contention");
+    for size in payload_sizes() {
+        group.throughput(Throughput::Bytes((LOGS * size) as u64));
+        for threads in [2, 4, 8, 16] {
+            let id = BenchmarkId::new(format!("{}_threads", threads), size);
+            group.bench_with_input(id, &size, |b, &size| {
+                b.iter_custom(|iters| {
+                    let mut elapsed = Duration::ZERO;
+                    for _ in 0..iters {
+                        let wal = fresh_wal("contention");
+                        let start = Instant::now();
+                        std::thread::scope(|scope| {
+                            for _ in 0..threads {
+                                scope.spawn(|| {
+                                    for _ in 0..LOGS / threads {
+                                        wal.try_write(&vec![7u8; size]).unwrap();
+                                    }
+                                });
+                            }
+                        });
+                        wal.flush().unwrap();
+                        elapsed += start.elapsed();
+                    }
+                    elapsed
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+fn batch_write(c: &mut Criterion) {
+    let mut group = c.benchmark_group("write");
+    for size in payload_sizes() {
+        group.throughput(Throughput::Bytes((LOGS * size) as u64));
+        group.bench_with_input(BenchmarkId::new("batch_write", size), &size, |b, &size| {
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::ZERO;
+                for _ in 0..iters {
+                    let wal = fresh_wal("batch_write");
+                    let start = Instant::now();
+                    for _ in 0..LOGS / BATCH {
+                        wal.batch_write(vec![vec![7u8; size]; BATCH]).unwrap();
+                    }
+                    wal.flush().unwrap();
+                    elapsed += start.elapsed();
+                }
+                elapsed
+            })
+        });
+    }
+    group.finish();
+}
+
+// logs written by a thread while another reads the WAL over and over
+fn read_while_write(c: &mut Criterion) {
+    let mut group = c.benchmark_group("read_while_write");
+    for size in payload_sizes() {
+        group.throughput(Throughput::Bytes((LOGS * size) as u64));
+        group.bench_with_input(BenchmarkId::new("write", size), &size, |b, &size| {
+            b.iter_custom(|iters| {
+                let mut elapsed = Duration::ZERO;
+                for _ in 0..iters {
+                    let wal = fresh_wal("read_while_write");
+                    let start = Instant::now();
+                    std::thread::scope(|scope| {
+                        let writer = scope.spawn(|| {
+                            for _ in 0..LOGS {
+                                wal.try_write(&vec![7u8; size]).unwrap();
+                            }
+                            wal.flush().unwrap();
+                        });
+                        while !writer.is_finished() {
+                            criterion::black_box(wal.read().unwrap());
+                        }
+                    });
+                    elapsed += start.elapsed();
+                }
+                elapsed
+            })
+        });
+    }
+    group.finish();
+}
+
+// opening a WAL of RECOVERED logs and reading them back, as done after a restart
+fn recovery(c: &mut Criterion) {
+    let mut group = c.benchmark_group("recovery");
+    group.sample_size(10);
+    for size in payload_sizes() {
+        let dir = format!("./tmp/bench/recovery_{}/", size);
+        {
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            let wal = Wal::<Vec<u8>>::new(&dir, CAPACITY).unwrap();
+            for _ in 0..RECOVERED / BATCH {
+                wal.batch_write(vec![vec![7u8; size]; BATCH]).unwrap();
+            }
+            wal.flush().unwrap();
+        }
+        group.throughput(Throughput::Bytes((RECOVERED * size) as u64));
+        group.bench_with_input(BenchmarkId::new("open_and_read", size), &dir, |b, dir| {
+            b.iter(|| {
+                let wal = WalBuilder::<Vec<u8>>::new(dir, CAPACITY)
+                    .read_only(true)
+                    .build()
+                    .unwrap();
+                assert_eq!(wal.read().unwrap().len(), RECOVERED);
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    single_thread,
+    contention,
+    batch_write,
+    read_while_write,
+    recovery
+);
+criterion_main!(benches);
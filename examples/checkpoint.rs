@@ -0,0 +1,91 @@
+//! Periodic checkpoint and truncation
+//!
+//! A counter of page views is rebuilt from the WAL. Every few views, a snapshot of the counters
+//! is saved along with the sequence number of the last log it covers, and the WAL is truncated
+//! up to it. On startup, the snapshot is loaded and only the logs written after it are replayed.
+//!
+//! Run with `cargo run --example checkpoint`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use walcraft::{Lsn, Wal, WalError};
+
+#[derive(Serialize, Deserialize, Debug)]
+struct View {
+    page: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+struct Snapshot {
+    // logs up to this sequence number are covered
+    through: Option<Lsn>,
+    views: BTreeMap<String, u64>,
+}
+
+impl Snapshot {
+    fn load(path: &Path) -> Snapshot {
+        std::fs::read(path)
+            .ok()
+            .and_then(|data| bincode::deserialize(&data).ok())
+            .unwrap_or_default()
+    }
+
+    // replace the snapshot atomically, so a crash leaves either the old or the new one
+    fn save(&self, path: &Path) -> std::io::Result<()> {
+        let data = bincode::serialize(self).expect("Failed to serialize snapshot");
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, data)?;
+        std::fs::rename(&tmp, path)
+    }
+}
+
+// load the snapshot and replay the logs written after it
+fn open(dir: &str, snapshot: &Path) -> Result<(Wal<View>, Snapshot), WalError> {
+    let wal: Wal<View> = Wal::new(dir, 40_000)?;
+    let mut state = Snapshot::load(snapshot);
+    let since = state.through.map(|lsn| lsn + 1);
+    for view in wal.entries_since(since)? {
+        *state.views.entry(view.page).or_default() += 1;
+    }
+    Ok((wal, state))
+}
+
+fn main() -> Result<(), WalError> {
+    let dir = "./tmp/example_checkpoint/";
+    let _ = std::fs::remove_dir_all(dir);
+    std::fs::create_dir_all(dir).expect("Failed to create WAL directory");
+    let snapshot = Path::new(dir).join("snapshot");
+
+    let (wal, mut state) = open(dir, &snapshot)?;
+    let pages = ["/", "/pricing", "/docs", "/"];
+    for i in 0..2000 {
+        let view = View {
+            page: pages[i % pages.len()].to_string(),
+        };
+        let lsn = wal.try_write(&view)?;
+        *state.views.entry(view.page).or_default() += 1;
+        state.through = Some(lsn);
+
+        // checkpoint loop: save the state, then drop the logs it covers
+        if i % 500 == 499 {
+            state.save(&snapshot)?;
+            let removed = wal.truncate(lsn)?;
+            println!("checkpoint through {}, {} files removed", lsn, removed);
+        }
+    }
+    // a few more views after the last checkpoint, only found in the WAL
+    for page in ["/docs", "/blog"] {
+        let view = View {
+            page: page.to_string(),
+        };
+        wal.try_write(&view)?;
+        *state.views.entry(view.page).or_default() += 1;
+    }
+    wal.close()?;
+
+    let (_wal, restored) = open(dir, &snapshot)?;
+    println!("restored views: {:?}", restored.views);
+    assert_eq!(restored.views, state.views);
+    Ok(())
+}
@@ -0,0 +1,156 @@
+//! Every option of [WalBuilder], with the values a typical service would pick
+//!
+//! Run with `cargo run --example configuration --all-features`.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use walcraft::{
+    Backpressure, EventListener, ForkBehavior, Framing, ManifestKind, OpenVerification, Overflow,
+    Pacing, RateLimit, Rejection, RotationEvent, SyncPolicy, Validation, WakeStrategy, Wal,
+    WalBuilder, WalError,
+};
+
+// Sends rotations to the logs of the service
+struct Logger;
+
+impl EventListener for Logger {
+    fn on_rotate(&self, rotation: &RotationEvent) {
+        println!("moved from wal_{} to wal_{}", rotation.from, rotation.to);
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Order {
+    account: u32,
+    amount: i64,
+    note: String,
+}
+
+fn main() -> Result<(), WalError> {
+    let dir = "./tmp/example_configuration/";
+    let _ = std::fs::remove_dir_all(dir);
+    std::fs::create_dir_all(dir).expect("Failed to create WAL directory");
+
+    let builder = WalBuilder::new(dir, 10_000)
+        // writing
+        .positional_writes(true)
+        .wake_strategy(WakeStrategy::MicroBatch(Duration::from_micros(200)))
+        .sync_policy(SyncPolicy::EveryNBytes(64 * 1024))
+        .strict(Validation::new().max_payload_bytes(4096).schema_version(1))
+        .framing(Framing::Native)
+        .manifest(ManifestKind::PerSegment)
+        .segments(6)
+        .segment_size(2048)
+        .retain_segments(4)
+        .file_prefix("orders_")
+        .segment_window(Duration::from_secs(600), 4096, 1 << 20)
+        .spawner(|task| std::thread::Builder::new().spawn(task).map(|_| ()))
+        .on_fork(ForkBehavior::ReadOnly)
+        .exclusive(true)
+        .duplicate_guard(true)
+        .pacing(Pacing::new().bytes_per_sec(20 << 20).cpu_percent(50))
+        .rate_limit(RateLimit::new(10_000).wait_up_to(Duration::from_millis(5)))
+        // memory
+        .memory_budget(1 << 20)
+        .backpressure(
+            Backpressure::new()
+                .max_logs(10_000)
+                .overflow(Overflow::Block),
+        )
+        .recent_cache(100, 64 * 1024)
+        .read_memory_cap(256 * 1024)
+        .read_cache(1 << 20)
+        .read_scratch_budget(32 * 1024)
+        .parallel_decode(2, 1 << 20)
+        // logs written before notes were added
+        .decode_fallback(|(account, amount): (u32, i64)| Order {
+            account,
+            amount,
+            note: String::new(),
+        })
+        // integrity
+        .verify_on_rotation(true)
+        .scrub_interval(Duration::from_secs(60))
+        .verify_on_open(OpenVerification::Deferred { block_reads: true })
+        .on_evict(|eviction| println!("wal_{} is reused", eviction.id))
+        .event_listener(Logger)
+        .on_error(|failure| eprintln!("{:?} failed: {}", failure.operation, failure.message))
+        .on_seal(|sealed| println!("wal_{} is sealed", sealed.digest.id))
+        .checkpointer(|lsns: std::ops::RangeInclusive<u64>| {
+            println!("snapshot through log {}", lsns.end());
+            Ok(())
+        })
+        .capacity_warnings(&[0.8, 0.95], |warning| println!("{:?}", warning))
+        // keyed workloads
+        .keyed(|order: &Order| order.account)
+        .merge(|old: Order, new: Order| Order {
+            amount: old.amount + new.amount,
+            ..new
+        })
+        .compaction_threshold(0.5)
+        .dedup_above(1024)
+        // overridden by `self_describing` below
+        .serialization_codec(walcraft::SerializationCodec::Bincode);
+    #[cfg(feature = "lz4")]
+    let builder = builder
+        .compress_above(512)
+        .compression_codec(walcraft::CompressionCodec::Lz4)
+        .max_expansion(64);
+    #[cfg(feature = "self-describing")]
+    let builder = builder.self_describing(1);
+    // chaos tests inject failures, a service keeps storage as it is
+    #[cfg(feature = "chaos")]
+    let builder = builder.chaos(walcraft::Chaos::new());
+    let wal = builder.build()?;
+
+    wal.write(Order {
+        account: 7,
+        amount: 100,
+        note: "first".to_string(),
+    });
+    wal.atomic(|batch| {
+        batch.write(Order {
+            account: 7,
+            amount: -40,
+            note: "refund".to_string(),
+        });
+        batch.write(Order {
+            account: 9,
+            amount: 40,
+            note: "credit".to_string(),
+        });
+    })?;
+    match wal.try_write(&Order {
+        account: 1,
+        amount: 0,
+        note: "x".repeat(10_000),
+    }) {
+        Err(WalError::Rejected(Rejection::PayloadTooLarge { size, limit })) => {
+            println!("rejected a log of {} bytes, limit is {}", size, limit)
+        }
+        // variants may be added to both enums, so a catch-all arm is required
+        Err(WalError::Rejected(rejection)) => println!("rejected: {}", rejection),
+        Err(e) => println!("failed: {:?}", e),
+        Ok(lsn) => println!("written as {}", lsn),
+    }
+
+    println!("account 7: {:?}", wal.read_by_key(&7u32)?);
+    println!("memory: {:?}", wal.memory_usage());
+    println!("stats: {:?}", wal.stats());
+    println!("health: {:?}", wal.health());
+    println!("chain intact: {}", wal.verify_chain()?.is_intact());
+
+    // fixed size records trade flexibility for O(1) lookups, and read-only handles never write
+    let fixed_dir = "./tmp/example_configuration/fixed/";
+    std::fs::create_dir_all(fixed_dir).expect("Failed to create WAL directory");
+    let fixed: Wal<u64> = WalBuilder::new(fixed_dir, 1000)
+        .fixed_record_size(8)
+        .build()?;
+    fixed.write(42);
+    let reader: Wal<u64> = WalBuilder::new(fixed_dir, 1000)
+        .fixed_record_size(8)
+        .read_only(true)
+        .build()?;
+    println!("last log: {:?}", reader.read_last(1)?);
+    Ok(())
+}
@@ -0,0 +1,91 @@
+//! Replay on startup into an in-memory state machine
+//!
+//! The state of a ledger lives in memory and every change to it is written to the WAL before
+//! being applied. On startup, the state is rebuilt by replaying the WAL, after checking what the
+//! WAL had to recover from.
+//!
+//! Run with `cargo run --example replay`.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use walcraft::{Wal, WalError};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+enum Command {
+    Deposit { account: u32, amount: i64 },
+    Transfer { from: u32, to: u32, amount: i64 },
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Ledger {
+    balances: BTreeMap<u32, i64>,
+}
+
+impl Ledger {
+    fn apply(&mut self, command: &Command) {
+        match *command {
+            Command::Deposit { account, amount } => {
+                *self.balances.entry(account).or_default() += amount;
+            }
+            Command::Transfer { from, to, amount } => {
+                *self.balances.entry(from).or_default() -= amount;
+                *self.balances.entry(to).or_default() += amount;
+            }
+        }
+    }
+
+    // write the command ahead of applying it, so the change is never lost once applied
+    fn execute(&mut self, wal: &Wal<Command>, command: Command) -> Result<(), WalError> {
+        wal.try_write(&command)?;
+        self.apply(&command);
+        Ok(())
+    }
+}
+
+// rebuild the ledger from the commands in the WAL
+fn open(dir: &str) -> Result<(Wal<Command>, Ledger), WalError> {
+    let wal = Wal::new(dir, 100)?;
+    if let Some(recovery) = wal.recovery() {
+        println!("recovered from: {}", recovery.reason);
+    }
+    let mut ledger = Ledger::default();
+    let report = wal.read_reported()?;
+    if report.skipped > 0 {
+        println!("{} commands could not be read", report.skipped);
+    }
+    for command in &report.logs {
+        ledger.apply(command);
+    }
+    Ok((wal, ledger))
+}
+
+fn main() -> Result<(), WalError> {
+    let dir = "./tmp/example_replay/";
+    let _ = std::fs::remove_dir_all(dir);
+    std::fs::create_dir_all(dir).expect("Failed to create WAL directory");
+
+    let (wal, mut ledger) = open(dir)?;
+    ledger.execute(
+        &wal,
+        Command::Deposit {
+            account: 1,
+            amount: 100,
+        },
+    )?;
+    ledger.execute(
+        &wal,
+        Command::Transfer {
+            from: 1,
+            to: 2,
+            amount: 30,
+        },
+    )?;
+    println!("before restart: {:?}", ledger.balances);
+    // the last commands are written before the process "restarts"
+    wal.close()?;
+
+    let (_wal, replayed) = open(dir)?;
+    println!("after restart: {:?}", replayed.balances);
+    assert_eq!(replayed, ledger);
+    Ok(())
+}
@@ -0,0 +1,98 @@
+//! Durable request logging in a web service built on axum
+//!
+//! Every request is logged before it's answered, from the handler serving it. The WAL is opened
+//! once at startup and shared with every handler as the state of the router, each request using
+//! a clone of the handle.
+//!
+//! Run with `cargo run --example request_log`.
+
+use axum::extract::State;
+use axum::http::{Method, StatusCode, Uri};
+use axum::routing::get;
+use axum::Router;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use walcraft::{Wal, WalError};
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Request {
+    method: String,
+    path: String,
+    received_ms: u64,
+}
+
+// log the request, then answer with the sequence number it was given
+async fn log_request(
+    State(wal): State<Wal<Request>>,
+    method: Method,
+    uri: Uri,
+) -> (StatusCode, String) {
+    let request = Request {
+        method: method.to_string(),
+        path: uri.path().to_string(),
+        received_ms: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64),
+    };
+    // the log is handed to the writer thread, without waiting for storage
+    match wal.try_write(&request) {
+        Ok(lsn) => (StatusCode::OK, format!("logged as {}\n", lsn)),
+        // a request that can't be logged isn't served
+        Err(e) => (StatusCode::SERVICE_UNAVAILABLE, format!("{:?}\n", e)),
+    }
+}
+
+// send a request to the service and return the body of the response
+async fn send(address: SocketAddr, path: &str) -> std::io::Result<String> {
+    let mut stream = TcpStream::connect(address).await?;
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, address
+    );
+    stream.write_all(request.as_bytes()).await?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await?;
+    Ok(response
+        .split("\r\n\r\n")
+        .nth(1)
+        .unwrap_or_default()
+        .to_string())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), WalError> {
+    let dir = "./tmp/example_request_log/";
+    let _ = std::fs::remove_dir_all(dir);
+    std::fs::create_dir_all(dir).expect("Failed to create WAL directory");
+    let wal = Wal::new(dir, 100)?;
+
+    let app = Router::new()
+        .route("/", get(log_request))
+        .route("/orders/{id}", get(log_request))
+        .route("/health", get(log_request))
+        .with_state(wal.clone());
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("Failed to bind");
+    let address = listener.local_addr().expect("No local address");
+    let server = tokio::spawn(async move { axum::serve(listener, app).await });
+
+    for path in ["/", "/orders/7", "/health"] {
+        let body = send(address, path).await.expect("Request failed");
+        print!("GET {} -> {}", path, body);
+    }
+    server.abort();
+
+    // the requests are in the WAL, in the order they were received
+    wal.flush()?;
+    for request in wal.read()? {
+        println!(
+            "{} {} at {}",
+            request.method, request.path, request.received_ms
+        );
+    }
+    Ok(())
+}
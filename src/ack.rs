@@ -0,0 +1,113 @@
+use crate::Lsn;
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+
+// Callback notified of logs once they are durable
+pub(crate) type OnAck = Arc<dyn Fn(RangeInclusive<Lsn>) + Send + Sync>;
+
+// Acknowledgments of written logs, delivered once they are durable in the order of their
+// sequence numbers
+// Batches wait in the queue from the time they are written until the sync covering them, which
+// may run on the sync stage while the writer goes on. Acknowledgments are delivered while the
+// queue is locked, so a sync completing on one thread never acknowledges logs ahead of a sync
+// completing on another.
+#[derive(Clone, Default)]
+pub(crate) struct Acks {
+    on_ack: Option<OnAck>,
+    // batches written but not yet synced, oldest first
+    written: Arc<Mutex<VecDeque<RangeInclusive<Lsn>>>>,
+}
+
+impl Acks {
+    pub fn new(on_ack: Option<OnAck>) -> Self {
+        Self {
+            on_ack,
+            written: Default::default(),
+        }
+    }
+
+    // the logs `lsns` were written, and wait for a sync
+    pub fn written(&self, lsns: RangeInclusive<Lsn>) {
+        if self.on_ack.is_some() {
+            self.lock().push_back(lsns);
+        }
+    }
+
+    // sequence number of the newest log waiting for a sync, which the next sync covers
+    pub fn last_written(&self) -> Option<Lsn> {
+        self.lock().back().map(|lsns| *lsns.end())
+    }
+
+    // acknowledge the logs written up to `through`, now that they are durable
+    pub fn durable(&self, through: Option<Lsn>) {
+        self.settle(through, true);
+    }
+
+    // forget the logs written up to `through` without acknowledging them, as their sync failed
+    pub fn lost(&self, through: Option<Lsn>) {
+        self.settle(through, false);
+    }
+
+    fn settle(&self, through: Option<Lsn>, durable: bool) {
+        let (on_ack, through) = match (&self.on_ack, through) {
+            (Some(on_ack), Some(through)) => (on_ack, through),
+            _ => return,
+        };
+        let mut written = self.lock();
+        // contiguous batches are acknowledged at once
+        let mut acked: Option<RangeInclusive<Lsn>> = None;
+        while written.front().is_some_and(|lsns| *lsns.end() <= through) {
+            let lsns = written.pop_front().expect("front was just checked");
+            acked = match acked {
+                Some(acked) if *acked.end() + 1 == *lsns.start() => {
+                    Some(*acked.start()..=*lsns.end())
+                }
+                Some(acked) => {
+                    if durable {
+                        on_ack(acked);
+                    }
+                    Some(lsns)
+                }
+                None => Some(lsns),
+            };
+        }
+        if let Some(acked) = acked.filter(|_| durable) {
+            on_ack(acked);
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, VecDeque<RangeInclusive<Lsn>>> {
+        self.written.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ordered() {
+        let acked = Arc::new(Mutex::new(Vec::new()));
+        let log = acked.clone();
+        let acks = Acks::new(Some(Arc::new(move |lsns| log.lock().unwrap().push(lsns))));
+        acks.written(0..=1);
+        acks.written(2..=4);
+        // a batch lost to a failed write is never written
+        acks.written(6..=6);
+        assert_eq!(acks.last_written(), Some(6));
+        acks.durable(Some(4));
+        acks.durable(Some(6));
+        // logs already acknowledged aren't acknowledged again
+        acks.durable(Some(6));
+        assert_eq!(*acked.lock().unwrap(), vec![0..=4, 6..=6]);
+
+        // a failed sync drops its logs
+        acks.written(7..=8);
+        acks.lost(acks.last_written());
+        acks.written(9..=9);
+        acks.durable(acks.last_written());
+        assert_eq!(acked.lock().unwrap().last(), Some(&(9..=9)));
+        assert_eq!(acks.last_written(), None);
+    }
+}
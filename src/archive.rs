@@ -0,0 +1,90 @@
+use crate::durable;
+use crate::storage::SharedStorage;
+use crate::Lsn;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// WAL file about to be dropped, handed to an [Archiver]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ArchivedSegment {
+    /// Sequence number of the WAL file, i.e. `N` in `wal_N`
+    pub id: u8,
+    /// Path of the WAL file, still holding its logs while the archiver runs
+    ///
+    /// With a custom [crate::WalBuilder::storage], the file is in that storage rather than on
+    /// the local file system, and is read with [ArchivedSegment::read].
+    pub path: PathBuf,
+    /// Sequence numbers of the first and the last log of the file
+    pub lsns: RangeInclusive<Lsn>,
+    /// Size of the file in bytes
+    pub bytes: u64,
+    pub(crate) storage: SharedStorage,
+}
+
+impl ArchivedSegment {
+    /// Content of the WAL file, from the storage of the WAL
+    pub fn read(&self) -> io::Result<Vec<u8>> {
+        self.storage
+            .read(&self.path)?
+            .ok_or_else(|| io::ErrorKind::NotFound.into())
+    }
+}
+
+/// Destination of WAL files before their logs are dropped, see [crate::WalBuilder::archiver]
+///
+/// Implemented for closures taking the file about to be dropped.
+pub trait Archiver: Send + Sync {
+    /// Copy or upload the file of `segment`, which is left in place and dropped afterwards
+    ///
+    /// On error, the logs are kept and the file is archived again later.
+    fn archive(&self, segment: &ArchivedSegment) -> io::Result<()>;
+}
+
+impl<F> Archiver for F
+where
+    F: Fn(&ArchivedSegment) -> io::Result<()> + Send + Sync,
+{
+    fn archive(&self, segment: &ArchivedSegment) -> io::Result<()> {
+        self(segment)
+    }
+}
+
+// Archiver shared by the builder and the writer thread
+pub(crate) type SharedArchiver = Arc<dyn Archiver>;
+
+// Archiver copying files to a directory, named after the range of their logs so that the files
+// archived over time never replace one another
+pub(crate) struct DirectoryArchiver {
+    dir: PathBuf,
+}
+
+impl DirectoryArchiver {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    // name of the archived copy of a file, sorting in the order logs were written
+    fn name(lsns: &RangeInclusive<Lsn>) -> String {
+        format!("wal_{:020}-{:020}", lsns.start(), lsns.end())
+    }
+}
+
+impl Archiver for DirectoryArchiver {
+    fn archive(&self, segment: &ArchivedSegment) -> io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let target = self.dir.join(Self::name(&segment.lsns));
+        // a crash while copying leaves a temporary file, never a partial archive
+        let temp = target.with_extension("tmp");
+        match segment.storage.is_local() {
+            true => std::fs::copy(&segment.path, &temp).map(|_| ())?,
+            false => std::fs::write(&temp, segment.read()?)?,
+        }
+        std::fs::File::open(&temp)?.sync_all()?;
+        durable::replace(&temp, &target)
+    }
+}
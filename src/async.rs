@@ -0,0 +1,213 @@
+//! Async handles for applications running on tokio
+//!
+//! Writes only add logs to the buffer, so they never block. Flushes await the reply of the writer
+//! on a tokio channel, and reads run on the blocking pool of the runtime, so tasks are never held
+//! up by file IO. The writer loop itself runs on the blocking pool unless
+//! [WalBuilder::spawner] is set.
+//!
+//! # Example
+//! ```
+//! use walcraft::r#async::Wal;
+//!
+//! # #[tokio::main]
+//! # async fn main() {
+//! # std::fs::create_dir_all("./tmp/async_wal_doc/").unwrap();
+//! let wal = Wal::new("./tmp/async_wal_doc/", 500).await.unwrap();
+//! wal.write("checkout".to_string()).await;
+//! wal.flush().await.unwrap();
+//!
+//! let mut logs = wal.read();
+//! while let Some(log) = logs.next().await {
+//!     println!("{}", log.unwrap());
+//! }
+//! # }
+//! ```
+
+use crate::writer::FlushReply;
+use crate::{Lsn, WalBuilder, WalError};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use tokio::sync::{mpsc, oneshot};
+
+// logs handed over to a stream ahead of its consumer
+const STREAM_AHEAD: usize = 64;
+
+/// Async handle to a WAL, see the [module](self) documentation
+///
+/// Handles can be cloned and shared among tasks like [crate::Wal], and every other operation is
+/// available on the blocking handle returned by [Wal::blocking].
+pub struct Wal<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    inner: crate::Wal<T>,
+}
+
+impl<T> Clone for Wal<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T> Wal<T>
+where
+    T: Serialize + for<'a> Deserialize<'a> + Send + 'static,
+{
+    /// Create a new WAL instance, like [crate::Wal::new]
+    ///
+    /// Must be called from within a tokio runtime.
+    pub async fn new(location: &str, capacity: usize) -> Result<Self, WalError> {
+        WalBuilder::new(location, capacity).build_async().await
+    }
+
+    // Open the WAL on the blocking pool, running the writer there too unless a spawner is set
+    pub(crate) async fn with_builder(mut builder: WalBuilder<T>) -> Result<Self, WalError> {
+        if builder.spawner.is_none() {
+            let runtime = tokio::runtime::Handle::current();
+            builder = builder.spawner(move |writer| {
+                runtime.spawn_blocking(writer);
+                Ok(())
+            });
+        }
+        let inner = tokio::task::spawn_blocking(move || builder.build())
+            .await
+            .map_err(|_| WalError::File("Failed to open the WAL".to_string()))??;
+        Ok(Self { inner })
+    }
+
+    /// Write an item to log, like [crate::Wal::write]
+    pub async fn write(&self, entry: T) -> Option<Lsn> {
+        self.inner.write(entry)
+    }
+
+    /// Write an item to log, reporting why it was not accepted, like [crate::Wal::try_write]
+    pub async fn try_write(&self, entry: &T) -> Result<Lsn, WalError> {
+        self.inner.try_write(entry)
+    }
+
+    /// Write an item to log and wait until it's durable, like [crate::Wal::write_sync]
+    pub async fn write_durable(&self, entry: &T) -> Result<Lsn, WalError> {
+        let lsn = self.inner.try_write(entry)?;
+        self.flush().await?;
+        Ok(lsn)
+    }
+
+    /// Batch write many logs in a single step, like [crate::Wal::batch_write]
+    pub async fn batch_write(&self, entries: Vec<T>) -> Option<Range<Lsn>> {
+        self.inner.batch_write(entries)
+    }
+
+    /// Wait until every log written so far is stored and synced, like [crate::Wal::flush]
+    pub async fn flush(&self) -> Result<(), WalError> {
+        let (reply, result) = oneshot::channel();
+        self.inner.request_flush(FlushReply::Async(reply))?;
+        result
+            .await
+            .map_err(|_| crate::Wal::<T>::writer_stopped())?
+    }
+
+    /// Stream of all written logs, oldest first, like [crate::Wal::read]
+    ///
+    /// Logs are read on the blocking pool and handed over as the stream is consumed. A failed
+    /// read ends the stream with its error.
+    pub fn read(&self) -> LogStream<T> {
+        let (sender, receiver) = mpsc::channel(STREAM_AHEAD);
+        let wal = self.inner.clone();
+        tokio::task::spawn_blocking(move || {
+            let logs = match wal.read() {
+                Ok(logs) => logs,
+                Err(e) => {
+                    let _ = sender.blocking_send(Err(e));
+                    return;
+                }
+            };
+            for log in logs {
+                // the stream was dropped
+                if sender.blocking_send(Ok(log)).is_err() {
+                    return;
+                }
+            }
+        });
+        LogStream { receiver }
+    }
+
+    /// Blocking handle to the same WAL, for operations without an async counterpart
+    ///
+    /// Operations reading or changing files block the calling thread, so tasks should call them
+    /// through [tokio::task::spawn_blocking].
+    pub fn blocking(&self) -> &crate::Wal<T> {
+        &self.inner
+    }
+}
+
+/// Logs of a WAL read by [Wal::read]
+pub struct LogStream<T> {
+    receiver: mpsc::Receiver<Result<T, WalError>>,
+}
+
+impl<T> LogStream<T> {
+    /// Next log, or None once every log was read
+    pub async fn next(&mut self) -> Option<Result<T, WalError>> {
+        self.receiver.recv().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Item {
+        id: usize,
+    }
+
+    fn clear_storage(name: &str) -> String {
+        let path = format!("./tmp/{}/", name);
+        if std::path::Path::new(&path).exists() {
+            std::fs::remove_dir_all(&path).expect("Failed to delete old files");
+        }
+        std::fs::create_dir_all(&path).expect("Failed to create test directory");
+        path
+    }
+
+    #[tokio::test]
+    async fn write_durable() {
+        let dir = clear_storage("async_write_durable");
+        let wal = Wal::new(&dir, 1000).await.unwrap();
+        wal.write(Item { id: 0 }).await;
+        assert_eq!(wal.write_durable(&Item { id: 1 }).await.unwrap(), 1);
+        let size = std::fs::metadata(format!("{}wal_1", dir)).unwrap().len();
+        assert!(size > 0);
+    }
+
+    #[tokio::test]
+    async fn write_and_stream() {
+        let dir = clear_storage("async_write_and_stream");
+        let wal = Wal::new(&dir, 1000).await.unwrap();
+        assert_eq!(wal.write(Item { id: 0 }).await, Some(0));
+        let writer = wal.clone();
+        tokio::spawn(async move {
+            writer
+                .batch_write((1..100).map(|id| Item { id }).collect())
+                .await
+        })
+        .await
+        .unwrap();
+        wal.flush().await.unwrap();
+
+        let mut logs = wal.read();
+        let mut ids = Vec::new();
+        while let Some(log) = logs.next().await {
+            ids.push(log.unwrap().id);
+        }
+        assert_eq!(ids, (0..100).collect::<Vec<_>>());
+        // the blocking handle sees the same logs
+        assert_eq!(wal.blocking().read().unwrap().len(), 100);
+    }
+}
@@ -0,0 +1,114 @@
+/// Bound on the logs waiting for the writer thread, so a writer falling behind a slow disk
+/// doesn't make the buffer grow without limit
+///
+/// Used with [crate::WalBuilder::backpressure]. Once the logs waiting in the buffer reach either
+/// limit, new logs are handled as set by [Overflow]. A batch larger than the bound never fits
+/// and is rejected with [crate::WalError::Backpressure] whatever the policy. The current depth of
+/// the buffer is reported by [crate::Wal::buffer_depth].
+///
+/// # Example
+/// ```
+/// use walcraft::{Backpressure, Overflow};
+///
+/// // at most 10k logs or 4 MB waiting, blocking writers until the writer thread catches up
+/// let bound = Backpressure::new()
+///     .max_logs(10_000)
+///     .max_bytes(4 << 20)
+///     .overflow(Overflow::Block);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Backpressure {
+    max_logs: Option<usize>,
+    max_bytes: Option<usize>,
+    overflow: Overflow,
+}
+
+/// What happens to a log that doesn't fit in the buffer bounded with [Backpressure]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Overflow {
+    /// Fail with [crate::WalError::Backpressure], the log is not written
+    #[default]
+    Reject,
+    /// Block the caller until the writer thread makes room for the log
+    Block,
+    /// Drop the oldest logs waiting in the buffer to make room for the log
+    ///
+    /// Dropped logs are never written, leaving gaps in the sequence numbers. Logs of an atomic
+    /// batch are dropped together.
+    DropOldest,
+}
+
+impl Backpressure {
+    /// Create a bound that lets everything through
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hold at most `logs` logs waiting for the writer thread
+    pub fn max_logs(mut self, logs: usize) -> Self {
+        self.max_logs = Some(logs.max(1));
+        self
+    }
+
+    /// Hold at most `bytes` of serialized payload waiting for the writer thread
+    pub fn max_bytes(mut self, bytes: usize) -> Self {
+        self.max_bytes = Some(bytes.max(1));
+        self
+    }
+
+    /// Handle logs that don't fit as set by `overflow`, see [Overflow]
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    pub(crate) fn policy(&self) -> Overflow {
+        self.overflow
+    }
+
+    // whether `logs` logs of `bytes` fit next to the ones already waiting
+    pub(crate) fn fits(&self, waiting: BufferDepth, logs: usize, bytes: usize) -> bool {
+        self.max_logs.is_none_or(|max| waiting.logs + logs <= max)
+            && self
+                .max_bytes
+                .is_none_or(|max| waiting.bytes + bytes <= max)
+    }
+
+    // whether `logs` logs of `bytes` fit in an empty buffer
+    pub(crate) fn ever_fits(&self, logs: usize, bytes: usize) -> bool {
+        self.fits(BufferDepth::default(), logs, bytes)
+    }
+}
+
+/// Logs waiting for the writer thread, as reported by [crate::Wal::buffer_depth]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct BufferDepth {
+    /// Number of logs waiting in the buffer
+    pub logs: usize,
+    /// Serialized payload of the logs waiting in the buffer
+    pub bytes: usize,
+    /// Logs dropped by [Overflow::DropOldest] since the WAL was opened
+    pub dropped: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fits() {
+        let bound = Backpressure::new().max_logs(3).max_bytes(100);
+        let waiting = BufferDepth {
+            logs: 2,
+            bytes: 50,
+            dropped: 0,
+        };
+        assert!(bound.fits(waiting, 1, 50));
+        assert!(!bound.fits(waiting, 2, 10));
+        assert!(!bound.fits(waiting, 1, 51));
+        assert!(!bound.ever_fits(4, 10));
+        // no limit lets everything through
+        assert!(Backpressure::new().fits(waiting, usize::MAX / 2, usize::MAX / 2));
+    }
+}
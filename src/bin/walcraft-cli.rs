@@ -0,0 +1,228 @@
+//! Inspect, dump, verify and truncate WAL directories from the command line
+//!
+//! Logs are handled as the byte payloads the application serialized, so the CLI works on any
+//! WAL. Payloads are printed as text when they are printable UTF-8, and in hex otherwise.
+//!
+//! ```text
+//! walcraft-cli inspect <dir>
+//! walcraft-cli dump <dir> [--format text|json]
+//! walcraft-cli verify <dir> [--repair]
+//! walcraft-cli truncate <dir> <lsn>
+//! walcraft-cli tail <dir> [-n <count>] [--follow]
+//! ```
+
+use std::process::ExitCode;
+use std::time::Duration;
+use walcraft::{Lsn, RawWal, VerifyReport, WalBuilder, WalError};
+
+// capacity the WAL is opened with, which only matters to logs written from now on
+const CAPACITY: usize = 1 << 30;
+
+// pause between two reads of `tail --follow`
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(500);
+
+const USAGE: &str = "usage:
+  walcraft-cli inspect <dir>                       files, logs and health of the WAL
+  walcraft-cli dump <dir> [--format text|json]     every log, oldest first
+  walcraft-cli verify <dir> [--repair]             check every file, cutting trailing garbage
+  walcraft-cli truncate <dir> <lsn>                remove the oldest files of logs up to <lsn>
+  walcraft-cli tail <dir> [-n <count>] [--follow]  newest logs, then logs as they are written";
+
+fn main() -> ExitCode {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let (command, dir, rest) = match args.as_slice() {
+        [command, dir, rest @ ..] => (command.as_str(), dir.as_str(), rest),
+        _ => return usage(),
+    };
+    let result = match (command, rest) {
+        ("inspect", []) => inspect(dir),
+        ("dump", []) => dump(dir, Format::Text),
+        ("dump", [flag, format]) if flag == "--format" => match format.as_str() {
+            "text" => dump(dir, Format::Text),
+            "json" => dump(dir, Format::Json),
+            _ => return usage(),
+        },
+        ("verify", []) => verify(dir, false),
+        ("verify", [flag]) if flag == "--repair" => verify(dir, true),
+        ("truncate", [lsn]) => match lsn.parse() {
+            Ok(lsn) => truncate(dir, lsn),
+            Err(_) => return usage(),
+        },
+        ("tail", rest) => match tail_options(rest) {
+            Some((count, follow)) => tail(dir, count, follow),
+            None => return usage(),
+        },
+        _ => return usage(),
+    };
+    match result {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn usage() -> ExitCode {
+    eprintln!("{}", USAGE);
+    ExitCode::from(2)
+}
+
+// number of logs and whether to follow the WAL, from the options of `tail`
+fn tail_options(options: &[String]) -> Option<(usize, bool)> {
+    let (mut count, mut follow) = (10, false);
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.as_str() {
+            "-n" => count = options.next()?.parse().ok()?,
+            "--follow" | "-f" => follow = true,
+            _ => return None,
+        }
+    }
+    Some((count, follow))
+}
+
+// Open the WAL at `dir` without writing to it unless `write` is set
+fn open(dir: &str, write: bool) -> Result<RawWal, WalError> {
+    if !std::path::Path::new(dir).is_dir() {
+        return Err(WalError::File(format!("No WAL at {}", dir)));
+    }
+    RawWal::with_builder(WalBuilder::new(dir, CAPACITY).read_only(!write))
+}
+
+fn inspect(dir: &str) -> Result<ExitCode, WalError> {
+    let wal = open(dir, false)?;
+    let report = wal.wal().verify()?;
+    println!(
+        "{:>6} {:>12} {:>10} {:>8} {:>8}  digest",
+        "file", "bytes", "logs", "corrupt", "wasted"
+    );
+    for s in &report.segments {
+        let digest = match s.digest_mismatch {
+            true => "mismatch",
+            false => "ok",
+        };
+        println!(
+            "{:>6} {:>12} {:>10} {:>8} {:>8}  {}",
+            s.id, s.size, s.entries, s.corrupt_frames, s.wasted_bytes, digest
+        );
+    }
+    let logs = wal.read_numbered()?;
+    match (logs.first(), logs.last()) {
+        (Some((first, _)), Some((last, _))) => println!("logs {} to {}", first, last),
+        _ => println!("no logs"),
+    }
+    if let Some(recovery) = wal.wal().recovery() {
+        println!("meta file disagrees with the files: {}", recovery.reason);
+    }
+    println!("{}", health(&report));
+    Ok(ExitCode::SUCCESS)
+}
+
+#[derive(Clone, Copy)]
+enum Format {
+    Text,
+    Json,
+}
+
+fn dump(dir: &str, format: Format) -> Result<ExitCode, WalError> {
+    let wal = open(dir, false)?;
+    for (lsn, payload) in wal.read_numbered()? {
+        print_log(lsn, &payload, format);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn print_log(lsn: Lsn, payload: &[u8], format: Format) {
+    // binary payloads may happen to be valid UTF-8
+    let text = std::str::from_utf8(payload)
+        .ok()
+        .filter(|text| !text.chars().any(|c| c.is_control() && !c.is_whitespace()));
+    match (format, text) {
+        (Format::Text, Some(text)) => println!("{}\t{}", lsn, text),
+        (Format::Text, None) => println!("{}\t0x{}", lsn, hex(payload)),
+        (Format::Json, Some(text)) => println!(
+            "{}",
+            serde_json::json!({"lsn": lsn, "size": payload.len(), "text": text})
+        ),
+        (Format::Json, None) => println!(
+            "{}",
+            serde_json::json!({"lsn": lsn, "size": payload.len(), "hex": hex(payload)})
+        ),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn verify(dir: &str, repair: bool) -> Result<ExitCode, WalError> {
+    let wal = open(dir, repair)?;
+    let report = match repair {
+        true => wal.wal().repair()?,
+        false => wal.wal().verify()?,
+    };
+    for s in report.segments.iter().filter(|s| s.corrupt_frames > 0) {
+        println!("file {}: {} frames can't be read", s.id, s.corrupt_frames);
+    }
+    for s in report.segments.iter().filter(|s| s.wasted_bytes > 0) {
+        println!(
+            "file {}: {} bytes past the last frame",
+            s.id, s.wasted_bytes
+        );
+    }
+    for s in report.segments.iter().filter(|s| s.digest_mismatch) {
+        println!("file {}: content doesn't match its digest", s.id);
+    }
+    for id in &report.repaired {
+        println!("file {}: cut to its last frame", id);
+    }
+    println!("{}", health(&report));
+    match report.is_healthy() {
+        true => Ok(ExitCode::SUCCESS),
+        false => Ok(ExitCode::FAILURE),
+    }
+}
+
+fn health(report: &VerifyReport) -> String {
+    let state = match (report.is_healthy(), report.meta_readable) {
+        (true, _) => "healthy",
+        (false, true) => "damaged",
+        (false, false) => "damaged, meta file can't be read",
+    };
+    format!(
+        "{} logs in {} files, {}",
+        report.entries,
+        report.segments.len(),
+        state
+    )
+}
+
+fn truncate(dir: &str, through: Lsn) -> Result<ExitCode, WalError> {
+    let wal = open(dir, true)?;
+    let removed = wal.wal().truncate(through)?;
+    println!("{} files removed", removed);
+    Ok(ExitCode::SUCCESS)
+}
+
+fn tail(dir: &str, count: usize, follow: bool) -> Result<ExitCode, WalError> {
+    let wal = open(dir, false)?;
+    let logs = wal.read_numbered()?;
+    let mut last = logs.last().map(|(lsn, _)| *lsn);
+    for (lsn, payload) in &logs[logs.len().saturating_sub(count)..] {
+        print_log(*lsn, payload, Format::Text);
+    }
+    if !follow {
+        return Ok(ExitCode::SUCCESS);
+    }
+    // the files are read again as another process writes to them
+    loop {
+        std::thread::sleep(FOLLOW_INTERVAL);
+        for (lsn, payload) in wal.read_numbered()? {
+            if last.is_none_or(|last| lsn > last) {
+                print_log(lsn, &payload, Format::Text);
+                last = Some(lsn);
+            }
+        }
+    }
+}
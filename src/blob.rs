@@ -0,0 +1,129 @@
+use crate::durable;
+use crate::entry::LogEntry;
+use crate::format::BLOB_REFERENCE_BYTES;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::Hasher;
+use std::path::{Path, PathBuf};
+
+// Store of large payloads, kept once in the `blobs` directory of the WAL whatever the number of
+// logs holding them
+// Frames of such logs hold a reference made of the hash and size of the payload, which names the
+// blob file. Blobs no longer referenced by any WAL file are removed when the writer moves on to
+// the next file.
+pub(crate) struct BlobStore {
+    dir: PathBuf,
+    // payloads larger than this are stored as blobs
+    threshold: usize,
+}
+
+impl BlobStore {
+    pub fn new(location: &Path, threshold: usize) -> Self {
+        Self {
+            dir: Self::dir(location),
+            threshold,
+        }
+    }
+
+    fn dir(location: &Path) -> PathBuf {
+        location.join("blobs")
+    }
+
+    fn path(dir: &Path, reference: &[u8]) -> PathBuf {
+        let name = reference
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>();
+        dir.join(name)
+    }
+
+    // reference to a payload, made of its hash and size
+    fn reference(payload: &[u8]) -> Vec<u8> {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(payload);
+        let mut reference = Vec::with_capacity(BLOB_REFERENCE_BYTES);
+        reference.extend(hasher.finish().to_be_bytes());
+        reference.extend((payload.len() as u64).to_be_bytes());
+        reference
+    }
+
+    // Replace the payload of a large log with a reference to its blob
+    // The payload is kept in the log when its blob can't be stored, or when another payload with
+    // the same reference is already stored
+    pub fn externalize(&self, entry: &mut LogEntry) {
+        if entry.blob() || entry.size() <= self.threshold {
+            return;
+        }
+        let reference = Self::reference(entry.payload());
+        let path = Self::path(&self.dir, &reference);
+        let stored = match std::fs::read(&path) {
+            Ok(existing) => existing == entry.payload(),
+            Err(_) => self.store(&path, entry.payload()).is_ok(),
+        };
+        if stored {
+            entry.set_blob(reference);
+        }
+    }
+
+    // write a blob atomically, so a blob file is always complete
+    fn store(&self, path: &Path, payload: &[u8]) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        durable::write_atomic(path, &path.with_extension("tmp"), payload)
+    }
+
+    // payload referenced by a log, None if its blob is missing
+    pub fn resolve(location: &Path, reference: &[u8]) -> Option<Vec<u8>> {
+        std::fs::read(Self::path(&Self::dir(location), reference)).ok()
+    }
+
+    // remove the blobs not in `referenced`
+    pub fn collect(&self, referenced: &HashSet<Vec<u8>>) {
+        let names = referenced
+            .iter()
+            .filter_map(|r| Self::path(&self.dir, r).file_name().map(|n| n.to_owned()))
+            .collect::<HashSet<_>>();
+        let files = match std::fs::read_dir(&self.dir) {
+            Ok(files) => files,
+            Err(_) => return,
+        };
+        for file in files.flatten() {
+            if !names.contains(&file.file_name()) {
+                let _ = std::fs::remove_file(file.path());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store() {
+        let dir = PathBuf::from("./tmp/blob_store/");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        std::fs::create_dir_all(&dir).unwrap();
+        let store = BlobStore::new(&dir, 8);
+
+        // small payloads stay in the log
+        let mut small = LogEntry::from_vec(vec![1; 8], 0);
+        store.externalize(&mut small);
+        assert!(!small.blob());
+
+        // the same large payload is stored once
+        let mut a = LogEntry::from_vec(vec![2; 100], 0);
+        let mut b = LogEntry::from_vec(vec![2; 100], 1);
+        store.externalize(&mut a);
+        store.externalize(&mut b);
+        assert!(a.blob() && b.blob());
+        assert_eq!(a.payload(), b.payload());
+        assert_eq!(a.size(), BLOB_REFERENCE_BYTES);
+        assert_eq!(std::fs::read_dir(dir.join("blobs")).unwrap().count(), 1);
+        assert_eq!(BlobStore::resolve(&dir, a.payload()), Some(vec![2; 100]));
+
+        store.collect(&HashSet::new());
+        assert_eq!(BlobStore::resolve(&dir, a.payload()), None);
+    }
+}
@@ -1,54 +1,799 @@
+use crate::backpressure::{Backpressure, BufferDepth, Overflow};
 use crate::entry::LogEntry;
-use std::sync::{Arc, Mutex};
+use crate::memory::{MemoryBudget, MemoryUsage};
+use crate::pool::PayloadPool;
+use crate::recent::Recent;
+use crate::stats::LATENCY_SAMPLING;
+use crate::{Lsn, WalError};
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
 
+// logs of a batch inserted under a single lock acquisition, larger batches are inserted in
+// chunks of this many logs
+const CHUNK: usize = 4096;
+
+// shards of a buffer without backpressure bound nor memory budget, at most
+const MAX_SHARDS: usize = 16;
+
+// shard of the next thread to add a log
+static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    // shard this thread adds single logs to, threads being spread over the shards in turn
+    static SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed);
+}
+
+// Logs added one at a time by the threads of a shard, in increasing sequence numbers
+#[derive(Default)]
+struct Shard {
+    entries: Vec<LogEntry>,
+    // when the sampled logs waiting in `entries` were added
+    sampled: Vec<Instant>,
+}
+
+// Counters updated by threads adding logs to a shard, without the lock of the buffer
+#[derive(Default)]
+struct Counters {
+    // sequence number to be assigned to the next log
+    next_lsn: AtomicU64,
+    // total size of serialized payloads ever added, used for the running average
+    payload_bytes: AtomicU64,
+    // logs waiting in the shards
+    shard_logs: AtomicUsize,
+    // serialized payload of the logs waiting in the shards
+    shard_bytes: AtomicUsize,
+    // whether a large batch is being inserted, notifying the writer once complete
+    filling: AtomicBool,
+}
+
+struct BufferInner {
+    // logs waiting to be picked by the writer
+    entries: Vec<LogEntry>,
+    // sequence number of the first log added to this buffer
+    first_lsn: Lsn,
+    // sequence number of the first log not yet drained by the writer
+    drained_lsn: Lsn,
+    // sequence number following the last log numbered under this lock
+    numbered_lsn: Lsn,
+    // serialized payload of the logs waiting in `entries`
+    pending_bytes: usize,
+    // copies of the logs most recently taken by the writer
+    recent: Option<Recent>,
+    // when the sampled logs waiting in `entries` were added
+    sampled: Vec<Instant>,
+    // when the sampled logs taken by the writer were added, until they are written
+    in_flight: Vec<Instant>,
+    // logs dropped to make room for newer ones, see [Overflow::DropOldest]
+    dropped: u64,
+    // sequence numbers reserved by a large batch still being inserted in chunks
+    filling: Option<Range<Lsn>>,
+    // logs added while a large batch is inserted, following it once it's complete
+    parked: Vec<LogEntry>,
+    // emptied batch handed back by the writer, taking the next logs
+    spare: Vec<LogEntry>,
+}
+
+impl BufferInner {
+    fn depth(&self) -> BufferDepth {
+        // logs of a large batch not inserted yet
+        let remaining = self.filling.as_ref().map_or(0, |filling| {
+            (filling.end - filling.start) as usize - (self.entries.len() - self.complete())
+        });
+        BufferDepth {
+            logs: self.entries.len() + self.parked.len() + remaining,
+            bytes: self.pending_bytes,
+            dropped: self.dropped,
+        }
+    }
+
+    // logs waiting for the writer ahead of a large batch still being inserted, all of them
+    // otherwise
+    fn complete(&self) -> usize {
+        match &self.filling {
+            Some(filling) => self.entries.partition_point(|e| e.lsn() < filling.start),
+            None => self.entries.len(),
+        }
+    }
+
+    // record the sequence numbers `lsns` given under the lock
+    // They follow the previous ones given under the lock, right away unless single logs were
+    // added to the shards meanwhile.
+    fn number(&mut self, lsns: &Range<Lsn>, sharded: bool) {
+        invariant!(
+            lsns.start == self.numbered_lsn || sharded && lsns.start > self.numbered_lsn,
+            "LSNs {}..{} don't follow the previous ones ending at {}",
+            lsns.start,
+            lsns.end,
+            self.numbered_lsn
+        );
+        self.numbered_lsn = lsns.end;
+    }
+
+    // add logs numbered from `next_lsn`, after a large batch still being inserted if any
+    fn push<I>(&mut self, logs: I)
+    where
+        I: IntoIterator<Item = LogEntry>,
+    {
+        match self.filling {
+            Some(_) => self.parked.extend(logs),
+            None => self.entries.extend(logs),
+        }
+    }
+
+    // drop the oldest log waiting for the writer, along with the rest of its atomic batch
+    // Only called with logs ahead of any batch still being inserted
+    fn drop_oldest(&mut self) {
+        let count = self
+            .entries
+            .iter()
+            .position(|e| !e.batched())
+            .map_or(self.entries.len(), |i| i + 1);
+        for entry in self.entries.drain(..count) {
+            self.pending_bytes -= entry.size();
+            if entry.lsn() % LATENCY_SAMPLING == 0 && !self.sampled.is_empty() {
+                self.sampled.remove(0);
+            }
+        }
+        // the dropped logs count as drained, so the next drain stays contiguous
+        self.drained_lsn += count as Lsn;
+        self.dropped += count as u64;
+    }
+}
+
+// Logs waiting for the writer
+// Threads adding single logs to an unbounded buffer only lock their shard, so concurrent writers
+// rarely wait for each other. Sequence numbers are taken under the lock of the shard, and the
+// writer drains every shard at once while holding all of their locks, so the logs it takes are
+// always contiguous. Batches, and every log of a buffer with a backpressure bound or a memory
+// budget, go through the single lock of `inner`, which sees every waiting log at once.
 #[derive(Clone)]
 pub(crate) struct Buffer {
-    inner: Arc<Mutex<Vec<LogEntry>>>,
+    inner: Arc<Mutex<BufferInner>>,
+    // shards of an unbounded buffer, none otherwise
+    shards: Arc<[Mutex<Shard>]>,
+    counters: Arc<Counters>,
+    memory: MemoryBudget,
+    // bound on the logs waiting for the writer, if any
+    bound: Option<Backpressure>,
+    // signaled whenever the writer drains the buffer, for writers blocked by the bound
+    room: Arc<Condvar>,
+    // signaled whenever logs are added, for the writer gathering a group of logs
+    grown: Arc<Condvar>,
+    // buffers of written payloads, reused to serialize new logs
+    pool: PayloadPool,
 }
 
 impl Buffer {
-    // create a new buffer
-    pub fn new() -> Self {
-        let inner = Arc::new(Mutex::new(Vec::new()));
-        Self { inner }
+    // create a new buffer, numbering logs from `next_lsn`
+    pub fn new(
+        next_lsn: Lsn,
+        recent: Option<Recent>,
+        memory: MemoryBudget,
+        bound: Option<Backpressure>,
+    ) -> Self {
+        let inner = BufferInner {
+            entries: Vec::new(),
+            first_lsn: next_lsn,
+            drained_lsn: next_lsn,
+            numbered_lsn: next_lsn,
+            pending_bytes: 0,
+            recent,
+            sampled: Vec::new(),
+            in_flight: Vec::new(),
+            dropped: 0,
+            filling: None,
+            parked: Vec::new(),
+            spare: Vec::new(),
+        };
+        let shards = match bound.is_none() && memory.limit().is_none() {
+            true => std::thread::available_parallelism().map_or(1, |n| n.get().min(MAX_SHARDS)),
+            false => 0,
+        };
+        let counters = Counters {
+            next_lsn: AtomicU64::new(next_lsn),
+            ..Counters::default()
+        };
+        Self {
+            inner: Arc::new(Mutex::new(inner)),
+            shards: (0..shards).map(|_| Mutex::default()).collect(),
+            counters: Arc::new(counters),
+            memory,
+            bound,
+            room: Arc::default(),
+            grown: Arc::default(),
+            pool: PayloadPool::default(),
+        }
     }
 
     // add a log to buffer
-    pub fn add(&self, entry: LogEntry) -> bool {
-        let mut buffer = match self.inner.lock() {
+    pub fn add(&self, mut entry: LogEntry) -> Result<(Lsn, bool), WalError> {
+        if !self.shards.is_empty() {
+            return Ok(self.add_to_shard(entry));
+        }
+        let buffer = match self.inner.lock() {
             Ok(g) => g,
             Err(e) => e.into_inner(),
         };
-        let notify = buffer.is_empty();
-        buffer.push(entry);
-        notify
+        let mut buffer = self.admit(buffer, 1, entry.size())?;
+        self.reserve(&mut buffer, entry.size())?;
+        // a large batch being inserted notifies the writer once complete
+        let notify = buffer.entries.is_empty() && buffer.filling.is_none();
+        self.counters
+            .payload_bytes
+            .fetch_add(entry.size() as u64, Ordering::Relaxed);
+        buffer.pending_bytes += entry.size();
+        let lsn = self.counters.next_lsn.fetch_add(1, Ordering::Relaxed);
+        buffer.number(&(lsn..lsn + 1), false);
+        if lsn.is_multiple_of(LATENCY_SAMPLING) {
+            buffer.sampled.push(Instant::now());
+        }
+        entry.set_lsn(lsn);
+        buffer.push([entry]);
+        self.grown.notify_one();
+        Ok((lsn, notify))
+    }
+
+    // add a log to the shard of the calling thread
+    // The writer only needs to be notified by the first log of the shards since the last drain.
+    fn add_to_shard(&self, mut entry: LogEntry) -> (Lsn, bool) {
+        let index = SHARD.with(|shard| *shard) % self.shards.len();
+        let mut shard = self.shards[index].lock().unwrap_or_else(|e| e.into_inner());
+        let lsn = self.counters.next_lsn.fetch_add(1, Ordering::Relaxed);
+        if lsn.is_multiple_of(LATENCY_SAMPLING) {
+            shard.sampled.push(Instant::now());
+        }
+        let size = entry.size();
+        entry.set_lsn(lsn);
+        shard.entries.push(entry);
+        self.counters
+            .payload_bytes
+            .fetch_add(size as u64, Ordering::Relaxed);
+        self.counters.shard_bytes.fetch_add(size, Ordering::Relaxed);
+        let first = self.counters.shard_logs.fetch_add(1, Ordering::Relaxed) == 0;
+        let notify = first && !self.counters.filling.load(Ordering::SeqCst);
+        drop(shard);
+        self.grown.notify_one();
+        (lsn, notify)
+    }
+
+    // lock every shard, along with the lock of `inner` held by the caller
+    fn lock_shards(&self) -> Vec<MutexGuard<'_, Shard>> {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap_or_else(|e| e.into_inner()))
+            .collect()
+    }
+
+    // copies of the logs waiting for the writer ahead of a large batch still being inserted
+    fn waiting(&self, buffer: &BufferInner) -> Vec<LogEntry> {
+        let mut data = buffer.entries[..buffer.complete()].to_vec();
+        if self.shards.is_empty() {
+            return data;
+        }
+        let limit = buffer.filling.as_ref().map_or(Lsn::MAX, |f| f.start);
+        for shard in self.lock_shards() {
+            let count = shard.entries.partition_point(|e| e.lsn() < limit);
+            data.extend_from_slice(&shard.entries[..count]);
+        }
+        data.sort_by_key(|e| e.lsn());
+        data
     }
 
     // add many logs to buffer
-    pub fn bulk_add(&self, entry: Vec<LogEntry>) -> bool {
-        let mut buffer = match self.inner.lock() {
+    // The logs receive a contiguous range of sequence numbers and cannot interleave with logs
+    // from other threads. Batches larger than [CHUNK] reserve their range and are inserted in
+    // chunks, so other threads only wait for a chunk at a time: logs they add meanwhile follow
+    // the batch, and the writer only takes the logs ahead of it.
+    pub fn bulk_add(&self, mut entry: Vec<LogEntry>) -> Result<(Range<Lsn>, bool), WalError> {
+        let bytes = entry.iter().map(|e| e.size()).sum::<usize>();
+        let chunked = entry.len() > CHUNK;
+        let buffer = match self.inner.lock() {
+            Ok(g) => g,
+            Err(e) => e.into_inner(),
+        };
+        let mut buffer = self.admit(buffer, entry.len(), bytes)?;
+        // one large batch is inserted at a time
+        while chunked && buffer.filling.is_some() {
+            buffer = self.room.wait(buffer).unwrap_or_else(|e| e.into_inner());
+            buffer = self.admit(buffer, entry.len(), bytes)?;
+        }
+        self.reserve(&mut buffer, bytes)?;
+        let notify = buffer.entries.is_empty() && buffer.filling.is_none() && !entry.is_empty();
+        let start = self
+            .counters
+            .next_lsn
+            .fetch_add(entry.len() as Lsn, Ordering::Relaxed);
+        let lsns = start..start + entry.len() as Lsn;
+        buffer.number(&lsns, !self.shards.is_empty());
+        let now = Instant::now();
+        let sampled = lsns.end.div_ceil(LATENCY_SAMPLING) - lsns.start.div_ceil(LATENCY_SAMPLING);
+        buffer.sampled.extend((0..sampled).map(|_| now));
+        self.counters
+            .payload_bytes
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        buffer.pending_bytes += bytes;
+        if !chunked {
+            for (i, e) in entry.iter_mut().enumerate() {
+                e.set_lsn(start + i as Lsn);
+            }
+            buffer.push(entry);
+            self.grown.notify_one();
+            return Ok((lsns, notify));
+        }
+        buffer.filling = Some(lsns.clone());
+        self.counters.filling.store(true, Ordering::SeqCst);
+        drop(buffer);
+
+        // numbered and split without holding the lock
+        for (i, e) in entry.iter_mut().enumerate() {
+            e.set_lsn(start + i as Lsn);
+        }
+        let mut logs = entry.into_iter();
+        loop {
+            let chunk = logs.by_ref().take(CHUNK).collect::<Vec<_>>();
+            let mut buffer = match self.inner.lock() {
+                Ok(g) => g,
+                Err(e) => e.into_inner(),
+            };
+            if chunk.is_empty() {
+                buffer.filling = None;
+                self.counters.filling.store(false, Ordering::SeqCst);
+                let parked = std::mem::take(&mut buffer.parked);
+                buffer.entries.extend(parked);
+                break;
+            }
+            buffer.entries.extend(chunk);
+        }
+        self.room.notify_all();
+        self.grown.notify_one();
+        // the writer may have only taken the logs ahead of the batch
+        Ok((lsns, true))
+    }
+
+    // wait for, or make, room for `logs` more logs of `bytes` within the backpressure bound
+    fn admit<'a>(
+        &self,
+        mut buffer: MutexGuard<'a, BufferInner>,
+        logs: usize,
+        bytes: usize,
+    ) -> Result<MutexGuard<'a, BufferInner>, WalError> {
+        let bound = match self.bound {
+            Some(bound) => bound,
+            None => return Ok(buffer),
+        };
+        if !bound.ever_fits(logs, bytes) {
+            return Err(WalError::Backpressure(format!(
+                "{} logs of {} bytes exceed the bound of the buffer",
+                logs, bytes
+            )));
+        }
+        while !bound.fits(buffer.depth(), logs, bytes) {
+            match bound.policy() {
+                Overflow::Reject => {
+                    return Err(WalError::Backpressure(format!(
+                        "Buffer is full with {} logs waiting for the writer thread",
+                        buffer.entries.len()
+                    )))
+                }
+                Overflow::Block => {
+                    buffer = self.room.wait(buffer).unwrap_or_else(|e| e.into_inner())
+                }
+                Overflow::DropOldest if buffer.complete() > 0 => buffer.drop_oldest(),
+                // a large batch being inserted is never dropped in part
+                Overflow::DropOldest => {
+                    buffer = self.room.wait(buffer).unwrap_or_else(|e| e.into_inner())
+                }
+            }
+        }
+        Ok(buffer)
+    }
+
+    // make room for `bytes` more of pending logs within the memory budget
+    // The recent cache only speeds up reads, so its oldest logs are dropped first
+    fn reserve(&self, buffer: &mut BufferInner, bytes: usize) -> Result<(), WalError> {
+        let available = match self.memory.available() {
+            Some(available) => available,
+            None => return Ok(()),
+        };
+        let needed = buffer.pending_bytes + bytes;
+        if needed > available {
+            return Err(WalError::Capacity(format!(
+                "Memory budget of {} bytes exceeded by logs waiting for the writer",
+                self.memory.limit().unwrap_or_default()
+            )));
+        }
+        if let Some(recent) = buffer.recent.as_mut() {
+            recent.shrink_to(available - needed);
+        }
+        Ok(())
+    }
+
+    // memory held by pending logs and the recent cache
+    pub fn memory_usage(&self) -> MemoryUsage {
+        let buffer = match self.inner.lock() {
+            Ok(g) => g,
+            Err(e) => e.into_inner(),
+        };
+        MemoryUsage {
+            buffer: buffer.pending_bytes + self.counters.shard_bytes.load(Ordering::Relaxed),
+            recent_cache: buffer.recent.as_ref().map_or(0, |r| r.bytes()),
+            scratch: self.memory.scratch(),
+            budget: self.memory.limit(),
+        }
+    }
+
+    // logs waiting for the writer
+    pub fn depth(&self) -> BufferDepth {
+        let mut depth = match self.inner.lock() {
+            Ok(g) => g.depth(),
+            Err(e) => e.into_inner().depth(),
+        };
+        depth.logs += self.counters.shard_logs.load(Ordering::Relaxed);
+        depth.bytes += self.counters.shard_bytes.load(Ordering::Relaxed);
+        depth
+    }
+
+    // sequence number to be assigned to the next log
+    pub fn next_lsn(&self) -> Lsn {
+        self.counters.next_lsn.load(Ordering::Relaxed)
+    }
+
+    // number logs from `next_lsn` on instead, false once a log was added to the buffer
+    #[cfg(feature = "replication")]
+    pub fn renumber(&self, next_lsn: Lsn) -> bool {
+        let mut buffer = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        if self.next_lsn() != buffer.first_lsn {
+            return false;
+        }
+        buffer.first_lsn = next_lsn;
+        buffer.drained_lsn = next_lsn;
+        buffer.numbered_lsn = next_lsn;
+        self.counters.next_lsn.store(next_lsn, Ordering::Relaxed);
+        true
+    }
+
+    // running average of serialized payload size, None until a log is added
+    pub fn average_payload_size(&self) -> Option<usize> {
+        let buffer = match self.inner.lock() {
+            Ok(g) => g,
+            Err(e) => e.into_inner(),
+        };
+        let count = self.next_lsn() - buffer.first_lsn;
+        if count == 0 {
+            return None;
+        }
+        let bytes = self.counters.payload_bytes.load(Ordering::Relaxed);
+        Some((bytes / count) as usize)
+    }
+
+    // Wait until the writer takes logs out of the buffer, or for `timeout` at most
+    // Returns right away when no log is waiting.
+    pub fn wait_for_drain(&self, timeout: Duration) {
+        let buffer = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let sharded = self.counters.shard_logs.load(Ordering::Relaxed);
+        if buffer.entries.is_empty() && buffer.filling.is_none() && sharded == 0 {
+            return;
+        }
+        let drained = buffer.drained_lsn;
+        let _ = self
+            .room
+            .wait_timeout_while(buffer, timeout, |b| b.drained_lsn == drained)
+            .unwrap_or_else(|e| e.into_inner());
+    }
+
+    // Wait until the logs waiting for the writer hold `bytes` of payload, or for `timeout` at
+    // most
+    // Logs added to a shard notify without the lock of the buffer, so a wakeup may be missed and
+    // the wait last until the timeout.
+    pub fn wait_for_bytes(&self, bytes: usize, timeout: Duration) {
+        let buffer = self.inner.lock().unwrap_or_else(|e| e.into_inner());
+        let sharded = &self.counters.shard_bytes;
+        let _ = self
+            .grown
+            .wait_timeout_while(buffer, timeout, |b| {
+                b.pending_bytes + sharded.load(Ordering::Relaxed) < bytes
+            })
+            .unwrap_or_else(|e| e.into_inner());
+    }
+
+    // copy of the logs not yet taken by the writer
+    pub fn pending(&self) -> Vec<LogEntry> {
+        let buffer = match self.inner.lock() {
             Ok(g) => g,
             Err(e) => e.into_inner(),
         };
-        let notify = buffer.is_empty();
-        buffer.extend(entry.into_iter());
-        notify
+        self.waiting(&buffer)
     }
 
     // get all items and empty the buffer
     pub fn drain(&self) -> Vec<LogEntry> {
-        let mut data = Vec::new();
+        let mut data;
         // Open new scope for locking the queue
         {
             let mut buffer = match self.inner.lock() {
                 Ok(g) => g,
                 Err(e) => e.into_inner(),
             };
+            // the batch written last takes the logs added from now on
+            data = std::mem::take(&mut buffer.spare);
             // If there is data, process it
-            if !buffer.is_empty() {
-                std::mem::swap(&mut *buffer, &mut data);
+            if buffer.filling.is_some() {
+                // the logs ahead of a large batch still being inserted
+                let complete = buffer.complete();
+                data.extend(buffer.entries.drain(..complete));
+                buffer.pending_bytes -= data.iter().map(|e| e.size()).sum::<usize>();
+            } else if !buffer.entries.is_empty() {
+                std::mem::swap(&mut buffer.entries, &mut data);
+                buffer.pending_bytes = 0;
+            }
+            // every shard is locked, so no log is being added to them
+            let mut shards = self.lock_shards();
+            let limit = buffer.filling.as_ref().map_or(Lsn::MAX, |f| f.start);
+            let (mut logs, mut bytes) = (0, 0);
+            for shard in shards.iter_mut() {
+                let count = shard.entries.partition_point(|e| e.lsn() < limit);
+                bytes += shard.entries[..count]
+                    .iter()
+                    .map(|e| e.size())
+                    .sum::<usize>();
+                logs += count;
+                data.extend(shard.entries.drain(..count));
+                buffer.sampled.append(&mut shard.sampled);
+            }
+            if logs > 0 {
+                self.counters.shard_logs.fetch_sub(logs, Ordering::Relaxed);
+                self.counters
+                    .shard_bytes
+                    .fetch_sub(bytes, Ordering::Relaxed);
+                // each shard and the logs of `entries` are already in order
+                data.sort_by_key(|e| e.lsn());
+            }
+            let drained = buffer
+                .filling
+                .as_ref()
+                .map_or(self.next_lsn(), |filling| filling.start);
+            drop(shards);
+            invariant!(
+                buffer.drained_lsn + data.len() as Lsn == drained,
+                "drained logs {}..{} are not contiguous with the next LSN {}",
+                buffer.drained_lsn,
+                buffer.drained_lsn + data.len() as Lsn,
+                drained
+            );
+            buffer.drained_lsn = drained;
+            buffer.in_flight = std::mem::take(&mut buffer.sampled);
+            // remembered under the same lock, so a log is always either in the buffer or here
+            let available = self.memory.available();
+            if let Some(recent) = buffer.recent.as_mut() {
+                recent.extend(&data);
+                if let Some(available) = available {
+                    recent.shrink_to(available);
+                }
             }
         }
+        self.room.notify_all();
         data
     }
+
+    // Hand back a batch once written, keeping its allocation for the next logs and the buffers of
+    // its payloads to serialize them into
+    pub fn recycle(&self, mut batch: Vec<LogEntry>, payloads: Vec<Vec<u8>>) {
+        batch.clear();
+        self.pool.recycle(payloads);
+        let mut buffer = match self.inner.lock() {
+            Ok(g) => g,
+            Err(e) => e.into_inner(),
+        };
+        if batch.capacity() > buffer.spare.capacity() {
+            buffer.spare = batch;
+        }
+    }
+
+    // buffers to serialize logs into
+    pub fn pool(&self) -> &PayloadPool {
+        &self.pool
+    }
+
+    // when the sampled logs of the last drain were added to the buffer
+    pub fn take_in_flight(&self) -> Vec<Instant> {
+        let mut buffer = match self.inner.lock() {
+            Ok(g) => g,
+            Err(e) => e.into_inner(),
+        };
+        std::mem::take(&mut buffer.in_flight)
+    }
+
+    // last `n` logs held in memory, from the oldest to the newest
+    // None if fewer than `n` logs are held, as older logs are only available on storage
+    pub fn last(&self, n: usize) -> Option<Vec<LogEntry>> {
+        let buffer = match self.inner.lock() {
+            Ok(g) => g,
+            Err(e) => e.into_inner(),
+        };
+        let recent = buffer.recent.as_ref()?;
+        let entries = self.waiting(&buffer);
+        if recent.len() + entries.len() < n {
+            return None;
+        }
+        let pending = entries.len().min(n);
+        let mut data = recent.last(n - pending).cloned().collect::<Vec<_>>();
+        data.extend_from_slice(&entries[entries.len() - pending..]);
+        Some(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounded(bound: Backpressure) -> Buffer {
+        Buffer::new(0, None, MemoryBudget::default(), Some(bound))
+    }
+
+    fn log(bytes: usize) -> LogEntry {
+        LogEntry::from_vec(vec![0; bytes], 0)
+    }
+
+    #[test]
+    fn reject() {
+        let buffer = bounded(Backpressure::new().max_logs(2));
+        buffer.add(log(10)).unwrap();
+        buffer.add(log(10)).unwrap();
+        assert!(matches!(
+            buffer.add(log(10)),
+            Err(WalError::Backpressure(_))
+        ));
+        assert_eq!(buffer.drain().len(), 2);
+        assert!(buffer.bulk_add(vec![log(10), log(10)]).is_ok());
+        // never fits, even in an empty buffer
+        buffer.drain();
+        assert!(matches!(
+            buffer.bulk_add(vec![log(10); 3]),
+            Err(WalError::Backpressure(_))
+        ));
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let buffer = bounded(
+            Backpressure::new()
+                .max_bytes(30)
+                .overflow(Overflow::DropOldest),
+        );
+        buffer.add(log(10)).unwrap();
+        let mut batch = vec![log(5), log(5)];
+        batch[0].set_batched(true);
+        buffer.bulk_add(batch).unwrap();
+        buffer.add(log(10)).unwrap();
+        // dropping the first log makes enough room
+        assert_eq!(buffer.add(log(10)).unwrap().0, 4);
+        let depth = buffer.depth();
+        assert_eq!((depth.logs, depth.bytes, depth.dropped), (4, 30, 1));
+        // the batch is dropped as a whole
+        assert_eq!(buffer.add(log(10)).unwrap().0, 5);
+        assert_eq!(buffer.depth().dropped, 3);
+        let lsns = buffer.drain().iter().map(|e| e.lsn()).collect::<Vec<_>>();
+        assert_eq!(lsns, [3, 4, 5]);
+    }
+
+    #[test]
+    fn block() {
+        let buffer = bounded(Backpressure::new().max_logs(1).overflow(Overflow::Block));
+        buffer.add(log(10)).unwrap();
+        let writer = {
+            let buffer = buffer.clone();
+            std::thread::spawn(move || buffer.add(log(10)).unwrap().0)
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!writer.is_finished());
+        assert_eq!(buffer.drain().len(), 1);
+        assert_eq!(writer.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn large_batch_contention() {
+        let buffer = Buffer::new(0, None, MemoryBudget::default(), None);
+        let logs = vec![log(8); 500_000];
+        let batch = {
+            let buffer = buffer.clone();
+            std::thread::spawn(move || {
+                let start = Instant::now();
+                let lsns = buffer.bulk_add(logs).unwrap().0;
+                (lsns, start.elapsed())
+            })
+        };
+        // logs added meanwhile only wait for a chunk at a time, not for the whole batch
+        let mut slowest = Duration::ZERO;
+        while !batch.is_finished() {
+            let start = Instant::now();
+            buffer.add(log(8)).unwrap();
+            slowest = slowest.max(start.elapsed());
+            std::thread::sleep(Duration::from_micros(10));
+        }
+        let (lsns, inserted) = batch.join().unwrap();
+        assert!(slowest < inserted / 2, "{:?} of {:?}", slowest, inserted);
+
+        // the batch is contiguous and followed by the logs added while it was inserted
+        let drained = buffer.drain();
+        assert_eq!(buffer.depth().logs, 0);
+        let order = drained.iter().map(|e| e.lsn()).collect::<Vec<_>>();
+        assert_eq!(order, (0..drained.len() as Lsn).collect::<Vec<_>>());
+        assert_eq!(lsns.end - lsns.start, 500_000);
+    }
+
+    #[test]
+    fn wait_for_drain() {
+        let buffer = Buffer::new(0, None, MemoryBudget::default(), None);
+        // nothing to wait for
+        let started = Instant::now();
+        buffer.wait_for_drain(Duration::from_secs(10));
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        buffer.add(log(10)).unwrap();
+        let writer = buffer.clone();
+        let drained = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            writer.drain().len()
+        });
+        // woken up by the drain, long before the timeout
+        buffer.wait_for_drain(Duration::from_secs(10));
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(drained.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn sharded() {
+        let buffer = Buffer::new(5, None, MemoryBudget::default(), None);
+        let writers = (0..8)
+            .map(|_| {
+                let buffer = buffer.clone();
+                std::thread::spawn(move || {
+                    (0..10_000)
+                        .map(|_| buffer.add(log(8)).unwrap().0)
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+        // every drain takes the logs following the previous one, in order
+        let mut drained = Vec::new();
+        while writers.iter().any(|w| !w.is_finished()) {
+            drained.extend(buffer.drain().iter().map(|e| e.lsn()));
+        }
+        drained.extend(buffer.drain().iter().map(|e| e.lsn()));
+        assert_eq!(drained, (5..80_005).collect::<Vec<_>>());
+        // each thread got increasing sequence numbers
+        for writer in writers {
+            let lsns = writer.join().unwrap();
+            assert!(lsns.windows(2).all(|w| w[0] < w[1]));
+        }
+        let depth = buffer.depth();
+        assert_eq!((depth.logs, depth.bytes), (0, 0));
+        assert_eq!(buffer.average_payload_size(), Some(8));
+    }
+
+    #[test]
+    fn drain_ahead_of_large_batch() {
+        let buffer = Buffer::new(0, None, MemoryBudget::default(), None);
+        buffer.add(log(8)).unwrap();
+        {
+            let mut inner = buffer.inner.lock().unwrap();
+            let next_lsn = 1 + CHUNK as Lsn * 2;
+            buffer.counters.next_lsn.store(next_lsn, Ordering::Relaxed);
+            inner.numbered_lsn = next_lsn;
+            inner.filling = Some(1..next_lsn);
+            buffer.counters.filling.store(true, Ordering::SeqCst);
+            let chunk = (1..=CHUNK as Lsn).map(|lsn| LogEntry::from_vec(vec![0; 8], lsn));
+            inner.entries.extend(chunk);
+        }
+        let (lsn, notify) = buffer.add(log(8)).unwrap();
+        assert_eq!(lsn, 1 + CHUNK as Lsn * 2);
+        assert!(!notify);
+        assert_eq!(buffer.depth().logs, 2 + CHUNK * 2);
+        // the batch is left out until it's complete
+        assert_eq!(buffer.pending().len(), 1);
+        assert_eq!(buffer.drain().len(), 1);
+        assert_eq!(buffer.drain().len(), 0);
+    }
 }
@@ -0,0 +1,1357 @@
+use crate::ack::OnAck;
+use crate::archive::{Archiver, DirectoryArchiver, SharedArchiver};
+use crate::checkpoint::{Checkpointer, SharedCheckpointer};
+use crate::codec::Codec;
+use crate::compaction::{self, KeyFn, MergeFn};
+use crate::compression::{CompressionCodec, MAX_EXPANSION};
+use crate::decode::Fallback;
+use crate::encryption::Cipher;
+use crate::ephemeral::EphemeralDir;
+use crate::event::{EventListener, OnRotate, RotationEvent};
+use crate::eviction::{OnCapacityWarning, OnEvict};
+use crate::failure::OnError;
+use crate::flush::SegmentWindow;
+use crate::layout::{ColdTier, Layout};
+use crate::migrate::{Envelope, Migrate};
+use crate::segment::OnSeal;
+use crate::spawn::{Spawner, WriterHandle};
+use crate::storage::SharedStorage;
+use crate::topic::SignalSender;
+use crate::{
+    Backpressure, CapacityWarning, Eviction, ForkBehavior, Framing, Lsn, ManifestKind,
+    MemoryStorage, OpenVerification, Pacing, RateLimit, SegmentSealed, SerializationCodec, Storage,
+    Validation, Wal, WalError, WalWriterHandle, WriteFailure,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+// Key of a log in keyed mode, hashed
+pub(crate) type TypedKey<T> = Arc<dyn Fn(&T) -> u64 + Send + Sync>;
+
+// Key of a log computed from its payload, once the codec of the payload is known
+pub(crate) type PayloadKey = Box<dyn FnOnce(Codec) -> KeyFn + Send>;
+
+// Accumulation of an older and a newer log with the same key
+pub(crate) type TypedMerge<T> = Arc<dyn Fn(T, T) -> T + Send + Sync>;
+
+// Accumulation of serialized payloads, once the codec of the payloads is known
+pub(crate) type PayloadMerge = Box<dyn FnOnce(Codec) -> MergeFn + Send>;
+
+// memory kept for the read scratch buffer unless configured otherwise
+const DEFAULT_SCRATCH_BUDGET: usize = 64 * 1024;
+// payload decoded at a time by parallel decode threads unless configured otherwise
+const DEFAULT_DECODE_WINDOW: usize = 8 * 1024 * 1024;
+
+/// Strategy used by the writer thread to wake up after being notified of new logs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum WakeStrategy {
+    /// Drain the buffer as soon as the writer is notified
+    #[default]
+    Eager,
+    /// Wait for the given delay after the first notification before draining the buffer, so
+    /// bursts of logs are written together
+    MicroBatch(Duration),
+    /// Gather logs for up to `within` after the first notification, or until they hold `bytes`
+    /// of payload, then write them with a single write and sync them with a single sync,
+    /// whatever the [SyncPolicy]
+    GroupCommit { within: Duration, bytes: usize },
+}
+
+/// When the writer thread syncs written logs to storage, trading throughput for durability
+///
+/// Logs written but not synced yet are safe from a crash of the process, but not from a crash of
+/// the machine. Whatever the policy, [Wal::flush] and [Wal::close] sync the current file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum SyncPolicy {
+    /// Leave syncing to the operating system
+    #[default]
+    Never,
+    /// Sync after every batch of logs, while the next batch is prepared
+    EveryWrite,
+    /// Sync once this many bytes were written since the last sync
+    EveryNBytes(usize),
+    /// Sync written logs once this long has passed since the last sync
+    Interval(Duration),
+}
+
+/// Builder to configure and create a [Wal] instance
+///
+/// # Example
+/// ```
+/// use walcraft::WalBuilder;
+///
+/// # std::fs::create_dir_all("./tmp/wal_builder_doc/").unwrap();
+/// let wal = WalBuilder::<String>::new("./tmp/wal_builder_doc/", 500).build().unwrap();
+/// wal.write("hello".to_string());
+/// ```
+///
+pub struct WalBuilder<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    // location of WAL files
+    pub(crate) location: PathBuf,
+    // capacity of data
+    pub(crate) capacity: usize,
+    // use positional writes with a tracked offset instead of append mode
+    pub(crate) positional_writes: bool,
+    // reserve the storage of every file when the writer starts it
+    pub(crate) preallocate: bool,
+    // how the writer wakes up on new logs
+    pub(crate) wake_strategy: WakeStrategy,
+    // fail to open the WAL while another process writes to it
+    pub(crate) exclusive: bool,
+    // when written logs are synced to storage
+    pub(crate) sync_policy: SyncPolicy,
+    // fail to open the WAL while this process writes to it
+    pub(crate) duplicate_guard: bool,
+    // number of processes allowed to write to the WAL at once, each through a slot of its own
+    pub(crate) slots: Option<u8>,
+    // rate of logs accepted from all handles
+    pub(crate) rate_limit: Option<RateLimit>,
+    // bound on the logs waiting for the writer thread
+    pub(crate) backpressure: Option<Backpressure>,
+    // limits on the resources used by the writer thread
+    pub(crate) pacing: Pacing,
+    // behavior of handles used in a forked process
+    pub(crate) fork_behavior: ForkBehavior,
+    // compress logs with payload larger than this
+    pub(crate) compress_above: Option<usize>,
+    // algorithm compressing the logs written from now on
+    pub(crate) compression_codec: CompressionCodec,
+    // key sealing log payloads, if they are encrypted
+    pub(crate) cipher: Option<Cipher>,
+    // highest ratio of decompressed to compressed size of a log accepted when reading
+    pub(crate) max_expansion: usize,
+    // memory available to hold logs in [Wal::read_bounded]
+    pub(crate) read_memory_cap: Option<usize>,
+    // whether reads include logs not yet written to a file
+    pub(crate) read_unflushed: bool,
+    // serialized payload of the largest read kept for [Wal::read_cached]
+    pub(crate) read_cache: Option<usize>,
+    // memory kept allocated between reads to hold raw file content
+    pub(crate) read_scratch_budget: usize,
+    // memory shared by the buffer, the recent cache and the scratch buffer
+    pub(crate) memory_budget: Option<usize>,
+    // rules enforced on logs before they are accepted
+    pub(crate) validation: Validation,
+    // encoding of log payloads
+    pub(crate) codec: Codec,
+    // older types of logs that don't deserialize as `T`, tried in turn
+    pub(crate) fallbacks: Vec<Fallback<T>>,
+    // payload size of every log when stored as fixed size records
+    pub(crate) record_size: Option<usize>,
+    // largest serialized payload accepted
+    pub(crate) max_entry_size: Option<usize>,
+    // encoding of frames in WAL files
+    pub(crate) framing: Framing,
+    // guard every frame with a checksum
+    pub(crate) checksums: bool,
+    // record when every log was written in its frame
+    pub(crate) timestamps: bool,
+    // stamp logs in the order of their sequence numbers, recording the wall clock and a
+    // monotonic time as well
+    pub(crate) strict_timestamps: bool,
+    // re-read and checksum every WAL file once the writer moves on to the next one
+    pub(crate) verify_on_rotation: bool,
+    // index the logs of every WAL file once the writer moves on to the next one
+    pub(crate) segment_index: bool,
+    // re-check a random sealed file against its digest this often
+    pub(crate) scrub_interval: Option<Duration>,
+    // check of sealed files when the WAL is opened
+    pub(crate) open_verification: OpenVerification,
+    // threads deserializing logs in [Wal::read] and the payload bytes they decode at a time
+    pub(crate) decode_threads: usize,
+    pub(crate) decode_window: usize,
+    // layout of the metadata stored next to WAL files
+    pub(crate) manifest: ManifestKind,
+    // maximum number of logs and payload bytes of recent logs kept in memory
+    pub(crate) recent_cache: Option<(usize, usize)>,
+    // callback notified before logs are dropped to stay within the capacity
+    pub(crate) on_evict: Option<OnEvict>,
+    pub(crate) on_seal: Option<OnSeal>,
+    // callback notified of logs once they are durable
+    pub(crate) on_ack: Option<OnAck>,
+    // callback notified of every failure of the writer thread
+    pub(crate) on_error: Option<OnError>,
+    // receivers of flushes, rotations, failures and recoveries
+    pub(crate) listeners: Vec<Arc<dyn EventListener>>,
+    // snapshot saved before logs are dropped, keeping them until it succeeds
+    pub(crate) checkpointer: Option<SharedCheckpointer>,
+    // destination of files before their logs are dropped, keeping them until it succeeds
+    pub(crate) archiver: Option<SharedArchiver>,
+    pub(crate) capacity_warnings: Option<(Vec<f64>, OnCapacityWarning)>,
+    // runs the writer loop instead of a new thread
+    pub(crate) spawner: Option<Spawner>,
+    // times the writer is created again after it panics
+    pub(crate) writer_restarts: usize,
+    // writer thread of the WAL this one is a topic of
+    pub(crate) shared: Option<(SignalSender, WriterHandle)>,
+    // capacity of the topics opened with [Wal::topic], by name
+    pub(crate) topic_capacities: HashMap<String, usize>,
+    // open existing WAL files without writing to storage
+    pub(crate) read_only: bool,
+    // key of every log, from the log itself and from its serialized payload
+    pub(crate) key: Option<(TypedKey<T>, PayloadKey)>,
+    // accumulation of logs with the same key, from logs and from serialized payloads
+    pub(crate) merge: Option<(TypedMerge<T>, PayloadMerge)>,
+    // share of superseded logs in a sealed file that triggers its compaction
+    pub(crate) compaction_threshold: Option<f64>,
+    // store payloads larger than this once in the blob store
+    pub(crate) dedup_above: Option<usize>,
+    // size of files adapted to cover a time window
+    pub(crate) segment_window: Option<SegmentWindow>,
+    // size of every file instead of a share of the capacity
+    pub(crate) segment_size: Option<usize>,
+    // number and names of the WAL files
+    pub(crate) layout: Layout,
+    // number of WAL files set explicitly, instead of the number recorded in meta file
+    pub(crate) segments: Option<u8>,
+    // number of sealed files kept when the writer moves on to the next file
+    pub(crate) retain_segments: Option<usize>,
+    // how long sealed files are kept after their last log was written
+    pub(crate) retain_for: Option<Duration>,
+    // failures and delays injected in storage
+    #[cfg(feature = "chaos")]
+    pub(crate) chaos: Option<crate::Chaos>,
+    // faults injected in the files written by the writer thread, for crash tests
+    #[cfg(test)]
+    pub(crate) faults: Option<crate::faults::Faults>,
+    // private directory of a WAL held in memory, removed along with the last handle
+    pub(crate) ephemeral: Option<Arc<EphemeralDir>>,
+    // Phantom ownership of generic
+    phantom: PhantomData<T>,
+}
+
+impl<T> WalBuilder<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    /// Create a new builder
+    ///
+    /// # Arguments
+    /// - `location`: The location on storage where to store WAL files
+    /// - `capacity`: The size of WAL on storage in bytes, shared by every file but the one being
+    ///   reused
+    ///
+    pub fn new(location: &str, capacity: usize) -> Self {
+        Self {
+            location: PathBuf::from(location),
+            capacity,
+            positional_writes: false,
+            preallocate: false,
+            wake_strategy: WakeStrategy::Eager,
+            sync_policy: SyncPolicy::Never,
+            pacing: Pacing::default(),
+            rate_limit: None,
+            backpressure: None,
+            exclusive: true,
+            duplicate_guard: true,
+            slots: None,
+            fork_behavior: ForkBehavior::Fail,
+            compress_above: None,
+            cipher: None,
+            compression_codec: CompressionCodec::default(),
+            max_expansion: MAX_EXPANSION,
+            read_memory_cap: None,
+            read_unflushed: true,
+            read_cache: None,
+            read_scratch_budget: DEFAULT_SCRATCH_BUDGET,
+            memory_budget: None,
+            validation: Validation::default(),
+            codec: Codec::Bincode,
+            fallbacks: Vec::new(),
+            record_size: None,
+            max_entry_size: None,
+            framing: Framing::Native,
+            checksums: false,
+            timestamps: false,
+            strict_timestamps: false,
+            verify_on_rotation: false,
+            segment_index: false,
+            scrub_interval: None,
+            open_verification: OpenVerification::Skip,
+            decode_threads: 1,
+            decode_window: DEFAULT_DECODE_WINDOW,
+            manifest: ManifestKind::Single,
+            recent_cache: None,
+            on_evict: None,
+            on_seal: None,
+            on_ack: None,
+            on_error: None,
+            listeners: Vec::new(),
+            checkpointer: None,
+            archiver: None,
+            capacity_warnings: None,
+            spawner: None,
+            writer_restarts: 0,
+            shared: None,
+            topic_capacities: HashMap::new(),
+            read_only: false,
+            key: None,
+            merge: None,
+            compaction_threshold: None,
+            dedup_above: None,
+            segment_window: None,
+            segment_size: None,
+            layout: Layout::default(),
+            segments: None,
+            retain_segments: None,
+            retain_for: None,
+            #[cfg(feature = "chaos")]
+            chaos: None,
+            #[cfg(test)]
+            faults: None,
+            ephemeral: None,
+            phantom: Default::default(),
+        }
+    }
+
+    /// Create a builder of a WAL held in memory, see [Wal::in_memory]
+    ///
+    /// # Arguments
+    /// - `capacity`: The size of WAL in memory in bytes, shared by every file but the one being
+    ///   reused
+    ///
+    pub fn in_memory(capacity: usize) -> Self {
+        let dir = EphemeralDir::create();
+        let mut builder = Self::new("", capacity).storage(MemoryStorage::new());
+        builder.location = dir.path().to_path_buf();
+        builder.ephemeral = Some(Arc::new(dir));
+        builder
+    }
+
+    /// Write logs at an explicit offset tracked by the writer instead of opening log files in
+    /// append mode
+    ///
+    /// The offset of the current log file is recorded in the meta file after every flush. When the
+    /// WAL is opened again, the size of the log file is checked against the recorded offset and
+    /// [WalError::ExternalModification] is returned if the file was changed by someone else.
+    ///
+    /// Disabled by default
+    pub fn positional_writes(mut self, enabled: bool) -> Self {
+        self.positional_writes = enabled;
+        self
+    }
+
+    /// Reserve the storage of a whole file when the writer thread starts it
+    ///
+    /// Appends then fill blocks already allocated to the file, so they don't update the metadata
+    /// of the file system to allocate blocks, and syncs have less to write. The size of files
+    /// still grows with written logs. Space is reserved with `fallocate` on Linux, where the file
+    /// system supports it, and this option has no effect elsewhere.
+    ///
+    /// Disabled by default
+    pub fn preallocate(mut self, enabled: bool) -> Self {
+        self.preallocate = enabled;
+        self
+    }
+
+    /// Set how the writer thread wakes up after being notified of new logs
+    ///
+    /// Defaults to [WakeStrategy::Eager], which gives the lowest latency. Bursty producers can use
+    /// [WakeStrategy::MicroBatch] with a small delay (e.g. 200µs) to write larger batches.
+    /// Producers waiting for durability under high throughput can use
+    /// [WakeStrategy::GroupCommit], so many logs share the cost of a write and a sync.
+    pub fn wake_strategy(mut self, strategy: WakeStrategy) -> Self {
+        self.wake_strategy = strategy;
+        self
+    }
+
+    /// Set when the writer thread syncs written logs to storage
+    ///
+    /// See [SyncPolicy]. Defaults to [SyncPolicy::Never].
+    pub fn sync_policy(mut self, policy: SyncPolicy) -> Self {
+        self.sync_policy = policy;
+        self
+    }
+
+    /// Limit the rate of logs accepted from all handles of the WAL
+    ///
+    /// See [RateLimit]. Logs over the limit are rejected with [WalError::Throttled], or dropped
+    /// by [Wal::write] and [Wal::batch_write]. Single handles are limited with
+    /// [Wal::with_rate_limit]. Unlimited by default.
+    pub fn rate_limit(mut self, limit: RateLimit) -> Self {
+        self.rate_limit = Some(limit);
+        self
+    }
+
+    /// Bound the logs waiting for the writer thread
+    ///
+    /// See [Backpressure]. Logs that don't fit are rejected with [WalError::Backpressure], or
+    /// dropped by [Wal::write] and [Wal::batch_write], unless the bound blocks the caller or drops
+    /// older logs instead. Unbounded by default.
+    pub fn backpressure(mut self, bound: Backpressure) -> Self {
+        self.backpressure = Some(bound);
+        self
+    }
+
+    /// Limit the disk bandwidth and CPU time used by the writer thread
+    ///
+    /// See [Pacing]. Large backlogs are then written over a longer time instead of starving
+    /// co-located workloads, while new logs wait in the buffer. Unlimited by default.
+    pub fn pacing(mut self, pacing: Pacing) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// Compress logs whose serialized payload is larger than `bytes`
+    ///
+    /// Small logs don't benefit from compression, so they are always stored raw. The decision is
+    /// made for every log by the writer, and a log is also stored raw when compression doesn't
+    /// make it smaller. Compression is disabled by default.
+    #[cfg(any(feature = "lz4", feature = "deflate", feature = "zstd"))]
+    pub fn compress_above(mut self, bytes: usize) -> Self {
+        self.compress_above = Some(bytes);
+        self
+    }
+
+    /// Compress logs with `codec`
+    ///
+    /// Only logs written from now on use the codec. Every compressed log records its codec, so the
+    /// WAL remains readable after the codec changes between runs. Defaults to LZ4 when the `lz4`
+    /// feature is enabled, then to Zstandard when the `zstd` feature is, and to DEFLATE
+    /// otherwise.
+    #[cfg(any(feature = "lz4", feature = "deflate", feature = "zstd"))]
+    pub fn compression_codec(mut self, codec: CompressionCodec) -> Self {
+        self.compression_codec = codec;
+        self
+    }
+
+    /// Encrypt the payloads of logs at rest with ChaCha20-Poly1305 under `key`
+    ///
+    /// Payloads are sealed once compressed, while the sequence number, producer, timestamp and tag
+    /// of every log stay readable. Every file gets a random nonce recorded in its header, and a
+    /// file written in the clear is encrypted when it is written again. Opening a WAL holding
+    /// encrypted files without the key, or with another key, fails with
+    /// [WalError::Unsupported], as does combining encryption with
+    /// [WalBuilder::fixed_record_size], [Framing::LengthDelimited] or [WalBuilder::dedup_above].
+    /// Not encrypted by default.
+    #[cfg(feature = "encryption")]
+    pub fn encryption_key(mut self, key: [u8; 32]) -> Self {
+        self.cipher = Some(Cipher::new(key));
+        self
+    }
+
+    /// Reject compressed logs declaring a decompressed size larger than `factor` times their
+    /// compressed size
+    ///
+    /// A corrupted or tampered size declaration could otherwise make a read allocate gigabytes.
+    /// Such logs are skipped when reading, like any corrupted frame, and the writer stores logs
+    /// raw rather than compressing them beyond the factor. Defaults to 255, the largest expansion
+    /// of LZ4, so only frames that can't be valid are rejected.
+    #[cfg(any(feature = "lz4", feature = "deflate", feature = "zstd"))]
+    pub fn max_expansion(mut self, factor: usize) -> Self {
+        self.max_expansion = factor.max(1);
+        self
+    }
+
+    /// Limit the memory used to hold logs during [Wal::read_bounded] to `bytes` of serialized
+    /// payload
+    ///
+    /// When more logs are stored, they are spilled to a temporary file and decoded lazily while
+    /// iterating. Unlimited by default.
+    pub fn read_memory_cap(mut self, bytes: usize) -> Self {
+        self.read_memory_cap = Some(bytes);
+        self
+    }
+
+    /// Whether reads include the logs accepted but not yet written to a file by the writer
+    /// thread, after the logs of the files
+    ///
+    /// Handles then read their own writes right away, without waiting for a flush. Without,
+    /// reads only return logs already written to storage, which survive a crash of the process.
+    /// Applies to [Wal::read] and the other methods reading logs, except [Wal::read_segments]
+    /// and [Wal::read_page], which only return logs of files. Enabled by default.
+    pub fn read_unflushed(mut self, enabled: bool) -> Self {
+        self.read_unflushed = enabled;
+        self
+    }
+
+    /// Keep the logs of the last [Wal::read_cached] in memory, until new logs are written
+    ///
+    /// Meant for applications reading the whole WAL over and over, such as health endpoints.
+    /// Reads of more than `bytes` of serialized payload aren't kept. Disabled by default.
+    pub fn read_cache(mut self, bytes: usize) -> Self {
+        self.read_cache = Some(bytes);
+        self
+    }
+
+    /// Keep up to `bytes` of buffer memory allocated between reads
+    ///
+    /// Reads load raw file content into a scratch buffer shared by all handles of the WAL, which
+    /// is reused by the next read instead of allocating a new one. A read may grow the buffer
+    /// beyond the budget, but the excess is released once the read is over, which keeps frequent
+    /// [Wal::read_last] calls from reallocating. Defaults to 64 KiB.
+    pub fn read_scratch_budget(mut self, bytes: usize) -> Self {
+        self.read_scratch_budget = bytes;
+        self
+    }
+
+    /// Cap the memory held by the WAL to `bytes`, across all its handles
+    ///
+    /// The budget covers the payload of logs waiting for the writer thread, the recent cache and
+    /// the scratch buffer kept between reads. The scratch buffer is kept within the budget, the
+    /// recent cache drops its oldest logs to make room for new ones, and writes are rejected with
+    /// [WalError::Capacity] while the logs waiting for the writer would exceed what is left.
+    /// Current usage is reported by [Wal::memory_usage]. Unlimited by default.
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = Some(bytes);
+        self
+    }
+
+    /// Enforce the given rules on every log before it is accepted
+    ///
+    /// Logs breaking a rule are rejected with [WalError::Rejected], which is reported by
+    /// [Wal::try_write] and [Wal::try_write_all]. No rules are enforced by default.
+    pub fn strict(mut self, rules: Validation) -> Self {
+        self.validation = rules;
+        self
+    }
+
+    /// Store logs as self-describing payloads tagged with their type name and `version`
+    ///
+    /// Every payload is JSON holding the type name, the version and the log itself, adjacently
+    /// tagged. Logs take more space than with the default bincode encoding, but they can be read
+    /// without knowing their type with [Wal::read_dynamic], inspected by other applications, and
+    /// decoded into a newer version of the type that adds fields with defaults. The encoding is
+    /// recorded in the WAL directory, and opening the WAL with another encoding fails.
+    #[cfg(feature = "self-describing")]
+    pub fn self_describing(mut self, version: u32) -> Self {
+        self.codec = Codec::Tagged { version };
+        self
+    }
+
+    /// Serialize logs with `codec` instead of bincode
+    ///
+    /// JSON and MessagePack take more space than bincode, but the WAL files can be read by
+    /// applications not written in Rust, and they handle types bincode can't, such as untagged
+    /// enums and maps with flattened fields. Like [WalBuilder::self_describing], the codec is
+    /// recorded in the WAL directory, and opening the WAL with another codec fails.
+    pub fn serialization_codec(mut self, codec: SerializationCodec) -> Self {
+        self.codec = codec.into();
+        self
+    }
+
+    /// Read logs that don't deserialize as `T` as an older type `U`, converted with `migrate`
+    ///
+    /// Reads try `T` first, then every fallback in the order they were registered, until one
+    /// deserializes the log, so logs written as older types are read without rewriting them.
+    /// Register newer types first: a bincode payload also deserializes as any type made of the
+    /// first fields of its own type. [Wal::read_reported] counts the logs handled by every
+    /// decoder. Keys and merges of [WalBuilder::keyed] only apply to logs of type `T`. Logs
+    /// written with [WalBuilder::versioned] are migrated with [Migrate] instead.
+    ///
+    /// # Example
+    /// ```
+    /// use serde::{Deserialize, Serialize};
+    /// use walcraft::WalBuilder;
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct OrderV1 {
+    ///     id: u64,
+    /// }
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct Order {
+    ///     id: u64,
+    ///     amount: u64,
+    /// }
+    ///
+    /// # std::fs::create_dir_all("./tmp/decode_fallback_doc/").unwrap();
+    /// let wal = WalBuilder::<Order>::new("./tmp/decode_fallback_doc/", 500)
+    ///     .decode_fallback(|old: OrderV1| Order { id: old.id, amount: 0 })
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn decode_fallback<U, F>(mut self, migrate: F) -> Self
+    where
+        U: for<'a> Deserialize<'a>,
+        F: Fn(U) -> T + Send + Sync + 'static,
+    {
+        self.fallbacks
+            .push(Arc::new(move |codec: Codec, payload: &[u8]| {
+                codec.decode_payload::<U>(payload).map(&migrate)
+            }));
+        self
+    }
+
+    /// Write logs behind an envelope holding the tag and version of `T`, migrating logs
+    /// written with older versions of `T` when read
+    ///
+    /// Logs are serialized with bincode after a header of [Migrate::TAG] and
+    /// [Migrate::VERSION]. Reads deserialize logs of the current version as `T` and hand logs of
+    /// other versions to [Migrate::migrate], so changing the fields of `T` doesn't turn older
+    /// logs into unreadable ones. Logs written with another tag are skipped. Like
+    /// [WalBuilder::serialization_codec], the encoding is recorded in the WAL directory, so it
+    /// must be chosen before the first log is written.
+    ///
+    /// # Example
+    /// ```
+    /// use serde::{Deserialize, Serialize};
+    /// use walcraft::{Envelope, Migrate, WalBuilder};
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct Order {
+    ///     id: u64,
+    /// }
+    ///
+    /// impl Migrate for Order {
+    ///     const VERSION: u8 = 1;
+    ///
+    ///     fn migrate(_: Envelope<'_>) -> Option<Self> {
+    ///         None
+    ///     }
+    /// }
+    ///
+    /// # let _ = std::fs::remove_dir_all("./tmp/versioned_doc/");
+    /// # std::fs::create_dir_all("./tmp/versioned_doc/").unwrap();
+    /// let wal = WalBuilder::<Order>::new("./tmp/versioned_doc/", 500)
+    ///     .versioned()
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn versioned(mut self) -> Self
+    where
+        T: Migrate,
+    {
+        self.codec = Codec::Enveloped {
+            tag: T::TAG,
+            version: T::VERSION,
+        };
+        // tried ahead of the fallbacks, which expect logs of the current version
+        self.fallbacks.insert(
+            0,
+            Arc::new(|_: Codec, payload: &[u8]| {
+                let log = Envelope::parse(payload)?;
+                match log.tag() == T::TAG && log.version() != T::VERSION {
+                    true => T::migrate(log),
+                    false => None,
+                }
+            }),
+        );
+        self
+    }
+
+    /// Store every log as a fixed size record of `bytes` bytes
+    ///
+    /// Frames have no length prefix and shorter payloads are padded, so the position of any log
+    /// is computed arithmetically, making [Wal::get] and [Wal::read_last] O(1) without an index.
+    /// Logs with a serialized payload larger than `bytes` are rejected, and logs are never
+    /// compressed. The same record size must be used every time the WAL is opened.
+    pub fn fixed_record_size(mut self, bytes: usize) -> Self {
+        self.record_size = Some(bytes);
+        self
+    }
+
+    /// Reject logs whose serialized payload is larger than `bytes`
+    ///
+    /// Writes of such logs fail with [crate::Rejection::PayloadTooLarge], whether or not the WAL
+    /// is [WalBuilder::strict], and raw payloads are held to the same limit. Without a limit, a
+    /// batch of logs larger than a whole WAL file is written to a file of its own, which holds
+    /// more than its share of the capacity until it's reused. Unlimited by default.
+    pub fn max_entry_size(mut self, bytes: usize) -> Self {
+        self.max_entry_size = Some(bytes);
+        self
+    }
+
+    /// Encoding of the frames in WAL files
+    ///
+    /// With [Framing::LengthDelimited], WAL files are length-delimited protobuf streams when the
+    /// payloads are protobuf messages written with [Wal::try_write_raw], and can be consumed
+    /// directly by non-Rust tooling. Ignored when [WalBuilder::fixed_record_size] is set. The
+    /// same framing must be used every time the WAL is opened. Defaults to [Framing::Native].
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    /// Guard the frame of every log with a CRC-32 checksum
+    ///
+    /// The checksum takes [crate::format::CHECKSUM_BYTES] of storage per log and covers the whole
+    /// frame, so a log damaged on storage is skipped by reads instead of being deserialized from
+    /// the damaged bytes. Skipped logs are counted in [crate::ReadMetrics::corrupted] of
+    /// [Wal::last_read], and reported by [Wal::verify] in [crate::VerifyReport::corrupt_frames].
+    /// Ignored with [WalBuilder::fixed_record_size] or [Framing::LengthDelimited]. The setting is
+    /// recorded in the header of every file, so changing it between runs only applies to the
+    /// files started afterwards. Files written before headers were introduced are read with the
+    /// current setting. Disabled by default.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::WalBuilder;
+    ///
+    /// # std::fs::create_dir_all("./tmp/checksums_doc/").unwrap();
+    /// let wal = WalBuilder::<u64>::new("./tmp/checksums_doc/", 500)
+    ///     .checksums(true)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn checksums(mut self, enabled: bool) -> Self {
+        self.checksums = enabled;
+        self
+    }
+
+    /// Record in its frame when every log was written, as the wall clock of the machine
+    ///
+    /// The timestamp takes [crate::format::TIMESTAMP_BYTES] of storage per log, and the time
+    /// range of the logs of every file is recorded in meta when the file is sealed, so
+    /// [Wal::read_range] only reads the files holding logs of the range. Stamps never go back
+    /// while the WAL is open: when the wall clock is set back, logs keep the last stamp handed
+    /// out until the clock catches up with it. Logs written at the same time from several
+    /// threads may still be stamped slightly out of the order of their sequence numbers, unless
+    /// [WalBuilder::strict_timestamps] is set. Ignored with [WalBuilder::fixed_record_size] or
+    /// [Framing::LengthDelimited]. Disabled by default.
+    pub fn timestamps(mut self, enabled: bool) -> Self {
+        self.timestamps = enabled;
+        self
+    }
+
+    /// Stamp logs in the order of their sequence numbers, with a strictly increasing monotonic
+    /// time next to the stamp and the wall clock
+    ///
+    /// Enables [WalBuilder::timestamps]. The writer thread stamps every log no earlier than the
+    /// log before it, including the newest log of the WAL when it's opened again, e.g. after the
+    /// clock was set back while it was closed. Time then only goes forward from one log to the
+    /// next for [Wal::read_range] and [WalBuilder::retain_for], while stamps keep following the
+    /// wall clock however fast logs are written. Logs stamped within the same millisecond are
+    /// told apart by their sequence number, and by a monotonic time to the nanosecond: the wall
+    /// clock when the WAL was opened, moved forward by the monotonic clock of the machine, and
+    /// strictly increasing from one log to the next. The reading of the wall clock when every
+    /// log was written is kept as well, and both take [crate::format::WALL_CLOCK_BYTES] and
+    /// [crate::format::MONOTONIC_BYTES] of storage per log. They are reported along with the
+    /// stamp by [Wal::read_attributed]. Disabled by default.
+    pub fn strict_timestamps(mut self, enabled: bool) -> Self {
+        self.strict_timestamps = enabled;
+        self
+    }
+
+    /// Verify every WAL file once the writer rotates to the next one
+    ///
+    /// The sealed file is read back from storage, checked to hold exactly the frames written to
+    /// it and checksummed. The result is recorded in the meta file and reported by
+    /// [Wal::segment_digests]. Verification adds a full read of every file on rotation and is
+    /// disabled by default.
+    pub fn verify_on_rotation(mut self, enabled: bool) -> Self {
+        self.verify_on_rotation = enabled;
+        self
+    }
+
+    /// Index the logs of every WAL file once the writer rotates to the next one
+    ///
+    /// The index of a file is stored in `index_N` next to `wal_N`, and holds where every block of
+    /// 64 logs starts along with their sequence numbers and timestamps. [Wal::read_from],
+    /// [Wal::read_range], [Wal::read_last] and [Wal::get] then only read the blocks holding the
+    /// logs they look for instead of whole files. Indexing adds a full read of every file on
+    /// rotation and is disabled by default. Files without an index are read whole, see
+    /// [Wal::rebuild_index] to index them. Ignored with [WalBuilder::fixed_record_size], whose
+    /// logs are found without an index, or [Framing::LengthDelimited].
+    pub fn segment_index(mut self, enabled: bool) -> Self {
+        self.segment_index = enabled;
+        self
+    }
+
+    /// Check a random sealed file against its digest every `interval` on a background thread
+    ///
+    /// The scrubber finds bit rot in old files before their logs are needed for recovery, and
+    /// reports it through [Wal::health]. It reads a single file per interval to keep its impact
+    /// on the application low, and stops once all handles of the WAL are dropped. Enables
+    /// [WalBuilder::verify_on_rotation], as only files with a digest can be checked.
+    pub fn scrub_interval(mut self, interval: Duration) -> Self {
+        self.scrub_interval = Some(interval);
+        self.verify_on_rotation = true;
+        self
+    }
+
+    /// Check sealed files against their digest when the WAL is opened, as chosen by `mode`
+    ///
+    /// Corrupted files are reported through [Wal::health], and progress through [Wal::stats].
+    /// Checking every file of a large WAL takes a while, so [OpenVerification::Deferred] lets
+    /// logs be written right away while files are checked in the background. Enables
+    /// [WalBuilder::verify_on_rotation], as only files with a digest can be checked. Files are
+    /// not checked by default.
+    pub fn verify_on_open(mut self, mode: OpenVerification) -> Self {
+        self.open_verification = mode;
+        self.verify_on_rotation |= mode != OpenVerification::Skip;
+        self
+    }
+
+    /// Deserialize logs read by [Wal::read] on `threads` worker threads
+    ///
+    /// Logs are decoded in windows of `window` bytes of serialized payload, split between the
+    /// threads and merged back in their original order, so CPU-bound deserialization of a large
+    /// WAL is spread across cores while only one window is in flight. [Wal::read_from],
+    /// [Wal::read_range] and [Wal::read_segments], which recover from or process large parts of
+    /// the WAL, are decoded the same way. Logs are decoded on the calling thread by default.
+    pub fn parallel_decode(mut self, threads: usize, window: usize) -> Self {
+        self.decode_threads = threads;
+        self.decode_window = window;
+        self
+    }
+
+    /// Layout used to store the current file pointer, write offset and digests
+    ///
+    /// Only applies to a new WAL. When existing WAL files are opened, the layout they were
+    /// written with is detected and kept. Defaults to [ManifestKind::Single].
+    pub fn manifest(mut self, kind: ManifestKind) -> Self {
+        self.manifest = kind;
+        self
+    }
+
+    /// Keep the last `entries` logs, up to `bytes` of serialized payload, in memory
+    ///
+    /// [Wal::read_last] is answered from memory and the buffer when enough logs are held, without
+    /// reading files. Disabled by default.
+    pub fn recent_cache(mut self, entries: usize, bytes: usize) -> Self {
+        self.recent_cache = Some((entries, bytes));
+        self
+    }
+
+    /// Call `f` from the writer thread whenever a WAL file is about to be reused to stay within
+    /// the capacity
+    ///
+    /// The [Eviction] describes the logs that are dropped, so systems consuming the WAL can
+    /// check that they had already processed that range. The writer is blocked while `f` runs.
+    pub fn on_evict<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&Eviction) + Send + Sync + 'static,
+    {
+        self.on_evict = Some(Arc::new(f));
+        self
+    }
+
+    /// Save a snapshot with `checkpointer` before logs are dropped from the WAL
+    ///
+    /// The writer thread calls it with the range of logs of a file about to be reused to stay
+    /// within the capacity, or removed by [WalBuilder::retain_segments], so the state built from
+    /// these logs is persisted before they become unrecoverable. While the checkpoint fails, the
+    /// logs are kept: the writer goes on writing to the current file beyond its capacity, and
+    /// tries again after every batch. The writer is blocked while the checkpoint runs, so it must
+    /// not flush or write to the WAL.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::WalBuilder;
+    ///
+    /// # std::fs::create_dir_all("./tmp/checkpointer_doc/").unwrap();
+    /// let wal = WalBuilder::<u64>::new("./tmp/checkpointer_doc/", 500)
+    ///     .checkpointer(|lsns: std::ops::RangeInclusive<u64>| {
+    ///         // persist the state covering logs up to lsns.end()
+    ///         Ok(())
+    ///     })
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn checkpointer<C>(mut self, checkpointer: C) -> Self
+    where
+        C: Checkpointer + 'static,
+    {
+        self.checkpointer = Some(Arc::new(checkpointer));
+        self
+    }
+
+    /// Hand every WAL file to `archiver` before its logs are dropped from the WAL
+    ///
+    /// The writer thread calls it with a file about to be reused to stay within the capacity, or
+    /// removed by [WalBuilder::retain_segments] or [WalBuilder::retain_for], once any
+    /// [WalBuilder::checkpointer] succeeded. The file is left in place while `archiver` copies or
+    /// uploads it, e.g. to object storage. While archiving fails, the logs are kept as they are
+    /// with a failing checkpoint, and the failure is reported to [WalBuilder::on_error]. The
+    /// writer is blocked while the archiver runs. Files emptied by [Wal::truncate] are not
+    /// archived.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::{ArchivedSegment, WalBuilder};
+    ///
+    /// # std::fs::create_dir_all("./tmp/archiver_doc/").unwrap();
+    /// let wal = WalBuilder::<u64>::new("./tmp/archiver_doc/", 500)
+    ///     .archiver(|segment: &ArchivedSegment| {
+    ///         // upload segment.path, holding logs segment.lsns
+    ///         Ok(())
+    ///     })
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn archiver<A>(mut self, archiver: A) -> Self
+    where
+        A: Archiver + 'static,
+    {
+        self.archiver = Some(Arc::new(archiver));
+        self
+    }
+
+    /// Copy every WAL file to the directory `dir` before its logs are dropped, see
+    /// [WalBuilder::archiver]
+    ///
+    /// Copies are named `wal_<first>-<last>` after the sequence numbers of their first and last
+    /// log, and are synced before the file is reused. The directory is created if needed.
+    pub fn archive_dir(self, dir: impl AsRef<Path>) -> Self {
+        self.archiver(DirectoryArchiver::new(dir.as_ref()))
+    }
+
+    /// Call `f` from the writer thread before logs are dropped to stay within the capacity
+    ///
+    /// A [CapacityWarning::Usage] is fired when the bytes stored in the WAL files cross any of
+    /// `thresholds`, given as shares of the capacity such as `0.8` and `0.95`, and again once
+    /// usage fell below a threshold and crosses it again. Once the WAL wrapped around, usage
+    /// stays close to the capacity. A [CapacityWarning::UnconsumedEviction] is fired when the
+    /// writer moves on to a file, if the file reused after it holds logs not yet read by a handle
+    /// consuming the WAL with [Wal::entries_since], or not yet committed by a [Wal::consumer],
+    /// leaving a whole file of time to catch up. The writer is blocked while `f` runs.
+    pub fn capacity_warnings<F>(mut self, thresholds: &[f64], f: F) -> Self
+    where
+        F: Fn(&CapacityWarning) + Send + Sync + 'static,
+    {
+        self.capacity_warnings = Some((thresholds.to_vec(), Arc::new(f)));
+        self
+    }
+
+    /// Call `f` from the writer thread whenever it fails to store logs or metadata
+    ///
+    /// The [WriteFailure] tells what failed, such as a write to a full disk, and which logs were
+    /// lost with it. The last failure is also kept for [Wal::last_error]. The writer is blocked
+    /// while `f` runs.
+    pub fn on_error<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&WriteFailure) + Send + Sync + 'static,
+    {
+        self.on_error = Some(Arc::new(f));
+        self
+    }
+
+    /// Send flushes, rotations, failures and recoveries of the WAL to `listener`
+    ///
+    /// Meant to wire the WAL into the logging and metrics of the application. Listeners are
+    /// called in the order they were added, see [EventListener] for the thread each event is
+    /// sent from.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::{EventListener, FlushEvent, WalBuilder};
+    ///
+    /// struct Logger;
+    ///
+    /// impl EventListener for Logger {
+    ///     fn on_flush(&self, flush: &FlushEvent) {
+    ///         println!("{} logs written to wal_{}", flush.entries, flush.segment);
+    ///     }
+    /// }
+    ///
+    /// # std::fs::create_dir_all("./tmp/event_listener_doc/").unwrap();
+    /// let wal = WalBuilder::<u64>::new("./tmp/event_listener_doc/", 500)
+    ///     .event_listener(Logger)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn event_listener<L>(mut self, listener: L) -> Self
+    where
+        L: EventListener + 'static,
+    {
+        self.listeners.push(Arc::new(listener));
+        self
+    }
+
+    /// Call `f` from the writer thread once for every file the writer leaves behind, as it
+    /// moves on to the next file
+    ///
+    /// The [RotationEvent] tells where the file is, which logs it holds and its size, so
+    /// processing such as shipping files to cold storage runs once per file, while no log is
+    /// added to it anymore. Like [WalBuilder::event_listener], with only the rotations. The
+    /// writer is blocked while `f` runs.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::WalBuilder;
+    ///
+    /// # std::fs::create_dir_all("./tmp/on_rotate_doc/").unwrap();
+    /// let wal = WalBuilder::<u64>::new("./tmp/on_rotate_doc/", 500)
+    ///     .on_rotate(|rotation| println!("{} logs sealed in {:?}", rotation.entries, rotation.path))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn on_rotate<F>(self, f: F) -> Self
+    where
+        F: Fn(&RotationEvent) + Send + Sync + 'static,
+    {
+        self.event_listener(OnRotate(f))
+    }
+
+    /// Call `f` from the writer thread whenever a WAL file is sealed, as the writer moves on to
+    /// the next file
+    ///
+    /// The [SegmentSealed] event carries the digest of the file and its link in the hash chain of
+    /// sealed files. Keeping the links outside of the WAL, such as in an audit trail, allows
+    /// [Wal::verify_chain] to prove that sealed files weren't changed since. Also enables
+    /// [WalBuilder::verify_on_rotation], as files are sealed only when their digest is recorded.
+    /// The writer is blocked while `f` runs.
+    pub fn on_seal<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&SegmentSealed) + Send + Sync + 'static,
+    {
+        self.on_seal = Some(Arc::new(f));
+        self.verify_on_rotation = true;
+        self
+    }
+
+    /// Call `f` with the sequence numbers of written logs once they are durable, as the
+    /// [SyncPolicy] makes them
+    ///
+    /// Logs are acknowledged in the order of their sequence numbers: once a log is acknowledged,
+    /// every older log written by the WAL was acknowledged before, even when syncs run on a thread
+    /// of their own. Replicated state machines can apply logs as they are acknowledged without
+    /// reordering them. Without a sync policy, logs are acknowledged once written to a file, and
+    /// otherwise once synced, by the policy or by [Wal::flush]. Logs lost by a failed write or
+    /// sync are never acknowledged. `f` runs on the thread completing the write or sync, which is
+    /// blocked while it runs.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::{SyncPolicy, WalBuilder};
+    ///
+    /// # std::fs::create_dir_all("./tmp/on_ack_doc/").unwrap();
+    /// let wal = WalBuilder::<u64>::new("./tmp/on_ack_doc/", 500)
+    ///     .sync_policy(SyncPolicy::EveryWrite)
+    ///     .on_ack(|lsns| println!("logs {:?} are durable", lsns))
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    pub fn on_ack<F>(mut self, f: F) -> Self
+    where
+        F: Fn(RangeInclusive<Lsn>) + Send + Sync + 'static,
+    {
+        self.on_ack = Some(Arc::new(f));
+        self
+    }
+
+    /// Run the writer with `spawn` instead of on a new thread
+    ///
+    /// `spawn` receives the writer loop and must run it on a thread of its choice, such as a
+    /// named thread, a managed thread pool or a thread with a custom panic hook. The loop runs
+    /// for as long as the WAL exists, so a pool must dedicate a thread to it. [WalBuilder::build]
+    /// waits until the loop has started, and fails if `spawn` returns an error or drops the loop.
+    pub fn spawner<F>(mut self, spawn: F) -> Self
+    where
+        F: Fn(Box<dyn FnOnce() + Send>) -> std::io::Result<()> + Send + Sync + 'static,
+    {
+        self.spawner = Some(Arc::new(spawn));
+        self
+    }
+
+    /// Create the writer again, up to `times` times, when it panics
+    ///
+    /// A panic of the writer, such as in a callback it runs, is reported to
+    /// [WalBuilder::on_error] and to [Wal::last_error] with [crate::WriteOperation::Panic]. The writer is
+    /// then restarted from the files, as when the WAL is opened, and logs of the batch it was
+    /// writing are lost. Once no restart is left, writes, flushes and reads fail with
+    /// [WalError::WriterDead] until the WAL is opened again. Topics, see [Wal::topic], share the
+    /// thread of their WAL and go on whatever happens to it. Defaults to 0.
+    pub fn restart_writer(mut self, times: usize) -> Self {
+        self.writer_restarts = times;
+        self
+    }
+
+    /// Capacity of the topic `name` opened with [Wal::topic], in bytes
+    ///
+    /// Topics without a capacity of their own get the capacity of the WAL.
+    pub fn topic_capacity(mut self, name: &str, capacity: usize) -> Self {
+        self.topic_capacities.insert(name.to_string(), capacity);
+        self
+    }
+
+    /// Fail to open the WAL with [WalError::Locked] while another process writes to it
+    ///
+    /// Processes writing to the same directory at the same time corrupt the WAL. With this
+    /// option, the process opening the WAL takes an OS lock on the `lease` file of the directory,
+    /// which it holds until all its handles are dropped or it exits, even by crashing. Handles
+    /// opened without this option don't take the lock, and a WAL opened again while a handle of
+    /// it is alive fails as well, unless one of them disables it. Read-only handles, see
+    /// [WalBuilder::read_only], never take it. Enabled by default.
+    pub fn exclusive(mut self, enabled: bool) -> Self {
+        self.exclusive = enabled;
+        self
+    }
+
+    /// Fail to open the WAL with [WalError::AlreadyOpen] while a handle of this process writes
+    /// to it
+    ///
+    /// Opening a directory twice starts two writer threads writing to the same files, so handles
+    /// are meant to be cloned instead. The directory is resolved to its canonical path, so it's
+    /// recognized through any path leading to it. Unlike [WalBuilder::exclusive], nothing is
+    /// written to the directory. Read-only handles, see [WalBuilder::read_only], are never
+    /// refused. Enabled by default.
+    pub fn duplicate_guard(mut self, enabled: bool) -> Self {
+        self.duplicate_guard = enabled;
+        self
+    }
+
+    /// Let up to `slots` processes write to the WAL at the same time
+    ///
+    /// Every handle opened this way leases the first free slot, a `slot_<id>` directory of the
+    /// WAL holding files of its own, and fails with [WalError::Locked] once all of them are
+    /// leased. Slots are leased like [WalBuilder::exclusive] and [WalBuilder::duplicate_guard]
+    /// lock a directory, whatever these options, so handles of the same process get slots of
+    /// their own too. After every batch, the writer appends the range of its logs to the `slots`
+    /// journal of the WAL under an OS lock, so [Wal::read_merged] reads the logs of every slot in
+    /// the order they were written, numbered across slots. Other reads only see the logs of the
+    /// slot of the handle. Read-only handles, see [WalBuilder::read_only], lease no slot. All
+    /// processes should open the WAL with the same options.
+    ///
+    /// # Examples
+    /// ```
+    /// use walcraft::WalBuilder;
+    ///
+    /// # std::fs::create_dir_all("./tmp/slots_doc/").unwrap();
+    /// // as opened by two processes
+    /// let first = WalBuilder::new("./tmp/slots_doc/", 1000).slots(2).build().unwrap();
+    /// let second = WalBuilder::new("./tmp/slots_doc/", 1000).slots(2).build().unwrap();
+    /// first.write("from the first".to_string());
+    /// first.flush().unwrap();
+    /// second.write("from the second".to_string());
+    /// second.flush().unwrap();
+    ///
+    /// let logs = first.read_merged().unwrap();
+    /// assert_eq!(logs[logs.len() - 2].log, Some("from the first".to_string()));
+    /// assert_eq!(logs[logs.len() - 1].log, Some("from the second".to_string()));
+    /// ```
+    pub fn slots(mut self, slots: u8) -> Self {
+        self.slots = Some(slots);
+        self
+    }
+
+    /// Open the WAL without ever writing to storage, e.g. for analysis of a directory mounted
+    /// read-only
+    ///
+    /// No writer thread is started and the lock of [WalBuilder::exclusive] isn't taken, so the
+    /// WAL can be read while another process writes to it. Writes fail right away with
+    /// [WalError::ReadOnlyFilesystem], which is also returned when a WAL on a read-only file
+    /// system is opened without this option.
+    pub fn read_only(mut self, enabled: bool) -> Self {
+        self.read_only = enabled;
+        self
+    }
+
+    /// Set how handles behave when used in a child process forked after the WAL was opened
+    ///
+    /// File descriptors are shared with the parent but the writer thread doesn't survive the
+    /// fork, so writing from the child would corrupt the WAL. Handles compare the current process
+    /// id with the one that opened the WAL on every operation. Defaults to [ForkBehavior::Fail];
+    /// daemonizing applications should open the WAL again after forking.
+    pub fn on_fork(mut self, behavior: ForkBehavior) -> Self {
+        self.fork_behavior = behavior;
+        self
+    }
+
+    /// Identify every log by the key returned by `key`, so a log supersedes older logs with the
+    /// same key
+    ///
+    /// Superseded logs are dropped by [Wal::compact], or on their own with
+    /// [WalBuilder::compaction_threshold].
+    pub fn keyed<F, K>(mut self, key: F) -> Self
+    where
+        F: Fn(&T) -> K + Send + Sync + 'static,
+        K: Hash,
+        T: 'static,
+    {
+        let typed: TypedKey<T> = Arc::new(move |log: &T| compaction::hash_key(&key(log)));
+        let decode = typed.clone();
+        let raw: PayloadKey = Box::new(move |codec: Codec| -> KeyFn {
+            Arc::new(move |payload: &[u8]| {
+                codec.decode_payload::<T>(payload).map(|log| decode(&log))
+            })
+        });
+        self.key = Some((typed, raw));
+        self
+    }
+
+    /// Accumulate logs with the same key with `merge`, called with the older and the newer log,
+    /// instead of keeping only the last one
+    ///
+    /// Requires [WalBuilder::keyed]. Compaction merges the logs of every key in a sealed file
+    /// into its last one, so every file holds at most one log per key, and logs are no longer
+    /// dropped for being superseded by logs of later files. [Wal::get_by_key] merges the
+    /// remaining logs of the key. Logs of atomic batches are never merged. Useful to keep
+    /// counters or sets bounded in size, as long as `merge` is associative.
+    pub fn merge<F>(mut self, merge: F) -> Self
+    where
+        F: Fn(T, T) -> T + Send + Sync + 'static,
+        T: 'static,
+    {
+        let typed: TypedMerge<T> = Arc::new(merge);
+        let apply = typed.clone();
+        let raw: PayloadMerge = Box::new(move |codec: Codec| -> MergeFn {
+            Arc::new(move |old: &[u8], new: &[u8]| {
+                let old = codec.decode_payload::<T>(old)?;
+                let new = codec.decode_payload::<T>(new)?;
+                let merged = codec.encode(&apply(old, new)).ok()?;
+                Some(merged.into_payload())
+            })
+        });
+        self.merge = Some((typed, raw));
+        self
+    }
+
+    /// Compact a sealed file once the estimated share of its logs superseded by later logs
+    /// exceeds `ratio`, between 0 and 1
+    ///
+    /// Only applies to [WalBuilder::keyed] WALs. Superseded logs are estimated by the writer with
+    /// a bloom filter of the keys of every file, starting afresh when the WAL is opened. The
+    /// compaction itself is exact and runs on the writer thread when it moves to the next file.
+    pub fn compaction_threshold(mut self, ratio: f64) -> Self {
+        self.compaction_threshold = Some(ratio);
+        self
+    }
+
+    /// Store serialized payloads larger than `bytes` once, however many logs hold them
+    ///
+    /// Such payloads are written to the `blobs` directory of the WAL under their hash, and their
+    /// frames only hold a reference to the blob, which reads resolve transparently. Useful when
+    /// the same large attachment is logged many times. Blobs no longer referenced are removed
+    /// when the writer moves on to the next file. Ignored with
+    /// [WalBuilder::fixed_record_size] or [Framing::LengthDelimited].
+    pub fn dedup_above(mut self, bytes: usize) -> Self {
+        self.dedup_above = Some(bytes);
+        self
+    }
+
+    /// Number of WAL files, reused in turn. 5 by default, and at least 2.
+    ///
+    /// Every file takes `capacity / (count - 1)` bytes, so more files drop fewer logs at a time
+    /// when the oldest file is reused. The number is recorded in the meta file, and an existing
+    /// WAL opened without this option keeps the number of files it was written with. A WAL must
+    /// be opened with at least the number of files it holds, and opening it with fewer fails
+    /// with [WalError::Unsupported].
+    pub fn segments(mut self, count: u8) -> Self {
+        self.segments = Some(count);
+        self
+    }
+
+    /// Keep only the `count` newest sealed WAL files, on top of the file being written
+    ///
+    /// Whenever the writer moves on to the next file, older files holding logs are removed as
+    /// [Wal::truncate] would, so the WAL holds the logs of the last `count` files whatever their
+    /// size. The capacity still applies, so the WAL never keeps more files than
+    /// [WalBuilder::segments] allows. Removed files are reported to [WalBuilder::on_evict].
+    pub fn retain_segments(mut self, count: usize) -> Self {
+        self.retain_segments = Some(count);
+        self
+    }
+
+    /// Keep sealed WAL files for `period` after their last log was written, e.g. 24 hours of logs
+    ///
+    /// The time range of the logs of every file is recorded in meta when the file is sealed. The
+    /// writer thread removes expired files as [Wal::truncate] would, whether logs keep coming or
+    /// not, and checks again when the WAL is opened. Files sealed before the range was recorded
+    /// expire by the time they were last modified. The capacity still applies, so files may be
+    /// reused before they expire. Removed files are reported to [WalBuilder::on_evict].
+    pub fn retain_for(mut self, period: Duration) -> Self {
+        self.retain_for = Some(period);
+        self
+    }
+
+    /// Move sealed WAL files to `location`, usually on a cheaper or larger disk, once `after`
+    /// newer files were sealed
+    ///
+    /// The file being written and the `after` files sealed last stay in the WAL directory, and
+    /// reads find every file in either place. A file is copied when the locations are on
+    /// different disks, and only removed from the WAL directory once the copy is complete. A
+    /// file that fails to move is reported to [WalBuilder::on_error] and stays where it is. The
+    /// WAL must be opened with the same cold location to read the files moved there.
+    pub fn cold_storage(mut self, location: &str, after: usize) -> Self {
+        self.layout.cold = Some(ColdTier {
+            location: Path::new(location).into(),
+            after,
+        });
+        self
+    }
+
+    /// Inject the failures and delays of `chaos` in the storage of the WAL, for chaos tests
+    ///
+    /// See [crate::Chaos]. Only available with the `chaos` feature.
+    #[cfg(feature = "chaos")]
+    pub fn chaos(mut self, chaos: crate::Chaos) -> Self {
+        self.chaos = Some(chaos);
+        self
+    }
+
+    /// Size of every WAL file in bytes, instead of a share of the capacity
+    ///
+    /// The WAL then takes up to [WalBuilder::segments] times `bytes` of storage, whatever the
+    /// capacity.
+    pub fn segment_size(mut self, bytes: usize) -> Self {
+        self.segment_size = Some(bytes);
+        self
+    }
+
+    /// Name WAL files `<prefix><id>` instead of `wal_<id>`
+    ///
+    /// Only WAL files are renamed, the other files of the directory keep their names, so a
+    /// directory still holds a single WAL. The prefix can't contain path separators or dots, and
+    /// a WAL must be opened with the prefix it was written with.
+    pub fn file_prefix(mut self, prefix: &str) -> Self {
+        self.layout.prefix = prefix.into();
+        self
+    }
+
+    /// Store WAL files in `storage` instead of the WAL directory, such as a
+    /// [crate::MemoryStorage] in tests, an object store or a raw block device
+    ///
+    /// See [Storage] for what a storage is asked to do. The meta file, the lock files and the
+    /// other files kept alongside logs, such as key filters and blobs, stay in the WAL directory,
+    /// which must exist on the local file system. [WalBuilder::positional_writes] and
+    /// [WalBuilder::preallocate] need local files and fail with [WalError::Unsupported], while
+    /// io_uring and memory maps are only used for local files. The WAL must be opened with the
+    /// same storage to read its logs.
+    pub fn storage<S: Storage + 'static>(mut self, storage: S) -> Self {
+        self.layout.storage = SharedStorage::Custom(Arc::new(storage));
+        self
+    }
+
+    /// Size every WAL file so it covers about `window` of logs, between `min` and `max` bytes
+    ///
+    /// The writer sizes the next file from the rate logs were written to the previous one, so
+    /// time-based retention and archival stay predictable whatever the traffic. Files replace
+    /// the file size set by the capacity or [WalBuilder::segment_size], and the WAL takes up to
+    /// [WalBuilder::segments] times `max` bytes of storage.
+    pub fn segment_window(mut self, window: Duration, min: usize, max: usize) -> Self {
+        self.segment_window = Some(SegmentWindow {
+            target: window,
+            min: min.min(max),
+            max,
+        });
+        self
+    }
+
+    /// Create the WAL instance with the given configuration
+    pub fn build(self) -> Result<Wal<T>, WalError> {
+        Wal::with_builder(self)
+    }
+
+    /// Create the WAL instance with the given configuration, as the single handle allowed to
+    /// write to it, see [WalWriterHandle]
+    ///
+    /// Handles given out with [WalWriterHandle::read_handle] can only read, so subsystems can be
+    /// handed the WAL without any risk of stray writes.
+    pub fn build_writer(self) -> Result<WalWriterHandle<T>, WalError> {
+        self.build().map(WalWriterHandle::new)
+    }
+
+    /// Create an async handle to the WAL with the given configuration, see [crate::r#async]
+    ///
+    /// Must be called from within a tokio runtime.
+    #[cfg(feature = "tokio")]
+    pub async fn build_async(self) -> Result<crate::r#async::Wal<T>, WalError>
+    where
+        T: Send + 'static,
+    {
+        crate::r#async::Wal::with_builder(self).await
+    }
+}
@@ -0,0 +1,53 @@
+use crate::snapshot::SnapshotVersion;
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+
+// What a read was taken from: the files and pending logs of its snapshot, and the files left out
+// by a truncation
+pub(crate) type ReadVersion = (SnapshotVersion, Vec<u8>);
+
+// Last full read of a WAL, shared by its handles and returned again until the logs change
+// Reads of more than `max_bytes` of serialized payload aren't kept. Logs are held as `Vec<T>`,
+// with `T` erased so that handles don't require logs to be shared between threads unless the
+// cache is used.
+pub(crate) struct ReadCache {
+    max_bytes: usize,
+    last: Mutex<Option<(ReadVersion, Arc<dyn Any + Send + Sync>)>>,
+}
+
+impl ReadCache {
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            last: Mutex::new(None),
+        }
+    }
+
+    // empty cache with the same limit, for logs of another type
+    pub fn retyped(&self) -> Self {
+        Self::new(self.max_bytes)
+    }
+
+    // logs of the last read, if it was taken from `version`
+    pub fn get<T>(&self, version: &ReadVersion) -> Option<Arc<Vec<T>>>
+    where
+        T: Send + Sync + 'static,
+    {
+        let last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        match &*last {
+            Some((v, logs)) if v == version => logs.clone().downcast().ok(),
+            _ => None,
+        }
+    }
+
+    // keep `logs` read from `version`, holding `bytes` of serialized payload
+    // The previous read is dropped either way, as it's outdated.
+    pub fn store<T>(&self, version: ReadVersion, bytes: usize, logs: Arc<Vec<T>>)
+    where
+        T: Send + Sync + 'static,
+    {
+        let logs: Arc<dyn Any + Send + Sync> = logs;
+        let mut last = self.last.lock().unwrap_or_else(|e| e.into_inner());
+        *last = (bytes <= self.max_bytes).then_some((version, logs));
+    }
+}
@@ -0,0 +1,76 @@
+use crate::checksum::{self, Sha256};
+use crate::reader::WalReader;
+use crate::WalError;
+
+// link preceding the first sealed file of a WAL
+pub(crate) const GENESIS: [u8; 32] = [0; 32];
+
+// Link of a sealed file whose content hashes to `sha256`, following the link `previous`
+pub(crate) fn link(previous: &[u8; 32], sha256: &[u8; 32]) -> [u8; 32] {
+    let mut sha = Sha256::new();
+    sha.update(previous);
+    sha.update(sha256);
+    sha.finish()
+}
+
+/// Outcome of [crate::Wal::verify_chain]
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct ChainVerification {
+    /// Sealed files whose content and link were checked, oldest first
+    pub checked: Vec<u8>,
+    /// First file whose content doesn't match its digest, or whose link doesn't follow the link
+    /// of the file sealed before it
+    pub broken_at: Option<u8>,
+    /// Link of the newest sealed file, to be compared with a copy kept outside of the WAL
+    pub head: Option<[u8; 32]>,
+}
+
+impl ChainVerification {
+    /// Whether every checked file still holds what it held when it was sealed
+    pub fn is_intact(&self) -> bool {
+        self.broken_at.is_none()
+    }
+}
+
+// Check the content and the links of the sealed files, oldest first
+// Files sealed by older versions have no link, and the chain starts over after them
+pub(crate) fn verify(reader: &WalReader) -> Result<ChainVerification, WalError> {
+    let mut report = ChainVerification::default();
+    let mut previous = None;
+    for digest in reader.segment_digests()? {
+        let (sha256, chain) = match (digest.sha256, digest.chain) {
+            (Some(sha256), Some(chain)) => (sha256, chain),
+            _ => {
+                previous = None;
+                continue;
+            }
+        };
+        let content = reader.raw(digest.id)?.unwrap_or_default();
+        // the link of the oldest file has nothing left to follow
+        let intact = checksum::sha256(&content) == sha256
+            && previous.is_none_or(|p| link(&p, &sha256) == chain);
+        if !intact && report.broken_at.is_none() {
+            report.broken_at = Some(digest.id);
+        }
+        report.checked.push(digest.id);
+        report.head = Some(chain);
+        previous = Some(chain);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links() {
+        let first = link(&GENESIS, &checksum::sha256(b"first"));
+        let second = link(&first, &checksum::sha256(b"second"));
+        assert_ne!(first, second);
+        assert_eq!(second, link(&first, &checksum::sha256(b"second")));
+        // the same content follows a different history
+        assert_ne!(second, link(&GENESIS, &checksum::sha256(b"second")));
+    }
+}
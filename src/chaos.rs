@@ -0,0 +1,145 @@
+// Misbehavior of storage injected by the writer thread, available with the `chaos` feature
+//
+// Applications embedding the WAL run their integration tests against delayed flushes, failed
+// syncs and slow disks without patching the crate. Decisions are drawn from a seeded generator,
+// so a failing test can be replayed with the same seed.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::thread::sleep;
+use std::time::Duration;
+
+/// Failures and delays injected in the storage of a WAL, for chaos tests
+///
+/// Used with [crate::WalBuilder::chaos]. Never enable it in production: logs whose sync failed
+/// are reported as such by [crate::Wal::flush], but may still be lost.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use walcraft::Chaos;
+///
+/// // a disk writing 1MB/s, failing 1 sync in 10, with flushes delayed by up to 50ms
+/// let chaos = Chaos::new()
+///     .slow_disk(1 << 20)
+///     .fail_syncs(0.1)
+///     .delay_flushes(Duration::from_millis(50))
+///     .seed(42);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Chaos {
+    flush_delay: Option<Duration>,
+    sync_failures: f64,
+    bytes_per_sec: Option<u64>,
+    seed: Option<u64>,
+}
+
+impl Chaos {
+    /// Storage behaving as usual, until misbehavior is added
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delay every flush by a random duration of up to `max`
+    pub fn delay_flushes(mut self, max: Duration) -> Self {
+        self.flush_delay = Some(max);
+        self
+    }
+
+    /// Fail a `share` of the syncs of WAL files, between 0 and 1
+    ///
+    /// A failed sync of [crate::Wal::flush] is returned as an error, and failed syncs of the
+    /// [crate::SyncPolicy] are only reported by [crate::Wal::last_error], as they would be on a
+    /// failing disk.
+    pub fn fail_syncs(mut self, share: f64) -> Self {
+        self.sync_failures = share.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Write to WAL files at no more than `bytes_per_sec`, as a slow disk would
+    pub fn slow_disk(mut self, bytes_per_sec: u64) -> Self {
+        self.bytes_per_sec = Some(bytes_per_sec.max(1));
+        self
+    }
+
+    /// Draw the failures and delays from `seed`, so a run can be replayed
+    ///
+    /// Without a seed, every WAL draws different ones.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+// Random decisions of the writer thread following a [Chaos] configuration
+pub(crate) struct ChaosMonkey {
+    chaos: Chaos,
+    state: u64,
+}
+
+impl ChaosMonkey {
+    pub fn new(chaos: Chaos) -> Self {
+        let state = chaos
+            .seed
+            .unwrap_or_else(|| RandomState::new().build_hasher().finish());
+        Self { chaos, state }
+    }
+
+    // next number of a splitmix64 sequence
+    fn next(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    // uniform number in [0, 1)
+    fn chance(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // pause before a flush
+    pub fn delay_flush(&mut self) {
+        if let Some(max) = self.chaos.flush_delay {
+            sleep(max.mul_f64(self.chance()));
+        }
+    }
+
+    // fail a sync about to happen, when drawn
+    pub fn sync(&mut self) -> io::Result<()> {
+        match self.chance() < self.chaos.sync_failures {
+            true => Err(io::Error::other("sync failure injected by chaos testing")),
+            false => Ok(()),
+        }
+    }
+
+    // pause as long as a slow disk takes to write `bytes`
+    pub fn write(&self, bytes: usize) {
+        if let Some(rate) = self.chaos.bytes_per_sec {
+            sleep(Duration::from_secs_f64(bytes as f64 / rate as f64));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeded() {
+        let chaos = Chaos::new().fail_syncs(0.5).seed(7);
+        let draws =
+            |mut monkey: ChaosMonkey| (0..100).map(|_| monkey.sync().is_err()).collect::<Vec<_>>();
+        let failures = draws(ChaosMonkey::new(chaos));
+        assert_eq!(failures, draws(ChaosMonkey::new(chaos)));
+        let count = failures.iter().filter(|f| **f).count();
+        assert!((30..=70).contains(&count));
+
+        let mut reliable = ChaosMonkey::new(Chaos::new());
+        assert!((0..100).all(|_| reliable.sync().is_ok()));
+        let mut failing = ChaosMonkey::new(Chaos::new().fail_syncs(2.0));
+        assert!((0..100).all(|_| failing.sync().is_err()));
+    }
+}
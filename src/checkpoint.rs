@@ -0,0 +1,27 @@
+use crate::Lsn;
+use std::io;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+/// Snapshot of the application state saved before logs are dropped, see
+/// [crate::WalBuilder::checkpointer]
+///
+/// Implemented for closures taking the range of logs about to be dropped.
+pub trait Checkpointer: Send + Sync {
+    /// Persist a snapshot covering at least the logs in `lsns`, before they are dropped
+    ///
+    /// On error, the logs are kept and the checkpoint is attempted again later.
+    fn checkpoint(&self, lsns: RangeInclusive<Lsn>) -> io::Result<()>;
+}
+
+impl<F> Checkpointer for F
+where
+    F: Fn(RangeInclusive<Lsn>) -> io::Result<()> + Send + Sync,
+{
+    fn checkpoint(&self, lsns: RangeInclusive<Lsn>) -> io::Result<()> {
+        self(lsns)
+    }
+}
+
+// Checkpointer shared by the builder and the writer thread
+pub(crate) type SharedCheckpointer = Arc<dyn Checkpointer>;
@@ -0,0 +1,104 @@
+use crate::entry::LogEntry;
+use crate::segment::SegmentSpan;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+// Clock stamping logs written through the handles of a WAL, see [crate::WalBuilder::timestamps]
+// Stamps follow the wall clock, but never go back: while the wall clock is set back, e.g. by
+// NTP, logs keep the last stamp handed out until the wall clock catches up with it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Clock {
+    // last stamp handed out by any handle of the WAL, in milliseconds since the unix epoch
+    last: Arc<AtomicU64>,
+    // record the reading of the wall clock next to the stamp, see
+    // [crate::WalBuilder::strict_timestamps]
+    wall_clock: bool,
+}
+
+impl Clock {
+    pub fn new(wall_clock: bool) -> Self {
+        Self {
+            last: Arc::default(),
+            wall_clock,
+        }
+    }
+
+    // stamp `log` with now, never before a stamp handed out earlier
+    pub fn stamp(&self, log: &mut LogEntry) {
+        let now = SegmentSpan::millis(SystemTime::now());
+        log.set_timestamp(Some(self.last.fetch_max(now, Ordering::Relaxed).max(now)));
+        if self.wall_clock {
+            log.set_wall_clock(Some(now));
+        }
+    }
+}
+
+// Monotonic time of the writer thread, in nanoseconds since the unix epoch: the wall clock when
+// the WAL was opened, moved forward by the monotonic clock of the machine only, so setting the
+// wall clock doesn't affect it. Readings strictly increase, see
+// [crate::WalBuilder::strict_timestamps]
+pub(crate) struct Monotonic {
+    // instant the clock was started, and its reading then
+    origin: Instant,
+    start: u64,
+    // last reading handed out
+    last: u64,
+}
+
+impl Monotonic {
+    // clock started now, whose readings follow `last`, the newest reading of the WAL
+    pub fn after(last: u64) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Self {
+            origin: Instant::now(),
+            start: now.max(last),
+            last,
+        }
+    }
+
+    // nanoseconds since the unix epoch of now, at least one after the last reading
+    pub fn read(&mut self) -> u64 {
+        let now = self.start + self.origin.elapsed().as_nanos() as u64;
+        self.last = now.max(self.last + 1);
+        self.last
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn never_goes_back() {
+        let clock = Clock::new(true);
+        let stamp = |clock: &Clock| {
+            let mut log = LogEntry::from_vec(Vec::new(), 0);
+            clock.stamp(&mut log);
+            (log.timestamp().unwrap(), log.wall_clock())
+        };
+        let (now, wall_clock) = stamp(&clock);
+        assert_eq!(wall_clock, Some(now));
+        // the wall clock was set back by an hour
+        clock.last.store(now + 3_600_000, Ordering::Relaxed);
+        let (later, wall_clock) = stamp(&clock.clone());
+        assert_eq!(later, now + 3_600_000);
+        assert!(wall_clock.is_some_and(|at| at < later));
+        assert_eq!(stamp(&Clock::new(false)).1, None);
+    }
+
+    #[test]
+    fn monotonic_increases() {
+        let mut clock = Monotonic::after(0);
+        let now = SegmentSpan::millis(SystemTime::now()) * 1_000_000;
+        let readings = (0..1000).map(|_| clock.read()).collect::<Vec<_>>();
+        assert!(readings.windows(2).all(|w| w[0] < w[1]));
+        // readings stay close to the wall clock
+        assert!(readings[999].abs_diff(now) < 1_000_000_000);
+        // and go on from the newest reading of the WAL, whatever the wall clock says
+        let mut clock = Monotonic::after(now + 3_600_000_000_000);
+        assert!(clock.read() > now + 3_600_000_000_000);
+    }
+}
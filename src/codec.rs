@@ -0,0 +1,296 @@
+use crate::entry::LogEntry;
+use crate::format;
+use crate::layout::Layout;
+use crate::migrate::Envelope;
+use crate::WalError;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// Format logs are serialized with, see [crate::WalBuilder::serialization_codec]
+///
+/// The format is recorded in the WAL directory, and opening the WAL with another format fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum SerializationCodec {
+    /// Bincode, compact and fast, but only readable with the exact Rust type of the logs
+    #[default]
+    Bincode,
+    /// JSON, readable by any tool, available with the `json` feature
+    #[cfg(feature = "json")]
+    Json,
+    /// MessagePack with named fields, a compact format readable by most languages, available with
+    /// the `msgpack` feature
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+}
+
+impl From<SerializationCodec> for Codec {
+    fn from(codec: SerializationCodec) -> Self {
+        match codec {
+            SerializationCodec::Bincode => Codec::Bincode,
+            #[cfg(feature = "json")]
+            SerializationCodec::Json => Codec::Json,
+            #[cfg(feature = "msgpack")]
+            SerializationCodec::MessagePack => Codec::MessagePack,
+        }
+    }
+}
+
+// Encoding of log payloads, recorded in the `codec` file of the WAL directory
+// Bincode is compact but needs the exact type to decode. Tagged payloads are JSON wrapping the
+// log with its type name and version, available with the `self-describing` feature. Enveloped
+// payloads are bincode behind the tag and version of the type, see [crate::Migrate]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum Codec {
+    #[default]
+    Bincode,
+    #[cfg(feature = "json")]
+    Json,
+    #[cfg(feature = "msgpack")]
+    MessagePack,
+    #[cfg(feature = "self-describing")]
+    Tagged {
+        version: u32,
+    },
+    Enveloped {
+        tag: u8,
+        version: u8,
+    },
+}
+
+impl Codec {
+    fn name(&self) -> &'static str {
+        match self {
+            Codec::Bincode => "bincode",
+            #[cfg(feature = "json")]
+            Codec::Json => "json",
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => "msgpack",
+            #[cfg(feature = "self-describing")]
+            Codec::Tagged { .. } => "tagged",
+            Codec::Enveloped { .. } => "enveloped",
+        }
+    }
+
+    pub fn encode<T: Serialize>(&self, data: &T) -> Result<LogEntry, WalError> {
+        self.encode_into(data, Vec::new())
+    }
+
+    // serialize a log into `buffer`, an empty buffer reused from logs already written
+    pub fn encode_into<T: Serialize>(
+        &self,
+        data: &T,
+        mut buffer: Vec<u8>,
+    ) -> Result<LogEntry, WalError> {
+        let encoded = match self {
+            Codec::Bincode => bincode::serialize_into(&mut buffer, data).map_err(|e| e.to_string()),
+            #[cfg(feature = "json")]
+            Codec::Json => serde_json::to_writer(&mut buffer, data).map_err(|e| e.to_string()),
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => {
+                rmp_serde::encode::write_named(&mut buffer, data).map_err(|e| e.to_string())
+            }
+            #[cfg(feature = "self-describing")]
+            Codec::Tagged { version } => {
+                let tagged = Tagged {
+                    type_name: std::any::type_name::<T>(),
+                    version: *version,
+                    content: data,
+                };
+                serde_json::to_writer(&mut buffer, &tagged).map_err(|e| e.to_string())
+            }
+            Codec::Enveloped { tag, version } => {
+                buffer.extend([*tag, *version]);
+                bincode::serialize_into(&mut buffer, data).map_err(|e| e.to_string())
+            }
+        };
+        encoded
+            .map(|_| LogEntry::from_vec(buffer, 0))
+            .map_err(WalError::Serialization)
+    }
+
+    pub fn decode_payload<T>(&self, payload: &[u8]) -> Option<T>
+    where
+        T: for<'a> Deserialize<'a>,
+    {
+        self.try_decode_payload(payload).ok()
+    }
+
+    // deserialize a payload, describing why it can't be deserialized
+    pub fn try_decode_payload<T>(&self, payload: &[u8]) -> Result<T, String>
+    where
+        T: for<'a> Deserialize<'a>,
+    {
+        match self {
+            Codec::Bincode => bincode::deserialize(payload).map_err(|e| e.to_string()),
+            // fixed size records pad the payload, so only the first value is parsed
+            #[cfg(feature = "json")]
+            Codec::Json => serde_json::Deserializer::from_slice(payload)
+                .into_iter::<T>()
+                .next()
+                .ok_or_else(|| "empty payload".to_string())?
+                .map_err(|e| e.to_string()),
+            #[cfg(feature = "msgpack")]
+            Codec::MessagePack => T::deserialize(&mut rmp_serde::Deserializer::new(payload))
+                .map_err(|e| e.to_string()),
+            #[cfg(feature = "self-describing")]
+            Codec::Tagged { .. } => {
+                // fixed size records pad the payload, so only the first value is parsed
+                serde_json::Deserializer::from_slice(payload)
+                    .into_iter::<Tagged<String, T>>()
+                    .next()
+                    .ok_or_else(|| "empty payload".to_string())?
+                    .map(|tagged| tagged.content)
+                    .map_err(|e| e.to_string())
+            }
+            // logs of other versions are left to the migration
+            Codec::Enveloped { tag, version } => match Envelope::parse(payload) {
+                Some(log) if (log.tag(), log.version()) == (*tag, *version) => {
+                    bincode::deserialize(log.content()).map_err(|e| e.to_string())
+                }
+                Some(log) => Err(format!(
+                    "log of tag {} version {}, expected tag {} version {}",
+                    log.tag(),
+                    log.version(),
+                    tag,
+                    version
+                )),
+                None => Err("payload shorter than its envelope".to_string()),
+            },
+        }
+    }
+
+    // check the codec recorded in the WAL directory, recording it if missing
+    // WALs written before codecs were recorded hold bincode payloads
+    pub fn check(&self, location: &Path, layout: &Layout, create: bool) -> Result<(), WalError> {
+        let path = location.join("codec");
+        let recorded = std::fs::read_to_string(&path).ok();
+        let recorded = recorded.as_deref().map(str::trim);
+        match recorded {
+            Some(name) if name == self.name() => Ok(()),
+            Some(name) => Err(WalError::Unsupported(format!(
+                "WAL was written with the `{}` codec, not `{}`",
+                name,
+                self.name()
+            ))),
+            None if *self == Codec::Bincode || !create => Ok(()),
+            None => {
+                let written = layout
+                    .ids()
+                    .any(|id| format::holds_frames(&layout.storage, &layout.path(location, id)));
+                if written {
+                    return Err(WalError::Unsupported(format!(
+                        "WAL already holds logs written with the `bincode` codec, not `{}`",
+                        self.name()
+                    )));
+                }
+                std::fs::write(&path, self.name())
+                    .map_err(|e| WalError::io(e, "Failed to write codec file"))
+            }
+        }
+    }
+}
+
+// Payload of a log with the tagged codec
+#[cfg(feature = "self-describing")]
+#[derive(Serialize, Deserialize)]
+struct Tagged<N, C> {
+    #[serde(rename = "type")]
+    type_name: N,
+    version: u32,
+    content: C,
+}
+
+/// Log decoded without knowing its type, as returned by [crate::Wal::read_dynamic]
+#[cfg(feature = "self-describing")]
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct DynamicLog {
+    /// Sequence number of the log
+    pub lsn: crate::Lsn,
+    /// Name of the type the log was written as
+    pub type_name: String,
+    /// Version given to [crate::WalBuilder::self_describing] by the writer of the log
+    pub version: u32,
+    /// Content of the log
+    pub content: serde_json::Value,
+}
+
+#[cfg(feature = "self-describing")]
+impl DynamicLog {
+    pub(crate) fn decode(entry: &LogEntry) -> Option<Self> {
+        let tagged = serde_json::Deserializer::from_slice(entry.payload())
+            .into_iter::<Tagged<String, serde_json::Value>>()
+            .next()?
+            .ok()?;
+        Some(Self {
+            lsn: entry.lsn(),
+            type_name: tagged.type_name,
+            version: tagged.version,
+            content: tagged.content,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct V1 {
+        id: u32,
+    }
+
+    #[cfg(feature = "self-describing")]
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct V2 {
+        id: u32,
+        #[serde(default)]
+        name: String,
+    }
+
+    #[cfg(feature = "self-describing")]
+    #[test]
+    fn tagged() {
+        let codec = Codec::Tagged { version: 1 };
+        let mut entry = codec.encode(&V1 { id: 7 }).unwrap();
+        entry.set_lsn(3);
+        let log = DynamicLog::decode(&entry).unwrap();
+        assert!(log.type_name.ends_with("V1"));
+        assert_eq!((log.lsn, log.version), (3, 1));
+        assert_eq!(log.content, serde_json::json!({ "id": 7 }));
+
+        // readers of a newer version fill in missing fields
+        let v2: V2 = Codec::Tagged { version: 2 }
+            .decode_payload(entry.payload())
+            .unwrap();
+        assert_eq!(v2.id, 7);
+        // padding of fixed size records is ignored
+        let mut padded = entry.payload().to_vec();
+        padded.resize(padded.len() + 8, 0);
+        assert_eq!(codec.decode_payload::<V1>(&padded), Some(V1 { id: 7 }));
+    }
+
+    #[test]
+    fn codecs() {
+        let codecs = [
+            SerializationCodec::Bincode,
+            #[cfg(feature = "json")]
+            SerializationCodec::Json,
+            #[cfg(feature = "msgpack")]
+            SerializationCodec::MessagePack,
+        ];
+        for codec in codecs.map(Codec::from) {
+            let entry = codec.encode(&V1 { id: 7 }).unwrap();
+            // padding of fixed size records is ignored
+            let mut padded = entry.payload().to_vec();
+            padded.resize(padded.len() + 8, 0);
+            assert_eq!(codec.decode_payload::<V1>(&padded), Some(V1 { id: 7 }));
+        }
+        #[cfg(feature = "json")]
+        assert_eq!(
+            Codec::Json.encode(&V1 { id: 7 }).unwrap().payload(),
+            br#"{"id":7}"#
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use crate::entry::LogEntry;
+use crate::format;
+use crate::reader::WalReader;
+use crate::WalError;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+
+// Key of a log in keyed mode, computed from its serialized payload
+// None if the payload can't be deserialized, in which case the log is always kept
+pub(crate) type KeyFn = Arc<dyn Fn(&[u8]) -> Option<u64> + Send + Sync>;
+
+// Payload accumulating an older and a newer payload with the same key
+// None if either payload can't be deserialized, in which case both logs are kept
+pub(crate) type MergeFn = Arc<dyn Fn(&[u8], &[u8]) -> Option<Vec<u8>> + Send + Sync>;
+
+// Whether compaction keeps a log, from its serialized payload
+pub(crate) type RetainFn = Arc<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+// hash of a key, as stored in sketches and compared by compaction
+pub(crate) fn hash_key<K: Hash>(key: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+// bits of the bloom filter of every WAL file
+pub(crate) const SKETCH_BITS: usize = 8192;
+// hash functions of the bloom filter
+const SKETCH_HASHES: u64 = 3;
+
+// bits of a bloom filter of SKETCH_BITS set for `key`
+pub(crate) fn bloom_positions(key: u64) -> impl Iterator<Item = usize> {
+    // double hashing from the two halves of the key hash
+    let (a, b) = (key & 0xFFFF_FFFF, (key >> 32) | 1);
+    (0..SKETCH_HASHES)
+        .map(move |i| (a.wrapping_add(i.wrapping_mul(b)) % SKETCH_BITS as u64) as usize)
+}
+
+// Approximate count of logs in a WAL file superseded by a later log with the same key
+// Keys written to the file are tracked in a bloom filter. A later log whose key may be in the
+// filter counts as one dead log of the file, so the count is an estimate.
+pub(crate) struct KeySketch {
+    bits: Vec<u64>,
+    entries: u64,
+    dead: u64,
+}
+
+impl KeySketch {
+    pub fn new() -> Self {
+        Self {
+            bits: vec![0; SKETCH_BITS / 64],
+            entries: 0,
+            dead: 0,
+        }
+    }
+
+    pub fn insert(&mut self, key: u64) {
+        for p in bloom_positions(key) {
+            self.bits[p / 64] |= 1 << (p % 64);
+        }
+        self.entries += 1;
+    }
+
+    pub fn contains(&self, key: u64) -> bool {
+        bloom_positions(key).all(|p| self.bits[p / 64] & (1 << (p % 64)) != 0)
+    }
+
+    // a log with `key` was written after this file
+    pub fn supersede(&mut self, key: u64) {
+        if self.dead < self.entries && self.contains(key) {
+            self.dead += 1;
+        }
+    }
+
+    // `removed` logs were dropped from the file, which has no known dead logs left
+    pub fn compacted(&mut self, removed: u64) {
+        self.entries = self.entries.saturating_sub(removed);
+        self.dead = 0;
+    }
+
+    // estimated share of dead logs in the file
+    pub fn dead_ratio(&self) -> f64 {
+        match self.entries {
+            0 => 0.0,
+            n => self.dead as f64 / n as f64,
+        }
+    }
+}
+
+// Rewrite the WAL file `id`, keeping only logs not superseded by a later log with the same key
+// `newer` lists the files written after `id`. Returns the number of removed logs.
+// With `merge`, the logs of every key in the file are merged into its last one instead, and logs
+// of later files supersede nothing, as they don't hold what older logs accumulated.
+pub(crate) fn compact_segment(
+    reader: &WalReader,
+    key: &KeyFn,
+    merge: Option<&MergeFn>,
+    id: u8,
+    newer: &[u8],
+) -> Result<u64, WalError> {
+    // keys of logs written after the file
+    let mut later = HashSet::new();
+    for i in newer.iter().filter(|_| merge.is_none()) {
+        let content = reader.raw(*i).ok().flatten().unwrap_or_default();
+        for (_, entry) in reader.frames(&content) {
+            later.extend(key(entry.payload()));
+        }
+    }
+
+    let path = reader.segment_path(id);
+    let content = match reader.storage().read(&path) {
+        Ok(Some(c)) => c,
+        _ => return Ok(0),
+    };
+    let frames = reader.frames(&content);
+    // frames replacing the original frame of merged logs
+    let mut merged = HashMap::new();
+    let keep = match merge {
+        None => {
+            // walk from the newest log, so the last log of every key is kept
+            let mut keep = vec![false; frames.len()];
+            for (i, (_, entry)) in frames.iter().enumerate().rev() {
+                keep[i] = match key(entry.payload()) {
+                    Some(k) => later.insert(k),
+                    None => true,
+                };
+            }
+            // the last log of an atomic batch marks the batch as complete, so it's never removed
+            for i in 1..frames.len() {
+                if frames[i - 1].1.batched() && !frames[i].1.batched() {
+                    keep[i] = true;
+                }
+            }
+            keep
+        }
+        Some(merge) => {
+            let (keep, payloads) = merge_keys(&frames, key, merge);
+            for (i, payload) in payloads {
+                let (_, entry) = &frames[i];
+                let mut log = LogEntry::from_vec(payload, entry.lsn());
+                log.set_producer(entry.producer());
+                log.set_timestamp(entry.timestamp());
+                log.set_wall_clock(entry.wall_clock());
+                log.set_monotonic(entry.monotonic());
+                log.set_tag(entry.tag().map(<[u8]>::to_vec));
+                let mut frame = Vec::new();
+                let cipher = reader.file_cipher(&content);
+                let _ = log.encode_frame(None, cipher, reader.checksummed(&content), &mut frame);
+                merged.insert(i, frame);
+            }
+            keep
+        }
+    };
+    rewrite(reader, &path, &content, &frames, &keep, &merged)
+}
+
+// Rewrite the WAL file `id`, keeping only logs whose payload `retain` accepts
+// Returns the number of removed logs. Logs ending an atomic batch are always kept, so a batch
+// never loses the log marking it as complete.
+pub(crate) fn retain_segment(
+    reader: &WalReader,
+    retain: &RetainFn,
+    id: u8,
+) -> Result<u64, WalError> {
+    let path = reader.segment_path(id);
+    let content = match reader.storage().read(&path) {
+        Ok(Some(c)) => c,
+        _ => return Ok(0),
+    };
+    let frames = reader.frames(&content);
+    let mut keep = frames
+        .iter()
+        .map(|(_, entry)| retain(entry.payload()))
+        .collect::<Vec<_>>();
+    for i in 1..frames.len() {
+        if frames[i - 1].1.batched() && !frames[i].1.batched() {
+            keep[i] = true;
+        }
+    }
+    rewrite(reader, &path, &content, &frames, &keep, &HashMap::new())
+}
+
+// Replace the file at `path` with its `frames` marked in `keep`, returning the number of removed
+// logs. `merged` holds frames replacing the original frame of merged logs.
+fn rewrite(
+    reader: &WalReader,
+    path: &Path,
+    content: &[u8],
+    frames: &[(Range<usize>, LogEntry)],
+    keep: &[bool],
+    merged: &HashMap<usize, Vec<u8>>,
+) -> Result<u64, WalError> {
+    let removed = keep.iter().filter(|k| !**k).count() as u64;
+    if removed == 0 {
+        return Ok(0);
+    }
+
+    // the compacted file replaces the old one atomically
+    let mut out = Vec::with_capacity(content.len());
+    out.extend_from_slice(&content[..reader.header_len(content)]);
+    // frames follow the header again, whatever was reclaimed before
+    format::clear_reclaimed(&mut out);
+    for (i, (span, _)) in frames.iter().enumerate().filter(|(i, _)| keep[*i]) {
+        match merged.get(&i) {
+            Some(frame) => out.extend_from_slice(frame),
+            None => out.extend_from_slice(&content[span.clone()]),
+        }
+    }
+    // merged payloads would otherwise be sealed under the nonce of the logs they replace
+    if !merged.is_empty() && reader.file_cipher(content).is_some() {
+        out = reader.reseal(&out).unwrap_or(out);
+    }
+    reader
+        .storage()
+        .replace(path, &out, "compact")
+        .map_err(|e| WalError::io(e, "Failed to write compacted log file"))?;
+    Ok(removed)
+}
+
+// Logs to keep of a file, along with the merged payload of the last log of every merged key
+// Logs of atomic batches are left as they are, so a batch never loses its last log.
+fn merge_keys(
+    frames: &[(Range<usize>, LogEntry)],
+    key: &KeyFn,
+    merge: &MergeFn,
+) -> (Vec<bool>, HashMap<usize, Vec<u8>>) {
+    let mut keep = vec![true; frames.len()];
+    let mut payloads = HashMap::new();
+    // last log of every key so far, with the payload merged up to it if it's merged
+    let mut last: HashMap<u64, (usize, Option<Vec<u8>>)> = HashMap::new();
+    for (i, (_, entry)) in frames.iter().enumerate() {
+        let in_batch = entry.batched() || (i > 0 && frames[i - 1].1.batched());
+        let k = match key(entry.payload()) {
+            Some(k) if !in_batch => k,
+            _ => continue,
+        };
+        let (previous, accumulated) = match last.insert(k, (i, None)) {
+            Some(previous) => previous,
+            None => continue,
+        };
+        let old = accumulated
+            .as_deref()
+            .unwrap_or(frames[previous].1.payload());
+        match merge(old, entry.payload()) {
+            Some(payload) => {
+                keep[previous] = false;
+                last.insert(k, (i, Some(payload)));
+            }
+            // the previous log keeps what was merged into it
+            None => payloads.extend(accumulated.map(|p| (previous, p))),
+        }
+    }
+    payloads.extend(last.into_values().filter_map(|(i, p)| Some((i, p?))));
+    (keep, payloads)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sketch() {
+        let mut sketch = KeySketch::new();
+        for k in 0..100u64 {
+            sketch.insert(hash_key(&k));
+        }
+        assert!((0..100u64).all(|k| sketch.contains(hash_key(&k))));
+        for k in 0..50u64 {
+            sketch.supersede(hash_key(&k));
+        }
+        assert!(sketch.dead_ratio() >= 0.5);
+        assert!(sketch.dead_ratio() <= 1.0);
+    }
+
+    #[test]
+    fn merge() {
+        let key: KeyFn = Arc::new(|payload: &[u8]| payload.first().map(|k| *k as u64));
+        // payloads are a key and a count, the count 0 can't be merged
+        let merge: MergeFn = Arc::new(|old: &[u8], new: &[u8]| {
+            (old[1] > 0 && new[1] > 0).then(|| vec![new[0], old[1] + new[1]])
+        });
+        let frames = [[1, 1], [2, 1], [1, 2], [1, 0], [1, 3], [1, 4]]
+            .iter()
+            .enumerate()
+            .map(|(i, p)| (0..0, LogEntry::from_vec(p.to_vec(), i as u64)))
+            .collect::<Vec<_>>();
+        let (keep, payloads) = merge_keys(&frames, &key, &merge);
+        assert_eq!(keep, vec![false, true, true, true, false, true]);
+        // the log that couldn't be merged holds what was merged before it
+        assert_eq!(payloads.get(&2), Some(&vec![1, 3]));
+        assert_eq!(payloads.get(&5), Some(&vec![1, 7]));
+        assert_eq!(payloads.len(), 2);
+    }
+}
@@ -0,0 +1,262 @@
+// Compression of log payloads, available with the `lz4`, `deflate` and `zstd` features
+// Without a feature nothing is compressed with its codec and logs compressed with it cannot be
+// decoded
+//
+// Compressed payloads start with the id of their codec, so the reader selects the decompressor of
+// every log and a WAL can hold logs compressed with several codecs. The decompressed size is
+// declared ahead of the compressed data. A payload declaring more than `max_expansion` times its
+// compressed size is rejected before anything is allocated, so a corrupted frame can't make a
+// read allocate gigabytes.
+
+// LZ4 never expands data by more than about 255 times, so no valid payload is rejected by default
+pub(crate) const MAX_EXPANSION: usize = 255;
+
+// ids of codecs, stored ahead of compressed payloads
+const LZ4_ID: u8 = 1;
+const DEFLATE_ID: u8 = 2;
+const ZSTD_ID: u8 = 3;
+
+// bytes of codec id and declared size ahead of the compressed data
+const HEADER_BYTES: usize = 5;
+
+/// Algorithm used to compress large logs
+///
+/// The codec of every compressed log is recorded in its frame, so changing the codec between
+/// runs doesn't make the logs already written unreadable, as long as the feature of their codec
+/// is enabled. A log is stored raw when the feature of the selected codec is not enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CompressionCodec {
+    /// LZ4, fast with a moderate ratio, available with the `lz4` feature
+    Lz4,
+    /// DEFLATE, slower with a better ratio, available with the `deflate` feature
+    Deflate,
+    /// Zstandard, with a ratio close to DEFLATE at a speed close to LZ4, available with the
+    /// `zstd` feature
+    Zstd,
+}
+
+impl Default for CompressionCodec {
+    // the fastest codec available
+    fn default() -> Self {
+        if cfg!(feature = "lz4") {
+            Self::Lz4
+        } else if cfg!(feature = "zstd") {
+            Self::Zstd
+        } else if cfg!(feature = "deflate") {
+            Self::Deflate
+        } else {
+            Self::Lz4
+        }
+    }
+}
+
+impl CompressionCodec {
+    fn id(self) -> u8 {
+        match self {
+            Self::Lz4 => LZ4_ID,
+            Self::Deflate => DEFLATE_ID,
+            Self::Zstd => ZSTD_ID,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            LZ4_ID => Some(Self::Lz4),
+            DEFLATE_ID => Some(Self::Deflate),
+            ZSTD_ID => Some(Self::Zstd),
+            _ => None,
+        }
+    }
+}
+
+// Compression of the logs written by the writer thread
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Compression {
+    // logs with payload larger than this are compressed
+    pub above: usize,
+    // highest ratio of decompressed to compressed size accepted when reading
+    pub max_expansion: usize,
+    pub codec: CompressionCodec,
+}
+
+impl Compression {
+    // compressed payload, None if it's not worth storing compressed or readers would reject it
+    pub fn apply(&self, data: &[u8]) -> Option<Vec<u8>> {
+        if data.len() <= self.above {
+            return None;
+        }
+        let mut compressed = Vec::with_capacity(HEADER_BYTES + data.len() / 2);
+        compressed.push(self.codec.id());
+        compressed.extend((data.len() as u32).to_le_bytes());
+        match self.codec {
+            CompressionCodec::Lz4 => lz4::compress(data, &mut compressed)?,
+            CompressionCodec::Deflate => deflate::compress(data, &mut compressed)?,
+            CompressionCodec::Zstd => zstd::compress(data, &mut compressed)?,
+        }
+        let accepted = compressed.len() < data.len()
+            && data.len() <= compressed.len().saturating_mul(self.max_expansion);
+        accepted.then_some(compressed)
+    }
+}
+
+// payload decompressed with the codec it records, None if the codec is unknown or not enabled
+pub fn decompress(data: &[u8], max_expansion: usize) -> Option<Vec<u8>> {
+    let codec = CompressionCodec::from_id(*data.first()?)?;
+    let declared = u32::from_le_bytes(data.get(1..HEADER_BYTES)?.try_into().ok()?) as usize;
+    if declared > data.len().saturating_mul(max_expansion) {
+        return None;
+    }
+    let compressed = &data[HEADER_BYTES..];
+    let decompressed = match codec {
+        CompressionCodec::Lz4 => lz4::decompress(compressed, declared)?,
+        CompressionCodec::Deflate => deflate::decompress(compressed, declared)?,
+        CompressionCodec::Zstd => zstd::decompress(compressed, declared)?,
+    };
+    (decompressed.len() == declared).then_some(decompressed)
+}
+
+#[cfg(feature = "lz4")]
+mod lz4 {
+    pub fn compress(data: &[u8], out: &mut Vec<u8>) -> Option<()> {
+        out.extend(lz4_flex::compress(data));
+        Some(())
+    }
+
+    pub fn decompress(data: &[u8], size: usize) -> Option<Vec<u8>> {
+        lz4_flex::decompress(data, size).ok()
+    }
+}
+
+#[cfg(not(feature = "lz4"))]
+mod lz4 {
+    pub fn compress(_data: &[u8], _out: &mut Vec<u8>) -> Option<()> {
+        None
+    }
+
+    pub fn decompress(_data: &[u8], _size: usize) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+#[cfg(feature = "deflate")]
+mod deflate {
+    // balance of speed and ratio, from 0 to 10
+    const LEVEL: u8 = 6;
+
+    pub fn compress(data: &[u8], out: &mut Vec<u8>) -> Option<()> {
+        out.extend(miniz_oxide::deflate::compress_to_vec(data, LEVEL));
+        Some(())
+    }
+
+    pub fn decompress(data: &[u8], size: usize) -> Option<Vec<u8>> {
+        miniz_oxide::inflate::decompress_to_vec_with_limit(data, size).ok()
+    }
+}
+
+#[cfg(not(feature = "deflate"))]
+mod deflate {
+    pub fn compress(_data: &[u8], _out: &mut Vec<u8>) -> Option<()> {
+        None
+    }
+
+    pub fn decompress(_data: &[u8], _size: usize) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+#[cfg(feature = "zstd")]
+mod zstd {
+    // the default level of the zstd command line tool
+    const LEVEL: i32 = 3;
+
+    pub fn compress(data: &[u8], out: &mut Vec<u8>) -> Option<()> {
+        out.extend(zstd::bulk::compress(data, LEVEL).ok()?);
+        Some(())
+    }
+
+    pub fn decompress(data: &[u8], size: usize) -> Option<Vec<u8>> {
+        zstd::bulk::decompress(data, size).ok()
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+mod zstd {
+    pub fn compress(_data: &[u8], _out: &mut Vec<u8>) -> Option<()> {
+        None
+    }
+
+    pub fn decompress(_data: &[u8], _size: usize) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+#[cfg(all(test, feature = "lz4"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expansion_limit() {
+        let compression = Compression {
+            above: 16,
+            max_expansion: MAX_EXPANSION,
+            codec: CompressionCodec::Lz4,
+        };
+        assert_eq!(compression.apply(&[0; 16]), None);
+        let data = vec![0; 100_000];
+        let compressed = compression.apply(&data).unwrap();
+        assert_eq!(decompress(&compressed, MAX_EXPANSION), Some(data.clone()));
+        // the writer keeps payloads raw rather than writing frames readers would reject
+        let strict = Compression {
+            max_expansion: 10,
+            ..compression
+        };
+        assert_eq!(strict.apply(&data), None);
+        assert_eq!(decompress(&compressed, 10), None);
+
+        // a corrupted size declaration is rejected without allocating it
+        let mut bomb = compressed.clone();
+        bomb[1..5].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(decompress(&bomb, MAX_EXPANSION), None);
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn codec_per_log() {
+        let lz4 = Compression {
+            above: 16,
+            max_expansion: MAX_EXPANSION,
+            codec: CompressionCodec::Lz4,
+        };
+        let deflate = Compression {
+            codec: CompressionCodec::Deflate,
+            ..lz4
+        };
+        let data = "walcraft".repeat(100).into_bytes();
+        let a = lz4.apply(&data).unwrap();
+        let b = deflate.apply(&data).unwrap();
+        assert_ne!(a, b);
+        // the decompressor is selected from the payload alone
+        assert_eq!(decompress(&a, MAX_EXPANSION), Some(data.clone()));
+        assert_eq!(decompress(&b, MAX_EXPANSION), Some(data));
+        // unknown codec
+        let mut unknown = b.clone();
+        unknown[0] = 0;
+        assert_eq!(decompress(&unknown, MAX_EXPANSION), None);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd() {
+        let zstd = Compression {
+            above: 16,
+            max_expansion: MAX_EXPANSION,
+            codec: CompressionCodec::Zstd,
+        };
+        let data = "{\"walcraft\": 1}".repeat(100).into_bytes();
+        let compressed = zstd.apply(&data).unwrap();
+        assert_eq!(compressed[0], ZSTD_ID);
+        assert!(compressed.len() < data.len() / 10);
+        assert_eq!(decompress(&compressed, MAX_EXPANSION), Some(data));
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{meta, Lsn, Wal, WalError};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Named reader of a WAL keeping its committed position in the WAL directory, see
+/// [Wal::consumer]
+///
+/// [Consumer::next_batch] returns the logs after the ones already returned, and
+/// [Consumer::commit] records that logs were processed, in the `consumer_<name>` file. A consumer
+/// opened again under the same name, e.g. after a restart, resumes after the last committed log.
+/// Consumers with different names move independently, and logs not yet committed by every
+/// consumer are reported by [crate::WalBuilder::capacity_warnings] before their file is reused.
+/// A consumer holds a handle of the WAL, keeping it open until dropped.
+///
+/// # Example
+/// ```
+/// use walcraft::Wal;
+///
+/// # let _ = std::fs::remove_dir_all("./tmp/consumer_doc/");
+/// # std::fs::create_dir_all("./tmp/consumer_doc/").unwrap();
+/// let wal: Wal<String> = Wal::new("./tmp/consumer_doc/", 500).unwrap();
+/// wal.write("signup".to_string());
+/// wal.write("checkout".to_string());
+///
+/// let mut analytics = wal.consumer("analytics").unwrap();
+/// let batch = analytics.next_batch(10).unwrap();
+/// assert_eq!(batch[1].1, "checkout");
+/// analytics.commit(batch[1].0).unwrap();
+///
+/// // resumes after the committed logs
+/// let mut analytics = wal.consumer("analytics").unwrap();
+/// assert!(analytics.next_batch(10).unwrap().is_empty());
+/// ```
+pub struct Consumer<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    wal: Wal<T>,
+    name: String,
+    // sequence number of the next log returned by a batch
+    position: Lsn,
+    // sequence number of the first log not committed, also tracked by the WAL
+    committed: Arc<AtomicU64>,
+}
+
+impl<T> Consumer<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    // consumer `name`, starting after the logs it last committed
+    pub(crate) fn open(wal: Wal<T>, name: &str) -> Result<Self, WalError> {
+        check_name(name)?;
+        let path = Self::path(&wal, name);
+        let stored = wal
+            .layout
+            .storage
+            .read(&path)
+            .map_err(|e| WalError::io_at(e, "Failed to read consumer offset", &path))?;
+        let committed = match stored {
+            None => 0,
+            Some(content) => String::from_utf8(content)
+                .ok()
+                .and_then(|text| meta::unseal(&text)?.trim().parse().ok())
+                .ok_or_else(|| WalError::Corruption {
+                    path: path.clone(),
+                    segment: None,
+                    offset: None,
+                    reason: "unreadable consumer offset".to_string(),
+                })?,
+        };
+        let committed = Arc::new(AtomicU64::new(committed));
+        wal.cursor.consumers().register(&committed);
+        Ok(Self {
+            wal,
+            name: name.to_string(),
+            position: committed.load(Ordering::Acquire),
+            committed,
+        })
+    }
+
+    /// Name of the consumer
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The next `n` logs at most, along with their sequence number, from the oldest to the
+    /// newest
+    ///
+    /// Returns the logs after the ones returned by the previous batch, or after the committed
+    /// ones for the first batch, and an empty batch once the consumer caught up. Logs that can't
+    /// be deserialized are skipped, as are logs dropped from the WAL before they were returned.
+    pub fn next_batch(&mut self, n: usize) -> Result<Vec<(Lsn, T)>, WalError> {
+        let from = self.position;
+        let logs = self.wal.read_snapshot(|reader| {
+            let mut logs = reader.read_indexed(|_| true, |block| *block.lsns.end() >= from)?;
+            logs.extend(reader.pending());
+            logs.retain(|entry| entry.lsn() >= from);
+            logs.truncate(n);
+            Ok(logs)
+        })?;
+        if let Some(last) = logs.last() {
+            self.position = last.lsn() + 1;
+        }
+        Ok(logs
+            .into_iter()
+            .filter_map(|entry| {
+                let lsn = entry.lsn();
+                self.wal.decoder.decode(entry).map(|log| (lsn, log))
+            })
+            .collect())
+    }
+
+    /// Record that the logs up to `lsn` included were processed
+    ///
+    /// The position is written to storage before returning, so a consumer opened later under
+    /// the same name starts after `lsn`. Batches already returned aren't returned again.
+    pub fn commit(&self, lsn: Lsn) -> Result<(), WalError> {
+        self.wal.check_writable()?;
+        let next = lsn.saturating_add(1);
+        let path = Self::path(&self.wal, &self.name);
+        self.wal
+            .layout
+            .storage
+            .replace(&path, meta::seal(&next.to_string()).as_bytes(), "tmp")
+            .map_err(|e| WalError::io_at(e, "Failed to write consumer offset", &path))?;
+        self.committed.store(next, Ordering::Release);
+        Ok(())
+    }
+
+    /// Sequence number of the next log returned by [Consumer::next_batch]
+    pub fn position(&self) -> Lsn {
+        self.position
+    }
+
+    /// Sequence number of the first log not committed, where a consumer opened again resumes
+    pub fn committed(&self) -> Lsn {
+        self.committed.load(Ordering::Acquire)
+    }
+
+    /// Return the logs from `lsn` on with the next batch, e.g. to process logs again or to go
+    /// back to the committed position after a failure
+    pub fn seek(&mut self, lsn: Lsn) {
+        self.position = lsn;
+    }
+
+    fn path(wal: &Wal<T>, name: &str) -> PathBuf {
+        wal.location.join(format!("consumer_{}", name))
+    }
+}
+
+// Fail unless `name` can be part of a file name
+fn check_name(name: &str) -> Result<(), WalError> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    match valid {
+        true => Ok(()),
+        false => Err(WalError::Unsupported(format!(
+            "Consumer name {:?} may only hold letters, digits, '-', '_' and '.'",
+            name
+        ))),
+    }
+}
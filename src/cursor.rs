@@ -0,0 +1,104 @@
+use crate::Lsn;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+// In-memory read position of a single WAL handle
+// Cloning a cursor copies its position, so every handle moves its own cursor independently
+pub(crate) struct Cursor {
+    next: Arc<AtomicU64>,
+    // read positions of all handles of the WAL, which this one joins once it's used
+    consumers: Consumers,
+    registered: AtomicBool,
+}
+
+impl Cursor {
+    pub fn new(consumers: Consumers) -> Self {
+        Self {
+            next: Arc::new(AtomicU64::new(0)),
+            consumers,
+            registered: AtomicBool::new(false),
+        }
+    }
+
+    // sequence number of the next log to be read
+    pub fn get(&self) -> Lsn {
+        self.next.load(Ordering::Acquire)
+    }
+
+    // read positions of all handles of the WAL
+    pub fn consumers(&self) -> &Consumers {
+        &self.consumers
+    }
+
+    // move the cursor to `lsn`
+    pub fn set(&self, lsn: Lsn) {
+        self.next.store(lsn, Ordering::Release);
+        if !self.registered.swap(true, Ordering::AcqRel) {
+            self.consumers.register(&self.next);
+        }
+    }
+}
+
+impl Clone for Cursor {
+    fn clone(&self) -> Self {
+        let cursor = Self {
+            next: Arc::new(AtomicU64::new(self.get())),
+            consumers: self.consumers.clone(),
+            registered: AtomicBool::new(false),
+        };
+        // a clone of a consumer is a consumer too
+        if self.registered.load(Ordering::Acquire) {
+            cursor.set(self.get());
+        }
+        cursor
+    }
+}
+
+// Read positions of the handles consuming the WAL with [crate::Wal::entries_since], and
+// committed positions of [crate::Consumer]s
+// Positions of dropped handles are forgotten
+#[derive(Clone, Default)]
+pub(crate) struct Consumers(Arc<Mutex<Vec<Weak<AtomicU64>>>>);
+
+impl Consumers {
+    // track the sequence number of the next log to be read by a consumer, until it's dropped
+    pub fn register(&self, next: &Arc<AtomicU64>) {
+        self.lock().push(Arc::downgrade(next));
+    }
+
+    // sequence number of the next log to be read by the consumer furthest behind, None without
+    // consumers
+    pub fn slowest(&self) -> Option<Lsn> {
+        let mut cursors = self.lock();
+        cursors.retain(|c| c.strong_count() > 0);
+        cursors
+            .iter()
+            .filter_map(|c| c.upgrade())
+            .map(|c| c.load(Ordering::Acquire))
+            .min()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Weak<AtomicU64>>> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slowest() {
+        let consumers = Consumers::default();
+        let first = Cursor::new(consumers.clone());
+        // handles that never read aren't consumers
+        let _idle = Cursor::new(consumers.clone());
+        assert_eq!(consumers.slowest(), None);
+        first.set(10);
+        let second = first.clone();
+        second.set(4);
+        assert_eq!(consumers.slowest(), Some(4));
+        drop(second);
+        assert_eq!(consumers.slowest(), Some(10));
+    }
+}
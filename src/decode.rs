@@ -0,0 +1,285 @@
+use crate::codec::Codec;
+use crate::{LogEntry, Lsn};
+use serde::Deserialize;
+use std::sync::Arc;
+
+// Deserializes a payload written as an older type, converting it to the current type
+pub(crate) type Fallback<T> = Arc<dyn Fn(Codec, &[u8]) -> Option<T> + Send + Sync>;
+
+// Deserializes payloads as `T`, then with every fallback registered with
+// [crate::WalBuilder::decode_fallback] in turn
+pub(crate) struct Decoder<T> {
+    codec: Codec,
+    fallbacks: Arc<[Fallback<T>]>,
+}
+
+impl<T> Clone for Decoder<T> {
+    fn clone(&self) -> Self {
+        Self {
+            codec: self.codec,
+            fallbacks: self.fallbacks.clone(),
+        }
+    }
+}
+
+impl<T> Decoder<T>
+where
+    T: for<'a> Deserialize<'a>,
+{
+    pub fn new(codec: Codec, fallbacks: Vec<Fallback<T>>) -> Self {
+        Self {
+            codec,
+            fallbacks: fallbacks.into(),
+        }
+    }
+
+    pub fn decode(&self, entry: LogEntry) -> Option<T> {
+        self.decode_payload(entry.payload())
+    }
+
+    pub fn decode_payload(&self, payload: &[u8]) -> Option<T> {
+        self.try_decode_payload(payload).ok().map(|(log, _)| log)
+    }
+
+    // deserialize a payload, along with the index of the decoder that handled it, 0 for `T`
+    // itself, or describe why `T` can't deserialize it
+    fn try_decode_payload(&self, payload: &[u8]) -> Result<(T, usize), String> {
+        let error = match self.codec.try_decode_payload(payload) {
+            Ok(log) => return Ok((log, 0)),
+            Err(e) => e,
+        };
+        self.fallbacks
+            .iter()
+            .enumerate()
+            .find_map(|(i, fallback)| fallback(self.codec, payload).map(|log| (log, i + 1)))
+            .ok_or(error)
+    }
+
+    // number of decoders, `T` itself included
+    fn count(&self) -> usize {
+        self.fallbacks.len() + 1
+    }
+}
+
+/// Logs read along with the number of logs that couldn't be deserialized, as returned by
+/// [crate::Wal::read_reported]
+///
+/// Logs that can't be deserialized are skipped by every read. A rising number of them usually
+/// means that the type of logs changed in a way older logs no longer fit.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct DecodeReport<T> {
+    /// Logs deserialized, in the order they were written
+    pub logs: Vec<T>,
+    /// Number of logs deserialized
+    pub decoded: u64,
+    /// Number of logs deserialized by every decoder: `T` itself first, then the migration of
+    /// [crate::WalBuilder::versioned] if any, followed by the fallbacks registered with
+    /// [crate::WalBuilder::decode_fallback] in their order
+    pub decoders: Vec<u64>,
+    /// Number of logs skipped as they couldn't be deserialized
+    pub skipped: u64,
+    /// Errors of the first skipped logs, at most [DecodeReport::MAX_ERRORS] of them
+    pub errors: Vec<DecodeError>,
+}
+
+impl<T> DecodeReport<T> {
+    /// Number of skipped logs whose error is kept in the report
+    pub const MAX_ERRORS: usize = 8;
+
+    // count a log, keeping it if it was deserialized
+    fn add(&mut self, result: Result<(T, usize), DecodeError>) {
+        match result {
+            Ok((log, decoder)) => {
+                self.decoded += 1;
+                self.decoders[decoder] += 1;
+                self.logs.push(log);
+            }
+            Err(e) => {
+                self.skipped += 1;
+                if self.errors.len() < Self::MAX_ERRORS {
+                    self.errors.push(e);
+                }
+            }
+        }
+    }
+}
+
+/// Log that couldn't be deserialized
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DecodeError {
+    /// Sequence number of the log
+    pub lsn: Lsn,
+    /// Why the log couldn't be deserialized
+    pub message: String,
+}
+
+// Deserialize logs on `threads` worker threads, keeping them in their original order
+pub(crate) fn decode<T>(
+    entries: Vec<LogEntry>,
+    decoder: &Decoder<T>,
+    threads: usize,
+    window: usize,
+) -> Vec<T>
+where
+    T: for<'a> Deserialize<'a> + Send,
+{
+    decode_reported(entries, decoder, threads, window).logs
+}
+
+// Deserialize logs on `threads` worker threads, keeping them in their original order and
+// counting the logs that can't be deserialized
+// Logs are taken in windows of about `window` bytes of payload, which are split between the
+// workers and merged back before the next window is started, so only a single window of logs
+// is being decoded at a time
+pub(crate) fn decode_reported<T>(
+    entries: Vec<LogEntry>,
+    decoder: &Decoder<T>,
+    threads: usize,
+    window: usize,
+) -> DecodeReport<T>
+where
+    T: for<'a> Deserialize<'a> + Send,
+{
+    let mut report = DecodeReport {
+        logs: Vec::with_capacity(entries.len()),
+        decoded: 0,
+        decoders: vec![0; decoder.count()],
+        skipped: 0,
+        errors: Vec::new(),
+    };
+    if threads <= 1 {
+        for entry in entries {
+            report.add(decode_one(decoder, entry));
+        }
+        return report;
+    }
+    let mut entries = entries.into_iter().peekable();
+    loop {
+        // take the next window of logs
+        let mut batch = Vec::new();
+        let mut used = 0usize;
+        while let Some(entry) = entries.peek() {
+            if !batch.is_empty() && used.saturating_add(entry.size()) > window {
+                break;
+            }
+            used += entry.size();
+            batch.extend(entries.next());
+        }
+        if batch.is_empty() {
+            break;
+        }
+
+        // split the window in contiguous chunks, one per worker
+        let chunk_size = batch.len().div_ceil(threads);
+        let mut chunks = Vec::with_capacity(threads);
+        let mut batch = batch.into_iter();
+        loop {
+            let chunk = batch.by_ref().take(chunk_size).collect::<Vec<_>>();
+            if chunk.is_empty() {
+                break;
+            }
+            chunks.push(chunk);
+        }
+
+        // decode in parallel and merge the results in order of chunks
+        std::thread::scope(|s| {
+            let workers = chunks
+                .into_iter()
+                .map(|chunk| {
+                    s.spawn(move || {
+                        chunk
+                            .into_iter()
+                            .map(|e| decode_one(decoder, e))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            for worker in workers {
+                if let Ok(decoded) = worker.join() {
+                    for result in decoded {
+                        report.add(result);
+                    }
+                }
+            }
+        });
+    }
+    report
+}
+
+fn decode_one<T>(decoder: &Decoder<T>, entry: LogEntry) -> Result<(T, usize), DecodeError>
+where
+    T: for<'a> Deserialize<'a>,
+{
+    decoder
+        .try_decode_payload(entry.payload())
+        .map_err(|message| DecodeError {
+            lsn: entry.lsn(),
+            message,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preserves_order() {
+        let entries = || {
+            (0..1000u64)
+                .map(|i| LogEntry::try_new(&i).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let expected = (0..1000u64).collect::<Vec<_>>();
+        let decoder = Decoder::new(Codec::Bincode, Vec::new());
+        assert_eq!(decode::<u64>(entries(), &decoder, 1, 64), expected);
+        assert_eq!(decode::<u64>(entries(), &decoder, 4, 64), expected);
+        assert_eq!(decode::<u64>(entries(), &decoder, 3, usize::MAX), expected);
+    }
+    #[test]
+    fn report() {
+        let mut entries = (0..20u64)
+            .map(|i| LogEntry::try_new(&i).unwrap())
+            .collect::<Vec<_>>();
+        // logs too short to hold a u64
+        for lsn in 0..10 {
+            entries.push(LogEntry::from_vec(vec![1], 100 + lsn));
+        }
+        let decoder = Decoder::new(Codec::Bincode, Vec::new());
+        for threads in [1, 3] {
+            let report = decode_reported::<u64>(entries.clone(), &decoder, threads, 64);
+            assert_eq!(report.logs, (0..20).collect::<Vec<_>>());
+            assert_eq!((report.decoded, report.skipped), (20, 10));
+            assert_eq!(report.decoders, vec![20]);
+            assert_eq!(report.errors.len(), DecodeReport::<u64>::MAX_ERRORS);
+            assert_eq!(report.errors[0].lsn, 100);
+        }
+    }
+
+    #[test]
+    fn fallbacks() {
+        // logs written as a byte, then as a u16 and finally as a u32
+        let entries = vec![
+            LogEntry::try_new(&7u32).unwrap(),
+            LogEntry::try_new(&5u16).unwrap(),
+            LogEntry::try_new(&3u8).unwrap(),
+            LogEntry::try_new(&8u32).unwrap(),
+        ];
+        let fallbacks: Vec<Fallback<u32>> = vec![
+            Arc::new(|codec: Codec, payload: &[u8]| {
+                codec.decode_payload::<u16>(payload).map(|v| v as u32 * 10)
+            }),
+            Arc::new(|codec: Codec, payload: &[u8]| {
+                codec.decode_payload::<u8>(payload).map(|v| v as u32 * 100)
+            }),
+        ];
+        let decoder = Decoder::new(Codec::Bincode, fallbacks);
+        for threads in [1, 2] {
+            let report = decode_reported(entries.clone(), &decoder, threads, 64);
+            assert_eq!(report.logs, vec![7, 50, 300, 8]);
+            assert_eq!(report.decoders, vec![2, 1, 1]);
+            assert_eq!(report.skipped, 0);
+        }
+    }
+}
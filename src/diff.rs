@@ -0,0 +1,72 @@
+use crate::compaction::KeyFn;
+use crate::entry::LogEntry;
+use crate::Lsn;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Logs written between two points of a WAL, as returned by [crate::Wal::diff]
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Diff<T> {
+    /// Every log written in the range, in the order they were written
+    pub added: Vec<T>,
+    /// Net effect of the range in keyed mode: the newest log of every key written in the range,
+    /// in the order they were written. Empty unless the WAL is keyed.
+    pub latest_by_key: Vec<T>,
+}
+
+// Logs of `entries` within `range`, along with the newest of them for every key when `key` is set
+pub(crate) fn split(
+    entries: Vec<LogEntry>,
+    range: Range<Lsn>,
+    key: Option<&KeyFn>,
+) -> (Vec<LogEntry>, Vec<LogEntry>) {
+    let added = entries
+        .into_iter()
+        .filter(|entry| range.contains(&entry.lsn()))
+        .collect::<Vec<_>>();
+    let key = match key {
+        Some(key) => key,
+        None => return (added, Vec::new()),
+    };
+    let mut newest = HashMap::new();
+    for (i, entry) in added.iter().enumerate() {
+        if let Some(hash) = key(entry.payload()) {
+            newest.insert(hash, i);
+        }
+    }
+    let mut latest = newest.into_values().collect::<Vec<_>>();
+    latest.sort_unstable();
+    let latest = latest.into_iter().map(|i| added[i].clone()).collect();
+    (added, latest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn net_effect() {
+        // logs of keys 1, 2, 1, 3, 2 with sequence numbers 0 to 4
+        let entries = || {
+            [1u8, 2, 1, 3, 2]
+                .iter()
+                .enumerate()
+                .map(|(lsn, k)| LogEntry::from_vec(vec![*k, lsn as u8], lsn as Lsn))
+                .collect::<Vec<_>>()
+        };
+        let key: KeyFn = Arc::new(|payload| payload.first().map(|k| *k as u64));
+        let lsns = |logs: Vec<LogEntry>| logs.iter().map(|e| e.lsn()).collect::<Vec<_>>();
+
+        let (added, latest) = split(entries(), 1..4, None);
+        assert_eq!(lsns(added), [1, 2, 3]);
+        assert!(latest.is_empty());
+
+        let (added, latest) = split(entries(), 0..5, Some(&key));
+        assert_eq!(lsns(added), [0, 1, 2, 3, 4]);
+        assert_eq!(lsns(latest), [2, 3, 4]);
+        let (_, latest) = split(entries(), 0..3, Some(&key));
+        assert_eq!(lsns(latest), [1, 2]);
+    }
+}
@@ -0,0 +1,37 @@
+use std::sync::mpsc::Receiver;
+
+// most logs of a single batch of [crate::Wal::drain_channel]
+pub(crate) const MAX_BATCH: usize = 1024;
+
+/// Receiving end of a channel drained by [crate::Wal::drain_channel]
+///
+/// Implemented for [std::sync::mpsc::Receiver], and for `crossbeam_channel::Receiver` with the
+/// `crossbeam` feature.
+pub trait ChannelReceiver<T> {
+    /// Wait for the next log, None once the channel is empty and all senders are gone
+    fn recv_log(&self) -> Option<T>;
+
+    /// The next log if one is ready, without waiting
+    fn try_recv_log(&self) -> Option<T>;
+}
+
+impl<T> ChannelReceiver<T> for Receiver<T> {
+    fn recv_log(&self) -> Option<T> {
+        self.recv().ok()
+    }
+
+    fn try_recv_log(&self) -> Option<T> {
+        self.try_recv().ok()
+    }
+}
+
+#[cfg(feature = "crossbeam")]
+impl<T> ChannelReceiver<T> for crossbeam_channel::Receiver<T> {
+    fn recv_log(&self) -> Option<T> {
+        self.recv().ok()
+    }
+
+    fn try_recv_log(&self) -> Option<T> {
+        self.try_recv().ok()
+    }
+}
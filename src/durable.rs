@@ -0,0 +1,122 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+#[cfg(windows)]
+use std::time::Duration;
+
+// attempts at replacing a file held open by another process, on Windows
+#[cfg(windows)]
+const RENAME_ATTEMPTS: u32 = 10;
+// wait before the next attempt, growing with every failed one
+#[cfg(windows)]
+const RENAME_BACKOFF: Duration = Duration::from_millis(5);
+
+// Replace the content of `path` at once, going through the temporary file `tmp`
+// A crash leaves either the old content or the new one, as the temporary file is synced before
+// it replaces `path`, and the directory is synced after.
+pub(crate) fn write_atomic(path: &Path, tmp: &Path, content: &[u8]) -> io::Result<()> {
+    write_synced(tmp, content)?;
+    replace(tmp, path)
+}
+
+// Write `content` to `path`, replacing what it held, and sync it to storage
+pub(crate) fn write_synced(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut file = create(path)?;
+    file.write_all(content)?;
+    file.sync_all()
+}
+
+// Create an empty file at `path`, emptying the one left there by an interrupted attempt
+pub(crate) fn create(path: &Path) -> io::Result<File> {
+    let open = || {
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+    };
+    match open() {
+        // Windows refuses to truncate a hidden or read-only file, which is removed instead
+        #[cfg(windows)]
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied && path.is_file() => {
+            std::fs::remove_file(path)?;
+            open()
+        }
+        result => result,
+    }
+}
+
+// Move the file `from` over `to`, then sync the directory so the new name survives a crash
+pub(crate) fn replace(from: &Path, to: &Path) -> io::Result<()> {
+    rename(from, to)?;
+    sync_parent(to)
+}
+
+#[cfg(not(windows))]
+fn rename(from: &Path, to: &Path) -> io::Result<()> {
+    std::fs::rename(from, to)
+}
+
+// Windows refuses to replace a file open in another process without sharing its deletion, such
+// as the meta file being read by a read-only handle, until that process closes it
+#[cfg(windows)]
+fn rename(from: &Path, to: &Path) -> io::Result<()> {
+    let mut attempt = 1;
+    loop {
+        match std::fs::rename(from, to) {
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied && attempt < RENAME_ATTEMPTS => {
+                std::thread::sleep(RENAME_BACKOFF * attempt);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Sync the directory holding `path`, making the files created, renamed or removed in it durable
+#[cfg(unix)]
+pub(crate) fn sync_parent(path: &Path) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+// Directories can't be opened as files elsewhere, and NTFS journals the changes of names
+#[cfg(not(unix))]
+pub(crate) fn sync_parent(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_atomic_over_existing() {
+        let dir = Path::new("./tmp/durable_write_atomic/");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+        let (path, tmp) = (dir.join("meta"), dir.join("meta.tmp"));
+
+        write_atomic(&path, &tmp, b"first").unwrap();
+        // a temporary file left by a crash is replaced, as is the previous content
+        std::fs::write(&tmp, b"left over by a crash").unwrap();
+        write_atomic(&path, &tmp, b"second").unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+        assert!(!tmp.exists());
+
+        // a file held open by a reader is replaced as well
+        let reader = File::open(&path).unwrap();
+        write_atomic(&path, &tmp, b"third").unwrap();
+        drop(reader);
+        assert_eq!(std::fs::read(&path).unwrap(), b"third");
+    }
+
+    #[test]
+    fn sync_parent_of_relative_path() {
+        sync_parent(Path::new("Cargo.toml")).unwrap();
+        sync_parent(Path::new("./tmp")).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+// Encryption of log payloads at rest, available with the `encryption` feature
+// Without the feature no key can be set, and WALs holding encrypted files fail to open
+//
+// Payloads are sealed with ChaCha20-Poly1305 once compressed, so WAL files hold no log in the
+// clear, while the sequence number, producer, timestamp and tag in the header of every frame stay
+// readable. Every file gets a random nonce when it is started, recorded right after its header,
+// and the nonce of a log is the nonce of its file with its sequence number mixed in, unique
+// within the file. Frames a crash left behind are resealed under a new nonce before the file is
+// written again, so a nonce never seals two payloads.
+
+use crate::format::SEGMENT_NONCE_BYTES;
+use crate::Lsn;
+
+// bytes of the keys encrypting logs
+pub(crate) const KEY_BYTES: usize = 32;
+
+// Nonce recorded after the header of an encrypted file
+pub(crate) type Nonce = [u8; SEGMENT_NONCE_BYTES];
+
+// Key encrypting the payloads of a WAL
+#[derive(Clone)]
+pub(crate) struct Cipher {
+    key: [u8; KEY_BYTES],
+}
+
+impl Cipher {
+    #[cfg(feature = "encryption")]
+    pub fn new(key: [u8; KEY_BYTES]) -> Self {
+        Self { key }
+    }
+
+    // cipher of the frames of a file recording `nonce`
+    pub fn file(&self, nonce: Nonce) -> FileCipher<'_> {
+        FileCipher {
+            cipher: self,
+            nonce,
+        }
+    }
+
+    // random nonce of a new file
+    pub fn nonce() -> Nonce {
+        chacha::nonce()
+    }
+}
+
+// Cipher of the frames of a single file
+#[derive(Clone, Copy)]
+pub(crate) struct FileCipher<'a> {
+    cipher: &'a Cipher,
+    nonce: Nonce,
+}
+
+impl FileCipher<'_> {
+    // nonce sealing the payload of the log `lsn`
+    fn log_nonce(&self, lsn: Lsn) -> Nonce {
+        let mut nonce = self.nonce;
+        for (byte, mixed) in nonce[SEGMENT_NONCE_BYTES - 8..]
+            .iter_mut()
+            .zip(lsn.to_le_bytes())
+        {
+            *byte ^= mixed;
+        }
+        nonce
+    }
+
+    // payload of the log `lsn` sealed, None if it can't be encrypted
+    pub fn seal(&self, lsn: Lsn, payload: &[u8]) -> Option<Vec<u8>> {
+        chacha::seal(&self.cipher.key, &self.log_nonce(lsn), payload)
+    }
+
+    // payload of the log `lsn` opened, None if it was altered or sealed with another key
+    pub fn open(&self, lsn: Lsn, sealed: &[u8]) -> Option<Vec<u8>> {
+        chacha::open(&self.cipher.key, &self.log_nonce(lsn), sealed)
+    }
+}
+
+#[cfg(feature = "encryption")]
+mod chacha {
+    use super::{Nonce, KEY_BYTES};
+    use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+    use chacha20poly1305::ChaCha20Poly1305;
+
+    pub fn nonce() -> Nonce {
+        ChaCha20Poly1305::generate_nonce(&mut OsRng).into()
+    }
+
+    pub fn seal(key: &[u8; KEY_BYTES], nonce: &Nonce, payload: &[u8]) -> Option<Vec<u8>> {
+        ChaCha20Poly1305::new(key.into())
+            .encrypt(nonce.into(), payload)
+            .ok()
+    }
+
+    pub fn open(key: &[u8; KEY_BYTES], nonce: &Nonce, sealed: &[u8]) -> Option<Vec<u8>> {
+        ChaCha20Poly1305::new(key.into())
+            .decrypt(nonce.into(), sealed)
+            .ok()
+    }
+}
+
+#[cfg(not(feature = "encryption"))]
+mod chacha {
+    use super::{Nonce, KEY_BYTES};
+
+    pub fn nonce() -> Nonce {
+        Nonce::default()
+    }
+
+    pub fn seal(_key: &[u8; KEY_BYTES], _nonce: &Nonce, _payload: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+
+    pub fn open(_key: &[u8; KEY_BYTES], _nonce: &Nonce, _sealed: &[u8]) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+#[cfg(all(test, feature = "encryption"))]
+mod tests {
+    use super::*;
+    use crate::format::SEAL_BYTES;
+
+    #[test]
+    fn seal() {
+        let cipher = Cipher::new([7; KEY_BYTES]);
+        let nonce = Cipher::nonce();
+        let file = cipher.file(nonce);
+        let sealed = file.seal(3, b"balance 10").unwrap();
+        assert_eq!(sealed.len(), 10 + SEAL_BYTES);
+        assert_eq!(file.open(3, &sealed).unwrap(), b"balance 10");
+        // the nonce of every log differs, and so does every key
+        assert_ne!(file.seal(4, b"balance 10").unwrap(), sealed);
+        assert!(file.open(4, &sealed).is_none());
+        let other = Cipher::new([8; KEY_BYTES]);
+        assert!(other.file(nonce).open(3, &sealed).is_none());
+    }
+}
@@ -1,40 +1,259 @@
-use serde::{Deserialize, Serialize};
+use crate::compression::Compression;
+use crate::encryption::FileCipher;
+use crate::format::{
+    self, encode_varint, fixed_frame_size, frame_overhead_bytes, BATCH_FLAG, BLOB_FLAG,
+    CHECKSUM_BYTES, COMPRESSED_FLAG, MONOTONIC_BYTES, PRODUCER_BYTES, PRODUCER_FLAG, TAG_FLAG,
+    TIMESTAMP_BYTES, TIMESTAMP_FLAG, WALL_CLOCK_BYTES, WALL_CLOCK_FLAG,
+};
+use crate::{Lsn, ProducerId};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct LogEntry {
     inner: Vec<u8>,
-    // checksum: u32 <- for future usage - Todo
+    // sequence number, assigned when the log is added to the buffer
+    lsn: Lsn,
+    // handle that wrote the log, if it was given an id
+    producer: Option<ProducerId>,
+    // hash of the key of the log in keyed mode, only held in memory
+    key: Option<u64>,
+    // more logs of the same atomic batch follow this one
+    batched: bool,
+    // the payload is a reference to a blob holding the serialized log
+    blob: bool,
+    // when the log was written, in milliseconds since the unix epoch, if it was stamped
+    timestamp: Option<u64>,
+    // reading of the wall clock when the log was written, with strict timestamps
+    wall_clock: Option<u64>,
+    // nanoseconds since the unix epoch read from the monotonic clock, with strict timestamps
+    monotonic: Option<u64>,
+    // tag the log was written with, at most MAX_TAG_BYTES
+    tag: Option<Vec<u8>>,
 }
 
 impl LogEntry {
-    pub fn new<T>(data: T) -> Option<LogEntry>
-    where
-        T: Serialize + for<'a> Deserialize<'a>,
-    {
-        let encoded = match bincode::serialize(&data) {
-            Ok(d) => d,
-            Err(_) => {
-                return None;
-            }
-        };
-        Some(Self { inner: encoded })
-    }
-
-    pub fn from_vec(v: Vec<u8>) -> Self {
-        Self { inner: v }
-    }
-
-    pub fn to_vec(self) -> Vec<u8> {
-        let size: [u8; 4] = (self.inner.len() as u32).to_ne_bytes();
-        let mut out = Vec::from(size);
-        out.extend(self.inner.into_iter());
+    #[cfg(test)]
+    pub fn try_new<T: serde::Serialize>(data: &T) -> Result<LogEntry, crate::WalError> {
+        crate::Codec::Bincode.encode(data)
+    }
+
+    pub fn from_vec(v: Vec<u8>, lsn: Lsn) -> Self {
+        Self {
+            inner: v,
+            lsn,
+            producer: None,
+            key: None,
+            batched: false,
+            blob: false,
+            timestamp: None,
+            wall_clock: None,
+            monotonic: None,
+            tag: None,
+        }
+    }
+
+    pub fn lsn(&self) -> Lsn {
+        self.lsn
+    }
+
+    pub fn set_lsn(&mut self, lsn: Lsn) {
+        self.lsn = lsn;
+    }
+
+    pub fn producer(&self) -> Option<ProducerId> {
+        self.producer
+    }
+
+    pub fn set_producer(&mut self, producer: Option<ProducerId>) {
+        self.producer = producer;
+    }
+
+    pub fn key(&self) -> Option<u64> {
+        self.key
+    }
+
+    pub fn set_key(&mut self, key: Option<u64>) {
+        self.key = key;
+    }
+
+    pub fn batched(&self) -> bool {
+        self.batched
+    }
+
+    pub fn set_batched(&mut self, batched: bool) {
+        self.batched = batched;
+    }
+
+    pub fn timestamp(&self) -> Option<u64> {
+        self.timestamp
+    }
+
+    pub fn set_timestamp(&mut self, timestamp: Option<u64>) {
+        self.timestamp = timestamp;
+    }
+
+    pub fn wall_clock(&self) -> Option<u64> {
+        self.wall_clock
+    }
+
+    pub fn set_wall_clock(&mut self, wall_clock: Option<u64>) {
+        self.wall_clock = wall_clock;
+    }
+
+    pub fn monotonic(&self) -> Option<u64> {
+        self.monotonic
+    }
+
+    pub fn set_monotonic(&mut self, monotonic: Option<u64>) {
+        self.monotonic = monotonic;
+    }
+
+    pub fn tag(&self) -> Option<&[u8]> {
+        self.tag.as_deref()
+    }
+
+    pub fn set_tag(&mut self, tag: Option<Vec<u8>>) {
+        self.tag = tag;
+    }
+
+    pub fn blob(&self) -> bool {
+        self.blob
+    }
+
+    // replace the payload with a reference to the blob holding it
+    pub fn set_blob(&mut self, reference: Vec<u8>) {
+        self.inner = reference;
+        self.blob = true;
+    }
+
+    // replace the reference with the payload held by the blob
+    pub fn resolve_blob(&mut self, payload: Vec<u8>) {
+        self.inner = payload;
+        self.blob = false;
+    }
+
+    // serialized payload
+    pub fn payload(&self) -> &[u8] {
+        &self.inner
+    }
+
+    pub fn into_payload(self) -> Vec<u8> {
+        self.inner
+    }
+
+    // size of serialized payload, without the frame overhead
+    pub fn size(&self) -> usize {
+        self.inner.len()
+    }
+
+    // Encode the log as a frame, compressing its payload when `compression` is worth it
+    // The frame carries a checksum when `checksummed` is set
+    #[cfg(test)]
+    pub fn into_frame(self, compression: Option<Compression>, checksummed: bool) -> Vec<u8> {
+        let mut out = Vec::new();
+        let _ = self.encode_frame(compression, None, checksummed, &mut out);
         out
     }
 
-    pub fn to_original<T>(self) -> Option<T>
-    where
-        T: Serialize + for<'a> Deserialize<'a>,
-    {
-        bincode::deserialize(&self.inner).ok()
+    // Encode the log as a frame appended to `out`, so a batch of logs fills a single buffer
+    // The payload is sealed with `cipher` once compressed, when the file is encrypted, and the
+    // frame carries a checksum when `checksummed` is set. Returns the buffer of the payload, to
+    // serialize another log into.
+    pub fn encode_frame(
+        self,
+        compression: Option<Compression>,
+        cipher: Option<FileCipher>,
+        checksummed: bool,
+        out: &mut Vec<u8>,
+    ) -> Vec<u8> {
+        // keep the raw payload when compression doesn't help
+        let smaller = compression.and_then(|c| c.apply(&self.inner));
+        let compressed = smaller.is_some();
+        // a payload that can't be sealed is dropped rather than stored in the clear
+        let sealed = cipher.map(|cipher| {
+            let payload = smaller.as_deref().unwrap_or(&self.inner);
+            cipher.seal(self.lsn, payload).unwrap_or_default()
+        });
+        let payload = sealed
+            .as_deref()
+            .or(smaller.as_deref())
+            .unwrap_or(&self.inner);
+        let mut size = payload.len() as u32;
+        if compressed {
+            size |= COMPRESSED_FLAG;
+        }
+        if self.producer.is_some() {
+            size |= PRODUCER_FLAG;
+        }
+        if self.batched {
+            size |= BATCH_FLAG;
+        }
+        if self.blob {
+            size |= BLOB_FLAG;
+        }
+        if self.timestamp.is_some() {
+            size |= TIMESTAMP_FLAG;
+        }
+        if self.wall_clock.is_some() {
+            size |= WALL_CLOCK_FLAG;
+        }
+        if self.tag.is_some() {
+            size |= TAG_FLAG;
+        }
+        let tag = self.tag.as_ref().map_or(0, |tag| tag.len() + 1);
+        out.reserve(
+            frame_overhead_bytes()
+                + CHECKSUM_BYTES
+                + PRODUCER_BYTES
+                + TIMESTAMP_BYTES
+                + WALL_CLOCK_BYTES
+                + MONOTONIC_BYTES
+                + tag
+                + payload.len(),
+        );
+        let start = out.len();
+        out.extend(size.to_le_bytes());
+        if checksummed {
+            out.extend([0; CHECKSUM_BYTES]);
+        }
+        out.extend(self.lsn.to_le_bytes());
+        if let Some(producer) = self.producer {
+            out.extend(producer.to_le_bytes());
+        }
+        if let Some(timestamp) = self.timestamp {
+            out.extend(timestamp.to_le_bytes());
+        }
+        if let Some(wall_clock) = self.wall_clock {
+            out.extend(wall_clock.to_le_bytes());
+            out.extend(self.monotonic.unwrap_or_default().to_le_bytes());
+        }
+        if let Some(tag) = &self.tag {
+            out.push(tag.len() as u8);
+            out.extend(tag);
+        }
+        out.extend(payload);
+        if checksummed {
+            format::stamp_checksum(&mut out[start..]);
+        }
+        self.inner
+    }
+
+    // Encode the log as a varint size followed by the payload, appended to `out`, returning the
+    // buffer of the payload
+    pub fn encode_delimited_frame(self, out: &mut Vec<u8>) -> Vec<u8> {
+        out.reserve(self.inner.len() + 10);
+        encode_varint(self.inner.len() as u64, out);
+        out.extend(&self.inner);
+        self.inner
+    }
+
+    // Encode the log as a fixed size record, padding the payload to `record_size` bytes, appended
+    // to `out`, returning the buffer of the payload
+    // Fixed size records don't record the producer, the timestamps nor the tag
+    pub fn encode_fixed_frame(self, record_size: usize, out: &mut Vec<u8>) -> Vec<u8> {
+        let end = out.len() + fixed_frame_size(record_size);
+        out.extend(self.lsn.to_le_bytes());
+        out.extend(&self.inner);
+        out.resize(end, 0);
+        self.inner
     }
 }
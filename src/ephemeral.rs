@@ -0,0 +1,40 @@
+// Private directory of a WAL held in memory, see [crate::Wal::in_memory]
+//
+// The WAL files of such a WAL live in a [crate::MemoryStorage], while the few small files
+// recording its state, such as the meta file, go to a directory of its own under the temporary
+// directory of the system. WALs held in memory never share a directory, and the directory is
+// removed once the builder and every handle of the WAL are gone.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// directories created by this process so far, naming the next one
+static CREATED: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug)]
+pub(crate) struct EphemeralDir {
+    path: PathBuf,
+}
+
+impl EphemeralDir {
+    pub fn create() -> Self {
+        let id = CREATED.fetch_add(1, Ordering::Relaxed);
+        let name = format!("walcraft-{}-{}", std::process::id(), id);
+        let path = std::env::temp_dir().join(name);
+        // a crashed process of the same id may have left the directory behind
+        let _ = std::fs::remove_dir_all(&path);
+        // a failure surfaces when the WAL writes its first file
+        let _ = std::fs::create_dir_all(&path);
+        Self { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for EphemeralDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
@@ -0,0 +1,103 @@
+use crate::{Lsn, Recovery, WriteFailure};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Receiver of what happens in a WAL, to wire it into the logging and metrics of the
+/// application, see [crate::WalBuilder::event_listener]
+///
+/// Every event is ignored unless its method is implemented. Events are sent from the writer
+/// thread, which is blocked while they're handled, except [EventListener::on_recovery] sent
+/// while the WAL is opened.
+pub trait EventListener: Send + Sync {
+    /// A batch of logs was written to a file
+    fn on_flush(&self, _flush: &FlushEvent) {}
+
+    /// The writer moved on to the next file
+    fn on_rotate(&self, _rotation: &RotationEvent) {}
+
+    /// The writer failed to store logs or metadata, as reported by [crate::Wal::last_error]
+    fn on_error(&self, _failure: &WriteFailure) {}
+
+    /// The meta file disagreed with the WAL files and was corrected when the WAL was opened, as
+    /// reported by [crate::Wal::recovery]
+    fn on_recovery(&self, _recovery: &Recovery) {}
+}
+
+/// Batch of logs written to a file, see [EventListener::on_flush]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FlushEvent {
+    /// Sequence number of the WAL file written to, i.e. `N` in `wal_N`
+    pub segment: u8,
+    /// Number of logs in the batch
+    pub entries: u64,
+    /// Bytes of the frames of the batch
+    pub bytes: u64,
+    /// Sequence numbers of the first and the last log of the batch
+    pub lsns: Option<RangeInclusive<Lsn>>,
+}
+
+/// Move of the writer to the next file, see [EventListener::on_rotate]
+///
+/// Sent once for every file the writer leaves behind, which is sealed: no log is added to it
+/// until the writer comes back to it and empties it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RotationEvent {
+    /// File left behind, i.e. `N` in `wal_N`
+    pub from: u8,
+    /// File the writer goes on with, emptied before its first log
+    pub to: u8,
+    /// Path of the file left behind
+    pub path: PathBuf,
+    /// Sequence numbers of the first and the last log of the file left behind, None if it holds
+    /// no log
+    pub lsns: Option<RangeInclusive<Lsn>>,
+    /// Number of logs in the file left behind
+    pub entries: u64,
+    /// Size of the file left behind in bytes
+    pub bytes: u64,
+}
+
+// Listener calling a closure on every rotation, see [crate::WalBuilder::on_rotate]
+pub(crate) struct OnRotate<F>(pub F);
+
+impl<F> EventListener for OnRotate<F>
+where
+    F: Fn(&RotationEvent) + Send + Sync,
+{
+    fn on_rotate(&self, rotation: &RotationEvent) {
+        (self.0)(rotation)
+    }
+}
+
+// Listeners registered with the builder, shared by the handles and the writer thread
+#[derive(Clone, Default)]
+pub(crate) struct Listeners(Arc<[Arc<dyn EventListener>]>);
+
+impl Listeners {
+    pub fn new(listeners: Vec<Arc<dyn EventListener>>) -> Self {
+        Self(listeners.into())
+    }
+
+    pub fn flush(&self, flush: &FlushEvent) {
+        self.0.iter().for_each(|l| l.on_flush(flush));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn rotate(&self, rotation: &RotationEvent) {
+        self.0.iter().for_each(|l| l.on_rotate(rotation));
+    }
+
+    pub fn error(&self, failure: &WriteFailure) {
+        self.0.iter().for_each(|l| l.on_error(failure));
+    }
+
+    pub fn recovery(&self, recovery: &Recovery) {
+        self.0.iter().for_each(|l| l.on_recovery(recovery));
+    }
+}
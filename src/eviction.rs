@@ -0,0 +1,120 @@
+use crate::Lsn;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Logs dropped from a WAL file that is reused to stay within the capacity
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Eviction {
+    /// Sequence number of the WAL file, i.e. `N` in `wal_N`
+    pub id: u8,
+    /// Sequence numbers of the first and the last evicted log, if the file held any
+    pub lsns: Option<RangeInclusive<Lsn>>,
+    /// Size of the evicted file in bytes
+    pub bytes: u64,
+    /// When the file was created, if supported by the file system
+    pub first_written: Option<SystemTime>,
+    /// When the file was last written to
+    pub last_written: Option<SystemTime>,
+}
+
+// Callback notified of every eviction
+pub(crate) type OnEvict = Arc<dyn Fn(&Eviction) + Send + Sync>;
+
+/// Warning fired by the writer before logs are dropped to stay within the capacity, see
+/// [crate::WalBuilder::capacity_warnings]
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum CapacityWarning {
+    /// Logs stored in the WAL files crossed `threshold` of the capacity
+    Usage {
+        /// Share of the capacity crossed, as given to the builder
+        threshold: f64,
+        /// Bytes stored in the WAL files
+        used: u64,
+        /// Bytes the WAL files hold before the oldest one is reused
+        capacity: u64,
+    },
+    /// The file reused after the current one is filled holds logs that a consumer reading with
+    /// [crate::Wal::entries_since] hasn't read yet
+    UnconsumedEviction {
+        /// Sequence number of the WAL file, i.e. `N` in `wal_N`
+        id: u8,
+        /// Sequence numbers of the first and the last log of the file
+        lsns: RangeInclusive<Lsn>,
+        /// Sequence number of the next log to be read by the consumer furthest behind
+        consumer: Lsn,
+    },
+}
+
+// Callback notified of every capacity warning
+pub(crate) type OnCapacityWarning = Arc<dyn Fn(&CapacityWarning) + Send + Sync>;
+
+// Usage thresholds crossed by the WAL files, reported once until usage falls below them again
+pub(crate) struct CapacityMonitor {
+    // shares of the capacity, in increasing order
+    thresholds: Vec<f64>,
+    // number of thresholds crossed at the last check
+    crossed: usize,
+    notify: OnCapacityWarning,
+}
+
+impl CapacityMonitor {
+    pub fn new(mut thresholds: Vec<f64>, notify: OnCapacityWarning) -> Self {
+        thresholds.retain(|t| t.is_finite());
+        thresholds.sort_by(f64::total_cmp);
+        Self {
+            thresholds,
+            crossed: 0,
+            notify,
+        }
+    }
+
+    // Report the thresholds crossed since the last check, when `used` bytes are stored
+    pub fn usage(&mut self, used: u64, capacity: u64) {
+        let crossed = self
+            .thresholds
+            .iter()
+            .take_while(|t| used as f64 >= **t * capacity as f64)
+            .count();
+        for threshold in self.thresholds.iter().take(crossed).skip(self.crossed) {
+            (self.notify)(&CapacityWarning::Usage {
+                threshold: *threshold,
+                used,
+                capacity,
+            });
+        }
+        self.crossed = crossed;
+    }
+
+    pub fn unconsumed(&self, id: u8, lsns: RangeInclusive<Lsn>, consumer: Lsn) {
+        (self.notify)(&CapacityWarning::UnconsumedEviction { id, lsns, consumer });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn thresholds() {
+        let fired = Arc::new(Mutex::new(Vec::new()));
+        let log = fired.clone();
+        let notify: OnCapacityWarning = Arc::new(move |w| {
+            if let CapacityWarning::Usage { threshold, .. } = w {
+                log.lock().unwrap().push(*threshold);
+            }
+        });
+        let mut monitor = CapacityMonitor::new(vec![0.95, 0.8], notify);
+        monitor.usage(50, 100);
+        monitor.usage(96, 100);
+        // reported once while usage stays above
+        monitor.usage(97, 100);
+        // and again once usage fell below
+        monitor.usage(85, 100);
+        monitor.usage(99, 100);
+        assert_eq!(*fired.lock().unwrap(), vec![0.8, 0.95, 0.95]);
+    }
+}
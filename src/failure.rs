@@ -0,0 +1,133 @@
+use crate::event::Listeners;
+use crate::Lsn;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Failure of the writer thread to store logs or metadata, such as a full disk or a revoked
+/// permission
+///
+/// Reported by [crate::Wal::last_error] and to [crate::WalBuilder::on_error]. Logs are accepted
+/// before the writer thread stores them, so these failures can't be returned by the writes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct WriteFailure {
+    /// What the writer thread was doing
+    pub operation: WriteOperation,
+    /// Kind of the I/O error, if the failure came from one
+    pub kind: Option<std::io::ErrorKind>,
+    /// Description of the failure
+    pub message: String,
+    /// Sequence numbers of the first and the last log of a batch that couldn't be written
+    pub lost: Option<RangeInclusive<Lsn>>,
+    /// When the failure happened
+    pub at: SystemTime,
+}
+
+/// Step of the writer thread that failed, see [WriteFailure]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum WriteOperation {
+    /// Writing a batch of logs to the current file, whose logs are lost
+    Write,
+    /// Syncing written logs to storage
+    Sync,
+    /// Moving on to the next file, the writer goes on with the current one
+    Rotate,
+    /// Recording the current file and the digests of sealed files in meta
+    Meta,
+    /// Moving a sealed file to the cold location, the file stays where it is
+    Move,
+    /// Archiving a file before its logs are dropped, the logs are kept
+    Archive,
+    /// Running the writer, which panicked and was restarted if [crate::WalBuilder::restart_writer]
+    /// allows it, dropping the batch it was writing
+    Panic,
+}
+
+// Callback notified of every failure
+pub(crate) type OnError = Arc<dyn Fn(&WriteFailure) + Send + Sync>;
+
+// Last failure of the writer thread, shared with the handles, and the callback and listeners
+// notified of every failure
+#[derive(Clone, Default)]
+pub(crate) struct Failures {
+    last: Arc<Mutex<Option<WriteFailure>>>,
+    notify: Option<OnError>,
+    listeners: Listeners,
+}
+
+impl Failures {
+    pub fn new(notify: Option<OnError>, listeners: Listeners) -> Self {
+        Self {
+            last: Arc::default(),
+            notify,
+            listeners,
+        }
+    }
+
+    pub fn io(
+        &self,
+        operation: WriteOperation,
+        error: &std::io::Error,
+        lost: Option<RangeInclusive<Lsn>>,
+    ) {
+        self.report(WriteFailure {
+            operation,
+            kind: Some(error.kind()),
+            message: error.to_string(),
+            lost,
+            at: SystemTime::now(),
+        });
+    }
+
+    pub fn other(&self, operation: WriteOperation, message: String) {
+        self.report(WriteFailure {
+            operation,
+            kind: None,
+            message,
+            lost: None,
+            at: SystemTime::now(),
+        });
+    }
+
+    fn report(&self, failure: WriteFailure) {
+        if let Some(notify) = &self.notify {
+            notify(&failure);
+        }
+        self.listeners.error(&failure);
+        *self.last.lock().unwrap_or_else(|e| e.into_inner()) = Some(failure);
+    }
+
+    pub fn last(&self) -> Option<WriteFailure> {
+        self.last.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report() {
+        let reported = Arc::new(Mutex::new(0));
+        let failures = Failures::new(
+            Some(Arc::new({
+                let reported = reported.clone();
+                move |_: &WriteFailure| *reported.lock().unwrap() += 1
+            })),
+            Listeners::default(),
+        );
+        assert_eq!(failures.last(), None);
+        let error = std::io::Error::from(std::io::ErrorKind::StorageFull);
+        failures.io(WriteOperation::Write, &error, Some(3..=7));
+        failures.other(
+            WriteOperation::Meta,
+            "Failed to create pointer file".to_string(),
+        );
+        // the last failure is kept, and every one is notified
+        let last = failures.clone().last().unwrap();
+        assert_eq!((last.operation, last.kind), (WriteOperation::Meta, None));
+        assert_eq!(*reported.lock().unwrap(), 2);
+    }
+}
@@ -0,0 +1,296 @@
+// Crash tests of the writer thread, injecting faults in the files it writes logs to
+//
+// The writer writes frames and syncs through [SegmentFile], which crash tests wrap in a
+// [FaultyFile]. Killing the writer in the middle of a write leaves the frames written so far and
+// part of the frame being written, and copies the WAL directory as it stands at that instant,
+// i.e. the files a restarted process finds. Files of a closed WAL are damaged with
+// [truncate_file] and [flip_bit]. Properties are checked over many runs drawn from a seeded
+// generator, so a failing run can be replayed from its seed.
+
+use crate::flush::SegmentFile;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+// Faults shared by a test and the writer thread of its WAL
+#[derive(Clone, Default)]
+pub(crate) struct Faults {
+    inner: Arc<Mutex<FaultState>>,
+}
+
+#[derive(Default)]
+struct FaultState {
+    // bytes written before the writer is killed, if it is to be killed
+    kill_after: Option<u64>,
+    // directory of the WAL, copied to `image` when the writer is killed
+    dir: PathBuf,
+    image: PathBuf,
+    killed: bool,
+}
+
+impl Faults {
+    // Kill the writer of the WAL at `dir` once `bytes` of frames are written, copying the WAL to
+    // `image` at that instant
+    pub fn kill_after(bytes: u64, dir: &str, image: &str) -> Self {
+        let state = FaultState {
+            kill_after: Some(bytes),
+            dir: dir.into(),
+            image: image.into(),
+            killed: false,
+        };
+        Self {
+            inner: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    // whether the writer was killed
+    pub fn killed(&self) -> bool {
+        self.inner.lock().unwrap().killed
+    }
+
+    // the file written by the writer, going through the faults
+    pub fn wrap<'a, F: SegmentFile>(&'a self, file: &'a mut F) -> FaultyFile<'a, F> {
+        FaultyFile { file, faults: self }
+    }
+
+    // Bytes of `data` to write before the writer is killed, killing it when they fall short
+    fn admit(&self, data: &[u8]) -> io::Result<usize> {
+        let mut state = self.inner.lock().unwrap();
+        if state.killed {
+            return Err(io::Error::other("writer killed by a crash test"));
+        }
+        let budget = match state.kill_after.as_mut() {
+            Some(budget) => budget,
+            None => return Ok(data.len()),
+        };
+        let admitted = (*budget).min(data.len() as u64) as usize;
+        *budget -= admitted as u64;
+        Ok(admitted)
+    }
+
+    // Kill the writer once the last bytes it could write are written
+    fn kill(&self) -> io::Error {
+        let mut state = self.inner.lock().unwrap();
+        state.killed = true;
+        copy_dir(&state.dir, &state.image).expect("failed to copy the WAL of a killed writer");
+        io::Error::other("writer killed by a crash test")
+    }
+}
+
+// File going through the faults of a crash test
+pub(crate) struct FaultyFile<'a, F> {
+    file: &'a mut F,
+    faults: &'a Faults,
+}
+
+impl<F: SegmentFile> SegmentFile for FaultyFile<'_, F> {
+    fn append(&mut self, data: &[u8]) -> io::Result<()> {
+        let admitted = self.faults.admit(data)?;
+        self.file.append(&data[..admitted])?;
+        match admitted == data.len() {
+            true => Ok(()),
+            false => Err(self.faults.kill()),
+        }
+    }
+
+    fn write_at(&mut self, data: &[u8], offset: u64) -> io::Result<()> {
+        let admitted = self.faults.admit(data)?;
+        self.file.write_at(&data[..admitted], offset)?;
+        match admitted == data.len() {
+            true => Ok(()),
+            false => Err(self.faults.kill()),
+        }
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.faults.admit(&[])?;
+        self.file.sync()
+    }
+}
+
+// copy the files of the directory `from` to a new directory `to`
+fn copy_dir(from: &Path, to: &Path) -> io::Result<()> {
+    let _ = std::fs::remove_dir_all(to);
+    std::fs::create_dir_all(to)?;
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() {
+            std::fs::copy(entry.path(), to.join(entry.file_name()))?;
+        }
+    }
+    Ok(())
+}
+
+// cut the file at `path` to `len` bytes, as a crash before its end reached storage would
+pub(crate) fn truncate_file(path: &Path, len: u64) {
+    let file = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+    file.set_len(len).unwrap();
+}
+
+// flip the bit `bit` of the file at `path`, as a failing disk would
+pub(crate) fn flip_bit(path: &Path, bit: u64) {
+    let mut content = std::fs::read(path).unwrap();
+    content[(bit / 8) as usize] ^= 1 << (bit % 8);
+    std::fs::write(path, content).unwrap();
+}
+
+// Seeded generator of the runs of property tests
+pub(crate) struct Runs {
+    state: u64,
+}
+
+impl Runs {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    // uniform number below `n`, a splitmix64 sequence
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        (z ^ (z >> 31)) % n.max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Wal, WalBuilder};
+
+    // runs of every property
+    const RUNS: u64 = 20;
+
+    // storage of the WAL, spread over files of about 400 bytes
+    const CAPACITY: usize = 4_000;
+
+    fn clear(dir: &str) {
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+    }
+
+    // WAL files of the WAL at `dir`, oldest first, along with their number of logs
+    fn segments(dir: &str) -> Vec<(PathBuf, u64)> {
+        let wal = Wal::<u64>::new(dir, CAPACITY).unwrap();
+        let report = wal.verify().unwrap();
+        report
+            .segments
+            .iter()
+            .map(|s| (Path::new(dir).join(format!("wal_{}", s.id)), s.entries))
+            .collect()
+    }
+
+    // write `count` logs numbered from 0, returning once they are durable
+    fn fill(dir: &str, count: u64) {
+        clear(dir);
+        let wal = Wal::new(dir, CAPACITY).unwrap();
+        for i in 0..count {
+            wal.try_write(&i).unwrap();
+        }
+        wal.flush().unwrap();
+    }
+
+    #[test]
+    fn acked_logs_survive_kill() {
+        for seed in 0..RUNS {
+            let dir = format!("./tmp/faults_kill_{}/", seed);
+            let image = format!("./tmp/faults_kill_{}_image/", seed);
+            clear(&dir);
+            let _ = std::fs::remove_dir_all(&image);
+            let mut runs = Runs::new(seed);
+            let faults = Faults::kill_after(runs.below(2_000), &dir, &image);
+            let mut builder = WalBuilder::new(&dir, CAPACITY);
+            builder.faults = Some(faults.clone());
+            let wal = builder.build().unwrap();
+
+            // logs numbered in the order they are written, and the number of them flushed
+            let (mut written, mut acked) = (0u64, 0u64);
+            for _ in 0..100 {
+                match runs.below(4) {
+                    0 | 1 => {
+                        if wal.try_write(&written).is_ok() {
+                            written += 1;
+                        }
+                    }
+                    2 => {
+                        let batch = (written..written + 1 + runs.below(8)).collect::<Vec<_>>();
+                        if let Ok(lsns) = wal.try_write_all(&batch) {
+                            written += lsns.end - lsns.start;
+                        }
+                    }
+                    _ => {
+                        if wal.flush().is_ok() {
+                            acked = written;
+                        }
+                    }
+                }
+            }
+            drop(wal);
+
+            // a writer never killed leaves its WAL as is
+            let restarted = match faults.killed() {
+                true => &image,
+                false => &dir,
+            };
+            let logs = Wal::<u64>::new(restarted, CAPACITY)
+                .unwrap()
+                .read()
+                .unwrap();
+            assert!(logs.len() as u64 >= acked, "seed {}", seed);
+            assert_eq!(logs, (0..logs.len() as u64).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    fn truncated_segment() {
+        for seed in 0..RUNS {
+            let dir = format!("./tmp/faults_truncate_{}/", seed);
+            fill(&dir, 200);
+            let files = segments(&dir);
+            let mut runs = Runs::new(seed);
+            let cut = runs.below(files.len() as u64) as usize;
+            let len = std::fs::metadata(&files[cut].0).unwrap().len();
+            truncate_file(&files[cut].0, runs.below(len));
+
+            // the logs of the other files are intact, and those of the file before the cut
+            let before = files[..cut].iter().map(|(_, logs)| logs).sum::<u64>();
+            let after = files[cut + 1..].iter().map(|(_, logs)| logs).sum::<u64>();
+            let logs = Wal::<u64>::new(&dir, CAPACITY).unwrap().read().unwrap();
+            assert!(
+                logs.starts_with(&(0..before).collect::<Vec<_>>()),
+                "seed {}",
+                seed
+            );
+            assert!(
+                logs.ends_with(&(200 - after..200).collect::<Vec<_>>()),
+                "seed {}",
+                seed
+            );
+            assert!(logs.windows(2).all(|w| w[0] < w[1]), "seed {}", seed);
+        }
+    }
+
+    #[test]
+    fn flipped_bit() {
+        for seed in 0..RUNS {
+            let dir = format!("./tmp/faults_flip_{}/", seed);
+            fill(&dir, 200);
+            let files = segments(&dir);
+            let mut runs = Runs::new(seed);
+            let flipped = runs.below(files.len() as u64) as usize;
+            let len = std::fs::metadata(&files[flipped].0).unwrap().len();
+            flip_bit(&files[flipped].0, runs.below(len * 8));
+
+            // a damaged file may not open, but never damages the logs of the files before it
+            let before = files[..flipped].iter().map(|(_, logs)| logs).sum::<u64>();
+            if let Ok(logs) = Wal::<u64>::new(&dir, CAPACITY).and_then(|wal| wal.read()) {
+                assert!(
+                    logs.starts_with(&(0..before).collect::<Vec<_>>()),
+                    "seed {}",
+                    seed
+                );
+            }
+        }
+    }
+}
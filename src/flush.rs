@@ -0,0 +1,381 @@
+use crate::builder::{SyncPolicy, WakeStrategy};
+use crate::compression::Compression;
+use crate::encryption::{Cipher, Nonce};
+use crate::entry::LogEntry;
+use crate::format::{self, FormatVersion, Framing};
+use std::fs::File;
+use std::io::{self, Write};
+use std::time::Duration;
+
+// Bounds of the size of a file meant to cover `target` worth of logs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SegmentWindow {
+    pub target: Duration,
+    pub min: usize,
+    pub max: usize,
+}
+
+// File the writer thread writes frames to
+// Implemented for the WAL files, and by in-memory files to test the writer's logic
+pub(crate) trait SegmentFile {
+    // write `data` at the end of the file
+    fn append(&mut self, data: &[u8]) -> io::Result<()>;
+    // write `data` at `offset`, regardless of the end of the file
+    fn write_at(&mut self, data: &[u8], offset: u64) -> io::Result<()>;
+    // flush written data to storage
+    fn sync(&mut self) -> io::Result<()>;
+}
+
+impl SegmentFile for File {
+    fn append(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write_all(data)
+    }
+
+    #[cfg(unix)]
+    fn write_at(&mut self, data: &[u8], offset: u64) -> io::Result<()> {
+        use std::os::unix::fs::FileExt;
+        self.write_all_at(data, offset)
+    }
+
+    #[cfg(not(unix))]
+    fn write_at(&mut self, data: &[u8], offset: u64) -> io::Result<()> {
+        use std::io::{Seek, SeekFrom};
+        self.seek(SeekFrom::Start(offset))?;
+        self.write_all(data)
+    }
+
+    // the size of the file is synced along with its data, which is all reads need
+    fn sync(&mut self) -> io::Result<()> {
+        self.sync_data()
+    }
+}
+
+// How frames are written to the current file, from its header
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct SegmentEncoding {
+    // nonce of the file, when its payloads are encrypted
+    pub nonce: Option<Nonce>,
+    // every frame carries a checksum
+    pub checksummed: bool,
+}
+
+// Decisions taken by the writer thread for every batch of logs, free of threads and files
+pub(crate) struct FlushPolicy {
+    // how to wake up after a notification
+    pub wake_strategy: WakeStrategy,
+    // compression of large logs, if enabled
+    pub compression: Option<Compression>,
+    // key sealing the payloads of new files, if they are encrypted
+    pub cipher: Option<Cipher>,
+    // highest ratio of decompressed to compressed size of the logs read back
+    pub max_expansion: usize,
+    // payload size of fixed size records
+    pub record_size: Option<usize>,
+    // encoding of frames, unless they are fixed size records
+    pub framing: Framing,
+    // native frames carry a checksum
+    pub checksums: bool,
+    // write at the tracked offset instead of appending to the current file
+    pub positional_writes: bool,
+    // storage capacity per file
+    pub capacity_per_file: usize,
+    // when written batches are synced to storage
+    pub sync: SyncPolicy,
+    // file size adapted to the rate of logs, if set
+    pub window: Option<SegmentWindow>,
+}
+
+impl FlushPolicy {
+    // time to wait after a notification before draining the buffer
+    pub fn delay(&self) -> Option<Duration> {
+        match self.wake_strategy {
+            WakeStrategy::Eager => None,
+            WakeStrategy::MicroBatch(delay) => Some(delay),
+            WakeStrategy::GroupCommit { .. } => None,
+        }
+    }
+
+    // longest wait for a group of logs and the payload that completes it, in group commit mode
+    pub fn group(&self) -> Option<(Duration, usize)> {
+        match self.wake_strategy {
+            WakeStrategy::GroupCommit { within, bytes } => Some((within, bytes)),
+            _ => None,
+        }
+    }
+
+    // whether written logs are ever synced by the writer thread
+    pub fn syncs(&self) -> bool {
+        self.sync != SyncPolicy::Never || self.group().is_some()
+    }
+
+    // Frames of a batch of logs, as written to the current file, encoded in `out`
+    // The frames fill a single buffer sized for the whole batch, written with a single call.
+    // Payloads are sealed under the nonce of the current file when it is encrypted, and frames
+    // carry a checksum when its frames do. The logs are taken out of `data`, and the buffers of
+    // their payloads are returned for the next logs.
+    pub fn encode(
+        &self,
+        data: &mut Vec<LogEntry>,
+        file: SegmentEncoding,
+        out: &mut Vec<u8>,
+    ) -> Vec<Vec<u8>> {
+        out.clear();
+        let payload = data.iter().map(|d| d.size()).sum::<usize>();
+        let logs = data.drain(..);
+        match (self.record_size, self.framing) {
+            (Some(size), _) => {
+                out.reserve(logs.len() * format::fixed_frame_size(size));
+                logs.map(|d| d.encode_fixed_frame(size, out)).collect()
+            }
+            (None, Framing::LengthDelimited) => {
+                out.reserve(payload + logs.len());
+                logs.map(|d| d.encode_delimited_frame(out)).collect()
+            }
+            (None, Framing::Native) => {
+                out.reserve(payload + logs.len() * format::frame_overhead_bytes());
+                let cipher = self.cipher.as_ref().zip(file.nonce).map(|(c, n)| c.file(n));
+                logs.map(|d| d.encode_frame(self.compression, cipher, file.checksummed, out))
+                    .collect()
+            }
+        }
+    }
+
+    // Start a new file with the header of the current format, returning the bytes written and
+    // how frames are written to the file
+    // Length-delimited files hold nothing but frames. Fixed size records carry no checksum.
+    pub fn start_file<F: SegmentFile>(&self, file: &mut F) -> io::Result<(u64, SegmentEncoding)> {
+        if self.record_size.is_none() && self.framing == Framing::LengthDelimited {
+            return Ok((0, SegmentEncoding::default()));
+        }
+        let encoding = SegmentEncoding {
+            nonce: self.cipher.as_ref().map(|_| Cipher::nonce()),
+            checksummed: self.checksums && self.record_size.is_none(),
+        };
+        let header =
+            FormatVersion::CURRENT.file_header(encoding.nonce.as_ref(), encoding.checksummed);
+        file.write_at(&header, 0)?;
+        Ok((header.len() as u64, encoding))
+    }
+
+    // write frames to the current file at `offset`
+    // The caller syncs the file once [FlushPolicy::sync_due] says so
+    pub fn write<F: SegmentFile>(
+        &self,
+        file: &mut F,
+        frames: &[u8],
+        offset: u64,
+    ) -> io::Result<()> {
+        match self.positional_writes {
+            true => file.write_at(frames, offset),
+            false => file.append(frames),
+        }
+    }
+
+    // the file holds `unsynced` bytes written since it was last synced `since` ago
+    pub fn sync_due(&self, unsynced: usize, since: Duration) -> bool {
+        if unsynced == 0 {
+            return false;
+        }
+        // every group is synced once written
+        if self.group().is_some() {
+            return true;
+        }
+        match self.sync {
+            SyncPolicy::Never => false,
+            SyncPolicy::EveryWrite => true,
+            SyncPolicy::EveryNBytes(bytes) => unsynced >= bytes,
+            SyncPolicy::Interval(interval) => since >= interval,
+        }
+    }
+
+    // a batch of `logs` logs with `bytes` of payload fills more than a whole file
+    // Frames are counted along with payloads, so logs with empty payloads fill files too
+    pub fn oversized(&self, logs: usize, bytes: usize) -> bool {
+        let frames = match (self.record_size, self.framing) {
+            (Some(size), _) => logs.saturating_mul(format::fixed_frame_size(size)),
+            (None, Framing::LengthDelimited) => bytes.saturating_add(logs),
+            (None, Framing::Native) => {
+                bytes.saturating_add(logs.saturating_mul(format::frame_overhead_bytes()))
+            }
+        };
+        frames >= self.capacity_per_file
+    }
+
+    // the current file holds `filled` bytes and the writer moves on to the next one
+    pub fn rotate(&self, filled: usize) -> bool {
+        filled >= self.capacity_per_file
+    }
+
+    // Size the next file after the rate of the file that took `elapsed` to fill `filled` bytes
+    // The next file covers about the target window at the same rate
+    pub fn adapt(&mut self, filled: usize, elapsed: Duration) {
+        let window = match self.window {
+            Some(w) => w,
+            None => return,
+        };
+        let size = match elapsed.as_nanos() {
+            0 => window.max,
+            nanos => {
+                let size = filled as u128 * window.target.as_nanos() / nanos;
+                size.min(window.max as u128) as usize
+            }
+        };
+        self.capacity_per_file = size.clamp(window.min, window.max);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compression::MAX_EXPANSION;
+
+    // file kept in memory, failing every write when `broken`
+    #[derive(Default)]
+    struct MemoryFile {
+        content: Vec<u8>,
+        broken: bool,
+    }
+
+    impl SegmentFile for MemoryFile {
+        fn append(&mut self, data: &[u8]) -> io::Result<()> {
+            let offset = self.content.len() as u64;
+            self.write_at(data, offset)
+        }
+
+        fn write_at(&mut self, data: &[u8], offset: u64) -> io::Result<()> {
+            if self.broken {
+                return Err(io::Error::other("broken file"));
+            }
+            let offset = offset as usize;
+            self.content
+                .resize(self.content.len().max(offset + data.len()), 0);
+            self.content[offset..offset + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+
+        fn sync(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn policy() -> FlushPolicy {
+        FlushPolicy {
+            wake_strategy: WakeStrategy::Eager,
+            compression: None,
+            cipher: None,
+            max_expansion: MAX_EXPANSION,
+            record_size: None,
+            framing: Framing::Native,
+            checksums: false,
+            positional_writes: false,
+            capacity_per_file: 100,
+            sync: SyncPolicy::Never,
+            window: None,
+        }
+    }
+
+    #[test]
+    fn flush() {
+        let mut policy = policy();
+        assert_eq!(policy.delay(), None);
+        let logs = || {
+            vec![
+                LogEntry::from_vec(vec![1, 2], 1),
+                LogEntry::from_vec(vec![3], 2),
+            ]
+        };
+        let mut frames = Vec::new();
+        let payloads = policy.encode(&mut logs(), SegmentEncoding::default(), &mut frames);
+        assert_eq!(payloads, vec![vec![1, 2], vec![3]]);
+        assert_eq!(frames.len(), 2 * (4 + 8) + 3);
+        let mut checksummed = Vec::new();
+        let file = SegmentEncoding {
+            checksummed: true,
+            ..Default::default()
+        };
+        policy.encode(&mut logs(), file, &mut checksummed);
+        assert_eq!(checksummed.len(), frames.len() + 2 * 4);
+
+        let mut file = MemoryFile::default();
+        assert!(policy.write(&mut file, &frames, 0).is_ok());
+        assert!(policy.write(&mut file, &frames, 0).is_ok());
+        assert_eq!(file.content.len(), 2 * frames.len());
+
+        // positional writes overwrite whatever follows the offset
+        policy.positional_writes = true;
+        assert!(policy.write(&mut file, &[9; 4], 2).is_ok());
+        assert_eq!(&file.content[..6], &[frames[0], frames[1], 9, 9, 9, 9]);
+        file.broken = true;
+        assert!(policy.write(&mut file, &frames, 0).is_err());
+
+        // fixed size records and length delimited frames
+        policy.record_size = Some(4);
+        let mut encoded = Vec::new();
+        policy.encode(&mut logs(), SegmentEncoding::default(), &mut encoded);
+        assert_eq!(encoded.len(), 2 * (8 + 4));
+        policy.record_size = None;
+        policy.framing = Framing::LengthDelimited;
+        policy.encode(&mut logs(), SegmentEncoding::default(), &mut encoded);
+        assert_eq!(encoded, vec![2, 1, 2, 1, 3]);
+    }
+
+    #[test]
+    fn sync() {
+        let mut policy = policy();
+        let second = Duration::from_secs(1);
+        assert!(!policy.sync_due(100, second));
+        policy.sync = SyncPolicy::EveryWrite;
+        assert!(policy.sync_due(1, Duration::ZERO));
+        assert!(!policy.sync_due(0, second));
+        policy.sync = SyncPolicy::EveryNBytes(100);
+        assert!(!policy.sync_due(99, second));
+        assert!(policy.sync_due(100, Duration::ZERO));
+        policy.sync = SyncPolicy::Interval(second);
+        assert!(!policy.sync_due(100, second / 2));
+        assert!(policy.sync_due(1, second));
+        assert!(!policy.sync_due(0, second));
+        policy.sync = SyncPolicy::Never;
+        policy.wake_strategy = WakeStrategy::GroupCommit {
+            within: second,
+            bytes: 1024,
+        };
+        assert!(policy.syncs());
+        assert!(policy.sync_due(1, Duration::ZERO));
+        assert_eq!(policy.delay(), None);
+    }
+
+    #[test]
+    fn rotation() {
+        let policy = policy();
+        assert!(!policy.rotate(99));
+        assert!(policy.rotate(100));
+        // frames of logs with empty payloads fill files too
+        assert!(!policy.oversized(8, 0));
+        assert!(policy.oversized(9, 0));
+        assert!(policy.oversized(1, 100));
+    }
+
+    #[test]
+    fn adaptive_size() {
+        let mut policy = policy();
+        policy.adapt(100, Duration::from_secs(1));
+        assert_eq!(policy.capacity_per_file, 100);
+
+        policy.window = Some(SegmentWindow {
+            target: Duration::from_secs(60),
+            min: 1000,
+            max: 100_000,
+        });
+        // 100 bytes per second fill 6000 bytes in a minute
+        policy.adapt(100, Duration::from_secs(1));
+        assert_eq!(policy.capacity_per_file, 6000);
+        assert!(!policy.rotate(5999));
+        // the size stays within bounds
+        policy.adapt(100, Duration::from_secs(3600));
+        assert_eq!(policy.capacity_per_file, 1000);
+        policy.adapt(100_000, Duration::from_millis(1));
+        assert_eq!(policy.capacity_per_file, 100_000);
+        policy.adapt(100, Duration::ZERO);
+        assert_eq!(policy.capacity_per_file, 100_000);
+    }
+}
@@ -0,0 +1,75 @@
+use crate::durable;
+use crate::Lsn;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+
+/// Store of the state folded from the logs of a WAL, see [crate::Wal::fold]
+///
+/// The state is saved along with the sequence number of the next log to fold into it, so the
+/// next fold resumes from there instead of replaying every log.
+pub trait SnapshotStore<S> {
+    /// The last saved state and the sequence number of the next log to fold into it, None
+    /// before the first save
+    fn load(&self) -> io::Result<Option<(S, Lsn)>>;
+
+    /// Save `state`, which covers every log before `next`
+    fn save(&self, state: &S, next: Lsn) -> io::Result<()>;
+}
+
+/// [SnapshotStore] keeping the state in a single file, serialized with bincode
+///
+/// Every save replaces the file at once, so a crash leaves either the old or the new state.
+#[derive(Debug, Clone)]
+pub struct FileSnapshotStore {
+    path: PathBuf,
+}
+
+impl FileSnapshotStore {
+    /// Store the state in the file at `path`, whose directory must exist
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: PathBuf::from(path),
+        }
+    }
+}
+
+impl<S> SnapshotStore<S> for FileSnapshotStore
+where
+    S: Serialize + for<'a> Deserialize<'a>,
+{
+    fn load(&self) -> io::Result<Option<(S, Lsn)>> {
+        let content = match std::fs::read(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let (next, state) = bincode::deserialize::<(Lsn, S)>(&content)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(Some((state, next)))
+    }
+
+    fn save(&self, state: &S, next: Lsn) -> io::Result<()> {
+        let content = bincode::serialize(&(next, state))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        durable::write_atomic(&self.path, &self.path.with_extension("tmp"), &content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_store() {
+        let dir = PathBuf::from("./tmp/fold_file_store/");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let store = FileSnapshotStore::new("./tmp/fold_file_store/state");
+        assert_eq!(SnapshotStore::<Vec<u32>>::load(&store).unwrap(), None);
+        store.save(&vec![1u32, 2], 7).unwrap();
+        store.save(&vec![1u32, 2, 3], 9).unwrap();
+        assert_eq!(store.load().unwrap(), Some((vec![1u32, 2, 3], 9)));
+        assert!(!dir.join("state.tmp").exists());
+    }
+}
@@ -0,0 +1,69 @@
+use crate::WalError;
+
+/// How a handle behaves when used in a child process forked after the WAL was opened
+///
+/// The writer thread and the locks shared with it don't survive a fork, so the child can't write
+/// safely while the parent keeps writing to the same files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum ForkBehavior {
+    /// Every operation of the handle fails with [WalError::ForkDetected]
+    #[default]
+    Fail,
+    /// The handle reads the WAL files as a read-only handle would, without the logs still waiting
+    /// in the buffer of the parent. Writes fail with [WalError::ForkDetected].
+    ReadOnly,
+}
+
+// Process that opened the WAL, compared to the current process by every operation of a handle
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ForkGuard {
+    pub pid: u32,
+    pub behavior: ForkBehavior,
+}
+
+impl ForkGuard {
+    pub fn new(behavior: ForkBehavior) -> Self {
+        Self {
+            pid: std::process::id(),
+            behavior,
+        }
+    }
+
+    // the handle is used in a process forked after the WAL was opened
+    pub fn forked(&self) -> bool {
+        std::process::id() != self.pid
+    }
+
+    // fail if the handle is used after a fork in a way not allowed by the behavior
+    pub fn check(&self, write: bool) -> Result<(), WalError> {
+        if !self.forked() || (self.behavior == ForkBehavior::ReadOnly && !write) {
+            return Ok(());
+        }
+        Err(WalError::ForkDetected(format!(
+            "WAL was opened by process {}, open it again in process {}",
+            self.pid,
+            std::process::id()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detection() {
+        let mut guard = ForkGuard::new(ForkBehavior::Fail);
+        assert!(!guard.forked());
+        assert!(guard.check(true).is_ok());
+
+        // as seen from a child process
+        guard.pid = guard.pid.wrapping_add(1);
+        assert!(guard.forked());
+        assert!(matches!(guard.check(false), Err(WalError::ForkDetected(_))));
+        guard.behavior = ForkBehavior::ReadOnly;
+        assert!(guard.check(false).is_ok());
+        assert!(matches!(guard.check(true), Err(WalError::ForkDetected(_))));
+    }
+}
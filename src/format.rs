@@ -0,0 +1,600 @@
+//! Description of the on-disk format of WAL files
+//!
+//! Every WAL file starts with a header of [SEGMENT_HEADER_BYTES]: the [SEGMENT_MAGIC] bytes, the
+//! [FORMAT_VERSION] of the frames that follow, one byte marking the byte order of the numbers in
+//! frames (1 for little endian, 2 for big endian), one byte of flags and a reserved zero byte. The
+//! meta file records the same version and byte order on a `format` line. Files with
+//! [Framing::LengthDelimited] have no header.
+//!
+//! The lowest bit of the flags is set when the payloads of the file are encrypted, in which case
+//! the header is followed by the [SEGMENT_NONCE_BYTES] of nonce of the file, and every payload is
+//! sealed with ChaCha20-Poly1305 under that nonce mixed with the sequence number of its log,
+//! which adds [SEAL_BYTES] to it. Payloads are compressed before they are sealed.
+//!
+//! The second bit of the flags is set once the oldest logs of a sealed file were reclaimed by
+//! [crate::Wal::truncate], in which case the header, and the nonce of an encrypted file, is
+//! followed by [SEGMENT_HEAD_BYTES] holding the offset of the first remaining frame as a little
+//! endian integer. The bytes in between are released to file systems supporting hole punching,
+//! and read as zeros.
+//!
+//! The third bit of the flags is set when every frame of the file carries a checksum, see
+//! [crate::WalBuilder::checksums]. Frames of files without a header carry one as set when reading
+//! them.
+//!
+//! Since version 2, every number in frames is little endian, whatever the machine writing them,
+//! so WAL directories can be copied between machines. Files of version 1 hold numbers in the
+//! byte order marked in their header, and files written before headers were introduced have
+//! none and are read as version 0, in the byte order of the machine reading them. Both are still
+//! read, and a current file of an older format is rewritten in little endian before logs are
+//! appended to it. A WAL written with a newer version fails to open instead of being misread.
+//!
+//! Every log is stored as a frame made of a length prefix, the sequence number ([crate::Lsn]) of
+//! the log and the serialized payload. Payloads may be empty, as for `()` or unit structs: their
+//! frame is made of the length prefix of 0 and the sequence number alone, and counts towards the
+//! capacity of its file like any other frame. The sequence number was added to frames after the
+//! first release, whose frames were made of the length prefix and the payload alone, and aren't
+//! read by later versions. In files with checksums, the length prefix is followed by
+//! [CHECKSUM_BYTES] of little endian CRC-32 of the frame, covering the length prefix and every
+//! byte after the checksum.
+//! The highest bit of the length prefix is set when the payload is compressed, in which case the
+//! payload starts with one byte identifying its codec and the decompressed size as a 4 bytes
+//! little endian integer. The next bit is set when the frame records the producer of the log, in
+//! which case [PRODUCER_BYTES] of producer id follow the sequence number. The third bit is set on
+//! every log of an atomic batch but the last one, so a batch cut short by a crash is recognised
+//! and skipped when reading. The fourth bit is set when the payload was moved to the blob store of
+//! the WAL, in which case the frame holds [BLOB_REFERENCE_BYTES] of reference to the blob instead.
+//! The fifth bit is set when the frame records when the log was written, in which case
+//! [TIMESTAMP_BYTES] of milliseconds since the unix epoch follow the producer id.
+//! The sixth bit is set when the frame also records the wall clock when the log was written,
+//! apart from its timestamp kept in order, in which case [WALL_CLOCK_BYTES] of milliseconds since
+//! the unix epoch follow the timestamp, then [MONOTONIC_BYTES] of nanoseconds since the unix epoch
+//! read from the monotonic clock, strictly increasing in the order of sequence numbers.
+//! The seventh bit is set when the log was written with a tag, in which case one byte holding the
+//! size of the tag and the tag itself follow the timestamps, so logs are filtered by tag without
+//! deserializing their payload.
+//! The remaining bits hold the payload size, which limits the payload of a single log to
+//! [MAX_PAYLOAD_BYTES].
+//!
+//! When logs are declared to be fixed size records, frames have no length prefix. Every frame is
+//! the sequence number followed by the payload padded to the record size, so the position of a
+//! log in a file is computed arithmetically.
+//!
+//! With [Framing::LengthDelimited], every frame is the payload prefixed with its size encoded as
+//! a base 128 varint, the framing of length-delimited protobuf streams. Such files hold nothing
+//! else, so they can be read by any tool supporting that framing.
+//!
+//! The helpers in this module can be used to estimate how much storage a set of logs will use.
+
+use crate::checksum::Crc32;
+use crate::encryption::Nonce;
+use crate::layout::Layout;
+use crate::storage::SharedStorage;
+use crate::WalError;
+use std::path::Path;
+
+/// Encoding of the frames in WAL files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum Framing {
+    /// Length prefix and sequence number ahead of every payload
+    #[default]
+    Native,
+    /// Payloads prefixed with their size as a varint, compatible with length-delimited protobuf
+    /// streams
+    ///
+    /// Sequence numbers are not stored, so every log read back has a sequence number of 0, and
+    /// logs are neither compressed nor attributed to a producer.
+    LengthDelimited,
+}
+
+/// Bytes at the start of the header of every WAL file
+pub const SEGMENT_MAGIC: [u8; 4] = [0x89, b'W', b'A', b'L'];
+
+/// Version of the format of frames written to WAL files
+pub const FORMAT_VERSION: u8 = 2;
+
+/// Number of bytes of the header at the start of every WAL file
+pub const SEGMENT_HEADER_BYTES: usize = 8;
+
+/// Bit of the flags of the header marking a file whose payloads are encrypted
+pub const SEGMENT_ENCRYPTED: u8 = 1;
+
+/// Bit of the flags of the header marking a file whose oldest logs were reclaimed
+pub const SEGMENT_RECLAIMED: u8 = 2;
+
+/// Bit of the flags of the header marking a file whose frames carry a checksum
+pub const SEGMENT_CHECKSUMMED: u8 = 4;
+
+/// Number of bytes holding the offset of the first frame of a file whose oldest logs were
+/// reclaimed
+pub const SEGMENT_HEAD_BYTES: usize = 8;
+
+/// Number of bytes of the nonce following the header of files with [SEGMENT_ENCRYPTED] set
+pub const SEGMENT_NONCE_BYTES: usize = 12;
+
+/// Number of bytes added to every payload of a file with [SEGMENT_ENCRYPTED] set
+pub const SEAL_BYTES: usize = 16;
+
+/// Number of bytes used by the length prefix of every frame
+pub const LENGTH_PREFIX_BYTES: usize = 4;
+
+/// Number of bytes used by the sequence number of every frame
+pub const LSN_BYTES: usize = 8;
+
+/// Number of bytes used by the checksum of every frame of a file with [SEGMENT_CHECKSUMMED] set
+pub const CHECKSUM_BYTES: usize = 4;
+
+/// Bit of the length prefix marking a compressed payload
+pub const COMPRESSED_FLAG: u32 = 1 << 31;
+
+/// Bit of the length prefix marking a frame that records the producer of the log
+pub const PRODUCER_FLAG: u32 = 1 << 30;
+
+/// Number of bytes used by the producer id of frames with [PRODUCER_FLAG] set
+pub const PRODUCER_BYTES: usize = 2;
+
+/// Bit of the length prefix marking a log followed by more logs of the same atomic batch
+pub const BATCH_FLAG: u32 = 1 << 29;
+
+/// Bit of the length prefix marking a frame that holds a reference to a blob instead of a payload
+pub const BLOB_FLAG: u32 = 1 << 28;
+
+/// Number of bytes of the reference held by frames with [BLOB_FLAG] set
+pub const BLOB_REFERENCE_BYTES: usize = 16;
+
+/// Bit of the length prefix marking a frame that records when the log was written
+pub const TIMESTAMP_FLAG: u32 = 1 << 27;
+
+/// Number of bytes used by the timestamp of frames with [TIMESTAMP_FLAG] set
+pub const TIMESTAMP_BYTES: usize = 8;
+
+/// Bit of the length prefix marking a frame that records the wall clock next to its timestamp
+pub const WALL_CLOCK_FLAG: u32 = 1 << 26;
+
+/// Number of bytes used by the wall clock of frames with [WALL_CLOCK_FLAG] set
+pub const WALL_CLOCK_BYTES: usize = 8;
+
+/// Number of bytes used by the monotonic time following the wall clock of frames with
+/// [WALL_CLOCK_FLAG] set
+pub const MONOTONIC_BYTES: usize = 8;
+
+/// Bit of the length prefix marking a frame that records the tag of the log
+pub const TAG_FLAG: u32 = 1 << 25;
+
+/// Largest tag of a single log, whose size is stored in one byte
+pub const MAX_TAG_BYTES: usize = u8::MAX as usize;
+
+/// Bits of the length prefix holding the payload size
+pub const LENGTH_MASK: u32 = TAG_FLAG - 1;
+
+/// Largest serialized payload of a single log
+pub const MAX_PAYLOAD_BYTES: usize = LENGTH_MASK as usize;
+
+/// Number of bytes added on top of the serialized payload of every log, without a producer id
+pub const fn frame_overhead_bytes() -> usize {
+    LENGTH_PREFIX_BYTES + LSN_BYTES
+}
+
+/// Size on disk of a single log with a serialized payload of `payload` bytes
+pub const fn frame_size(payload: usize) -> usize {
+    payload + frame_overhead_bytes()
+}
+
+/// Size on disk of a single log stored as a fixed size record of `record_size` bytes
+pub const fn fixed_frame_size(record_size: usize) -> usize {
+    record_size + LSN_BYTES
+}
+
+/// Size on disk of many logs with the given serialized payload sizes
+///
+/// # Example
+/// ```
+/// use walcraft::format;
+///
+/// let size = format::estimate_disk_size([10, 20, 30]);
+/// assert_eq!(size, 60 + 3 * format::frame_overhead_bytes());
+/// ```
+pub fn estimate_disk_size<I>(payloads: I) -> usize
+where
+    I: IntoIterator<Item = usize>,
+{
+    payloads.into_iter().map(frame_size).sum()
+}
+
+/// Number of logs with an average serialized payload of `payload` bytes that fit in `capacity`
+/// bytes of storage
+pub const fn entries_per_capacity(capacity: usize, payload: usize) -> usize {
+    capacity / frame_size(payload)
+}
+
+// Checksum of a frame, covering its length prefix and every byte after its checksum
+// None if the frame is too short to hold a checksum
+pub(crate) fn frame_checksum(frame: &[u8]) -> Option<u32> {
+    let rest = frame.get(LENGTH_PREFIX_BYTES + CHECKSUM_BYTES..)?;
+    let mut crc = Crc32::new();
+    crc.update(&frame[..LENGTH_PREFIX_BYTES]);
+    crc.update(rest);
+    Some(crc.finish())
+}
+
+// write the checksum of a frame encoded with room for it after its length prefix
+pub(crate) fn stamp_checksum(frame: &mut [u8]) {
+    if let Some(crc) = frame_checksum(frame) {
+        frame[LENGTH_PREFIX_BYTES..LENGTH_PREFIX_BYTES + CHECKSUM_BYTES]
+            .copy_from_slice(&crc.to_le_bytes());
+    }
+}
+
+// Version and byte order of the frames of a WAL file, from its header or the meta file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FormatVersion {
+    pub version: u8,
+    pub little_endian: bool,
+}
+
+impl FormatVersion {
+    // format written by this build
+    pub const CURRENT: Self = Self {
+        version: FORMAT_VERSION,
+        little_endian: true,
+    };
+
+    pub fn header(&self) -> [u8; SEGMENT_HEADER_BYTES] {
+        let mut header = [0; SEGMENT_HEADER_BYTES];
+        header[..4].copy_from_slice(&SEGMENT_MAGIC);
+        header[4] = self.version;
+        header[5] = if self.little_endian { 1 } else { 2 };
+        header
+    }
+
+    // header of a file whose payloads are encrypted, followed by the nonce of the file
+    pub fn encrypted_header(&self, nonce: &Nonce) -> Vec<u8> {
+        let mut header = Vec::from(self.header());
+        header[6] |= SEGMENT_ENCRYPTED;
+        header.extend(nonce);
+        header
+    }
+
+    // header of a new file, encrypted under `nonce` if any, whose frames carry a checksum if
+    // `checksummed` is set
+    pub fn file_header(&self, nonce: Option<&Nonce>, checksummed: bool) -> Vec<u8> {
+        let mut header = match nonce {
+            Some(nonce) => self.encrypted_header(nonce),
+            None => Vec::from(self.header()),
+        };
+        if checksummed {
+            header[6] |= SEGMENT_CHECKSUMMED;
+        }
+        header
+    }
+
+    // format of the header at the start of raw file content, None for files without a header
+    pub fn from_header(buffer: &[u8]) -> Option<Self> {
+        let header = buffer.get(..SEGMENT_HEADER_BYTES)?;
+        if header[..4] != SEGMENT_MAGIC {
+            return None;
+        }
+        let little_endian = match header[5] {
+            1 => true,
+            2 => false,
+            _ => return None,
+        };
+        Some(Self {
+            version: header[4],
+            little_endian,
+        })
+    }
+
+    // fields of the `format` line of the meta file, as written by `fields`
+    pub fn parse(fields: &[&str]) -> Option<Self> {
+        let version = fields.first()?.parse().ok()?;
+        let little_endian = match *fields.get(1)? {
+            "le" => true,
+            "be" => false,
+            _ => return None,
+        };
+        Some(Self {
+            version,
+            little_endian,
+        })
+    }
+
+    pub fn fields(&self) -> String {
+        let order = if self.little_endian { "le" } else { "be" };
+        format!("{} {}", self.version, order)
+    }
+
+    // fail unless frames of this format can be read by this build
+    // Frames of older versions are read in the byte order they were written with
+    pub fn check(&self, source: &Path) -> Result<(), WalError> {
+        if self.version > FORMAT_VERSION {
+            return Err(WalError::Unsupported(format!(
+                "{} was written with format version {}, newer than version {}",
+                source.display(),
+                self.version,
+                FORMAT_VERSION
+            )));
+        }
+        Ok(())
+    }
+
+    // Fail unless every WAL file at `location` can be read by this build
+    // Only the headers are read
+    pub fn check_files(location: &Path, layout: &Layout) -> Result<(), WalError> {
+        for id in layout.ids() {
+            let path = layout.path(location, id);
+            let header = match layout.storage.read_head(&path, SEGMENT_HEADER_BYTES as u64) {
+                Ok(Some(header)) if header.len() == SEGMENT_HEADER_BYTES => header,
+                _ => continue,
+            };
+            if let Some(format) = Self::from_header(&header) {
+                format.check(&path)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// bytes of the header at the start of raw file content, along with the nonce of encrypted
+// files, 0 for files without a header
+// Frames of a file whose oldest logs were reclaimed start where its header tells
+pub(crate) fn header_len(buffer: &[u8]) -> usize {
+    let base = base_header_len(buffer);
+    if base == 0 || buffer[6] & SEGMENT_RECLAIMED == 0 {
+        return base;
+    }
+    match buffer.get(base..base + SEGMENT_HEAD_BYTES) {
+        Some(head) => {
+            let head = u64::from_le_bytes(head.try_into().expect("offset has a fixed size"));
+            (head as usize).clamp(base + SEGMENT_HEAD_BYTES, buffer.len())
+        }
+        None => buffer.len(),
+    }
+}
+
+// offset of the first frame of a file, from the start of its content up to the end of its
+// header, None when the content ends before the header does
+pub(crate) fn frames_offset(buffer: &[u8]) -> Option<u64> {
+    let base = base_header_len(buffer);
+    if base == 0 || buffer[6] & SEGMENT_RECLAIMED == 0 {
+        return Some(base as u64);
+    }
+    let head = buffer.get(base..base + SEGMENT_HEAD_BYTES)?;
+    let head = u64::from_le_bytes(head.try_into().expect("offset has a fixed size"));
+    Some(head.max((base + SEGMENT_HEAD_BYTES) as u64))
+}
+
+// bytes of the header at the start of raw file content, and of the nonce of an encrypted file
+fn base_header_len(buffer: &[u8]) -> usize {
+    match (FormatVersion::from_header(buffer), segment_nonce(buffer)) {
+        (Some(_), Some(_)) => SEGMENT_HEADER_BYTES + SEGMENT_NONCE_BYTES,
+        (Some(_), None) => SEGMENT_HEADER_BYTES,
+        (None, _) => 0,
+    }
+}
+
+// header written over the start of raw file content, whose frames before `head` are reclaimed
+pub(crate) fn reclaimed_header(buffer: &[u8], head: u64) -> Vec<u8> {
+    let mut header = buffer[..base_header_len(buffer)].to_vec();
+    header[6] |= SEGMENT_RECLAIMED;
+    header.extend(head.to_le_bytes());
+    header
+}
+
+// turn the header of a file whose oldest logs were reclaimed into one followed by frames
+pub(crate) fn clear_reclaimed(header: &mut Vec<u8>) {
+    let base = base_header_len(header);
+    if base > 0 && header[6] & SEGMENT_RECLAIMED != 0 {
+        header[6] &= !SEGMENT_RECLAIMED;
+        header.truncate(base);
+    }
+}
+
+// whether the file at `path` holds anything past its header
+pub(crate) fn holds_frames(storage: &SharedStorage, path: &Path) -> bool {
+    let header_bytes = (SEGMENT_HEADER_BYTES + SEGMENT_NONCE_BYTES) as u64;
+    match (storage.size(path), storage.read_head(path, header_bytes)) {
+        (Some(size), Ok(Some(header))) => size > header_len(&header) as u64,
+        _ => false,
+    }
+}
+
+// nonce of the file at `path`, None unless its payloads are encrypted
+pub(crate) fn file_nonce(storage: &SharedStorage, path: &Path) -> Option<Nonce> {
+    let header_bytes = (SEGMENT_HEADER_BYTES + SEGMENT_NONCE_BYTES) as u64;
+    let header = storage.read_head(path, header_bytes).ok()??;
+    segment_nonce(&header)
+}
+
+// nonce of the file whose raw content starts with `buffer`, None unless its payloads are
+// encrypted
+pub(crate) fn segment_nonce(buffer: &[u8]) -> Option<Nonce> {
+    FormatVersion::from_header(buffer)?;
+    if buffer[6] & SEGMENT_ENCRYPTED == 0 {
+        return None;
+    }
+    let end = SEGMENT_HEADER_BYTES + SEGMENT_NONCE_BYTES;
+    buffer.get(SEGMENT_HEADER_BYTES..end)?.try_into().ok()
+}
+
+// whether the frames of the file whose raw content starts with `buffer` carry a checksum
+pub(crate) fn checksummed(buffer: &[u8]) -> bool {
+    FormatVersion::from_header(buffer).is_some() && buffer[6] & SEGMENT_CHECKSUMMED != 0
+}
+
+// How the frames of a WAL file are encoded, from its header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FrameEncoding {
+    pub order: ByteOrder,
+    // every frame carries a checksum after its length prefix
+    pub checksummed: bool,
+}
+
+impl FrameEncoding {
+    // encoding of the frames of raw file content, from its header
+    pub fn of(buffer: &[u8]) -> Self {
+        Self {
+            order: ByteOrder::of(buffer),
+            checksummed: checksummed(buffer),
+        }
+    }
+}
+
+// Byte order of the numbers in frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ByteOrder {
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    // byte order of the machine running this build
+    pub const NATIVE: Self = match cfg!(target_endian = "little") {
+        true => Self::Little,
+        false => Self::Big,
+    };
+
+    // byte order of the frames of raw file content, from its header
+    // Files without a header were written in the byte order of the machine, assumed to be this one
+    pub fn of(buffer: &[u8]) -> Self {
+        match FormatVersion::from_header(buffer) {
+            Some(FormatVersion {
+                little_endian: true,
+                ..
+            }) => Self::Little,
+            Some(_) => Self::Big,
+            None => Self::NATIVE,
+        }
+    }
+
+    // number at the start of `bytes`, None if they are too short
+    pub fn u16(self, bytes: &[u8]) -> Option<u16> {
+        let bytes = bytes.get(..2)?.try_into().ok()?;
+        Some(match self {
+            Self::Little => u16::from_le_bytes(bytes),
+            Self::Big => u16::from_be_bytes(bytes),
+        })
+    }
+
+    pub fn u32(self, bytes: &[u8]) -> Option<u32> {
+        let bytes = bytes.get(..4)?.try_into().ok()?;
+        Some(match self {
+            Self::Little => u32::from_le_bytes(bytes),
+            Self::Big => u32::from_be_bytes(bytes),
+        })
+    }
+
+    pub fn u64(self, bytes: &[u8]) -> Option<u64> {
+        let bytes = bytes.get(..8)?.try_into().ok()?;
+        Some(match self {
+            Self::Little => u64::from_le_bytes(bytes),
+            Self::Big => u64::from_be_bytes(bytes),
+        })
+    }
+}
+
+// append `value` to `out` as a base 128 varint
+pub(crate) fn encode_varint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+// value of the varint at the start of `buffer` and the bytes it takes
+// None if the varint is incomplete or longer than 10 bytes
+pub(crate) fn decode_varint(buffer: &[u8]) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, byte) in buffer.iter().take(10).enumerate() {
+        value |= ((byte & 0x7F) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn varint() {
+        for value in [0, 1, 127, 128, 300, u32::MAX as u64, u64::MAX] {
+            let mut out = Vec::new();
+            encode_varint(value, &mut out);
+            assert_eq!(decode_varint(&out), Some((value, out.len())));
+        }
+        let mut out = Vec::new();
+        encode_varint(300, &mut out);
+        assert_eq!(out, vec![0xAC, 0x02]);
+        assert_eq!(decode_varint(&out[..1]), None);
+    }
+
+    #[test]
+    fn format_version() {
+        let current = FormatVersion::CURRENT;
+        let header = current.header();
+        assert_eq!(FormatVersion::from_header(&header), Some(current));
+        assert_eq!(header_len(&header), SEGMENT_HEADER_BYTES);
+        // files written before headers
+        assert_eq!(header_len(&[12, 0, 0, 0, 0, 0, 0, 0, 0]), 0);
+        // encrypted files record their nonce after the header
+        assert_eq!(segment_nonce(&header), None);
+        let encrypted = current.encrypted_header(&[9; SEGMENT_NONCE_BYTES]);
+        assert_eq!(segment_nonce(&encrypted), Some([9; SEGMENT_NONCE_BYTES]));
+        assert_eq!(header_len(&encrypted), encrypted.len());
+        // files with checksums are marked in their header
+        assert!(!checksummed(&header));
+        let marked = current.file_header(Some(&[9; SEGMENT_NONCE_BYTES]), true);
+        assert!(checksummed(&marked));
+        assert_eq!(segment_nonce(&marked), Some([9; SEGMENT_NONCE_BYTES]));
+        let fields = current.fields();
+        let fields = fields.split_whitespace().collect::<Vec<_>>();
+        assert_eq!(FormatVersion::parse(&fields), Some(current));
+
+        let path = Path::new("wal_1");
+        assert!(current.check(path).is_ok());
+        let newer = FormatVersion {
+            version: FORMAT_VERSION + 1,
+            ..current
+        };
+        assert!(newer.check(path).is_err());
+        // frames of another byte order are read in that order
+        let swapped = FormatVersion {
+            version: 1,
+            little_endian: false,
+        };
+        assert!(swapped.check(path).is_ok());
+        assert_eq!(ByteOrder::of(&swapped.header()), ByteOrder::Big);
+        assert_eq!(ByteOrder::of(&header), ByteOrder::Little);
+        assert_eq!(ByteOrder::of(&[]), ByteOrder::NATIVE);
+    }
+
+    #[test]
+    fn reclaimed_header() {
+        let mut content = Vec::from(FormatVersion::CURRENT.header());
+        content.resize(100, 7);
+        let header = super::reclaimed_header(&content, 60);
+        assert_eq!(header.len(), SEGMENT_HEADER_BYTES + SEGMENT_HEAD_BYTES);
+        content[..header.len()].copy_from_slice(&header);
+        assert_eq!(header_len(&content), 60);
+        // an offset past the content is cut to its end
+        assert_eq!(header_len(&content[..40]), 40);
+        assert_eq!(header_len(&content[..12]), 12);
+
+        let mut header = header;
+        clear_reclaimed(&mut header);
+        assert_eq!(header, FormatVersion::CURRENT.header());
+    }
+
+    #[test]
+    fn byte_order() {
+        let bytes = [1, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(ByteOrder::Little.u16(&bytes), Some(0x0201));
+        assert_eq!(ByteOrder::Big.u32(&bytes), Some(0x01020304));
+        assert_eq!(ByteOrder::Little.u64(&bytes), Some(0x0807060504030201));
+        assert_eq!(ByteOrder::Big.u64(&bytes[1..]), None);
+    }
+}
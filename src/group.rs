@@ -0,0 +1,109 @@
+use crate::{Lsn, Wal, WalError};
+use serde::{Deserialize, Serialize};
+
+/// Record written to the coordinator WAL of a [WalGroup] once every member is flushed
+///
+/// It names the durable sequence number of every member at the time of the group flush, so
+/// logs up to these sequence numbers form a consistent recovery point across the WALs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[non_exhaustive]
+pub struct GroupCommit {
+    /// Name of every member with the sequence number of its last durable log, None for a member
+    /// that holds no logs yet
+    pub members: Vec<(String, Option<Lsn>)>,
+}
+
+impl GroupCommit {
+    /// Sequence number of the last durable log of the member `name`
+    ///
+    /// None if the group had no such member or the member held no logs.
+    pub fn durable_lsn(&self, name: &str) -> Option<Lsn> {
+        self.members
+            .iter()
+            .find(|(member, _)| member == name)
+            .and_then(|(_, lsn)| *lsn)
+    }
+}
+
+// Flush of a member, returning its last durable log
+type MemberFlush = Box<dyn Fn() -> Result<Option<Lsn>, WalError> + Send>;
+
+/// WALs flushed together, with a commit record naming their durable logs
+///
+/// Applications with a WAL per partition flush the whole group with [WalGroup::flush]. Every
+/// member is flushed, then a [GroupCommit] is written to the coordinator WAL and synced. After
+/// a crash, the last commit read with [WalGroup::last_commit] tells which logs of every member
+/// were durable together, even when some members got further. Members are flushed one after
+/// the other, so the group is not atomic: a commit is only written once all of them succeed.
+///
+/// # Example
+/// ```
+/// use walcraft::{GroupCommit, Wal, WalGroup};
+///
+/// # std::fs::create_dir_all("./tmp/group_doc/orders/").unwrap();
+/// # std::fs::create_dir_all("./tmp/group_doc/payments/").unwrap();
+/// # std::fs::create_dir_all("./tmp/group_doc/coordinator/").unwrap();
+/// let orders: Wal<String> = Wal::new("./tmp/group_doc/orders/", 10_000).unwrap();
+/// let payments: Wal<u64> = Wal::new("./tmp/group_doc/payments/", 10_000).unwrap();
+/// let coordinator: Wal<GroupCommit> = Wal::new("./tmp/group_doc/coordinator/", 10_000).unwrap();
+/// let group = WalGroup::new(coordinator)
+///     .member("orders", &orders)
+///     .member("payments", &payments);
+///
+/// let lsn = orders.write("order 1".to_string());
+/// payments.write(250);
+/// let commit = group.flush().unwrap();
+/// assert_eq!(commit.durable_lsn("orders"), lsn);
+/// ```
+pub struct WalGroup {
+    coordinator: Wal<GroupCommit>,
+    members: Vec<(String, MemberFlush)>,
+}
+
+impl WalGroup {
+    /// Create a group writing its commit records to `coordinator`
+    pub fn new(coordinator: Wal<GroupCommit>) -> Self {
+        Self {
+            coordinator,
+            members: Vec::new(),
+        }
+    }
+
+    /// Add a WAL to the group under `name`
+    ///
+    /// The group holds a handle to the WAL, so its writer thread runs as long as the group.
+    pub fn member<T>(mut self, name: impl Into<String>, wal: &Wal<T>) -> Self
+    where
+        T: Serialize + for<'a> Deserialize<'a> + Send + 'static,
+    {
+        let wal = wal.clone();
+        let flush = move || {
+            // logs accepted before the flush are durable once it returns
+            let next = wal.buffer.next_lsn();
+            wal.flush()?;
+            Ok(next.checked_sub(1))
+        };
+        self.members.push((name.into(), Box::new(flush)));
+        self
+    }
+
+    /// Flush every member, then write and sync a commit record naming their durable logs
+    ///
+    /// No commit is written when a member fails to flush, and the error of the first failing
+    /// member is returned.
+    pub fn flush(&self) -> Result<GroupCommit, WalError> {
+        let mut members = Vec::with_capacity(self.members.len());
+        for (name, flush) in &self.members {
+            members.push((name.clone(), flush()?));
+        }
+        let commit = GroupCommit { members };
+        self.coordinator.try_write(&commit)?;
+        self.coordinator.flush()?;
+        Ok(commit)
+    }
+
+    /// Last commit record written to the coordinator WAL, None if the group was never flushed
+    pub fn last_commit(&self) -> Result<Option<GroupCommit>, WalError> {
+        Ok(self.coordinator.read_last(1)?.pop())
+    }
+}
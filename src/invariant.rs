@@ -0,0 +1,11 @@
+// Runtime checks of internal invariants, enabled by the `debug-invariants` feature
+//
+// The condition is type checked in every build but only evaluated when the feature is enabled,
+// so expensive checks cost nothing in regular builds.
+macro_rules! invariant {
+    ($cond:expr, $($arg:tt)+) => {
+        if cfg!(feature = "debug-invariants") && !$cond {
+            panic!("walcraft invariant violated: {}", format_args!($($arg)+));
+        }
+    };
+}
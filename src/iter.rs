@@ -0,0 +1,101 @@
+use crate::decode::Decoder;
+use crate::entry::LogEntry;
+use crate::lock::Reading;
+use crate::reader::WalReader;
+use crate::scratch::Scratch;
+use crate::{Lsn, WalError};
+use serde::Deserialize;
+use std::sync::{Arc, Mutex};
+
+/// Logs returned by [crate::Wal::iter] and [crate::Wal::iter_from], read lazily in the order
+/// they were written
+///
+/// Only the file being iterated is held in memory: the next file is read once the logs of the
+/// previous one are consumed, and logs are deserialized one by one. The files are read as they
+/// were when the iterator was created, so logs written later are left out and files recycled by
+/// the writer thread meanwhile are still read in full, though logs removed by
+/// [crate::Wal::truncate] from a file not yet read may be skipped. Logs that can't be
+/// deserialized are skipped, as with [crate::Wal::read]. An error reading a file is yielded once
+/// and ends the iteration.
+pub struct WalIter<T> {
+    reader: WalReader,
+    // taken while a file is read, so a truncation doesn't reclaim it midway
+    read_lock: Option<Arc<Mutex<Scratch>>>,
+    // files left to read, oldest first
+    files: std::vec::IntoIter<u8>,
+    // logs not yet written to a file, yielded after the files
+    pending: Option<Vec<LogEntry>>,
+    current: std::vec::IntoIter<LogEntry>,
+    decoder: Decoder<T>,
+    // sequence number of the first log yielded
+    from: Lsn,
+}
+
+impl<T> WalIter<T>
+where
+    T: for<'a> Deserialize<'a>,
+{
+    pub(crate) fn new(
+        reader: WalReader,
+        read_lock: Option<Arc<Mutex<Scratch>>>,
+        pending: Vec<LogEntry>,
+        decoder: Decoder<T>,
+        from: Lsn,
+    ) -> Result<Self, WalError> {
+        let files = reader.files_from(from)?;
+        Ok(Self {
+            reader,
+            read_lock,
+            files: files.into_iter(),
+            pending: Some(pending),
+            current: Vec::new().into_iter(),
+            decoder,
+            from,
+        })
+    }
+
+    // logs of the next file holding any, then the pending logs, None once all were read
+    fn next_batch(&mut self) -> Option<Result<Vec<LogEntry>, WalError>> {
+        for id in self.files.by_ref() {
+            let _guard = self.read_lock.as_ref().and_then(|lock| {
+                (!Reading::held(lock)).then(|| lock.lock().unwrap_or_else(|e| e.into_inner()))
+            });
+            match self.reader.read_files(|i| i == id) {
+                Ok(entries) if !entries.is_empty() => return Some(Ok(entries)),
+                // file hasn't been created yet or holds no logs
+                Ok(_) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        self.pending.take().map(Ok)
+    }
+}
+
+impl<T> Iterator for WalIter<T>
+where
+    T: for<'a> Deserialize<'a>,
+{
+    type Item = Result<T, WalError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            for entry in self.current.by_ref() {
+                if entry.lsn() < self.from {
+                    continue;
+                }
+                if let Some(log) = self.decoder.decode(entry) {
+                    return Some(Ok(log));
+                }
+            }
+            match self.next_batch()? {
+                Ok(entries) => self.current = entries.into_iter(),
+                Err(e) => {
+                    // nothing is read after an error
+                    self.files = Vec::new().into_iter();
+                    self.pending = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
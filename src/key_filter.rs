@@ -0,0 +1,110 @@
+use crate::compaction::{self, KeyFn, SKETCH_BITS};
+use crate::reader::WalReader;
+use std::path::{Path, PathBuf};
+
+// Bloom filter of the keys of a sealed WAL file, stored in `keys_N` next to `wal_N`
+// Lookups by key skip the files whose filter doesn't contain the key. Files without a filter,
+// like the current file, are always read. Compaction only removes logs, so a filter stays valid
+// for a compacted file.
+pub(crate) struct KeyFilter {
+    bits: Vec<u64>,
+}
+
+// Key hashed ahead of the filter, so filters written with a different hasher are ignored
+const PROBE: &str = "walcraft";
+
+impl KeyFilter {
+    // filter of the keys of all logs in the WAL file `id`
+    pub fn build(reader: &WalReader, key: &KeyFn, id: u8) -> Self {
+        let mut bits = vec![0u64; SKETCH_BITS / 64];
+        let content = reader.raw(id).ok().flatten().unwrap_or_default();
+        for (_, entry) in reader.frames(&content) {
+            // logs without a key are never looked up
+            if let Some(k) = key(entry.payload()) {
+                for p in compaction::bloom_positions(k) {
+                    bits[p / 64] |= 1 << (p % 64);
+                }
+            }
+        }
+        Self { bits }
+    }
+
+    // filter of the WAL file `id`, None if it's missing or unusable
+    pub fn load(location: &Path, id: u8) -> Option<Self> {
+        let content = std::fs::read(Self::path(location, id)).ok()?;
+        if content.len() != 8 + SKETCH_BITS / 8 {
+            return None;
+        }
+        let words = content
+            .chunks_exact(8)
+            .map(|c| u64::from_le_bytes(c.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        if words[0] != compaction::hash_key(&PROBE) {
+            return None;
+        }
+        Some(Self {
+            bits: words[1..].to_vec(),
+        })
+    }
+
+    pub fn store(&self, location: &Path, id: u8) -> std::io::Result<()> {
+        let mut content = Vec::with_capacity(8 + SKETCH_BITS / 8);
+        content.extend(compaction::hash_key(&PROBE).to_le_bytes());
+        for word in &self.bits {
+            content.extend(word.to_le_bytes());
+        }
+        std::fs::write(Self::path(location, id), content)
+    }
+
+    // drop the filter of a file that is written to again
+    pub fn remove(location: &Path, id: u8) {
+        let _ = std::fs::remove_file(Self::path(location, id));
+    }
+
+    pub fn contains(&self, key: u64) -> bool {
+        compaction::bloom_positions(key).all(|p| self.bits[p / 64] & (1 << (p % 64)) != 0)
+    }
+
+    fn path(location: &Path, id: u8) -> PathBuf {
+        location.join(format!("keys_{}", id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::LogEntry;
+    use std::io::Write;
+    use std::sync::Arc;
+
+    #[test]
+    fn filter() {
+        let dir = PathBuf::from("./tmp/key_filter/");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut file = std::fs::File::create(dir.join("wal_1")).unwrap();
+        for k in 0..100u32 {
+            let frame = LogEntry::try_new(&k).unwrap().into_frame(None, false);
+            file.write_all(&frame).unwrap();
+        }
+        let key: KeyFn = Arc::new(|payload: &[u8]| {
+            bincode::deserialize::<u32>(payload)
+                .ok()
+                .map(|k| compaction::hash_key(&k))
+        });
+        let reader = WalReader::new(dir.clone());
+        KeyFilter::build(&reader, &key, 1).store(&dir, 1).unwrap();
+
+        let filter = KeyFilter::load(&dir, 1).unwrap();
+        assert!((0..100u32).all(|k| filter.contains(compaction::hash_key(&k))));
+        let misses = (100..1100u32)
+            .filter(|k| filter.contains(compaction::hash_key(k)))
+            .count();
+        assert!(misses < 10);
+
+        KeyFilter::remove(&dir, 1);
+        assert!(KeyFilter::load(&dir, 1).is_none());
+    }
+}
@@ -0,0 +1,244 @@
+use crate::slots::Slots;
+use crate::storage::SharedStorage;
+use crate::WalError;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// files of a new WAL, reused in turn
+pub(crate) const DEFAULT_SEGMENTS: u8 = 5;
+// name of WAL files, followed by their id
+pub(crate) const DEFAULT_PREFIX: &str = "wal_";
+// files written by a WAL in its directory next to the WAL files, and the prefixes of those
+// followed by an id or a name
+const OWNED_FILES: [&str; 7] = [
+    "meta",
+    "truncate",
+    "codec",
+    "schema",
+    "lease",
+    "slots",
+    "slots.lock",
+];
+const OWNED_PREFIXES: [&str; 4] = ["manifest_", "index_", "keys_", "consumer_"];
+const OWNED_DIRS: [&str; 2] = ["blobs", "topics"];
+// extensions of the temporary files replacing the files above
+const SCRATCH_EXTENSIONS: [&str; 4] = ["tmp", "recycle", "convert", "replace"];
+
+// Number and names of the WAL files in a directory
+// Files are numbered from 1 to `segments`, and the writer moves on from the last one to the first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Layout {
+    pub segments: u8,
+    pub prefix: Arc<str>,
+    // second location sealed files are moved to, usually on another disk
+    pub cold: Option<ColdTier>,
+    // storage of the WAL files, the local file system by default
+    pub storage: SharedStorage,
+}
+
+// Location of sealed files once `after` newer files were sealed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ColdTier {
+    pub location: Arc<Path>,
+    pub after: usize,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Self {
+            segments: DEFAULT_SEGMENTS,
+            prefix: DEFAULT_PREFIX.into(),
+            cold: None,
+            storage: SharedStorage::Local,
+        }
+    }
+}
+
+impl Layout {
+    // ids of all WAL files
+    pub fn ids(&self) -> RangeInclusive<u8> {
+        1..=self.segments
+    }
+
+    // file holding the logs of `id`, the cold one once the file was moved
+    pub fn path(&self, location: &Path, id: u8) -> PathBuf {
+        let hot = self.hot_path(location, id);
+        match self.cold_path(id) {
+            Some(cold) if !self.storage.exists(&hot) && self.storage.exists(&cold) => cold,
+            _ => hot,
+        }
+    }
+
+    // file written by the writer, in the WAL directory
+    pub fn hot_path(&self, location: &Path, id: u8) -> PathBuf {
+        location.join(format!("{}{}", self.prefix, id))
+    }
+
+    // file `id` once moved to the cold location, None without one
+    pub fn cold_path(&self, id: u8) -> Option<PathBuf> {
+        let cold = self.cold.as_ref()?;
+        Some(self.hot_path(&cold.location, id))
+    }
+
+    // file written after the file `pointer`
+    pub fn next(&self, pointer: u8) -> u8 {
+        match pointer >= self.segments {
+            true => 1,
+            false => pointer + 1,
+        }
+    }
+
+    // WAL files from the newest to the oldest, when `pointer` is the current one
+    pub fn read_order(&self, mut pointer: u8) -> Vec<u8> {
+        let mut d = Vec::with_capacity(self.segments as usize);
+        while d.len() < self.segments as usize {
+            d.push(pointer);
+            pointer -= 1;
+            if pointer < 1 {
+                pointer = self.segments;
+            }
+        }
+        d
+    }
+
+    // highest id of the WAL files at `location` or moved to the cold location, None without files
+    pub fn highest_id(&self, location: &Path) -> Option<u8> {
+        let cold = self.cold.as_ref().map(|c| &*c.location);
+        [Some(location), cold]
+            .into_iter()
+            .flatten()
+            .flat_map(|dir| self.storage.list(dir))
+            .filter_map(|path| self.id_of(&path))
+            .max()
+    }
+
+    // whether the entry `name` of the WAL directory was written by the WAL, a directory when
+    // `dir` is set, as opposed to files of the user stored next to them
+    pub fn owns(&self, name: &str, dir: bool) -> bool {
+        if dir {
+            return OWNED_DIRS.contains(&name) || Slots::id_of(name).is_some();
+        }
+        let name = match name.rsplit_once('.') {
+            Some((stem, ext)) if SCRATCH_EXTENSIONS.contains(&ext) => stem,
+            _ => name,
+        };
+        OWNED_FILES.contains(&name)
+            || OWNED_PREFIXES.iter().any(|p| name.starts_with(p))
+            || self.id_of(Path::new(name)).is_some()
+    }
+
+    // id of the WAL file at `path`, None for other files
+    fn id_of(&self, path: &Path) -> Option<u8> {
+        let name = path.file_name()?.to_str()?;
+        name.strip_prefix(&*self.prefix)?.parse::<u8>().ok()
+    }
+
+    // Fail unless the layout can describe the WAL files at `location`
+    // Files beyond the last one were written with more segments, and would never be read
+    pub fn check(&self, location: &Path) -> Result<(), WalError> {
+        if self.segments < 2 {
+            return Err(WalError::Unsupported(
+                "A WAL needs at least 2 files".to_string(),
+            ));
+        }
+        // temporary files are named after WAL files with an extension
+        if self.prefix.is_empty() || self.prefix.contains(['/', '\\', '.']) {
+            return Err(WalError::Unsupported(format!(
+                "`{}` is not a valid prefix of WAL file names",
+                self.prefix
+            )));
+        }
+        let beyond = self
+            .storage
+            .list(location)
+            .into_iter()
+            .filter_map(|path| {
+                let name = path.file_name()?.to_str()?;
+                name.strip_prefix(&*self.prefix)?.parse::<u16>().ok()
+            })
+            .find(|id| *id > self.segments as u16);
+        match beyond {
+            Some(id) => Err(WalError::Unsupported(format!(
+                "WAL holds file {}{} but is opened with {} files",
+                self.prefix, id, self.segments
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn order() {
+        let layout = Layout::default();
+        assert_eq!(layout.next(1), 2);
+        assert_eq!(layout.next(5), 1);
+        assert_eq!(layout.read_order(5), Vec::from([5, 4, 3, 2, 1]));
+        assert_eq!(layout.read_order(4), Vec::from([4, 3, 2, 1, 5]));
+        assert_eq!(layout.read_order(3), Vec::from([3, 2, 1, 5, 4]));
+        assert_eq!(layout.read_order(2), Vec::from([2, 1, 5, 4, 3]));
+        assert_eq!(layout.read_order(1), Vec::from([1, 5, 4, 3, 2]));
+
+        let layout = Layout {
+            segments: 3,
+            prefix: "segment-".into(),
+            ..Layout::default()
+        };
+        assert_eq!(layout.next(3), 1);
+        assert_eq!(layout.read_order(1), Vec::from([1, 3, 2]));
+        assert_eq!(
+            layout.path(Path::new("./tmp/"), 2),
+            Path::new("./tmp/segment-2")
+        );
+    }
+
+    #[test]
+    fn check() {
+        let dir = Path::new("./tmp/layout_check/");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(dir.join("wal_7"), b"").unwrap();
+        let layout = |segments| Layout {
+            segments,
+            ..Layout::default()
+        };
+        assert!(matches!(
+            layout(5).check(dir),
+            Err(WalError::Unsupported(_))
+        ));
+        assert!(layout(7).check(dir).is_ok());
+        assert!(layout(1).check(dir).is_err());
+        let prefixed = Layout {
+            segments: 5,
+            prefix: "wal.".into(),
+            ..Layout::default()
+        };
+        assert!(prefixed.check(dir).is_err());
+    }
+
+    #[test]
+    fn cold() {
+        let dir = Path::new("./tmp/layout_cold/");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir.join("cold")).unwrap();
+        let layout = Layout {
+            cold: Some(ColdTier {
+                location: dir.join("cold").into(),
+                after: 1,
+            }),
+            ..Layout::default()
+        };
+        // files are found in the cold location once they're only there
+        std::fs::write(dir.join("cold/wal_7"), b"").unwrap();
+        std::fs::write(dir.join("wal_2"), b"").unwrap();
+        std::fs::write(dir.join("cold/wal_2"), b"").unwrap();
+        assert_eq!(layout.path(dir, 7), dir.join("cold/wal_7"));
+        assert_eq!(layout.path(dir, 2), dir.join("wal_2"));
+        assert_eq!(layout.path(dir, 3), dir.join("wal_3"));
+        assert_eq!(layout.highest_id(dir), Some(7));
+    }
+}
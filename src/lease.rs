@@ -0,0 +1,79 @@
+use crate::WalError;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// Exclusive right of a process to write to a WAL directory, held through an OS lock on the
+// `lease` file for as long as a handle is alive
+// The lock is released by the OS when the process exits, so a crashed writer never keeps it.
+// The `lease` file holds the id of the process holding it, for error messages. The id is written
+// over the previous one with a fixed width, so the file is never seen empty once written.
+#[derive(Debug)]
+pub(crate) struct WriterLease {
+    _file: File,
+}
+
+impl WriterLease {
+    pub fn acquire(location: &Path) -> Result<Self, WalError> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(location.join("lease"))
+            .map_err(|e| WalError::io(e, "Failed to open lease file"))?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut holder = String::new();
+                let _ = file.read_to_string(&mut holder);
+                // a new file is empty until its holder writes its id
+                let holder = match holder.trim() {
+                    "" => "another process".to_string(),
+                    id => format!("process {}", id),
+                };
+                return Err(WalError::Locked(format!(
+                    "WAL is already written by {}",
+                    holder
+                )));
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(WalError::io(e, "Failed to lock lease file"));
+            }
+        }
+        // process ids have at most 10 digits
+        let _ = file
+            .seek(SeekFrom::Start(0))
+            .and_then(|_| write!(file, "{:<10}", std::process::id()));
+        Ok(Self { _file: file })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclusive() {
+        let dir = Path::new("./tmp/writer_lease/");
+        std::fs::create_dir_all(dir).unwrap();
+        let lease = WriterLease::acquire(dir).unwrap();
+        match WriterLease::acquire(dir) {
+            Err(WalError::Locked(e)) => assert!(e.ends_with(&std::process::id().to_string())),
+            r => panic!("unexpected {:?}", r),
+        }
+        drop(lease);
+        assert!(WriterLease::acquire(dir).is_ok());
+
+        // a lease file not written yet doesn't report an empty process id
+        let dir = Path::new("./tmp/writer_lease_new/");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+        let file = File::create(dir.join("lease")).unwrap();
+        file.lock().unwrap();
+        match WriterLease::acquire(dir) {
+            Err(WalError::Locked(e)) => assert!(e.ends_with("by another process")),
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+}
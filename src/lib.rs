@@ -1,27 +1,368 @@
+#[macro_use]
+mod invariant;
+#[macro_use]
+mod trace;
+
+mod ack;
+mod archive;
+#[cfg(feature = "tokio")]
+pub mod r#async;
+mod backpressure;
+mod blob;
 mod buffer;
+mod builder;
+mod cache;
+mod chain;
+#[cfg(feature = "chaos")]
+mod chaos;
+mod checkpoint;
+mod checksum;
+mod clock;
+mod codec;
+mod compaction;
+mod compression;
+mod consumer;
+mod cursor;
+mod decode;
+mod diff;
+mod drain;
+mod durable;
+mod encryption;
 mod entry;
+mod ephemeral;
+mod event;
+mod eviction;
+mod failure;
+#[cfg(test)]
+mod faults;
+mod flush;
+mod fold;
+mod fork;
+pub mod format;
+mod group;
+mod iter;
+mod key_filter;
+mod layout;
+mod lease;
 mod lock;
+mod manifest;
+mod memory;
+mod meta;
+mod migrate;
+mod open;
+mod pacing;
+mod page;
+mod pipeline;
+mod pool;
+mod producer;
+mod prometheus;
+mod rate_limit;
+mod raw;
 mod reader;
+mod recent;
+mod recovery;
+mod repair;
+mod replay;
+#[cfg(feature = "replication")]
+pub mod replication;
+mod role;
+mod scratch;
+mod scrub;
+mod segment;
+mod segment_index;
+#[cfg(all(feature = "signals", unix))]
+mod signals;
+mod sink;
+mod slots;
+mod snapshot;
+mod spawn;
+mod spill;
+mod stats;
+mod storage;
+mod subscribe;
+mod topic;
+mod transaction;
+mod truncation;
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+mod uring;
+mod validate;
+mod verify;
 mod writer;
 
+use self::ack::Acks;
+pub use self::archive::{ArchivedSegment, Archiver};
+pub use self::backpressure::{Backpressure, BufferDepth, Overflow};
 use self::buffer::Buffer;
+pub use self::builder::{SyncPolicy, WakeStrategy, WalBuilder};
+use self::builder::{TypedKey, TypedMerge};
+use self::cache::ReadCache;
+pub use self::chain::ChainVerification;
+#[cfg(feature = "chaos")]
+pub use self::chaos::Chaos;
+pub use self::checkpoint::Checkpointer;
+use self::clock::Clock;
+use self::codec::Codec;
+#[cfg(feature = "self-describing")]
+pub use self::codec::DynamicLog;
+pub use self::codec::SerializationCodec;
+use self::compaction::{KeyFn, RetainFn};
+pub use self::compression::CompressionCodec;
+pub use self::consumer::Consumer;
+use self::cursor::{Consumers, Cursor};
+use self::decode::Decoder;
+pub use self::decode::{DecodeError, DecodeReport};
+pub use self::diff::Diff;
+pub use self::drain::ChannelReceiver;
+use self::encryption::Cipher;
 use self::entry::LogEntry;
-use self::lock::LockManager;
-use self::reader::WalReader;
-use self::writer::{WalWriter, WalWriterProps};
+use self::ephemeral::EphemeralDir;
+use self::event::Listeners;
+pub use self::event::{EventListener, FlushEvent, RotationEvent};
+pub use self::eviction::{CapacityWarning, Eviction};
+use self::failure::Failures;
+pub use self::failure::{WriteFailure, WriteOperation};
+pub use self::fold::{FileSnapshotStore, SnapshotStore};
+pub use self::fork::ForkBehavior;
+use self::fork::ForkGuard;
+use self::format::FormatVersion;
+pub use self::format::Framing;
+pub use self::group::{GroupCommit, WalGroup};
+pub use self::iter::WalIter;
+use self::key_filter::KeyFilter;
+use self::layout::Layout;
+use self::lease::WriterLease;
+use self::lock::{LockManager, Reading};
+use self::manifest::Manifest;
+pub use self::manifest::ManifestKind;
+use self::memory::MemoryBudget;
+pub use self::memory::MemoryUsage;
+pub use self::migrate::{Envelope, Migrate};
+use self::open::OpenDirectory;
+pub use self::pacing::Pacing;
+pub use self::page::{Page, PageToken};
+pub use self::producer::{Attributed, ProducerStats};
+use self::prometheus::Exposition;
+use self::rate_limit::Limiter;
+pub use self::rate_limit::RateLimit;
+pub use self::raw::RawWal;
+use self::reader::{SegmentData, WalReader};
+use self::recent::Recent;
+pub use self::recovery::Recovery;
+pub use self::repair::{SegmentCheck, VerifyReport};
+pub use self::replay::Replay;
+pub use self::role::{WalReadHandle, WalWriterHandle};
+use self::scratch::Scratch;
+pub use self::scrub::{Corruption, Health};
+use self::segment::SegmentSpan;
+pub use self::segment::{SegmentDigest, SegmentEntries, SegmentSealed};
+pub use self::sink::WalSink;
+use self::slots::Slots;
+use self::snapshot::Snapshot;
+use self::spawn::{Shutdown, WriterHandle};
+pub use self::spill::BoundedRead;
+use self::spill::SpillWriter;
+pub use self::stats::{
+    FileStatus, HandleStats, LatencyStats, ReadMetrics, RotationHistory, RotationRecord,
+    WalMetrics, WalStats, WalStatus,
+};
+use self::stats::{HandleCounters, HandleRegistry, LatencyHistogram, Metrics};
+pub use self::storage::{LocalStorage, MemoryStorage, Storage, StorageSegment};
+use self::subscribe::Subscribers;
+pub use self::subscribe::Subscription;
+use self::topic::{Restart, SignalSender, Topics};
+pub use self::transaction::{Transaction, TransactionStream};
+use self::truncation::Truncation;
+pub use self::validate::{Rejection, Validation};
+pub use self::verify::OpenVerification;
+use self::verify::Verifier;
+use self::writer::{FlushReply, Signal, WalWriter, WalWriterProps};
 use serde::{Deserialize, Serialize};
+use std::hash::Hash;
+use std::io::{BufRead, Write};
 use std::marker::PhantomData;
-use std::path::PathBuf;
-use std::sync::mpsc::Sender;
+use std::ops::{ControlFlow, Range};
+use std::path::{Path, PathBuf};
 use std::sync::{mpsc, Arc, Mutex};
-use std::thread::{sleep, Thread};
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Log sequence number assigned to every log in the order it enters the WAL
+pub type Lsn = u64;
+
+/// Id of a producer writing to the WAL, recorded with every log it writes
+pub type ProducerId = u16;
 
+/// Failure of an operation on the WAL
+///
+/// Implements [std::error::Error], so it converts into the error types of `anyhow` and
+/// `thiserror`, and [WalError::Io] exposes the underlying [std::io::Error] as its source.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum WalError {
+    /// The capacity of the WAL or of its buffer can't hold the logs
     Capacity(String),
+    /// A file of the WAL is missing or unusable
     File(String),
+    /// A file operation failed
+    Io {
+        /// What the WAL was doing
+        context: String,
+        /// File operated on, when known
+        path: Option<PathBuf>,
+        source: std::io::Error,
+    },
+    /// A file holds content the WAL didn't write, e.g. failing its checksum
+    Corruption {
+        path: PathBuf,
+        /// Id of the WAL file, None for other files such as the meta file
+        segment: Option<u8>,
+        /// Offset of the damaged content in the file, when known
+        offset: Option<u64>,
+        reason: String,
+    },
+    /// A log couldn't be serialized or deserialized
     Serialization(String),
+    /// The size of the current file isn't the size recorded in the meta file, see
+    /// [WalBuilder::positional_writes]
+    ExternalModification {
+        path: PathBuf,
+        /// Size recorded in the meta file
+        recorded: u64,
+        /// Size of the file
+        size: u64,
+    },
+    /// A log was refused by the rules of [WalBuilder::strict]
+    Rejected(Rejection),
+    /// The writer thread has stopped, e.g. after a panic
+    WriterDead(String),
+    /// The writer of the WAL was closed, and takes no more logs
+    Closed,
+    /// The WAL can't be written to
+    ReadOnlyFilesystem(String),
+    /// The operation or combination of options isn't supported
+    Unsupported(String),
+    /// The handle was used in a forked process, see [WalBuilder::fork_behavior]
+    ForkDetected(String),
+    /// The log exceeds the rate limit, see [WalBuilder::rate_limit]
+    Throttled(String),
+    /// Another process writes to the WAL, see [WalBuilder::exclusive]
+    Locked(String),
+    /// Another handle of this process writes to the WAL, see [WalBuilder::duplicate_guard]
+    AlreadyOpen(String),
+    /// The buffer is full, see [WalBuilder::backpressure]
+    Backpressure(String),
+    /// The log a [PageToken] points to was dropped from the WAL, see [Wal::read_page]
+    CompactedAway {
+        /// Sequence number of the log the token points to
+        lsn: Lsn,
+        /// Sequence number of the oldest log of the WAL
+        oldest: Lsn,
+    },
+    /// A follower and its primary disagree on the logs they hold
+    Replication(String),
+}
+
+impl WalError {
+    // error for a failed file operation, telling a read-only file system apart
+    pub(crate) fn io(error: std::io::Error, message: &str) -> Self {
+        match error.kind() {
+            std::io::ErrorKind::ReadOnlyFilesystem => Self::ReadOnlyFilesystem(format!(
+                "{}, open the WAL with `WalBuilder::read_only` to read it",
+                message
+            )),
+            _ => Self::Io {
+                context: message.to_string(),
+                path: None,
+                source: error,
+            },
+        }
+    }
+
+    // error for a failed operation on the file at `path`
+    pub(crate) fn io_at(error: std::io::Error, message: &str, path: &Path) -> Self {
+        match Self::io(error, message) {
+            Self::Io {
+                context, source, ..
+            } => Self::Io {
+                context,
+                path: Some(path.to_path_buf()),
+                source,
+            },
+            other => other,
+        }
+    }
+}
+
+impl std::fmt::Display for WalError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Capacity(message) => write!(f, "capacity exceeded: {}", message),
+            Self::Io {
+                context,
+                path: Some(path),
+                source,
+            } => write!(f, "{} ({}): {}", context, path.display(), source),
+            Self::Io {
+                context, source, ..
+            } => write!(f, "{}: {}", context, source),
+            Self::Corruption {
+                path,
+                offset,
+                reason,
+                ..
+            } => {
+                write!(f, "{} is corrupted", path.display())?;
+                if let Some(offset) = offset {
+                    write!(f, " at offset {}", offset)?;
+                }
+                write!(f, ": {}", reason)
+            }
+            Self::Serialization(message) => write!(f, "serialization failed: {}", message),
+            Self::ExternalModification {
+                path,
+                recorded,
+                size,
+            } => write!(
+                f,
+                "log file {} has {} bytes but {} bytes were recorded",
+                path.display(),
+                size,
+                recorded
+            ),
+            Self::Rejected(rejection) => write!(f, "log rejected: {}", rejection),
+            Self::Closed => write!(f, "WAL is closed"),
+            Self::CompactedAway { lsn, oldest } => write!(
+                f,
+                "log {} was dropped from the WAL, whose oldest log is {}",
+                lsn, oldest
+            ),
+            Self::File(message)
+            | Self::WriterDead(message)
+            | Self::ReadOnlyFilesystem(message)
+            | Self::Unsupported(message)
+            | Self::ForkDetected(message)
+            | Self::Throttled(message)
+            | Self::Locked(message)
+            | Self::AlreadyOpen(message)
+            | Self::Backpressure(message)
+            | Self::Replication(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for WalError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::Rejected(rejection) => Some(rejection),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for WalError {
+    fn from(error: std::io::Error) -> Self {
+        Self::io(error, "File operation failed")
+    }
 }
 
 /// A Write Ahead Log (WAL) solution for concurrent operations
@@ -29,7 +370,7 @@ pub enum WalError {
 /// # How?
 /// This library gives atomic guarantees in concurrent environments and high performance/throughput
 /// by using in-memory buffer and leveraging append-only logs. The library spawns a dedicated log
-/// writing thread that is parked when read operations happen.
+/// writing thread, which keeps writing while reads work on a snapshot of the files.
 ///
 /// The logs are split across multiple files. The older files are deleted to preserve the capacity constraints.
 ///
@@ -48,7 +389,8 @@ pub enum WalError {
 /// let log = Log {id: 1, value: 5.6234};
 ///
 /// // initiate wal and add a log
-/// let wal = Wal::new("./tmp/", 500).unwrap(); // 500MB of log capacity
+/// # std::fs::create_dir_all("./tmp/wal_doc/").unwrap();
+/// let wal = Wal::new("./tmp/wal_doc/", 500 << 20).unwrap(); // 500MB of log capacity
 /// wal.write(log); // write a log
 ///
 /// // write a log in another thread
@@ -63,29 +405,163 @@ pub enum WalError {
 /// wal.write(log);
 /// ```
 ///
-#[derive(Clone)]
 pub struct Wal<T>
 where
     T: Serialize + for<'a> Deserialize<'a>,
 {
     // location of WAL files
     location: PathBuf,
-    // capacity of data
-    capacity: usize, // todo: remove this field as this shall be managed by the writer
     // Shared buffer to communicate with [WalWriter]
     buffer: Buffer,
-    // A channel to alert [WalWriter] of new logs
-    sender: Sender<()>,
-    // Lock manager to switch between read and write mode for file IO
+    // A channel to alert [WalWriter] of new logs, shared with the topics of the WAL
+    sender: SignalSender,
+    // Waits for the writer to stop once the last handle is dropped, after `sender`
+    shutdown: Arc<Shutdown>,
+    // Lock keeping files and buffer in agreement while a snapshot is taken
     lock: LockManager,
-    // Handle to write thread.. needed to unpark the thread when going from read to write mode
-    writer: Thread,
-    // State for whether we are in read mode or write mode.. true here means read mode
-    read_lock: Arc<Mutex<()>>,
+    // Handle to write thread, to find out whether it's still running
+    // Not spawned in read-only mode
+    writer: Option<WriterHandle>,
+    // Held while in read mode, guarding the scratch buffer shared by reads of all handles
+    read_lock: Arc<Mutex<Scratch>>,
+    // Memory available to hold logs in [Wal::read_bounded]
+    read_memory_cap: Option<usize>,
+    // Whether reads include logs not yet written to a file
+    read_unflushed: bool,
+    // Last read of [Wal::read_cached], shared by all handles
+    read_cache: Option<Arc<ReadCache>>,
+    // Rules enforced on logs before they are accepted
+    validation: Validation,
+    // Encoding of log payloads
+    codec: Codec,
+    // Deserialization of logs, falling back to older types
+    decoder: Decoder<T>,
+    // Payload size of every log when stored as fixed size records
+    record_size: Option<usize>,
+    // Encoding of frames in WAL files
+    framing: Framing,
+    // Every frame carries a checksum
+    checksums: bool,
+    // Number and names of WAL files
+    layout: Layout,
+    // Highest expansion of compressed logs accepted when reading
+    max_expansion: usize,
+    // Key of the payloads of encrypted files
+    cipher: Option<Cipher>,
+    // Number of threads deserializing logs in [Wal::read]
+    decode_threads: usize,
+    // Payload bytes decoded at a time by the decode threads
+    decode_window: usize,
+    // Recorded with every log written by this handle
+    producer: Option<ProducerId>,
+    // Clock recording when every log was written in its frame, if logs are stamped
+    clock: Option<Clock>,
+    // Key of every log in keyed mode, hashed
+    key: Option<TypedKey<T>>,
+    // Key of every log in keyed mode, computed from its serialized payload
+    raw_key: Option<KeyFn>,
+    // Accumulation of logs with the same key in keyed mode
+    merge: Option<TypedMerge<T>>,
+    // Correction of the meta file made when the WAL was opened
+    recovery: Option<Recovery>,
+    // Findings of the scrubber
+    health: Arc<Mutex<Health>>,
+    // Check of sealed files started when the WAL was opened
+    verifier: Arc<Verifier>,
+    // Time sampled logs took from the buffer to a file
+    latency: Arc<Mutex<LatencyHistogram>>,
+    // Recent rotations of the writer thread, for [Wal::rotation_history]
+    rotations: Arc<Mutex<RotationHistory>>,
+    // Failures of the writer thread to store logs
+    failures: Failures,
+    // Counters of writes, syncs, rotations and reads
+    metrics: Arc<Metrics>,
+    // Position of this handle for [Wal::entries_since]
+    cursor: Cursor,
+    // Cost of the last read through this handle
+    last_read: Mutex<Option<ReadMetrics>>,
+    // Receivers of the logs written from now on
+    subscribers: Subscribers,
+    // Process that opened the WAL, and how to behave when used in a forked process
+    fork: ForkGuard,
+    // Exclusive right to write to the directory, released once all handles are dropped
+    lease: Option<Arc<WriterLease>>,
+    // Registration of the directory as open in this process, removed once all handles are dropped
+    open: Option<Arc<OpenDirectory>>,
+    // Slots of a WAL written by several processes, and the one written by this handle
+    slots: Option<Slots>,
+    // Private directory of a WAL held in memory, removed once all handles are dropped
+    ephemeral: Option<Arc<EphemeralDir>>,
+    // Rate of logs accepted from all handles, and from this handle and its clones
+    rate_limit: Option<Arc<Limiter>>,
+    handle_limit: Option<Arc<Limiter>>,
+    // Logs written through every label of handles, and the counters of this handle's label
+    handles: HandleRegistry,
+    counters: Arc<HandleCounters>,
+    // Topics opened from this handle and its clones
+    topics: Arc<Topics<T>>,
     // Phantom ownership of generic to avoid usage of complex lifetimes
     phantom: PhantomData<T>,
 }
 
+// Handles can be cloned whatever the type of logs is
+impl<T> Clone for Wal<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    fn clone(&self) -> Self {
+        Self {
+            location: self.location.clone(),
+            buffer: self.buffer.clone(),
+            sender: self.sender.clone(),
+            shutdown: self.shutdown.clone(),
+            lock: self.lock.clone(),
+            writer: self.writer.clone(),
+            read_lock: self.read_lock.clone(),
+            read_memory_cap: self.read_memory_cap,
+            read_unflushed: self.read_unflushed,
+            read_cache: self.read_cache.clone(),
+            validation: self.validation.clone(),
+            codec: self.codec,
+            decoder: self.decoder.clone(),
+            record_size: self.record_size,
+            framing: self.framing,
+            checksums: self.checksums,
+            layout: self.layout.clone(),
+            max_expansion: self.max_expansion,
+            cipher: self.cipher.clone(),
+            decode_threads: self.decode_threads,
+            decode_window: self.decode_window,
+            producer: self.producer,
+            clock: self.clock.clone(),
+            key: self.key.clone(),
+            raw_key: self.raw_key.clone(),
+            merge: self.merge.clone(),
+            recovery: self.recovery.clone(),
+            health: self.health.clone(),
+            verifier: self.verifier.clone(),
+            latency: self.latency.clone(),
+            rotations: self.rotations.clone(),
+            failures: self.failures.clone(),
+            metrics: self.metrics.clone(),
+            cursor: self.cursor.clone(),
+            last_read: Mutex::default(),
+            subscribers: self.subscribers.clone(),
+            fork: self.fork,
+            lease: self.lease.clone(),
+            open: self.open.clone(),
+            slots: self.slots.clone(),
+            ephemeral: self.ephemeral.clone(),
+            rate_limit: self.rate_limit.clone(),
+            handle_limit: self.handle_limit.clone(),
+            handles: self.handles.clone(),
+            counters: self.counters.clone(),
+            topics: self.topics.clone(),
+            phantom: PhantomData,
+        }
+    }
+}
+
 impl<T> Wal<T>
 where
     T: Serialize + for<'a> Deserialize<'a>,
@@ -94,52 +570,483 @@ where
     ///
     /// # Arguments
     /// - `location`: The location on storage where to store WAL files
-    /// - `capacity`: The size of WAL on storage in MBs
+    /// - `capacity`: The size of WAL on storage in bytes, shared by every file but the one being
+    ///   reused
+    ///
+    /// The WAL already stored at `location`, if any, is opened and new logs are appended after
+    /// its newest logs, see [Wal::open]. Use [Wal::open] to require an existing WAL, and
+    /// [Wal::create] to start from an empty one.
+    ///
+    /// Fails with [WalError::AlreadyOpen] while another handle of this process still alive writes
+    /// to the WAL at `location`, see [WalBuilder::duplicate_guard], and with [WalError::Locked]
+    /// while another process does, see [WalBuilder::exclusive].
     ///
     /// # Examples
     /// The code below creates a WAL at location `/tmp/` for 2GB
     /// ```rust,ignore
     /// use walcraft::Wal;
-    /// let wal = Wal::new("./tmp/", 2_000);
+    /// let wal = Wal::new("./tmp/", 2 << 30);
     /// ```
     ///
     pub fn new(location: &str, capacity: usize) -> Result<Self, WalError> {
+        WalBuilder::new(location, capacity).build()
+    }
+
+    /// Open the WAL stored at `location`, failing with [WalError::File] when there is none
+    ///
+    /// The current file and write offset are taken from the meta file, which is checked against
+    /// the WAL files and corrected when it disagrees with them, see [Wal::recovery]. The format
+    /// and number of the WAL files are checked too, and logs are appended to the current file
+    /// after the newest logs, so no stored log is overwritten before its turn.
+    ///
+    /// # Examples
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/open_doc/").unwrap();
+    /// let wal: Wal<String> = Wal::create("./tmp/open_doc/", 500).unwrap();
+    /// wal.write("first".to_string());
+    /// drop(wal);
+    ///
+    /// let wal: Wal<String> = Wal::open("./tmp/open_doc/", 500).unwrap();
+    /// wal.write("second".to_string());
+    /// wal.flush().unwrap();
+    /// assert_eq!(wal.read().unwrap(), vec!["first", "second"]);
+    /// ```
+    ///
+    pub fn open(location: &str, capacity: usize) -> Result<Self, WalError> {
+        let builder = WalBuilder::new(location, capacity);
+        let recorded = Manifest::open(&builder.location, builder.manifest).load();
+        if recorded.is_err() && builder.layout.highest_id(&builder.location).is_none() {
+            return Err(WalError::File(format!("No WAL at {}", location)));
+        }
+        builder.build()
+    }
+
+    /// Create an empty WAL at `location`, removing the WAL stored there, see [Wal::recreate]
+    pub fn create(location: &str, capacity: usize) -> Result<Self, WalError> {
+        Self::recreate(WalBuilder::new(location, capacity))
+    }
+
+    /// Create an empty WAL held in memory, handy for tests and logs that needn't outlive the
+    /// process
+    ///
+    /// WAL files are kept in a [MemoryStorage] rather than on disk, and behave as they would on
+    /// disk: files are filled and reused in turn, logs are read in the order they were written,
+    /// and the oldest logs are dropped once the capacity is reached. The small files recording
+    /// the state of the WAL, such as the meta file, go to a private directory under the temporary
+    /// directory of the system, so WALs held in memory never share files, and the directory is
+    /// removed along with the last handle. Use [WalBuilder::in_memory] to configure the WAL.
+    ///
+    /// # Examples
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// let wal: Wal<String> = Wal::in_memory(500).unwrap();
+    /// wal.write("first".to_string());
+    /// wal.flush().unwrap();
+    /// assert_eq!(wal.read().unwrap(), vec!["first"]);
+    /// ```
+    ///
+    pub fn in_memory(capacity: usize) -> Result<Self, WalError> {
+        WalBuilder::in_memory(capacity).build()
+    }
+
+    /// Create a new WAL instance with the configuration of a [WalBuilder]
+    ///
+    /// # Examples
+    /// ```
+    /// use walcraft::{Wal, WalBuilder};
+    /// # std::fs::create_dir_all("./tmp/with_builder_doc/").unwrap();
+    /// let builder = WalBuilder::new("./tmp/with_builder_doc/", 500);
+    /// let wal: Wal<String> = Wal::with_builder(builder).unwrap();
+    /// ```
+    ///
+    pub fn with_builder(builder: WalBuilder<T>) -> Result<Self, WalError> {
+        Self::start(builder, None)
+    }
+
+    /// Remove the WAL at the location of the builder, if any, and create a new one in its place
+    ///
+    /// Every file the WAL wrote in the directory goes: the WAL files and their meta files,
+    /// manifests, indexes, key filters, blobs, topics, slots of [WalBuilder::slots], consumer
+    /// offsets and pending truncation, along with the WAL files moved to the cold location of
+    /// [WalBuilder::cold_storage]. Other files and directories are left in place. The directory
+    /// is created when missing. Nothing is removed while a handle of this process or another
+    /// process writes to the WAL or one of its slots, which fails with
+    /// [WalError::AlreadyOpen] or [WalError::Locked], and no other handle can open the WAL
+    /// before the new one is ready. Handy for tests starting from an empty WAL every time.
+    ///
+    /// # Examples
+    /// ```
+    /// use walcraft::{Wal, WalBuilder};
+    ///
+    /// let builder = WalBuilder::new("./tmp/recreate_doc/", 500);
+    /// let wal: Wal<String> = Wal::recreate(builder).unwrap();
+    /// wal.write("first".to_string());
+    /// drop(wal);
+    ///
+    /// let wal: Wal<String> = Wal::recreate(WalBuilder::new("./tmp/recreate_doc/", 500)).unwrap();
+    /// assert!(wal.read().unwrap().is_empty());
+    /// ```
+    ///
+    pub fn recreate(builder: WalBuilder<T>) -> Result<Self, WalError> {
+        if builder.read_only {
+            return Err(WalError::Unsupported(
+                "A read-only WAL can't be recreated".to_string(),
+            ));
+        }
+        let location = &builder.location;
+        std::fs::create_dir_all(location)
+            .map_err(|e| WalError::io(e, "Failed to create WAL directory"))?;
+        // held until the new WAL is open, so no handle writes to the files being removed
+        let open = OpenDirectory::register(location)?;
+        let lease = WriterLease::acquire(location)?;
+        // nor to the slots of a WAL written by several processes
+        let slots = Slots::lease_all(location)?;
+        let entries = std::fs::read_dir(location)
+            .map_err(|e| WalError::io(e, "Failed to read WAL directory"))?;
+        let layout = &builder.layout;
+        for entry in entries.flatten() {
+            let (path, name) = (entry.path(), entry.file_name());
+            let dir = path.is_dir();
+            // files of the user stored next to the WAL are left alone
+            if name == "lease" || !layout.owns(&name.to_string_lossy(), dir) {
+                continue;
+            }
+            let removed = match dir {
+                true => std::fs::remove_dir_all(&path),
+                false => std::fs::remove_file(&path),
+            };
+            removed.map_err(|e| WalError::io(e, "Failed to remove WAL file"))?;
+        }
+        let storage = &layout.storage;
+        if !storage.is_local() {
+            for path in storage.list(location) {
+                let name = path.file_name().map(|n| n.to_string_lossy());
+                if !name.is_some_and(|name| layout.owns(&name, false)) {
+                    continue;
+                }
+                storage
+                    .delete(&path)
+                    .map_err(|e| WalError::io(e, "Failed to remove WAL file"))?;
+            }
+        }
+        // whatever the number of files the old WAL had
+        for id in 1..=u8::MAX {
+            if let Some(cold) = builder.layout.cold_path(id) {
+                let _ = storage.delete(&cold);
+            }
+        }
+        drop(slots);
+        Self::start(builder, Some((open, lease)))
+    }
+
+    // Open the WAL, with the registration of its directory and its lease when already held
+    fn start(
+        mut builder: WalBuilder<T>,
+        mut held: Option<(OpenDirectory, WriterLease)>,
+    ) -> Result<Self, WalError> {
+        // a handle of a WAL shared by processes writes to a slot of its own
+        let slots = match builder.slots {
+            Some(count) if !builder.read_only => {
+                let (slots, location, open, lease) = Slots::lease(&builder.location, count)?;
+                builder.location = location;
+                held = Some((open, lease));
+                Some(slots)
+            }
+            Some(count) => Some(Slots::reading(&builder.location, count)),
+            None => None,
+        };
+        let capacity = builder.capacity;
         if capacity < 100 {
             return Err(WalError::Capacity(
                 "Capacity should be at least 100".to_string(),
             ));
         }
-        let location = PathBuf::from(location);
-        let (tx, rx) = mpsc::channel();
-        let buffer = Buffer::new();
+        let location = builder.location;
+        let mut layout = builder.layout;
+        let recorded = Manifest::open(&location, builder.manifest).load().ok();
+        // an existing WAL keeps the number of files it was written with unless told otherwise
+        layout.segments = builder
+            .segments
+            .or(recorded.as_ref().and_then(|m| m.segments))
+            // files beyond the default are still read when meta is lost
+            .or_else(|| {
+                layout
+                    .highest_id(&location)
+                    .filter(|id| *id > layout.segments)
+            })
+            .unwrap_or(layout.segments);
+        layout.check(&location)?;
+        // frames written in a format this build doesn't know are never misread
+        if let Some(format) = recorded.as_ref().and_then(|m| m.format) {
+            format.check(&location.join("meta"))?;
+        }
+        if builder.record_size.is_some() || builder.framing != Framing::LengthDelimited {
+            FormatVersion::check_files(&location, &layout)?;
+        }
+        // merged logs are written back as native frames
+        if builder.merge.is_some()
+            && (builder.key.is_none()
+                || builder.record_size.is_some()
+                || builder.framing != Framing::Native)
+        {
+            return Err(WalError::Unsupported(
+                "Logs are merged only in keyed mode with native frames".to_string(),
+            ));
+        }
+        // only the payloads of native frames are sealed, and blobs would be stored in the clear
+        if builder.cipher.is_some()
+            && (builder.record_size.is_some()
+                || builder.framing != Framing::Native
+                || builder.dedup_above.is_some())
+        {
+            return Err(WalError::Unsupported(
+                "Logs are encrypted only in native frames, without deduplication".to_string(),
+            ));
+        }
+        // a custom storage has no local files to write at an offset or to reserve space for
+        if !layout.storage.is_local() && (builder.positional_writes || builder.preallocate) {
+            return Err(WalError::Unsupported(
+                "Positional writes and preallocation need local WAL files".to_string(),
+            ));
+        }
+        WalReader::new(location.clone())
+            .layout(layout.clone())
+            .cipher(builder.cipher.clone())
+            .check_cipher()?;
+        let (open, lease) = match held {
+            Some((open, lease)) => (Some(open), Some(lease)),
+            None => {
+                let open = match builder.duplicate_guard && !builder.read_only {
+                    true => Some(OpenDirectory::register(&location)?),
+                    false => None,
+                };
+                // taken before anything is written, so another process never sees a half opened
+                // WAL
+                let lease = match builder.exclusive && !builder.read_only {
+                    true => Some(WriterLease::acquire(&location)?),
+                    false => None,
+                };
+                (open, lease)
+            }
+        };
+        let (open, lease) = (open.map(Arc::new), lease.map(Arc::new));
+        let mut validation = builder.validation;
+        validation.check_schema(&location, !builder.read_only)?;
+        builder
+            .codec
+            .check(&location, &layout, !builder.read_only)?;
+        for size in [builder.record_size, builder.max_entry_size]
+            .into_iter()
+            .flatten()
+        {
+            validation.limit_payload(size);
+        }
+        // a truncation interrupted by a crash is completed before anything else
+        if !builder.read_only {
+            Truncation::resume(&location, builder.manifest, &layout)?;
+        }
+        // don't trust a meta file that disagrees with the WAL files
+        let recovery = recovery::reconcile(
+            &location,
+            builder.record_size,
+            builder.framing,
+            builder.checksums,
+            builder.manifest,
+            &layout,
+            !builder.read_only,
+        )?;
+        let listeners = Listeners::new(builder.listeners);
+        if let Some(recovery) = &recovery {
+            listeners.recovery(recovery);
+        }
+        // continue numbering logs after the ones already stored
+        let next_lsn = WalReader::new(location.clone())
+            .layout(layout.clone())
+            .fixed(builder.record_size)
+            .framing(builder.framing)
+            .checksums(builder.checksums)
+            .last_lsn()
+            .map_or(0, |lsn| lsn.saturating_add(1));
+        let recent = builder
+            .recent_cache
+            .map(|(entries, bytes)| Recent::new(entries, bytes));
+        let memory = MemoryBudget::new(builder.memory_budget);
+        let buffer = Buffer::new(next_lsn, recent, memory.clone(), builder.backpressure);
         let lock = LockManager::new();
+        // only native frames have room for timestamps
+        let timestamps = (builder.timestamps || builder.strict_timestamps)
+            && builder.record_size.is_none()
+            && builder.framing == Framing::Native;
+        let strict_timestamps = timestamps && builder.strict_timestamps;
+        let clock = timestamps.then(|| Clock::new(strict_timestamps));
+
+        let (key, raw_key) = builder.key.unzip();
+        let raw_key = raw_key.map(|key| key(builder.codec));
+        let (merge, raw_merge) = builder.merge.unzip();
+        let raw_merge = raw_merge.map(|merge| merge(builder.codec));
+        let latency = Arc::new(Mutex::new(LatencyHistogram::default()));
+        let rotations = Arc::new(Mutex::new(RotationHistory::default()));
+        let failures = Failures::new(builder.on_error, listeners.clone());
+        let metrics = Arc::new(Metrics::default());
+        let consumers = Consumers::default();
+        let subscribers = Subscribers::default();
+        let read_lock = Arc::new(Mutex::new(Scratch::new(
+            builder.read_scratch_budget,
+            memory,
+        )));
 
         // start writer thread
         let props = WalWriterProps {
             buffer: buffer.clone(),
             location: location.clone(),
-            receiver: rx,
             lock: lock.clone(),
+            read_lock: read_lock.clone(),
             capacity,
+            positional_writes: builder.positional_writes,
+            preallocate: builder.preallocate,
+            wake_strategy: builder.wake_strategy,
+            sync_policy: builder.sync_policy,
+            compress_above: builder.compress_above,
+            compression_codec: builder.compression_codec,
+            cipher: builder.cipher.clone(),
+            max_expansion: builder.max_expansion,
+            record_size: builder.record_size,
+            framing: builder.framing,
+            checksums: builder.checksums,
+            verify_on_rotation: builder.verify_on_rotation,
+            segment_index: builder.segment_index,
+            manifest: builder.manifest,
+            on_evict: builder.on_evict,
+            on_seal: builder.on_seal,
+            failures: failures.clone(),
+            metrics: metrics.clone(),
+            listeners,
+            checkpointer: builder.checkpointer,
+            archiver: builder.archiver,
+            capacity_warnings: builder.capacity_warnings,
+            consumers: consumers.clone(),
+            key: raw_key.clone(),
+            merge: raw_merge,
+            compaction_threshold: builder.compaction_threshold,
+            latency: latency.clone(),
+            rotations: rotations.clone(),
+            dedup_above: builder.dedup_above,
+            segment_window: builder.segment_window,
+            segment_size: builder.segment_size,
+            retain_segments: builder.retain_segments,
+            retain_for: builder.retain_for,
+            timestamps,
+            strict_timestamps,
+            subscribers: subscribers.clone(),
+            #[cfg(feature = "chaos")]
+            chaos: builder.chaos,
+            #[cfg(test)]
+            faults: builder.faults.clone(),
+            layout: layout.clone(),
+            pacing: builder.pacing,
+            acks: Acks::new(builder.on_ack),
+            slots: slots.clone(),
         };
-        let writer = WalWriter::new(props)?;
-        let writer = std::thread::spawn(move || writer.run()).thread().clone();
+        // nothing is written in read-only mode, so there is no writer
+        let (tx, rx) = mpsc::channel();
+        let (sender, writer) = match builder.read_only {
+            true => (SignalSender::new(tx), None),
+            false => {
+                let restart = (builder.writer_restarts > 0)
+                    .then(|| Restart::new(props.clone(), builder.writer_restarts));
+                let writer = WalWriter::new(props)?;
+                // topics are written by the thread of their WAL
+                let (sender, handle) = match builder.shared {
+                    Some((sender, handle)) => (sender.topic(), handle.topic()),
+                    None => (
+                        SignalSender::new(tx),
+                        WriterHandle::spawn(builder.spawner.as_ref(), move || topic::run(rx))?,
+                    ),
+                };
+                sender.attach(writer, handle.clone(), restart)?;
+                (sender, Some(handle))
+            }
+        };
+
+        let health = scrub::health(&location, &layout, builder.scrub_interval);
+        let verifier = Verifier::start(&location, &layout, builder.open_verification, &health);
+
+        let fork = ForkGuard::new(builder.fork_behavior);
+        let handles = HandleRegistry::default();
+        let shutdown = Arc::new(Shutdown {
+            writer: writer.clone(),
+            fork,
+        });
 
         // return WAL handle
         Ok(Self {
             location,
             buffer,
-            capacity,
             writer,
-            sender: tx,
+            sender,
+            shutdown,
             lock,
-            read_lock: Arc::new(Mutex::new(())),
+            read_lock,
+            read_memory_cap: builder.read_memory_cap,
+            read_unflushed: builder.read_unflushed,
+            read_cache: builder
+                .read_cache
+                .map(|bytes| Arc::new(ReadCache::new(bytes))),
+            validation,
+            codec: builder.codec,
+            decoder: Decoder::new(builder.codec, builder.fallbacks),
+            record_size: builder.record_size,
+            framing: builder.framing,
+            checksums: builder.checksums,
+            layout,
+            max_expansion: builder.max_expansion,
+            cipher: builder.cipher,
+            decode_threads: builder.decode_threads,
+            decode_window: builder.decode_window,
+            producer: None,
+            clock,
+            key,
+            raw_key,
+            merge,
+            recovery,
+            health,
+            verifier,
+            latency,
+            rotations,
+            failures,
+            metrics,
+            cursor: Cursor::new(consumers),
+            last_read: Mutex::default(),
+            subscribers,
+            fork,
+            lease,
+            open,
+            slots,
+            ephemeral: builder.ephemeral,
+            rate_limit: builder
+                .rate_limit
+                .map(|limit| Arc::new(Limiter::new(limit))),
+            handle_limit: None,
+            counters: handles.counters(None),
+            handles,
+            topics: Arc::new(Topics::new(
+                capacity,
+                builder.topic_capacities,
+                builder.read_only,
+            )),
             phantom: Default::default(),
         })
     }
 
     /// Write an item to log
     ///
+    /// Returns the sequence number assigned to the log, or None if the log was not accepted, see
+    /// [Wal::try_write] for the reason. Sequence numbers grow with every log written to the WAL,
+    /// also across restarts, and are read back with [Wal::read_attributed].
+    ///
     /// # Example
     /// ```
     /// use serde::{Deserialize, Serialize};
@@ -155,27 +1062,111 @@ where
     /// let log2 = Log {id: 13, value: 0.3484};
     ///
     /// // create wal and add a log
-    /// let wal = Wal::new("./tmp/", 500).unwrap();
-    /// wal.write(log1);
-    /// wal.write(log2);
+    /// # std::fs::create_dir_all("./tmp/write_doc/").unwrap();
+    /// let wal = Wal::new("./tmp/write_doc/", 500).unwrap();
+    /// let first = wal.write(log1).unwrap();
+    /// let second = wal.write(log2).unwrap();
+    /// assert!(first < second);
+    /// ```
+    ///
+    pub fn write(&self, entry: T) -> Option<Lsn> {
+        self.try_write(&entry).ok()
+    }
+
+    /// Write an item to log, reporting why it was not accepted
+    ///
+    /// Returns the sequence number assigned to the log. Unlike [Wal::write], serialization
+    /// failures and violations of the rules set with [WalBuilder::strict] are returned as errors.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/try_write_doc/").unwrap();
+    /// let wal = Wal::new("./tmp/try_write_doc/", 500).unwrap();
+    /// let lsn = wal.try_write(&"log".to_string()).unwrap();
     /// ```
     ///
-    pub fn write(&self, entry: T) {
+    pub fn try_write(&self, entry: &T) -> Result<Lsn, WalError> {
+        self.check_writable()?;
         // Serializing entry to binary
-        let entry = match LogEntry::new(entry) {
-            None => return,
-            Some(e) => e,
-        };
-        // add log to buffer
-        let notify = self.buffer.add(entry);
-        // notify writer thread
-        if notify {
-            let _ = self.sender.send(());
+        let entry = self.encode(entry)?;
+        self.throttle(1)?;
+        self.enqueue_one(entry)
+    }
+
+    /// Write an item to log and wait until it's durable
+    ///
+    /// Returns the sequence number of the log once the writer thread has written it to its file
+    /// and synced the file to storage, whatever the [SyncPolicy], so the log survives a crash as
+    /// soon as the call returns. Logs accepted before it by other handles are synced along with
+    /// it. Fails like [Wal::try_write] when the log isn't accepted, and like [Wal::flush] when it
+    /// can't be made durable. See [WalBuilder::on_ack] to learn when logs are durable without
+    /// waiting for them.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/write_sync_doc/").unwrap();
+    /// let wal = Wal::new("./tmp/write_sync_doc/", 500).unwrap();
+    /// let lsn = wal.write_sync(&"payment captured".to_string()).unwrap();
+    /// println!("log {} is on disk", lsn);
+    /// ```
+    ///
+    pub fn write_sync(&self, entry: &T) -> Result<Lsn, WalError> {
+        let lsn = self.try_write(entry)?;
+        self.flush()?;
+        Ok(lsn)
+    }
+
+    /// Write an item to log along with `tag`, a small byte string such as the id of the entity
+    /// the log is about
+    ///
+    /// The tag is stored in the frame ahead of the payload, so [Wal::read_filtered] and
+    /// [Wal::read_by_tag] pick logs by tag without deserializing the others. Logs written without
+    /// a tag have none.
+    ///
+    /// Fails with [Rejection::TagTooLarge] for tags longer than [format::MAX_TAG_BYTES], and with
+    /// [WalError::Unsupported] with [WalBuilder::fixed_record_size] or
+    /// [Framing::LengthDelimited], whose frames have no room for tags.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/tagged_doc/").unwrap();
+    /// let wal = Wal::new("./tmp/tagged_doc/", 500).unwrap();
+    /// wal.try_write_tagged(b"account-7", &"deposit 10".to_string()).unwrap();
+    /// let logs = wal.read_by_tag(b"account-7").unwrap();
+    /// assert!(logs.contains(&"deposit 10".to_string()));
+    /// ```
+    ///
+    pub fn try_write_tagged(&self, tag: &[u8], entry: &T) -> Result<Lsn, WalError> {
+        if self.record_size.is_some() || self.framing != Framing::Native {
+            return Err(WalError::Unsupported(
+                "Only native frames record the tag of logs".to_string(),
+            ));
+        }
+        if tag.len() > format::MAX_TAG_BYTES {
+            self.counters.rejected(1);
+            return Err(WalError::Rejected(Rejection::TagTooLarge {
+                size: tag.len(),
+                limit: format::MAX_TAG_BYTES,
+            }));
         }
+        self.check_writable()?;
+        let mut entry = self.encode(entry)?;
+        entry.set_tag(Some(tag.to_vec()));
+        self.throttle(1)?;
+        self.enqueue_one(entry)
     }
 
     /// Batch write many logs in a single step
     ///
+    /// Logs that can't be serialized are skipped, and the others get a contiguous range of
+    /// sequence numbers, which is returned. None is returned when no log was accepted.
+    ///
     /// # Example
     /// ```
     /// use serde::{Deserialize, Serialize};
@@ -192,157 +1183,6593 @@ where
     /// let logs = vec![log1, log2];
     ///
     /// // create wal and add a log
-    /// let wal = Wal::new("./tmp/", 500).unwrap();
-    /// wal.batch_write(logs);
+    /// # std::fs::create_dir_all("./tmp/batch_write_doc/").unwrap();
+    /// let wal = Wal::new("./tmp/batch_write_doc/", 500).unwrap();
+    /// let lsn = wal.batch_write(logs).unwrap();
+    /// assert_eq!(lsn.end - lsn.start, 2);
     /// ```
     ///
-    pub fn batch_write(&self, entries: Vec<T>) {
+    pub fn batch_write(&self, entries: Vec<T>) -> Option<Range<Lsn>> {
+        self.check_writable().ok()?;
         // serialize to binary
         let mut data = Vec::with_capacity(entries.len());
         for entry in entries {
-            if let Some(d) = LogEntry::new(entry) {
+            if let Ok(d) = self.encode(&entry) {
                 data.push(d);
             }
         }
-        if data.is_empty() {
-            return;
-        }
-        // add logs to buffer
-        let notify = self.buffer.bulk_add(data);
-        // notify writer thread
-        if notify {
-            let _ = self.sender.send(());
+        if data.is_empty() || self.throttle(data.len() as u64).is_err() {
+            return None;
         }
+        self.enqueue(data).ok()
     }
 
-    /// Read all written logs
-    //  ToDo: update this method as below and add an `iter()` method
-    //  1. This an also be changed to read last 'x' amount of logs
-    //     such as wal.read(10_000) read last 10k entries
-    //     The files shall be read in the reverse order of what they are written
-    //     This will best preserve the last 'x' logs
-    //     Also some logs shall come from the buffer as well?
-    //  2. Add iter() method that will provide an iterator over all items in array
-    //     `for item in wal.iter() {}`
-    //
-    pub fn read(&self) -> Result<Vec<T>, WalError> {
-        loop {
-            // acquire read lock
-            let _ = self.read_lock.lock();
-
-            // park writer thread
-            self.lock.request_to_stop();
-            while !self.lock.has_stopped() {
-                sleep(Duration::from_millis(1));
-            }
-
-            // read data
-            let reader = WalReader::new(self.location.clone());
-            let buffer = reader.read()?;
-            let mut data = Vec::with_capacity(buffer.len());
-            for item in buffer {
-                if let Some(d) = item.to_original() {
-                    data.push(d);
-                }
-            }
-            if data.len() > self.capacity {
-                let cutoff = data.len() - self.capacity;
-                data = data.split_off(cutoff);
-            }
+    /// Write many logs atomically with respect to other writers
+    ///
+    /// Unlike [Wal::batch_write], either all logs are accepted or none of them are. The logs are
+    /// added to the buffer in a single step, so they receive a contiguous range of sequence numbers
+    /// and are never interleaved with logs written from other handles.
+    ///
+    /// # Example
+    /// ```
+    /// use serde::{Deserialize, Serialize};
+    /// use walcraft::Wal;
+    ///
+    /// // Log to write
+    /// #[derive(Serialize, Deserialize)]
+    /// struct Log {
+    ///     id: usize,
+    ///     value: f64
+    /// }
+    /// let logs = [Log {id: 12, value: 5.6234}, Log {id: 13, value: 0.3484}];
+    ///
+    /// // create wal and add the logs
+    /// # std::fs::create_dir_all("./tmp/try_write_all_doc/").unwrap();
+    /// let wal = Wal::new("./tmp/try_write_all_doc/", 500).unwrap();
+    /// let lsn = wal.try_write_all(&logs).unwrap();
+    /// assert_eq!(lsn.end - lsn.start, 2);
+    /// ```
+    ///
+    pub fn try_write_all(&self, entries: &[T]) -> Result<Range<Lsn>, WalError> {
+        self.check_writable()?;
+        // serialize to binary, bailing out before anything reaches the buffer
+        let data = entries
+            .iter()
+            .map(|entry| self.encode(entry))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.throttle(data.len() as u64)?;
+        self.enqueue(data)
+    }
+
+    /// Write the logs added to the transaction by `f` as an atomic batch
+    ///
+    /// Like [Wal::try_write_all], the logs get a contiguous range of sequence numbers. In addition,
+    /// every log of the batch but the last is marked in its frame, so a batch cut short by a crash
+    /// is skipped when reading: after recovery, either all logs of the batch appear or none does.
+    /// Nothing is written when adding a log to the transaction fails. Logs written to several
+    /// streams with [Transaction::stream] make up a single batch, so they all appear or none does.
+    /// Not supported with [WalBuilder::fixed_record_size] or [Framing::LengthDelimited], whose
+    /// frames can't be marked.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/atomic_doc/").unwrap();
+    /// let wal = Wal::new("./tmp/atomic_doc/", 500).unwrap();
+    /// let lsn = wal
+    ///     .atomic(|txn| {
+    ///         txn.write("debit".to_string());
+    ///         txn.write("credit".to_string());
+    ///     })
+    ///     .unwrap();
+    /// assert_eq!(lsn.end - lsn.start, 2);
+    /// ```
+    ///
+    pub fn atomic<F>(&self, f: F) -> Result<Range<Lsn>, WalError>
+    where
+        F: FnOnce(&mut Transaction<T>),
+    {
+        self.check_atomic()?;
+        let mut txn = Transaction::new(self);
+        f(&mut txn);
+        self.enqueue_atomic(txn)
+    }
 
-            // start writer thread
-            self.lock.start();
-            self.writer.unpark();
+    /// Start a transaction, whose logs are staged in memory until [Transaction::commit] writes
+    /// them as an atomic batch
+    ///
+    /// Like [Wal::atomic], the logs get a contiguous range of sequence numbers, and after a crash
+    /// either all logs of the batch appear or none does. In addition, the commit waits until the
+    /// logs are synced to storage. Dropping the transaction discards its logs. Committing fails
+    /// with [WalError::Unsupported] with [WalBuilder::fixed_record_size] or
+    /// [Framing::LengthDelimited].
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/transaction_doc/").unwrap();
+    /// let wal = Wal::new("./tmp/transaction_doc/", 500).unwrap();
+    /// let mut txn = wal.transaction();
+    /// txn.write("debit".to_string());
+    /// txn.write("credit".to_string());
+    /// let lsn = txn.commit().unwrap();
+    /// assert_eq!(lsn.end - lsn.start, 2);
+    /// ```
+    ///
+    pub fn transaction(&self) -> Transaction<'_, T> {
+        Transaction::new(self)
+    }
 
-            // return data
-            break Ok(data);
+    // fail unless the WAL can take atomic batches
+    fn check_atomic(&self) -> Result<(), WalError> {
+        self.check_writable()?;
+        if self.record_size.is_some() || self.framing == Framing::LengthDelimited {
+            return Err(WalError::Unsupported(
+                "Atomic batches need the native frame format".to_string(),
+            ));
         }
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::path::Path;
-    use std::time::Duration;
+    // add the logs of a transaction to the buffer as an atomic batch
+    fn enqueue_atomic(&self, txn: Transaction<T>) -> Result<Range<Lsn>, WalError> {
+        let data = txn.finish()?;
+        self.throttle(data.len() as u64)?;
+        // the whole batch is drained and written to the same file in one go
+        self.enqueue(data)
+    }
 
-    #[derive(Serialize, Deserialize, Debug)]
-    struct Item {
-        id: u16,
+    /// A sink adding the logs fed to it in batches, for a task writing many small logs
+    ///
+    /// See [WalSink]. Like a clone, the sink writes to the same WAL.
+    pub fn sink(&self) -> WalSink<T> {
+        WalSink::new(self.clone())
     }
 
-    fn clear_storage() {
-        let mut paths = Vec::new();
-        paths.push("./tmp/meta".to_string());
-        for i in 1..6 {
-            paths.push(format!("./tmp/wal_{}", i));
-        }
-        for path in paths {
-            if Path::new(&path).exists() {
-                std::fs::remove_file(path).expect("Failed to delete old file");
+    /// Write every log received from `receiver` until all its senders are gone, returning the
+    /// number of written logs
+    ///
+    /// Runs on the calling thread, so pipelines built on channels are made durable by draining
+    /// their receiver on a thread of their own. Logs are written in batches of the logs ready
+    /// when the previous batch was written, up to 1024: a batch holds a single log under a light
+    /// load, and grows with the load. Stops at the first log that can't be written, returning
+    /// the error once the logs received before it are written.
+    ///
+    /// # Example
+    /// ```
+    /// use std::sync::mpsc;
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/drain_channel_doc/").unwrap();
+    /// let wal: Wal<String> = Wal::new("./tmp/drain_channel_doc/", 500).unwrap();
+    /// let (tx, rx) = mpsc::channel();
+    /// let drain = {
+    ///     let wal = wal.clone();
+    ///     std::thread::spawn(move || wal.drain_channel(rx))
+    /// };
+    /// for i in 0..10 {
+    ///     tx.send(format!("request {}", i)).unwrap();
+    /// }
+    /// drop(tx);
+    /// assert_eq!(drain.join().unwrap().unwrap(), 10);
+    /// ```
+    ///
+    pub fn drain_channel<R>(&self, receiver: R) -> Result<u64, WalError>
+    where
+        R: ChannelReceiver<T>,
+    {
+        let mut sink = self
+            .sink()
+            .max_logs(drain::MAX_BATCH)
+            .max_delay(Duration::MAX);
+        let mut written = 0;
+        while let Some(log) = receiver.recv_log() {
+            sink.feed(&log)?;
+            written += 1;
+            // take whatever else is ready, so the batch grows with the load
+            while sink.pending() > 0 {
+                match receiver.try_recv_log() {
+                    Some(log) => sink.feed(&log)?,
+                    None => break,
+                }
+                written += 1;
             }
+            sink.flush()?;
         }
+        Ok(written)
     }
 
-    #[test]
-    fn simple_write() {
-        clear_storage();
-        let wal = Wal::new("./tmp/", 10_000).unwrap();
-        for i in 0..1000 {
-            let item = Item { id: i };
-            wal.write(item);
+    /// Export all logs to a writer of text lines, rendering each log with `render`
+    ///
+    /// Logs are written from the oldest to the newest, one per line, and are read as
+    /// [Wal::read_bounded] does, so exporting a WAL larger than the available memory is fine.
+    /// Exporting stops at the first log failing to render or to be written. Returns the number of
+    /// exported logs.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/export_lines_doc/").unwrap();
+    /// let wal = Wal::<(u32, String)>::new("./tmp/export_lines_doc/", 500).unwrap();
+    /// let mut csv = Vec::new();
+    /// wal.export_lines(&mut csv, |(id, value)| Ok::<_, String>(format!("{},{}", id, value)))
+    ///     .unwrap();
+    /// ```
+    ///
+    pub fn export_lines<W, F, E>(&self, mut writer: W, mut render: F) -> Result<u64, WalError>
+    where
+        W: Write,
+        F: FnMut(&T) -> Result<String, E>,
+        E: std::fmt::Display,
+    {
+        let mut exported = 0;
+        for log in self.read_bounded()? {
+            let line = render(&log).map_err(|e| {
+                WalError::Serialization(format!("Log {} of the export: {}", exported + 1, e))
+            })?;
+            writeln!(writer, "{}", line)
+                .map_err(|e| WalError::io(e, "Failed to write the export"))?;
+            exported += 1;
         }
-        // allow some time for WalWriter to work
-        sleep(Duration::from_secs(2));
-        // check that log file exists
-        let metadata = std::fs::metadata("./tmp/wal_1").expect("Failed to read file");
-        assert!(metadata.len() > 5000); // at least 5KB of data is added
+        writer
+            .flush()
+            .map_err(|e| WalError::io(e, "Failed to write the export"))?;
+        Ok(exported)
     }
 
-    #[test]
-    fn multiple_files() {
-        // clear existing files
-        clear_storage();
-        // create a new wal object
-        let wal = Wal::new("./tmp/", 100).unwrap();
-        // This shall be dumped to first file
-        let dump = (1..=30)
-            .into_iter()
-            .map(|i| Item { id: i })
-            .collect::<Vec<_>>();
-        wal.batch_write(dump);
-        sleep(Duration::from_millis(100));
-        // This shall be dumped to second file
-        let dump = (40..=45)
-            .into_iter()
-            .map(|i| Item { id: i })
-            .collect::<Vec<_>>();
-        wal.batch_write(dump);
-        // allow some time for WalWriter to work
-        sleep(Duration::from_secs(2));
-        // check that log file exists
-        let metadata1 = std::fs::metadata("./tmp/wal_1").expect("Failed to read file1");
-        assert!(metadata1.len() > 10); // at least 200 bytes of data is added
-        let metadata2 = std::fs::metadata("./tmp/wal_2").expect("Failed to read file2");
-        assert!(metadata2.len() > 10); // more than 10 bytes of data
+    /// Export all logs to a writer as newline-delimited JSON, one log per line
+    ///
+    /// Behaves as [Wal::export_lines], for archiving, diffing or loading logs into another
+    /// system. The export can be imported back with [Wal::import_json_lines].
+    #[cfg(feature = "json-export")]
+    pub fn export_json_lines<W: Write>(&self, writer: W) -> Result<u64, WalError> {
+        self.export_lines(writer, serde_json::to_string)
     }
 
-    #[test]
-    fn read_after_write() {
-        clear_storage();
-        // create a new wal object
-        let wal = Wal::new("./tmp/", 1000).unwrap();
-        // This shall be dumped to first file
-        let dump = (1..=1234)
-            .into_iter()
-            .map(|i| Item { id: i })
-            .collect::<Vec<_>>();
-        wal.batch_write(dump);
+    /// Export all logs to the file at `path` as newline-delimited JSON, see
+    /// [Wal::export_json_lines]
+    ///
+    /// The file is created, or truncated if it exists, and synced once written.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/export_jsonl_doc/").unwrap();
+    /// let wal = Wal::<u32>::new("./tmp/export_jsonl_doc/", 500).unwrap();
+    /// wal.export_jsonl("./tmp/export.jsonl").unwrap();
+    /// ```
+    ///
+    #[cfg(feature = "json-export")]
+    pub fn export_jsonl(&self, path: impl AsRef<Path>) -> Result<u64, WalError> {
+        let path = path.as_ref();
+        let file = std::fs::File::create(path)
+            .map_err(|e| WalError::io_at(e, "Failed to create the export", path))?;
+        let mut writer = std::io::BufWriter::new(file);
+        let exported = self.export_json_lines(&mut writer)?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .and_then(|file| file.sync_all())
+            .map_err(|e| WalError::io_at(e, "Failed to sync the export", path))?;
+        Ok(exported)
+    }
+
+    /// Import logs from a reader of text lines, such as a CSV export, parsing each line with `parse`
+    ///
+    /// Lines are written in order, in batches getting contiguous ranges of sequence numbers. Blank
+    /// lines are skipped. Importing stops at the first line failing to parse or to be written,
+    /// reported along with its line number, and the logs of the lines before it are kept. With
+    /// [WalBuilder::memory_budget], batches wait for the writer thread to make room for them.
+    /// Returns the number of imported logs.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// let csv = "1,5.6234\n2,0.3484\n";
+    /// # std::fs::create_dir_all("./tmp/import_lines_doc/").unwrap();
+    /// let wal = Wal::new("./tmp/import_lines_doc/", 500).unwrap();
+    /// let imported = wal
+    ///     .import_lines(csv.as_bytes(), |line| {
+    ///         let (id, value) = line.split_once(',').ok_or("missing column")?;
+    ///         Ok::<_, String>((id.parse::<u32>().map_err(|e| e.to_string())?, value.to_string()))
+    ///     })
+    ///     .unwrap();
+    /// assert_eq!(imported, 2);
+    /// ```
+    ///
+    pub fn import_lines<R, F, E>(&self, reader: R, mut parse: F) -> Result<u64, WalError>
+    where
+        R: BufRead,
+        F: FnMut(&str) -> Result<T, E>,
+        E: std::fmt::Display,
+    {
+        // logs written to the buffer in one step
+        const BATCH: usize = 1024;
+        self.check_writable()?;
+        let mut imported = 0;
+        let mut batch = Vec::with_capacity(BATCH);
+        // line number of the first log of the batch
+        let mut first = 1;
+        let mut lines = reader.lines().enumerate().peekable();
+        while let Some((i, line)) = lines.next() {
+            let line =
+                line.map_err(|e| WalError::io(e, &format!("Failed to read line {}", i + 1)))?;
+            if batch.is_empty() {
+                first = i + 1;
+            }
+            if !line.trim().is_empty() {
+                match parse(&line) {
+                    Ok(log) => batch.push(log),
+                    Err(e) => {
+                        // the lines before are imported all the same
+                        self.import_batch(&batch, first)?;
+                        return Err(WalError::Serialization(format!("Line {}: {}", i + 1, e)));
+                    }
+                }
+            }
+            if batch.len() == BATCH || (lines.peek().is_none() && !batch.is_empty()) {
+                imported += self.import_batch(&batch, first)?;
+                batch.clear();
+            }
+        }
+        Ok(imported)
+    }
+
+    /// Import logs from a reader of newline-delimited JSON, one log per line
+    ///
+    /// Behaves as [Wal::import_lines], for seeding a WAL from the export of another system.
+    ///
+    /// # Example
+    /// ```
+    /// use serde::{Deserialize, Serialize};
+    /// use walcraft::Wal;
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct Log {
+    ///     id: usize,
+    ///     value: f64
+    /// }
+    ///
+    /// let export = r#"{"id": 1, "value": 5.6234}
+    /// {"id": 2, "value": 0.3484}"#;
+    /// # std::fs::create_dir_all("./tmp/import_json_lines_doc/").unwrap();
+    /// let wal = Wal::<Log>::new("./tmp/import_json_lines_doc/", 500).unwrap();
+    /// assert_eq!(wal.import_json_lines(export.as_bytes()).unwrap(), 2);
+    /// ```
+    ///
+    #[cfg(feature = "json-import")]
+    pub fn import_json_lines<R: BufRead>(&self, reader: R) -> Result<u64, WalError>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        self.import_lines(reader, |line| serde_json::from_str(line))
+    }
+
+    /// Import logs from the file at `path` of newline-delimited JSON, see [Wal::import_json_lines]
+    #[cfg(feature = "json-import")]
+    pub fn import_jsonl(&self, path: impl AsRef<Path>) -> Result<u64, WalError> {
+        let path = path.as_ref();
+        let file = std::fs::File::open(path)
+            .map_err(|e| WalError::io_at(e, "Failed to open the export", path))?;
+        self.import_json_lines(std::io::BufReader::new(file))
+    }
+
+    // write a batch of imported logs whose first one was read from line `first`
+    fn import_batch(&self, batch: &[T], first: usize) -> Result<u64, WalError> {
+        loop {
+            match self.try_write_all(batch) {
+                Ok(range) => return Ok(range.end - range.start),
+                // the writer thread makes room as it drains the buffer
+                Err(WalError::Capacity(_)) if self.buffer.memory_usage().buffer > 0 => {
+                    self.buffer.wait_for_drain(Duration::from_millis(100))
+                }
+                // a batch larger than the whole budget is written in halves
+                Err(WalError::Capacity(_)) if batch.len() > 1 => {
+                    let (head, tail) = batch.split_at(batch.len() / 2);
+                    let imported = self.import_batch(head, first)?;
+                    return Ok(imported + self.import_batch(tail, first)?);
+                }
+                Err(WalError::Serialization(e)) => {
+                    return Err(WalError::Serialization(format!(
+                        "Lines from {}: {}",
+                        first, e
+                    )))
+                }
+                Err(WalError::Capacity(e)) => {
+                    return Err(WalError::Capacity(format!("Lines from {}: {}", first, e)))
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Read all written logs
+    ///
+    /// Logs are returned in the order they were written, which is the order of their sequence
+    /// numbers, across file boundaries and reused files. Logs accepted but not yet written to a
+    /// file by the writer thread are included after the ones already in files, see
+    /// [WalBuilder::read_unflushed]. To read only the newest logs, [Wal::read_last] stops at the
+    /// files holding them, and [Wal::iter] reads logs lazily instead of collecting them all.
+    ///
+    /// Logs are deserialized on the threads configured with [WalBuilder::parallel_decode]. With
+    /// the `mmap` feature, frames are parsed from memory maps of the files instead of copies of
+    /// the files on the heap.
+    pub fn read(&self) -> Result<Vec<T>, WalError>
+    where
+        T: Send,
+    {
+        let buffer = self.read_all()?;
+        Ok(self.decode_all(buffer))
+    }
+
+    // deserialize logs on the threads of [WalBuilder::parallel_decode], keeping their order
+    fn decode_all(&self, entries: Vec<LogEntry>) -> Vec<T>
+    where
+        T: Send,
+    {
+        decode::decode(
+            entries,
+            &self.decoder,
+            self.decode_threads,
+            self.decode_window,
+        )
+    }
+
+    /// Iterate over all written logs, reading them lazily in the order they were written
+    ///
+    /// Unlike [Wal::read], logs aren't collected up front: files are read one at a time as the
+    /// iterator advances, and logs are deserialized one by one, so iterating a WAL takes the
+    /// memory of its largest file rather than of all its logs. The iterator sees the files as
+    /// they were when it was created and doesn't hold up the writer thread or other reads in
+    /// between. Logs accepted but not yet written to a file are yielded last, see
+    /// [WalBuilder::read_unflushed].
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::WalBuilder;
+    ///
+    /// # std::fs::create_dir_all("./tmp/iter_doc/").unwrap();
+    /// let wal = WalBuilder::<u32>::new("./tmp/iter_doc/", 500).build().unwrap();
+    /// for log in wal.iter().unwrap() {
+    ///     println!("{}", log.unwrap());
+    /// }
+    /// ```
+    ///
+    pub fn iter(&self) -> Result<WalIter<T>, WalError> {
+        self.iter_from(0)
+    }
+
+    /// Iterate over the logs from the sequence number `lsn` on, reading them lazily like
+    /// [Wal::iter]
+    ///
+    /// Like [Wal::read_from], files only holding older logs are skipped without being read.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/iter_from_doc/").unwrap();
+    /// let wal: Wal<String> = Wal::new("./tmp/iter_from_doc/", 500).unwrap();
+    /// let lsn = wal.try_write(&"deployed v2".to_string()).unwrap();
+    /// let mut since_deploy = wal.iter_from(lsn).unwrap();
+    /// assert_eq!(since_deploy.next().unwrap().unwrap(), "deployed v2");
+    /// ```
+    ///
+    pub fn iter_from(&self, lsn: Lsn) -> Result<WalIter<T>, WalError> {
+        self.fork.check(false)?;
+        let _span = span!("walcraft.read");
+        self.metrics.read();
+        self.verifier.wait_for_reads();
+        if self.fork.forked() {
+            return WalIter::new(self.reader(), None, Vec::new(), self.decoder.clone(), lsn);
+        }
+        let reader = {
+            let _scratch = match Reading::held(&self.read_lock) {
+                true => None,
+                false => Some(self.read_lock.lock().unwrap_or_else(|e| e.into_inner())),
+            };
+            self.check_writer()?;
+            self.take_snapshot()?
+        };
+        let pending = reader.pending();
+        let read_lock = Some(self.read_lock.clone());
+        WalIter::new(reader, read_lock, pending, self.decoder.clone(), lsn)
+    }
+
+    /// Read all written logs like [Wal::read], sharing them with the handles reading them again
+    /// until they change
+    ///
+    /// With [WalBuilder::read_cache], the logs of the last read are kept and returned again
+    /// without reading files or deserializing logs, as long as no log was written, truncated,
+    /// compacted or dropped since. Handles of a WAL share its cache. Without it, every call
+    /// reads the WAL.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::WalBuilder;
+    ///
+    /// # std::fs::create_dir_all("./tmp/read_cached_doc/").unwrap();
+    /// let wal = WalBuilder::<u64>::new("./tmp/read_cached_doc/", 500)
+    ///     .read_cache(1 << 20)
+    ///     .build()
+    ///     .unwrap();
+    /// let logs = wal.read_cached().unwrap();
+    /// // nothing changed, so the same logs are returned
+    /// assert!(std::sync::Arc::ptr_eq(&logs, &wal.read_cached().unwrap()));
+    /// ```
+    ///
+    pub fn read_cached(&self) -> Result<Arc<Vec<T>>, WalError>
+    where
+        T: Send + Sync + 'static,
+    {
+        let cache = match &self.read_cache {
+            Some(cache) => cache,
+            None => return self.read().map(Arc::new),
+        };
+        self.read_snapshot(|reader| {
+            let version = reader.version();
+            if let Some(logs) = version.as_ref().and_then(|v| cache.get(v)) {
+                return Ok(logs);
+            }
+            let mut buffer = reader.read()?;
+            buffer.extend(reader.pending());
+            let bytes = buffer.iter().map(|e| e.size()).sum();
+            let logs = Arc::new(decode::decode(
+                buffer,
+                &self.decoder,
+                self.decode_threads,
+                self.decode_window,
+            ));
+            if let Some(version) = version {
+                cache.store(version, bytes, logs.clone());
+            }
+            Ok(logs)
+        })
+    }
+
+    /// Read all written logs like [Wal::read], deserializing only the fields of a projection `P`
+    ///
+    /// Replays needing a couple of fields of large logs skip deserializing and allocating the
+    /// others. With JSON, MessagePack or self-describing logs, `P` can hold any subset of the
+    /// fields of `T`, as unknown fields are ignored. Bincode stores fields in order without their
+    /// names, so `P` must then hold the leading fields of `T`, in the same order. Logs that don't
+    /// deserialize as `P` are skipped.
+    ///
+    /// # Example
+    /// ```
+    /// use serde::{Deserialize, Serialize};
+    /// use walcraft::Wal;
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct Order {
+    ///     id: u64,
+    ///     lines: Vec<String>,
+    /// }
+    ///
+    /// #[derive(Deserialize)]
+    /// struct OrderId {
+    ///     id: u64,
+    /// }
+    ///
+    /// # std::fs::create_dir_all("./tmp/read_projected_doc/").unwrap();
+    /// let wal: Wal<Order> = Wal::new("./tmp/read_projected_doc/", 500).unwrap();
+    /// let ids: Vec<u64> = wal
+    ///     .read_projected::<OrderId>()
+    ///     .unwrap()
+    ///     .into_iter()
+    ///     .map(|order| order.id)
+    ///     .collect();
+    /// ```
+    ///
+    pub fn read_projected<P>(&self) -> Result<Vec<P>, WalError>
+    where
+        P: for<'a> Deserialize<'a> + Send,
+    {
+        let buffer = self.read_all()?;
+        Ok(decode::decode(
+            buffer,
+            &Decoder::new(self.codec, Vec::new()),
+            self.decode_threads,
+            self.decode_window,
+        ))
+    }
+
+    /// Read all written logs like [Wal::read], along with the number of logs skipped as they
+    /// couldn't be deserialized
+    ///
+    /// The errors of the first skipped logs are kept, so monitoring can report why logs no longer
+    /// fit their type.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/read_reported_doc/").unwrap();
+    /// let wal: Wal<String> = Wal::new("./tmp/read_reported_doc/", 500).unwrap();
+    /// let report = wal.read_reported().unwrap();
+    /// if report.skipped > 0 {
+    ///     eprintln!("{} logs skipped, first errors: {:?}", report.skipped, report.errors);
+    /// }
+    /// ```
+    ///
+    pub fn read_reported(&self) -> Result<DecodeReport<T>, WalError>
+    where
+        T: Send,
+    {
+        let buffer = self.read_all()?;
+        Ok(decode::decode_reported(
+            buffer,
+            &self.decoder,
+            self.decode_threads,
+            self.decode_window,
+        ))
+    }
+
+    /// Write an already serialized log, such as an encoded protobuf message
+    ///
+    /// The payload is stored as is, so it must be readable by [Wal::read] only if it is the
+    /// serialized form of a `T`. Meant for WALs using [Framing::LengthDelimited], whose files
+    /// then hold the payloads exactly as written. Returns the sequence number assigned to the log.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::{Framing, WalBuilder};
+    ///
+    /// # std::fs::create_dir_all("./tmp/raw_doc/").unwrap();
+    /// let wal = WalBuilder::<Vec<u8>>::new("./tmp/raw_doc/", 500)
+    ///     .framing(Framing::LengthDelimited)
+    ///     .build()
+    ///     .unwrap();
+    /// wal.try_write_raw(&[0x08, 0x96, 0x01]).unwrap();
+    /// ```
+    ///
+    pub fn try_write_raw(&self, payload: &[u8]) -> Result<Lsn, WalError> {
+        self.write_raw_vec(payload.to_vec())
+    }
+
+    // write a payload the caller owns, without copying it
+    pub(crate) fn write_raw_vec(&self, payload: Vec<u8>) -> Result<Lsn, WalError> {
+        self.check_writable()?;
+        let key = self.raw_key.as_ref().and_then(|key| key(&payload));
+        let mut log = LogEntry::from_vec(payload, 0);
+        self.validation
+            .check_size(&log)
+            .inspect_err(|_| self.counters.rejected(1))?;
+        log.set_producer(self.producer);
+        self.stamp(&mut log);
+        log.set_key(key);
+        self.throttle(1)?;
+        self.enqueue_one(log)
+    }
+
+    /// Read the payloads of all written logs without deserializing them, in the same order as
+    /// [Wal::read]
+    pub fn read_raw(&self) -> Result<Vec<Vec<u8>>, WalError> {
+        let buffer = self.read_all()?;
+        Ok(buffer
+            .into_iter()
+            .map(|entry| entry.into_payload())
+            .collect())
+    }
+
+    /// Read the payloads of all written logs like [Wal::read_raw], along with their sequence
+    /// number
+    pub fn read_raw_numbered(&self) -> Result<Vec<(Lsn, Vec<u8>)>, WalError> {
+        let buffer = self.read_all()?;
+        Ok(buffer
+            .into_iter()
+            .map(|entry| (entry.lsn(), entry.into_payload()))
+            .collect())
+    }
+
+    /// Read the payloads of all written logs like [Wal::read_raw], without copying them
+    ///
+    /// The content of every file is read into a single buffer shared by its payloads, so they
+    /// can be handed over to network stacks as is. Compressed payloads, payloads stored as blobs
+    /// and logs not yet written to a file are held in buffers of their own. Available with the
+    /// `bytes` feature.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/read_raw_bytes_doc/").unwrap();
+    /// let wal: Wal<String> = Wal::new("./tmp/read_raw_bytes_doc/", 500).unwrap();
+    /// for payload in wal.read_raw_bytes().unwrap() {
+    ///     // e.g. forward `payload` to a replica without copying it
+    /// }
+    /// ```
+    ///
+    #[cfg(feature = "bytes")]
+    pub fn read_raw_bytes(&self) -> Result<Vec<bytes::Bytes>, WalError> {
+        let payloads = self.read_snapshot(|reader| {
+            let mut payloads = reader.read_bytes()?;
+            let pending = reader.pending().into_iter();
+            payloads.extend(pending.map(|e| (e.lsn(), bytes::Bytes::from(e.into_payload()))));
+            Ok(payloads)
+        })?;
+        Ok(payloads.into_iter().map(|(_, payload)| payload).collect())
+    }
+
+    /// Read all written logs without knowing their type, in the same order as [Wal::read]
+    ///
+    /// Only available for WALs storing self-describing logs with
+    /// [WalBuilder::self_describing]. Logs that aren't self-describing are skipped.
+    #[cfg(feature = "self-describing")]
+    pub fn read_dynamic(&self) -> Result<Vec<DynamicLog>, WalError> {
+        if !matches!(self.codec, Codec::Tagged { .. }) {
+            return Err(WalError::Unsupported(
+                "Logs are not self-describing, see `WalBuilder::self_describing`".to_string(),
+            ));
+        }
+        let buffer = self.read_all()?;
+        Ok(buffer.iter().filter_map(DynamicLog::decode).collect())
+    }
+
+    /// Read all written logs grouped by the WAL file they are stored in
+    ///
+    /// The files are returned from the oldest to the one currently being written, so consumers
+    /// can process and checkpoint the WAL file by file.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/read_segments_doc/").unwrap();
+    /// let wal: Wal<u32> = Wal::new("./tmp/read_segments_doc/", 500).unwrap();
+    /// for segment in wal.read_segments().unwrap() {
+    ///     println!("wal_{} has {} logs", segment.id, segment.entries.len());
+    /// }
+    /// ```
+    ///
+    pub fn read_segments(&self) -> Result<Vec<SegmentEntries<T>>, WalError>
+    where
+        T: Send,
+    {
+        let segments = self.read_snapshot(|reader| reader.read_segments())?;
+        Ok(segments
+            .into_iter()
+            .map(|segment| self.decode_segment(segment))
+            .collect())
+    }
+
+    /// Read the logs of a single WAL file, `wal_<id>`, along with metadata of the file
+    ///
+    /// Meant to examine the file covering a time window or named by a corruption report,
+    /// see [Health], without reading the whole WAL. Returns None when the file hasn't been
+    /// created yet or `id` is beyond the number of WAL files. Logs still in the buffer are left
+    /// out, so a flush makes sure the current file holds every log written so far.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/read_segment_doc/").unwrap();
+    /// let wal: Wal<u32> = Wal::new("./tmp/read_segment_doc/", 500).unwrap();
+    /// if let Some(segment) = wal.read_segment(1).unwrap() {
+    ///     println!("wal_1 holds logs {:?} in {} bytes", segment.lsns, segment.size);
+    /// }
+    /// ```
+    ///
+    pub fn read_segment(&self, id: u8) -> Result<Option<SegmentEntries<T>>, WalError>
+    where
+        T: Send,
+    {
+        let segment = self.read_snapshot(|reader| reader.read_segment(id))?;
+        Ok(segment.map(|segment| self.decode_segment(segment)))
+    }
+
+    fn decode_segment(&self, segment: SegmentData) -> SegmentEntries<T>
+    where
+        T: Send,
+    {
+        SegmentEntries {
+            id: segment.id,
+            path: segment.path,
+            size: segment.size,
+            active: segment.active,
+            lsns: segment.lsns,
+            digest: segment.digest,
+            entries: self.decode_all(segment.entries),
+        }
+    }
+
+    /// Digests of the WAL files sealed so far, oldest first
+    ///
+    /// Digests are recorded only when [WalBuilder::verify_on_rotation] is enabled. A digest with
+    /// `verified` set to false means the file didn't read back exactly what the writer wrote.
+    pub fn segment_digests(&self) -> Result<Vec<SegmentDigest>, WalError> {
+        self.read_snapshot(|reader| reader.segment_digests())
+    }
+
+    /// Check that the sealed files still hold what they held when they were sealed, and that
+    /// their links follow each other in the hash chain described by [SegmentSealed]
+    ///
+    /// Only files whose digest is recorded are checked, see [WalBuilder::verify_on_rotation].
+    /// Compaction rewrites sealed files and breaks the chain. Since the meta file could be
+    /// rewritten along with the files, the returned `head` should be compared with the link last
+    /// reported to [WalBuilder::on_seal] to detect changes to the chain itself.
+    pub fn verify_chain(&self) -> Result<ChainVerification, WalError> {
+        self.read_snapshot(chain::verify)
+    }
+
+    /// Correction made to the meta file when the WAL was opened, if it disagreed with the WAL
+    /// files
+    ///
+    /// The file holding the logs with the highest sequence numbers is taken as the current file,
+    /// instead of trusting a possibly stale meta file.
+    /// A damaged meta file, e.g. one failing its checksum, is rebuilt from the WAL files instead of
+    /// failing the open, see [Recovery::rebuilt].
+    pub fn recovery(&self) -> Option<&Recovery> {
+        self.recovery.as_ref()
+    }
+
+    /// Health of the WAL files as found by the scrubber enabled with [WalBuilder::scrub_interval]
+    /// and by [WalBuilder::verify_on_open]
+    ///
+    /// Reports no corruption when neither is enabled.
+    pub fn health(&self) -> Health {
+        self.health
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Last failure of the writer thread to store logs or metadata, None if it never failed
+    ///
+    /// Logs are accepted before the writer thread stores them, so a full disk or a revoked
+    /// permission can't fail the writes themselves. Every failure is also reported to
+    /// [WalBuilder::on_error]. Serialization failures are returned by [Wal::try_write].
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/last_error_doc/").unwrap();
+    /// let wal: Wal<u64> = Wal::new("./tmp/last_error_doc/", 500).unwrap();
+    /// if let Some(failure) = wal.last_error() {
+    ///     eprintln!("{:?} failed: {}", failure.operation, failure.message);
+    /// }
+    /// ```
+    ///
+    pub fn last_error(&self) -> Option<WriteFailure> {
+        self.failures.last()
+    }
+
+    /// Whether every sealed file was checked as requested by [WalBuilder::verify_on_open]
+    ///
+    /// Only false while [OpenVerification::Deferred] checks are running, in which case logs read
+    /// meanwhile may come from corrupted files unless reads wait for the check.
+    pub fn verified(&self) -> bool {
+        self.verifier.finished()
+    }
+
+    /// A handle recording `id` as the producer of every log it writes
+    ///
+    /// The id is stored in the frame of every log, taking [format::PRODUCER_BYTES] of storage,
+    /// and is reported by [Wal::read_attributed] and [Wal::producer_stats]. Like a clone, the new
+    /// handle writes to the same WAL. Fixed size records don't record the producer.
+    pub fn with_producer(&self, id: ProducerId) -> Self {
+        let mut wal = self.clone();
+        wal.producer = Some(id);
+        wal
+    }
+
+    /// A handle writing at most at the rate of `limit`, shared with its clones
+    ///
+    /// Gives a component its own share of the WAL, on top of the limit of the whole WAL set with
+    /// [WalBuilder::rate_limit]. Logs over either limit are rejected with [WalError::Throttled],
+    /// or dropped by [Wal::write] and [Wal::batch_write]. Like a clone, the new handle writes to
+    /// the same WAL.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::{RateLimit, Wal};
+    ///
+    /// # std::fs::create_dir_all("./tmp/with_rate_limit_doc/").unwrap();
+    /// let wal: Wal<String> = Wal::new("./tmp/with_rate_limit_doc/", 500).unwrap();
+    /// let audit = wal.with_rate_limit(RateLimit::new(100));
+    /// audit.try_write(&"login".to_string()).unwrap();
+    /// ```
+    ///
+    pub fn with_rate_limit(&self, limit: RateLimit) -> Self {
+        let mut wal = self.clone();
+        wal.handle_limit = Some(Arc::new(Limiter::new(limit)));
+        wal
+    }
+
+    /// Limit the disk bandwidth and CPU time of the writer thread with `pacing` from now on
+    ///
+    /// Replaces the pacing given to [WalBuilder::pacing] for all handles of the WAL, e.g. to
+    /// throttle the WAL while another workload needs the disk and lift the limits once it's
+    /// done. Bytes already written count against the new bandwidth. The writer applies the
+    /// pacing once it's done with its current pause, if any.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::{Pacing, Wal};
+    ///
+    /// # std::fs::create_dir_all("./tmp/set_pacing_doc/").unwrap();
+    /// let wal: Wal<String> = Wal::new("./tmp/set_pacing_doc/", 500).unwrap();
+    /// wal.set_pacing(Pacing::new().bytes_per_sec(1 << 20)).unwrap();
+    /// // unlimited again
+    /// wal.set_pacing(Pacing::new()).unwrap();
+    /// ```
+    pub fn set_pacing(&self, pacing: Pacing) -> Result<(), WalError> {
+        self.check_writable()?;
+        self.sender
+            .send(Signal::Pace(pacing))
+            .map_err(|_| WalError::WriterDead("Writer thread has stopped".to_string()))
+    }
+
+    /// A handle counting the logs it writes under `label`, shared with its clones
+    ///
+    /// Counters are reported by [Wal::handle_stats], so the components responsible for most of
+    /// the logs written to the WAL can be found. Handles labeled alike share their counters, and
+    /// the handles without a label are counted together. Like a clone, the new handle writes to
+    /// the same WAL.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/labeled_doc/").unwrap();
+    /// let wal: Wal<String> = Wal::new("./tmp/labeled_doc/", 500).unwrap();
+    /// let ingest = wal.labeled("ingest-worker-3");
+    /// ingest.try_write(&"event".to_string()).unwrap();
+    /// ```
+    pub fn labeled(&self, label: impl Into<String>) -> Self {
+        let mut wal = self.clone();
+        wal.counters = self.handles.counters(Some(&label.into()));
+        wal
+    }
+
+    /// A handle writing and reading logs of type `U`, e.g. while migrating logs to an enum
+    /// wrapping their old type
+    ///
+    /// The new handle shares the writer, buffer, files and limits of this one, so logs written
+    /// through both are ordered as usual. Nothing is converted: logs written as `T` are read
+    /// through the new handle only if they deserialize as `U`, and are otherwise skipped like any
+    /// corrupt log. Use [Wal::read_reported] to find them.
+    ///
+    /// Fails with [WalError::Unsupported] in keyed mode, where keys are computed from logs of type
+    /// `T`.
+    ///
+    /// # Example
+    /// ```
+    /// use serde::{Deserialize, Serialize};
+    /// use walcraft::Wal;
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// enum Event {
+    ///     Login(String),
+    ///     Logout(String),
+    /// }
+    ///
+    /// # std::fs::create_dir_all("./tmp/retype_doc/").unwrap();
+    /// let wal: Wal<String> = Wal::new("./tmp/retype_doc/", 500).unwrap();
+    /// let events = wal.retype::<Event>().unwrap();
+    /// events.try_write(&Event::Logout("bob".to_string())).unwrap();
+    /// ```
+    ///
+    pub fn retype<U>(&self) -> Result<Wal<U>, WalError>
+    where
+        U: Serialize + for<'a> Deserialize<'a>,
+    {
+        if self.key.is_some() {
+            return Err(WalError::Unsupported(
+                "Logs of a keyed WAL can't change type".to_string(),
+            ));
+        }
+        Ok(Wal {
+            location: self.location.clone(),
+            buffer: self.buffer.clone(),
+            sender: self.sender.clone(),
+            shutdown: self.shutdown.clone(),
+            lock: self.lock.clone(),
+            writer: self.writer.clone(),
+            read_lock: self.read_lock.clone(),
+            read_memory_cap: self.read_memory_cap,
+            read_unflushed: self.read_unflushed,
+            // logs of the new type are cached on their own
+            read_cache: self.read_cache.as_ref().map(|c| Arc::new(c.retyped())),
+            validation: self.validation.clone(),
+            codec: self.codec,
+            decoder: Decoder::new(self.codec, Vec::new()),
+            record_size: self.record_size,
+            framing: self.framing,
+            checksums: self.checksums,
+            layout: self.layout.clone(),
+            max_expansion: self.max_expansion,
+            cipher: self.cipher.clone(),
+            decode_threads: self.decode_threads,
+            decode_window: self.decode_window,
+            producer: self.producer,
+            clock: self.clock.clone(),
+            key: None,
+            raw_key: None,
+            merge: None,
+            recovery: self.recovery.clone(),
+            health: self.health.clone(),
+            verifier: self.verifier.clone(),
+            latency: self.latency.clone(),
+            rotations: self.rotations.clone(),
+            failures: self.failures.clone(),
+            metrics: self.metrics.clone(),
+            cursor: self.cursor.clone(),
+            last_read: Mutex::default(),
+            subscribers: self.subscribers.clone(),
+            fork: self.fork,
+            lease: self.lease.clone(),
+            open: self.open.clone(),
+            slots: self.slots.clone(),
+            ephemeral: self.ephemeral.clone(),
+            rate_limit: self.rate_limit.clone(),
+            handle_limit: self.handle_limit.clone(),
+            handles: self.handles.clone(),
+            counters: self.counters.clone(),
+            topics: Arc::new(self.topics.retype()),
+            phantom: PhantomData,
+        })
+    }
+
+    /// Handle of the topic `name`, a WAL of its own in the `topics/<name>` directory of this one
+    ///
+    /// Every topic has its own files, meta and capacity, set with [WalBuilder::topic_capacity]
+    /// and otherwise the capacity of this WAL, while the writer thread of this WAL writes them
+    /// all, so many logical streams don't take a thread each. Topics are opened on first use
+    /// with default options, and later calls return a handle to the same topic. A topic stays
+    /// open as long as any handle of this WAL or of the topic, and is read-only if this WAL is.
+    ///
+    /// Fails with [WalError::Unsupported] if `name` isn't a single directory name.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::WalBuilder;
+    ///
+    /// # std::fs::create_dir_all("./tmp/topic_doc/").unwrap();
+    /// let wal = WalBuilder::new("./tmp/topic_doc/", 1000)
+    ///     .topic_capacity("orders", 5000)
+    ///     .build()
+    ///     .unwrap();
+    /// wal.try_write(&"started".to_string()).unwrap();
+    /// let orders = wal.topic("orders").unwrap();
+    /// orders.try_write(&"order 1".to_string()).unwrap();
+    /// ```
+    pub fn topic(&self, name: &str) -> Result<Wal<T>, WalError> {
+        self.fork.check(!self.topics.read_only)?;
+        let mut opened = self.topics.opened.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(topic) = opened.get(name) {
+            return Ok(topic.clone());
+        }
+        topic::check_name(name)?;
+        let location = self.location.join("topics").join(name);
+        if !self.topics.read_only {
+            std::fs::create_dir_all(&location)
+                .map_err(|e| WalError::io(e, "Failed to create topic directory"))?;
+        }
+        let mut builder =
+            WalBuilder::new("", self.topics.capacity(name)).read_only(self.topics.read_only);
+        builder.location = location;
+        builder.shared = self
+            .writer
+            .clone()
+            .map(|writer| (self.sender.clone(), writer));
+        let topic = Self::start(builder, None)?;
+        opened.insert(name.to_string(), topic.clone());
+        Ok(topic)
+    }
+
+    /// Read all written logs along with their sequence number, producer and timestamps, from the
+    /// oldest to the newest
+    ///
+    /// Logs that can't be deserialized are returned without a value, so their producer can be
+    /// found.
+    pub fn read_attributed(&self) -> Result<Vec<Attributed<T>>, WalError> {
+        let segments = self.read_snapshot(|reader| reader.read_segments())?;
+        let data = segments
+            .into_iter()
+            .flat_map(|segment| segment.entries)
+            .map(|entry| self.attribute(entry.lsn(), entry))
+            .collect();
+        Ok(data)
+    }
+
+    /// Read the logs of every slot of a WAL written by several processes, see
+    /// [WalBuilder::slots], along with their sequence number across slots, producer and
+    /// timestamps, in the order they were written
+    ///
+    /// The logs of other processes are read from their files, as far as they were written. Logs a
+    /// slot failed to record in the journal of the slots come last. A WAL opened without slots
+    /// is read like [Wal::read_attributed].
+    pub fn read_merged(&self) -> Result<Vec<Attributed<T>>, WalError> {
+        let Some(slots) = &self.slots else {
+            return self.read_attributed();
+        };
+        let mut entries = Vec::new();
+        for (id, dir) in slots.dirs() {
+            let segments = match slots.id() == Some(id) {
+                true => self.read_snapshot(|reader| reader.read_segments())?,
+                false if dir.is_dir() => self.reader_at(dir).read_segments()?,
+                false => continue,
+            };
+            let logs = segments.into_iter().flat_map(|segment| segment.entries);
+            entries.extend(logs.map(|entry| (id, entry)));
+        }
+        let data = slots
+            .merge(entries)?
+            .into_iter()
+            .map(|(lsn, entry)| self.attribute(lsn, entry))
+            .collect();
+        Ok(data)
+    }
+
+    // Log read with its sequence number `lsn`, producer and timestamps
+    fn attribute(&self, lsn: Lsn, entry: LogEntry) -> Attributed<T> {
+        Attributed {
+            lsn,
+            producer: entry.producer(),
+            written_at: entry.timestamp().map(SegmentSpan::time),
+            wall_clock: entry.wall_clock().map(SegmentSpan::time),
+            monotonic: entry
+                .monotonic()
+                .map(|at| UNIX_EPOCH + Duration::from_nanos(at)),
+            log: self.decoder.decode(entry),
+        }
+    }
+
+    /// Number and size of the stored logs of every producer, ordered by producer id
+    pub fn producer_stats(&self) -> Result<Vec<ProducerStats>, WalError> {
+        let mut stats: Vec<ProducerStats> = Vec::new();
+        self.read_snapshot(|reader| {
+            reader.for_each_segment(|segment| {
+                for entry in segment.entries {
+                    let index = match stats.binary_search_by_key(&entry.producer(), |s| s.producer)
+                    {
+                        Ok(i) => i,
+                        Err(i) => {
+                            let producer = entry.producer();
+                            stats.insert(
+                                i,
+                                ProducerStats {
+                                    producer,
+                                    ..Default::default()
+                                },
+                            );
+                            i
+                        }
+                    };
+                    let s = &mut stats[index];
+                    s.entries += 1;
+                    s.bytes += entry.size() as u64;
+                    if self.decoder.decode(entry).is_none() {
+                        s.malformed += 1;
+                    }
+                }
+                Ok(())
+            })
+        })?;
+        Ok(stats)
+    }
+
+    /// Drop logs superseded by a later log with the same key from all files but the current one
+    ///
+    /// Requires [WalBuilder::keyed], the last written log of every key is kept. Returns the
+    /// number of dropped logs. Compaction also runs on its own for files with many superseded
+    /// logs when [WalBuilder::compaction_threshold] is set. Digests recorded with
+    /// [WalBuilder::verify_on_rotation] are updated for compacted files.
+    pub fn compact(&self) -> Result<u64, WalError> {
+        self.check_writable()?;
+        if self.raw_key.is_none() {
+            return Ok(0);
+        }
+        // the writer thread compacts the files, keeping digests of sealed files up to date
+        let dead = || WalError::WriterDead("Writer thread has stopped".to_string());
+        let (reply, result) = mpsc::sync_channel(1);
+        self.sender
+            .send(Signal::Compact(reply))
+            .map_err(|_| dead())?;
+        result.recv().map_err(|_| dead())?
+    }
+
+    /// Drop the logs rejected by `keep` from all files but the current one, returning the number
+    /// of dropped logs
+    ///
+    /// Unlike [Wal::compact], any WAL can be compacted this way, e.g. to drop the entries of a
+    /// state machine superseded by a later snapshot, instead of losing the oldest logs once the
+    /// files are full. Logs of the current file and of the buffer are left alone, so recent
+    /// history is never lost. Logs that can't be deserialized as `T` are kept, as is the last
+    /// log of every atomic batch. Digests recorded with [WalBuilder::verify_on_rotation] are
+    /// updated for compacted files.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # let _ = std::fs::remove_dir_all("./tmp/compact_with_doc/");
+    /// # std::fs::create_dir_all("./tmp/compact_with_doc/").unwrap();
+    /// let wal = Wal::new("./tmp/compact_with_doc/", 10_000).unwrap();
+    /// for i in 0..100u32 {
+    ///     wal.write(i);
+    /// }
+    /// wal.flush().unwrap();
+    /// // keep the even logs of the sealed files
+    /// let dropped = wal.compact_with(|i: &u32| i % 2 == 0).unwrap();
+    /// assert_eq!(wal.read().unwrap().len() as u64, 100 - dropped);
+    /// ```
+    pub fn compact_with<F>(&self, keep: F) -> Result<u64, WalError>
+    where
+        F: Fn(&T) -> bool + Send + Sync + 'static,
+        T: 'static,
+    {
+        self.check_writable()?;
+        let decoder = self.decoder.clone();
+        let retain: RetainFn = Arc::new(move |payload: &[u8]| {
+            decoder.decode_payload(payload).as_ref().is_none_or(&keep)
+        });
+        let dead = || WalError::WriterDead("Writer thread has stopped".to_string());
+        let (reply, result) = mpsc::sync_channel(1);
+        self.sender
+            .send(Signal::Retain(retain, reply))
+            .map_err(|_| dead())?;
+        result.recv().map_err(|_| dead())?
+    }
+
+    /// Check every WAL file, counting readable logs, unreadable frames and bytes past the last
+    /// valid frame, and comparing sealed files with their recorded digest
+    ///
+    /// Files are read as they are at one instant, without stopping the writer thread, and nothing
+    /// is changed, see [Wal::repair] to fix what can be fixed. Unlike [Wal::health], every file
+    /// is read in full. Handy for health checks and operations tools.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/verify_doc/").unwrap();
+    /// let wal = Wal::new("./tmp/verify_doc/", 500).unwrap();
+    /// wal.write("checkout".to_string());
+    /// wal.flush().unwrap();
+    /// let report = wal.verify().unwrap();
+    /// assert!(report.is_healthy());
+    /// ```
+    ///
+    pub fn verify(&self) -> Result<VerifyReport, WalError> {
+        self.read_snapshot(|reader| {
+            let digests = reader.segment_digests();
+            let mut report = VerifyReport {
+                meta_readable: digests.is_ok(),
+                ..Default::default()
+            };
+            let digests = digests.unwrap_or_default();
+            for id in reader.files()?.into_iter().rev() {
+                let content = match reader.raw(id)? {
+                    Some(content) => content,
+                    None => continue,
+                };
+                let digest = digests.iter().find(|d| d.id == id);
+                report.push(repair::check_segment(reader, id, &content, digest));
+            }
+            Ok(report)
+        })
+    }
+
+    /// Cut the bytes past the last valid frame off the sealed files and write the meta file
+    /// again, then [Wal::verify] the WAL
+    ///
+    /// The files cut are listed in [VerifyReport::repaired], and their digests are recorded again.
+    /// The current file is cut when the WAL is opened. Frames that can't be read and files that
+    /// no longer match their digest otherwise are left as they are, and still reported.
+    pub fn repair(&self) -> Result<VerifyReport, WalError> {
+        self.check_writable()?;
+        let (reply, result) = mpsc::sync_channel(1);
+        self.sender
+            .send(Signal::Repair(reply))
+            .map_err(|_| Self::writer_stopped())?;
+        let repaired = result.recv().map_err(|_| Self::writer_stopped())??;
+        let mut report = self.verify()?;
+        report.repaired = repaired;
+        Ok(report)
+    }
+
+    /// Index the sealed WAL files without an index, or whose index no longer matches the file,
+    /// returning the number of files indexed
+    ///
+    /// Indexes are written when the writer rotates to the next file with
+    /// [WalBuilder::segment_index], which only covers the files sealed since. This indexes the
+    /// files sealed before, and the ones whose index was lost.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/rebuild_index_doc/").unwrap();
+    /// let wal: Wal<u32> = Wal::new("./tmp/rebuild_index_doc/", 500).unwrap();
+    /// wal.rebuild_index().unwrap();
+    /// ```
+    ///
+    pub fn rebuild_index(&self) -> Result<usize, WalError> {
+        self.check_writable()?;
+        let (reply, result) = mpsc::sync_channel(1);
+        self.sender
+            .send(Signal::Index(reply))
+            .map_err(|_| Self::writer_stopped())?;
+        result.recv().map_err(|_| Self::writer_stopped())?
+    }
+
+    /// Write all accepted logs to the current file and sync it to storage
+    ///
+    /// Blocks until the writer thread has written every log accepted before the call, after the
+    /// delay of [WakeStrategy::MicroBatch] if any, and the file holding them is synced.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/flush_doc/").unwrap();
+    /// let wal = Wal::new("./tmp/flush_doc/", 500).unwrap();
+    /// wal.write("checkout".to_string());
+    /// wal.flush().unwrap();
+    /// ```
+    ///
+    pub fn flush(&self) -> Result<(), WalError> {
+        let (reply, result) = mpsc::sync_channel(1);
+        self.request_flush(FlushReply::Blocking(reply))?;
+        result.recv().map_err(|_| Self::writer_stopped())?
+    }
+
+    /// Flush the WAL and drop this handle
+    ///
+    /// Once the last handle of the WAL is closed or dropped, the writer thread writes the logs
+    /// left in the buffer, syncs the current file and stops, and dropping the handle waits for
+    /// it. Closing reports the errors that dropping ignores.
+    pub fn close(self) -> Result<(), WalError> {
+        match self.writer {
+            Some(_) => self.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Flush the WAL when the process receives SIGTERM or SIGINT, before it terminates
+    ///
+    /// Deployments stopping a process with SIGTERM, then killing it, would otherwise lose the
+    /// logs still in the buffer. Once a signal arrives, every WAL registered in the process is
+    /// flushed and synced, then the process terminates as it would without a handler. The
+    /// handler holds a handle to the WAL, so the writer thread runs until the process exits.
+    /// Flushing is best effort: a WAL whose writer is stuck delays the termination until the
+    /// process is killed. Only available on unix with the `signals` feature.
+    #[cfg(all(feature = "signals", unix))]
+    pub fn install_signal_flush(&self) -> Result<(), WalError>
+    where
+        T: Send + 'static,
+    {
+        self.check_writable()?;
+        let wal = self.clone();
+        signals::register(Box::new(move || wal.flush()))
+    }
+
+    /// Remove the oldest files holding only logs up to `through`, e.g. once a snapshot of the
+    /// state built from these logs is saved
+    ///
+    /// Files are removed from the oldest one until a file holds a log newer than `through`, and
+    /// the current file is never removed, so some logs up to `through` may be kept. Returns the
+    /// number of removed files. A truncation interrupted by a crash is completed when the WAL is
+    /// opened again, and is never seen half done by reads.
+    ///
+    /// Logs up to `through` at the start of the oldest file left are reclaimed as well, unless the
+    /// current file holds them or a read is in progress, which leaves them to a later truncation:
+    /// the file keeps its size, but reads skip them and the disk space they take is released on
+    /// file systems able to punch holes in files, such as most file systems of Linux. Atomic
+    /// batches are only reclaimed whole, and files of [WalBuilder::fixed_record_size] logs, of
+    /// another framing or of an older format are left whole.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/truncate_doc/").unwrap();
+    /// let wal: Wal<u64> = Wal::new("./tmp/truncate_doc/", 500).unwrap();
+    /// // logs up to 1000 are covered by a snapshot
+    /// let removed = wal.truncate(1000).unwrap();
+    /// ```
+    ///
+    pub fn truncate(&self, through: Lsn) -> Result<usize, WalError> {
+        self.check_writable()?;
+        // the writer thread owns meta file, which drops the digests of removed files
+        let dead = || WalError::WriterDead("Writer thread has stopped".to_string());
+        let (reply, result) = mpsc::sync_channel(1);
+        self.sender
+            .send(Signal::Truncate(through, reply))
+            .map_err(|_| dead())?;
+        result.recv().map_err(|_| dead())?
+    }
+
+    /// Remove the oldest files holding only logs older than `lsn`, see [Wal::truncate]
+    ///
+    /// Returns the number of removed files. Logs from `lsn` on are always kept.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/truncate_before_doc/").unwrap();
+    /// let wal: Wal<u64> = Wal::new("./tmp/truncate_before_doc/", 500).unwrap();
+    /// // the state was checkpointed right before log 1000
+    /// let removed = wal.truncate_before(1000).unwrap();
+    /// ```
+    ///
+    pub fn truncate_before(&self, lsn: Lsn) -> Result<usize, WalError> {
+        match lsn.checked_sub(1) {
+            Some(through) => self.truncate(through),
+            None => {
+                self.check_writable()?;
+                Ok(0)
+            }
+        }
+    }
+
+    /// Remove all logs, including those not yet written to a file
+    ///
+    /// Every WAL file is removed, and the writer thread starts over from the first file. Logs
+    /// written after the call are kept, and keep their sequence numbers following the removed
+    /// ones until the WAL is opened again. Like [Wal::truncate], a clear interrupted by a crash is
+    /// completed when the WAL is opened again, and is never seen half done by reads.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/clear_doc/").unwrap();
+    /// let wal: Wal<u64> = Wal::new("./tmp/clear_doc/", 500).unwrap();
+    /// wal.write(1);
+    /// wal.clear().unwrap();
+    /// assert!(wal.read().unwrap().is_empty());
+    /// ```
+    ///
+    pub fn clear(&self) -> Result<(), WalError> {
+        self.check_writable()?;
+        let dead = || WalError::WriterDead("Writer thread has stopped".to_string());
+        let (reply, result) = mpsc::sync_channel(1);
+        self.sender.send(Signal::Clear(reply)).map_err(|_| dead())?;
+        result.recv().map_err(|_| dead())?
+    }
+
+    // Serialize a log, enforcing the rules and recording the producer of this handle
+    fn encode(&self, entry: &T) -> Result<LogEntry, WalError> {
+        let mut log = self
+            .validation
+            .encode(entry, self.codec, self.buffer.pool().take())
+            .inspect_err(|_| self.counters.rejected(1))?;
+        log.set_producer(self.producer);
+        self.stamp(&mut log);
+        log.set_key(self.key.as_ref().map(|key| key(entry)));
+        Ok(log)
+    }
+
+    // Record in `log` when it was written, unless logs aren't stamped
+    fn stamp(&self, log: &mut LogEntry) {
+        if let Some(clock) = &self.clock {
+            clock.stamp(log);
+        }
+    }
+
+    // Take `logs` from the rate limits of this handle and of the WAL
+    fn throttle(&self, logs: u64) -> Result<(), WalError> {
+        self.acquire(logs)
+            .inspect_err(|_| self.counters.rejected(logs))
+    }
+
+    fn acquire(&self, logs: u64) -> Result<(), WalError> {
+        if let Some(limit) = &self.handle_limit {
+            limit.acquire(logs)?;
+        }
+        if let Some(limit) = &self.rate_limit {
+            if let Err(e) = limit.acquire(logs) {
+                // nothing is written, so the handle keeps its share
+                if let Some(handle) = &self.handle_limit {
+                    handle.release(logs);
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    // Ask the writer to flush the logs of the buffer, replying to `reply` once done
+    pub(crate) fn request_flush(&self, reply: FlushReply) -> Result<(), WalError> {
+        self.check_writable()?;
+        self.check_writer()?;
+        self.sender
+            .send(Signal::Flush(reply))
+            .map_err(|_| Self::writer_stopped())
+    }
+
+    pub(crate) fn writer_stopped() -> WalError {
+        WalError::WriterDead("Writer thread has stopped".to_string())
+    }
+
+    // Fail if the WAL was opened in read-only mode
+    fn check_writable(&self) -> Result<(), WalError> {
+        self.fork.check(true)?;
+        match self.writer {
+            // logs left in the buffer of a dead writer would never be written
+            Some(_) => self.check_writer(),
+            None => Err(WalError::ReadOnlyFilesystem(
+                "WAL was opened in read-only mode".to_string(),
+            )),
+        }
+    }
+
+    // Fail if the writer was closed or the writer thread has panicked
+    fn check_writer(&self) -> Result<(), WalError> {
+        if self.writer.as_ref().is_some_and(|w| w.is_closed()) {
+            return Err(WalError::Closed);
+        }
+        if self.writer.as_ref().is_some_and(|w| w.is_finished()) {
+            return Err(WalError::WriterDead(
+                "Writer thread has stopped, reopen the WAL to continue".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    // Alert the writer thread of new logs in the buffer
+    // Add a log to the buffer and notify the writer thread if needed
+    fn enqueue_one(&self, entry: LogEntry) -> Result<Lsn, WalError> {
+        let bytes = entry.size() as u64;
+        let (lsn, notify) = self
+            .buffer
+            .add(entry)
+            .inspect_err(|_| self.counters.rejected(1))?;
+        self.counters.accepted(1, bytes);
+        if notify {
+            self.notify();
+        }
+        Ok(lsn)
+    }
+
+    // Add logs to the buffer in a single step and notify the writer thread if needed
+    fn enqueue(&self, data: Vec<LogEntry>) -> Result<Range<Lsn>, WalError> {
+        let entries = data.len() as u64;
+        let bytes = data.iter().map(|e| e.size() as u64).sum();
+        let (range, notify) = self
+            .buffer
+            .bulk_add(data)
+            .inspect_err(|_| self.counters.rejected(entries))?;
+        self.counters.accepted(entries, bytes);
+        if notify {
+            self.notify();
+        }
+        Ok(range)
+    }
+
+    fn notify(&self) {
+        let sent = self.sender.send(Signal::Logs).is_ok();
+        invariant!(sent, "writer thread is no longer receiving notifications");
+    }
+
+    // All logs in files followed by the ones still in the buffer, in the order they were written
+    fn read_all(&self) -> Result<Vec<LogEntry>, WalError> {
+        self.read_snapshot(|reader| {
+            let mut logs = reader.read()?;
+            logs.extend(reader.pending());
+            Ok(logs)
+        })
+    }
+
+    // Reader of the WAL files
+    fn reader(&self) -> WalReader {
+        self.reader_at(self.location.clone())
+    }
+
+    // Reader of the WAL files at `location`, written with the options of this handle
+    fn reader_at(&self, location: PathBuf) -> WalReader {
+        WalReader::new(location)
+            .layout(self.layout.clone())
+            .fixed(self.record_size)
+            .framing(self.framing)
+            .checksums(self.checksums)
+            .max_expansion(self.max_expansion)
+            .cipher(self.cipher.clone())
+    }
+
+    // Read the files as they were at one instant, without stopping the writer thread
+    // Logs on their way from the buffer to a file are waited for, and new logs written while the
+    // files are read are left out.
+    fn read_snapshot<R>(
+        &self,
+        f: impl FnOnce(&WalReader) -> Result<R, WalError>,
+    ) -> Result<R, WalError> {
+        // the writer thread and the threads holding locks are gone in a forked process
+        self.fork.check(false)?;
+        let _span = span!("walcraft.read");
+        let start = Instant::now();
+        self.metrics.read();
+        self.verifier.wait_for_reads();
+        if self.fork.forked() {
+            let reader = self.reader();
+            let out = f(&reader);
+            self.read_done(&reader, start);
+            return out;
+        }
+
+        // acquire read lock, unless a read of this thread already holds it
+        let mut scratch = match Reading::held(&self.read_lock) {
+            true => None,
+            false => Some(self.read_lock.lock().unwrap_or_else(|e| e.into_inner())),
+        };
+        let _reading = Reading::enter(&self.read_lock);
+        self.check_writer()?;
+
+        let reader = self.take_snapshot()?;
+        let reader = match scratch.as_mut() {
+            Some(scratch) => reader.scratch(scratch.take()),
+            None => reader,
+        };
+        let out = f(&reader);
+        self.read_done(&reader, start);
+        if let Some(scratch) = scratch.as_mut() {
+            scratch.restore(reader.into_scratch());
+        }
+        out
+    }
+
+    // Reader of the files and pending logs as they are now
+    fn take_snapshot(&self) -> Result<WalReader, WalError> {
+        // files and pending logs agree while the writer can't take logs out of the buffer
+        let _flushing = self.lock.flush_guard();
+        let pending = match self.read_unflushed {
+            true => self.buffer.pending(),
+            false => Vec::new(),
+        };
+        let reader = self.reader();
+        let meta = reader.meta_or_scan()?;
+        let snapshot = Snapshot::take(&self.location, &self.layout, meta, pending)?;
+        Ok(reader.snapshot(snapshot))
+    }
+
+    // record the cost of a read started at `start`, for [Wal::last_read]
+    fn read_done(&self, reader: &WalReader, start: Instant) {
+        let metrics = reader.metrics(start.elapsed());
+        *self.last_read.lock().unwrap_or_else(|e| e.into_inner()) = Some(metrics);
+    }
+
+    /// Read all written logs without exceeding the read memory cap
+    ///
+    /// The logs are held in memory as long as they fit in the cap configured with
+    /// [WalBuilder::read_memory_cap]. Beyond that, they are spilled to a temporary file and
+    /// decoded one by one while iterating the result, so even a WAL much larger than the
+    /// available memory can be exported. Logs are returned from the oldest to the newest file.
+    /// Logs of a WAL set with [WalBuilder::encryption_key] are never spilled, as the temporary
+    /// file would hold them in the clear.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::WalBuilder;
+    ///
+    /// # std::fs::create_dir_all("./tmp/read_bounded_doc/").unwrap();
+    /// let wal = WalBuilder::<u32>::new("./tmp/read_bounded_doc/", 500)
+    ///     .read_memory_cap(1024 * 1024)
+    ///     .build()
+    ///     .unwrap();
+    /// for log in wal.read_bounded().unwrap() {
+    ///     println!("{}", log);
+    /// }
+    /// ```
+    ///
+    pub fn read_bounded(&self) -> Result<BoundedRead<T>, WalError> {
+        let cap = match self.cipher {
+            Some(_) => usize::MAX,
+            None => self.read_memory_cap.unwrap_or(usize::MAX),
+        };
+        self.read_snapshot(|reader| {
+            let mut memory = Vec::new();
+            let mut used = 0usize;
+            let mut spill: Option<SpillWriter> = None;
+            reader.for_each_segment(|segment| {
+                for entry in segment.entries {
+                    // move everything to disk once the cap is exceeded
+                    if spill.is_none() && used.saturating_add(entry.size()) > cap {
+                        let mut writer = SpillWriter::new()?;
+                        for e in memory.drain(..) {
+                            writer.push(&e)?;
+                        }
+                        spill = Some(writer);
+                    }
+                    match spill.as_mut() {
+                        Some(writer) => writer.push(&entry)?,
+                        None => {
+                            used += entry.size();
+                            memory.push(entry);
+                        }
+                    }
+                }
+                Ok(())
+            })?;
+            match spill {
+                Some(writer) => BoundedRead::spilled(writer, self.decoder.clone()),
+                None => Ok(BoundedRead::memory(memory, &self.decoder)),
+            }
+        })
+    }
+
+    /// Get the log with the given sequence number
+    ///
+    /// Returns `None` when the log isn't stored, e.g. when it was overwritten by newer logs. With
+    /// [WalBuilder::fixed_record_size], the position of the log is computed arithmetically so
+    /// only a single record is read from storage. With [WalBuilder::segment_index], only the
+    /// block of the sealed file holding the log is read.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/get_doc/").unwrap();
+    /// let wal: Wal<u64> = Wal::new("./tmp/get_doc/", 500).unwrap();
+    /// let log = wal.get(42).unwrap();
+    /// ```
+    ///
+    pub fn get(&self, lsn: Lsn) -> Result<Option<T>, WalError> {
+        let entry = self.read_snapshot(|reader| reader.get(lsn))?;
+        Ok(entry.and_then(|e| self.decoder.decode(e)))
+    }
+
+    /// Read all logs with the given key, from the oldest to the newest
+    ///
+    /// Requires [WalBuilder::keyed], and `key` must be of the type returned by the key function.
+    /// The keys of every file are recorded when the writer moves on to the next file, so files
+    /// that can't hold the key are skipped. Logs not yet written to a file are included.
+    ///
+    /// The key function runs during the read and may use the WAL itself, as reads don't hold up
+    /// the writer thread. Reads from it take a snapshot of their own, while logs written from it
+    /// are left out of the read in progress.
+    ///
+    /// # Example
+    /// ```
+    /// use serde::{Deserialize, Serialize};
+    /// use walcraft::WalBuilder;
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct Update {
+    ///     account: u32,
+    ///     balance: i64,
+    /// }
+    ///
+    /// # std::fs::create_dir_all("./tmp/keyed_doc/").unwrap();
+    /// let wal = WalBuilder::new("./tmp/keyed_doc/", 500)
+    ///     .keyed(|u: &Update| u.account)
+    ///     .build()
+    ///     .unwrap();
+    /// let updates = wal.read_by_key(&7u32).unwrap();
+    /// assert!(updates.iter().all(|u| u.account == 7));
+    /// ```
+    ///
+    pub fn read_by_key<K: Hash>(&self, key: &K) -> Result<Vec<T>, WalError> {
+        let raw_key = self.raw_key.as_ref().ok_or_else(|| {
+            WalError::Unsupported("Logs are looked up by key only in keyed mode".to_string())
+        })?;
+        let hash = compaction::hash_key(key);
+        let entries = self.read_snapshot(|reader| {
+            let mut logs = reader.read_files(|id| {
+                KeyFilter::load(&self.location, id).is_none_or(|filter| filter.contains(hash))
+            })?;
+            logs.retain(|entry| raw_key(entry.payload()) == Some(hash));
+            logs.extend(
+                reader
+                    .pending()
+                    .into_iter()
+                    .filter(|entry| entry.key() == Some(hash)),
+            );
+            Ok(logs)
+        })?;
+        Ok(entries
+            .into_iter()
+            .filter_map(|e| self.decoder.decode(e))
+            .collect())
+    }
+
+    /// Get the last written log with the given key, or all its logs merged with
+    /// [WalBuilder::merge]
+    ///
+    /// Requires [WalBuilder::keyed], see [Wal::read_by_key].
+    pub fn get_by_key<K: Hash>(&self, key: &K) -> Result<Option<T>, WalError> {
+        let logs = self.read_by_key(key)?;
+        Ok(match &self.merge {
+            Some(merge) => logs.into_iter().reduce(|old, new| merge(old, new)),
+            None => logs.into_iter().last(),
+        })
+    }
+
+    /// Read the logs whose tag is accepted by `filter`, from the oldest to the newest
+    ///
+    /// `filter` is given the tag of every log written with [Wal::try_write_tagged], and None for
+    /// logs written without one. Only the accepted logs are deserialized, and files are read one
+    /// at a time, so the logs of a single entity are recovered without holding the whole WAL in
+    /// memory. Logs not yet written to a file are included.
+    ///
+    /// `filter` may use the WAL itself, like the key function of [Wal::read_by_key]. A
+    /// truncation from it leaves the logs at the start of the oldest file to a later truncation.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/read_filtered_doc/").unwrap();
+    /// let wal: Wal<String> = Wal::new("./tmp/read_filtered_doc/", 500).unwrap();
+    /// let accounts = wal
+    ///     .read_filtered(|tag| tag.is_some_and(|tag| tag.starts_with(b"account-")))
+    ///     .unwrap();
+    /// ```
+    ///
+    pub fn read_filtered<F>(&self, filter: F) -> Result<Vec<T>, WalError>
+    where
+        F: Fn(Option<&[u8]>) -> bool,
+    {
+        let entries = self.read_snapshot(|reader| {
+            let mut logs = reader.read_tagged(&filter)?;
+            logs.extend(
+                reader
+                    .pending()
+                    .into_iter()
+                    .filter(|entry| filter(entry.tag())),
+            );
+            Ok(logs)
+        })?;
+        Ok(entries
+            .into_iter()
+            .filter_map(|e| self.decoder.decode(e))
+            .collect())
+    }
+
+    /// Read all logs written with the given tag, from the oldest to the newest, see
+    /// [Wal::read_filtered]
+    pub fn read_by_tag(&self, tag: &[u8]) -> Result<Vec<T>, WalError> {
+        self.read_filtered(|t| t == Some(tag))
+    }
+
+    /// Read the last `n` written logs, from the oldest to the newest
+    ///
+    /// Logs accepted but not yet written to a file by the writer thread are the newest ones, and
+    /// files are read from the newest one until `n` logs are collected. With
+    /// [WalBuilder::fixed_record_size], only the needed records at the end of each file are read,
+    /// as are the last blocks of the files indexed with [WalBuilder::segment_index].
+    /// With [WalBuilder::recent_cache], logs held in memory are returned without reading files
+    /// whenever there are at least `n` of them, including logs not yet written to a file.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/read_last_doc/").unwrap();
+    /// let wal: Wal<u64> = Wal::new("./tmp/read_last_doc/", 500).unwrap();
+    /// let recent = wal.read_last(10).unwrap();
+    /// assert!(recent.len() <= 10);
+    /// ```
+    ///
+    pub fn read_last(&self, n: usize) -> Result<Vec<T>, WalError> {
+        let entries = self.last_entries(n)?;
+        Ok(entries
+            .into_iter()
+            .filter_map(|e| self.decoder.decode(e))
+            .collect())
+    }
+
+    /// Get the `k`-th most recent log, `0` being the last written one
+    ///
+    /// Like [Wal::read_last], only the newest files are read, and only the requested log is
+    /// deserialized. None if the WAL holds `k` logs or fewer, or if the log can't be
+    /// deserialized.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/nth_from_end_doc/").unwrap();
+    /// let wal: Wal<u64> = Wal::new("./tmp/nth_from_end_doc/", 500).unwrap();
+    /// // the checkpoint before the last one
+    /// let previous = wal.nth_from_end(1).unwrap();
+    /// ```
+    ///
+    pub fn nth_from_end(&self, k: usize) -> Result<Option<T>, WalError> {
+        let n = k.saturating_add(1);
+        let mut entries = self.last_entries(n)?;
+        if entries.len() < n {
+            return Ok(None);
+        }
+        // the oldest of the last `n` logs
+        Ok(self.decoder.decode(entries.swap_remove(0)))
+    }
+
+    // Last `n` logs, from the recent cache when it holds them, or else from the newest files
+    // followed by the logs still in the buffer
+    fn last_entries(&self, n: usize) -> Result<Vec<LogEntry>, WalError> {
+        if let Some(entries) = self.read_unflushed.then(|| self.buffer.last(n)).flatten() {
+            return Ok(entries);
+        }
+        self.read_snapshot(|reader| {
+            let mut pending = reader.pending();
+            let mut entries = reader.read_last(n.saturating_sub(pending.len()))?;
+            let skipped = pending.len().saturating_sub(n);
+            entries.extend(pending.drain(skipped..));
+            Ok(entries)
+        })
+    }
+
+    /// Read all logs written since the last call to this method
+    ///
+    /// Every handle keeps an in-memory cursor (not persisted) of the next log to return, starting
+    /// at the beginning of the WAL. Passing `Some(lsn)` moves the cursor to `lsn` before reading.
+    /// After the call, the cursor points right after the last returned log, so calling this
+    /// method repeatedly returns only the newly written logs. Clones of a handle start at the
+    /// position of the original and then move independently.
+    ///
+    /// Logs not yet written to a file by the writer thread are returned as well, unless disabled
+    /// with [WalBuilder::read_unflushed].
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/entries_since_doc/").unwrap();
+    /// let wal: Wal<u32> = Wal::new("./tmp/entries_since_doc/", 500).unwrap();
+    /// let everything = wal.entries_since(None).unwrap();
+    /// // ... later on, get only the logs written in the meantime
+    /// let new_logs = wal.entries_since(None).unwrap();
+    /// ```
+    ///
+    pub fn entries_since(&self, lsn: Option<Lsn>) -> Result<Vec<T>, WalError> {
+        if let Some(lsn) = lsn {
+            self.cursor.set(lsn);
+        }
+        let since = self.cursor.get();
+        let (entries, pending) = self.read_snapshot(|reader| {
+            let entries = reader.read_indexed(|_| true, |block| *block.lsns.end() >= since)?;
+            Ok((entries, reader.pending()))
+        })?;
+        let mut data = Vec::new();
+        let mut next = since;
+        for item in entries.into_iter().chain(pending) {
+            if item.lsn() < since {
+                continue;
+            }
+            next = next.max(item.lsn() + 1);
+            if let Some(d) = self.decoder.decode(item) {
+                data.push(d);
+            }
+        }
+        self.cursor.set(next);
+        Ok(data)
+    }
+
+    /// Read all logs written since `at`, and keep reading the newer ones like
+    /// [Wal::entries_since]
+    ///
+    /// Moves the cursor of the handle to the first log stamped at `at` or later, see
+    /// [WalBuilder::timestamps], or to the end of the WAL when there's none, before reading. Logs
+    /// without a stamp are never taken for the first one, so a WAL without timestamps only returns
+    /// the logs written during the call. An [Instant] is turned into the time it stands for with
+    /// `SystemTime::now() - instant.elapsed()`.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::{Duration, SystemTime};
+    /// use walcraft::WalBuilder;
+    ///
+    /// # std::fs::create_dir_all("./tmp/entries_since_time_doc/").unwrap();
+    /// let wal = WalBuilder::new("./tmp/entries_since_time_doc/", 500)
+    ///     .timestamps(true)
+    ///     .build()
+    ///     .unwrap();
+    /// wal.write(42u64);
+    /// // logs of the last 5 minutes, then only the new ones
+    /// let recent = wal.entries_since_time(SystemTime::now() - Duration::from_secs(300)).unwrap();
+    /// assert!(recent.contains(&42));
+    /// let new_logs = wal.entries_since(None).unwrap();
+    /// ```
+    ///
+    pub fn entries_since_time(&self, at: SystemTime) -> Result<Vec<T>, WalError> {
+        let at = SegmentSpan::millis(at);
+        let end = self.buffer.next_lsn();
+        let first = self.read_snapshot(|reader| {
+            let spans = reader.spans();
+            let keep = |id| {
+                spans
+                    .iter()
+                    .find(|s| s.id == id)
+                    .is_none_or(|s| at <= s.last)
+            };
+            let mut logs = reader.read_indexed(keep, |block| {
+                block.times.as_ref().is_some_and(|t| at <= *t.end())
+            })?;
+            logs.extend(reader.pending());
+            let first = logs.iter().find(|e| e.timestamp().is_some_and(|t| t >= at));
+            Ok(first.map(|e| e.lsn()))
+        })?;
+        self.entries_since(Some(first.unwrap_or(end)))
+    }
+
+    /// Read the logs from the sequence number `lsn` on, from the oldest to the newest
+    ///
+    /// Unlike [Wal::entries_since], the handle keeps no cursor. Files only holding older logs
+    /// are skipped, which is told from the first log of every file, and files indexed with
+    /// [WalBuilder::segment_index] are read from the block holding `lsn` on. Logs not yet
+    /// written to a file by the writer thread are included, unless disabled with
+    /// [WalBuilder::read_unflushed]. See [Wal::iter_from] to read the logs lazily.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/read_from_doc/").unwrap();
+    /// let wal: Wal<String> = Wal::new("./tmp/read_from_doc/", 500).unwrap();
+    /// let lsn = wal.try_write(&"deployed v2".to_string()).unwrap();
+    /// let since_deploy = wal.read_from(lsn).unwrap();
+    /// assert_eq!(since_deploy[0], "deployed v2");
+    /// ```
+    ///
+    pub fn read_from(&self, lsn: Lsn) -> Result<Vec<T>, WalError>
+    where
+        T: Send,
+    {
+        let entries = self.read_snapshot(|reader| {
+            let files = reader.files_from(lsn)?;
+            let keep = |id| files.contains(&id);
+            let mut logs = reader.read_indexed(keep, |block| *block.lsns.end() >= lsn)?;
+            logs.extend(reader.pending());
+            logs.retain(|entry| entry.lsn() >= lsn);
+            Ok(logs)
+        })?;
+        Ok(self.decode_all(entries))
+    }
+
+    /// Read at most `limit` logs from the position `token`, or from the oldest log when None
+    ///
+    /// Meant to page through a WAL too large to be read at once, such as from a UI or an ETL
+    /// job: only the files from the token on are read, and the returned [Page] carries the token
+    /// of the next page. Tokens stay valid as the writer moves on to other files, and reading
+    /// from a token whose log was dropped from the WAL since, e.g. as its file was reused, fails
+    /// with [WalError::CompactedAway]. Only logs already written to storage by the writer thread
+    /// are returned.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/read_page_doc/").unwrap();
+    /// let wal: Wal<u32> = Wal::new("./tmp/read_page_doc/", 500).unwrap();
+    /// let mut page = wal.read_page(None, 100).unwrap();
+    /// while !page.entries.is_empty() {
+    ///     // ... process page.entries
+    ///     page = wal.read_page(Some(page.next), 100).unwrap();
+    /// }
+    /// ```
+    ///
+    pub fn read_page(&self, token: Option<PageToken>, limit: usize) -> Result<Page<T>, WalError> {
+        let (entries, next) = self.read_snapshot(|reader| page::read_page(reader, token, limit))?;
+        Ok(Page {
+            entries: entries
+                .into_iter()
+                .filter_map(|item| self.decoder.decode(item))
+                .collect(),
+            next,
+        })
+    }
+
+    /// Fold the logs of the WAL into a state, resuming from the state last saved in `store`
+    ///
+    /// The state starts from the one saved by the previous fold, or from `initial` before the
+    /// first one, and `f` folds every log written since into it, in the order they were written.
+    /// The state is saved once every WAL file is folded and at the end, along with the sequence
+    /// number of the next log, so a fold interrupted by a crash resumes from the last saved
+    /// state. Logs dropped from the WAL since the last save are missing from the state, see
+    /// [WalBuilder::checkpointer] to keep them from being dropped first.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::{FileSnapshotStore, Wal};
+    ///
+    /// # std::fs::create_dir_all("./tmp/fold_doc/").unwrap();
+    /// # let _ = std::fs::remove_file("./tmp/fold_doc/balance");
+    /// let wal: Wal<i64> = Wal::new("./tmp/fold_doc/", 500).unwrap();
+    /// # wal.clear().unwrap();
+    /// let store = FileSnapshotStore::new("./tmp/fold_doc/balance");
+    /// wal.write(100);
+    /// wal.write(-30);
+    /// assert_eq!(wal.fold(&store, 0, |balance, amount| balance + amount).unwrap(), 70);
+    /// // only the new logs are folded into the saved state
+    /// wal.write(5);
+    /// assert_eq!(wal.fold(&store, 0, |balance, amount| balance + amount).unwrap(), 75);
+    /// ```
+    ///
+    pub fn fold<S, F>(
+        &self,
+        store: &impl SnapshotStore<S>,
+        initial: S,
+        mut f: F,
+    ) -> Result<S, WalError>
+    where
+        F: FnMut(S, T) -> S,
+    {
+        let (mut state, mut next) = store
+            .load()
+            .map_err(|e| WalError::io(e, "Failed to load folded state"))?
+            .unwrap_or((initial, 0));
+        let (segments, pending) =
+            self.read_snapshot(|reader| Ok((reader.read_segments()?, reader.pending())))?;
+        let batches = segments.into_iter().map(|s| s.entries).chain([pending]);
+        for entries in batches {
+            let folded = next;
+            for entry in entries.into_iter().filter(|e| e.lsn() >= folded) {
+                next = entry.lsn() + 1;
+                if let Some(log) = self.decoder.decode(entry) {
+                    state = f(state, log);
+                }
+            }
+            if next > folded {
+                store
+                    .save(&state, next)
+                    .map_err(|e| WalError::io(e, "Failed to save folded state"))?;
+            }
+        }
+        Ok(state)
+    }
+
+    /// Hand every log of the WAL to `f`, from the oldest to the newest, until it breaks
+    ///
+    /// The usual way to rebuild a state machine when starting: logs are read like [Wal::read],
+    /// but deserialized one at a time as `f` takes them, so no `Vec` of logs is built, and the
+    /// logs after the one `f` breaks at are never deserialized. `f` runs once the files are read,
+    /// so it may use the WAL, e.g. to write or truncate. Returns the number of logs handed to `f`.
+    /// [Wal::replay] is the one pacing logs as they were written.
+    ///
+    /// # Example
+    /// ```
+    /// use std::ops::ControlFlow;
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/replay_into_doc/").unwrap();
+    /// let wal: Wal<i64> = Wal::new("./tmp/replay_into_doc/", 500).unwrap();
+    /// # wal.clear().unwrap();
+    /// wal.write(100);
+    /// wal.write(-30);
+    /// let mut balance = 0;
+    /// wal.replay_into(|amount| {
+    ///     balance += amount;
+    ///     ControlFlow::Continue(())
+    /// })
+    /// .unwrap();
+    /// assert_eq!(balance, 70);
+    /// ```
+    ///
+    pub fn replay_into<F>(&self, mut f: F) -> Result<u64, WalError>
+    where
+        F: FnMut(T) -> ControlFlow<()>,
+    {
+        let mut replayed = 0;
+        for entry in self.read_all()? {
+            let log = match self.decoder.decode(entry) {
+                Some(log) => log,
+                None => continue,
+            };
+            replayed += 1;
+            if f(log).is_break() {
+                break;
+            }
+        }
+        Ok(replayed)
+    }
+
+    /// Follow the logs written to the WAL from now on, like `tail -f`
+    ///
+    /// The writer thread hands every batch of logs to the subscription once it's written to a
+    /// WAL file, as durable as the [SyncPolicy] makes it, so change data capture pipelines are
+    /// built on the WAL without polling [Wal::read]. Logs written before the call aren't
+    /// included, see [Wal::entries_since] to catch up first. Logs pile up in memory until the
+    /// subscription takes them, and dropping it stops the delivery.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/subscribe_doc/").unwrap();
+    /// let wal: Wal<String> = Wal::new("./tmp/subscribe_doc/", 500).unwrap();
+    /// let mut changes = wal.subscribe();
+    /// wal.write("created".to_string());
+    /// assert_eq!(changes.next(), Some("created".to_string()));
+    /// ```
+    ///
+    pub fn subscribe(&self) -> Subscription<T> {
+        Subscription::new(self.subscribers.add(), self.decoder.clone())
+    }
+
+    /// Reader `name` of the WAL, resuming after the logs it last committed, see [Consumer]
+    ///
+    /// Every consumer keeps its committed position in the `consumer_<name>` file of the WAL
+    /// directory, so independent readers such as an indexer and an analytics job don't keep
+    /// track of what they processed on their own. Fails with [WalError::Unsupported] unless
+    /// `name` only holds ASCII letters, digits, `-`, `_` and `.`.
+    pub fn consumer(&self, name: &str) -> Result<Consumer<T>, WalError> {
+        Consumer::open(self.clone(), name)
+    }
+
+    /// All written logs paced as they were originally written, to replay traffic to downstream
+    /// systems in load tests and simulations
+    ///
+    /// Logs are paced by the time they were stamped with, see [WalBuilder::timestamps]. Without
+    /// stamps, or to pace logs by another time, [Replay::timestamp] tells when every log was
+    /// written, e.g. from a field of the log. The iterator sleeps between logs to reproduce the
+    /// gaps between their timestamps, scaled with [Replay::speed], and returns logs without a
+    /// timestamp right away. Logs are read like [Wal::read] when this is called.
+    ///
+    /// # Example
+    /// ```
+    /// use serde::{Deserialize, Serialize};
+    /// use std::time::{Duration, SystemTime};
+    /// use walcraft::Wal;
+    ///
+    /// #[derive(Serialize, Deserialize)]
+    /// struct Request {
+    ///     path: String,
+    ///     at: SystemTime,
+    /// }
+    ///
+    /// # std::fs::create_dir_all("./tmp/replay_doc/").unwrap();
+    /// let wal = Wal::new("./tmp/replay_doc/", 500).unwrap();
+    /// # wal.clear().unwrap();
+    /// let at = SystemTime::now();
+    /// wal.write(Request { path: "/".to_string(), at });
+    /// wal.write(Request { path: "/login".to_string(), at: at + Duration::from_millis(10) });
+    /// // ten times as fast, so the second request comes 1ms after the first one
+    /// for request in wal.replay().unwrap().timestamp(|r| r.at).speed(10.0) {
+    ///     println!("{}", request.path);
+    /// }
+    /// ```
+    ///
+    pub fn replay(&self) -> Result<Replay<T>, WalError> {
+        let logs = self.read_all()?.into_iter().filter_map(|entry| {
+            let at = entry.timestamp().map(SegmentSpan::time);
+            self.decoder.decode(entry).map(|log| (log, at))
+        });
+        Ok(Replay::new(logs.collect()))
+    }
+
+    /// Read the logs written from `from` to `to`, both included, from the oldest to the newest
+    ///
+    /// Only logs stamped with [WalBuilder::timestamps] are returned. The time range of the logs
+    /// of every file is recorded when the file is sealed, so files whose logs were all written
+    /// outside the range are skipped, and reading the last minutes of a large WAL only reads its
+    /// newest files. Within files indexed with [WalBuilder::segment_index], only the blocks of logs
+    /// written in the range are read. Logs not yet written to a file are included.
+    ///
+    /// # Example
+    /// ```
+    /// use std::time::{Duration, SystemTime};
+    /// use walcraft::WalBuilder;
+    ///
+    /// # std::fs::create_dir_all("./tmp/read_range_doc/").unwrap();
+    /// let wal = WalBuilder::new("./tmp/read_range_doc/", 500)
+    ///     .timestamps(true)
+    ///     .build()
+    ///     .unwrap();
+    /// wal.write(42u64);
+    /// // logs of the last 5 minutes
+    /// let now = SystemTime::now();
+    /// let recent = wal.read_range(now - Duration::from_secs(300), now).unwrap();
+    /// assert!(recent.contains(&42));
+    /// ```
+    ///
+    pub fn read_range(&self, from: SystemTime, to: SystemTime) -> Result<Vec<T>, WalError>
+    where
+        T: Send,
+    {
+        let range = SegmentSpan::millis(from)..=SegmentSpan::millis(to);
+        let entries = self.read_snapshot(|reader| {
+            let spans = reader.spans();
+            let keep = |id| {
+                spans
+                    .iter()
+                    .find(|s| s.id == id)
+                    .is_none_or(|s| s.first <= *range.end() && *range.start() <= s.last)
+            };
+            let mut logs = reader.read_indexed(keep, |block| {
+                block
+                    .times
+                    .as_ref()
+                    .is_some_and(|t| *t.start() <= *range.end() && *range.start() <= *t.end())
+            })?;
+            logs.extend(reader.pending());
+            logs.retain(|entry| entry.timestamp().is_some_and(|at| range.contains(&at)));
+            Ok(logs)
+        })?;
+        Ok(self.decode_all(entries))
+    }
+
+    /// Logs written between two points of the WAL, given as a range of sequence numbers
+    ///
+    /// Handy to find out what changed between two events, e.g. a deploy and an incident, given
+    /// the sequence numbers returned by writes at those times. Logs stamped with
+    /// [WalBuilder::timestamps] are found by time with [Wal::read_range] instead. Logs
+    /// not yet written to a file are included. In keyed mode, the newest log of every key written
+    /// in the range is also returned, which is the net effect of the range. Logs no longer held
+    /// by the WAL are missing from the result.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/diff_doc/").unwrap();
+    /// let wal: Wal<String> = Wal::new("./tmp/diff_doc/", 500).unwrap();
+    /// let deploy = wal.try_write(&"deployed v2".to_string()).unwrap();
+    /// let incident = wal.try_write(&"incident".to_string()).unwrap();
+    /// let diff = wal.diff(deploy..incident).unwrap();
+    /// assert_eq!(diff.added, vec!["deployed v2".to_string()]);
+    /// ```
+    ///
+    pub fn diff(&self, range: Range<Lsn>) -> Result<Diff<T>, WalError> {
+        let entries = self.read_all()?;
+        let (added, latest) = diff::split(entries, range, self.raw_key.as_ref());
+        let decode = |logs: Vec<LogEntry>| {
+            logs.into_iter()
+                .filter_map(|e| self.decoder.decode(e))
+                .collect()
+        };
+        Ok(Diff {
+            added: decode(added),
+            latest_by_key: decode(latest),
+        })
+    }
+
+    /// Memory currently held by this WAL, shared by all its handles
+    ///
+    /// Counts the payload of logs waiting for the writer thread and of logs in the recent cache,
+    /// along with the scratch buffer kept between reads.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/memory_usage_doc/").unwrap();
+    /// let wal: Wal<u64> = Wal::new("./tmp/memory_usage_doc/", 500).unwrap();
+    /// let usage = wal.memory_usage();
+    /// println!("{} bytes held", usage.total());
+    /// ```
+    ///
+    pub fn memory_usage(&self) -> MemoryUsage {
+        self.buffer.memory_usage()
+    }
+
+    /// Logs waiting for the writer thread, shared by all handles
+    ///
+    /// Tells how far the writer thread is behind, and how many logs were dropped by
+    /// [Overflow::DropOldest] when the buffer is bounded with [WalBuilder::backpressure].
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/buffer_depth_doc/").unwrap();
+    /// let wal: Wal<u64> = Wal::new("./tmp/buffer_depth_doc/", 500).unwrap();
+    /// let depth = wal.buffer_depth();
+    /// println!("{} logs waiting", depth.logs);
+    /// ```
+    ///
+    pub fn buffer_depth(&self) -> BufferDepth {
+        self.buffer.depth()
+    }
+
+    /// Statistics of the WAL, shared by all handles
+    ///
+    /// Every 16th log is sampled to measure the time from adding it to the buffer until the
+    /// writer thread writes it to a file.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/stats_doc/").unwrap();
+    /// let wal: Wal<u64> = Wal::new("./tmp/stats_doc/", 500).unwrap();
+    /// let latency = wal.stats().write_latency;
+    /// println!("p99 of {} samples: {:?}", latency.samples, latency.p99);
+    /// ```
+    ///
+    pub fn stats(&self) -> WalStats {
+        let latency = self.latency.lock().unwrap_or_else(|e| e.into_inner());
+        let (verified_files, unverified_files) = self.verifier.counts();
+        WalStats {
+            write_latency: latency.stats(),
+            verified_files,
+            unverified_files,
+        }
+    }
+
+    /// Recent moves of the writer thread to the next file since the WAL was opened, shared by
+    /// all handles
+    ///
+    /// Tells when the last rotations happened, how many logs and bytes every file left behind
+    /// holds and how long it took to fill, along with a histogram of the number of logs of all
+    /// files left behind. Files filling much faster or slower than expected point to a capacity
+    /// or [WalBuilder::segments] setting that doesn't fit the load.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/rotation_history_doc/").unwrap();
+    /// let wal: Wal<u64> = Wal::new("./tmp/rotation_history_doc/", 500).unwrap();
+    /// for rotation in wal.rotation_history().rotations {
+    ///     println!("wal_{}: {} logs in {:?}", rotation.segment, rotation.entries, rotation.since_previous);
+    /// }
+    /// ```
+    ///
+    pub fn rotation_history(&self) -> RotationHistory {
+        self.rotations
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Cost of the last read through this handle, None before the first one
+    ///
+    /// Every method reading WAL files records the files it opened, the bytes it read and the
+    /// frames it parsed, along with the time it took, so the cost of replays can be logged and
+    /// watched as the WAL grows. Handles record their own reads, clones included.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/last_read_doc/").unwrap();
+    /// let wal: Wal<u64> = Wal::new("./tmp/last_read_doc/", 500).unwrap();
+    /// wal.read().unwrap();
+    /// let read = wal.last_read().unwrap();
+    /// println!("{} bytes of {} files read in {:?}", read.bytes, read.segments, read.duration);
+    /// ```
+    ///
+    pub fn last_read(&self) -> Option<ReadMetrics> {
+        *self.last_read.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Every metric of the WAL in the Prometheus text exposition format
+    ///
+    /// Gathers [Wal::stats], [Wal::metrics], [Wal::rotation_history], [Wal::last_read] and
+    /// [Wal::handle_stats] as counters and gauges named `walcraft_*`, ready to be served as is on
+    /// a metrics endpoint.
+    /// Handle counters carry their label, empty for the handles without one. Latencies are in
+    /// seconds.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/to_prometheus_doc/").unwrap();
+    /// let wal: Wal<u64> = Wal::new("./tmp/to_prometheus_doc/", 500).unwrap();
+    /// let text = wal.to_prometheus();
+    /// assert!(text.contains("# TYPE walcraft_write_latency_seconds gauge"));
+    /// ```
+    ///
+    pub fn to_prometheus(&self) -> String {
+        let mut exposition = Exposition::default();
+        exposition.stats(&self.stats());
+        exposition.metrics(&self.metrics());
+        exposition.rotations(&self.rotation_history());
+        if let Some(read) = self.last_read() {
+            exposition.last_read(&read);
+        }
+        exposition.handles(&self.handle_stats());
+        exposition.finish()
+    }
+
+    /// Counters of the activity of the WAL since it was opened, shared by all handles
+    ///
+    /// Logs and bytes are counted once the writer thread wrote them to a file, and every read
+    /// through any handle is counted once. With the `tracing` feature, writes, syncs, rotations
+    /// and reads are also traced as spans named `walcraft.*`.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/metrics_doc/").unwrap();
+    /// let wal: Wal<u64> = Wal::new("./tmp/metrics_doc/", 500).unwrap();
+    /// wal.write(42);
+    /// wal.flush().unwrap();
+    /// let metrics = wal.metrics();
+    /// println!("{} logs in {} bytes", metrics.entries_written, metrics.bytes_written);
+    /// ```
+    ///
+    pub fn metrics(&self) -> WalMetrics {
+        self.metrics.snapshot(self.buffer.depth())
+    }
+
+    /// State of the WAL files, for capacity planning
+    ///
+    /// Tells which file is being written, the size of every file, the oldest and the newest
+    /// logs, and when the writer is expected to move on to the next file at the rate logs were
+    /// written over the last seconds. Logs waiting in the buffer count as the newest ones.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/status_doc/").unwrap();
+    /// let wal: Wal<u64> = Wal::new("./tmp/status_doc/", 500).unwrap();
+    /// let status = wal.status().unwrap();
+    /// println!("writing wal_{}, {} bytes on disk", status.current_file, status.disk_bytes);
+    /// if let Some(until) = status.until_rotation {
+    ///     println!("next rotation in {:?}", until);
+    /// }
+    /// ```
+    ///
+    pub fn status(&self) -> Result<WalStatus, WalError> {
+        let until_rotation = self.metrics.until_rotation();
+        self.read_snapshot(|reader| {
+            let mut files = Vec::new();
+            for id in reader.files()?.into_iter().rev() {
+                if let Some(bytes) = reader.size(id)? {
+                    files.push(FileStatus { id, bytes });
+                }
+            }
+            let mut pending = reader.pending();
+            let oldest = match reader.read_first()? {
+                Some(entry) => Some(entry),
+                None => pending.first().cloned(),
+            };
+            let newest = match pending.pop() {
+                Some(entry) => Some(entry),
+                None => reader.read_last(1)?.pop(),
+            };
+            let (lsns, times) = match oldest.zip(newest) {
+                Some((oldest, newest)) => {
+                    let at = |entry: &LogEntry| {
+                        let millis = entry.timestamp()?;
+                        Some(SystemTime::UNIX_EPOCH + Duration::from_millis(millis))
+                    };
+                    let times = at(&oldest).zip(at(&newest)).map(|(o, n)| o..=n);
+                    (Some(oldest.lsn()..=newest.lsn()), times)
+                }
+                None => (None, None),
+            };
+            Ok(WalStatus {
+                current_file: reader.current_pointer()?,
+                disk_bytes: files.iter().map(|f| f.bytes).sum(),
+                files,
+                lsns,
+                times,
+                until_rotation,
+            })
+        })
+    }
+
+    /// Logs written since the WAL was opened through every label of handles, see [Wal::labeled]
+    ///
+    /// Labels are listed in the order they were first used, and the handles without a label are
+    /// listed first. Logs are counted once accepted in the buffer, whether or not they were
+    /// written to a file yet.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/handle_stats_doc/").unwrap();
+    /// let wal: Wal<u64> = Wal::new("./tmp/handle_stats_doc/", 500).unwrap();
+    /// wal.labeled("billing").write(42);
+    /// for handle in wal.handle_stats() {
+    ///     println!("{:?}: {} logs, {} bytes", handle.label, handle.entries, handle.bytes);
+    /// }
+    /// ```
+    ///
+    pub fn handle_stats(&self) -> Vec<HandleStats> {
+        self.handles.stats()
+    }
+
+    /// Estimate how many logs fit in `capacity` bytes of storage
+    ///
+    /// The estimate uses the running average size of logs written through this WAL instance,
+    /// so `None` is returned until at least one log has been written.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/estimate_entries_doc/").unwrap();
+    /// let wal = Wal::new("./tmp/estimate_entries_doc/", 500).unwrap();
+    /// assert_eq!(wal.estimate_entries(1_000_000), None);
+    /// wal.write(42u64);
+    /// assert_eq!(wal.estimate_entries(2_000), Some(100));
+    /// ```
+    ///
+    pub fn estimate_entries(&self, capacity: usize) -> Option<usize> {
+        let payload = self.buffer.average_payload_size()?;
+        Some(format::entries_per_capacity(capacity, payload))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::ops::RangeInclusive;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[derive(Serialize, Deserialize, Debug)]
+    struct Item {
+        id: u16,
+    }
+
+    fn clear_storage(name: &str) -> String {
+        let path = format!("./tmp/{}/", name);
+        if Path::new(&path).exists() {
+            std::fs::remove_dir_all(&path).expect("Failed to delete old files");
+        }
+        std::fs::create_dir_all(&path).expect("Failed to create test directory");
+        path
+    }
+
+    #[test]
+    fn simple_write() {
+        let dir = clear_storage("simple_write");
+        let wal = Wal::new(&dir, 10_000).unwrap();
+        for i in 0..1000 {
+            let item = Item { id: i };
+            wal.write(item);
+        }
+        // allow some time for WalWriter to work
+        sleep(Duration::from_secs(2));
+        // check that log file exists
+        let metadata = std::fs::metadata(format!("{}wal_1", dir)).expect("Failed to read file");
+        assert!(metadata.len() > 5000); // at least 5KB of data is added
+    }
+
+    #[test]
+    fn multiple_files() {
+        // clear existing files
+        let dir = clear_storage("multiple_files");
+        // create a new wal object
+        let wal = Wal::new(&dir, 100).unwrap();
+        // This shall be dumped to first file
+        let dump = (1..=30).map(|i| Item { id: i }).collect::<Vec<_>>();
+        wal.batch_write(dump);
+        sleep(Duration::from_millis(100));
+        // This shall be dumped to second file
+        let dump = (40..=45).map(|i| Item { id: i }).collect::<Vec<_>>();
+        wal.batch_write(dump);
+        // allow some time for WalWriter to work
+        sleep(Duration::from_secs(2));
+        // check that log file exists
+        let metadata1 = std::fs::metadata(format!("{}wal_1", dir)).expect("Failed to read file1");
+        assert!(metadata1.len() > 10); // at least 200 bytes of data is added
+        let metadata2 = std::fs::metadata(format!("{}wal_2", dir)).expect("Failed to read file2");
+        assert!(metadata2.len() > 10); // more than 10 bytes of data
+    }
+
+    #[test]
+    fn read_after_write() {
+        let dir = clear_storage("read_after_write");
+        // create a new wal object
+        let wal = Wal::new(&dir, 1000).unwrap();
+        // This shall be dumped to first file
+        let dump = (1..=1234).map(|i| Item { id: i }).collect::<Vec<_>>();
+        wal.batch_write(dump);
         sleep(Duration::from_secs(2));
         let data = wal.read();
         assert!(data.is_ok());
         let data = data.unwrap();
-        assert_eq!(data.len(), 1000);
+        // the batch is written at once, and the capacity counts bytes rather than logs
+        assert_eq!(data.len(), 1234);
         assert_eq!(data.last().unwrap().id, 1234);
     }
+
+    #[test]
+    fn try_write_all() {
+        let dir = clear_storage("try_write_all");
+        let wal = Wal::new(&dir, 1000).unwrap();
+        let first = (1..=10).map(|i| Item { id: i }).collect::<Vec<_>>();
+        let second = (11..=15).map(|i| Item { id: i }).collect::<Vec<_>>();
+        // ranges are contiguous across calls
+        let r1 = wal.try_write_all(&first).unwrap();
+        let r2 = wal.try_write_all(&second).unwrap();
+        assert_eq!(r1, 0..10);
+        assert_eq!(r2, 10..15);
+        // empty submissions reserve nothing
+        assert_eq!(wal.try_write_all(&[]).unwrap(), 15..15);
+        sleep(Duration::from_secs(1));
+        let data = wal.read().unwrap();
+        let ids = data.iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, (1..=15).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn read_while_writing() {
+        let dir = clear_storage("read_while_writing");
+        let wal = Wal::new(&dir, 100_000).unwrap();
+        let writer = {
+            let wal = wal.clone();
+            std::thread::spawn(move || {
+                for i in 1..=2000 {
+                    wal.write(Item { id: i });
+                }
+            })
+        };
+        // every read sees the logs written up to some point, with none missing
+        let mut seen = 0;
+        while seen < 2000 {
+            let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+            assert_eq!(ids, (1..=ids.len() as u16).collect::<Vec<_>>());
+            assert!(ids.len() >= seen);
+            seen = ids.len();
+        }
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn drain_channel() {
+        let dir = clear_storage("drain_channel");
+        let wal = Wal::new(&dir, 100_000).unwrap();
+        let (tx, rx) = mpsc::sync_channel(16);
+        let producer = std::thread::spawn(move || {
+            for i in 1..=5000 {
+                tx.send(Item { id: i }).unwrap();
+            }
+        });
+        assert_eq!(wal.drain_channel(rx).unwrap(), 5000);
+        producer.join().unwrap();
+        wal.flush().unwrap();
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, (1..=5000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn positional_writes() {
+        let dir = clear_storage("positional_writes");
+        let builder = WalBuilder::new(&dir, 1000).positional_writes(true);
+        let wal: Wal<Item> = builder.build().unwrap();
+        wal.batch_write((1..=10).map(|i| Item { id: i }).collect());
+        sleep(Duration::from_millis(500));
+        let size = std::fs::metadata(format!("{}wal_1", dir)).unwrap().len();
+        let meta = std::fs::read_to_string(format!("{}meta", dir)).unwrap();
+        let format = FormatVersion::CURRENT.fields();
+        assert_eq!(
+            meta::unseal(&meta),
+            Some(format!("1 {}\nsegments 5\nformat {}", size, format).as_str())
+        );
+        assert_eq!(wal.read().unwrap().len(), 10);
+        drop(wal);
+        // reopening an untouched file resumes from the recorded offset
+        let builder = WalBuilder::<Item>::new(&dir, 1000).positional_writes(true);
+        assert!(builder.build().is_ok());
+        // external modification is detected on reopen
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(format!("{}wal_1", dir))
+            .unwrap();
+        file.write_all(&[1, 2, 3]).unwrap();
+        let builder = WalBuilder::<Item>::new(&dir, 1000).positional_writes(true);
+        match builder.build() {
+            Err(WalError::ExternalModification {
+                recorded,
+                size: found,
+                ..
+            }) => assert_eq!((recorded, found), (size, size + 3)),
+            _ => panic!("external modification wasn't detected"),
+        }
+    }
+
+    #[test]
+    fn read_segments() {
+        let dir = clear_storage("read_segments");
+        let wal = Wal::new(&dir, 100).unwrap();
+        // each batch fills a whole file
+        wal.batch_write((1..=50).map(|i| Item { id: i }).collect());
+        sleep(Duration::from_millis(100));
+        wal.batch_write((51..=60).map(|i| Item { id: i }).collect());
+        sleep(Duration::from_millis(500));
+        let segments = wal.read_segments().unwrap();
+        let ids = segments.iter().map(|s| s.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(segments[0].entries.len(), 50);
+        assert_eq!(segments[1].entries.len(), 10);
+        assert!(segments[2].entries.is_empty());
+        assert!(segments[2].active);
+        assert!(!segments[0].active);
+    }
+
+    #[test]
+    fn read_cached() {
+        let dir = clear_storage("read_cached");
+        let wal = WalBuilder::new(&dir, 1000).read_cache(100).build().unwrap();
+        wal.batch_write((1..=3).map(|id| Item { id }).collect());
+        let first = wal.read_cached().unwrap();
+        assert_eq!(first.len(), 3);
+        // shared by every handle until logs change
+        assert!(Arc::ptr_eq(&first, &wal.clone().read_cached().unwrap()));
+        wal.write(Item { id: 4 });
+        let second = wal.read_cached().unwrap();
+        assert_eq!(
+            second.iter().map(|i| i.id).collect::<Vec<_>>(),
+            [1, 2, 3, 4]
+        );
+        wal.flush().unwrap();
+        let flushed = wal.read_cached().unwrap();
+        assert!(Arc::ptr_eq(&flushed, &wal.read_cached().unwrap()));
+        wal.clear().unwrap();
+        assert!(wal.read_cached().unwrap().is_empty());
+
+        // reads larger than the cache aren't kept
+        wal.batch_write((5..=100).map(|id| Item { id }).collect());
+        let large = wal.read_cached().unwrap();
+        assert!(!Arc::ptr_eq(&large, &wal.read_cached().unwrap()));
+    }
+
+    #[test]
+    fn read_while_rotating() {
+        let dir = clear_storage("read_while_rotating");
+        let wal = Wal::new(&dir, 1000).unwrap();
+        // every batch fills a file, and the read races the writer moving on to the next one
+        for _ in 0..20 {
+            wal.batch_write((0..100).map(|id| Item { id }).collect());
+            wal.read().unwrap();
+        }
+    }
+
+    #[test]
+    fn read_segment() {
+        let dir = clear_storage("read_segment");
+        let wal = WalBuilder::new(&dir, 100)
+            .verify_on_rotation(true)
+            .build()
+            .unwrap();
+        // each batch fills a whole file
+        wal.batch_write((1..=50).map(|i| Item { id: i }).collect());
+        wal.flush().unwrap();
+        wal.batch_write((51..=60).map(|i| Item { id: i }).collect());
+        wal.flush().unwrap();
+
+        let sealed = wal.read_segment(1).unwrap().unwrap();
+        assert_eq!(sealed.entries.len(), 50);
+        assert!(sealed.digest.is_some_and(|d| d.verified && d.entries == 50));
+        let next = wal.read_segment(2).unwrap().unwrap();
+        assert_eq!(next.entries[0].id, 51);
+        let (first, last) = (sealed.lsns.unwrap(), next.lsns.unwrap());
+        assert_eq!(*first.end() + 1, *last.start());
+        assert_eq!(last.end() - last.start(), 9);
+        // the second batch filled its file too
+        let current = wal.read_segment(3).unwrap().unwrap();
+        assert!(current.active && current.lsns.is_none() && current.digest.is_none());
+        // not created yet, and beyond the number of files
+        assert!(wal.read_segment(4).unwrap().is_none());
+        assert!(wal.read_segment(200).unwrap().is_none());
+    }
+
+    #[test]
+    fn micro_batch_wake() {
+        let dir = clear_storage("micro_batch_wake");
+        let wal = WalBuilder::new(&dir, 1000)
+            .wake_strategy(WakeStrategy::MicroBatch(Duration::from_millis(5)))
+            .build()
+            .unwrap();
+        for i in 1..=100 {
+            wal.write(Item { id: i });
+        }
+        sleep(Duration::from_millis(500));
+        assert_eq!(wal.read().unwrap().len(), 100);
+    }
+
+    #[test]
+    fn recreate() {
+        let dir = clear_storage("recreate");
+        let cold = format!("{}cold/", dir);
+        let builder = || WalBuilder::new(&dir, 100).cold_storage(&cold, 0);
+        let wal = Wal::recreate(builder()).unwrap();
+        for i in 0..5 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        assert!(Path::new(&format!("{}wal_1", cold)).exists());
+        // nothing is removed while the WAL is open
+        assert!(matches!(
+            Wal::<Item>::recreate(builder()),
+            Err(WalError::AlreadyOpen(_))
+        ));
+        assert_eq!(wal.read().unwrap().len(), 5);
+        drop(wal);
+        std::fs::write(format!("{}notes.txt", dir), b"kept").unwrap();
+        std::fs::create_dir_all(format!("{}reports", dir)).unwrap();
+
+        let wal: Wal<Item> = Wal::recreate(builder()).unwrap();
+        assert!(wal.read().unwrap().is_empty());
+        assert!(!Path::new(&format!("{}wal_1", cold)).exists());
+        // files of the user are left alone
+        assert!(Path::new(&format!("{}notes.txt", dir)).exists());
+        assert!(Path::new(&format!("{}reports", dir)).is_dir());
+        wal.write(Item { id: 9 });
+        wal.flush().unwrap();
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![9]);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn preallocate() {
+        use std::os::unix::fs::MetadataExt;
+        let dir = clear_storage("preallocate");
+        let wal = WalBuilder::new(&dir, 40_000)
+            .preallocate(true)
+            .build()
+            .unwrap();
+        wal.write(Item { id: 1 });
+        wal.flush().unwrap();
+        // the file keeps the size of its frames, with the storage of its capacity reserved
+        let metadata = std::fs::metadata(format!("{}wal_1", dir)).unwrap();
+        assert!(metadata.len() < 100);
+        assert!(metadata.blocks() * 512 >= 10_000);
+        assert_eq!(wal.read().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn fold() {
+        // saves kept in memory
+        #[derive(Default)]
+        struct Saves(Mutex<Vec<(Vec<u16>, Lsn)>>);
+        impl SnapshotStore<Vec<u16>> for Saves {
+            fn load(&self) -> std::io::Result<Option<(Vec<u16>, Lsn)>> {
+                Ok(self.0.lock().unwrap().last().cloned())
+            }
+            fn save(&self, state: &Vec<u16>, next: Lsn) -> std::io::Result<()> {
+                self.0.lock().unwrap().push((state.clone(), next));
+                Ok(())
+            }
+        }
+
+        let dir = clear_storage("fold");
+        let wal = Wal::new(&dir, 100).unwrap();
+        // two logs fill a file, and the last one waits in the buffer
+        for i in 0..5 {
+            wal.write(Item { id: i });
+            if i < 4 {
+                wal.flush().unwrap();
+            }
+        }
+        let saves = Saves::default();
+        let push = |mut ids: Vec<u16>, item: Item| {
+            ids.push(item.id);
+            ids
+        };
+        let ids = wal.fold(&saves, Vec::new(), push).unwrap();
+        assert_eq!(ids, vec![0, 1, 2, 3, 4]);
+        // saved once per file holding logs, and once for the buffer
+        let nexts = saves
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|s| s.1)
+            .collect::<Vec<_>>();
+        assert_eq!(nexts, vec![2, 4, 5]);
+
+        // folding again resumes from the saved state
+        wal.write(Item { id: 5 });
+        let ids = wal.fold(&saves, Vec::new(), push).unwrap();
+        assert_eq!(ids, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(saves.0.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn replay_into() {
+        let dir = clear_storage("replay_into");
+        let wal = Wal::new(&dir, 100).unwrap();
+        // two logs fill a file, and the last one waits in the buffer
+        for i in 0..5 {
+            wal.write(Item { id: i });
+            if i < 4 {
+                wal.flush().unwrap();
+            }
+        }
+        let mut ids = Vec::new();
+        let replayed = wal.replay_into(|item: Item| {
+            ids.push(item.id);
+            ControlFlow::Continue(())
+        });
+        assert_eq!(replayed.unwrap(), 5);
+        assert_eq!(ids, vec![0, 1, 2, 3, 4]);
+
+        // stops at the log `f` breaks at
+        let mut ids = Vec::new();
+        let replayed = wal.replay_into(|item: Item| {
+            ids.push(item.id);
+            match item.id {
+                2 => ControlFlow::Break(()),
+                _ => ControlFlow::Continue(()),
+            }
+        });
+        assert_eq!(replayed.unwrap(), 3);
+        assert_eq!(ids, vec![0, 1, 2]);
+
+        // the WAL can be used while replaying
+        let replayed = wal.replay_into(|_: Item| {
+            assert_eq!(wal.read().unwrap().len(), 5);
+            ControlFlow::Break(())
+        });
+        assert_eq!(replayed.unwrap(), 1);
+    }
+
+    #[test]
+    fn replay() {
+        let dir = clear_storage("replay");
+        let wal = Wal::new(&dir, 1000).unwrap();
+        for i in 0..3 {
+            wal.write(Item { id: i });
+        }
+        // logs written 40ms apart, replayed twice as fast
+        let at = |item: &Item| SystemTime::UNIX_EPOCH + Duration::from_millis(item.id as u64 * 40);
+        let started = std::time::Instant::now();
+        let ids = wal
+            .replay()
+            .unwrap()
+            .timestamp(at)
+            .speed(2.0)
+            .map(|i| i.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![0, 1, 2]);
+        assert!(started.elapsed() >= Duration::from_millis(40));
+        // logs without stamps are returned right away
+        let started = std::time::Instant::now();
+        assert_eq!(wal.replay().unwrap().count(), 3);
+        assert!(started.elapsed() < Duration::from_millis(40));
+
+        // stamped logs are paced by their stamps
+        let dir = clear_storage("replay_stamped");
+        let wal = WalBuilder::new(&dir, 1000)
+            .timestamps(true)
+            .build()
+            .unwrap();
+        for i in 0..3 {
+            if i > 0 {
+                std::thread::sleep(Duration::from_millis(40));
+            }
+            wal.write(Item { id: i });
+        }
+        let started = std::time::Instant::now();
+        let ids = wal.replay().unwrap().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![0, 1, 2]);
+        assert!(started.elapsed() >= Duration::from_millis(70));
+    }
+
+    #[test]
+    fn group_commit() {
+        let dir = clear_storage("group_commit");
+        let wal = WalBuilder::new(&dir, 100_000)
+            .wake_strategy(WakeStrategy::GroupCommit {
+                within: Duration::from_millis(300),
+                bytes: 20,
+            })
+            .build()
+            .unwrap();
+        // ten logs of two bytes complete a group before its time is up
+        for i in 0..10 {
+            wal.write(Item { id: i });
+        }
+        sleep(Duration::from_millis(100));
+        let metrics = wal.metrics();
+        assert_eq!((metrics.entries_written, metrics.flushes), (10, 1));
+        // a smaller group is written once its time is up
+        wal.write(Item { id: 10 });
+        sleep(Duration::from_millis(100));
+        assert_eq!(wal.metrics().entries_written, 10);
+        sleep(Duration::from_millis(400));
+        let metrics = wal.metrics();
+        assert_eq!((metrics.entries_written, metrics.flushes), (11, 2));
+        assert_eq!(wal.read().unwrap().len(), 11);
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn compress_large_logs() {
+        let dir = clear_storage("compress_large_logs");
+        let wal = WalBuilder::new(&dir, 1000)
+            .compress_above(64)
+            .build()
+            .unwrap();
+        let small = "small".to_string();
+        let large = "large".repeat(100);
+        wal.batch_write(vec![small.clone(), large.clone()]);
+        sleep(Duration::from_millis(500));
+        // only the large log is compressed
+        let size = std::fs::metadata(format!("{}wal_1", dir)).unwrap().len() as usize;
+        assert!(size < format::estimate_disk_size([small.len() + 8, large.len() + 8]));
+        assert_eq!(wal.read().unwrap(), vec![small, large]);
+    }
+
+    #[cfg(all(feature = "lz4", feature = "deflate"))]
+    #[test]
+    fn mixed_codecs() {
+        let dir = clear_storage("mixed_codecs");
+        let large = |i: usize| format!("{}{}", i, "large".repeat(100));
+        let open = |codec| {
+            WalBuilder::new(&dir, 1000)
+                .compress_above(64)
+                .compression_codec(codec)
+                .build()
+                .unwrap()
+        };
+        let wal = open(CompressionCodec::Lz4);
+        wal.batch_write(vec![large(1), large(2)]);
+        wal.close().unwrap();
+        // the codec changes between runs
+        let wal = open(CompressionCodec::Deflate);
+        wal.batch_write(vec![large(3)]);
+        wal.flush().unwrap();
+        assert_eq!(wal.read().unwrap(), vec![large(1), large(2), large(3)]);
+    }
+
+    #[test]
+    fn format_header() {
+        let dir = clear_storage("format_header");
+        let wal = Wal::new(&dir, 1000).unwrap();
+        wal.batch_write((1..=3).map(|i| Item { id: i }).collect());
+        wal.close().unwrap();
+        let path = format!("{}wal_1", dir);
+        let content = std::fs::read(&path).unwrap();
+        assert_eq!(content[..4], format::SEGMENT_MAGIC);
+        assert_eq!(content[6] & format::SEGMENT_CHECKSUMMED, 0);
+
+        // files written before headers are still read
+        std::fs::write(&path, &content[format::SEGMENT_HEADER_BYTES..]).unwrap();
+        let wal = Wal::<Item>::new(&dir, 1000).unwrap();
+        assert_eq!(wal.read().unwrap().len(), 3);
+        drop(wal);
+
+        // files written in a newer format are never misread
+        let mut newer = content.clone();
+        newer[4] = format::FORMAT_VERSION + 1;
+        std::fs::write(&path, &newer).unwrap();
+        let opened = Wal::<Item>::new(&dir, 1000);
+        assert!(matches!(opened, Err(WalError::Unsupported(_))));
+    }
+
+    #[test]
+    fn big_endian_files() {
+        let dir = clear_storage("big_endian_files");
+        let wal = Wal::new(&dir, 1000).unwrap();
+        wal.batch_write((1..=3).map(|i| Item { id: i }).collect());
+        wal.close().unwrap();
+        let path = format!("{}wal_1", dir);
+        let content = std::fs::read(&path).unwrap();
+        // frames are written in little endian on every machine
+        assert_eq!(content[4], format::FORMAT_VERSION);
+        assert_eq!(content[5], 1);
+
+        // a file of version 1 copied from a big endian machine
+        let mut copied = vec![0x89, b'W', b'A', b'L', 1, 2, 0, 0];
+        let mut offset = format::SEGMENT_HEADER_BYTES;
+        while offset < content.len() {
+            let prefix = u32::from_le_bytes(content[offset..offset + 4].try_into().unwrap());
+            let lsn = u64::from_le_bytes(content[offset + 4..offset + 12].try_into().unwrap());
+            let end = offset + format::frame_size((prefix & format::LENGTH_MASK) as usize);
+            copied.extend(prefix.to_be_bytes());
+            copied.extend(lsn.to_be_bytes());
+            copied.extend(&content[offset + format::frame_overhead_bytes()..end]);
+            offset = end;
+        }
+        std::fs::write(&path, &copied).unwrap();
+        let wal = Wal::<Item>::new(&dir, 1000).unwrap();
+        assert_eq!(wal.read().unwrap().len(), 3);
+        // the file is converted before logs are appended to it
+        wal.write(Item { id: 4 });
+        wal.close().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap()[..content.len()], content[..]);
+        let wal = Wal::<Item>::new(&dir, 1000).unwrap();
+        let ids = wal
+            .read()
+            .unwrap()
+            .into_iter()
+            .map(|i| i.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn wal_group() {
+        let orders = Wal::new(&clear_storage("wal_group_orders"), 10_000).unwrap();
+        let payments = Wal::new(&clear_storage("wal_group_payments"), 10_000).unwrap();
+        let dir = clear_storage("wal_group_coordinator");
+        let group = WalGroup::new(Wal::new(&dir, 10_000).unwrap())
+            .member("orders", &orders)
+            .member("payments", &payments);
+        assert_eq!(group.last_commit().unwrap(), None);
+
+        orders.batch_write((1..=3).map(|i| Item { id: i }).collect());
+        let commit = group.flush().unwrap();
+        assert_eq!(commit.durable_lsn("orders"), Some(2));
+        assert_eq!(commit.durable_lsn("payments"), None);
+        assert_eq!(orders.read().unwrap().len(), 3);
+
+        payments.write(Item { id: 10 });
+        orders.write(Item { id: 4 });
+        group.flush().unwrap();
+        // logs written after the last commit are not part of the recovery point
+        orders.write(Item { id: 5 });
+        orders.flush().unwrap();
+        drop(group);
+
+        let coordinator = Wal::<GroupCommit>::new(&dir, 10_000).unwrap();
+        let commit = WalGroup::new(coordinator).last_commit().unwrap().unwrap();
+        assert_eq!(
+            commit.members,
+            vec![
+                ("orders".to_string(), Some(3)),
+                ("payments".to_string(), Some(0))
+            ]
+        );
+    }
+
+    #[test]
+    fn metrics() {
+        let dir = clear_storage("metrics");
+        let wal = Wal::new(&dir, 100).unwrap();
+        assert_eq!(wal.metrics(), WalMetrics::default());
+        wal.batch_write((1..=30).map(|id| Item { id }).collect());
+        wal.flush().unwrap();
+        assert_eq!(wal.read().unwrap().len(), 30);
+
+        let metrics = wal.clone().metrics();
+        assert_eq!(metrics.entries_written, 30);
+        assert!(metrics.bytes_written >= 30 * 2);
+        assert!(metrics.flushes >= 1 && metrics.last_sync_latency.is_some());
+        // 30 logs don't fit in a single file of a 100 bytes WAL
+        assert!(metrics.rotations >= 1);
+        assert_eq!(metrics.buffer_depth.logs, 0);
+        assert_eq!(metrics.reads, 1);
+    }
+
+    #[test]
+    fn status() {
+        let dir = clear_storage("status");
+        let wal = WalBuilder::new(&dir, 1000)
+            .timestamps(true)
+            .build()
+            .unwrap();
+        let status = wal.status().unwrap();
+        assert_eq!((status.current_file, status.lsns), (1, None));
+        assert_eq!(status.until_rotation, None);
+
+        for id in 0..40 {
+            wal.write(Item { id });
+            sleep(Duration::from_millis(5));
+        }
+        wal.flush().unwrap();
+        let status = wal.status().unwrap();
+        assert!(status.current_file > 1);
+        assert_eq!(status.files.last().map(|f| f.id), Some(status.current_file));
+        for file in &status.files {
+            let size = std::fs::metadata(format!("{}wal_{}", dir, file.id)).unwrap();
+            assert_eq!(file.bytes, size.len());
+        }
+        let total = status.files.iter().map(|f| f.bytes).sum::<u64>();
+        assert_eq!(status.disk_bytes, total);
+        assert_eq!(status.lsns, Some(0..=39));
+        let times = status.times.unwrap();
+        assert!(times.start() < times.end() && *times.end() <= SystemTime::now());
+        assert!(status
+            .until_rotation
+            .is_some_and(|d| d < Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn handle_stats() {
+        let dir = clear_storage("handle_stats");
+        let wal = WalBuilder::new(&dir, 1000)
+            .strict(Validation::new().max_payload_bytes(2))
+            .build()
+            .unwrap();
+        let ingest = wal.labeled("ingest");
+        let clone = ingest.clone();
+        wal.write(Item { id: 1 });
+        ingest.batch_write((1..=3).map(|i| Item { id: i }).collect());
+        clone.write(Item { id: 4 });
+        // too large for the rules of the WAL
+        assert!(clone.try_write_raw(&[1, 2, 3]).is_err());
+        wal.labeled("audit").try_write_raw(&[7]).unwrap();
+
+        let stats = wal.handle_stats();
+        let labels = stats.iter().map(|s| s.label.as_deref()).collect::<Vec<_>>();
+        assert_eq!(labels, vec![None, Some("ingest"), Some("audit")]);
+        assert_eq!(
+            (stats[0].entries, stats[0].bytes, stats[0].errors),
+            (1, 2, 0)
+        );
+        assert_eq!(
+            (stats[1].entries, stats[1].bytes, stats[1].errors),
+            (4, 8, 1)
+        );
+        assert_eq!(stats[2].entries, 1);
+    }
+
+    #[cfg(feature = "chaos")]
+    #[test]
+    fn chaos() {
+        let dir = clear_storage("chaos");
+        let failures = Arc::new(Mutex::new(Vec::new()));
+        let wal = WalBuilder::new(&dir, 10_000)
+            .chaos(Chaos::new().fail_syncs(1.0))
+            .on_error({
+                let failures = failures.clone();
+                move |f| failures.lock().unwrap().push(f.operation)
+            })
+            .build()
+            .unwrap();
+        assert!(wal.last_error().is_none());
+        wal.write(Item { id: 1 });
+        assert!(wal.flush().is_err());
+        let failure = wal.last_error().unwrap();
+        assert_eq!(failure.operation, WriteOperation::Sync);
+        assert_eq!(*failures.lock().unwrap(), [WriteOperation::Sync]);
+        // logs are written, only their sync failed
+        assert_eq!(wal.read().unwrap().len(), 1);
+        drop(wal);
+
+        let dir = clear_storage("chaos_slow");
+        let wal = WalBuilder::new(&dir, 10_000)
+            .chaos(
+                Chaos::new()
+                    .slow_disk(1000)
+                    .delay_flushes(Duration::from_millis(10))
+                    .seed(3),
+            )
+            .build()
+            .unwrap();
+        let started = std::time::Instant::now();
+        wal.batch_write((1..=10).map(|i| Item { id: i }).collect());
+        wal.flush().unwrap();
+        // 10 frames of 14 bytes at 1000 bytes per second
+        assert!(started.elapsed() >= Duration::from_millis(140));
+    }
+
+    #[test]
+    fn nth_from_end() {
+        let dir = clear_storage("nth_from_end");
+        let wal = WalBuilder::new(&dir, 100).build().unwrap();
+        assert!(wal.nth_from_end(0).unwrap().is_none());
+        wal.batch_write((0..250).map(|id| Item { id }).collect());
+        wal.flush().unwrap();
+        assert_eq!(wal.nth_from_end(0).unwrap().map(|i| i.id), Some(249));
+        assert_eq!(wal.nth_from_end(1).unwrap().map(|i| i.id), Some(248));
+        assert_eq!(wal.nth_from_end(249).unwrap().map(|i| i.id), Some(0));
+        assert!(wal.nth_from_end(250).unwrap().is_none());
+        assert!(wal.nth_from_end(usize::MAX).unwrap().is_none());
+    }
+
+    #[test]
+    fn consumer() {
+        let dir = clear_storage("consumer");
+        let wal = WalBuilder::new(&dir, 1000).build().unwrap();
+        for id in 0..10 {
+            wal.write(Item { id });
+        }
+        let ids = |batch: Vec<(Lsn, Item)>| batch.iter().map(|(_, i)| i.id).collect::<Vec<_>>();
+        let mut analytics = wal.consumer("analytics").unwrap();
+        let mut indexer = wal.consumer("indexer").unwrap();
+        assert_eq!(ids(analytics.next_batch(4).unwrap()), vec![0, 1, 2, 3]);
+        assert_eq!(ids(analytics.next_batch(4).unwrap()), vec![4, 5, 6, 7]);
+        analytics.commit(5).unwrap();
+        // consumers move independently
+        assert_eq!(ids(indexer.next_batch(2).unwrap()), vec![0, 1]);
+        assert_eq!(analytics.committed(), 6);
+        assert_eq!(analytics.position(), 8);
+
+        // a consumer opened again resumes after its committed logs
+        drop((analytics, indexer));
+        wal.close().unwrap();
+        let wal = WalBuilder::<Item>::new(&dir, 1000).build().unwrap();
+        let mut analytics = wal.consumer("analytics").unwrap();
+        assert_eq!(ids(analytics.next_batch(10).unwrap()), vec![6, 7, 8, 9]);
+        assert!(analytics.next_batch(10).unwrap().is_empty());
+        analytics.seek(2);
+        assert_eq!(ids(analytics.next_batch(1).unwrap()), vec![2]);
+        let mut indexer = wal.consumer("indexer").unwrap();
+        assert_eq!(indexer.next_batch(1).unwrap()[0].0, 0);
+
+        for name in ["", "a/b", "..\\x"] {
+            assert!(matches!(wal.consumer(name), Err(WalError::Unsupported(_))));
+        }
+    }
+
+    #[test]
+    fn subscribe() {
+        let dir = clear_storage("subscribe");
+        let wal = Wal::new(&dir, 10_000).unwrap();
+        wal.write(Item { id: 0 });
+        wal.flush().unwrap();
+        let mut changes = wal.subscribe();
+        let writer = wal.clone();
+        let producer = std::thread::spawn(move || {
+            for i in 1..=5 {
+                writer.write(Item { id: i });
+                sleep(Duration::from_millis(5));
+            }
+            writer.batch_write((6..=8).map(|i| Item { id: i }).collect());
+        });
+        // logs written before subscribing are not delivered
+        let ids = changes.by_ref().take(8).map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, (1..=8).collect::<Vec<_>>());
+        producer.join().unwrap();
+        assert!(changes.next_timeout(Duration::from_millis(20)).is_none());
+
+        // the subscription ends once the WAL is closed
+        wal.write(Item { id: 9 });
+        drop(wal);
+        assert_eq!(changes.map(|i| i.id).collect::<Vec<_>>(), vec![9]);
+    }
+
+    #[test]
+    fn read_projected() {
+        #[derive(Serialize, Deserialize)]
+        struct Wide {
+            id: usize,
+            name: String,
+            tags: Vec<String>,
+        }
+        let wide = |id| Wide {
+            id,
+            name: format!("item {}", id),
+            tags: vec!["large".repeat(20); 5],
+        };
+
+        let dir = clear_storage("read_projected");
+        let wal = Wal::new(&dir, 10_000).unwrap();
+        wal.batch_write((1..=3).map(wide).collect());
+        wal.flush().unwrap();
+        // bincode projects onto the leading fields
+        let ids = wal.read_projected::<Item>().unwrap();
+        assert_eq!(ids.iter().map(|i| i.id).collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        #[cfg(feature = "json")]
+        {
+            #[derive(Deserialize)]
+            struct Name {
+                name: String,
+            }
+            let dir = clear_storage("read_projected_json");
+            let wal = WalBuilder::new(&dir, 10_000)
+                .serialization_codec(SerializationCodec::Json)
+                .build()
+                .unwrap();
+            wal.batch_write((1..=3).map(wide).collect());
+            wal.flush().unwrap();
+            // any field with named fields
+            let names = wal.read_projected::<Name>().unwrap();
+            assert_eq!(names[1].name, "item 2");
+        }
+    }
+
+    #[test]
+    fn torn_write() {
+        let dir = clear_storage("torn_write");
+        let wal = Wal::new(&dir, 1000).unwrap();
+        wal.batch_write((1..=3).map(|i| Item { id: i }).collect());
+        wal.close().unwrap();
+        // a crash left half a frame at the end of the file
+        let path = format!("{}wal_1", dir);
+        let mut content = std::fs::read(&path).unwrap();
+        let header = format::SEGMENT_HEADER_BYTES;
+        let frame = (content.len() - header) / 3;
+        content.extend_from_within(header..header + frame / 2);
+        std::fs::write(&path, &content).unwrap();
+
+        let wal = Wal::new(&dir, 1000).unwrap();
+        let size = std::fs::metadata(&path).unwrap().len() as usize;
+        assert_eq!(size, header + frame * 3);
+        wal.write(Item { id: 4 });
+        wal.flush().unwrap();
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn entries_since() {
+        let dir = clear_storage("entries_since");
+        let wal = Wal::new(&dir, 1000).unwrap();
+        wal.batch_write((1..=5).map(|i| Item { id: i }).collect());
+        sleep(Duration::from_millis(200));
+        assert_eq!(wal.entries_since(None).unwrap().len(), 5);
+        // nothing new
+        assert!(wal.entries_since(None).unwrap().is_empty());
+        wal.batch_write((6..=8).map(|i| Item { id: i }).collect());
+        sleep(Duration::from_millis(200));
+        let ids = wal
+            .entries_since(None)
+            .unwrap()
+            .iter()
+            .map(|i| i.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![6, 7, 8]);
+        // explicit position
+        assert_eq!(wal.entries_since(Some(6)).unwrap().len(), 2);
+
+        // position by time
+        let dir = clear_storage("entries_since_time");
+        let wal = WalBuilder::new(&dir, 1000)
+            .timestamps(true)
+            .build()
+            .unwrap();
+        wal.batch_write((1..=3).map(|i| Item { id: i }).collect());
+        wal.flush().unwrap();
+        sleep(Duration::from_millis(20));
+        let at = SystemTime::now();
+        wal.batch_write((4..=5).map(|i| Item { id: i }).collect());
+        wal.flush().unwrap();
+        let ids = wal.entries_since_time(at).unwrap();
+        assert_eq!(ids.iter().map(|i| i.id).collect::<Vec<_>>(), vec![4, 5]);
+        assert!(wal.entries_since(None).unwrap().is_empty());
+        assert_eq!(
+            wal.entries_since_time(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .len(),
+            5
+        );
+        // nothing written since moves the cursor to the end
+        let later = SystemTime::now() + Duration::from_secs(60);
+        assert!(wal.entries_since_time(later).unwrap().is_empty());
+        wal.write(Item { id: 6 });
+        wal.flush().unwrap();
+        assert_eq!(wal.entries_since(None).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn read_unflushed() {
+        // logs wait in the buffer for a while before the writer takes them
+        let wait = WakeStrategy::MicroBatch(Duration::from_secs(2));
+        let dir = clear_storage("read_unflushed");
+        let wal = WalBuilder::new(&dir, 1000)
+            .wake_strategy(wait)
+            .build()
+            .unwrap();
+        wal.write(1u32);
+        assert_eq!(wal.read().unwrap(), vec![1]);
+        assert_eq!(wal.entries_since(None).unwrap(), vec![1]);
+        assert_eq!(wal.read_last(1).unwrap(), vec![1]);
+
+        let dir = clear_storage("read_flushed_only");
+        let wal = WalBuilder::new(&dir, 1000)
+            .wake_strategy(wait)
+            .read_unflushed(false)
+            .build()
+            .unwrap();
+        wal.write(1u32);
+        assert!(wal.read().unwrap().is_empty());
+        assert!(wal.read_last(1).unwrap().is_empty());
+        wal.flush().unwrap();
+        assert_eq!(wal.read().unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn lsn_continues_after_reopen() {
+        let dir = clear_storage("lsn_continues_after_reopen");
+        let wal = Wal::new(&dir, 1000).unwrap();
+        let lsn = wal
+            .try_write_all(&[Item { id: 1 }, Item { id: 2 }])
+            .unwrap();
+        assert_eq!(lsn, 0..2);
+        sleep(Duration::from_millis(200));
+        drop(wal);
+        let wal = Wal::new(&dir, 1000).unwrap();
+        let lsn = wal.try_write_all(&[Item { id: 3 }]).unwrap();
+        assert_eq!(lsn, 2..3);
+        // plain writes report their sequence numbers too
+        assert_eq!(wal.write(Item { id: 4 }), Some(3));
+        assert_eq!(
+            wal.batch_write(vec![Item { id: 5 }, Item { id: 6 }]),
+            Some(4..6)
+        );
+        assert_eq!(wal.batch_write(Vec::new()), None);
+        wal.flush().unwrap();
+        let lsns = wal.read_attributed().unwrap();
+        let lsns = lsns.iter().map(|l| l.lsn).collect::<Vec<_>>();
+        assert_eq!(lsns, (0..6).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn read_bounded() {
+        let dir = clear_storage("read_bounded");
+        let wal = WalBuilder::new(&dir, 1000)
+            .read_memory_cap(20)
+            .build()
+            .unwrap();
+        wal.batch_write((1..=100).map(|i| Item { id: i }).collect());
+        sleep(Duration::from_millis(200));
+        let data = wal.read_bounded().unwrap();
+        assert!(data.is_spilled());
+        let ids = data.map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, (1..=100).collect::<Vec<_>>());
+        drop(wal);
+        // within the cap, logs stay in memory
+        let wal = WalBuilder::<Item>::new(&dir, 1000)
+            .read_memory_cap(1000)
+            .build()
+            .unwrap();
+        let data = wal.read_bounded().unwrap();
+        assert!(!data.is_spilled());
+        assert_eq!(data.count(), 100);
+    }
+
+    #[test]
+    fn strict_mode() {
+        let dir = clear_storage("strict_mode");
+        let rules = Validation::new().max_payload_bytes(1).schema_version(3);
+        let wal = WalBuilder::new(&dir, 1000)
+            .strict(rules.clone())
+            .build()
+            .unwrap();
+        assert!(wal.try_write(&Item { id: 1 }).is_err());
+        assert!(wal.try_write_all(&[Item { id: 1 }]).is_err());
+        drop(wal);
+        // the directory now declares version 3
+        let rules = rules.schema_version(4);
+        let result = WalBuilder::<Item>::new(&dir, 1000).strict(rules).build();
+        assert!(matches!(
+            result,
+            Err(WalError::Rejected(Rejection::SchemaMismatch {
+                expected: 4,
+                found: 3
+            }))
+        ));
+    }
+
+    #[test]
+    fn fixed_size_records() {
+        let dir = clear_storage("fixed_size_records");
+        let wal = WalBuilder::new(&dir, 100)
+            .fixed_record_size(4)
+            .build()
+            .unwrap();
+        // payloads longer than the record are rejected
+        assert!(wal.try_write(&Item { id: 1 }).is_ok());
+        let other = clear_storage("fixed_size_records_other");
+        assert!(WalBuilder::<u64>::new(&other, 100)
+            .fixed_record_size(4)
+            .build()
+            .unwrap()
+            .try_write(&1)
+            .is_err());
+        // two records fit in each file, after the header
+        for i in 2..=12 {
+            wal.write(Item { id: i });
+            sleep(Duration::from_millis(5));
+        }
+        sleep(Duration::from_millis(200));
+        // records have no length prefix
+        let size = std::fs::metadata(format!("{}wal_1", dir)).unwrap().len();
+        let header = format::SEGMENT_HEADER_BYTES as u64;
+        assert_eq!((size - header) % format::fixed_frame_size(4) as u64, 0);
+        assert_eq!(wal.get(4).unwrap().unwrap().id, 5);
+        assert_eq!(wal.get(9).unwrap().unwrap().id, 10);
+        assert!(wal.get(100).unwrap().is_none());
+        let ids = wal
+            .read_last(5)
+            .unwrap()
+            .iter()
+            .map(|i| i.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![8, 9, 10, 11, 12]);
+        let ids = wal
+            .read_from(9)
+            .unwrap()
+            .iter()
+            .map(|i| i.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![10, 11, 12]);
+    }
+
+    #[test]
+    fn errors() {
+        let dir = clear_storage("errors");
+        std::fs::write(
+            format!("{}meta", dir),
+            meta::seal("2 120").replace('2', "3"),
+        )
+        .unwrap();
+        let error = meta::Meta::read(Path::new(&dir)).unwrap_err();
+        assert!(matches!(
+            &error,
+            WalError::Corruption { segment: None, offset: None, path, .. }
+                if path.ends_with("meta")
+        ));
+        assert!(error.to_string().contains("checksum"));
+
+        // the io error is kept as the source
+        let missing = std::io::Error::new(std::io::ErrorKind::NotFound, "gone");
+        let error: Box<dyn std::error::Error> = Box::new(WalError::from(missing));
+        assert!(error.to_string().ends_with("gone"));
+        assert_eq!(error.source().unwrap().to_string(), "gone");
+        let error = Wal::<Item>::new(&format!("{}missing/dir", dir), 100)
+            .err()
+            .unwrap();
+        assert!(matches!(error, WalError::Io { .. }));
+    }
+
+    #[test]
+    fn read_without_meta() {
+        let dir = clear_storage("read_without_meta");
+        let wal = Wal::new(&dir, 100).unwrap();
+        // two logs fill a file
+        for i in 0..5 {
+            wal.write(Item { id: i });
+        }
+        wal.flush().unwrap();
+        let ids = |wal: &Wal<Item>| wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+
+        // the current file is found by its logs when meta is damaged or missing
+        std::fs::write(format!("{}meta", dir), "").unwrap();
+        assert_eq!(ids(&wal), vec![0, 1, 2, 3, 4]);
+        assert_eq!(wal.read_last(2).unwrap().len(), 2);
+        std::fs::remove_file(format!("{}meta", dir)).unwrap();
+        assert_eq!(ids(&wal), vec![0, 1, 2, 3, 4]);
+        let reader = WalBuilder::new(&dir, 100).read_only(true).build().unwrap();
+        assert_eq!(ids(&reader), vec![0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn verify_and_repair() {
+        let dir = clear_storage("verify_and_repair");
+        let wal = WalBuilder::new(&dir, 100)
+            .verify_on_rotation(true)
+            .build()
+            .unwrap();
+        // two logs fill a file
+        for i in 0..5 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        let report = wal.verify().unwrap();
+        assert!(report.is_healthy());
+        assert_eq!(report.entries, 5);
+        let ids = |report: &VerifyReport| report.segments.iter().map(|s| s.id).collect::<Vec<_>>();
+        assert_eq!(ids(&report), vec![1, 2, 3]);
+
+        // garbage at the end of a sealed file
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(format!("{}wal_1", dir))
+            .unwrap();
+        file.write_all(&[0xff; 5]).unwrap();
+        let report = wal.verify().unwrap();
+        assert!(!report.is_healthy());
+        assert_eq!((report.entries, report.wasted_bytes), (5, 5));
+        assert!(report.segments[0].digest_mismatch);
+
+        let report = wal.repair().unwrap();
+        assert_eq!(report.repaired, vec![1]);
+        assert!(report.is_healthy());
+        assert_eq!(wal.read().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn open_existing() {
+        let dir = clear_storage("open_existing");
+        assert!(matches!(
+            Wal::<Item>::open(&dir, 100),
+            Err(WalError::File(_))
+        ));
+        let wal = Wal::create(&dir, 100).unwrap();
+        // two logs fill a file
+        for i in 0..5 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        drop(wal);
+
+        // logs are appended to the current file rather than the first one
+        let first = std::fs::read(format!("{}wal_1", dir)).unwrap();
+        let wal = Wal::<Item>::open(&dir, 100).unwrap();
+        wal.write(Item { id: 5 });
+        wal.flush().unwrap();
+        assert_eq!(std::fs::read(format!("{}wal_1", dir)).unwrap(), first);
+        let ids = |wal: &Wal<Item>| wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids(&wal), (0..6).collect::<Vec<_>>());
+        drop(wal);
+
+        let wal = Wal::<Item>::create(&dir, 100).unwrap();
+        assert!(ids(&wal).is_empty());
+    }
+
+    #[test]
+    fn verify_on_rotation() {
+        let dir = clear_storage("verify_on_rotation");
+        let wal = WalBuilder::new(&dir, 100)
+            .verify_on_rotation(true)
+            .build()
+            .unwrap();
+        // two logs fill a file
+        for i in 1..=4 {
+            wal.write(Item { id: i });
+            sleep(Duration::from_millis(20));
+        }
+        sleep(Duration::from_millis(100));
+        let digests = wal.segment_digests().unwrap();
+        assert_eq!(digests.iter().map(|d| d.id).collect::<Vec<_>>(), vec![1, 2]);
+        let content = std::fs::read(format!("{}wal_1", dir)).unwrap();
+        assert_eq!(digests[0].crc32, checksum::crc32(&content));
+        assert_eq!(digests[0].size, content.len() as u64);
+        assert!(digests.iter().all(|d| d.verified && d.entries == 2));
+        // digests are kept across restarts, as logs are appended to the current file
+        drop(wal);
+        let wal = Wal::<Item>::new(&dir, 100).unwrap();
+        assert_eq!(wal.segment_digests().unwrap(), digests);
+    }
+
+    #[test]
+    fn read_scratch_budget() {
+        let dir = clear_storage("read_scratch_budget");
+        let wal = WalBuilder::new(&dir, 1000)
+            .read_scratch_budget(16)
+            .build()
+            .unwrap();
+        for i in 0..20 {
+            wal.write(Item { id: i });
+        }
+        sleep(Duration::from_millis(50));
+        // the buffer is reused by every read, while memory beyond the budget is released
+        for _ in 0..3 {
+            let ids = wal
+                .read_last(2)
+                .unwrap()
+                .iter()
+                .map(|i| i.id)
+                .collect::<Vec<_>>();
+            assert_eq!(ids, vec![18, 19]);
+            assert!(wal.read_lock.lock().unwrap().capacity() <= 16);
+        }
+        assert_eq!(wal.get(7).unwrap().unwrap().id, 7);
+        assert_eq!(wal.read().unwrap().len(), 20);
+    }
+
+    #[test]
+    fn writer_dead() {
+        let dir = clear_storage("writer_dead");
+        let mut wal = Wal::new(&dir, 100).unwrap();
+        wal.write(Item { id: 1 });
+        sleep(Duration::from_millis(100));
+        assert_eq!(wal.read().unwrap().len(), 1);
+        // replace the writer with one that has died
+        wal.writer = Some(WriterHandle::spawn(None, || panic!("writer died")).unwrap());
+        while !wal.writer.as_ref().unwrap().is_finished() {
+            sleep(Duration::from_millis(1));
+        }
+        assert!(matches!(wal.read(), Err(WalError::WriterDead(_))));
+        assert!(matches!(wal.get(0), Err(WalError::WriterDead(_))));
+    }
+
+    #[test]
+    fn restart_writer() {
+        // the writer panics while sealing the first file
+        let build = |dir: &str, restarts: usize| {
+            let panicked = AtomicBool::new(false);
+            WalBuilder::new(dir, 100)
+                .on_seal(move |_| {
+                    if !panicked.swap(true, Ordering::Relaxed) {
+                        panic!("seal failed");
+                    }
+                })
+                .restart_writer(restarts)
+                .build()
+                .unwrap()
+        };
+        let dir = clear_storage("restart_writer_dead");
+        let wal: Wal<Item> = build(&dir, 0);
+        for i in 0..3 {
+            wal.write(Item { id: i });
+        }
+        // once given up, logs are refused instead of piling up in the buffer
+        assert!(matches!(wal.flush(), Err(WalError::WriterDead(_))));
+        assert!(matches!(
+            wal.try_write(&Item { id: 3 }),
+            Err(WalError::WriterDead(_))
+        ));
+        let failure = wal.last_error().unwrap();
+        assert_eq!(failure.operation, WriteOperation::Panic);
+        assert!(
+            failure.message.contains("seal failed"),
+            "{}",
+            failure.message
+        );
+
+        // a restarted writer picks up from the files
+        let dir = clear_storage("restart_writer");
+        let wal: Wal<Item> = build(&dir, 1);
+        for i in 0..3 {
+            wal.write(Item { id: i });
+        }
+        // the flush may be answered by the restarted writer
+        let _ = wal.flush();
+        assert_eq!(wal.last_error().unwrap().operation, WriteOperation::Panic);
+        for i in 3..6 {
+            wal.write(Item { id: i });
+        }
+        wal.flush().unwrap();
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids[..2], [0, 1]);
+        assert_eq!(ids[ids.len() - 3..], [3, 4, 5]);
+    }
+
+    #[test]
+    fn max_entry_size() {
+        let dir = clear_storage("max_entry_size");
+        let wal = WalBuilder::<String>::new(&dir, 100)
+            .max_entry_size(32)
+            .build()
+            .unwrap();
+        assert!(matches!(
+            wal.try_write(&"x".repeat(40)),
+            Err(WalError::Rejected(Rejection::PayloadTooLarge {
+                limit: 32,
+                ..
+            }))
+        ));
+        assert!(wal.try_write(&"x".repeat(20)).is_ok());
+        drop(wal);
+
+        // without a limit, a log larger than a file doesn't overflow a file holding logs
+        let dir = clear_storage("max_entry_size_unlimited");
+        let wal = WalBuilder::<String>::new(&dir, 100).build().unwrap();
+        for log in ["a".to_string(), "x".repeat(40), "b".to_string()] {
+            wal.write(log);
+            wal.flush().unwrap();
+        }
+        let sizes = wal
+            .read_segments()
+            .unwrap()
+            .iter()
+            .map(|s| s.entries.iter().map(|e| e.len()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(sizes[..3], [vec![1], vec![40], vec![1]]);
+    }
+
+    #[test]
+    fn per_segment_manifest() {
+        let dir = clear_storage("per_segment_manifest");
+        let wal = WalBuilder::new(&dir, 100)
+            .manifest(ManifestKind::PerSegment)
+            .verify_on_rotation(true)
+            .build()
+            .unwrap();
+        for i in 1..=4 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        assert!(!Path::new(&format!("{}meta", dir)).exists());
+        assert_eq!(wal.read().unwrap().len(), 4);
+        let digests = wal.segment_digests().unwrap();
+        assert_eq!(digests.len(), 2);
+        drop(wal);
+
+        // the layout is detected when opened again
+        let wal = Wal::<Item>::new(&dir, 100).unwrap();
+        assert!(!Path::new(&format!("{}meta", dir)).exists());
+        assert_eq!(wal.segment_digests().unwrap(), digests);
+    }
+
+    #[test]
+    fn parallel_decode() {
+        let dir = clear_storage("parallel_decode");
+        let wal = WalBuilder::new(&dir, 1000)
+            .parallel_decode(4, 64)
+            .build()
+            .unwrap();
+        let items = (0..50).map(|id| Item { id }).collect::<Vec<_>>();
+        wal.batch_write(items);
+        sleep(Duration::from_millis(100));
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, (0..50).collect::<Vec<_>>());
+        let ids = wal
+            .read_from(20)
+            .unwrap()
+            .iter()
+            .map(|i| i.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, (20..50).collect::<Vec<_>>());
+        let ids = wal
+            .read_segments()
+            .unwrap()
+            .iter()
+            .flat_map(|s| s.entries.iter().map(|i| i.id))
+            .collect::<Vec<_>>();
+        assert_eq!(ids, (0..50).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn on_evict() {
+        let dir = clear_storage("on_evict");
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let log = evicted.clone();
+        let wal = WalBuilder::new(&dir, 100)
+            .on_evict(move |e| log.lock().unwrap().push(e.clone()))
+            .build()
+            .unwrap();
+        // two logs fill a file, so the first file is reused once the fifth one is filled
+        for i in 0..10 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        let evicted = evicted.lock().unwrap();
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].id, 1);
+        assert_eq!(evicted[0].lsns, Some(0..=1));
+        assert_eq!(evicted[0].bytes, format::SEGMENT_HEADER_BYTES as u64 + 28);
+        assert!(evicted[0].last_written.is_some());
+    }
+
+    #[test]
+    fn checkpointer() {
+        let dir = clear_storage("checkpointer");
+        let saved = Arc::new(Mutex::new(Vec::new()));
+        let failing = Arc::new(AtomicBool::new(true));
+        let (log, fail) = (saved.clone(), failing.clone());
+        let wal = WalBuilder::new(&dir, 100)
+            .checkpointer(move |lsns: RangeInclusive<Lsn>| {
+                if fail.load(Ordering::Relaxed) {
+                    return Err(std::io::Error::other("snapshot failed"));
+                }
+                log.lock().unwrap().push(lsns);
+                Ok(())
+            })
+            .build()
+            .unwrap();
+        let ids = |wal: &Wal<Item>| wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        // two logs fill a file, so the first file is due for reuse once the fifth one is filled
+        for i in 0..10 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        wal.write(Item { id: 10 });
+        wal.flush().unwrap();
+        // the logs are kept while the checkpoint fails
+        assert_eq!(ids(&wal), (0..11).collect::<Vec<_>>());
+        assert!(saved.lock().unwrap().is_empty());
+
+        failing.store(false, Ordering::Relaxed);
+        wal.write(Item { id: 11 });
+        wal.flush().unwrap();
+        assert_eq!(*saved.lock().unwrap(), vec![0..=1]);
+        assert_eq!(ids(&wal), (2..12).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn archiver() {
+        let dir = clear_storage("archiver");
+        let archive = format!("{}archive/", dir);
+        let wal = WalBuilder::new(&dir, 100)
+            .archive_dir(&archive)
+            .build()
+            .unwrap();
+        let ids = |wal: &Wal<Item>| wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        for i in 0..12 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        // the two oldest files are copied before they're reused
+        assert_eq!(ids(&wal), (4..12).collect::<Vec<_>>());
+        let mut archived = std::fs::read_dir(&archive)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        archived.sort();
+        assert_eq!(
+            archived,
+            vec![
+                format!("wal_{:020}-{:020}", 0, 1),
+                format!("wal_{:020}-{:020}", 2, 3)
+            ]
+        );
+        let archived = std::fs::read(format!("{}{}", archive, archived[0])).unwrap();
+        assert_eq!(archived.len(), format::SEGMENT_HEADER_BYTES + 28);
+
+        // the logs are kept while archiving fails
+        let dir = clear_storage("archiver_failing");
+        let failing = Arc::new(AtomicBool::new(true));
+        let fail = failing.clone();
+        let wal = WalBuilder::new(&dir, 100)
+            .archiver(move |segment: &ArchivedSegment| {
+                assert_eq!(segment.id, 1);
+                assert_eq!(segment.lsns, 0..=1);
+                match fail.load(Ordering::Relaxed) {
+                    true => Err(std::io::Error::other("upload failed")),
+                    false => Ok(()),
+                }
+            })
+            .build()
+            .unwrap();
+        for i in 0..12 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        assert_eq!(ids(&wal), (0..12).collect::<Vec<_>>());
+        assert_eq!(
+            wal.last_error().map(|f| f.operation),
+            Some(WriteOperation::Archive)
+        );
+        failing.store(false, Ordering::Relaxed);
+        wal.write(Item { id: 12 });
+        wal.flush().unwrap();
+        assert_eq!(ids(&wal), (2..13).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn read_page() {
+        let dir = clear_storage("read_page");
+        let wal = WalBuilder::new(&dir, 100).build().unwrap();
+        let ids = |page: &Page<Item>| page.entries.iter().map(|i| i.id).collect::<Vec<_>>();
+        for i in 0..5 {
+            wal.write(Item { id: i });
+        }
+        wal.flush().unwrap();
+        let page = wal.read_page(None, 3).unwrap();
+        assert_eq!(ids(&page), vec![0, 1, 2]);
+        assert_eq!(page.next.lsn(), 3);
+        // tokens survive a round trip through text
+        let token = page.next.to_string().parse::<PageToken>().unwrap();
+        assert_eq!(token, page.next);
+        let page = wal.read_page(Some(token), 3).unwrap();
+        assert_eq!(ids(&page), vec![3, 4]);
+
+        // past the last log, the token returns the logs written since, in later files too
+        let end = page.next;
+        assert!(wal.read_page(Some(end), 3).unwrap().entries.is_empty());
+        for i in 5..9 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        let page = wal.read_page(Some(end), 10).unwrap();
+        assert_eq!(ids(&page), vec![5, 6, 7, 8]);
+
+        // once the files of the token are reused, its logs are gone
+        for i in 9..20 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        match wal.read_page(Some(end), 10) {
+            Err(WalError::CompactedAway { lsn: 5, oldest }) => assert!(oldest > 5),
+            r => panic!("unexpected {:?}", r.map(|p| ids(&p))),
+        }
+        let page = wal.read_page(None, 100).unwrap();
+        assert_eq!(page.entries.last().unwrap().id, 19);
+        assert!("1-2".parse::<PageToken>().is_err());
+    }
+
+    #[test]
+    fn retain_segments() {
+        let dir = clear_storage("retain_segments");
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let log = evicted.clone();
+        let wal = WalBuilder::new(&dir, 100)
+            .retain_segments(1)
+            .on_evict(move |e| log.lock().unwrap().push(e.id))
+            .build()
+            .unwrap();
+        // two logs fill a file, so three files are sealed
+        for i in 0..7 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![4, 5, 6]);
+        assert_eq!(*evicted.lock().unwrap(), vec![1, 2]);
+        assert!(!Path::new(&format!("{}wal_1", dir)).exists());
+    }
+
+    #[test]
+    fn retain_for() {
+        let dir = clear_storage("retain_for");
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let log = evicted.clone();
+        let wal = WalBuilder::new(&dir, 100)
+            .retain_for(Duration::from_millis(500))
+            .on_evict(move |e| log.lock().unwrap().push(e.id))
+            .build()
+            .unwrap();
+        // two logs fill a file, so three files are sealed
+        for i in 0..7 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        assert_eq!(wal.read().unwrap().len(), 7);
+        // the time range of every sealed file is recorded
+        let meta = std::fs::read_to_string(format!("{}meta", dir)).unwrap();
+        assert_eq!(meta.matches("\nwritten ").count(), 3);
+
+        // sealed files expire while no more logs come
+        std::thread::sleep(Duration::from_millis(1200));
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![6]);
+        assert_eq!(*evicted.lock().unwrap(), vec![1, 2, 3]);
+        assert!(!Path::new(&format!("{}wal_3", dir)).exists());
+    }
+
+    #[test]
+    fn segment_index() {
+        let dir = clear_storage("segment_index");
+        let wal = WalBuilder::new(&dir, 20_000)
+            .segment_index(true)
+            .timestamps(true)
+            .build()
+            .unwrap();
+        for i in 0..500 {
+            wal.write(Item { id: i });
+        }
+        wal.flush().unwrap();
+        let ids = |logs: Vec<Item>| logs.iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(
+            ids(wal.read_from(100).unwrap()),
+            (100..500).collect::<Vec<_>>()
+        );
+        assert_eq!(wal.get(70).unwrap().map(|i| i.id), Some(70));
+        assert_eq!(ids(wal.read_last(3).unwrap()), vec![497, 498, 499]);
+        let all = wal.read_range(SystemTime::UNIX_EPOCH, SystemTime::now());
+        assert_eq!(all.unwrap().len(), 500);
+
+        // sealed files are indexed, and indexed again on demand once their index is lost
+        let index = segment_index::SegmentIndex::load(Path::new(&dir), 1).unwrap();
+        assert!(index.blocks.len() > 1);
+        std::fs::remove_file(format!("{}index_1", dir)).unwrap();
+        assert_eq!(wal.rebuild_index().unwrap(), 1);
+        assert_eq!(wal.rebuild_index().unwrap(), 0);
+
+        // only the blocks holding the logs looked for are read, so a damaged first block hides
+        // the rest of the file from full reads only
+        let mut file = OpenOptions::new()
+            .write(true)
+            .open(format!("{}wal_1", dir))
+            .unwrap();
+        std::io::Seek::seek(&mut file, std::io::SeekFrom::Start(index.blocks[0].offset)).unwrap();
+        file.write_all(&[0xff; 4]).unwrap();
+        drop(file);
+        let second = *index.blocks[1].lsns.start();
+        assert_eq!(wal.read_from(second).unwrap().len(), 500 - second as usize);
+        assert_eq!(wal.get(second).unwrap().map(|i| i.id), Some(second as u16));
+        assert!(wal.read().unwrap().len() < 500 - second as usize);
+    }
+
+    #[test]
+    fn read_range() {
+        let dir = clear_storage("read_range");
+        let wal = WalBuilder::new(&dir, 200).timestamps(true).build().unwrap();
+        for i in 0..4 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(20));
+        let incident = SystemTime::now();
+        for i in 4..8 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        // the newest logs are still in the buffer
+        wal.write(Item { id: 8 });
+        let ids = |logs: Vec<Item>| logs.iter().map(|i| i.id).collect::<Vec<_>>();
+        let now = SystemTime::now();
+        assert_eq!(
+            ids(wal.read_range(incident, now).unwrap()),
+            vec![4, 5, 6, 7, 8]
+        );
+        let before = incident - Duration::from_millis(1);
+        assert_eq!(
+            ids(wal.read_range(SystemTime::UNIX_EPOCH, before).unwrap()),
+            vec![0, 1, 2, 3]
+        );
+        drop(wal);
+
+        // timestamps are read back from the files
+        let wal: Wal<Item> = WalBuilder::new(&dir, 200).read_only(true).build().unwrap();
+        assert_eq!(
+            ids(wal.read_range(incident, now).unwrap()),
+            vec![4, 5, 6, 7, 8]
+        );
+        assert!(wal
+            .read_range(now + Duration::from_secs(1), now + Duration::from_secs(2))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn strict_timestamps() {
+        let dir = clear_storage("strict_timestamps");
+        let open = || {
+            WalBuilder::<Item>::new(&dir, 10_000)
+                .strict_timestamps(true)
+                .build()
+                .unwrap()
+        };
+        let wal = open();
+        // many more logs than milliseconds are written
+        wal.batch_write((0..5000).map(|id| Item { id }).collect());
+        wal.flush().unwrap();
+        drop(wal);
+        let wal = open();
+        wal.write(Item { id: 5000 });
+        wal.flush().unwrap();
+
+        let logs = wal.read_attributed().unwrap();
+        assert_eq!(logs.len(), 5001);
+        let stamps = logs
+            .iter()
+            .map(|l| (l.written_at.unwrap(), l.monotonic.unwrap()))
+            .collect::<Vec<_>>();
+        assert!(stamps.windows(2).all(|w| w[0].0 <= w[1].0));
+        assert!(stamps.windows(2).all(|w| w[0].1 < w[1].1));
+        // stamps don't run ahead of the clock
+        assert!(stamps[5000].0 <= SystemTime::now());
+        // the wall clock is kept as it was read
+        for log in &logs {
+            assert!(log
+                .wall_clock
+                .is_some_and(|at| at <= log.written_at.unwrap()));
+        }
+        drop(wal);
+
+        // logs stamped without the option don't keep the wall clock
+        let wal = WalBuilder::<Item>::new(&dir, 10_000)
+            .timestamps(true)
+            .build()
+            .unwrap();
+        wal.write(Item { id: 5001 });
+        wal.flush().unwrap();
+        let last = wal.read_attributed().unwrap().pop().unwrap();
+        assert!(last.written_at.is_some() && last.wall_clock.is_none());
+        assert!(last.monotonic.is_none());
+    }
+
+    #[test]
+    fn topics() {
+        let dir = clear_storage("topics");
+        let wal = WalBuilder::new(&dir, 1000)
+            .topic_capacity("small", 100)
+            .build()
+            .unwrap();
+        assert!(wal.topic("a/b").is_err());
+        let orders = wal.topic("orders").unwrap();
+        let small = wal.topic("small").unwrap();
+        wal.write(Item { id: 0 });
+        orders.write(Item { id: 1 });
+        orders.write(Item { id: 2 });
+        // two logs fill a file of the small topic
+        for i in 3..6 {
+            small.write(Item { id: i });
+            small.flush().unwrap();
+        }
+        wal.flush().unwrap();
+        orders.flush().unwrap();
+        let ids = |logs: Vec<Item>| logs.iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids(wal.read().unwrap()), vec![0]);
+        assert_eq!(ids(orders.read().unwrap()), vec![1, 2]);
+        assert_eq!(ids(small.read().unwrap()), vec![3, 4, 5]);
+        assert!(Path::new(&format!("{}topics/small/wal_2", dir)).exists());
+        assert!(!Path::new(&format!("{}topics/orders/wal_2", dir)).exists());
+        // the same topic is returned again
+        wal.topic("orders").unwrap().write(Item { id: 6 });
+        assert_eq!(ids(orders.read().unwrap()), vec![1, 2, 6]);
+        drop((orders, small));
+        drop(wal);
+
+        // the logs of every topic are written once all handles are dropped
+        let wal: Wal<Item> = Wal::new(&dir, 1000).unwrap();
+        assert_eq!(ids(wal.read().unwrap()), vec![0]);
+        let orders = wal.topic("orders").unwrap();
+        assert_eq!(ids(orders.read().unwrap()), vec![1, 2, 6]);
+    }
+
+    #[test]
+    fn tags() {
+        let dir = clear_storage("tags");
+        let wal = WalBuilder::new(&dir, 200).build().unwrap();
+        for i in 0..6 {
+            let tag = format!("account-{}", i % 2);
+            wal.try_write_tagged(tag.as_bytes(), &Item { id: i })
+                .unwrap();
+            wal.flush().unwrap();
+        }
+        wal.write(Item { id: 6 });
+        // the newest log is still in the buffer
+        wal.try_write_tagged(b"account-1", &Item { id: 7 }).unwrap();
+        let ids = |logs: Vec<Item>| logs.iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(
+            ids(wal.read_by_tag(b"account-1").unwrap()),
+            vec![1, 3, 5, 7]
+        );
+        assert_eq!(
+            ids(wal.read_filtered(|tag| tag.is_none()).unwrap()),
+            vec![6]
+        );
+        let tag = [0; format::MAX_TAG_BYTES + 1];
+        assert!(matches!(
+            wal.try_write_tagged(&tag, &Item { id: 8 }),
+            Err(WalError::Rejected(Rejection::TagTooLarge { .. }))
+        ));
+        drop(wal);
+
+        // tags are read back from the files
+        let wal: Wal<Item> = Wal::new(&dir, 200).unwrap();
+        assert_eq!(ids(wal.read_by_tag(b"account-0").unwrap()), vec![0, 2, 4]);
+        assert_eq!(wal.read().unwrap().len(), 8);
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encryption() {
+        let dir = clear_storage("encryption");
+        let secret = |i: usize| format!("secret-{}", i);
+        let wal: Wal<String> = Wal::new(&dir, 1000).unwrap();
+        wal.batch_write(vec![secret(0), secret(1)]);
+        wal.close().unwrap();
+        // the file written in the clear is encrypted once written again
+        let open = |key| WalBuilder::new(&dir, 1000).encryption_key(key).build();
+        let wal: Wal<String> = open([1; 32]).unwrap();
+        wal.batch_write((2..40).map(secret).collect());
+        wal.flush().unwrap();
+        for id in wal.layout.ids() {
+            let content = std::fs::read(format!("{}wal_{}", dir, id)).unwrap_or_default();
+            assert!(!content.windows(6).any(|w| w == b"secret"));
+        }
+        assert_eq!(wal.read().unwrap(), (0..40).map(secret).collect::<Vec<_>>());
+        wal.close().unwrap();
+
+        // the key is needed to open the WAL again
+        let wal: Wal<String> = open([1; 32]).unwrap();
+        assert_eq!(wal.read().unwrap().len(), 40);
+        drop(wal);
+        assert!(matches!(
+            Wal::<String>::new(&dir, 1000),
+            Err(WalError::Unsupported(_))
+        ));
+        assert!(matches!(open([2; 32]), Err(WalError::Unsupported(_))));
+    }
+
+    #[test]
+    fn cold_storage() {
+        let dir = clear_storage("cold_storage");
+        let cold = format!("{}cold/", dir);
+        let wal = WalBuilder::new(&dir, 100)
+            .cold_storage(&cold, 1)
+            .build()
+            .unwrap();
+        // two logs fill a file, so three files are sealed and the two oldest ones are moved
+        for i in 0..7 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        for id in [1, 2] {
+            assert!(Path::new(&format!("{}wal_{}", cold, id)).exists());
+            assert!(!Path::new(&format!("{}wal_{}", dir, id)).exists());
+        }
+        assert!(Path::new(&format!("{}wal_3", dir)).exists());
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, (0..7).collect::<Vec<_>>());
+        drop(wal);
+
+        // files are read across both locations when opened again
+        let wal: Wal<Item> = WalBuilder::new(&dir, 100)
+            .cold_storage(&cold, 1)
+            .read_only(true)
+            .build()
+            .unwrap();
+        assert_eq!(wal.read().unwrap().len(), 7);
+    }
+
+    #[test]
+    fn custom_storage() {
+        let dir = clear_storage("custom_storage");
+        let cold = format!("{}cold/", dir);
+        let storage = MemoryStorage::new();
+        let builder = || {
+            WalBuilder::new(&dir, 100)
+                .cold_storage(&cold, 1)
+                .storage(storage.clone())
+        };
+        let wal = builder().build().unwrap();
+        for i in 0..7 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        // WAL files only live in the storage, moved to the cold location there too
+        for id in 1..=5 {
+            assert!(!Path::new(&format!("{}wal_{}", dir, id)).exists());
+        }
+        let cold_file = Path::new(&cold).join("wal_1");
+        assert!(storage.read(&cold_file).unwrap().is_some());
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, (0..7).collect::<Vec<_>>());
+        let report = wal.verify().unwrap();
+        assert!(report.segments.iter().all(|s| s.corrupt_frames == 0));
+        drop(wal);
+
+        // logs are found again with the same storage, and only with it
+        let wal: Wal<Item> = builder().build().unwrap();
+        assert_eq!(wal.read().unwrap().len(), 7);
+        assert!(wal.truncate(3).unwrap() > 0);
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert!(ids.len() < 7 && ids.ends_with(&[6]));
+        drop(wal);
+        let local: Wal<Item> = Wal::new(&dir, 100).unwrap();
+        assert!(local.read().unwrap().is_empty());
+        drop(local);
+
+        // features working on local files are refused
+        let positional = builder().positional_writes(true).build();
+        assert!(matches!(positional, Err(WalError::Unsupported(_))));
+    }
+
+    #[test]
+    fn in_memory() {
+        let wal = Wal::in_memory(100).unwrap();
+        let dir = wal.location.clone();
+        assert!(dir.starts_with(std::env::temp_dir()));
+        // two logs fill a file, so the oldest logs are dropped as files are reused
+        for i in 0..20 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert!(ids.len() < 20);
+        assert_eq!(ids, (20 - ids.len() as u16..20).collect::<Vec<_>>());
+        assert!(!dir.join("wal_1").exists());
+        assert!(wal.last_read().unwrap().bytes > 0);
+
+        // WALs held in memory never share logs, and leave nothing behind
+        let other: Wal<Item> = Wal::in_memory(100).unwrap();
+        assert!(other.read().unwrap().is_empty());
+        assert_ne!(other.location, dir);
+        let clone = wal.clone();
+        drop(wal);
+        assert!(dir.exists());
+        drop(clone);
+        assert!(!dir.exists());
+    }
+
+    #[test]
+    fn capacity_warnings() {
+        let dir = clear_storage("capacity_warnings");
+        let warnings = Arc::new(Mutex::new(Vec::new()));
+        let log = warnings.clone();
+        let wal = WalBuilder::new(&dir, 100)
+            .capacity_warnings(&[0.5, 0.9], move |w| log.lock().unwrap().push(w.clone()))
+            .build()
+            .unwrap();
+        wal.write(Item { id: 0 });
+        wal.flush().unwrap();
+        // the consumer stops after the first log
+        assert_eq!(wal.entries_since(None).unwrap().len(), 1);
+        // two logs fill a file, so the first file is reused next once the fourth one is filled
+        for i in 1..8 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        let warnings = warnings.lock().unwrap();
+        assert_eq!(
+            *warnings,
+            vec![
+                CapacityWarning::Usage {
+                    threshold: 0.5,
+                    used: 58,
+                    capacity: 100
+                },
+                CapacityWarning::Usage {
+                    threshold: 0.9,
+                    used: 94,
+                    capacity: 100
+                },
+                CapacityWarning::UnconsumedEviction {
+                    id: 1,
+                    lsns: 0..=1,
+                    consumer: 1
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn hash_chain() {
+        let dir = clear_storage("hash_chain");
+        let sealed = Arc::new(Mutex::new(Vec::<SegmentSealed>::new()));
+        let build = || {
+            let log = sealed.clone();
+            WalBuilder::new(&dir, 100)
+                .on_seal(move |s| log.lock().unwrap().push(s.clone()))
+                .build()
+                .unwrap()
+        };
+        let wal = build();
+        // two logs fill a file
+        for i in 0..5 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        let report = wal.verify_chain().unwrap();
+        assert!(report.is_intact());
+        assert_eq!(report.checked, vec![1, 2]);
+        {
+            let sealed = sealed.lock().unwrap();
+            assert_eq!(sealed.len(), 2);
+            assert_eq!(sealed[0].previous, [0; 32]);
+            assert_eq!(sealed[1].previous, sealed[0].digest.chain.unwrap());
+            assert_eq!(report.head, sealed[1].digest.chain);
+        }
+        drop(wal);
+
+        // the chain goes on from the file sealed last when the WAL is opened again
+        let wal = build();
+        for i in 5..9 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        let sealed = sealed.lock().unwrap().clone();
+        assert!(sealed.len() > 2);
+        assert_eq!(sealed[2].previous, sealed[1].digest.chain.unwrap());
+        let report = wal.verify_chain().unwrap();
+        assert!(report.is_intact());
+        assert_eq!(report.head, sealed.last().unwrap().digest.chain);
+
+        // a changed sealed file breaks the chain
+        let id = report.checked[0];
+        let path = format!("{}wal_{}", dir, id);
+        let mut content = std::fs::read(&path).unwrap();
+        content[0] ^= 0x01;
+        std::fs::write(&path, content).unwrap();
+        assert_eq!(wal.verify_chain().unwrap().broken_at, Some(id));
+    }
+
+    #[test]
+    fn recent_cache() {
+        let dir = clear_storage("recent_cache");
+        let wal = WalBuilder::new(&dir, 1000)
+            .recent_cache(5, 1024)
+            .build()
+            .unwrap();
+        for i in 0..20 {
+            wal.write(Item { id: i });
+        }
+        sleep(Duration::from_millis(100));
+        // answered from memory, even once files are gone
+        for id in 1..=5 {
+            let _ = std::fs::remove_file(format!("{}wal_{}", dir, id));
+        }
+        let ids = wal
+            .read_last(3)
+            .unwrap()
+            .iter()
+            .map(|i| i.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![17, 18, 19]);
+        // more logs than cached fall back to files
+        assert!(wal.read_last(6).unwrap().is_empty());
+    }
+
+    #[test]
+    fn stale_meta() {
+        let dir = clear_storage("stale_meta");
+        let wal = Wal::new(&dir, 100).unwrap();
+        for i in 0..6 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        drop(wal);
+        // newest logs are in wal_3, while meta is rolled back to the first file
+        std::fs::write(format!("{}meta", dir), "1").unwrap();
+        let wal = Wal::<Item>::new(&dir, 100).unwrap();
+        let recovery = wal.recovery().unwrap();
+        assert_eq!(recovery.recorded, Some(1));
+        assert_eq!(recovery.pointer, 3);
+        drop(wal);
+
+        // a meta file pointing at a just started file is fine
+        let dir = clear_storage("stale_meta_started");
+        let wal = Wal::new(&dir, 100).unwrap();
+        for i in 0..4 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        drop(wal);
+        assert!(Wal::<Item>::new(&dir, 100).unwrap().recovery().is_none());
+    }
+
+    #[test]
+    fn damaged_meta() {
+        let dir = clear_storage("damaged_meta");
+        let wal = WalBuilder::new(&dir, 100).segments(7).build().unwrap();
+        for i in 0..9 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        let written = wal.read().unwrap().len();
+        drop(wal);
+        let path = format!("{}meta", dir);
+        let meta = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, meta.replacen("segments 7", "segments 3", 1)).unwrap();
+
+        // the number of files and the current file are found from the WAL files
+        let wal = Wal::<Item>::new(&dir, 100).unwrap();
+        let recovery = wal.recovery().unwrap().clone();
+        assert!(recovery.rebuilt);
+        assert_eq!(recovery.recorded, None);
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, (9 - written as u16..9).collect::<Vec<_>>());
+        drop(wal);
+        // the rebuilt meta file passes its checksum
+        let wal = Wal::<Item>::new(&dir, 100).unwrap();
+        assert!(wal.recovery().is_none_or(|r| !r.rebuilt));
+        assert_eq!(wal.read().unwrap().len(), written);
+        drop(wal);
+
+        // an empty meta file, as left by a crash while writing it
+        std::fs::write(&path, "").unwrap();
+        assert!(
+            Wal::<Item>::new(&dir, 100)
+                .unwrap()
+                .recovery()
+                .unwrap()
+                .rebuilt
+        );
+    }
+
+    #[test]
+    fn event_listener() {
+        struct Recorder(Arc<Mutex<Vec<String>>>);
+
+        impl EventListener for Recorder {
+            fn on_flush(&self, flush: &FlushEvent) {
+                let event = format!("flush {} to {}", flush.entries, flush.segment);
+                self.0.lock().unwrap().push(event);
+            }
+
+            fn on_rotate(&self, rotation: &RotationEvent) {
+                let event = format!("rotate {} to {}", rotation.from, rotation.to);
+                self.0.lock().unwrap().push(event);
+            }
+
+            fn on_recovery(&self, recovery: &Recovery) {
+                let event = format!("recovery to {}", recovery.pointer);
+                self.0.lock().unwrap().push(event);
+            }
+        }
+
+        let dir = clear_storage("event_listener");
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let rotations = Arc::new(Mutex::new(Vec::new()));
+        let sealed = rotations.clone();
+        let wal = WalBuilder::new(&dir, 100)
+            .event_listener(Recorder(events.clone()))
+            .on_rotate(move |rotation| sealed.lock().unwrap().push(rotation.clone()))
+            .build()
+            .unwrap();
+        // the batch fills the first file
+        wal.batch_write((1..=50).map(|id| Item { id }).collect());
+        wal.flush().unwrap();
+        assert_eq!(*events.lock().unwrap(), ["flush 50 to 1", "rotate 1 to 2"]);
+        // the sealed file is described once
+        let rotations = rotations.lock().unwrap().clone();
+        assert_eq!(rotations.len(), 1);
+        let rotation = &rotations[0];
+        assert_eq!(rotation.path, PathBuf::from(format!("{}wal_1", dir)));
+        assert_eq!(rotation.lsns, Some(0..=49));
+        assert_eq!(rotation.entries, 50);
+        assert_eq!(
+            rotation.bytes,
+            std::fs::metadata(&rotation.path).unwrap().len()
+        );
+        drop(wal);
+
+        // the meta file is lost and the current file found from the WAL files
+        std::fs::remove_file(format!("{}meta", dir)).unwrap();
+        events.lock().unwrap().clear();
+        let _wal = WalBuilder::<Item>::new(&dir, 100)
+            .event_listener(Recorder(events.clone()))
+            .build()
+            .unwrap();
+        assert_eq!(*events.lock().unwrap(), ["recovery to 1"]);
+    }
+
+    #[test]
+    fn custom_spawner() {
+        let dir = clear_storage("custom_spawner");
+        let spawned = Arc::new(Mutex::new(0));
+        let count = spawned.clone();
+        let wal = WalBuilder::new(&dir, 100)
+            .spawner(move |task| {
+                *count.lock().unwrap() += 1;
+                std::thread::Builder::new()
+                    .name("custom-writer".to_string())
+                    .spawn(task)
+                    .map(|_| ())
+            })
+            .build()
+            .unwrap();
+        wal.write(Item { id: 1 });
+        sleep(Duration::from_millis(100));
+        assert_eq!(*spawned.lock().unwrap(), 1);
+        assert_eq!(wal.read().unwrap().len(), 1);
+
+        // a spawner that never runs the writer fails the build
+        let dir = clear_storage("custom_spawner_dropped");
+        let built = WalBuilder::<Item>::new(&dir, 100)
+            .spawner(|_task| Ok(()))
+            .build();
+        assert!(built.is_err());
+    }
+
+    #[test]
+    fn read_only() {
+        let dir = clear_storage("read_only");
+        let wal = Wal::new(&dir, 100).unwrap();
+        for i in 0..3 {
+            wal.write(Item { id: i });
+            sleep(Duration::from_millis(20));
+        }
+        sleep(Duration::from_millis(100));
+        drop(wal);
+        let meta = std::fs::read_to_string(format!("{}meta", dir)).unwrap();
+
+        let wal = WalBuilder::<Item>::new(&dir, 100)
+            .read_only(true)
+            .build()
+            .unwrap();
+        assert_eq!(wal.read_last(3).unwrap().len(), 3);
+        assert!(matches!(
+            wal.try_write(&Item { id: 3 }),
+            Err(WalError::ReadOnlyFilesystem(_))
+        ));
+        assert!(wal.try_write_all(&[Item { id: 3 }]).is_err());
+        assert_eq!(
+            std::fs::read_to_string(format!("{}meta", dir)).unwrap(),
+            meta
+        );
+
+        let error = std::io::Error::from(std::io::ErrorKind::ReadOnlyFilesystem);
+        assert!(matches!(
+            WalError::io(error, "Failed to open log file"),
+            WalError::ReadOnlyFilesystem(_)
+        ));
+    }
+
+    #[test]
+    fn producers() {
+        let dir = clear_storage("producers");
+        let wal = Wal::new(&dir, 1000).unwrap();
+        let first = wal.with_producer(1);
+        let second = wal.with_producer(2);
+        wal.write(Item { id: 0 });
+        first.write(Item { id: 1 });
+        second.write(Item { id: 2 });
+        first.write(Item { id: 3 });
+        sleep(Duration::from_millis(100));
+
+        let logs = wal.read_attributed().unwrap();
+        let producers = logs.iter().map(|l| l.producer).collect::<Vec<_>>();
+        assert_eq!(producers, vec![None, Some(1), Some(2), Some(1)]);
+        assert_eq!(logs[3].log.as_ref().unwrap().id, 3);
+        assert_eq!(wal.read().unwrap().len(), 4);
+
+        // logs of another type are malformed
+        let stats = WalBuilder::<String>::new(&dir, 1000)
+            .read_only(true)
+            .build()
+            .unwrap()
+            .producer_stats()
+            .unwrap();
+        let ids = stats.iter().map(|s| s.producer).collect::<Vec<_>>();
+        assert_eq!(ids, vec![None, Some(1), Some(2)]);
+        assert_eq!(stats[1].entries, 2);
+        assert_eq!(stats[1].bytes, 4);
+        assert_eq!(stats[1].malformed, 2);
+    }
+
+    #[test]
+    fn merged_compaction() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Counter {
+            key: u8,
+            count: u32,
+        }
+        let dir = clear_storage("merged_compaction");
+        let wal = WalBuilder::new(&dir, 400)
+            .keyed(|c: &Counter| c.key)
+            .merge(|old: Counter, new: Counter| Counter {
+                key: new.key,
+                count: old.count + new.count,
+            })
+            .build()
+            .unwrap();
+        for i in 0..20 {
+            wal.write(Counter {
+                key: (i % 2) as u8,
+                count: 1,
+            });
+            sleep(Duration::from_millis(5));
+        }
+        wal.flush().unwrap();
+        assert_eq!(wal.get_by_key(&0u8).unwrap().unwrap().count, 10);
+
+        // sealed files keep a single log per key, holding the sum of its logs
+        let before = wal.read().unwrap().len();
+        wal.compact().unwrap();
+        let logs = wal.read().unwrap();
+        assert!(logs.len() < before);
+        assert_eq!(wal.get_by_key(&0u8).unwrap().unwrap().count, 10);
+        assert_eq!(wal.get_by_key(&1u8).unwrap().unwrap().count, 10);
+
+        // merging needs keys
+        let builder = WalBuilder::new(&dir, 400).merge(|_: Counter, new: Counter| new);
+        assert!(matches!(builder.build(), Err(WalError::Unsupported(_))));
+    }
+
+    #[test]
+    fn keyed_compaction() {
+        #[derive(Serialize, Deserialize)]
+        struct Update {
+            key: u8,
+            value: u16,
+        }
+        let dir = clear_storage("keyed_compaction");
+        let wal = WalBuilder::new(&dir, 400)
+            .keyed(|u: &Update| u.key)
+            .compaction_threshold(0.5)
+            .verify_on_rotation(true)
+            .build()
+            .unwrap();
+        // every file takes 7 logs, each key is updated over and over
+        for value in 0..24 {
+            wal.write(Update {
+                key: (value % 2) as u8,
+                value,
+            });
+            wal.flush().unwrap();
+        }
+        // sealed files were compacted on their own, the latest value of each key is kept
+        let logs = wal.read_attributed().unwrap();
+        assert!(logs.len() < 24);
+        let last = logs
+            .iter()
+            .rev()
+            .take(2)
+            .map(|l| l.log.as_ref().unwrap().value);
+        assert_eq!(last.collect::<Vec<_>>(), vec![23, 22]);
+
+        // manual compaction leaves no superseded logs in sealed files, while the current file
+        // isn't compacted
+        wal.compact().unwrap();
+        let values = wal
+            .read_attributed()
+            .unwrap()
+            .iter()
+            .map(|l| l.log.as_ref().unwrap().value)
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![21, 22, 23]);
+        // digests describe the compacted files
+        let digests = wal.segment_digests().unwrap();
+        assert!(!digests.is_empty());
+        for d in digests {
+            let content = std::fs::read(format!("{}wal_{}", dir, d.id)).unwrap();
+            assert_eq!(d.crc32, checksum::crc32(&content));
+        }
+    }
+
+    #[test]
+    fn compact_with() {
+        let dir = clear_storage("compact_with");
+        let wal = WalBuilder::new(&dir, 10000)
+            .verify_on_rotation(true)
+            .build()
+            .unwrap();
+        for id in 0..230 {
+            wal.write(Item { id });
+            if id % 10 == 9 {
+                wal.flush().unwrap();
+            }
+        }
+        let removed = wal
+            .compact_with(|item: &Item| item.id.is_multiple_of(10))
+            .unwrap();
+        assert!(removed > 0);
+        // sealed files only hold retained logs, the current file is left whole
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids.len() as u64, 230 - removed);
+        let first = ids.iter().position(|id| id % 10 != 0).unwrap();
+        assert!(ids[..first].iter().all(|id| id % 10 == 0));
+        assert_eq!(ids[first..], (ids[first]..230).collect::<Vec<_>>());
+        // retained logs keep their sequence numbers
+        assert_eq!(wal.get(10).unwrap().map(|i| i.id), Some(10));
+        assert!(wal.get(11).unwrap().is_none());
+        // nothing left to drop
+        assert_eq!(
+            wal.compact_with(|item: &Item| item.id.is_multiple_of(10))
+                .unwrap(),
+            0
+        );
+        for d in wal.segment_digests().unwrap() {
+            let content = std::fs::read(format!("{}wal_{}", dir, d.id)).unwrap();
+            assert_eq!(d.crc32, checksum::crc32(&content));
+        }
+    }
+
+    #[test]
+    fn lookup_by_key() {
+        #[derive(Serialize, Deserialize)]
+        struct Update {
+            key: u8,
+            value: u16,
+        }
+        let dir = clear_storage("lookup_by_key");
+        let wal = WalBuilder::new(&dir, 400)
+            .keyed(|u: &Update| u.key)
+            .build()
+            .unwrap();
+        // every file takes 7 logs, keys change from one file to the next
+        for value in 0..24 {
+            wal.write(Update {
+                key: (value / 7) as u8,
+                value,
+            });
+            sleep(Duration::from_millis(5));
+        }
+        sleep(Duration::from_millis(100));
+        // sealed files have a filter of their keys, the current one doesn't
+        let filter = KeyFilter::load(Path::new(&dir), 1).unwrap();
+        assert!(filter.contains(compaction::hash_key(&0u8)));
+        assert!(!filter.contains(compaction::hash_key(&1u8)));
+        assert!(KeyFilter::load(Path::new(&dir), 4).is_none());
+
+        let values = |key: u8| {
+            wal.read_by_key(&key)
+                .unwrap()
+                .iter()
+                .map(|u| u.value)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(values(1), (7..14).collect::<Vec<_>>());
+        assert_eq!(values(3), vec![21, 22, 23]);
+        assert!(values(9).is_empty());
+        assert_eq!(wal.get_by_key(&2u8).unwrap().unwrap().value, 20);
+        assert!(wal.get_by_key(&9u8).unwrap().is_none());
+
+        // logs are looked up by key only in keyed mode
+        let plain: Wal<Item> = Wal::new(&clear_storage("lookup_by_key_plain"), 400).unwrap();
+        assert!(matches!(
+            plain.get_by_key(&1u8),
+            Err(WalError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn iter() {
+        let dir = clear_storage("iter");
+        let wal = Wal::new(&dir, 2000).unwrap();
+        let mut id = 0;
+        while !Path::new(&format!("{}wal_3", dir)).exists() {
+            wal.write(Item { id });
+            wal.flush().unwrap();
+            id += 1;
+        }
+        wal.write(Item { id });
+        let mut logs = wal.iter().unwrap();
+        assert_eq!(logs.next().unwrap().unwrap().id, 0);
+        // logs written once the iterator is created are left out
+        wal.write(Item { id: id + 1 });
+        wal.flush().unwrap();
+        let ids = logs.map(|log| log.unwrap().id).collect::<Vec<_>>();
+        assert_eq!(ids, (1..=id).collect::<Vec<_>>());
+        assert_eq!(wal.iter().unwrap().count(), id as usize + 2);
+    }
+
+    #[test]
+    fn empty_payloads() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        struct Marker;
+
+        let setups: [fn(WalBuilder<Marker>) -> WalBuilder<Marker>; 4] = [
+            |b| b,
+            |b| b.checksums(true),
+            |b| b.fixed_record_size(4),
+            |b| b.segment_index(true),
+        ];
+        for (i, setup) in setups.iter().enumerate() {
+            let dir = clear_storage(&format!("empty_payloads_{}", i));
+            let wal = setup(WalBuilder::new(&dir, 2000)).build().unwrap();
+            wal.write(Marker);
+            wal.flush().unwrap();
+            // a batch of empty logs is sized by its frames, so it doesn't overflow the file
+            // holding the first log
+            wal.batch_write((0..100).map(|_| Marker).collect());
+            wal.flush().unwrap();
+            assert_eq!(wal.rotation_history().rotations[0].entries, 1);
+            assert_eq!(
+                wal.read().unwrap(),
+                (0..101).map(|_| Marker).collect::<Vec<_>>()
+            );
+            assert_eq!(wal.get(100).unwrap(), Some(Marker));
+            assert_eq!(wal.read_last(1).unwrap(), vec![Marker]);
+            assert_eq!(wal.verify().unwrap().corrupt_frames, 0);
+        }
+
+        let dir = clear_storage("empty_payloads_rejected");
+        let wal = WalBuilder::new(&dir, 2000)
+            .strict(Validation::new().reject_empty_payloads())
+            .build()
+            .unwrap();
+        assert!(matches!(
+            wal.try_write(&Marker),
+            Err(WalError::Rejected(Rejection::EmptyPayload))
+        ));
+        assert!(wal.try_write_raw(&[]).is_err());
+    }
+
+    #[test]
+    fn write_sync() {
+        let dir = clear_storage("write_sync");
+        let acked = Arc::new(Mutex::new(Vec::new()));
+        let log = acked.clone();
+        let wal = WalBuilder::new(&dir, 100_000)
+            .sync_policy(SyncPolicy::Never)
+            .on_ack(move |lsns| log.lock().unwrap().push(lsns))
+            .build()
+            .unwrap();
+        wal.write(Item { id: 0 });
+        let lsn = wal.write_sync(&Item { id: 1 }).unwrap();
+        assert_eq!(lsn, 1);
+        // synced along with the logs accepted before it
+        let acked = acked.lock().unwrap().clone();
+        assert_eq!(acked.into_iter().flatten().collect::<Vec<_>>(), vec![0, 1]);
+        let size = std::fs::metadata(format!("{}wal_1", dir)).unwrap().len();
+        assert!(size > 0);
+    }
+
+    #[test]
+    fn iter_from() {
+        let dir = clear_storage("iter_from");
+        let wal = Wal::new(&dir, 2000).unwrap();
+        let mut id = 0;
+        let last = format!("{}wal_3", dir);
+        while std::fs::metadata(&last).map_or(0, |m| m.len()) < 100 {
+            wal.write(Item { id });
+            wal.flush().unwrap();
+            id += 1;
+        }
+        let all = wal.read().unwrap().len() as u64;
+        let full = wal.last_read().unwrap().bytes;
+        for lsn in [0, 1, all / 2, all - 1, all, all + 5] {
+            let read = wal.read_from(lsn).unwrap();
+            let ids = read.iter().map(|log| log.id).collect::<Vec<_>>();
+            assert_eq!(ids, (lsn.min(all) as u16..id).collect::<Vec<_>>());
+            let iterated = wal.iter_from(lsn).unwrap().map(|log| log.unwrap().id);
+            assert_eq!(iterated.collect::<Vec<_>>(), ids);
+        }
+        // only the first frame of the files holding older logs is read
+        wal.read_from(all - 1).unwrap();
+        let skipping = wal.last_read().unwrap().bytes;
+        assert!(skipping < full / 2);
+
+        // the oldest file starts after its reclaimed logs
+        wal.truncate(10).unwrap();
+        let ids = wal
+            .read_from(1)
+            .unwrap()
+            .iter()
+            .map(|log| log.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, (11..id).collect::<Vec<_>>());
+        // found from its header, so the file isn't read whole either
+        wal.read_from(all - 1).unwrap();
+        assert!(wal.last_read().unwrap().bytes <= skipping + 64);
+    }
+
+    #[test]
+    fn handle_roles() {
+        let dir = clear_storage("handle_roles");
+        let writer = WalBuilder::new(&dir, 2000).build_writer().unwrap();
+        let reader = writer.read_handle();
+        let lsn = writer.try_write(&Item { id: 1 }).unwrap();
+        writer.batch_write(vec![Item { id: 2 }, Item { id: 3 }]);
+        writer.flush().unwrap();
+        assert_eq!(reader.get(lsn).unwrap().unwrap().id, 1);
+        assert_eq!(writer.read_last(1).unwrap()[0].id, 3);
+
+        // read handles keep reading once the writer is gone
+        let shared = reader.clone();
+        drop(writer);
+        let ids = shared.iter().unwrap().map(|log| log.unwrap().id);
+        assert_eq!(ids.collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(reader.read().unwrap().len(), 3);
+        assert_eq!(reader.metrics().entries_written, 3);
+    }
+
+    #[test]
+    fn rotation_history() {
+        let dir = clear_storage("rotation_history");
+        let wal = Wal::new(&dir, 100).unwrap();
+        assert_eq!(wal.rotation_history(), RotationHistory::default());
+        // two logs fill a file, so the writer leaves wal_1 and wal_2 behind
+        for id in 0..5 {
+            wal.write(Item { id });
+            wal.flush().unwrap();
+        }
+        let history = wal.clone().rotation_history();
+        let rotations = history
+            .rotations
+            .iter()
+            .map(|r| (r.segment, r.entries))
+            .collect::<Vec<_>>();
+        assert_eq!(rotations, vec![(1, 2), (2, 2)]);
+        assert!(history.rotations.iter().all(|r| r.bytes > 0));
+        assert_eq!(history.entries_per_segment, vec![0, 0, 2]);
+        assert_eq!(wal.metrics().rotations, 2);
+    }
+
+    #[test]
+    fn write_latency() {
+        let dir = clear_storage("write_latency");
+        let wal = WalBuilder::new(&dir, 10_000)
+            .wake_strategy(WakeStrategy::MicroBatch(Duration::from_millis(20)))
+            .build()
+            .unwrap();
+        assert_eq!(wal.stats().write_latency, LatencyStats::default());
+        for i in 0..40 {
+            wal.write(Item { id: i });
+        }
+        sleep(Duration::from_millis(100));
+        // logs 0, 16 and 32 are sampled, and wait for the micro-batch delay
+        let latency = wal.clone().stats().write_latency;
+        assert_eq!(latency.samples, 3);
+        assert!(latency.max >= Duration::from_millis(20));
+        assert!(latency.p50 <= latency.p99);
+        assert!(latency.p99 <= latency.max);
+    }
+
+    #[test]
+    fn dedup_blobs() {
+        let dir = clear_storage("dedup_blobs");
+        let wal = WalBuilder::<Vec<u8>>::new(&dir, 400)
+            .dedup_above(100)
+            .build()
+            .unwrap();
+        let attachment = vec![7u8; 1000];
+        for _ in 0..3 {
+            wal.write(attachment.clone());
+            wal.flush().unwrap();
+        }
+        // the attachment is stored once, frames only reference it
+        let blobs = Path::new(&dir).join("blobs");
+        assert_eq!(std::fs::read_dir(&blobs).unwrap().count(), 1);
+        assert!(std::fs::metadata(format!("{}wal_1", dir)).unwrap().len() < 100);
+        assert_eq!(wal.read().unwrap(), vec![attachment; 3]);
+
+        // the blob is removed once no file references it
+        for _ in 0..12 {
+            wal.write(vec![1; 40]);
+            wal.flush().unwrap();
+        }
+        assert!(wal.read().unwrap().iter().all(|log| log.len() == 40));
+        assert_eq!(std::fs::read_dir(&blobs).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn truncate() {
+        let dir = clear_storage("truncate_oldest");
+        let wal = WalBuilder::new(&dir, 100)
+            .verify_on_rotation(true)
+            .build()
+            .unwrap();
+        // two logs fill a file, so wal_1 to wal_3 are sealed
+        for i in 0..7 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        // wal_2 holds LSN 3, so only wal_1 goes, while LSN 2 is reclaimed from wal_2
+        assert_eq!(wal.truncate(2).unwrap(), 1);
+        assert!(!Path::new(&format!("{}wal_1", dir)).exists());
+        let ids = |wal: &Wal<Item>| wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids(&wal), vec![3, 4, 5, 6]);
+        let digests = wal.segment_digests().unwrap();
+        assert_eq!(digests.iter().map(|d| d.id).collect::<Vec<_>>(), vec![2, 3]);
+        assert_eq!(wal.truncate(2).unwrap(), 0);
+
+        // the current file is kept
+        assert_eq!(wal.truncate(100).unwrap(), 2);
+        assert_eq!(ids(&wal), vec![6]);
+        assert!(wal.segment_digests().unwrap().is_empty());
+        // writes go on, through the removed files
+        for i in 7..12 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        assert_eq!(ids(&wal), (6..12).collect::<Vec<_>>());
+        assert!(Truncation::pending(Path::new(&dir)).is_none());
+    }
+
+    #[test]
+    fn truncate_before() {
+        let dir = clear_storage("truncate_before");
+        let wal = WalBuilder::new(&dir, 100).build().unwrap();
+        for i in 0..7 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        assert_eq!(wal.truncate_before(0).unwrap(), 0);
+        // wal_2 holds LSN 3, which is kept
+        assert_eq!(wal.truncate_before(3).unwrap(), 1);
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn reclaim_head() {
+        let dir = clear_storage("reclaim_head");
+        let wal = WalBuilder::new(&dir, 400_000)
+            .segment_index(true)
+            .build()
+            .unwrap();
+        // 4 KiB logs, so the reclaimed head spans whole blocks of the file system
+        let log = |lsn: u64| vec![lsn as u8; 4096];
+        for lsn in 0..10 {
+            wal.write(log(lsn));
+        }
+        let batch = wal.atomic(|txn| (10..14).for_each(|lsn| txn.write(log(lsn))));
+        assert_eq!(batch.unwrap(), 10..14);
+        let mut lsn = 14;
+        while !Path::new(&format!("{}wal_2", dir)).exists() {
+            wal.write(log(lsn));
+            wal.flush().unwrap();
+            lsn += 1;
+        }
+        assert!(lsn > 16);
+        let path = format!("{}wal_1", dir);
+        let size = std::fs::metadata(&path).unwrap().len();
+
+        // the batch holding LSN 11 is kept whole
+        assert_eq!(wal.truncate(11).unwrap(), 0);
+        let logs = wal.read().unwrap();
+        assert_eq!(logs, (10..lsn).map(log).collect::<Vec<_>>());
+        assert_eq!(wal.read_from(12).unwrap(), logs[2..].to_vec());
+        assert_eq!(wal.truncate(11).unwrap(), 0);
+        assert_eq!(wal.read().unwrap(), logs);
+        assert_eq!(wal.truncate(15).unwrap(), 0);
+        let logs = wal.read().unwrap();
+        assert_eq!(logs, (16..lsn).map(log).collect::<Vec<_>>());
+        // the file keeps its size
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), size);
+
+        // the disk space of the head is released
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::fs::MetadataExt;
+            assert!(std::fs::metadata(&path).unwrap().blocks() * 512 < size);
+        }
+        drop(wal);
+
+        // reopened with the head reclaimed
+        let wal = WalBuilder::<Vec<u8>>::new(&dir, 400_000).build().unwrap();
+        assert_eq!(wal.read().unwrap(), logs);
+        assert!(wal.verify().unwrap().is_healthy());
+    }
+
+    #[test]
+    fn clear() {
+        let dir = clear_storage("clear");
+        let wal = WalBuilder::new(&dir, 100)
+            .verify_on_rotation(true)
+            .build()
+            .unwrap();
+        for i in 0..7 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        // logs still in the buffer are removed too
+        wal.write(Item { id: 7 });
+        wal.clear().unwrap();
+        assert!(wal.read().unwrap().is_empty());
+        assert!(wal.segment_digests().unwrap().is_empty());
+        assert!(!Path::new(&format!("{}wal_2", dir)).exists());
+        assert!(Truncation::pending(Path::new(&dir)).is_none());
+
+        // writes start over from the first file, numbered after the removed logs
+        let lsn = wal.write(Item { id: 8 }).unwrap();
+        assert_eq!(lsn, 8);
+        wal.flush().unwrap();
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![8]);
+        assert!(Path::new(&format!("{}wal_1", dir)).exists());
+        drop(wal);
+        let wal: Wal<Item> = Wal::new(&dir, 100).unwrap();
+        assert_eq!(wal.read().unwrap().len(), 1);
+
+        // the first file only counts the logs written to it since
+        wal.clear().unwrap();
+        for id in 9..12 {
+            wal.write(Item { id });
+            wal.flush().unwrap();
+        }
+        let rotation = wal.rotation_history().rotations[0];
+        assert_eq!((rotation.segment, rotation.entries), (1, 2));
+    }
+
+    #[test]
+    fn segment_window() {
+        let dir = clear_storage("segment_window");
+        // files of 100 bytes without a window
+        let wal = WalBuilder::new(&dir, 400)
+            .segment_window(Duration::from_secs(3600), 200, 200)
+            .build()
+            .unwrap();
+        for i in 0..10 {
+            wal.write(Item { id: i });
+            sleep(Duration::from_millis(5));
+        }
+        sleep(Duration::from_millis(50));
+        // 10 logs of 14 bytes fit in the first file
+        let size = std::fs::metadata(format!("{}wal_1", dir)).unwrap().len();
+        assert_eq!(size, format::SEGMENT_HEADER_BYTES as u64 + 140);
+        assert!(!Path::new(&format!("{}wal_2", dir)).exists());
+        for i in 10..20 {
+            wal.write(Item { id: i });
+            sleep(Duration::from_millis(5));
+        }
+        sleep(Duration::from_millis(50));
+        assert!(Path::new(&format!("{}wal_2", dir)).exists());
+        assert_eq!(wal.read().unwrap().len(), 20);
+    }
+
+    #[test]
+    fn length_delimited_framing() {
+        let dir = clear_storage("length_delimited_framing");
+        let wal = WalBuilder::<Vec<u8>>::new(&dir, 100)
+            .framing(Framing::LengthDelimited)
+            .build()
+            .unwrap();
+        let payloads = vec![vec![0x08, 0x96, 0x01], vec![], vec![7; 200]];
+        for payload in &payloads {
+            wal.try_write_raw(payload).unwrap();
+        }
+        sleep(Duration::from_millis(100));
+        // the file holds only varint sizes and payloads
+        let mut expected = Vec::new();
+        for payload in &payloads {
+            format::encode_varint(payload.len() as u64, &mut expected);
+            expected.extend(payload);
+        }
+        assert_eq!(std::fs::read(format!("{}wal_1", dir)).unwrap(), expected);
+        assert_eq!(wal.read_raw().unwrap(), payloads);
+    }
+
+    #[test]
+    fn atomic_batch() {
+        let dir = clear_storage("atomic_batch");
+        let wal = WalBuilder::new(&dir, 100).build().unwrap();
+        let lsn = wal
+            .atomic(|txn| {
+                txn.write(Item { id: 1 });
+                txn.write(Item { id: 2 });
+                txn.write(Item { id: 3 });
+            })
+            .unwrap();
+        assert_eq!(lsn, 0..3);
+        wal.write(Item { id: 4 });
+        sleep(Duration::from_millis(100));
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2, 3, 4]);
+        drop(wal);
+
+        // simulate a crash in the middle of a batch, cutting the file after its second log
+        let path = format!("{}wal_1", dir);
+        let content = std::fs::read(&path).unwrap();
+        let frame = content.len() / 4;
+        std::fs::write(&path, &content[..2 * frame]).unwrap();
+        let wal = WalBuilder::<Item>::new(&dir, 100)
+            .read_only(true)
+            .build()
+            .unwrap();
+        assert!(wal.read().unwrap().is_empty());
+
+        // a failed log aborts the whole batch
+        let other = clear_storage("atomic_batch_rejected");
+        let wal = WalBuilder::<Vec<u8>>::new(&other, 100)
+            .strict(Validation::new().max_payload_bytes(16))
+            .build()
+            .unwrap();
+        let result = wal.atomic(|txn| {
+            txn.write(vec![1; 4]);
+            txn.write(vec![2; 100]);
+        });
+        assert!(matches!(result, Err(WalError::Rejected(_))));
+        assert_eq!(wal.atomic(|txn| txn.write(vec![3; 4])).unwrap(), 0..1);
+    }
+
+    #[test]
+    fn atomic_streams() {
+        let dir = clear_storage("atomic_streams");
+        let wal = WalBuilder::new(&dir, 1000).build().unwrap();
+        let ids = |wal: &Wal<Item>, stream: &[u8]| {
+            let logs = wal.read_by_tag(stream).unwrap();
+            logs.iter().map(|i| i.id).collect::<Vec<_>>()
+        };
+        wal.atomic(|txn| {
+            txn.stream("a").write(Item { id: 1 });
+            let mut b = txn.stream("b");
+            b.write(Item { id: 2 });
+            b.write(Item { id: 3 });
+        })
+        .unwrap();
+        wal.flush().unwrap();
+        let committed = std::fs::read(format!("{}wal_1", dir)).unwrap().len();
+        let lsns = wal
+            .atomic(|txn| {
+                txn.stream("a").write(Item { id: 4 });
+                txn.stream("b").write(Item { id: 5 });
+            })
+            .unwrap();
+        assert_eq!(lsns, 3..5);
+        wal.flush().unwrap();
+        assert_eq!(ids(&wal, b"a"), vec![1, 4]);
+        assert_eq!(ids(&wal, b"b"), vec![2, 3, 5]);
+        drop(wal);
+
+        // simulate a crash after the log of stream a was written, but not the one of stream b
+        let path = format!("{}wal_1", dir);
+        let content = std::fs::read(&path).unwrap();
+        let torn = committed + (content.len() - committed) / 2;
+        std::fs::write(&path, &content[..torn]).unwrap();
+        let wal = WalBuilder::<Item>::new(&dir, 1000).build().unwrap();
+        assert_eq!(ids(&wal, b"a"), vec![1]);
+        assert_eq!(ids(&wal, b"b"), vec![2, 3]);
+        // the torn transaction is gone for good once the WAL is written to again
+        wal.atomic(|txn| txn.stream("b").write(Item { id: 6 }))
+            .unwrap();
+        wal.flush().unwrap();
+        assert_eq!(ids(&wal, b"a"), vec![1]);
+        assert_eq!(ids(&wal, b"b"), vec![2, 3, 6]);
+
+        // a stream name too long for a tag aborts the whole transaction
+        let name = "s".repeat(format::MAX_TAG_BYTES + 1);
+        let result = wal.atomic(|txn| {
+            txn.stream("a").write(Item { id: 7 });
+            txn.stream(&name).write(Item { id: 8 });
+        });
+        assert!(matches!(
+            result,
+            Err(WalError::Rejected(Rejection::TagTooLarge { .. }))
+        ));
+        assert_eq!(wal.read().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn transaction() {
+        let dir = clear_storage("transaction");
+        let wal = WalBuilder::new(&dir, 100).build().unwrap();
+        let mut txn = wal.transaction();
+        txn.write(Item { id: 1 });
+        txn.write(Item { id: 2 });
+        // staged logs aren't visible until committed
+        assert!(wal.read().unwrap().is_empty());
+        assert_eq!(txn.len(), 2);
+        assert_eq!(txn.commit().unwrap(), 0..2);
+        // committed logs are already synced to the file
+        let content = std::fs::read(format!("{}wal_1", dir)).unwrap();
+        let frames = WalReader::new(dir.clone().into()).frames(&content);
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].1.batched() && !frames[1].1.batched());
+
+        // a dropped transaction writes nothing
+        let mut txn = wal.transaction();
+        txn.write(Item { id: 3 });
+        drop(txn);
+        wal.write(Item { id: 4 });
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2, 4]);
+
+        let other = clear_storage("transaction_unsupported");
+        let wal = WalBuilder::<Item>::new(&other, 100)
+            .framing(Framing::LengthDelimited)
+            .build()
+            .unwrap();
+        let mut txn = wal.transaction();
+        txn.write(Item { id: 1 });
+        assert!(matches!(txn.commit(), Err(WalError::Unsupported(_))));
+    }
+
+    #[test]
+    fn read_in_write_order() {
+        let dir = clear_storage("read_in_write_order");
+        let ids = |wal: &Wal<Item>| wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        // rotate through all files more than once
+        let wal = WalBuilder::new(&dir, 100).build().unwrap();
+        for i in 1..=24 {
+            wal.write(Item { id: i });
+            sleep(Duration::from_millis(5));
+        }
+        sleep(Duration::from_millis(100));
+        let read = ids(&wal);
+        assert_eq!(read.last(), Some(&24));
+        assert!(read.windows(2).all(|w| w[1] == w[0] + 1));
+
+        // logs not yet flushed come last, without gaps
+        for i in 25..=40 {
+            wal.write(Item { id: i });
+        }
+        let read = ids(&wal);
+        assert_eq!(read.last(), Some(&40));
+        assert!(read.windows(2).all(|w| w[1] == w[0] + 1));
+
+        // the writer starts over with the first file after a restart
+        drop(wal);
+        sleep(Duration::from_millis(100));
+        let wal = WalBuilder::new(&dir, 100).build().unwrap();
+        wal.write(Item { id: 41 });
+        sleep(Duration::from_millis(100));
+        let read = ids(&wal);
+        assert_eq!(read.last(), Some(&41));
+        assert!(read.windows(2).all(|w| w[1] > w[0]));
+    }
+
+    #[test]
+    fn read_buffered_logs() {
+        let dir = clear_storage("read_buffered_logs");
+        // the writer only takes the logs after they are read
+        let wal = WalBuilder::new(&dir, 100)
+            .wake_strategy(WakeStrategy::MicroBatch(Duration::from_millis(300)))
+            .build()
+            .unwrap();
+        for i in 1..=3 {
+            wal.write(Item { id: i });
+        }
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2, 3]);
+        let size = std::fs::metadata(format!("{}wal_1", dir)).unwrap().len();
+        assert_eq!(size, format::SEGMENT_HEADER_BYTES as u64);
+
+        // the same logs are read back from the file
+        sleep(Duration::from_millis(400));
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert!(std::fs::metadata(format!("{}wal_1", dir)).unwrap().len() > 0);
+    }
+
+    #[test]
+    fn memory_budget() {
+        let dir = clear_storage("memory_budget");
+        // the writer only takes the logs once the buffer is full
+        let wal = WalBuilder::<Vec<u8>>::new(&dir, 100)
+            .memory_budget(400)
+            .read_scratch_budget(100)
+            .recent_cache(10, 1000)
+            .wake_strategy(WakeStrategy::MicroBatch(Duration::from_millis(200)))
+            .build()
+            .unwrap();
+        let usage = wal.memory_usage();
+        assert_eq!(usage.budget, Some(400));
+        assert!(usage.scratch >= 100 && usage.total() <= 400);
+
+        // each log has 100 bytes of payload, and 300 bytes are left by the scratch buffer
+        for _ in 0..3 {
+            wal.try_write(&vec![0; 92]).unwrap();
+        }
+        assert!(matches!(
+            wal.try_write(&vec![0; 92]),
+            Err(WalError::Capacity(_))
+        ));
+        assert_eq!(wal.memory_usage().buffer, 300);
+
+        // logs taken by the writer move to the recent cache, which makes room for new ones
+        sleep(Duration::from_millis(400));
+        let usage = wal.memory_usage();
+        assert_eq!((usage.buffer, usage.recent_cache), (0, 300));
+        wal.try_write(&vec![0; 92]).unwrap();
+        assert!(wal.memory_usage().total() <= 400);
+    }
+
+    #[cfg(feature = "self-describing")]
+    #[test]
+    fn self_describing() {
+        let dir = clear_storage("self_describing");
+        let wal = WalBuilder::new(&dir, 100)
+            .self_describing(3)
+            .build()
+            .unwrap();
+        wal.write(Item { id: 5 });
+        sleep(Duration::from_millis(100));
+        assert_eq!(wal.read().unwrap()[0].id, 5);
+        let logs = wal.read_dynamic().unwrap();
+        assert_eq!(logs[0].version, 3);
+        assert_eq!(logs[0].content, serde_json::json!({ "id": 5 }));
+        drop(wal);
+
+        // the codec is recorded with the WAL
+        assert!(WalBuilder::<Item>::new(&dir, 100).build().is_err());
+        let plain = Wal::<Item>::new(&clear_storage("self_describing_plain"), 100).unwrap();
+        assert!(plain.read_dynamic().is_err());
+    }
+
+    #[cfg(all(feature = "signals", unix))]
+    #[test]
+    fn signal_flush() {
+        use std::os::unix::process::ExitStatusExt;
+        use std::process::{Command, Stdio};
+
+        let dir = clear_storage("signal_flush");
+        // the process receiving the signal runs the ignored test below
+        let status = Command::new(std::env::current_exe().unwrap())
+            .args(["--ignored", "--exact", "tests::signal_flush_child"])
+            .env("WALCRAFT_SIGNAL_DIR", &dir)
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert_eq!(status.signal(), Some(signal_hook::consts::SIGTERM));
+        let wal = Wal::<Item>::new(&dir, 10000).unwrap();
+        assert_eq!(wal.read().unwrap().len(), 100);
+    }
+
+    #[cfg(all(feature = "signals", unix))]
+    #[test]
+    #[ignore = "terminated by a signal, run by `signal_flush`"]
+    fn signal_flush_child() {
+        let dir = std::env::var("WALCRAFT_SIGNAL_DIR").unwrap();
+        let wal = Wal::new(&dir, 10000).unwrap();
+        wal.install_signal_flush().unwrap();
+        wal.batch_write((0..100).map(|id| Item { id }).collect());
+        signal_hook::low_level::raise(signal_hook::consts::SIGTERM).unwrap();
+        // the process terminates once the WAL is flushed
+        sleep(Duration::from_secs(10));
+        unreachable!("process survived SIGTERM");
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn json_codec() {
+        let dir = clear_storage("json_codec");
+        let wal = WalBuilder::new(&dir, 100)
+            .serialization_codec(SerializationCodec::Json)
+            .build()
+            .unwrap();
+        wal.write(Item { id: 5 });
+        wal.flush().unwrap();
+        assert_eq!(wal.read().unwrap()[0].id, 5);
+        // other tools find plain JSON in the payloads
+        let payloads = wal.read_raw().unwrap();
+        assert_eq!(payloads[0].as_slice(), br#"{"id":5}"#);
+        drop(wal);
+
+        // the codec is recorded with the WAL
+        assert!(WalBuilder::<Item>::new(&dir, 100).build().is_err());
+    }
+
+    #[test]
+    fn scrubber() {
+        let dir = clear_storage("scrubber");
+        let wal = WalBuilder::new(&dir, 100)
+            .scrub_interval(Duration::from_millis(10))
+            .build()
+            .unwrap();
+        // two logs fill a file, so wal_1 and wal_2 are sealed
+        for i in 1..=5 {
+            wal.write(Item { id: i });
+            sleep(Duration::from_millis(20));
+        }
+        sleep(Duration::from_millis(100));
+        assert!(wal.health().scrubbed > 0);
+        assert!(wal.health().corruptions.is_empty());
+
+        // bit rot in a sealed file is found
+        let path = format!("{}wal_1", dir);
+        let mut content = std::fs::read(&path).unwrap();
+        content[12] ^= 0x01;
+        std::fs::write(&path, content).unwrap();
+        sleep(Duration::from_millis(300));
+        let health = wal.health();
+        assert_eq!(health.corruptions.len(), 1);
+        assert_eq!(health.corruptions[0].id, 1);
+    }
+
+    #[test]
+    fn verify_on_open() {
+        let dir = clear_storage("verify_on_open");
+        let wal = WalBuilder::new(&dir, 100)
+            .verify_on_rotation(true)
+            .build()
+            .unwrap();
+        // two logs fill a file, so wal_1 and wal_2 are sealed
+        for i in 1..=5 {
+            wal.write(Item { id: i });
+            sleep(Duration::from_millis(20));
+        }
+        wal.close().unwrap();
+        let path = format!("{}wal_2", dir);
+        let mut content = std::fs::read(&path).unwrap();
+        content[12] ^= 0x01;
+        std::fs::write(&path, content).unwrap();
+
+        // reads wait for the check of sealed files
+        let wal: Wal<Item> = WalBuilder::new(&dir, 100)
+            .verify_on_open(OpenVerification::Deferred { block_reads: true })
+            .build()
+            .unwrap();
+        wal.read().unwrap();
+        assert!(wal.verified());
+        let stats = wal.stats();
+        assert!(stats.verified_files > 0);
+        assert_eq!(stats.unverified_files, 0);
+        let health = wal.health();
+        assert_eq!(health.corruptions.len(), 1);
+        assert_eq!(health.corruptions[0].id, 2);
+        drop(wal);
+
+        // without verification, nothing is checked
+        let wal: Wal<Item> = Wal::new(&dir, 100).unwrap();
+        assert!(wal.verified());
+        assert_eq!(wal.stats().verified_files, 0);
+        assert!(wal.health().corruptions.is_empty());
+    }
+
+    #[test]
+    fn import_lines() {
+        let dir = clear_storage("import_lines");
+        // batches are split and wait for the writer thread to make room within the budget
+        let wal = WalBuilder::new(&dir, 5000)
+            .memory_budget(16 * 1024)
+            .read_scratch_budget(1024)
+            .build()
+            .unwrap();
+        let mut csv = (0..3000).map(|i| format!("{}\n", i)).collect::<String>();
+        csv.push_str("\n3000\n");
+        let parse = |line: &str| line.parse().map(|id| Item { id });
+        assert_eq!(wal.import_lines(csv.as_bytes(), parse).unwrap(), 3001);
+        sleep(Duration::from_millis(200));
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, (0..=3000).collect::<Vec<_>>());
+
+        // the line failing to parse is reported, the lines before it are kept
+        let dir = clear_storage("import_lines_invalid");
+        let wal = Wal::new(&dir, 100).unwrap();
+        match wal.import_lines("1\n2\nx\n4\n".as_bytes(), parse) {
+            Err(WalError::Serialization(e)) => assert!(e.starts_with("Line 3:"), "{}", e),
+            r => panic!("unexpected {:?}", r),
+        }
+        sleep(Duration::from_millis(100));
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, vec![1, 2]);
+    }
+
+    #[cfg(feature = "json-import")]
+    #[test]
+    fn import_json_lines() {
+        let dir = clear_storage("import_json_lines");
+        let wal = Wal::<Item>::new(&dir, 100).unwrap();
+        let export = "{\"id\": 1}\n{\"id\": 2}\n";
+        assert_eq!(wal.import_json_lines(export.as_bytes()).unwrap(), 2);
+        sleep(Duration::from_millis(100));
+        assert_eq!(wal.read().unwrap()[1].id, 2);
+        assert!(wal.import_json_lines("{\"id\": -1}".as_bytes()).is_err());
+    }
+
+    #[cfg(all(feature = "json-export", feature = "json-import"))]
+    #[test]
+    fn export_jsonl() {
+        let dir = clear_storage("export_jsonl");
+        let wal = Wal::<Item>::new(&dir, 100).unwrap();
+        wal.try_write_all(&(0..5).map(|id| Item { id }).collect::<Vec<_>>())
+            .unwrap();
+        wal.flush().unwrap();
+        let path = format!("{}export.jsonl", dir);
+        assert_eq!(wal.export_jsonl(&path).unwrap(), 5);
+        let export = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(export.lines().next(), Some("{\"id\":0}"));
+
+        // the export is loaded into another WAL as it was
+        let other = Wal::<Item>::new(&clear_storage("export_jsonl_import"), 100).unwrap();
+        assert_eq!(other.import_jsonl(&path).unwrap(), 5);
+        sleep(Duration::from_millis(100));
+        let ids = other
+            .read()
+            .unwrap()
+            .iter()
+            .map(|i| i.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, (0..5).collect::<Vec<_>>());
+        assert!(matches!(
+            other.import_jsonl(format!("{}missing.jsonl", dir)),
+            Err(WalError::Io { path: Some(_), .. })
+        ));
+    }
+
+    #[test]
+    fn forked_handle() {
+        let dir = clear_storage("forked_handle");
+        let mut wal = WalBuilder::new(&dir, 100)
+            .on_fork(ForkBehavior::ReadOnly)
+            .build()
+            .unwrap();
+        wal.write(Item { id: 1 });
+        sleep(Duration::from_millis(100));
+
+        // as seen from a child process, files are read but nothing is written
+        wal.fork.pid = wal.fork.pid.wrapping_add(1);
+        assert!(matches!(
+            wal.try_write(&Item { id: 2 }),
+            Err(WalError::ForkDetected(_))
+        ));
+        assert_eq!(wal.read().unwrap().len(), 1);
+        let clone = wal.clone();
+        assert!(matches!(clone.compact(), Err(WalError::ForkDetected(_))));
+
+        wal.fork.behavior = ForkBehavior::Fail;
+        assert!(matches!(wal.read(), Err(WalError::ForkDetected(_))));
+    }
+
+    #[test]
+    fn read_reported() {
+        let dir = clear_storage("read_reported");
+        let wal = Wal::new(&dir, 100).unwrap();
+        wal.write(Item { id: 1 });
+        // a log of another type that no longer fits
+        wal.try_write_raw(&[1]).unwrap();
+        wal.write(Item { id: 2 });
+        sleep(Duration::from_millis(100));
+        let report = wal.read_reported().unwrap();
+        assert_eq!(report.logs.iter().map(|i| i.id).collect::<Vec<_>>(), [1, 2]);
+        assert_eq!((report.decoded, report.skipped), (2, 1));
+        assert_eq!(report.errors[0].lsn, 1);
+        assert_eq!(report.decoders, [2]);
+        drop(wal);
+
+        // read as its older type once a fallback is registered
+        let wal = WalBuilder::new(&dir, 100)
+            .decode_fallback(|old: u8| Item {
+                id: old as u16 * 100,
+            })
+            .build()
+            .unwrap();
+        let report = wal.read_reported().unwrap();
+        let ids = report.logs.iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, [1, 100, 2]);
+        assert_eq!((report.decoded, report.skipped), (3, 0));
+        assert_eq!(report.decoders, [2, 1]);
+        assert_eq!(wal.get(1).unwrap().map(|i| i.id), Some(100));
+    }
+
+    #[test]
+    fn versioned() {
+        #[derive(Serialize, Deserialize)]
+        struct ItemV1 {
+            id: u8,
+        }
+        impl Migrate for ItemV1 {
+            const VERSION: u8 = 1;
+            fn migrate(_: Envelope<'_>) -> Option<Self> {
+                None
+            }
+        }
+        #[derive(Serialize, Deserialize)]
+        struct ItemV2 {
+            id: u16,
+        }
+        impl Migrate for ItemV2 {
+            const VERSION: u8 = 2;
+            fn migrate(log: Envelope<'_>) -> Option<Self> {
+                match log.version() {
+                    1 => log.decode::<ItemV1>().map(|old| ItemV2 {
+                        id: old.id as u16 * 100,
+                    }),
+                    _ => None,
+                }
+            }
+        }
+
+        let dir = clear_storage("versioned");
+        let wal = WalBuilder::new(&dir, 100).versioned().build().unwrap();
+        wal.write(ItemV1 { id: 3 });
+        wal.write(ItemV1 { id: 4 });
+        wal.close().unwrap();
+        // the encoding is recorded with the WAL
+        assert!(Wal::<ItemV2>::new(&dir, 100).is_err());
+
+        let wal = WalBuilder::new(&dir, 100).versioned().build().unwrap();
+        wal.write(ItemV2 { id: 5 });
+        sleep(Duration::from_millis(100));
+        let report = wal.read_reported().unwrap();
+        let ids = report.logs.iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, [300, 400, 5]);
+        assert_eq!(report.decoders, [1, 2]);
+        // logs of the current version aren't read as the older one
+        wal.close().unwrap();
+        let wal = WalBuilder::<ItemV1>::new(&dir, 100)
+            .versioned()
+            .build()
+            .unwrap();
+        assert_eq!(wal.read_reported().unwrap().skipped, 1);
+    }
+
+    #[test]
+    fn paced_writer() {
+        let dir = clear_storage("paced_writer");
+        let wal = WalBuilder::new(&dir, 100)
+            .pacing(Pacing::new().bytes_per_sec(1000))
+            .build()
+            .unwrap();
+        // bytes written across files
+        let size = || {
+            (1..=5)
+                .filter_map(|id| std::fs::metadata(format!("{}wal_{}", dir, id)).ok())
+                .map(|m| m.len())
+                .sum::<u64>()
+        };
+        // twice the bytes allowed per second overdraw the bucket for a second
+        wal.write(vec![0u8; 2000]);
+        sleep(Duration::from_millis(100));
+        let first = size();
+        assert!(first > 2000);
+        wal.write(vec![0u8; 10]);
+        sleep(Duration::from_millis(200));
+        assert_eq!(size(), first);
+        // the log is still readable meanwhile
+        assert_eq!(wal.read().unwrap().len(), 2);
+        sleep(Duration::from_millis(1000));
+        assert!(size() > first);
+
+        // lifting the limit at runtime writes logs right away
+        wal.set_pacing(Pacing::new()).unwrap();
+        wal.write(vec![0u8; 2000]);
+        sleep(Duration::from_millis(100));
+        let second = size();
+        wal.write(vec![0u8; 10]);
+        sleep(Duration::from_millis(100));
+        assert!(size() > second);
+    }
+
+    #[test]
+    fn diff() {
+        let dir = clear_storage("diff");
+        let wal = WalBuilder::new(&dir, 100)
+            .keyed(|item: &Item| item.id % 2)
+            .build()
+            .unwrap();
+        let lsns = (1..=5)
+            .map(|id| wal.try_write(&Item { id }).unwrap())
+            .collect::<Vec<_>>();
+        sleep(Duration::from_millis(100));
+        let ids = |logs: Vec<Item>| logs.iter().map(|i| i.id).collect::<Vec<_>>();
+        let diff = wal.diff(lsns[1]..lsns[4]).unwrap();
+        assert_eq!(ids(diff.added), [2, 3, 4]);
+        // the newest log of even and odd ids
+        assert_eq!(ids(diff.latest_by_key), [3, 4]);
+        assert!(wal
+            .diff(lsns[4] + 1..lsns[4] + 10)
+            .unwrap()
+            .added
+            .is_empty());
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn read_raw_bytes() {
+        let dir = clear_storage("read_raw_bytes");
+        let wal = WalBuilder::new(&dir, 1000).dedup_above(64).build().unwrap();
+        let large = "large".repeat(20);
+        wal.batch_write(vec!["a".to_string(), large.clone(), "b".to_string()]);
+        sleep(Duration::from_millis(100));
+        wal.write("pending".to_string());
+        let payloads = wal.read_raw_bytes().unwrap();
+        assert_eq!(payloads, wal.read_raw().unwrap());
+        // payloads written as is share the buffer of their file
+        let file = payloads[0].as_ptr() as usize;
+        let next = payloads[2].as_ptr() as usize;
+        assert!(next > file && next - file < 100);
+        let decoded = payloads
+            .iter()
+            .map(|p| bincode::deserialize::<String>(p).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(decoded[1], large);
+        assert_eq!(decoded.len(), 4);
+    }
+
+    #[test]
+    fn rate_limit() {
+        let dir = clear_storage("rate_limit");
+        let wal = WalBuilder::new(&dir, 100)
+            .rate_limit(RateLimit::new(1).burst(5))
+            .build()
+            .unwrap();
+        let noisy = wal.with_rate_limit(RateLimit::new(1).burst(2));
+        noisy
+            .try_write_all(&[Item { id: 1 }, Item { id: 2 }])
+            .unwrap();
+        assert!(matches!(
+            noisy.try_write(&Item { id: 3 }),
+            Err(WalError::Throttled(_))
+        ));
+        // other handles keep what's left of the WAL's limit
+        for id in 4..=6 {
+            wal.try_write(&Item { id }).unwrap();
+        }
+        assert!(matches!(
+            wal.atomic(|txn| txn.write(Item { id: 7 })),
+            Err(WalError::Throttled(_))
+        ));
+        sleep(Duration::from_millis(100));
+        assert_eq!(wal.read().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn exclusive_writer() {
+        let dir = clear_storage("exclusive_writer");
+        let open = || {
+            WalBuilder::<Item>::new(&dir, 100)
+                .duplicate_guard(false)
+                .build()
+        };
+        let wal = open().unwrap();
+        let clone = wal.clone();
+        assert!(matches!(open(), Err(WalError::Locked(_))));
+        // readers don't need the lease
+        assert!(WalBuilder::<Item>::new(&dir, 100)
+            .read_only(true)
+            .build()
+            .is_ok());
+        drop(wal);
+        assert!(matches!(open(), Err(WalError::Locked(_))));
+        drop(clone);
+        assert!(open().is_ok());
+        // writers can opt out of the lease
+        let held = open().unwrap();
+        assert!(WalBuilder::<Item>::new(&dir, 100)
+            .exclusive(false)
+            .duplicate_guard(false)
+            .build()
+            .is_ok());
+        drop(held);
+    }
+
+    #[test]
+    fn slots() {
+        let dir = clear_storage("slots");
+        let open = || WalBuilder::<Item>::new(&dir, 1000).slots(2).build();
+        // as opened by two processes
+        let (first, second) = (open().unwrap(), open().unwrap());
+        assert!(matches!(open(), Err(WalError::Locked(_))));
+        for id in 0..40 {
+            let wal = match id % 3 {
+                0 => &first,
+                _ => &second,
+            };
+            wal.write(Item { id });
+            wal.flush().unwrap();
+        }
+        assert_eq!(first.read().unwrap().len(), 14);
+        let merged = first.read_merged().unwrap();
+        let ids = merged.iter().map(|log| log.log.as_ref().unwrap().id);
+        assert_eq!(ids.collect::<Vec<_>>(), (0..40).collect::<Vec<_>>());
+        let lsns = merged.iter().map(|log| log.lsn);
+        assert_eq!(lsns.collect::<Vec<_>>(), (0..40).collect::<Vec<_>>());
+
+        // a slot is leased again once its handles are dropped, and readers lease none
+        drop(second);
+        let third = open().unwrap();
+        third.write(Item { id: 40 });
+        third.flush().unwrap();
+        let reader = WalBuilder::<Item>::new(&dir, 1000)
+            .slots(2)
+            .read_only(true)
+            .build()
+            .unwrap();
+        let merged = reader.read_merged().unwrap();
+        assert_eq!(merged.len(), 41);
+        assert_eq!(merged[40].lsn, 40);
+        assert_eq!(merged[40].log.as_ref().unwrap().id, 40);
+        // nothing is removed while a slot is written
+        assert!(matches!(
+            Wal::recreate(WalBuilder::<Item>::new(&dir, 1000).slots(2)),
+            Err(WalError::AlreadyOpen(_))
+        ));
+    }
+
+    #[test]
+    fn duplicate_guard() {
+        let dir = clear_storage("duplicate_guard");
+        let open = || WalBuilder::<Item>::new(&dir, 100).exclusive(false).build();
+        let wal = open().unwrap();
+        let clone = wal.clone();
+        assert!(matches!(open(), Err(WalError::AlreadyOpen(_))));
+        assert!(matches!(
+            Wal::<Item>::new("./tmp/../tmp/duplicate_guard", 100),
+            Err(WalError::AlreadyOpen(_))
+        ));
+        assert!(WalBuilder::<Item>::new(&dir, 100)
+            .read_only(true)
+            .build()
+            .is_ok());
+        drop(wal);
+        assert!(matches!(open(), Err(WalError::AlreadyOpen(_))));
+        // the writer thread has stopped once the last handle is dropped
+        drop(clone);
+        assert!(open().is_ok());
+    }
+
+    #[test]
+    fn retype() {
+        #[derive(Serialize, Deserialize, Debug, PartialEq)]
+        enum Versioned {
+            V1(u16),
+        }
+
+        let dir = clear_storage("retype");
+        let wal: Wal<Item> = Wal::new(&dir, 1000).unwrap();
+        wal.try_write(&Item { id: 1 }).unwrap();
+        let versioned = wal.retype::<Versioned>().unwrap();
+        let lsn = versioned.try_write(&Versioned::V1(2)).unwrap();
+        assert_eq!(lsn, 1);
+        std::thread::sleep(Duration::from_millis(50));
+        // old logs don't deserialize as the new type
+        let report = versioned.read_reported().unwrap();
+        assert_eq!(report.logs, vec![Versioned::V1(2)]);
+        assert_eq!(report.skipped, 1);
+
+        let keyed: Wal<Item> = WalBuilder::new(&clear_storage("retype_keyed"), 1000)
+            .keyed(|item: &Item| item.id)
+            .build()
+            .unwrap();
+        assert!(matches!(
+            keyed.retype::<Versioned>(),
+            Err(WalError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn recycle() {
+        let dir = clear_storage("recycle");
+        // left by a crash while the first file was recycled
+        std::fs::write(format!("{}wal_1.recycle", dir), b"").unwrap();
+        let wal = WalBuilder::new(&dir, 100).build().unwrap();
+        assert!(!Path::new(&format!("{}wal_1.recycle", dir)).exists());
+        // two logs fill a file, so the first file is recycled once the fifth one is filled
+        for i in 0..11 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        let rotations = wal.rotation_history().rotations;
+        let left = rotations.iter().map(|r| (r.segment, r.entries));
+        assert_eq!(left.collect::<Vec<_>>(), [1, 2, 3, 4, 5].map(|id| (id, 2)));
+        let ids: Vec<_> = wal.read().unwrap().into_iter().map(|i| i.id).collect();
+        assert_eq!(ids, (2..11).collect::<Vec<_>>());
+        assert_eq!(
+            std::fs::metadata(format!("{}wal_1", dir)).unwrap().len(),
+            format::SEGMENT_HEADER_BYTES as u64 + 14
+        );
+        assert!(!Path::new(&format!("{}wal_1.recycle", dir)).exists());
+    }
+
+    #[test]
+    fn sink() {
+        let dir = clear_storage("sink");
+        let wal = Wal::new(&dir, 1000).unwrap();
+        let mut sink = wal.sink().max_logs(3).max_delay(Duration::from_secs(60));
+        sink.feed(&Item { id: 1 }).unwrap();
+        sink.feed(&Item { id: 2 }).unwrap();
+        assert_eq!(sink.pending(), 2);
+        wal.try_write(&Item { id: 3 }).unwrap();
+        // the third log fills the batch
+        sink.feed(&Item { id: 4 }).unwrap();
+        assert_eq!(sink.pending(), 0);
+        sink.feed(&Item { id: 5 }).unwrap();
+        assert_eq!(sink.flush().unwrap(), 4..5);
+        assert_eq!(sink.flush().unwrap(), 5..5);
+
+        // logs of a sink are flushed once they waited long enough, or when it's dropped
+        let mut sink = wal.sink().max_delay(Duration::ZERO);
+        sink.feed(&Item { id: 6 }).unwrap();
+        assert_eq!(sink.pending(), 0);
+        let mut sink = wal.sink();
+        sink.feed(&Item { id: 7 }).unwrap();
+        drop(sink);
+        sleep(Duration::from_millis(50));
+        let ids: Vec<_> = wal.read().unwrap().into_iter().map(|i| i.id).collect();
+        assert_eq!(ids, vec![3, 1, 2, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn layout() {
+        let dir = clear_storage("layout");
+        let open = |segments| {
+            WalBuilder::<Item>::new(&dir, 100)
+                .segments(segments)
+                .file_prefix("seg_")
+                .build()
+        };
+        let wal = open(3).unwrap();
+        // three logs fill a file, so the first file is reused once the third one is filled
+        for i in 0..13 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        let ids: Vec<_> = wal.read().unwrap().into_iter().map(|i| i.id).collect();
+        assert_eq!(ids, (6..13).collect::<Vec<_>>());
+        assert!(Path::new(&format!("{}seg_3", dir)).exists());
+        assert!(!Path::new(&format!("{}seg_4", dir)).exists());
+        assert!(!Path::new(&format!("{}wal_1", dir)).exists());
+        drop(wal);
+        // opened without the option, the WAL keeps the number of files recorded in meta file
+        let wal = WalBuilder::<Item>::new(&dir, 100)
+            .file_prefix("seg_")
+            .build()
+            .unwrap();
+        for i in 13..25 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        assert!(!Path::new(&format!("{}seg_4", dir)).exists());
+        let ids: Vec<_> = wal.read().unwrap().into_iter().map(|i| i.id).collect();
+        assert_eq!(ids.last(), Some(&24));
+        drop(wal);
+        // files beyond the last one would never be read
+        assert!(matches!(open(2), Err(WalError::Unsupported(_))));
+        assert!(matches!(open(1), Err(WalError::Unsupported(_))));
+
+        let dir = clear_storage("layout_size");
+        let wal = WalBuilder::new(&dir, 100_000)
+            .segment_size(28)
+            .build()
+            .unwrap();
+        // two logs fill a file whatever the capacity
+        for i in 0..11 {
+            wal.write(Item { id: i });
+            wal.flush().unwrap();
+        }
+        let ids: Vec<_> = wal.read().unwrap().into_iter().map(|i| i.id).collect();
+        assert_eq!(ids, (2..11).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn on_ack() {
+        // logs synced by the flush only are acknowledged as well
+        let policies = [
+            SyncPolicy::Never,
+            SyncPolicy::Interval(Duration::from_secs(3600)),
+        ];
+        for policy in policies {
+            let dir = clear_storage("on_ack");
+            let acked = Arc::new(Mutex::new(Vec::new()));
+            let log = acked.clone();
+            let wal = WalBuilder::new(&dir, 100_000)
+                .sync_policy(policy)
+                .on_ack(move |lsns| log.lock().unwrap().push(lsns))
+                .build()
+                .unwrap();
+            let writers = (0..4)
+                .map(|_| {
+                    let wal = wal.clone();
+                    std::thread::spawn(move || {
+                        for i in 0..50 {
+                            wal.write(Item { id: i });
+                        }
+                    })
+                })
+                .collect::<Vec<_>>();
+            writers.into_iter().for_each(|w| w.join().unwrap());
+            wal.flush().unwrap();
+            // acknowledged in order, without a gap
+            let acked = acked.lock().unwrap();
+            let mut next = 0;
+            for lsns in acked.iter() {
+                assert_eq!(*lsns.start(), next);
+                next = lsns.end() + 1;
+            }
+            assert_eq!(next, 200);
+        }
+    }
+
+    #[test]
+    fn capacity_after_reopen() {
+        let dir = clear_storage("capacity_after_reopen");
+        let wal = Wal::new(&dir, 100).unwrap();
+        wal.write(Item { id: 1 });
+        wal.close().unwrap();
+        // two logs fill a file, including the one written before the WAL was opened again
+        let wal = Wal::new(&dir, 100).unwrap();
+        wal.write(Item { id: 2 });
+        wal.flush().unwrap();
+        wal.write(Item { id: 3 });
+        wal.flush().unwrap();
+        let size = |id| {
+            std::fs::metadata(format!("{}wal_{}", dir, id))
+                .unwrap()
+                .len()
+        };
+        let header = format::SEGMENT_HEADER_BYTES as u64;
+        assert_eq!((size(1), size(2)), (header + 28, header + 14));
+        let ids = wal.read().unwrap().iter().map(|i| i.id).collect::<Vec<_>>();
+        assert_eq!(ids, [1, 2, 3]);
+    }
+
+    #[test]
+    fn flush_and_close() {
+        let dir = clear_storage("flush_and_close");
+        // logs wait in the buffer for a while
+        let wal = WalBuilder::new(&dir, 1000)
+            .wake_strategy(WakeStrategy::MicroBatch(Duration::from_millis(50)))
+            .build()
+            .unwrap();
+        wal.write(Item { id: 1 });
+        wal.flush().unwrap();
+        assert_eq!(
+            std::fs::metadata(format!("{}wal_1", dir)).unwrap().len(),
+            format::SEGMENT_HEADER_BYTES as u64 + 14
+        );
+        wal.flush().unwrap();
+
+        // the last handle writes what's left on its way out
+        let clone = wal.clone();
+        clone.write(Item { id: 2 });
+        clone.close().unwrap();
+        wal.write(Item { id: 3 });
+        drop(wal);
+        assert_eq!(
+            std::fs::metadata(format!("{}wal_1", dir)).unwrap().len(),
+            format::SEGMENT_HEADER_BYTES as u64 + 42
+        );
+    }
+
+    #[test]
+    fn last_read() {
+        let dir = clear_storage("last_read");
+        let wal = Wal::new(&dir, 100).unwrap();
+        assert_eq!(wal.last_read(), None);
+        // two logs fill a file, so the last one is alone in wal_3
+        for id in 0..5 {
+            wal.write(Item { id });
+            wal.flush().unwrap();
+        }
+        assert_eq!(wal.read().unwrap().len(), 5);
+        let read = wal.last_read().unwrap();
+        assert_eq!(read.segments, 3);
+        assert_eq!(read.bytes, 3 * format::SEGMENT_HEADER_BYTES as u64 + 5 * 14);
+        assert_eq!(read.frames, 5);
+        assert!(read.duration > Duration::ZERO);
+
+        // the newest log is read from the current file only
+        wal.read_last(1).unwrap();
+        let read = wal.last_read().unwrap();
+        assert_eq!((read.segments, read.frames), (1, 1));
+        // every handle records its own reads
+        assert_eq!(wal.clone().last_read(), None);
+    }
+
+    #[test]
+    fn sync_policy() {
+        let dir = clear_storage("sync_policy");
+        let wal = WalBuilder::new(&dir, 1000)
+            .sync_policy(SyncPolicy::Interval(Duration::from_millis(100)))
+            .build()
+            .unwrap();
+        // the first log is sampled, and counted once synced
+        wal.write(Item { id: 1 });
+        sleep(Duration::from_millis(30));
+        assert_eq!(
+            std::fs::metadata(format!("{}wal_1", dir)).unwrap().len(),
+            format::SEGMENT_HEADER_BYTES as u64 + 14
+        );
+        assert_eq!(wal.stats().write_latency.samples, 0);
+        sleep(Duration::from_millis(150));
+        let latency = wal.stats().write_latency;
+        assert_eq!(latency.samples, 1);
+        assert!(latency.max >= Duration::from_millis(90));
+
+        let dir = clear_storage("sync_policy_every_write");
+        let wal = WalBuilder::new(&dir, 1000)
+            .sync_policy(SyncPolicy::EveryWrite)
+            .build()
+            .unwrap();
+        for i in 0..20 {
+            wal.write(Item { id: i });
+        }
+        wal.flush().unwrap();
+        assert_eq!(wal.read().unwrap().len(), 20);
+        assert_eq!(wal.stats().write_latency.samples, 2);
+    }
+
+    #[test]
+    fn nested_read() {
+        let dir = clear_storage("nested_read");
+        // the handle used by the key function of the reading thread, taken on first use
+        let nested: Arc<Mutex<Option<Wal<Item>>>> = Arc::default();
+        let reading = std::thread::current().id();
+        let inner = nested.clone();
+        let wal = WalBuilder::new(&dir, 1000)
+            .keyed(move |item: &Item| {
+                if std::thread::current().id() != reading {
+                    return item.id % 2;
+                }
+                let wal = inner.lock().unwrap().take();
+                if let Some(wal) = wal {
+                    // the outer read holds the read lock of this thread
+                    wal.write(Item { id: 100 });
+                    wal.flush().unwrap();
+                    assert_eq!(wal.read().unwrap().len(), 5);
+                    assert_eq!(wal.read_last(1).unwrap()[0].id, 100);
+                    assert_eq!(wal.truncate(0).unwrap(), 0);
+                }
+                item.id % 2
+            })
+            .build()
+            .unwrap();
+        for id in 0..4 {
+            wal.write(Item { id });
+        }
+        wal.flush().unwrap();
+        *nested.lock().unwrap() = Some(wal.clone());
+        let ids = |logs: Vec<Item>| logs.iter().map(|i| i.id).collect::<Vec<_>>();
+        // the log written during the read is left out of its snapshot
+        assert_eq!(ids(wal.read_by_key(&0u16).unwrap()), vec![0, 2]);
+        assert!(nested.lock().unwrap().is_none());
+        assert_eq!(ids(wal.read().unwrap()), vec![0, 1, 2, 3, 100]);
+    }
+
+    #[test]
+    fn truncate_during_read() {
+        let dir = clear_storage("truncate_during_read");
+        let wal = Wal::new(&dir, 2000).unwrap();
+        let mut id = 0;
+        while !Path::new(&format!("{}wal_2", dir)).exists() {
+            wal.write(Item { id });
+            wal.flush().unwrap();
+            id += 1;
+        }
+        assert!(id > 5);
+        let truncated = Cell::new(false);
+        let logs = wal
+            .read_filtered(|_| {
+                // the writer leaves the logs to a later truncation instead of waiting for the read
+                if !truncated.replace(true) {
+                    assert_eq!(wal.truncate(3).unwrap(), 0);
+                }
+                true
+            })
+            .unwrap();
+        assert_eq!(logs.len(), id as usize);
+        assert_eq!(wal.read().unwrap()[0].id, 0);
+        wal.truncate(3).unwrap();
+        assert_eq!(wal.read().unwrap()[0].id, 4);
+    }
+
+    #[test]
+    fn checksums() {
+        let dir = clear_storage("checksums");
+        let open = || {
+            WalBuilder::new(&dir, 100_000)
+                .checksums(true)
+                .build()
+                .unwrap()
+        };
+        let wal = open();
+        for i in 0..3u8 {
+            wal.write(vec![i; 32]);
+        }
+        wal.flush().unwrap();
+        assert_eq!(wal.read().unwrap().len(), 3);
+
+        // damage the payload of the second log on storage
+        let path = format!("{}wal_1", dir);
+        let mut content = std::fs::read(&path).unwrap();
+        let at = content.windows(32).position(|w| w == [1; 32]).unwrap();
+        content[at + 5] = 7;
+        std::fs::write(&path, &content).unwrap();
+        assert_eq!(wal.read().unwrap(), vec![vec![0; 32], vec![2; 32]]);
+        assert_eq!(wal.last_read().unwrap().corrupted, 1);
+        assert_eq!(wal.verify().unwrap().corrupt_frames, 1);
+        drop(wal);
+
+        // the damaged frame doesn't cut the frames following it once the WAL is opened again
+        let wal: Wal<Vec<u8>> = open();
+        wal.write(vec![3; 32]);
+        wal.flush().unwrap();
+        assert_eq!(wal.read().unwrap().len(), 3);
+        assert_eq!(wal.last_read().unwrap().corrupted, 1);
+    }
 }
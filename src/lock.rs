@@ -1,20 +1,10 @@
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex, MutexGuard};
 
 struct LockInner {
-    // Interface to signal writer to stop here
-    can_write: AtomicBool,
-    // confirmation from writer that it has stopped
-    is_writing: AtomicBool,
-}
-
-impl LockInner {
-    pub fn new() -> Self {
-        Self {
-            can_write: AtomicBool::new(true),
-            is_writing: AtomicBool::new(true),
-        }
-    }
+    // held by the writer from taking logs out of the buffer until they are in a file, and by
+    // readers that need files and buffer to agree
+    flushing: Mutex<()>,
 }
 
 #[derive(Clone)]
@@ -22,44 +12,56 @@ pub(crate) struct LockManager {
     inner: Arc<LockInner>,
 }
 
-// todo: change ordering to ensure proper synchronization between threads
 impl LockManager {
     pub fn new() -> Self {
         Self {
-            inner: Arc::new(LockInner::new()),
+            inner: Arc::new(LockInner {
+                flushing: Mutex::new(()),
+            }),
         }
     }
 
-    // writer to check if it can write
-    pub fn can_write(&self) -> bool {
-        self.inner.can_write.load(Ordering::Relaxed)
+    // wait for logs taken out of the buffer to reach a file, and keep new ones in the buffer
+    // until the guard is dropped
+    pub fn flush_guard(&self) -> MutexGuard<'_, ()> {
+        self.inner
+            .flushing
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
     }
+}
 
-    // request from interface to writer to stop
-    pub fn request_to_stop(&self) {
-        self.inner.can_write.store(false, Ordering::Relaxed);
-    }
+thread_local! {
+    // read locks held by reads in progress on this thread, by address
+    static READING: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
 
-    // response from writer that it is stopping
-    pub fn stop(&self) {
-        if self.inner.can_write.load(Ordering::Relaxed) {
-            panic!("Method `request_to_stop` to stop shall be called before calling `stop`");
-        }
-        self.inner.is_writing.store(false, Ordering::Relaxed);
+// Marks a read lock as held by the current thread until dropped, so a read nested in a read of
+// the same WAL, e.g. from the key function of [crate::Wal::read_by_key], goes on under the lock
+// of the outer read instead of waiting for it
+pub(crate) struct Reading(usize);
+
+impl Reading {
+    pub fn enter<T>(lock: &Arc<T>) -> Self {
+        let address = Arc::as_ptr(lock) as *const () as usize;
+        READING.with(|held| held.borrow_mut().push(address));
+        Self(address)
     }
 
-    // check if writer has stopped
-    pub fn has_stopped(&self) -> bool {
-        if self.inner.can_write.load(Ordering::Relaxed) {
-            panic!("The lock has not been request to stop");
-        }
-        self.inner.is_writing.load(Ordering::Relaxed)
+    // whether a read of the current thread holds `lock`
+    pub fn held<T>(lock: &Arc<T>) -> bool {
+        let address = Arc::as_ptr(lock) as *const () as usize;
+        READING.with(|held| held.borrow().contains(&address))
     }
+}
 
-    // start write again
-    // this is more of a reset method
-    pub fn start(&self) {
-        self.inner.can_write.store(true, Ordering::Relaxed);
-        self.inner.is_writing.store(true, Ordering::Relaxed);
+impl Drop for Reading {
+    fn drop(&mut self) {
+        READING.with(|held| {
+            let mut held = held.borrow_mut();
+            if let Some(i) = held.iter().rposition(|a| *a == self.0) {
+                held.remove(i);
+            }
+        });
     }
 }
@@ -0,0 +1,426 @@
+use crate::checksum;
+use crate::durable;
+use crate::format::FormatVersion;
+use crate::meta::{self, Meta};
+use crate::segment::{SegmentDigest, SegmentSpan};
+use crate::WalError;
+use std::path::{Path, PathBuf};
+
+/// Layout used to store the metadata of a WAL on storage
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum ManifestKind {
+    /// A single `meta` file holding the current file pointer and digests of sealed files
+    #[default]
+    Single,
+    /// A `manifest_N` record for every WAL file `wal_N`, each replaced atomically
+    ///
+    /// The current file is the one whose record has the highest generation, so readers always
+    /// see either the old or the new record of a file and never a partially written one.
+    PerSegment,
+}
+
+// Store of the metadata of a WAL, in either of the supported layouts
+pub(crate) struct Manifest {
+    location: PathBuf,
+    kind: ManifestKind,
+    // generation and file of the latest per segment record
+    generation: u64,
+    current: u8,
+    // sealed files already recorded in per segment records
+    recorded: Vec<Sealed>,
+}
+
+// id of a sealed file, with its digest and the time range of its logs
+type Sealed = (u8, Option<SegmentDigest>, Option<SegmentSpan>);
+
+impl Manifest {
+    // Detect the layout used by the WAL files at `location`, falling back to `preferred` for a
+    // new WAL
+    pub fn open(location: &Path, preferred: ManifestKind) -> Self {
+        let kind = if !Self::record_ids(location).is_empty() {
+            ManifestKind::PerSegment
+        } else if location.join("meta").exists() {
+            ManifestKind::Single
+        } else {
+            preferred
+        };
+        Self {
+            location: location.to_path_buf(),
+            kind,
+            generation: 0,
+            current: 0,
+            recorded: Vec::new(),
+        }
+    }
+
+    pub fn load(&mut self) -> Result<Meta, WalError> {
+        if self.kind == ManifestKind::Single {
+            return Meta::read(&self.location);
+        }
+        let records = Self::read_records(&self.location);
+        let (current, latest) = records
+            .iter()
+            .max_by_key(|(_, record)| record.generation)
+            .ok_or_else(|| WalError::File("Failed to read manifest".to_string()))?;
+        self.generation = latest.generation;
+        self.current = *current;
+        self.recorded = records
+            .iter()
+            .filter(|(_, r)| r.digest.is_some() || r.span.is_some())
+            .map(|(id, r)| (*id, r.digest.clone(), r.span))
+            .collect();
+        Ok(Meta {
+            pointer: *current,
+            offset: latest.offset,
+            segments: latest.segments,
+            format: latest.format,
+            chain: latest.chain,
+            digests: records
+                .iter()
+                .filter(|(id, _)| id != current)
+                .filter_map(|(_, r)| r.digest.clone())
+                .collect(),
+            spans: records
+                .iter()
+                .filter(|(id, _)| id != current)
+                .filter_map(|(_, r)| r.span)
+                .collect(),
+        })
+    }
+
+    // whether metadata was written but can't be read back, e.g. as it fails its checksum
+    pub fn damaged(&self) -> bool {
+        match self.kind {
+            ManifestKind::Single => {
+                self.location.join("meta").exists() && Meta::read(&self.location).is_err()
+            }
+            ManifestKind::PerSegment => Self::record_ids(&self.location)
+                .into_iter()
+                .any(|id| Self::read_record(&self.location, id).is_none()),
+        }
+    }
+
+    // Replace damaged metadata with `meta`, after a `load` of whatever can still be read
+    pub fn rebuild(&mut self, meta: &Meta) -> Result<(), WalError> {
+        if self.kind == ManifestKind::PerSegment {
+            for id in Self::record_ids(&self.location) {
+                if Self::read_record(&self.location, id).is_none() {
+                    std::fs::remove_file(Self::record_path(&self.location, id))
+                        .map_err(|e| WalError::io(e, "Failed to remove damaged manifest"))?;
+                }
+            }
+        }
+        self.store(meta)
+    }
+
+    pub fn store(&mut self, meta: &Meta) -> Result<(), WalError> {
+        if self.kind == ManifestKind::Single {
+            return meta.write(&self.location);
+        }
+        // records of sealed files only change when they are sealed or removed
+        let sealed = Self::sealed(meta);
+        let dropped = self
+            .recorded
+            .iter()
+            .map(|(id, ..)| *id)
+            .filter(|id| *id != meta.pointer && !sealed.iter().any(|(s, ..)| s == id))
+            .collect::<Vec<_>>();
+        for id in dropped {
+            let generation = Self::read_record(&self.location, id).map_or(0, |r| r.generation);
+            let record = Record {
+                generation,
+                offset: None,
+                segments: None,
+                format: None,
+                digest: None,
+                span: None,
+                chain: None,
+            };
+            self.write_record(id, &record)?;
+            self.recorded.retain(|(r, ..)| *r != id);
+        }
+        for entry in sealed {
+            if self.recorded.contains(&entry) {
+                continue;
+            }
+            let (id, digest, span) = entry.clone();
+            let generation = match Self::read_record(&self.location, id) {
+                Some(record) => record.generation,
+                None => 0,
+            };
+            let record = Record {
+                generation,
+                offset: None,
+                segments: None,
+                format: None,
+                digest,
+                span,
+                chain: None,
+            };
+            self.write_record(id, &record)?;
+            self.recorded.retain(|(r, ..)| *r != id);
+            self.recorded.push(entry);
+        }
+        // a new generation marks the file as current
+        if meta.pointer != self.current {
+            self.generation += 1;
+            self.current = meta.pointer;
+            self.recorded.retain(|(r, ..)| *r != meta.pointer);
+        }
+        let record = Record {
+            generation: self.generation,
+            offset: meta.offset,
+            segments: meta.segments,
+            format: meta.format,
+            digest: None,
+            span: None,
+            chain: meta.chain,
+        };
+        self.write_record(meta.pointer, &record)
+    }
+
+    // sealed files described in `meta`, by their digest or the time range of their logs
+    fn sealed(meta: &Meta) -> Vec<Sealed> {
+        let mut ids = meta
+            .digests
+            .iter()
+            .map(|d| d.id)
+            .chain(meta.spans.iter().map(|s| s.id))
+            .filter(|id| *id != meta.pointer)
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids.dedup();
+        ids.into_iter()
+            .map(|id| {
+                let digest = meta.digests.iter().find(|d| d.id == id).cloned();
+                let span = meta.spans.iter().find(|s| s.id == id).copied();
+                (id, digest, span)
+            })
+            .collect()
+    }
+
+    fn record_path(location: &Path, id: u8) -> PathBuf {
+        location.join(format!("manifest_{}", id))
+    }
+
+    // ids of the files with a per segment record, whatever the number of WAL files
+    fn record_ids(location: &Path) -> Vec<u8> {
+        let entries = match std::fs::read_dir(location) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+        let mut ids: Vec<u8> = entries
+            .flatten()
+            .filter_map(|e| {
+                let name = e.file_name().into_string().ok()?;
+                name.strip_prefix("manifest_")?.parse().ok()
+            })
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    fn read_records(location: &Path) -> Vec<(u8, Record)> {
+        Self::record_ids(location)
+            .into_iter()
+            .filter_map(|id| Self::read_record(location, id).map(|r| (id, r)))
+            .collect()
+    }
+
+    fn read_record(location: &Path, id: u8) -> Option<Record> {
+        let text = std::fs::read_to_string(Self::record_path(location, id)).ok()?;
+        Record::parse(id, &text)
+    }
+
+    // write the record to a temporary file first, so it replaces the old record atomically
+    fn write_record(&self, id: u8, record: &Record) -> Result<(), WalError> {
+        let path = Self::record_path(&self.location, id);
+        let tmp = path.with_extension("tmp");
+        let content = meta::seal(&record.to_string());
+        durable::write_atomic(&path, &tmp, content.as_bytes())
+            .map_err(|e| WalError::io(e, "Failed to write manifest"))
+    }
+}
+
+// Content of a `manifest_N` file
+//
+// `generation <number>` on the first line, followed by `offset <bytes>` for the current file
+// when positional writes are used, `segments <count>` and `format <version> <le|be>` for the
+// current file, `chain <link>` for
+// the current file once a file was sealed, and
+// `digest <crc32> <size> <entries> <ok|corrupt> [<sha256> <link>]` and `written <first> <last>`
+// once the file is sealed, and finally `crc <crc32>` guarding the lines before it
+#[derive(Debug, Clone, PartialEq)]
+struct Record {
+    generation: u64,
+    offset: Option<u64>,
+    segments: Option<u8>,
+    format: Option<FormatVersion>,
+    digest: Option<SegmentDigest>,
+    span: Option<SegmentSpan>,
+    chain: Option<[u8; 32]>,
+}
+
+impl Record {
+    fn parse(id: u8, text: &str) -> Option<Self> {
+        let mut lines = meta::unseal(text)?.lines();
+        let generation = match lines.next()?.split_whitespace().collect::<Vec<_>>()[..] {
+            ["generation", g] => g.parse().ok()?,
+            _ => return None,
+        };
+        let mut record = Self {
+            generation,
+            offset: None,
+            segments: None,
+            format: None,
+            digest: None,
+            span: None,
+            chain: None,
+        };
+        for line in lines {
+            match line.split_whitespace().collect::<Vec<_>>()[..] {
+                ["offset", offset] => record.offset = Some(offset.parse().ok()?),
+                ["segments", count] => record.segments = Some(count.parse().ok()?),
+                ["format", ref fields @ ..] => record.format = Some(FormatVersion::parse(fields)?),
+                ["digest", ref fields @ ..] => {
+                    record.digest = Some(SegmentDigest::parse(id, fields)?)
+                }
+                ["written", ref fields @ ..] => record.span = Some(SegmentSpan::parse(id, fields)?),
+                ["chain", link] => record.chain = Some(checksum::from_hex(link)?),
+                // ignore lines written by newer versions
+                _ => continue,
+            }
+        }
+        Some(record)
+    }
+}
+
+impl std::fmt::Display for Record {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "generation {}", self.generation)?;
+        if let Some(offset) = self.offset {
+            write!(f, "\noffset {}", offset)?;
+        }
+        if let Some(segments) = self.segments {
+            write!(f, "\nsegments {}", segments)?;
+        }
+        if let Some(format) = &self.format {
+            write!(f, "\nformat {}", format.fields())?;
+        }
+        if let Some(d) = &self.digest {
+            write!(f, "\ndigest {}", d.fields())?;
+        }
+        if let Some(s) = &self.span {
+            write!(f, "\nwritten {}", s.fields())?;
+        }
+        if let Some(chain) = &self.chain {
+            write!(f, "\nchain {}", checksum::to_hex(chain))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clear_storage(name: &str) -> PathBuf {
+        let path = PathBuf::from(format!("./tmp/{}/", name));
+        if path.exists() {
+            std::fs::remove_dir_all(&path).expect("Failed to delete old files");
+        }
+        std::fs::create_dir_all(&path).expect("Failed to create test directory");
+        path
+    }
+
+    #[test]
+    fn per_segment() {
+        let dir = clear_storage("manifest_per_segment");
+        let mut manifest = Manifest::open(&dir, ManifestKind::PerSegment);
+        assert!(manifest.load().is_err());
+        let digest = SegmentDigest {
+            id: 1,
+            crc32: 0xCBF4_3926,
+            size: 300,
+            entries: 25,
+            verified: true,
+            sha256: None,
+            chain: None,
+        };
+        manifest
+            .store(&Meta {
+                pointer: 1,
+                offset: None,
+                segments: None,
+                format: None,
+                digests: vec![],
+                spans: vec![],
+                chain: None,
+            })
+            .unwrap();
+        let meta = Meta {
+            pointer: 2,
+            offset: Some(40),
+            segments: None,
+            format: Some(FormatVersion::CURRENT),
+            digests: vec![digest],
+            spans: vec![SegmentSpan {
+                id: 1,
+                first: 10,
+                last: 20,
+            }],
+            chain: None,
+        };
+        manifest.store(&meta).unwrap();
+
+        // the layout is detected when opened again
+        let mut manifest = Manifest::open(&dir, ManifestKind::Single);
+        assert_eq!(manifest.kind, ManifestKind::PerSegment);
+        assert_eq!(manifest.load().unwrap(), meta);
+        assert!(!dir.join("meta").exists());
+
+        // going back to the first file starts a new generation
+        manifest
+            .store(&Meta {
+                pointer: 1,
+                offset: Some(0),
+                segments: None,
+                format: None,
+                digests: vec![],
+                spans: vec![],
+                chain: None,
+            })
+            .unwrap();
+        let meta = Manifest::open(&dir, ManifestKind::Single).load().unwrap();
+        assert_eq!(meta.pointer, 1);
+        assert!(meta.digests.is_empty());
+    }
+
+    #[test]
+    fn damaged() {
+        let dir = clear_storage("manifest_damaged");
+        let mut manifest = Manifest::open(&dir, ManifestKind::PerSegment);
+        let meta = |pointer| Meta {
+            pointer,
+            ..Default::default()
+        };
+        manifest.store(&meta(1)).unwrap();
+        manifest.store(&meta(2)).unwrap();
+        assert!(!manifest.damaged());
+        let path = Manifest::record_path(&dir, 2);
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, text.replace("generation 2", "generation 9")).unwrap();
+        assert!(manifest.damaged());
+
+        // the damaged record is left out and replaced
+        let mut manifest = Manifest::open(&dir, ManifestKind::PerSegment);
+        assert_eq!(manifest.load().unwrap().pointer, 1);
+        manifest.rebuild(&meta(2)).unwrap();
+        assert!(!manifest.damaged());
+        assert_eq!(
+            Manifest::open(&dir, ManifestKind::Single).load().unwrap(),
+            meta(2)
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Memory held by a WAL handle and its clones, as reported by [crate::Wal::memory_usage]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct MemoryUsage {
+    /// Serialized payload of logs waiting for the writer thread
+    pub buffer: usize,
+    /// Serialized payload of logs kept by [crate::WalBuilder::recent_cache]
+    pub recent_cache: usize,
+    /// Memory kept allocated between reads to hold raw file content
+    pub scratch: usize,
+    /// Budget set with [crate::WalBuilder::memory_budget], if any
+    pub budget: Option<usize>,
+}
+
+impl MemoryUsage {
+    /// Memory held across the buffer, the recent cache and the scratch buffer
+    pub fn total(&self) -> usize {
+        self.buffer + self.recent_cache + self.scratch
+    }
+}
+
+// Memory budget of a WAL, shared by the buffer, the recent cache and the scratch buffer
+// The scratch buffer is sized between reads, so the buffer and the recent cache get what it
+// leaves of the budget
+#[derive(Debug, Clone, Default)]
+pub(crate) struct MemoryBudget {
+    limit: Option<usize>,
+    // capacity of the scratch buffer kept between reads
+    scratch: Arc<AtomicUsize>,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            scratch: Arc::default(),
+        }
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn scratch(&self) -> usize {
+        self.scratch.load(Ordering::Relaxed)
+    }
+
+    pub fn set_scratch(&self, bytes: usize) {
+        self.scratch.store(bytes, Ordering::Relaxed);
+    }
+
+    // bytes left to the buffer and the recent cache, None without a budget
+    pub fn available(&self) -> Option<usize> {
+        self.limit.map(|limit| limit.saturating_sub(self.scratch()))
+    }
+}
@@ -0,0 +1,196 @@
+use crate::checksum;
+use crate::durable;
+use crate::format::FormatVersion;
+use crate::segment::{SegmentDigest, SegmentSpan};
+use crate::WalError;
+use std::path::Path;
+
+// Content of the `meta` file
+//
+// The first line holds the pointer of the current WAL file, optionally followed by the write
+// offset of that file. The second line, `segments <count>`, holds the number of WAL files, and
+// the third one, `format <version> <le|be>`, the format of the frames written. Every following
+// line describes a sealed WAL file:
+// `digest <id> <crc32> <size> <entries> <ok|corrupt> [<sha256> <link>]` or
+// `written <id> <first> <last>`, the time range in which its logs were written, followed by `chain <link>`, the link of the file sealed last, once a file was sealed. The last
+// line, `crc <crc32>`, guards everything before it against corruption.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct Meta {
+    pub pointer: u8,
+    pub offset: Option<u64>,
+    pub segments: Option<u8>,
+    pub format: Option<FormatVersion>,
+    pub digests: Vec<SegmentDigest>,
+    pub spans: Vec<SegmentSpan>,
+    pub chain: Option<[u8; 32]>,
+}
+
+impl Meta {
+    // read the meta file from the WAL directory
+    pub fn read(location: &Path) -> Result<Self, WalError> {
+        let path = location.join("meta");
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| WalError::io_at(e, "Failed to read pointer file", &path))?;
+        let corrupted = |reason: &str| WalError::Corruption {
+            path: path.clone(),
+            segment: None,
+            offset: None,
+            reason: reason.to_string(),
+        };
+        let text = unseal(&text).ok_or_else(|| corrupted("checksum doesn't match the content"))?;
+        Self::parse(text).ok_or_else(|| corrupted("content can't be parsed"))
+    }
+
+    // write the meta file to the WAL directory
+    // A temporary file replaces the meta file once written, so readers never see it half written
+    pub fn write(&self, location: &Path) -> Result<(), WalError> {
+        let tmp = location.join("meta.tmp");
+        let content = seal(&self.to_string());
+        durable::write_atomic(&location.join("meta"), &tmp, content.as_bytes())
+            .map_err(|e| WalError::io(e, "Failed to write to pointer file"))
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        let mut lines = text.lines();
+        let mut parts = lines.next()?.split_whitespace();
+        let pointer = parts.next()?.parse::<u8>().ok()?;
+        let offset = match parts.next() {
+            Some(o) => Some(o.parse::<u64>().ok()?),
+            None => None,
+        };
+        let mut segments = None;
+        let mut format = None;
+        let mut digests = Vec::new();
+        let mut spans = Vec::new();
+        let mut chain = None;
+        for line in lines {
+            let parts = line.split_whitespace().collect::<Vec<_>>();
+            match parts.as_slice() {
+                ["segments", count] => segments = Some(count.parse().ok()?),
+                ["format", fields @ ..] => format = Some(FormatVersion::parse(fields)?),
+                ["digest", id, fields @ ..] => {
+                    digests.push(SegmentDigest::parse(id.parse().ok()?, fields)?)
+                }
+                ["written", id, fields @ ..] => {
+                    spans.push(SegmentSpan::parse(id.parse().ok()?, fields)?)
+                }
+                ["chain", link] => chain = Some(checksum::from_hex(link)?),
+                // ignore lines written by newer versions
+                _ => continue,
+            }
+        }
+        Some(Self {
+            pointer,
+            offset,
+            segments,
+            format,
+            digests,
+            spans,
+            chain,
+        })
+    }
+}
+
+// `text` followed by a `crc <crc32>` line of its checksum
+pub(crate) fn seal(text: &str) -> String {
+    format!("{}\ncrc {:08x}", text, checksum::crc32(text.as_bytes()))
+}
+
+// `text` without its `crc` line, None if the checksum doesn't match
+// Text written before checksums were added has no such line and is taken as is
+pub(crate) fn unseal(text: &str) -> Option<&str> {
+    match text.rsplit_once("\ncrc ") {
+        Some((body, crc)) => {
+            let crc = u32::from_str_radix(crc.trim_end(), 16).ok()?;
+            (crc == checksum::crc32(body.as_bytes())).then_some(body)
+        }
+        None => Some(text),
+    }
+}
+
+impl std::fmt::Display for Meta {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // write current pointer, followed by the write offset when it is tracked
+        match self.offset {
+            Some(offset) => write!(f, "{} {}", self.pointer, offset)?,
+            None => write!(f, "{}", self.pointer)?,
+        }
+        if let Some(segments) = self.segments {
+            write!(f, "\nsegments {}", segments)?;
+        }
+        if let Some(format) = &self.format {
+            write!(f, "\nformat {}", format.fields())?;
+        }
+        for d in &self.digests {
+            write!(f, "\ndigest {} {}", d.id, d.fields())?;
+        }
+        for s in &self.spans {
+            write!(f, "\nwritten {} {}", s.id, s.fields())?;
+        }
+        if let Some(chain) = &self.chain {
+            write!(f, "\nchain {}", checksum::to_hex(chain))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        assert_eq!(
+            Meta::parse("3"),
+            Some(Meta {
+                pointer: 3,
+                ..Default::default()
+            })
+        );
+        let meta = Meta {
+            pointer: 2,
+            offset: Some(120),
+            segments: Some(7),
+            format: Some(FormatVersion::CURRENT),
+            digests: vec![SegmentDigest {
+                id: 1,
+                crc32: 0xCBF4_3926,
+                size: 300,
+                entries: 25,
+                verified: true,
+                sha256: None,
+                chain: None,
+            }],
+            spans: vec![SegmentSpan {
+                id: 1,
+                first: 1_700_000_000_000,
+                last: 1_700_000_360_000,
+            }],
+            chain: None,
+        };
+        assert_eq!(Meta::parse(&meta.to_string()), Some(meta.clone()));
+        assert_eq!(Meta::parse("x"), None);
+
+        // digests linked in the hash chain
+        let mut digest = meta.digests[0].clone();
+        digest.sha256 = Some([7; 32]);
+        digest.chain = Some([9; 32]);
+        let meta = Meta {
+            digests: vec![digest],
+            chain: Some([9; 32]),
+            ..meta
+        };
+        assert_eq!(Meta::parse(&meta.to_string()), Some(meta));
+    }
+
+    #[test]
+    fn checksum() {
+        let sealed = seal("2 120\nsegments 7");
+        assert_eq!(unseal(&sealed), Some("2 120\nsegments 7"));
+        // a flipped digit is caught
+        assert_eq!(unseal(&sealed.replacen('7', "8", 1)), None);
+        assert_eq!(unseal(&sealed.replace("crc ", "crc x")), None);
+        // written before checksums were added
+        assert_eq!(unseal("2 120"), Some("2 120"));
+    }
+}
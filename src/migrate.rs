@@ -0,0 +1,95 @@
+use serde::Deserialize;
+
+/// Type of logs whose layout changes over time, see [crate::WalBuilder::versioned]
+///
+/// Every log is written behind an envelope holding [Migrate::TAG] and [Migrate::VERSION]. Logs
+/// written with the current tag and version are deserialized as `Self`, while logs written with
+/// another version are handed to [Migrate::migrate], so they stay readable after the layout of
+/// `Self` changed. Bump [Migrate::VERSION] with every change of the layout.
+///
+/// # Example
+/// ```
+/// use serde::{Deserialize, Serialize};
+/// use walcraft::{Envelope, Migrate};
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct OrderV1 {
+///     id: u64,
+/// }
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct Order {
+///     id: u64,
+///     amount: u64,
+/// }
+///
+/// impl Migrate for Order {
+///     const VERSION: u8 = 2;
+///
+///     fn migrate(log: Envelope<'_>) -> Option<Self> {
+///         match log.version() {
+///             1 => log.decode::<OrderV1>().map(|old| Order { id: old.id, amount: 0 }),
+///             _ => None,
+///         }
+///     }
+/// }
+/// ```
+pub trait Migrate: Sized {
+    /// Tag of the type, logs written with another tag are skipped
+    ///
+    /// Tell apart WALs of different types stored with the same layout.
+    const TAG: u8 = 0;
+
+    /// Version of the current layout of the type
+    const VERSION: u8;
+
+    /// Convert a log written with another version of the type, usually an older one
+    ///
+    /// Returns None for logs that can't be converted, which are skipped by reads.
+    fn migrate(log: Envelope<'_>) -> Option<Self>;
+}
+
+/// Log written with another version of its type, as given to [Migrate::migrate]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Envelope<'a> {
+    tag: u8,
+    version: u8,
+    content: &'a [u8],
+}
+
+impl<'a> Envelope<'a> {
+    // envelope of a payload, None if it's too short to hold one
+    pub(crate) fn parse(payload: &'a [u8]) -> Option<Self> {
+        match payload {
+            [tag, version, content @ ..] => Some(Self {
+                tag: *tag,
+                version: *version,
+                content,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Tag of the type the log was written as
+    pub fn tag(&self) -> u8 {
+        self.tag
+    }
+
+    /// Version of the type the log was written as
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// The log serialized with bincode
+    pub fn content(&self) -> &'a [u8] {
+        self.content
+    }
+
+    /// Deserialize the log as `U`, the type it was written as
+    pub fn decode<U>(&self) -> Option<U>
+    where
+        U: for<'de> Deserialize<'de>,
+    {
+        bincode::deserialize(self.content).ok()
+    }
+}
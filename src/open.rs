@@ -0,0 +1,62 @@
+use crate::WalError;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Directories of the WALs open for writing in this process
+static OPEN: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+// Registration of a WAL directory as open for writing in this process, held for as long as a
+// handle is alive
+// Two writer threads of the same process would interleave their frames in the same files. Unlike
+// the lease, it's checked before any file is touched and catches directories reached through
+// different paths, such as symbolic links.
+#[derive(Debug)]
+pub(crate) struct OpenDirectory {
+    path: PathBuf,
+}
+
+impl OpenDirectory {
+    pub fn register(location: &Path) -> Result<Self, WalError> {
+        let path = location
+            .canonicalize()
+            .map_err(|e| WalError::io(e, "Failed to resolve WAL directory"))?;
+        if !Self::open().insert(path.clone()) {
+            return Err(WalError::AlreadyOpen(format!(
+                "WAL at {} is already open in this process, clone its handle instead",
+                path.display()
+            )));
+        }
+        Ok(Self { path })
+    }
+
+    fn open() -> std::sync::MutexGuard<'static, BTreeSet<PathBuf>> {
+        OPEN.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for OpenDirectory {
+    fn drop(&mut self) {
+        Self::open().remove(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register() {
+        let dir = Path::new("./tmp/open_directory/");
+        std::fs::create_dir_all(dir).unwrap();
+        let open = OpenDirectory::register(dir).unwrap();
+        // the same directory through another path
+        let other = Path::new("./tmp/../tmp/open_directory");
+        assert!(matches!(
+            OpenDirectory::register(other),
+            Err(WalError::AlreadyOpen(_))
+        ));
+        drop(open);
+        assert!(OpenDirectory::register(other).is_ok());
+    }
+}
@@ -0,0 +1,159 @@
+use std::time::{Duration, Instant};
+
+/// Limits on the resources used by the writer thread, for WALs sharing a host with
+/// latency-critical workloads
+///
+/// Used with [crate::WalBuilder::pacing], and changed at runtime with [crate::Wal::set_pacing].
+/// The writer pauses after writing a batch until it's back within the limits, so logs accumulate
+/// in the buffer meanwhile and are written in larger batches. Producers are only held back by
+/// [crate::WalBuilder::memory_budget]. Unlimited by default.
+///
+/// # Example
+/// ```
+/// use walcraft::Pacing;
+///
+/// // at most 20 MB/s of writes, and a quarter of a core
+/// let pacing = Pacing::new().bytes_per_sec(20 << 20).cpu_percent(25);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Pacing {
+    bytes_per_sec: Option<u64>,
+    cpu_percent: Option<u8>,
+}
+
+impl Pacing {
+    /// Create limits that allow everything
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write at most `bytes` per second on average, in bursts of up to a second worth of bytes
+    pub fn bytes_per_sec(mut self, bytes: u64) -> Self {
+        self.bytes_per_sec = Some(bytes.max(1));
+        self
+    }
+
+    /// Keep the writer busy at most `percent` of the time, between 1 and 100
+    pub fn cpu_percent(mut self, percent: u8) -> Self {
+        self.cpu_percent = Some(percent.clamp(1, 100));
+        self
+    }
+}
+
+// Pauses of the writer thread enforcing the limits of a pacing
+// Written bytes are taken from a token bucket refilled at the allowed rate and holding up to a
+// second worth of bytes. Time spent writing a batch is followed by enough idle time for the busy
+// share to stay within the CPU limit.
+#[derive(Debug)]
+pub(crate) struct Pacer {
+    pacing: Pacing,
+    // bytes that can be written right away, negative when overdrawn
+    tokens: f64,
+    // when tokens were last refilled
+    refilled: Instant,
+}
+
+impl Pacer {
+    pub fn new(pacing: Pacing, now: Instant) -> Self {
+        Self {
+            pacing,
+            tokens: pacing.bytes_per_sec.unwrap_or_default() as f64,
+            refilled: now,
+        }
+    }
+
+    // enforce `pacing` from now on, bytes already written count against the new bandwidth
+    pub fn set(&mut self, pacing: Pacing) {
+        let rate = pacing.bytes_per_sec.unwrap_or_default() as f64;
+        self.tokens = match self.pacing.bytes_per_sec {
+            Some(_) => self.tokens.min(rate),
+            None => rate,
+        };
+        self.pacing = pacing;
+    }
+
+    // time to pause after a batch of `bytes` took `busy` to write, at `now`
+    pub fn delay(&mut self, bytes: usize, busy: Duration, now: Instant) -> Option<Duration> {
+        let bandwidth = self.pacing.bytes_per_sec.and_then(|rate| {
+            let rate = rate as f64;
+            let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+            self.tokens = (self.tokens + elapsed * rate).min(rate) - bytes as f64;
+            self.refilled = now;
+            (self.tokens < 0.0).then(|| Duration::from_secs_f64(-self.tokens / rate))
+        });
+        let cpu = self
+            .pacing
+            .cpu_percent
+            .filter(|percent| *percent < 100)
+            .map(|percent| busy * (100 - percent as u32) / percent as u32);
+        match (bandwidth, cpu) {
+            (None, None) => None,
+            (a, b) => Some(a.unwrap_or_default().max(b.unwrap_or_default())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bandwidth() {
+        let start = Instant::now();
+        let mut pacer = Pacer::new(Pacing::new(), start);
+        assert_eq!(pacer.delay(1 << 30, Duration::from_secs(1), start), None);
+
+        let mut pacer = Pacer::new(Pacing::new().bytes_per_sec(1000), start);
+        // a second worth of bytes is written right away
+        assert_eq!(pacer.delay(1000, Duration::ZERO, start), None);
+        // the bucket is then overdrawn
+        assert_eq!(
+            pacer.delay(500, Duration::ZERO, start),
+            Some(Duration::from_millis(500))
+        );
+        // and refilled over time, up to a second worth of bytes
+        let later = start + Duration::from_secs(10);
+        assert_eq!(pacer.delay(1000, Duration::ZERO, later), None);
+        assert_eq!(
+            pacer.delay(100, Duration::ZERO, later),
+            Some(Duration::from_millis(100))
+        );
+    }
+
+    #[test]
+    fn cpu() {
+        let start = Instant::now();
+        let busy = Duration::from_millis(10);
+        let mut pacer = Pacer::new(Pacing::new().cpu_percent(25), start);
+        assert_eq!(pacer.delay(1, busy, start), Some(Duration::from_millis(30)));
+        let mut pacer = Pacer::new(Pacing::new().cpu_percent(100), start);
+        assert_eq!(pacer.delay(1, busy, start), None);
+
+        // the longest pause wins
+        let pacing = Pacing::new().cpu_percent(50).bytes_per_sec(100);
+        let mut pacer = Pacer::new(pacing, start);
+        assert_eq!(pacer.delay(300, busy, start), Some(Duration::from_secs(2)));
+    }
+
+    #[test]
+    fn set() {
+        let start = Instant::now();
+        let mut pacer = Pacer::new(Pacing::new(), start);
+        assert_eq!(pacer.delay(5000, Duration::ZERO, start), None);
+        // a new limit starts with a second worth of bytes
+        pacer.set(Pacing::new().bytes_per_sec(1000));
+        assert_eq!(pacer.delay(1000, Duration::ZERO, start), None);
+        assert_eq!(
+            pacer.delay(500, Duration::ZERO, start),
+            Some(Duration::from_millis(500))
+        );
+        // the overdrawn bytes are paid back at the new rate
+        pacer.set(Pacing::new().bytes_per_sec(2000));
+        assert_eq!(
+            pacer.delay(500, Duration::ZERO, start),
+            Some(Duration::from_millis(500))
+        );
+        pacer.set(Pacing::new());
+        assert_eq!(pacer.delay(5000, Duration::ZERO, start), None);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::entry::LogEntry;
+use crate::reader::WalReader;
+use crate::{Lsn, WalError};
+use serde::{Deserialize, Serialize};
+
+/// Position of the next log to read with [crate::Wal::read_page]
+///
+/// Points to the frame of the log in its WAL file, along with the sequence number of the log,
+/// so paging goes on from there without reading the files before it. When the file was reused
+/// or rewritten since, the log is found again by its sequence number, and paging fails with
+/// [WalError::CompactedAway] once the log was dropped from the WAL. Tokens can be stored with
+/// serde, or passed around as text in the `<segment>-<offset>-<lsn>` form of their
+/// [std::fmt::Display] and [std::str::FromStr] implementations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageToken {
+    segment: u8,
+    offset: u64,
+    lsn: Lsn,
+}
+
+impl PageToken {
+    /// Sequence number of the WAL file of the next log, i.e. `N` in `wal_N`
+    pub fn segment(&self) -> u8 {
+        self.segment
+    }
+
+    /// Offset of the frame of the next log in its WAL file
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Sequence number of the next log
+    pub fn lsn(&self) -> Lsn {
+        self.lsn
+    }
+}
+
+impl std::fmt::Display for PageToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}-{}", self.segment, self.offset, self.lsn)
+    }
+}
+
+impl std::str::FromStr for PageToken {
+    type Err = WalError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || WalError::Serialization(format!("Invalid page token {:?}", s));
+        let mut fields = s.splitn(3, '-');
+        let mut next = || fields.next().ok_or_else(invalid);
+        Ok(Self {
+            segment: next()?.parse().map_err(|_| invalid())?,
+            offset: next()?.parse().map_err(|_| invalid())?,
+            lsn: next()?.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Logs returned by [crate::Wal::read_page]
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct Page<T> {
+    /// Logs of the page, in the order they were written
+    pub entries: Vec<T>,
+    /// Position of the log following the page, to read the next page from
+    ///
+    /// Past the last log, the token points to where the next log will be written, so the same
+    /// token returns the logs written in the meantime.
+    pub next: PageToken,
+}
+
+// Logs of at most `limit` frames from the position `from`, or from the oldest log, along with
+// the position of the frame following them
+pub(crate) fn read_page(
+    reader: &WalReader,
+    from: Option<PageToken>,
+    limit: usize,
+) -> Result<(Vec<LogEntry>, PageToken), WalError> {
+    let mut files = reader.files()?;
+    files.reverse();
+    let mut next = from.unwrap_or(PageToken {
+        segment: files.first().copied().unwrap_or(1),
+        offset: 0,
+        lsn: 0,
+    });
+    let start = match from {
+        Some(token) => match resume(reader, &files, token)? {
+            Some(index) => index,
+            // the token's file was rewritten, logs are found again by their sequence number
+            None => {
+                next.offset = 0;
+                0
+            }
+        },
+        None => 0,
+    };
+    let mut entries = Vec::new();
+    for &id in &files[start.min(files.len())..] {
+        let content = match reader.raw(id)? {
+            Some(content) => content,
+            None => continue,
+        };
+        let frames = reader.frames(&content);
+        // end of the frames, where the next log of the file will be written
+        let end = frames
+            .last()
+            .map_or(reader.header_len(&content), |(span, _)| span.end);
+        for (span, entry) in frames {
+            if (id == next.segment && (span.start as u64) < next.offset) || entry.lsn() < next.lsn {
+                continue;
+            }
+            let position = PageToken {
+                segment: id,
+                offset: span.start as u64,
+                lsn: entry.lsn(),
+            };
+            if entries.len() == limit {
+                return Ok((entries, position));
+            }
+            next.lsn = entry.lsn() + 1;
+            entries.push(entry);
+        }
+        next.segment = id;
+        next.offset = end as u64;
+    }
+    Ok((entries, next))
+}
+
+// Index in `files` of the file to resume reading from at the frame of `token`, None if the file
+// no longer holds the frame, checking that the logs from the token on are still in the WAL
+fn resume(reader: &WalReader, files: &[u8], token: PageToken) -> Result<Option<usize>, WalError> {
+    let oldest = files
+        .iter()
+        .find_map(|id| reader.lsn_range(*id).transpose())
+        .transpose()?
+        .map(|lsns| *lsns.start());
+    if let Some(oldest) = oldest.filter(|oldest| token.lsn < *oldest) {
+        return Err(WalError::CompactedAway {
+            lsn: token.lsn,
+            oldest,
+        });
+    }
+    let index = match files.iter().position(|id| *id == token.segment) {
+        Some(index) => index,
+        None => return Ok(None),
+    };
+    let content = reader.raw(token.segment)?.unwrap_or_default();
+    let frames = reader.frames(&content);
+    let resumed = match frames
+        .iter()
+        .position(|(span, _)| span.start as u64 == token.offset)
+    {
+        // the token's log is still where it was written
+        Some(i) => frames[i].1.lsn() == token.lsn,
+        // the token points past the end of the file, which still ends with the logs before it
+        None => frames.last().map_or(
+            reader.header_len(&content) as u64 == token.offset,
+            |(span, entry)| span.end as u64 == token.offset && entry.lsn() < token.lsn,
+        ),
+    };
+    Ok(resumed.then_some(index))
+}
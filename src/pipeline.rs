@@ -0,0 +1,129 @@
+use crate::ack::Acks;
+use crate::failure::{Failures, WriteOperation};
+use crate::flush::SegmentFile;
+use crate::stats::{self, LatencyHistogram, Metrics};
+use crate::Lsn;
+use std::io;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// Second stage of the flush pipeline, syncing written batches on its own thread
+// The writer frames the next batch while the previous one is synced, and waits for that sync
+// before writing, so batches are written and made durable in order. Latency of sampled logs is
+// recorded once they are durable along with the time syncs take, logs written are acknowledged
+// to `acks`, and failed syncs are reported to `failures`.
+pub(crate) struct SyncStage<F> {
+    // batches to sync, with the time their sampled logs were added to the buffer and the last
+    // log written
+    jobs: Sender<(F, Vec<Instant>, Option<Lsn>)>,
+    // notified once a batch is synced
+    synced: Receiver<()>,
+    // a batch is being synced
+    pending: bool,
+}
+
+impl<F: SegmentFile + Send + 'static> SyncStage<F> {
+    // Start the thread of the stage, which stops once the stage is dropped
+    pub fn spawn(
+        latency: Arc<Mutex<LatencyHistogram>>,
+        failures: Failures,
+        metrics: Arc<Metrics>,
+        acks: Acks,
+    ) -> io::Result<Self> {
+        let (jobs, receiver) = mpsc::channel::<(F, Vec<Instant>, Option<Lsn>)>();
+        let (notify, synced) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("walcraft-sync".to_string())
+            .spawn(move || {
+                for (mut file, sampled, through) in receiver {
+                    match stats::timed_sync(&metrics, || file.sync()) {
+                        Ok(()) => {
+                            stats::record_latency(&latency, &sampled);
+                            acks.durable(through);
+                        }
+                        Err(e) => {
+                            failures.io(WriteOperation::Sync, &e, None);
+                            acks.lost(through);
+                        }
+                    }
+                    if notify.send(()).is_err() {
+                        return;
+                    }
+                }
+            })?;
+        Ok(Self {
+            jobs,
+            synced,
+            pending: false,
+        })
+    }
+
+    // Sync `file` in the background, after the batch being synced, up to the log `through`
+    pub fn submit(&mut self, file: F, sampled: Vec<Instant>, through: Option<Lsn>) {
+        self.wait();
+        self.pending = self.jobs.send((file, sampled, through)).is_ok();
+    }
+
+    // Wait for the batch being synced, if any
+    pub fn wait(&mut self) {
+        if self.pending {
+            let _ = self.synced.recv();
+            self.pending = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    // file counting its syncs, each taking a while
+    struct SlowFile(Arc<AtomicUsize>);
+
+    impl SegmentFile for SlowFile {
+        fn append(&mut self, _: &[u8]) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn write_at(&mut self, _: &[u8], _: u64) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn sync(&mut self) -> io::Result<()> {
+            sleep(Duration::from_millis(20));
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn overlap() {
+        let latency = Arc::new(Mutex::new(LatencyHistogram::default()));
+        let metrics = Arc::new(Metrics::default());
+        let mut stage = SyncStage::spawn(
+            latency.clone(),
+            Failures::default(),
+            metrics.clone(),
+            Acks::default(),
+        )
+        .unwrap();
+        let syncs = Arc::new(AtomicUsize::new(0));
+
+        // the caller goes on while the batch is synced
+        stage.submit(SlowFile(syncs.clone()), vec![Instant::now()], None);
+        assert_eq!(syncs.load(Ordering::SeqCst), 0);
+        // the next batch is synced after the previous one
+        stage.submit(SlowFile(syncs.clone()), vec![], None);
+        assert_eq!(syncs.load(Ordering::SeqCst), 1);
+        stage.wait();
+        assert_eq!(syncs.load(Ordering::SeqCst), 2);
+        stage.wait();
+        assert_eq!(latency.lock().unwrap().stats().samples, 1);
+        let flushes = metrics.snapshot(Default::default()).flushes;
+        assert_eq!(flushes, 2);
+    }
+}
@@ -0,0 +1,81 @@
+// Buffers recycled from the payloads of written logs to serialize new logs into
+//
+// Once the writer has encoded a batch into frames, it hands the payloads of its logs back to the
+// pool of the WAL. Handles serialize every log into a buffer of the pool, taking a few buffers at
+// a time into a stash of their thread, so writing a log neither allocates nor locks anything but
+// the buffer of the WAL once the pool is warm. Only small buffers are kept, as logs of any size
+// may land in them.
+
+use std::cell::RefCell;
+use std::sync::{Arc, Mutex};
+
+// buffers kept by the pool of a WAL
+const POOLED: usize = 4096;
+// buffers of a larger capacity are freed rather than kept
+const MAX_BUFFER_BYTES: usize = 1024;
+// buffers moved from the pool to the stash of a thread at once
+const REFILL: usize = 64;
+
+thread_local! {
+    // buffers taken from the pools of any WAL by this thread
+    static STASH: RefCell<Vec<Vec<u8>>> = const { RefCell::new(Vec::new()) };
+}
+
+#[derive(Clone, Default)]
+pub(crate) struct PayloadPool {
+    buffers: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl PayloadPool {
+    // empty buffer to serialize a log into
+    pub fn take(&self) -> Vec<u8> {
+        STASH.with_borrow_mut(|stash| {
+            if stash.is_empty() {
+                let mut buffers = self.buffers.lock().unwrap_or_else(|e| e.into_inner());
+                let from = buffers.len().saturating_sub(REFILL);
+                stash.extend(buffers.drain(from..));
+            }
+            stash.pop().unwrap_or_default()
+        })
+    }
+
+    // keep the payloads of written logs for the next logs
+    pub fn recycle(&self, payloads: Vec<Vec<u8>>) {
+        let mut buffers = self.buffers.lock().unwrap_or_else(|e| e.into_inner());
+        let room = POOLED.saturating_sub(buffers.len());
+        let kept = payloads
+            .into_iter()
+            .filter(|p| (1..=MAX_BUFFER_BYTES).contains(&p.capacity()))
+            .take(room)
+            .map(|mut p| {
+                p.clear();
+                p
+            });
+        buffers.extend(kept);
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.buffers.lock().unwrap().len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recycle() {
+        let pool = PayloadPool::default();
+        let fresh = pool.take();
+        assert_eq!(fresh.capacity(), 0);
+        // large and empty buffers aren't worth keeping
+        let payloads = vec![vec![1; 10], vec![2; 2 * MAX_BUFFER_BYTES], Vec::new()];
+        pool.recycle(payloads);
+        assert_eq!(pool.len(), 1);
+        let reused = pool.take();
+        assert!(reused.is_empty());
+        assert!(reused.capacity() >= 10);
+        assert_eq!(pool.len(), 0);
+    }
+}
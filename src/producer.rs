@@ -0,0 +1,36 @@
+use crate::{Lsn, ProducerId};
+use std::time::SystemTime;
+
+/// A log read along with where it came from
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct Attributed<T> {
+    /// Sequence number of the log
+    pub lsn: Lsn,
+    /// Id of the handle that wrote the log, see [crate::Wal::with_producer]
+    pub producer: Option<ProducerId>,
+    /// When the log was written, if it was stamped, see [crate::WalBuilder::timestamps]
+    pub written_at: Option<SystemTime>,
+    /// Reading of the wall clock when the log was written, which may be earlier than
+    /// `written_at`, see [crate::WalBuilder::strict_timestamps]
+    pub wall_clock: Option<SystemTime>,
+    /// Time of the log read from the monotonic clock to the nanosecond, strictly increasing in
+    /// the order of sequence numbers, see [crate::WalBuilder::strict_timestamps]
+    pub monotonic: Option<SystemTime>,
+    /// The log, or None if its payload couldn't be deserialized
+    pub log: Option<T>,
+}
+
+/// Volume of logs stored by a single producer
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ProducerStats {
+    /// Id of the producer, None for logs written by handles without an id
+    pub producer: Option<ProducerId>,
+    /// Number of logs
+    pub entries: u64,
+    /// Serialized payload of the logs in bytes
+    pub bytes: u64,
+    /// Number of logs whose payload couldn't be deserialized
+    pub malformed: u64,
+}
@@ -0,0 +1,336 @@
+use crate::{HandleStats, ReadMetrics, RotationHistory, WalMetrics, WalStats};
+use std::fmt::{Display, Write};
+use std::time::Duration;
+
+// Metrics in the Prometheus text exposition format
+// Every metric is written once with its help and type lines, followed by its samples.
+#[derive(Debug, Default)]
+pub(crate) struct Exposition {
+    text: String,
+}
+
+impl Exposition {
+    // help and type lines of the metric `name`, which the following samples belong to
+    fn metric(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP walcraft_{} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE walcraft_{} {}", name, kind);
+    }
+
+    // a sample of the metric `name`, with the given label pairs
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        let _ = write!(self.text, "walcraft_{}", name);
+        if !labels.is_empty() {
+            let labels = labels
+                .iter()
+                .map(|(key, value)| format!("{}=\"{}\"", key, escape(value)))
+                .collect::<Vec<_>>();
+            let _ = write!(self.text, "{{{}}}", labels.join(","));
+        }
+        let _ = writeln!(self.text, " {}", value);
+    }
+
+    // a metric with a single sample without labels
+    fn single(&mut self, name: &str, kind: &str, help: &str, value: impl Display) {
+        self.metric(name, kind, help);
+        self.sample(name, &[], value);
+    }
+
+    pub fn stats(&mut self, stats: &WalStats) {
+        let latency = &stats.write_latency;
+        self.metric(
+            "write_latency_seconds",
+            "gauge",
+            "Time from adding a log to the buffer until it's written, by quantile of sampled logs",
+        );
+        for (quantile, value) in [
+            ("0.5", latency.p50),
+            ("0.9", latency.p90),
+            ("0.99", latency.p99),
+            ("1", latency.max),
+        ] {
+            self.sample(
+                "write_latency_seconds",
+                &[("quantile", quantile)],
+                seconds(value),
+            );
+        }
+        self.single(
+            "write_latency_samples_total",
+            "counter",
+            "Logs sampled for the write latency",
+            latency.samples,
+        );
+        self.single(
+            "verified_files_total",
+            "counter",
+            "Sealed files checked against their digest",
+            stats.verified_files,
+        );
+        self.single(
+            "unverified_files",
+            "gauge",
+            "Sealed files still to be checked against their digest",
+            stats.unverified_files,
+        );
+    }
+
+    pub fn rotations(&mut self, history: &RotationHistory) {
+        if let Some(last) = history.rotations.last() {
+            self.single(
+                "last_rotation_entries",
+                "gauge",
+                "Logs held by the file left behind by the last rotation",
+                last.entries,
+            );
+            self.single(
+                "last_rotation_bytes",
+                "gauge",
+                "Bytes of the file left behind by the last rotation",
+                last.bytes,
+            );
+            self.single(
+                "last_rotation_fill_seconds",
+                "gauge",
+                "Time the writer thread spent on the file left behind by the last rotation",
+                seconds(last.since_previous),
+            );
+        }
+        if history.entries_per_segment.is_empty() {
+            return;
+        }
+        let name = "rotated_files_total";
+        self.metric(
+            name,
+            "counter",
+            "Files left behind by rotations, by the most logs held by the files counted",
+        );
+        for (bucket, files) in history.entries_per_segment.iter().enumerate() {
+            // bucket `i` counts the files of fewer than 2^i logs, the first one empty files
+            let max = (1u64 << bucket) - 1;
+            self.sample(name, &[("max_entries", &max.to_string())], files);
+        }
+    }
+
+    pub fn last_read(&mut self, read: &ReadMetrics) {
+        self.single(
+            "last_read_segments",
+            "gauge",
+            "Files opened by the last read",
+            read.segments,
+        );
+        self.single(
+            "last_read_bytes",
+            "gauge",
+            "Bytes read from the files by the last read",
+            read.bytes,
+        );
+        self.single(
+            "last_read_frames",
+            "gauge",
+            "Frames parsed by the last read",
+            read.frames,
+        );
+        self.single(
+            "last_read_corrupted",
+            "gauge",
+            "Frames skipped by the last read as they failed their checksum",
+            read.corrupted,
+        );
+        self.single(
+            "last_read_seconds",
+            "gauge",
+            "Time the last read spent reading files",
+            seconds(read.duration),
+        );
+    }
+
+    pub fn metrics(&mut self, metrics: &WalMetrics) {
+        self.single(
+            "entries_written_total",
+            "counter",
+            "Logs written to a file by the writer thread",
+            metrics.entries_written,
+        );
+        self.single(
+            "written_bytes_total",
+            "counter",
+            "Bytes of the frames written to a file by the writer thread",
+            metrics.bytes_written,
+        );
+        self.single(
+            "flushes_total",
+            "counter",
+            "Syncs of the current file to storage",
+            metrics.flushes,
+        );
+        self.single(
+            "rotations_total",
+            "counter",
+            "Moves of the writer thread to the next file",
+            metrics.rotations,
+        );
+        self.single(
+            "reads_total",
+            "counter",
+            "Reads of the WAL through any handle",
+            metrics.reads,
+        );
+        let depth = &metrics.buffer_depth;
+        self.single(
+            "buffer_logs",
+            "gauge",
+            "Logs waiting in the buffer for the writer thread",
+            depth.logs,
+        );
+        self.single(
+            "buffer_bytes",
+            "gauge",
+            "Serialized payload of the logs waiting in the buffer",
+            depth.bytes,
+        );
+        self.single(
+            "buffer_dropped_total",
+            "counter",
+            "Logs dropped from a full buffer",
+            depth.dropped,
+        );
+        if let Some(latency) = metrics.last_sync_latency {
+            self.single(
+                "last_sync_seconds",
+                "gauge",
+                "Time the last sync to storage took",
+                seconds(latency),
+            );
+        }
+    }
+
+    pub fn handles(&mut self, handles: &[HandleStats]) {
+        if handles.is_empty() {
+            return;
+        }
+        type Field = fn(&HandleStats) -> u64;
+        let counters: [(&str, &str, Field); 3] = [
+            (
+                "handle_entries_total",
+                "Logs accepted through the handles with the label",
+                |h| h.entries,
+            ),
+            (
+                "handle_bytes_total",
+                "Serialized payload of the logs accepted through the handles with the label",
+                |h| h.bytes,
+            ),
+            (
+                "handle_errors_total",
+                "Logs rejected through the handles with the label",
+                |h| h.errors,
+            ),
+        ];
+        for (name, help, field) in counters {
+            self.metric(name, "counter", help);
+            for handle in handles {
+                let label = handle.label.as_deref().unwrap_or_default();
+                self.sample(name, &[("label", label)], field(handle));
+            }
+        }
+    }
+
+    pub fn finish(self) -> String {
+        self.text
+    }
+}
+
+impl WalStats {
+    /// Statistics in the Prometheus text exposition format, ready to be served on a metrics
+    /// endpoint, see [crate::Wal::to_prometheus] for every metric of a WAL
+    pub fn to_prometheus(&self) -> String {
+        let mut exposition = Exposition::default();
+        exposition.stats(self);
+        exposition.finish()
+    }
+}
+
+impl WalMetrics {
+    /// Counters in the Prometheus text exposition format, ready to be served on a metrics
+    /// endpoint, see [crate::Wal::to_prometheus] for every metric of a WAL
+    pub fn to_prometheus(&self) -> String {
+        let mut exposition = Exposition::default();
+        exposition.metrics(self);
+        exposition.finish()
+    }
+}
+
+fn seconds(duration: Duration) -> f64 {
+    duration.as_secs_f64()
+}
+
+// a label value, with backslashes, quotes and line feeds escaped
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LatencyStats;
+
+    #[test]
+    fn exposition() {
+        let stats = WalStats {
+            write_latency: LatencyStats {
+                samples: 3,
+                p50: Duration::from_micros(500),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let text = stats.to_prometheus();
+        assert!(text.contains("# TYPE walcraft_write_latency_seconds gauge\n"));
+        assert!(text.contains("walcraft_write_latency_seconds{quantile=\"0.5\"} 0.0005\n"));
+        assert!(text.contains("walcraft_write_latency_samples_total 3\n"));
+
+        let metrics = WalMetrics {
+            entries_written: 7,
+            ..Default::default()
+        };
+        let text = metrics.to_prometheus();
+        assert!(text.contains("# TYPE walcraft_entries_written_total counter\n"));
+        assert!(text.contains("walcraft_entries_written_total 7\n"));
+        // no sync yet
+        assert!(!text.contains("last_sync_seconds"));
+
+        let history = RotationHistory {
+            entries_per_segment: vec![1, 0, 2],
+            ..Default::default()
+        };
+        let mut exposition = Exposition::default();
+        exposition.rotations(&history);
+        exposition.last_read(&ReadMetrics {
+            bytes: 64,
+            ..Default::default()
+        });
+        let text = exposition.finish();
+        assert!(text.contains("# TYPE walcraft_rotated_files_total counter\n"));
+        assert!(text.contains("walcraft_rotated_files_total{max_entries=\"0\"} 1\n"));
+        assert!(text.contains("walcraft_rotated_files_total{max_entries=\"3\"} 2\n"));
+        // no rotation recorded yet
+        assert!(!text.contains("last_rotation"));
+        assert!(text.contains("walcraft_last_read_bytes 64\n"));
+
+        let mut exposition = Exposition::default();
+        exposition.handles(&[HandleStats {
+            label: Some("a \"b\"".to_string()),
+            entries: 2,
+            ..Default::default()
+        }]);
+        let text = exposition.finish();
+        assert!(text.contains("walcraft_handle_entries_total{label=\"a \\\"b\\\"\"} 2\n"));
+        // every line is a comment or a sample
+        for line in text.lines() {
+            assert!(line.starts_with("# ") || line.starts_with("walcraft_"));
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use crate::WalError;
+use std::sync::Mutex;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// Rate of logs a WAL or a handle accepts, so a misbehaving component can't flood the WAL and
+/// evict the history of everyone else
+///
+/// Used with [crate::WalBuilder::rate_limit] for all handles of a WAL, and with
+/// [crate::Wal::with_rate_limit] for a single handle and its clones. Logs over the limit are
+/// rejected with [WalError::Throttled], unless the writer is allowed to wait for a moment.
+///
+/// # Example
+/// ```
+/// use std::time::Duration;
+/// use walcraft::RateLimit;
+///
+/// // 1000 logs per second on average, bursts of up to 5000 logs, waiting up to 10ms
+/// let limit = RateLimit::new(1000)
+///     .burst(5000)
+///     .wait_up_to(Duration::from_millis(10));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    per_sec: u64,
+    burst: u64,
+    wait: Duration,
+}
+
+impl RateLimit {
+    /// Accept `logs_per_sec` logs per second on average, in bursts of up to a second worth of logs
+    pub fn new(logs_per_sec: u64) -> Self {
+        let per_sec = logs_per_sec.max(1);
+        Self {
+            per_sec,
+            burst: per_sec,
+            wait: Duration::ZERO,
+        }
+    }
+
+    /// Accept bursts of up to `logs` logs written at once
+    pub fn burst(mut self, logs: u64) -> Self {
+        self.burst = logs.max(1);
+        self
+    }
+
+    /// Block writes over the limit for up to `wait` until they fit, instead of failing right away
+    pub fn wait_up_to(mut self, wait: Duration) -> Self {
+        self.wait = wait;
+        self
+    }
+}
+
+// Token bucket holding up to `burst` logs and refilled at the rate of the limit
+#[derive(Debug)]
+pub(crate) struct Limiter {
+    limit: RateLimit,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    // take `logs` tokens at `now`, or return the time until they are available
+    fn take(&mut self, limit: &RateLimit, logs: u64, now: Instant) -> Result<(), Duration> {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.per_sec as f64).min(limit.burst as f64);
+        self.refilled = now;
+        let missing = logs as f64 - self.tokens;
+        if missing <= 0.0 {
+            self.tokens -= logs as f64;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64(missing / limit.per_sec as f64))
+    }
+}
+
+impl Limiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            bucket: Mutex::new(Bucket {
+                tokens: limit.burst as f64,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    // take `logs` tokens, waiting for them as long as the limit allows
+    pub fn acquire(&self, logs: u64) -> Result<(), WalError> {
+        if logs > self.limit.burst {
+            return Err(self.throttled(logs));
+        }
+        let deadline = Instant::now() + self.limit.wait;
+        loop {
+            let now = Instant::now();
+            let wait = match self.bucket().take(&self.limit, logs, now) {
+                Ok(()) => return Ok(()),
+                Err(wait) => wait,
+            };
+            if now + wait > deadline {
+                return Err(self.throttled(logs));
+            }
+            sleep(wait);
+        }
+    }
+
+    // give back tokens of logs that weren't written after all
+    pub fn release(&self, logs: u64) {
+        let mut bucket = self.bucket();
+        bucket.tokens = (bucket.tokens + logs as f64).min(self.limit.burst as f64);
+    }
+
+    fn bucket(&self) -> std::sync::MutexGuard<'_, Bucket> {
+        self.bucket.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn throttled(&self, logs: u64) -> WalError {
+        WalError::Throttled(format!(
+            "{} logs exceed the limit of {} logs per second in bursts of {}",
+            logs, self.limit.per_sec, self.limit.burst
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket() {
+        let limit = RateLimit::new(10).burst(20);
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 20.0,
+            refilled: start,
+        };
+        assert!(bucket.take(&limit, 20, start).is_ok());
+        assert_eq!(
+            bucket.take(&limit, 5, start),
+            Err(Duration::from_millis(500))
+        );
+        // refilled at 10 logs per second, up to the burst
+        assert!(bucket
+            .take(&limit, 5, start + Duration::from_millis(500))
+            .is_ok());
+        let later = start + Duration::from_secs(60);
+        assert!(bucket.take(&limit, 20, later).is_ok());
+        assert!(bucket.take(&limit, 1, later).is_err());
+    }
+
+    #[test]
+    fn limiter() {
+        let limiter = Limiter::new(RateLimit::new(100).burst(2));
+        assert!(limiter.acquire(2).is_ok());
+        assert!(matches!(limiter.acquire(1), Err(WalError::Throttled(_))));
+        limiter.release(1);
+        assert!(limiter.acquire(1).is_ok());
+        // more logs than a burst never fit
+        assert!(limiter.acquire(3).is_err());
+
+        // waiting for 10ms is enough for one log at 100 logs per second
+        let limiter = Limiter::new(
+            RateLimit::new(100)
+                .burst(1)
+                .wait_up_to(Duration::from_millis(50)),
+        );
+        assert!(limiter.acquire(1).is_ok());
+        let start = Instant::now();
+        assert!(limiter.acquire(1).is_ok());
+        assert!(start.elapsed() >= Duration::from_millis(5));
+    }
+}
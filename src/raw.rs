@@ -0,0 +1,105 @@
+use crate::{Lsn, Wal, WalBuilder, WalError};
+
+/// WAL of byte payloads serialized by the application, e.g. protobuf or capnp messages
+///
+/// Payloads are stored as given and read back as stored, without going through serde, so there is
+/// no type to declare and no serialization round-trip. Every other operation, such as flushing,
+/// truncating or subscribing, is available on the underlying WAL returned by [RawWal::wal].
+///
+/// # Example
+/// ```
+/// use walcraft::RawWal;
+///
+/// # std::fs::create_dir_all("./tmp/raw_wal_doc/").unwrap();
+/// let wal = RawWal::new("./tmp/raw_wal_doc/", 500).unwrap();
+/// wal.write_bytes(&[0x08, 0x96, 0x01]).unwrap();
+/// wal.write_vec(vec![0x08, 0x2a]).unwrap();
+/// for payload in wal.read_bytes().unwrap() {
+///     // e.g. decode `payload` with prost
+/// }
+/// ```
+#[derive(Clone)]
+pub struct RawWal {
+    wal: Wal<Vec<u8>>,
+}
+
+impl RawWal {
+    /// Open the WAL at `location` with default options, see [Wal::new]
+    pub fn new(location: &str, capacity: usize) -> Result<Self, WalError> {
+        Self::with_builder(WalBuilder::new(location, capacity))
+    }
+
+    /// Open the WAL with the given configuration
+    ///
+    /// Fails with [WalError::Unsupported] in keyed mode, whose keys are computed from
+    /// deserialized logs.
+    pub fn with_builder(builder: WalBuilder<Vec<u8>>) -> Result<Self, WalError> {
+        if builder.key.is_some() {
+            return Err(WalError::Unsupported(
+                "Payloads of a raw WAL can't be keyed".to_string(),
+            ));
+        }
+        Ok(Self {
+            wal: builder.build()?,
+        })
+    }
+
+    /// Write a payload, returning its sequence number, see [Wal::try_write]
+    pub fn write_bytes(&self, payload: &[u8]) -> Result<Lsn, WalError> {
+        self.wal.try_write_raw(payload)
+    }
+
+    /// Write a payload owned by the caller like [RawWal::write_bytes], without copying it
+    pub fn write_vec(&self, payload: Vec<u8>) -> Result<Lsn, WalError> {
+        self.wal.write_raw_vec(payload)
+    }
+
+    /// Read the payloads of all written logs, from the oldest to the newest
+    pub fn read_bytes(&self) -> Result<Vec<Vec<u8>>, WalError> {
+        self.wal.read_raw()
+    }
+
+    /// Read the payloads of all written logs along with their sequence number
+    pub fn read_numbered(&self) -> Result<Vec<(Lsn, Vec<u8>)>, WalError> {
+        self.wal.read_raw_numbered()
+    }
+
+    /// Read the payloads of all written logs without copying them, see [Wal::read_raw_bytes]
+    #[cfg(feature = "bytes")]
+    pub fn read_shared(&self) -> Result<Vec<bytes::Bytes>, WalError> {
+        self.wal.read_raw_bytes()
+    }
+
+    /// The underlying WAL, for every other operation
+    ///
+    /// Its typed reads deserialize payloads as `Vec<u8>`, which payloads written here aren't,
+    /// so read them through this handle instead.
+    pub fn wal(&self) -> &Wal<Vec<u8>> {
+        &self.wal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn payloads() {
+        let dir = "./tmp/raw_wal_payloads/";
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+        let wal = RawWal::new(dir, 500).unwrap();
+        assert_eq!(wal.write_bytes(&[1, 2, 3]).unwrap(), 0);
+        assert_eq!(wal.write_vec(Vec::new()).unwrap(), 1);
+        wal.wal().flush().unwrap();
+        wal.write_vec(vec![4]).unwrap();
+        drop(wal);
+
+        let wal = RawWal::new(dir, 500).unwrap();
+        assert_eq!(
+            wal.read_bytes().unwrap(),
+            vec![vec![1, 2, 3], vec![], vec![4]]
+        );
+        assert_eq!(wal.read_numbered().unwrap()[2], (2, vec![4]));
+    }
+}
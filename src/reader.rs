@@ -1,69 +1,1392 @@
-use crate::{LogEntry, WalError};
-use std::fs::OpenOptions;
-use std::io::Read;
+use crate::blob::BlobStore;
+use crate::compression::{self, MAX_EXPANSION};
+use crate::encryption::{Cipher, FileCipher};
+use crate::format::{
+    self, ByteOrder, FormatVersion, FrameEncoding, Framing, BATCH_FLAG, BLOB_FLAG, CHECKSUM_BYTES,
+    COMPRESSED_FLAG, LENGTH_MASK, LENGTH_PREFIX_BYTES, LSN_BYTES, MONOTONIC_BYTES, PRODUCER_BYTES,
+    PRODUCER_FLAG, SEGMENT_HEADER_BYTES, TAG_FLAG, TIMESTAMP_BYTES, TIMESTAMP_FLAG,
+    WALL_CLOCK_BYTES, WALL_CLOCK_FLAG,
+};
+use crate::layout::Layout;
+use crate::manifest::{Manifest, ManifestKind};
+use crate::meta::Meta;
+use crate::recovery;
+use crate::segment::{SegmentDigest, SegmentSpan};
+use crate::segment_index::{IndexBlock, SegmentIndex};
+use crate::snapshot::{Snapshot, SnapshotVersion};
+use crate::stats::{ReadMetrics, ReadTally};
+use crate::storage::{Opened, SharedStorage};
+use crate::truncation::Truncation;
+use crate::{LogEntry, Lsn, ProducerId, WalError};
+use std::cell::RefCell;
+use std::io::{Read, Seek, SeekFrom};
+use std::ops::{Range, RangeInclusive};
 use std::path::PathBuf;
+use std::time::Duration;
+
+// bytes read from the start of a file to find its header, or the header of its first frame
+const FIRST_FRAME_PROBE: u64 = 64;
+
+// Logs of a single WAL file along with metadata of the file
+pub(crate) struct SegmentData {
+    pub id: u8,
+    pub path: PathBuf,
+    pub size: u64,
+    pub active: bool,
+    pub lsns: Option<RangeInclusive<Lsn>>,
+    pub digest: Option<SegmentDigest>,
+    pub entries: Vec<LogEntry>,
+}
 
 pub(crate) struct WalReader {
     location: PathBuf,
+    // size of payload when all logs are stored as fixed size records
+    record_size: Option<usize>,
+    // encoding of frames, unless they are fixed size records
+    framing: Framing,
+    // buffer holding raw file content, reused by every read
+    scratch: RefCell<Vec<u8>>,
+    // files being removed by a truncation, whose logs are no longer read
+    truncated: Vec<u8>,
+    // compressed frames declaring a larger expansion are rejected as corrupted
+    max_expansion: usize,
+    // number and names of the WAL files
+    layout: Layout,
+    // every native frame carries a checksum
+    checksums: bool,
+    // files, bytes and frames read so far
+    tally: ReadTally,
+    // files and pending logs to read instead of the files as they are now
+    snapshot: Option<Snapshot>,
+    // key opening the payloads of encrypted files
+    cipher: Option<Cipher>,
 }
 
 impl WalReader {
     pub fn new(location: PathBuf) -> Self {
-        Self { location }
+        Self {
+            record_size: None,
+            framing: Framing::Native,
+            scratch: RefCell::default(),
+            truncated: Truncation::pending(&location)
+                .map(|t| t.ids)
+                .unwrap_or_default(),
+            max_expansion: MAX_EXPANSION,
+            layout: Layout::default(),
+            checksums: false,
+            tally: ReadTally::default(),
+            snapshot: None,
+            cipher: None,
+            location,
+        }
+    }
+
+    // read logs stored as fixed size records
+    pub fn fixed(mut self, record_size: Option<usize>) -> Self {
+        self.record_size = record_size;
+        self
+    }
+
+    // read frames encoded with `framing`
+    pub fn framing(mut self, framing: Framing) -> Self {
+        self.framing = framing;
+        self
+    }
+
+    // read native frames carrying a checksum if `enabled` is set
+    pub fn checksums(mut self, enabled: bool) -> Self {
+        self.checksums = enabled;
+        self
     }
 
+    // whether the native frames of raw file content carry a checksum, from its header
+    // Files written before headers were introduced carry one as set with [WalReader::checksums]
+    pub fn checksummed(&self, buffer: &[u8]) -> bool {
+        match FormatVersion::from_header(buffer) {
+            Some(_) => format::checksummed(buffer),
+            None => self.checksums,
+        }
+    }
+
+    // how the native frames of raw file content are encoded, from its header
+    pub fn encoding(&self, buffer: &[u8]) -> FrameEncoding {
+        FrameEncoding {
+            checksummed: self.checksummed(buffer),
+            ..FrameEncoding::of(buffer)
+        }
+    }
+
+    // reject compressed frames expanding by more than `factor`
+    pub fn max_expansion(mut self, factor: usize) -> Self {
+        self.max_expansion = factor;
+        self
+    }
+
+    // read the WAL files described by `layout`
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    // open the payloads of encrypted files with `cipher`
+    pub fn cipher(mut self, cipher: Option<Cipher>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    // read files as they were when `snapshot` was taken
+    pub fn snapshot(mut self, snapshot: Snapshot) -> Self {
+        self.snapshot = Some(snapshot);
+        self
+    }
+
+    // What the snapshot read holds, along with the files left out by a truncation
+    // None without a snapshot, as the files may change while they're read
+    pub fn version(&self) -> Option<(SnapshotVersion, Vec<u8>)> {
+        let snapshot = self.snapshot.as_ref()?;
+        Some((snapshot.version(), self.truncated.clone()))
+    }
+
+    // logs accepted but not yet written to a file when the snapshot was taken
+    pub fn pending(&self) -> Vec<LogEntry> {
+        self.snapshot
+            .as_ref()
+            .map(|s| s.pending.clone())
+            .unwrap_or_default()
+    }
+
+    // time ranges of the logs of sealed files when the snapshot was taken, empty without one
+    pub fn spans(&self) -> Vec<SegmentSpan> {
+        self.snapshot
+            .as_ref()
+            .map(|s| s.spans.clone())
+            .unwrap_or_default()
+    }
+
+    // read file content into the given buffer instead of allocating a new one
+    pub fn scratch(mut self, buffer: Vec<u8>) -> Self {
+        self.scratch = RefCell::new(buffer);
+        self
+    }
+
+    // take back the buffer, so it can be reused by the next reader
+    pub fn into_scratch(self) -> Vec<u8> {
+        self.scratch.into_inner()
+    }
+
+    // files, bytes and frames read so far, taking `duration`
+    pub fn metrics(&self, duration: Duration) -> ReadMetrics {
+        self.tally.metrics(duration)
+    }
+
+    // read logs of all files in the order they were written
+    // Files are parsed one by one, so a torn frame at the end of a file doesn't affect the next
+    // file, and logs are sorted by sequence number as files reused after a restart don't follow
+    // the file order
     pub fn read(&self) -> Result<Vec<LogEntry>, WalError> {
-        let pointer = self.current_pointer()?;
-        let read_order = Self::read_order(pointer);
-        let mut buffer = vec![];
+        self.read_files(|_| true)
+    }
+
+    // read logs of the files for which `keep` returns true, in the order they were written
+    pub fn read_files(&self, keep: impl Fn(u8) -> bool) -> Result<Vec<LogEntry>, WalError> {
+        let mut read_order = self.files()?;
+        read_order.reverse();
+        read_order.retain(|i| keep(*i));
+        let mut data = Vec::new();
+        for i in read_order {
+            if let Some((_, entries)) = self.load(i)? {
+                data.extend(entries);
+            }
+        }
+        data.sort_by_key(|entry| entry.lsn());
+        Ok(data)
+    }
+
+    // read logs of the files for which `keep` returns true, in the order they were written, only
+    // reading the blocks accepted by `block` of the files with an index
+    // Logs of the blocks read are all returned, callers pick the logs they look for.
+    pub fn read_indexed(
+        &self,
+        keep: impl Fn(u8) -> bool,
+        block: impl Fn(&IndexBlock) -> bool,
+    ) -> Result<Vec<LogEntry>, WalError> {
+        let mut read_order = self.files()?;
+        read_order.reverse();
+        read_order.retain(|i| keep(*i));
+        let mut data = Vec::new();
         for i in read_order {
+            if let Some(entries) = self.read_blocks(i, &block)? {
+                data.extend(entries);
+            } else if let Some((_, entries)) = self.load(i)? {
+                data.extend(entries);
+            }
+        }
+        data.sort_by_key(|entry| entry.lsn());
+        Ok(data)
+    }
+
+    // whether files hold native frames, the only ones that are indexed
+    pub fn indexable(&self) -> bool {
+        self.record_size.is_none() && self.framing == Framing::Native
+    }
+
+    // Logs of the blocks of the file `id` accepted by `keep`, None when the file has no index
+    // matching its content, so it's read whole
+    // Only the header of the file and the blocks kept are read.
+    fn read_blocks(
+        &self,
+        id: u8,
+        keep: impl Fn(&IndexBlock) -> bool,
+    ) -> Result<Option<Vec<LogEntry>>, WalError> {
+        if !self.indexable() {
+            return Ok(None);
+        }
+        let index = match SegmentIndex::load(&self.location, id) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        let mut file = match self.open(id)? {
+            Some(file) if file.size() == index.size => file,
+            _ => return Ok(None),
+        };
+        if index.blocks.is_empty() {
+            return Ok(Some(Vec::new()));
+        }
+        let failed = |e| WalError::io_at(e, "Failed to read file", &self.segment_path(id));
+        let start = index.blocks.first().map_or(index.size, |b| b.offset);
+        let mut header = vec![0; start as usize];
+        file.read_exact(&mut header).map_err(failed)?;
+        self.tally.read(header.len());
+        let (encoding, sealing) = (self.encoding(&header), self.sealing(&header));
+        let mut data = Vec::new();
+        let mut buffer = self.scratch.borrow_mut();
+        for (i, block) in index.blocks.iter().enumerate() {
+            if !keep(block) {
+                continue;
+            }
+            let end = index.blocks.get(i + 1).map_or(index.size, |b| b.offset);
+            buffer.clear();
+            buffer.resize((end - block.offset) as usize, 0);
+            file.seek(SeekFrom::Start(block.offset))
+                .and_then(|_| file.read_exact(&mut buffer))
+                .map_err(failed)?;
+            let mut entries = self.parse_frames(&buffer, encoding, sealing);
+            self.tally.read(buffer.len());
+            self.tally.parsed(entries.len());
+            // the file was replaced by one with the same size since it was indexed
+            if entries
+                .first()
+                .is_some_and(|e| e.lsn() != *block.lsns.start())
             {
-                let file_name = format!("wal_{}", i);
-                let mut path = self.location.clone();
-                path.push(file_name);
-                if let Ok(mut file) = OpenOptions::new().read(true).open(path) {
-                    file.read_to_end(&mut buffer)
-                        .map_err(|_| WalError::File("Failed to read file".to_string()))?;
+                return Ok(None);
+            }
+            self.resolve_blobs(entries.iter_mut());
+            data.extend(entries);
+        }
+        Ok(Some(data))
+    }
+
+    // read logs whose tag is accepted by `keep`, in the order they were written
+    // Only a single file is held in memory at a time, along with the logs kept so far
+    pub fn read_tagged(
+        &self,
+        keep: impl Fn(Option<&[u8]>) -> bool,
+    ) -> Result<Vec<LogEntry>, WalError> {
+        let mut read_order = self.files()?;
+        read_order.reverse();
+        let mut data = Vec::new();
+        for i in read_order {
+            if let Some((_, entries)) = self.load(i)? {
+                data.extend(entries.into_iter().filter(|entry| keep(entry.tag())));
+            }
+        }
+        data.sort_by_key(|entry| entry.lsn());
+        Ok(data)
+    }
+
+    // open the file `id`, along with the bytes to read from it, None if the file doesn't exist
+    fn open(&self, id: u8) -> Result<Option<Opened>, WalError> {
+        let path = self.segment_path(id);
+        let opened = match &self.snapshot {
+            Some(snapshot) => snapshot.open(id)?,
+            None => match self.layout.storage.open(&path) {
+                Ok(opened) => opened,
+                Err(e) => match self.layout.storage.is_local() {
+                    true => None,
+                    false => return Err(WalError::io_at(e, "Failed to read file", &path)),
+                },
+            },
+        };
+        if opened.is_some() {
+            self.tally.opened(&path);
+        }
+        Ok(opened)
+    }
+
+    // read a whole file into the scratch buffer
+    // Returns false if the file doesn't exist
+    fn read_into(&self, id: u8, buffer: &mut Vec<u8>) -> Result<bool, WalError> {
+        let opened = match self.open(id)? {
+            Some(opened) => opened,
+            None => return Ok(false),
+        };
+        opened
+            .read_into(buffer)
+            .map_err(|e| WalError::io_at(e, "Failed to read file", &self.segment_path(id)))?;
+        self.tally.read(buffer.len());
+        Ok(true)
+    }
+
+    // content of a whole file, None if it doesn't exist
+    pub fn raw(&self, id: u8) -> Result<Option<Vec<u8>>, WalError> {
+        let mut content = Vec::new();
+        Ok(self.read_into(id, &mut content)?.then_some(content))
+    }
+
+    // read a whole file into the scratch buffer and split it into logs
+    // Returns the size of the file along with its logs, or None if the file doesn't exist
+    #[cfg(not(feature = "mmap"))]
+    fn load(&self, id: u8) -> Result<Option<(u64, Vec<LogEntry>)>, WalError> {
+        let mut buffer = self.scratch.borrow_mut();
+        if !self.read_into(id, &mut buffer)? {
+            return Ok(None);
+        }
+        Ok(Some((buffer.len() as u64, self.parse(&buffer))))
+    }
+
+    // map a whole file and split it into logs, parsing frames right from the mapping
+    // Returns the size of the file along with its logs, or None if the file doesn't exist
+    #[cfg(feature = "mmap")]
+    fn load(&self, id: u8) -> Result<Option<(u64, Vec<LogEntry>)>, WalError> {
+        let (file, size) = match self.open(id)? {
+            Some(Opened::File(file, size)) => (file, size),
+            // files of a custom storage are read whole
+            Some(Opened::Content(content)) => {
+                let content = content.get_ref();
+                self.tally.read(content.len());
+                return Ok(Some((content.len() as u64, self.parse(content))));
+            }
+            None => return Ok(None),
+        };
+        // empty files can't be mapped
+        if size == 0 {
+            return Ok(Some((0, Vec::new())));
+        }
+        // SAFETY: the mapping covers the bytes the file held when opened, and the writer never
+        // shrinks a file readers may hold: cleared, compacted and converted files are replaced by
+        // renaming new files over them, and truncated files are unlinked, so the mapped bytes
+        // stay valid. Only the tail of the current file is trimmed, when the writer opens it.
+        let map = unsafe { memmap2::MmapOptions::new().len(size as usize).map(&file) }
+            .map_err(|e| WalError::io(e, "Failed to map log file"))?;
+        self.tally.read(map.len());
+        Ok(Some((size, self.parse(&map))))
+    }
+
+    // read every WAL file separately, from the oldest file to the current one
+    pub fn read_segments(&self) -> Result<Vec<SegmentData>, WalError> {
+        let mut segments = Vec::new();
+        self.for_each_segment(|segment| {
+            segments.push(segment);
+            Ok(())
+        })?;
+        Ok(segments)
+    }
+
+    // pass every WAL file to `f`, from the oldest file to the current one
+    // Only a single file is held in memory at a time
+    pub fn for_each_segment<F>(&self, mut f: F) -> Result<(), WalError>
+    where
+        F: FnMut(SegmentData) -> Result<(), WalError>,
+    {
+        let pointer = self.current_pointer()?;
+        let digests = self.recorded_digests();
+        let mut read_order = self.files()?;
+        read_order.reverse();
+        for i in read_order {
+            match self.load(i)? {
+                Some((size, entries)) => f(self.segment(i, pointer, size, entries, &digests))?,
+                // file hasn't been created yet
+                None => continue,
+            }
+        }
+        Ok(())
+    }
+
+    // a single WAL file, None if it hasn't been created yet or is being removed by a truncation
+    pub fn read_segment(&self, id: u8) -> Result<Option<SegmentData>, WalError> {
+        if !self.files()?.contains(&id) {
+            return Ok(None);
+        }
+        let pointer = self.current_pointer()?;
+        let digests = self.recorded_digests();
+        Ok(self
+            .load(id)?
+            .map(|(size, entries)| self.segment(id, pointer, size, entries, &digests)))
+    }
+
+    fn segment(
+        &self,
+        id: u8,
+        pointer: u8,
+        size: u64,
+        entries: Vec<LogEntry>,
+        digests: &[SegmentDigest],
+    ) -> SegmentData {
+        let first = entries.iter().map(|e| e.lsn()).min();
+        let last = entries.iter().map(|e| e.lsn()).max();
+        SegmentData {
+            id,
+            path: self.segment_path(id),
+            size,
+            active: id == pointer,
+            lsns: first.zip(last).map(|(first, last)| first..=last),
+            digest: digests.iter().find(|d| d.id == id).cloned(),
+            entries,
+        }
+    }
+
+    // digests of sealed files, none when meta can't be read
+    fn recorded_digests(&self) -> Vec<SegmentDigest> {
+        self.meta().map(|meta| meta.digests).unwrap_or_default()
+    }
+
+    // sequence numbers of the first and the last log of a WAL file
+    pub fn lsn_range(&self, id: u8) -> Result<Option<RangeInclusive<Lsn>>, WalError> {
+        let entries = match self.load(id)? {
+            Some((_, entries)) => entries,
+            None => return Ok(None),
+        };
+        let first = entries.iter().map(|e| e.lsn()).min();
+        let last = entries.iter().map(|e| e.lsn()).max();
+        Ok(first.zip(last).map(|(first, last)| first..=last))
+    }
+
+    // highest sequence number stored in any WAL file
+    pub fn last_lsn(&self) -> Option<Lsn> {
+        self.read_segments()
+            .ok()?
+            .iter()
+            .flat_map(|segment| segment.entries.iter().map(|e| e.lsn()))
+            .max()
+    }
+
+    pub fn segment_path(&self, pointer: u8) -> PathBuf {
+        self.layout.path(&self.location, pointer)
+    }
+
+    // storage of the WAL files
+    pub fn storage(&self) -> &SharedStorage {
+        &self.layout.storage
+    }
+
+    // find the log with the given sequence number
+    // With fixed size records, the position of the log is computed from the first log of a file
+    pub fn get(&self, lsn: Lsn) -> Result<Option<LogEntry>, WalError> {
+        for i in self.files()? {
+            if let Some(record_size) = self.record_size {
+                let frame = format::fixed_frame_size(record_size);
+                let mut file = match self.open(i)? {
+                    Some(opened) => opened,
+                    None => continue,
+                };
+                let size = file.size();
+                let (start, order) = Self::records_start(&mut file, size);
+                let count = (size - start) / frame as u64;
+                if count == 0 {
+                    continue;
+                }
+                let first = match self
+                    .read_records(&mut file, record_size, (start, order), 0, 1)?
+                    .pop()
+                {
+                    Some(e) => e.lsn(),
+                    None => continue,
+                };
+                if lsn < first || lsn >= first + count {
+                    continue;
                 }
+                let found = self
+                    .read_records(&mut file, record_size, (start, order), lsn - first, 1)?
+                    .pop();
+                if let Some(entry) = found.filter(|e| e.lsn() == lsn) {
+                    return Ok(Some(entry));
+                }
+            }
+            // sequence numbers are not contiguous within the file, so look at every log of the
+            // block holding it, or of the whole file without an index
+            let indexed = self.read_blocks(i, |block| block.lsns.contains(&lsn))?;
+            let entries = match indexed {
+                Some(entries) => entries,
+                None => self.load(i)?.map(|(_, e)| e).unwrap_or_default(),
+            };
+            if let Some(entry) = entries.into_iter().find(|e| e.lsn() == lsn) {
+                return Ok(Some(entry));
             }
         }
+        Ok(None)
+    }
+
+    // size of the WAL file `id` in bytes, None if it doesn't exist
+    pub fn size(&self, id: u8) -> Result<Option<u64>, WalError> {
+        Ok(self.open(id)?.map(|opened| opened.size()))
+    }
+
+    // the oldest log stored in any WAL file
+    pub fn read_first(&self) -> Result<Option<LogEntry>, WalError> {
+        for i in self.files()?.into_iter().rev() {
+            let entries = self.load(i)?.map(|(_, e)| e).unwrap_or_default();
+            if let Some(first) = entries.into_iter().next() {
+                return Ok(Some(first));
+            }
+        }
+        Ok(None)
+    }
+
+    // WAL files holding logs from the sequence number `lsn` on, from the oldest to the newest
+    // A file is left out when another one starts after it yet no later than `lsn`, as every file
+    // holds a contiguous run of sequence numbers. Only the first frame of every file is read.
+    pub fn files_from(&self, lsn: Lsn) -> Result<Vec<u8>, WalError> {
+        let mut files = self.files()?;
+        files.reverse();
+        if lsn == 0 {
+            return Ok(files);
+        }
+        let mut firsts = Vec::with_capacity(files.len());
+        for &id in &files {
+            firsts.push(self.first_lsn(id)?);
+        }
+        let starts = firsts.iter().flatten().copied().collect::<Vec<_>>();
+        Ok(files
+            .into_iter()
+            .zip(firsts)
+            .filter(|(_, first)| match first {
+                Some(first) => !starts.iter().any(|s| first < s && *s <= lsn),
+                // files without a readable first log are read in full
+                None => true,
+            })
+            .map(|(id, _)| id)
+            .collect())
+    }
+
+    // sequence number of the first log of a WAL file, read from its first frame alone
+    // None when the file holds no log, or its frames don't carry a sequence number
+    fn first_lsn(&self, id: u8) -> Result<Option<Lsn>, WalError> {
+        let mut file = match self.open(id)? {
+            Some(opened) => opened,
+            None => return Ok(None),
+        };
+        let size = file.size();
+        if let Some(record_size) = self.record_size {
+            let (start, order) = Self::records_start(&mut file, size);
+            if size < start + format::fixed_frame_size(record_size) as u64 {
+                return Ok(None);
+            }
+            let records = self.read_records(&mut file, record_size, (start, order), 0, 1)?;
+            return Ok(records.first().map(|entry| entry.lsn()));
+        }
+        if self.framing != Framing::Native {
+            return Ok(None);
+        }
+        let failed = |e| WalError::io_at(e, "Failed to read file", &self.segment_path(id));
+        // the header, the nonce and the head offset of a reclaimed file
+        let mut header = vec![0; size.min(FIRST_FRAME_PROBE) as usize];
+        file.read_exact(&mut header).map_err(failed)?;
+        self.tally.read(header.len());
+        let start = match format::frames_offset(&header) {
+            Some(start) => start,
+            None => return Ok(None),
+        };
+        let encoding = self.encoding(&header);
+        let mut frame = vec![0; size.saturating_sub(start).min(FIRST_FRAME_PROBE) as usize];
+        file.seek(SeekFrom::Start(start))
+            .and_then(|_| file.read_exact(&mut frame))
+            .map_err(failed)?;
+        self.tally.read(frame.len());
+        Ok(FrameHeader::decode(&frame, encoding).map(|header| header.lsn))
+    }
+
+    // read the last `n` logs, from the oldest to the newest
+    // With fixed size records, only the needed records at the end of files are read
+    pub fn read_last(&self, n: usize) -> Result<Vec<LogEntry>, WalError> {
+        let mut data = Vec::new();
+        for i in self.files()? {
+            let remaining = n - data.len();
+            if remaining == 0 {
+                break;
+            }
+            let mut entries = match self.record_size {
+                Some(record_size) => {
+                    let mut file = match self.open(i)? {
+                        Some(opened) => opened,
+                        None => continue,
+                    };
+                    let size = file.size();
+                    let frame = format::fixed_frame_size(record_size) as u64;
+                    let (start, order) = Self::records_start(&mut file, size);
+                    let count = (size - start) / frame;
+                    let take = count.min(remaining as u64);
+                    self.read_records(&mut file, record_size, (start, order), count - take, take)?
+                }
+                None => {
+                    let tail = SegmentIndex::load(&self.location, i)
+                        .map(|index| index.tail(remaining as u64));
+                    let mut entries = match tail.map(|t| self.read_blocks(i, t)).transpose()? {
+                        Some(Some(entries)) => entries,
+                        _ => {
+                            let mut buffer = self.scratch.borrow_mut();
+                            if !self.read_into(i, &mut buffer)? {
+                                continue;
+                            }
+                            self.parse(&buffer)
+                        }
+                    };
+                    entries.split_off(entries.len().saturating_sub(remaining))
+                }
+            };
+            // older files go before the logs collected so far
+            entries.append(&mut data);
+            data = entries;
+        }
+        Ok(data)
+    }
+
+    // offset of the first fixed size record of a file of `size` bytes, after its header, and the
+    // byte order of the records
+    fn records_start(file: &mut Opened, size: u64) -> (u64, ByteOrder) {
+        let mut header = [0; SEGMENT_HEADER_BYTES];
+        match size >= SEGMENT_HEADER_BYTES as u64
+            && file
+                .seek(SeekFrom::Start(0))
+                .and_then(|_| file.read_exact(&mut header))
+                .is_ok()
+        {
+            true => (format::header_len(&header) as u64, ByteOrder::of(&header)),
+            false => (0, ByteOrder::NATIVE),
+        }
+    }
+
+    // read `count` fixed size records starting from record number `index`, the first record
+    // being at offset `start` and records having the byte order `order`
+    fn read_records(
+        &self,
+        file: &mut Opened,
+        record_size: usize,
+        (start, order): (u64, ByteOrder),
+        index: u64,
+        count: u64,
+    ) -> Result<Vec<LogEntry>, WalError> {
+        let frame = format::fixed_frame_size(record_size);
+        let mut buffer = self.scratch.borrow_mut();
+        buffer.clear();
+        buffer.resize(frame * count as usize, 0);
+        file.seek(SeekFrom::Start(start + index * frame as u64))
+            .and_then(|_| file.read_exact(&mut buffer))
+            .map_err(|e| WalError::io(e, "Failed to read file"))?;
+        self.tally.read(buffer.len());
+        let data = Self::parse_fixed(&buffer, record_size, order);
+        self.tally.parsed(data.len());
+        Ok(data)
+    }
+
+    // bytes of the header at the start of raw file content
+    // Length-delimited files have none, whatever their first bytes are
+    pub fn header_len(&self, buffer: &[u8]) -> usize {
+        match self.record_size.is_none() && self.framing == Framing::LengthDelimited {
+            true => 0,
+            false => format::header_len(buffer),
+        }
+    }
+
+    // split raw file content into logs
+    // A partial frame at the end of the content is ignored
+    fn parse(&self, buffer: &[u8]) -> Vec<LogEntry> {
+        let (encoding, sealing) = (self.encoding(buffer), self.sealing(buffer));
+        let buffer = &buffer[self.header_len(buffer)..];
+        let data = match (self.record_size, self.framing) {
+            (Some(record_size), _) => Self::parse_fixed(buffer, record_size, encoding.order),
+            (None, Framing::LengthDelimited) => Self::parse_delimited(buffer)
+                .into_iter()
+                .map(|(_, entry)| entry)
+                .collect(),
+            (None, Framing::Native) => {
+                let mut data = self.parse_frames(buffer, encoding, sealing);
+                self.resolve_blobs(data.iter_mut());
+                data
+            }
+        };
+        self.tally.parsed(data.len());
+        data
+    }
+
+    // replace references to blobs with the payloads they hold
+    // A log whose blob is missing keeps its reference, so it can't be deserialized
+    fn resolve_blobs<'e>(&self, entries: impl Iterator<Item = &'e mut LogEntry>) {
+        for entry in entries.filter(|e| e.blob()) {
+            if let Some(payload) = BlobStore::resolve(&self.location, entry.payload()) {
+                entry.resolve_blob(payload);
+            }
+        }
+    }
+
+    // references to blobs held by raw file content
+    pub fn blob_references(&self, buffer: &[u8]) -> Vec<Vec<u8>> {
+        if self.record_size.is_some() || self.framing != Framing::Native {
+            return Vec::new();
+        }
+        let (encoding, sealing) = (self.encoding(buffer), self.sealing(buffer));
+        self.parse_frames(&buffer[self.header_len(buffer)..], encoding, sealing)
+            .into_iter()
+            .filter(|e| e.blob())
+            .map(LogEntry::into_payload)
+            .collect()
+    }
+
+    // split raw file content into logs, along with the bytes taken by the frame of every log
+    pub fn frames(&self, buffer: &[u8]) -> Vec<(Range<usize>, LogEntry)> {
+        let start = self.header_len(buffer);
+        let encoding = self.encoding(buffer);
+        if let Some(record_size) = self.record_size {
+            let frame = format::fixed_frame_size(record_size);
+            return Self::parse_fixed(&buffer[start..], record_size, encoding.order)
+                .into_iter()
+                .enumerate()
+                .map(|(i, entry)| (start + i * frame..start + (i + 1) * frame, entry))
+                .collect();
+        }
+        if self.framing == Framing::LengthDelimited {
+            return Self::parse_delimited(buffer);
+        }
+        let sealing = self.sealing(buffer);
+        let mut data = Vec::new();
+        let mut offset = start;
+        while let Some(header) = FrameHeader::decode(&buffer[offset..], encoding) {
+            if header.end() > buffer.len() - offset {
+                break;
+            }
+            let span = offset..offset + header.end();
+            if !self.intact(&header, &buffer[span.clone()]) {
+                offset = span.end;
+                continue;
+            }
+            if let Some(entry) = header.entry(
+                &buffer[offset + header.header_bytes..span.end],
+                self.max_expansion,
+                sealing,
+            ) {
+                data.push((span.clone(), entry));
+            }
+            offset = span.end;
+        }
+        Self::drop_incomplete_batch(&mut data, |(_, entry)| entry.batched());
+        self.resolve_blobs(data.iter_mut().map(|(_, entry)| entry));
+        data
+    }
+
+    // count the complete frames in raw file content, along with the bytes they take
+    pub fn scan(&self, buffer: &[u8]) -> (usize, usize) {
+        let start = self.header_len(buffer);
+        if let Some(record_size) = self.record_size {
+            let frame = format::fixed_frame_size(record_size);
+            let count = (buffer.len() - start) / frame;
+            return (count, start + count * frame);
+        }
+        if self.framing == Framing::LengthDelimited {
+            let frames = Self::parse_delimited(buffer);
+            return (frames.len(), frames.last().map_or(0, |(span, _)| span.end));
+        }
+        let encoding = self.encoding(buffer);
+        let mut count = 0;
+        let mut offset = start;
+        while let Some(header) = FrameHeader::decode(&buffer[offset..], encoding) {
+            if header.end() > buffer.len() - offset {
+                break;
+            }
+            count += 1;
+            offset += header.end();
+        }
+        (count, offset)
+    }
+
+    // numbers of logs that can be read and of whole frames whose payload can't, in raw file
+    // content up to the end of the last frame that can be appended to
+    pub fn count_logs(&self, buffer: &[u8]) -> (usize, usize) {
+        if self.record_size.is_some() || self.framing == Framing::LengthDelimited {
+            return (self.scan(buffer).0, 0);
+        }
+        let valid = self.valid_len(buffer);
+        let (encoding, sealing) = (self.encoding(buffer), self.sealing(buffer));
+        let (mut readable, mut corrupt) = (0, 0);
+        let mut offset = self.header_len(buffer);
+        while let Some(header) = FrameHeader::decode(&buffer[offset..valid], encoding) {
+            let frame = &buffer[offset..offset + header.end()];
+            let payload = &frame[header.header_bytes..];
+            match header.intact(frame) {
+                true if header.entry(payload, self.max_expansion, sealing).is_some() => {
+                    readable += 1
+                }
+                _ => corrupt += 1,
+            }
+            offset += header.end();
+        }
+        (readable, corrupt)
+    }
+
+    // bytes of raw file content up to the end of the last frame that can be appended to
+    // A partial frame and an atomic batch missing its last log, both left by a crash while
+    // writing, are excluded
+    pub fn valid_len(&self, buffer: &[u8]) -> usize {
+        if self.record_size.is_some() || self.framing == Framing::LengthDelimited {
+            return self.scan(buffer).1;
+        }
+        let encoding = self.encoding(buffer);
+        let mut valid = self.header_len(buffer);
+        let mut offset = valid;
+        while let Some(header) = FrameHeader::decode(&buffer[offset..], encoding) {
+            if header.end() > buffer.len() - offset {
+                break;
+            }
+            offset += header.end();
+            if !header.batched {
+                valid = offset;
+            }
+        }
+        valid
+    }
+
+    // raw file content with the numbers of its frames rewritten in little endian, behind the
+    // header of the current format, None if they already are little endian
+    // Payloads are copied as is, and a partial frame at the end of the content is dropped
+    pub fn to_little_endian(&self, buffer: &[u8]) -> Option<Vec<u8>> {
+        let encoding = self.encoding(buffer);
+        let order = encoding.order;
+        if order == ByteOrder::Little
+            || (self.record_size.is_none() && self.framing == Framing::LengthDelimited)
+        {
+            return None;
+        }
+        let checksummed = encoding.checksummed && self.record_size.is_none();
+        let mut out = FormatVersion::CURRENT.file_header(None, checksummed);
+        let buffer = &buffer[self.header_len(buffer)..];
+        if let Some(record_size) = self.record_size {
+            for frame in buffer.chunks_exact(format::fixed_frame_size(record_size)) {
+                out.extend(order.u64(frame)?.to_le_bytes());
+                out.extend(&frame[LSN_BYTES..]);
+            }
+            return Some(out);
+        }
+        let mut offset = 0;
+        while let Some(header) = FrameHeader::decode(&buffer[offset..], encoding) {
+            if header.end() > buffer.len() - offset {
+                break;
+            }
+            let frame = &buffer[offset..offset + header.end()];
+            let start = out.len();
+            out.extend(order.u32(frame)?.to_le_bytes());
+            if checksummed {
+                out.extend([0; CHECKSUM_BYTES]);
+            }
+            out.extend(header.lsn.to_le_bytes());
+            if let Some(producer) = header.producer {
+                out.extend(producer.to_le_bytes());
+            }
+            if let Some(timestamp) = header.timestamp {
+                out.extend(timestamp.to_le_bytes());
+            }
+            if let Some(wall_clock) = header.wall_clock {
+                out.extend(wall_clock.to_le_bytes());
+                out.extend(header.monotonic.unwrap_or_default().to_le_bytes());
+            }
+            if let Some(tag) = &header.tag {
+                out.push(tag.len() as u8);
+                out.extend(tag);
+            }
+            out.extend(&frame[header.header_bytes..]);
+            // frames damaged on storage keep failing their checksum
+            if checksummed && header.intact(frame) {
+                format::stamp_checksum(&mut out[start..]);
+            }
+            offset += header.end();
+        }
+        Some(out)
+    }
+
+    // how the payloads of raw file content are stored, from its header
+    fn sealing(&self, buffer: &[u8]) -> Sealing<'_> {
+        match (format::segment_nonce(buffer), &self.cipher) {
+            (None, _) => Sealing::Clear,
+            (Some(nonce), Some(cipher)) => Sealing::Sealed(cipher.file(nonce)),
+            (Some(_), None) => Sealing::Unknown,
+        }
+    }
+
+    // cipher of the frames of raw file content, None unless it's encrypted with the key of the
+    // reader
+    pub fn file_cipher(&self, buffer: &[u8]) -> Option<FileCipher<'_>> {
+        match self.sealing(buffer) {
+            Sealing::Sealed(cipher) => Some(cipher),
+            _ => None,
+        }
+    }
+
+    // whether raw file content must be resealed before logs are appended to it: files written
+    // in the clear get encrypted, and frames a crash left past the valid content would otherwise
+    // see their nonce sealing the next logs
+    pub fn needs_reseal(&self, buffer: &[u8]) -> bool {
+        self.cipher.is_some()
+            && self.record_size.is_none()
+            && self.framing == Framing::Native
+            && !buffer.is_empty()
+            && (format::segment_nonce(buffer).is_none() || self.valid_len(buffer) < buffer.len())
+    }
+
+    // raw file content with every payload sealed under a new nonce, up to the end of the last
+    // frame that can be appended to, None without a key
+    // Payloads that can't be opened are copied as they are, and stay unreadable
+    pub fn reseal(&self, buffer: &[u8]) -> Option<Vec<u8>> {
+        let cipher = self.cipher.as_ref()?;
+        let previous = self.file_cipher(buffer);
+        let valid = self.valid_len(buffer);
+        let encoding = self.encoding(buffer);
+        let order = encoding.order;
+        let nonce = Cipher::nonce();
+        let file = cipher.file(nonce);
+        let mut out = FormatVersion::CURRENT.file_header(Some(&nonce), encoding.checksummed);
+        let mut offset = self.header_len(buffer);
+        while offset < valid {
+            let header = FrameHeader::decode(&buffer[offset..], encoding)?;
+            let frame = &buffer[offset..offset + header.end()];
+            let start = out.len();
+            let payload = &frame[header.header_bytes..];
+            let clear = match (previous, format::segment_nonce(buffer)) {
+                (Some(previous), _) => previous.open(header.lsn, payload),
+                (None, None) => Some(payload.to_vec()),
+                (None, Some(_)) => None,
+            };
+            let sealed = clear
+                .and_then(|clear| file.seal(header.lsn, &clear))
+                .unwrap_or_else(|| payload.to_vec());
+            let prefix = order.u32(frame)? & !LENGTH_MASK | sealed.len() as u32;
+            out.extend(prefix.to_le_bytes());
+            if let Some(checksum) = header.checksum {
+                out.extend(checksum.to_le_bytes());
+            }
+            out.extend(header.lsn.to_le_bytes());
+            if let Some(producer) = header.producer {
+                out.extend(producer.to_le_bytes());
+            }
+            if let Some(timestamp) = header.timestamp {
+                out.extend(timestamp.to_le_bytes());
+            }
+            if let Some(wall_clock) = header.wall_clock {
+                out.extend(wall_clock.to_le_bytes());
+                out.extend(header.monotonic.unwrap_or_default().to_le_bytes());
+            }
+            if let Some(tag) = &header.tag {
+                out.push(tag.len() as u8);
+                out.extend(tag);
+            }
+            out.extend(sealed);
+            // frames damaged on storage keep failing their checksum
+            if header.checksum.is_some() && header.intact(frame) {
+                format::stamp_checksum(&mut out[start..]);
+            }
+            offset += header.end();
+        }
+        Some(out)
+    }
+
+    // Fail unless the payloads of every WAL file can be opened, trying the key of the reader on
+    // the first frame of every encrypted file
+    // Only the start of every file is read
+    pub fn check_cipher(&self) -> Result<(), WalError> {
+        for id in self.layout.ids() {
+            let path = self.segment_path(id);
+            let content = match self.layout.storage.read_head(&path, 1 << 16) {
+                Ok(Some(content)) => content,
+                _ => continue,
+            };
+            if format::segment_nonce(&content).is_none() {
+                continue;
+            }
+            let cipher = match self.sealing(&content) {
+                Sealing::Sealed(cipher) => cipher,
+                _ => {
+                    return Err(WalError::Unsupported(format!(
+                        "{} is encrypted, set `WalBuilder::encryption_key` to open it",
+                        path.display()
+                    )))
+                }
+            };
+            let start = self.header_len(&content);
+            let header = match FrameHeader::decode(&content[start..], self.encoding(&content)) {
+                Some(header) if header.end() <= content.len() - start => header,
+                _ => continue,
+            };
+            let sealed = &content[start + header.header_bytes..start + header.end()];
+            if cipher.open(header.lsn, sealed).is_none() {
+                return Err(WalError::Unsupported(format!(
+                    "{} was encrypted with another key",
+                    path.display()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_fixed(buffer: &[u8], record_size: usize, order: ByteOrder) -> Vec<LogEntry> {
+        buffer
+            .chunks_exact(format::fixed_frame_size(record_size))
+            .map(|frame| {
+                let (lsn, payload) = frame.split_at(LSN_BYTES);
+                let lsn = order.u64(lsn).expect("LSN has a fixed size");
+                LogEntry::from_vec(Vec::from(payload), lsn)
+            })
+            .collect()
+    }
+
+    // split content of length-delimited frames, stopping at the first partial frame
+    fn parse_delimited(buffer: &[u8]) -> Vec<(Range<usize>, LogEntry)> {
         let mut data = Vec::new();
         let mut offset = 0;
-        while offset < buffer.len() {
-            let bytes = [
-                buffer[offset],
-                buffer[offset + 1],
-                buffer[offset + 2],
-                buffer[offset + 3],
-            ];
-            let size = u32::from_ne_bytes(bytes) as usize;
-            let end = offset + 4 + size;
-            let d = Vec::from(&buffer[offset + 4..end]);
-            data.push(LogEntry::from_vec(d));
+        while let Some((size, prefix)) = format::decode_varint(&buffer[offset..]) {
+            let start = offset + prefix;
+            let end = match usize::try_from(size)
+                .ok()
+                .and_then(|s| start.checked_add(s))
+            {
+                Some(end) if end <= buffer.len() => end,
+                _ => break,
+            };
+            let entry = LogEntry::from_vec(Vec::from(&buffer[start..end]), 0);
+            data.push((offset..end, entry));
             offset = end;
         }
+        data
+    }
+
+    // split native frames following the header of a file into logs
+    fn parse_frames(
+        &self,
+        buffer: &[u8],
+        encoding: FrameEncoding,
+        sealing: Sealing,
+    ) -> Vec<LogEntry> {
+        let mut data = Vec::new();
+        let mut offset = 0;
+        while let Some(header) = FrameHeader::decode(&buffer[offset..], encoding) {
+            if header.end() > buffer.len() - offset {
+                break;
+            }
+            let frame = &buffer[offset..offset + header.end()];
+            let payload = &frame[header.header_bytes..];
+            offset += header.end();
+            if !self.intact(&header, frame) {
+                continue;
+            }
+            if let Some(entry) = header.entry(payload, self.max_expansion, sealing) {
+                data.push(entry);
+            }
+        }
+        Self::drop_incomplete_batch(&mut data, LogEntry::batched);
+        data
+    }
+
+    // whether a whole frame matches its checksum, counting the frames that don't
+    // Frames failing their checksum are skipped rather than deserialized from damaged bytes
+    fn intact(&self, header: &FrameHeader, frame: &[u8]) -> bool {
+        let intact = header.intact(frame);
+        if !intact {
+            self.tally.corrupted();
+        }
+        intact
+    }
+
+    // drop the logs of an atomic batch cut short before its last log was written
+    // Batches are written to a single file in one go, so only the last one of a file can be cut
+    fn drop_incomplete_batch<E>(data: &mut Vec<E>, batched: impl Fn(&E) -> bool) {
+        while data.last().is_some_and(&batched) {
+            data.pop();
+        }
+    }
+
+    // digests of sealed files recorded in meta file, oldest first
+    pub fn segment_digests(&self) -> Result<Vec<SegmentDigest>, WalError> {
+        let meta = self.meta()?;
+        let order = self.read_order(meta.pointer);
+        let mut digests = meta.digests;
+        digests.sort_by_key(|d| std::cmp::Reverse(order.iter().position(|p| *p == d.id)));
+        Ok(digests)
+    }
+
+    // WAL files from the newest to the oldest, without the files of a truncation in progress
+    pub fn files(&self) -> Result<Vec<u8>, WalError> {
+        let mut order = self.read_order(self.current_pointer()?);
+        order.retain(|id| !self.truncated.contains(id));
+        Ok(order)
+    }
+
+    // file being written
+    pub fn current_pointer(&self) -> Result<u8, WalError> {
+        match &self.snapshot {
+            Some(snapshot) => Ok(snapshot.pointer),
+            None => self.meta_or_scan().map(|meta| meta.pointer),
+        }
+    }
+
+    pub fn meta(&self) -> Result<Meta, WalError> {
+        Manifest::open(&self.location, ManifestKind::default()).load()
+    }
+
+    // Metadata of the WAL, or what the WAL files tell when meta is missing or can't be read
+    // The current file is then the one holding the newest logs, or the one modified last when
+    // frames carry no sequence numbers, and nothing else is known of the files.
+    pub fn meta_or_scan(&self) -> Result<Meta, WalError> {
+        if let Ok(meta) = self.meta() {
+            return Ok(meta);
+        }
+        let newest = match self.record_size.is_none() && self.framing == Framing::LengthDelimited {
+            true => self
+                .layout
+                .ids()
+                .filter_map(|id| Some((id, self.storage().times(&self.segment_path(id)).1?)))
+                .max_by_key(|(_, modified)| *modified)
+                .map(|(id, _)| id),
+            false => recovery::newest(self, &self.layout)?,
+        };
+        Ok(Meta {
+            pointer: newest.unwrap_or(1),
+            ..Meta::default()
+        })
+    }
+
+    // WAL files from the newest to the oldest, when `pointer` is the current one
+    pub fn read_order(&self, pointer: u8) -> Vec<u8> {
+        self.layout.read_order(pointer)
+    }
+}
+
+// Payloads held as slices of a shared buffer per file, available with the `bytes` feature
+#[cfg(feature = "bytes")]
+impl WalReader {
+    // payloads of all files in the order they were written, along with their sequence numbers
+    // Every file is read into a single buffer, which the payloads stored as is point into.
+    // Compressed payloads and blobs are held in their own buffer.
+    pub fn read_bytes(&self) -> Result<Vec<(Lsn, bytes::Bytes)>, WalError> {
+        let mut data = Vec::new();
+        for i in self.files()?.into_iter().rev() {
+            let mut content = Vec::new();
+            if !self.read_into(i, &mut content)? {
+                continue;
+            }
+            let content = bytes::Bytes::from(content);
+            data.extend(self.slice_payloads(&content));
+        }
+        data.sort_by_key(|(lsn, _)| *lsn);
         Ok(data)
     }
 
-    fn current_pointer(&self) -> Result<u8, WalError> {
-        let mut path = self.location.clone();
-        path.push("meta");
-        let s = std::fs::read_to_string(path)
-            .map_err(|_| WalError::File("Failed to read pointer file".to_string()))?;
-        s.parse::<u8>()
-            .map_err(|_| WalError::File("Failed to read pointer file".to_string()))
+    // split the content of a file into payloads referencing it
+    fn slice_payloads(&self, content: &bytes::Bytes) -> Vec<(Lsn, bytes::Bytes)> {
+        let header = self.header_len(content);
+        let order = ByteOrder::of(content);
+        if let Some(record_size) = self.record_size {
+            let frame = format::fixed_frame_size(record_size);
+            return (0..(content.len() - header) / frame)
+                .map(|i| {
+                    let start = header + i * frame;
+                    let lsn = order.u64(&content[start..]).expect("LSN has a fixed size");
+                    let payload = content.slice(start + LSN_BYTES..start + frame);
+                    (lsn, payload)
+                })
+                .collect();
+        }
+        let mut data = Vec::new();
+        let mut offset = header;
+        // length-delimited frames carry no sequence numbers, so they keep the order of the file
+        if self.framing == Framing::LengthDelimited {
+            while let Some((size, prefix)) = format::decode_varint(&content[offset..]) {
+                let start = offset + prefix;
+                match usize::try_from(size)
+                    .ok()
+                    .and_then(|s| start.checked_add(s))
+                {
+                    Some(end) if end <= content.len() => {
+                        data.push((0, content.slice(start..end)));
+                        offset = end;
+                    }
+                    _ => break,
+                }
+            }
+            return data;
+        }
+        let sealing = self.sealing(content);
+        let mut frames = Vec::new();
+        let encoding = self.encoding(content);
+        while let Some(header) = FrameHeader::decode(&content[offset..], encoding) {
+            if header.end() > content.len() - offset {
+                break;
+            }
+            let frame = offset..offset + header.end();
+            let span = offset + header.header_bytes..frame.end;
+            offset = span.end;
+            if !self.intact(&header, &content[frame]) {
+                continue;
+            }
+            let stored = !header.compressed && !header.blob && matches!(sealing, Sealing::Clear);
+            let payload = match stored {
+                true => content.slice(span),
+                false => match header.entry(&content[span], self.max_expansion, sealing) {
+                    Some(mut entry) => {
+                        self.resolve_blobs(std::iter::once(&mut entry));
+                        bytes::Bytes::from(entry.into_payload())
+                    }
+                    None => continue,
+                },
+            };
+            frames.push((header.lsn, header.batched, payload));
+        }
+        Self::drop_incomplete_batch(&mut frames, |(_, batched, _)| *batched);
+        data.extend(frames.into_iter().map(|(lsn, _, payload)| (lsn, payload)));
+        data
+    }
+}
+
+// How the payloads of a file are stored
+#[derive(Clone, Copy)]
+enum Sealing<'a> {
+    Clear,
+    Sealed(FileCipher<'a>),
+    // encrypted with a key the reader doesn't have
+    Unknown,
+}
+
+// Fields stored ahead of the payload of a frame
+struct FrameHeader {
+    size: usize,
+    lsn: Lsn,
+    compressed: bool,
+    producer: Option<ProducerId>,
+    timestamp: Option<u64>,
+    wall_clock: Option<u64>,
+    monotonic: Option<u64>,
+    tag: Option<Vec<u8>>,
+    batched: bool,
+    blob: bool,
+    // checksum of the frame, when frames carry one
+    checksum: Option<u32>,
+    // length prefix, checksum, sequence number, producer id, timestamps and tag
+    header_bytes: usize,
+}
+
+impl FrameHeader {
+    // None if `buffer` is too short to hold the header
+    // The length prefix is followed by a checksum in files with checksums
+    fn decode(buffer: &[u8], encoding: FrameEncoding) -> Option<Self> {
+        let order = encoding.order;
+        let prefix = order.u32(buffer)?;
+        let (checksum, lsn_start) = match encoding.checksummed {
+            false => (None, LENGTH_PREFIX_BYTES),
+            true => {
+                let crc = order.u32(buffer.get(LENGTH_PREFIX_BYTES..)?)?;
+                (Some(crc), LENGTH_PREFIX_BYTES + CHECKSUM_BYTES)
+            }
+        };
+        let lsn_end = lsn_start + LSN_BYTES;
+        let lsn: Lsn = order.u64(buffer.get(lsn_start..)?)?;
+        let (producer, header_bytes) = match prefix & PRODUCER_FLAG {
+            0 => (None, lsn_end),
+            _ => {
+                let id: ProducerId = order.u16(buffer.get(lsn_end..)?)?;
+                (Some(id), lsn_end + PRODUCER_BYTES)
+            }
+        };
+        let (timestamp, header_bytes) = match prefix & TIMESTAMP_FLAG {
+            0 => (None, header_bytes),
+            _ => {
+                let at = order.u64(buffer.get(header_bytes..)?)?;
+                (Some(at), header_bytes + TIMESTAMP_BYTES)
+            }
+        };
+        let (wall_clock, monotonic, header_bytes) = match prefix & WALL_CLOCK_FLAG {
+            0 => (None, None, header_bytes),
+            _ => {
+                let at = order.u64(buffer.get(header_bytes..)?)?;
+                let header_bytes = header_bytes + WALL_CLOCK_BYTES;
+                let monotonic = order.u64(buffer.get(header_bytes..)?)?;
+                (Some(at), Some(monotonic), header_bytes + MONOTONIC_BYTES)
+            }
+        };
+        let (tag, header_bytes) = match prefix & TAG_FLAG {
+            0 => (None, header_bytes),
+            _ => {
+                let size = *buffer.get(header_bytes)? as usize;
+                let tag = buffer.get(header_bytes + 1..header_bytes + 1 + size)?;
+                (Some(tag.to_vec()), header_bytes + 1 + size)
+            }
+        };
+        Some(Self {
+            size: (prefix & LENGTH_MASK) as usize,
+            lsn,
+            compressed: prefix & COMPRESSED_FLAG != 0,
+            producer,
+            timestamp,
+            wall_clock,
+            monotonic,
+            tag,
+            batched: prefix & BATCH_FLAG != 0,
+            blob: prefix & BLOB_FLAG != 0,
+            checksum,
+            header_bytes,
+        })
     }
 
-    fn read_order(mut pointer: u8) -> Vec<u8> {
-        let mut d = Vec::with_capacity(5);
-        while d.len() < 5 {
-            d.push(pointer);
-            pointer -= 1;
-            if pointer < 1 {
-                pointer = 5;
+    // size of the whole frame
+    fn end(&self) -> usize {
+        self.header_bytes + self.size
+    }
+
+    // whether the whole `frame` matches its checksum, always true for frames without one
+    fn intact(&self, frame: &[u8]) -> bool {
+        match self.checksum {
+            Some(checksum) => format::frame_checksum(frame) == Some(checksum),
+            None => true,
+        }
+    }
+
+    // log stored in the frame, None if its payload can't be opened with the key of its file or
+    // decompressed within `max_expansion`
+    fn entry(&self, payload: &[u8], max_expansion: usize, sealing: Sealing) -> Option<LogEntry> {
+        let opened;
+        let payload = match sealing {
+            Sealing::Clear => payload,
+            Sealing::Sealed(cipher) => {
+                opened = cipher.open(self.lsn, payload)?;
+                &opened
             }
+            // kept sealed, so the log is counted but can't be deserialized
+            Sealing::Unknown => return Some(self.with_payload(Vec::from(payload))),
+        };
+        let payload = match self.compressed {
+            false => Vec::from(payload),
+            true => compression::decompress(payload, max_expansion)?,
+        };
+        Some(self.with_payload(payload))
+    }
+
+    // log with the fields of the frame and the given payload
+    fn with_payload(&self, payload: Vec<u8>) -> LogEntry {
+        let mut entry = LogEntry::from_vec(payload, self.lsn);
+        if self.blob {
+            let reference = entry.payload().to_vec();
+            entry.set_blob(reference);
         }
-        d
+        entry.set_producer(self.producer);
+        entry.set_timestamp(self.timestamp);
+        entry.set_wall_clock(self.wall_clock);
+        entry.set_monotonic(self.monotonic);
+        entry.set_tag(self.tag.clone());
+        entry.set_batched(self.batched);
+        entry
     }
 }
 
@@ -81,10 +1404,83 @@ mod tests {
 
     #[test]
     fn order() {
-        assert_eq!(WalReader::read_order(5), Vec::from([5, 4, 3, 2, 1]));
-        assert_eq!(WalReader::read_order(4), Vec::from([4, 3, 2, 1, 5]));
-        assert_eq!(WalReader::read_order(3), Vec::from([3, 2, 1, 5, 4]));
-        assert_eq!(WalReader::read_order(2), Vec::from([2, 1, 5, 4, 3]));
-        assert_eq!(WalReader::read_order(1), Vec::from([1, 5, 4, 3, 2]));
+        let reader = WalReader::new(PathBuf::from("./tmp/"));
+        assert_eq!(reader.read_order(5), Vec::from([5, 4, 3, 2, 1]));
+        assert_eq!(reader.read_order(4), Vec::from([4, 3, 2, 1, 5]));
+        assert_eq!(reader.read_order(3), Vec::from([3, 2, 1, 5, 4]));
+        assert_eq!(reader.read_order(2), Vec::from([2, 1, 5, 4, 3]));
+        assert_eq!(reader.read_order(1), Vec::from([1, 5, 4, 3, 2]));
+    }
+
+    #[test]
+    fn checksums() {
+        let reader = WalReader::new(PathBuf::from("./tmp/")).checksums(true);
+        let mut content = Vec::new();
+        for lsn in 1..=3 {
+            content.extend(LogEntry::from_vec(vec![lsn as u8; 10], lsn).into_frame(None, true));
+        }
+        let lsns = |content: &[u8]| {
+            let frames = reader.frames(content);
+            frames.iter().map(|(_, e)| e.lsn()).collect::<Vec<_>>()
+        };
+        assert_eq!(lsns(&content), vec![1, 2, 3]);
+
+        // a damaged payload fails the checksum of its frame only
+        let frame = content.len() / 3;
+        content[2 * frame - 1] ^= 1;
+        assert_eq!(lsns(&content), vec![1, 3]);
+        assert_eq!(reader.scan(&content), (3, content.len()));
+        assert_eq!(reader.parse(&content).len(), 2);
+        assert_eq!(reader.metrics(Duration::ZERO).corrupted, 2);
+    }
+
+    #[test]
+    fn frame_header() {
+        let mut entry = LogEntry::from_vec(vec![7; 10], 3);
+        entry.set_producer(Some(9));
+        entry.set_timestamp(Some(1_700_000_000_123));
+        entry.set_wall_clock(Some(1_700_000_000_100));
+        entry.set_monotonic(Some(1_700_000_000_123_456_789));
+        entry.set_tag(Some(b"account-7".to_vec()));
+        let frame = entry.into_frame(None, false);
+        let encoding = FrameEncoding {
+            order: ByteOrder::Little,
+            checksummed: false,
+        };
+        let header = FrameHeader::decode(&frame, encoding).unwrap();
+        assert_eq!((header.lsn, header.producer), (3, Some(9)));
+        assert_eq!(header.timestamp, Some(1_700_000_000_123));
+        assert_eq!(header.wall_clock, Some(1_700_000_000_100));
+        assert_eq!(header.monotonic, Some(1_700_000_000_123_456_789));
+        assert_eq!((header.size, header.end()), (10, frame.len()));
+        let entry = header
+            .entry(&frame[header.header_bytes..], MAX_EXPANSION, Sealing::Clear)
+            .unwrap();
+        assert_eq!(entry.timestamp(), Some(1_700_000_000_123));
+        assert_eq!(entry.tag(), Some(&b"account-7"[..]));
+    }
+
+    #[test]
+    fn valid_len() {
+        let reader = WalReader::new(PathBuf::from("./tmp/"));
+        let frame = |lsn, batched| {
+            let mut entry = LogEntry::from_vec(vec![7; 10], lsn);
+            entry.set_batched(batched);
+            entry.into_frame(None, false)
+        };
+        let mut content = frame(1, false);
+        let complete = content.len();
+        assert_eq!(reader.valid_len(&content), complete);
+        // frame cut short
+        let torn = frame(2, false);
+        content.extend(&torn[..torn.len() - 3]);
+        assert_eq!(reader.valid_len(&content), complete);
+        // batch missing its last log
+        content.truncate(complete);
+        content.extend(frame(2, true));
+        content.extend(frame(3, true));
+        assert_eq!(reader.valid_len(&content), complete);
+        content.extend(frame(4, false));
+        assert_eq!(reader.valid_len(&content), content.len());
     }
 }
@@ -0,0 +1,91 @@
+use crate::entry::LogEntry;
+use std::collections::VecDeque;
+
+// Most recent logs taken by the writer, kept in memory so [crate::Wal::read_last] can be answered
+// without reading files
+// The oldest logs are dropped once either the entry or the byte limit is exceeded
+pub(crate) struct Recent {
+    entries: VecDeque<LogEntry>,
+    // serialized payload held in `entries`
+    bytes: usize,
+    max_entries: usize,
+    max_bytes: usize,
+}
+
+impl Recent {
+    pub fn new(max_entries: usize, max_bytes: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            bytes: 0,
+            max_entries,
+            max_bytes,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // serialized payload of the logs held
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    // drop the oldest logs until at most `bytes` of payload are held
+    pub fn shrink_to(&mut self, bytes: usize) {
+        while self.bytes > bytes {
+            match self.entries.pop_front() {
+                Some(e) => self.bytes -= e.size(),
+                None => break,
+            }
+        }
+    }
+
+    // remember copies of logs handed to the writer
+    pub fn extend(&mut self, data: &[LogEntry]) {
+        for entry in data {
+            self.bytes += entry.size();
+            self.entries.push_back(entry.clone());
+        }
+        while self.entries.len() > self.max_entries || self.bytes > self.max_bytes {
+            match self.entries.pop_front() {
+                Some(e) => self.bytes -= e.size(),
+                None => break,
+            }
+        }
+    }
+
+    // last `n` logs, from the oldest to the newest
+    pub fn last(&self, n: usize) -> impl Iterator<Item = &LogEntry> {
+        self.entries
+            .iter()
+            .skip(self.entries.len().saturating_sub(n))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(lsn: u64) -> LogEntry {
+        LogEntry::from_vec(vec![0; 10], lsn)
+    }
+
+    #[test]
+    fn limits() {
+        let mut recent = Recent::new(3, 100);
+        recent.extend(&[entry(0), entry(1), entry(2), entry(3)]);
+        let lsns = recent.last(5).map(|e| e.lsn()).collect::<Vec<_>>();
+        assert_eq!(lsns, vec![1, 2, 3]);
+        let lsns = recent.last(2).map(|e| e.lsn()).collect::<Vec<_>>();
+        assert_eq!(lsns, vec![2, 3]);
+
+        // byte limit
+        let mut recent = Recent::new(10, 25);
+        recent.extend(&[entry(0), entry(1), entry(2)]);
+        assert_eq!(recent.len(), 2);
+        recent.shrink_to(15);
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent.bytes(), 10);
+    }
+}
@@ -0,0 +1,175 @@
+use crate::format::{self, FormatVersion, Framing, SEGMENT_HEADER_BYTES};
+use crate::layout::Layout;
+use crate::manifest::{Manifest, ManifestKind};
+use crate::meta::Meta;
+use crate::reader::WalReader;
+use crate::{Lsn, WalError};
+use std::path::Path;
+
+/// Decision taken when the WAL was opened with a meta file that disagreed with the WAL files
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Recovery {
+    /// File pointer found in the meta file, if it could be read
+    pub recorded: Option<u8>,
+    /// File pointer the meta file was corrected to
+    pub pointer: u8,
+    /// Why the recorded pointer was rejected
+    pub reason: String,
+    /// Whether the meta file was damaged, e.g. failing its checksum, and rebuilt from the WAL files
+    ///
+    /// Digests of sealed files that were lost along with it are no longer checked.
+    pub rebuilt: bool,
+}
+
+// Cross-check the current file recorded in meta against the logs stored in the WAL files
+// The current file is the one holding the newest logs, or the file right after it when that one
+// was just started and is still empty. A disagreeing meta file is corrected when `repair` is set,
+// and a damaged one is rebuilt.
+pub(crate) fn reconcile(
+    location: &Path,
+    record_size: Option<usize>,
+    framing: Framing,
+    checksums: bool,
+    kind: ManifestKind,
+    layout: &Layout,
+    repair: bool,
+) -> Result<Option<Recovery>, WalError> {
+    let reader = WalReader::new(location.to_path_buf())
+        .fixed(record_size)
+        .checksums(checksums)
+        .layout(layout.clone());
+    // length-delimited frames carry no sequence numbers to compare files with
+    let newest = match record_size.is_none() && framing == Framing::LengthDelimited {
+        true => None,
+        false => newest(&reader, layout)?,
+    };
+    let mut manifest = Manifest::open(location, kind);
+    if manifest.damaged() {
+        return rebuild(&mut manifest, location, newest, layout, repair).map(Some);
+    }
+    // no logs to check against
+    let newest = match newest {
+        Some(id) => id,
+        None => return Ok(None),
+    };
+    let recorded = manifest.load().ok();
+    let reason = match &recorded {
+        None => "meta file is missing or unreadable".to_string(),
+        Some(meta) if meta.pointer == newest => return Ok(None),
+        Some(meta) => {
+            let started = layout.next(newest) == meta.pointer
+                && reader.lsn_range(meta.pointer)?.is_none()
+                && !format::holds_frames(&layout.storage, &layout.path(location, meta.pointer));
+            if started {
+                return Ok(None);
+            }
+            format!(
+                "{}{} holds newer logs than {}{}",
+                layout.prefix, newest, layout.prefix, meta.pointer
+            )
+        }
+    };
+
+    // digests of other files are still valid, the write offset is not
+    let digests = recorded
+        .as_ref()
+        .map(|m| m.digests.clone())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|d| d.id != newest)
+        .collect();
+    let spans = recorded
+        .as_ref()
+        .map(|m| m.spans.clone())
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|s| s.id != newest)
+        .collect();
+    if repair {
+        manifest.store(&Meta {
+            pointer: newest,
+            offset: None,
+            segments: recorded.as_ref().and_then(|m| m.segments),
+            format: recorded.as_ref().and_then(|m| m.format),
+            digests,
+            spans,
+            chain: recorded.as_ref().and_then(|m| m.chain),
+        })?;
+    }
+    Ok(Some(Recovery {
+        recorded: recorded.map(|m| m.pointer),
+        pointer: newest,
+        reason,
+        rebuilt: false,
+    }))
+}
+
+// file holding the logs with the highest sequence number, None without logs
+pub(crate) fn newest(reader: &WalReader, layout: &Layout) -> Result<Option<u8>, WalError> {
+    let mut newest: Option<(u8, Lsn)> = None;
+    for id in layout.ids() {
+        if let Some(range) = reader.lsn_range(id)? {
+            if newest.is_none_or(|(_, lsn)| *range.end() > lsn) {
+                newest = Some((id, *range.end()));
+            }
+        }
+    }
+    Ok(newest.map(|(id, _)| id))
+}
+
+// Rebuild damaged metadata from what can still be read of it and from the WAL files
+// The current file is the one holding the newest logs, and the format is read from its header.
+// A damaged meta file never fails the open, as the WAL files hold everything needed to go on.
+fn rebuild(
+    manifest: &mut Manifest,
+    location: &Path,
+    newest: Option<u8>,
+    layout: &Layout,
+    repair: bool,
+) -> Result<Recovery, WalError> {
+    let recorded = manifest.load().ok();
+    let pointer = newest
+        .or(recorded.as_ref().map(|m| m.pointer))
+        .filter(|id| layout.ids().contains(id))
+        .unwrap_or(1);
+    let path = layout.path(location, pointer);
+    let format = layout
+        .storage
+        .read_head(&path, SEGMENT_HEADER_BYTES as u64)
+        .ok()
+        .flatten()
+        .and_then(|header| FormatVersion::from_header(&header))
+        .or(recorded.as_ref().and_then(|m| m.format));
+    if repair {
+        let digests = recorded
+            .as_ref()
+            .map(|m| m.digests.clone())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|d| d.id != pointer)
+            .collect();
+        let spans = recorded
+            .as_ref()
+            .map(|m| m.spans.clone())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|s| s.id != pointer)
+            .collect();
+        manifest.rebuild(&Meta {
+            pointer,
+            offset: None,
+            segments: Some(layout.segments),
+            format,
+            digests,
+            spans,
+            chain: recorded.as_ref().and_then(|m| m.chain),
+        })?;
+    }
+    Ok(Recovery {
+        recorded: recorded.map(|m| m.pointer),
+        pointer,
+        reason: "meta file is damaged, rebuilt from the WAL files".to_string(),
+        rebuilt: true,
+    })
+}
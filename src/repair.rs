@@ -0,0 +1,77 @@
+use crate::checksum;
+use crate::reader::WalReader;
+use crate::segment::SegmentDigest;
+
+/// Findings of [crate::Wal::verify] for a single WAL file
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SegmentCheck {
+    /// Sequence number of the WAL file, i.e. `N` in `wal_N`
+    pub id: u8,
+    /// Size of the WAL file in bytes
+    pub size: u64,
+    /// Number of logs that can be read
+    pub entries: u64,
+    /// Number of whole frames whose payload can't be read, e.g. failing their checksum or to
+    /// decompress
+    pub corrupt_frames: u64,
+    /// Bytes past the last frame logs can be appended to, such as a frame torn by a crash
+    pub wasted_bytes: u64,
+    /// Whether the file no longer matches the digest recorded when it was sealed, see
+    /// [crate::WalBuilder::verify_on_rotation]
+    pub digest_mismatch: bool,
+}
+
+/// Report of [crate::Wal::verify] and [crate::Wal::repair] on the files of a WAL
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct VerifyReport {
+    /// Findings of every WAL file, from the oldest to the newest
+    pub segments: Vec<SegmentCheck>,
+    /// Logs that can be read in all files
+    pub entries: u64,
+    /// Whole frames whose payload can't be read in all files
+    pub corrupt_frames: u64,
+    /// Bytes past the last valid frame in all files
+    pub wasted_bytes: u64,
+    /// Whether the meta file could be read
+    pub meta_readable: bool,
+    /// Files cut to their last valid frame by [crate::Wal::repair]
+    pub repaired: Vec<u8>,
+}
+
+impl VerifyReport {
+    /// Whether every file and the meta file are intact
+    pub fn is_healthy(&self) -> bool {
+        self.meta_readable
+            && self.corrupt_frames == 0
+            && self.wasted_bytes == 0
+            && self.segments.iter().all(|s| !s.digest_mismatch)
+    }
+
+    // add the findings of a file
+    pub(crate) fn push(&mut self, check: SegmentCheck) {
+        self.entries += check.entries;
+        self.corrupt_frames += check.corrupt_frames;
+        self.wasted_bytes += check.wasted_bytes;
+        self.segments.push(check);
+    }
+}
+
+// Check the raw content of the file `id` against the digest recorded when it was sealed, if any
+pub(crate) fn check_segment(
+    reader: &WalReader,
+    id: u8,
+    content: &[u8],
+    digest: Option<&SegmentDigest>,
+) -> SegmentCheck {
+    let (entries, corrupt) = reader.count_logs(content);
+    SegmentCheck {
+        id,
+        size: content.len() as u64,
+        entries: entries as u64,
+        corrupt_frames: corrupt as u64,
+        wasted_bytes: (content.len() - reader.valid_len(content)) as u64,
+        digest_mismatch: digest.is_some_and(|d| d.crc32 != checksum::crc32(content)),
+    }
+}
@@ -0,0 +1,106 @@
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Logs of the WAL paced as they were originally written, see [crate::Wal::replay]
+///
+/// Every log is returned once as much time passed since the first one as between their
+/// timestamps, divided by the speed. Pacing follows the schedule of the first log, so time spent
+/// by the caller between two logs doesn't add up to a drift. Logs whose timestamp goes back in
+/// time, or without a timestamp, are returned right away.
+pub struct Replay<T> {
+    // logs along with when they were written, if known
+    logs: std::vec::IntoIter<(T, Option<SystemTime>)>,
+    // time scale, 2.0 replaying twice as fast as the logs were written
+    speed: f64,
+    // timestamp of the first log and when it was returned
+    start: Option<(SystemTime, Instant)>,
+}
+
+impl<T> Replay<T> {
+    pub(crate) fn new(logs: Vec<(T, Option<SystemTime>)>) -> Self {
+        Self {
+            logs: logs.into_iter(),
+            speed: 1.0,
+            start: None,
+        }
+    }
+
+    /// Pace the logs by the time `timestamp` tells they were written, e.g. from a field of the
+    /// log, instead of their stamps
+    pub fn timestamp<F>(mut self, timestamp: F) -> Self
+    where
+        F: Fn(&T) -> SystemTime,
+    {
+        let logs = self.logs.map(|(log, _)| {
+            let at = timestamp(&log);
+            (log, Some(at))
+        });
+        self.logs = logs.collect::<Vec<_>>().into_iter();
+        self
+    }
+
+    /// Replay `speed` times as fast as the logs were written, e.g. 10.0 to compress an hour of
+    /// traffic in six minutes, or 0.5 to stretch it to two hours
+    ///
+    /// Speeds that aren't positive are ignored.
+    pub fn speed(mut self, speed: f64) -> Self {
+        if speed > 0.0 {
+            self.speed = speed;
+        }
+        self
+    }
+
+    // time to wait from the replay of the first log until the replay of a log written at `at`
+    fn offset(&self, first: SystemTime, at: SystemTime) -> Duration {
+        at.duration_since(first)
+            .unwrap_or_default()
+            .div_f64(self.speed)
+    }
+}
+
+impl<T> Iterator for Replay<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (log, at) = self.logs.next()?;
+        let at = match at {
+            Some(at) => at,
+            None => return Some(log),
+        };
+        match self.start {
+            None => self.start = Some((at, Instant::now())),
+            Some((first, started)) => {
+                let due = started + self.offset(first, at);
+                if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                    sleep(wait);
+                }
+            }
+        }
+        Some(log)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.logs.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pacing() {
+        let start = SystemTime::UNIX_EPOCH;
+        let logs = [0, 100, 50, 200].map(|ms| start + Duration::from_millis(ms));
+        let started = Instant::now();
+        let replayed = Replay::new(logs.map(|at| (at, None)).to_vec())
+            .timestamp(|at| *at)
+            .speed(2.0)
+            .collect::<Vec<_>>();
+        assert_eq!(replayed, logs);
+        // the last log was written 200ms after the first one
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(100), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(180), "{:?}", elapsed);
+    }
+}
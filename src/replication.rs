@@ -0,0 +1,489 @@
+//! Replication of a WAL to followers over TCP
+//!
+//! A primary serves its logs with [Wal::serve_replication], and a follower WAL, usually in
+//! another process or on another machine, copies them with [Wal::follow]. Followers keep the
+//! sequence numbers of the primary, so a log has the same number on both sides, and resume from
+//! their next sequence number whenever they reconnect, e.g. after a restart or a network failure.
+//! Only logs written to the files of the primary by its writer thread are sent, as durable as its
+//! [crate::SyncPolicy] makes them.
+//!
+//! Every connection starts with the follower asking for the logs from its next sequence number
+//! on: `WREP`, the version of the protocol as one byte, then the sequence number as a 64-bit
+//! little-endian integer. The primary replies with one status byte, 0 when it holds the logs
+//! asked for and 1 when the follower is ahead of it, then the sequence number of the first log
+//! it sends. Every log follows as its sequence number, the length of its payload as a 32-bit
+//! little-endian integer, and its payload.
+//!
+//! # Example
+//! ```
+//! use std::time::Duration;
+//! use walcraft::Wal;
+//!
+//! # let _ = std::fs::remove_dir_all("./tmp/replication_doc/");
+//! # std::fs::create_dir_all("./tmp/replication_doc/primary/").unwrap();
+//! # std::fs::create_dir_all("./tmp/replication_doc/follower/").unwrap();
+//! let primary: Wal<String> = Wal::new("./tmp/replication_doc/primary/", 500).unwrap();
+//! let server = primary.serve_replication("127.0.0.1:0").unwrap();
+//!
+//! let replica: Wal<String> = Wal::new("./tmp/replication_doc/follower/", 500).unwrap();
+//! let follower = replica.follow(server.local_addr()).unwrap();
+//! let lsn = primary.try_write(&"checkout".to_string()).unwrap();
+//! primary.flush().unwrap();
+//! assert!(follower.wait_for(lsn, Duration::from_secs(5)));
+//! assert_eq!(replica.get(lsn).unwrap(), Some("checkout".to_string()));
+//! ```
+
+use crate::entry::LogEntry;
+use crate::{Lsn, Wal, WalError};
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufWriter, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, JoinHandle};
+use std::time::{Duration, Instant};
+
+// first bytes sent by a follower
+const MAGIC: &[u8; 4] = b"WREP";
+// version of the protocol
+const VERSION: u8 = 1;
+// the primary holds the logs asked for
+const STATUS_OK: u8 = 0;
+// the follower holds logs the primary doesn't have
+const STATUS_AHEAD: u8 = 1;
+// how often blocked threads check whether they should stop
+const POLL: Duration = Duration::from_millis(20);
+// wait for the handshake of a follower, or for a primary to accept a connection
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// wait before a follower connects again after losing its primary
+const RETRY: Duration = Duration::from_millis(200);
+
+/// Server sending the logs of a WAL to its followers, see [Wal::serve_replication]
+///
+/// The server stops accepting followers once dropped, and connected followers are disconnected.
+pub struct ReplicationServer {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+}
+
+impl ReplicationServer {
+    /// Address the server listens on, handy when bound to port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for ReplicationServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Handle to the thread copying the logs of a primary to a follower WAL, see [Wal::follow]
+///
+/// The thread connects again whenever the connection is lost, and stops once the handle is
+/// dropped, or once the follower can't go on, as told by [Follower::last_error].
+pub struct Follower {
+    state: Arc<FollowerState>,
+    thread: Option<JoinHandle<()>>,
+}
+
+// Progress of a follower, shared with its thread
+struct FollowerState {
+    stop: AtomicBool,
+    // sequence number of the next log expected from the primary
+    next: AtomicU64,
+    connected: AtomicBool,
+    // the thread gave up after an error it can't recover from
+    stopped: AtomicBool,
+    last_error: Mutex<Option<String>>,
+}
+
+impl Follower {
+    /// Sequence number of the next log expected from the primary
+    pub fn next_lsn(&self) -> Lsn {
+        self.state.next.load(Ordering::Relaxed)
+    }
+
+    /// Whether the follower is connected to its primary
+    pub fn connected(&self) -> bool {
+        self.state.connected.load(Ordering::Relaxed)
+    }
+
+    /// Whether the follower gave up, e.g. as the primary dropped logs the follower never got
+    pub fn stopped(&self) -> bool {
+        self.state.stopped.load(Ordering::Relaxed)
+    }
+
+    /// The last error met by the follower, while connecting or copying logs
+    pub fn last_error(&self) -> Option<String> {
+        self.state
+            .last_error
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+
+    /// Wait until the log `lsn` of the primary was added to the follower WAL, or for `timeout` at
+    /// most, returning whether it was
+    pub fn wait_for(&self, lsn: Lsn, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while self.next_lsn() <= lsn {
+            if self.stopped() || Instant::now() >= deadline {
+                return false;
+            }
+            sleep(Duration::from_millis(1));
+        }
+        true
+    }
+}
+
+impl Drop for Follower {
+    // the thread holds a handle of the follower WAL, released before returning
+    fn drop(&mut self) {
+        self.state.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl<T> Wal<T>
+where
+    T: Serialize + for<'a> Deserialize<'a> + Send + 'static,
+{
+    /// Send the logs of the WAL to the followers connecting to `addr`, see the
+    /// [module](crate::replication) documentation
+    ///
+    /// Every follower is served by a thread of its own, sending the logs it misses, then every
+    /// log as the writer thread writes it. Followers asking for logs already dropped from the WAL
+    /// get the oldest logs left. Available with the `replication` feature.
+    pub fn serve_replication<A: ToSocketAddrs>(
+        &self,
+        addr: A,
+    ) -> Result<ReplicationServer, WalError> {
+        let listener = TcpListener::bind(addr)
+            .map_err(|e| WalError::io(e, "Failed to listen for followers"))?;
+        let local = listener
+            .local_addr()
+            .map_err(|e| WalError::io(e, "Failed to listen for followers"))?;
+        listener
+            .set_nonblocking(true)
+            .map_err(|e| WalError::io(e, "Failed to listen for followers"))?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let (wal, stopped) = (self.clone(), stop.clone());
+        std::thread::Builder::new()
+            .name("walcraft-replication".to_string())
+            .spawn(move || accept(wal, listener, stopped))
+            .map_err(|e| WalError::io(e, "Failed to spawn replication thread"))?;
+        Ok(ReplicationServer { addr: local, stop })
+    }
+
+    /// Copy the logs of the primary serving replication at `primary` to this WAL, from its next
+    /// sequence number on, see the [module](crate::replication) documentation
+    ///
+    /// The WAL must only be written by the follower: logs keep the sequence numbers they have on
+    /// the primary, and the follower stops once they don't match. An empty WAL starts from the
+    /// oldest log of the primary, while a WAL missing logs the primary already dropped stops
+    /// with [WalError::CompactedAway]. Available with the `replication` feature.
+    pub fn follow<A: ToSocketAddrs>(&self, primary: A) -> Result<Follower, WalError> {
+        self.check_writable()?;
+        let addr = primary
+            .to_socket_addrs()
+            .map_err(|e| WalError::io(e, "Failed to resolve the primary"))?
+            .next()
+            .ok_or_else(|| WalError::Unsupported("No address for the primary".to_string()))?;
+        let state = Arc::new(FollowerState {
+            stop: AtomicBool::new(false),
+            next: AtomicU64::new(self.buffer.next_lsn()),
+            connected: AtomicBool::new(false),
+            stopped: AtomicBool::new(false),
+            last_error: Mutex::new(None),
+        });
+        let (wal, shared) = (self.clone(), state.clone());
+        let thread = std::thread::Builder::new()
+            .name("walcraft-follower".to_string())
+            .spawn(move || follow(wal, addr, shared))
+            .map_err(|e| WalError::io(e, "Failed to spawn follower thread"))?;
+        Ok(Follower {
+            state,
+            thread: Some(thread),
+        })
+    }
+
+    // logs written to the files from `from` on
+    fn durable_from(&self, from: Lsn) -> Result<Vec<LogEntry>, WalError> {
+        self.read_snapshot(|reader| {
+            let mut logs = reader.read_indexed(|_| true, |block| *block.lsns.end() >= from)?;
+            logs.retain(|entry| entry.lsn() >= from);
+            Ok(logs)
+        })
+    }
+}
+
+// Accept followers until the server is dropped
+fn accept<T>(wal: Wal<T>, listener: TcpListener, stop: Arc<AtomicBool>)
+where
+    T: Serialize + for<'a> Deserialize<'a> + Send + 'static,
+{
+    while !stop.load(Ordering::Relaxed) {
+        let stream = match listener.accept() {
+            Ok((stream, _)) => stream,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                sleep(POLL);
+                continue;
+            }
+            Err(_) => continue,
+        };
+        let (wal, stop) = (wal.clone(), stop.clone());
+        // a follower that can't be served connects again
+        let _ = std::thread::Builder::new()
+            .name("walcraft-replica".to_string())
+            .spawn(move || {
+                let _ = serve(&wal, stream, &stop);
+            });
+    }
+}
+
+// Send the logs a follower asks for, then the logs written from then on, until either side stops
+fn serve<T>(wal: &Wal<T>, stream: TcpStream, stop: &AtomicBool) -> Result<(), WalError>
+where
+    T: Serialize + for<'a> Deserialize<'a> + Send + 'static,
+{
+    let fail = |e| WalError::io(e, "Failed to serve follower");
+    stream.set_nonblocking(false).map_err(fail)?;
+    stream.set_nodelay(true).map_err(fail)?;
+    stream
+        .set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+        .map_err(fail)?;
+    let mut handshake = [0; 13];
+    (&stream).read_exact(&mut handshake).map_err(fail)?;
+    if &handshake[..4] != MAGIC || handshake[4] != VERSION {
+        return Err(WalError::Unsupported("Not a walcraft follower".to_string()));
+    }
+    let from = Lsn::from_le_bytes(handshake[5..].try_into().unwrap_or_default());
+
+    // logs written while the files are read are in both, and only sent once
+    let written = wal.subscribers.add();
+    let logs = wal.durable_from(from)?;
+    let mut out = BufWriter::new(&stream);
+    if from > wal.buffer.next_lsn() {
+        out.write_all(&[STATUS_AHEAD]).map_err(fail)?;
+        out.write_all(&wal.buffer.next_lsn().to_le_bytes())
+            .map_err(fail)?;
+        return out.flush().map_err(fail);
+    }
+    let mut next = logs.first().map_or(from, |log| log.lsn());
+    out.write_all(&[STATUS_OK]).map_err(fail)?;
+    out.write_all(&next.to_le_bytes()).map_err(fail)?;
+    send(&mut out, &logs, &mut next).map_err(fail)?;
+    while !stop.load(Ordering::Relaxed) {
+        match written.recv_timeout(POLL) {
+            Ok(logs) => send(&mut out, &logs, &mut next).map_err(fail)?,
+            Err(RecvTimeoutError::Timeout) => continue,
+            // the writer thread has stopped
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    Ok(())
+}
+
+// send the logs from `next` on, moving `next` past them
+fn send(out: &mut impl Write, logs: &[LogEntry], next: &mut Lsn) -> io::Result<()> {
+    for log in logs {
+        if log.lsn() < *next {
+            continue;
+        }
+        let payload = log.payload();
+        let len = u32::try_from(payload.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "payload too large"))?;
+        out.write_all(&log.lsn().to_le_bytes())?;
+        out.write_all(&len.to_le_bytes())?;
+        out.write_all(payload)?;
+        *next = log.lsn() + 1;
+    }
+    out.flush()
+}
+
+// Copy the logs of the primary at `addr`, connecting again until stopped
+fn follow<T>(wal: Wal<T>, addr: SocketAddr, state: Arc<FollowerState>)
+where
+    T: Serialize + for<'a> Deserialize<'a> + Send + 'static,
+{
+    while !state.stop.load(Ordering::Relaxed) {
+        let error = replicate(&wal, addr, &state).err();
+        state.connected.store(false, Ordering::Relaxed);
+        // only a failed connection is worth another attempt
+        let fatal = error
+            .as_ref()
+            .is_some_and(|e| !matches!(e, WalError::Io { .. }));
+        if let Some(e) = error {
+            *state.last_error.lock().unwrap_or_else(|e| e.into_inner()) = Some(e.to_string());
+        }
+        if fatal {
+            state.stopped.store(true, Ordering::Relaxed);
+            return;
+        }
+        let retry_at = Instant::now() + RETRY;
+        while Instant::now() < retry_at && !state.stop.load(Ordering::Relaxed) {
+            sleep(POLL);
+        }
+    }
+}
+
+// Copy logs over one connection, until it's lost or the follower is stopped
+fn replicate<T>(wal: &Wal<T>, addr: SocketAddr, state: &FollowerState) -> Result<(), WalError>
+where
+    T: Serialize + for<'a> Deserialize<'a> + Send + 'static,
+{
+    let fail = |e| WalError::io(e, "Failed to replicate from the primary");
+    let mut stream = TcpStream::connect_timeout(&addr, HANDSHAKE_TIMEOUT).map_err(fail)?;
+    stream.set_nodelay(true).map_err(fail)?;
+    stream.set_read_timeout(Some(POLL)).map_err(fail)?;
+    let from = wal.buffer.next_lsn();
+    let mut handshake = MAGIC.to_vec();
+    handshake.push(VERSION);
+    handshake.extend_from_slice(&from.to_le_bytes());
+    stream.write_all(&handshake).map_err(fail)?;
+
+    let mut reply = [0; 9];
+    if !read_full(&mut stream, &mut reply, &state.stop).map_err(fail)? {
+        return Ok(());
+    }
+    let start = Lsn::from_le_bytes(reply[1..].try_into().unwrap_or_default());
+    if reply[0] == STATUS_AHEAD {
+        return Err(WalError::Replication(format!(
+            "The follower expects log {} while the primary is at log {}",
+            from, start
+        )));
+    }
+    // an empty follower starts from the oldest log of the primary
+    if start > from && !(wal.reader().last_lsn().is_none() && wal.buffer.renumber(start)) {
+        return Err(WalError::CompactedAway {
+            lsn: from,
+            oldest: start,
+        });
+    }
+    state.next.store(start, Ordering::Relaxed);
+    state.connected.store(true, Ordering::Relaxed);
+
+    let mut header = [0; 12];
+    loop {
+        if !read_full(&mut stream, &mut header, &state.stop).map_err(fail)? {
+            return Ok(());
+        }
+        let lsn = Lsn::from_le_bytes(header[..8].try_into().unwrap_or_default());
+        let len = u32::from_le_bytes(header[8..].try_into().unwrap_or_default());
+        let mut payload = vec![0; len as usize];
+        if !read_full(&mut stream, &mut payload, &state.stop).map_err(fail)? {
+            return Ok(());
+        }
+        let written = wal.write_raw_vec(payload)?;
+        if written != lsn {
+            return Err(WalError::Replication(format!(
+                "Log {} of the primary was written as log {}, the follower WAL must only be \
+                 written by the follower",
+                lsn, written
+            )));
+        }
+        state.next.store(lsn + 1, Ordering::Relaxed);
+    }
+}
+
+// Fill `buf` from the stream, false if the follower was stopped first
+fn read_full(stream: &mut TcpStream, buf: &mut [u8], stop: &AtomicBool) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        if stop.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+        match stream.read(&mut buf[filled..]) {
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => filled += n,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) => {}
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clear(name: &str) -> String {
+        let dir = format!("./tmp/{}/", name);
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn follow_and_resume() {
+        let primary: Wal<u32> = Wal::new(&clear("replication_primary"), 10_000).unwrap();
+        for i in 0..10 {
+            primary.write(i);
+        }
+        primary.flush().unwrap();
+        let server = primary.serve_replication("127.0.0.1:0").unwrap();
+
+        let dir = clear("replication_follower");
+        let replica: Wal<u32> = Wal::new(&dir, 10_000).unwrap();
+        let follower = replica.follow(server.local_addr()).unwrap();
+        // logs written before and after the follower connected are copied
+        assert!(follower.wait_for(9, Duration::from_secs(5)));
+        let lsn = primary.try_write(&10).unwrap();
+        primary.flush().unwrap();
+        assert!(follower.wait_for(lsn, Duration::from_secs(5)));
+        assert_eq!(replica.read().unwrap(), (0..=10).collect::<Vec<_>>());
+        assert_eq!(replica.get(lsn).unwrap(), Some(10));
+
+        // a follower opened again resumes after its last log
+        drop(follower);
+        replica.close().unwrap();
+        for i in 11..20 {
+            primary.write(i);
+        }
+        primary.flush().unwrap();
+        let replica: Wal<u32> = Wal::new(&dir, 10_000).unwrap();
+        let follower = replica.follow(server.local_addr()).unwrap();
+        assert!(follower.wait_for(19, Duration::from_secs(5)));
+        assert_eq!(replica.read().unwrap(), (0..20).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn follower_missing_dropped_logs() {
+        let primary: Wal<u32> = Wal::new(&clear("replication_dropped"), 1000).unwrap();
+        for i in 0..60 {
+            primary.write(i);
+            if i % 5 == 4 {
+                primary.flush().unwrap();
+            }
+        }
+        primary.truncate(20).unwrap();
+        let oldest = primary.read_raw_numbered().unwrap()[0].0;
+        assert!(oldest > 1);
+        let server = primary.serve_replication("127.0.0.1:0").unwrap();
+
+        // an empty follower starts from the oldest log left
+        let replica: Wal<u32> = Wal::new(&clear("replication_empty"), 1000).unwrap();
+        let follower = replica.follow(server.local_addr()).unwrap();
+        assert!(follower.wait_for(59, Duration::from_secs(5)));
+        assert_eq!(replica.get(oldest).unwrap(), Some(oldest as u32));
+
+        // a follower with logs can't skip the ones it misses
+        let behind: Wal<u32> = Wal::new(&clear("replication_behind"), 1000).unwrap();
+        behind.try_write(&0).unwrap();
+        behind.flush().unwrap();
+        let follower = behind.follow(server.local_addr()).unwrap();
+        assert!(!follower.wait_for(59, Duration::from_secs(5)));
+        assert!(follower.stopped());
+        assert!(follower.last_error().unwrap().contains("dropped"));
+    }
+}
@@ -0,0 +1,168 @@
+use crate::{Lsn, Wal, WalError, WalIter, WalMetrics, WalStats};
+use serde::{Deserialize, Serialize};
+use std::ops::{Deref, Range};
+
+/// The only handle allowed to write to a WAL, created with [crate::WalBuilder::build_writer]
+///
+/// Unlike [Wal], this handle can't be cloned, so whoever holds it is the single writer of the
+/// WAL. Subsystems that only read get a [WalReadHandle] from [WalWriterHandle::read_handle],
+/// which has no way to write. The writer reads through the same methods, as it dereferences to
+/// its own read handle.
+///
+/// # Example
+/// ```
+/// use walcraft::WalBuilder;
+///
+/// # let _ = std::fs::remove_dir_all("./tmp/writer_handle_doc/");
+/// # std::fs::create_dir_all("./tmp/writer_handle_doc/").unwrap();
+/// let writer = WalBuilder::<String>::new("./tmp/writer_handle_doc/", 500)
+///     .build_writer()
+///     .unwrap();
+/// let reader = writer.read_handle();
+/// std::thread::spawn(move || println!("{} logs", reader.read().unwrap().len()));
+///
+/// writer.write("order placed".to_string());
+/// writer.flush().unwrap();
+/// assert_eq!(writer.read().unwrap().len(), 1);
+/// ```
+pub struct WalWriterHandle<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    reader: WalReadHandle<T>,
+}
+
+impl<T> WalWriterHandle<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    pub(crate) fn new(wal: Wal<T>) -> Self {
+        Self {
+            reader: WalReadHandle { wal },
+        }
+    }
+
+    /// Read-only handle of the WAL, which can be cloned and shared freely
+    pub fn read_handle(&self) -> WalReadHandle<T> {
+        self.reader.clone()
+    }
+
+    /// Write a log, see [Wal::write]
+    pub fn write(&self, entry: T) -> Option<Lsn> {
+        self.reader.wal.write(entry)
+    }
+
+    /// Write a log, reporting why it was not accepted, see [Wal::try_write]
+    pub fn try_write(&self, entry: &T) -> Result<Lsn, WalError> {
+        self.reader.wal.try_write(entry)
+    }
+
+    /// Write a log and wait until it's durable, see [Wal::write_sync]
+    pub fn write_sync(&self, entry: &T) -> Result<Lsn, WalError> {
+        self.reader.wal.write_sync(entry)
+    }
+
+    /// Write a batch of logs, see [Wal::batch_write]
+    pub fn batch_write(&self, entries: Vec<T>) -> Option<Range<Lsn>> {
+        self.reader.wal.batch_write(entries)
+    }
+
+    /// Write a batch of logs, reporting why it was not accepted, see [Wal::try_write_all]
+    pub fn try_write_all(&self, entries: &[T]) -> Result<Range<Lsn>, WalError> {
+        self.reader.wal.try_write_all(entries)
+    }
+
+    /// Write the logs accepted so far to the files, see [Wal::flush]
+    pub fn flush(&self) -> Result<(), WalError> {
+        self.reader.wal.flush()
+    }
+
+    /// Remove the logs up to the sequence number `through`, see [Wal::truncate]
+    pub fn truncate(&self, through: Lsn) -> Result<usize, WalError> {
+        self.reader.wal.truncate(through)
+    }
+}
+
+impl<T> Deref for WalWriterHandle<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    type Target = WalReadHandle<T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.reader
+    }
+}
+
+/// Handle reading a WAL without being able to write to it, see [WalWriterHandle::read_handle]
+///
+/// Read handles share the WAL of their writer and can be cloned like [Wal]. They keep the WAL
+/// open, as any handle does, after the writer handle is dropped.
+pub struct WalReadHandle<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    wal: Wal<T>,
+}
+
+impl<T> Clone for WalReadHandle<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    fn clone(&self) -> Self {
+        Self {
+            wal: self.wal.clone(),
+        }
+    }
+}
+
+impl<T> WalReadHandle<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    /// Read all written logs, see [Wal::read]
+    pub fn read(&self) -> Result<Vec<T>, WalError>
+    where
+        T: Send,
+    {
+        self.wal.read()
+    }
+
+    /// Iterate over all written logs, see [Wal::iter]
+    pub fn iter(&self) -> Result<WalIter<T>, WalError> {
+        self.wal.iter()
+    }
+
+    /// Get the log with the given sequence number, see [Wal::get]
+    pub fn get(&self, lsn: Lsn) -> Result<Option<T>, WalError> {
+        self.wal.get(lsn)
+    }
+
+    /// Read the last `n` written logs, see [Wal::read_last]
+    pub fn read_last(&self, n: usize) -> Result<Vec<T>, WalError> {
+        self.wal.read_last(n)
+    }
+
+    /// Read the logs from the sequence number `lsn` on, see [Wal::read_from]
+    pub fn read_from(&self, lsn: Lsn) -> Result<Vec<T>, WalError>
+    where
+        T: Send,
+    {
+        self.wal.read_from(lsn)
+    }
+
+    /// Iterate over the logs from the sequence number `lsn` on, see [Wal::iter_from]
+    pub fn iter_from(&self, lsn: Lsn) -> Result<WalIter<T>, WalError> {
+        self.wal.iter_from(lsn)
+    }
+
+    /// Statistics of the WAL, see [Wal::stats]
+    pub fn stats(&self) -> WalStats {
+        self.wal.stats()
+    }
+
+    /// Counters of the WAL, see [Wal::metrics]
+    pub fn metrics(&self) -> WalMetrics {
+        self.wal.metrics()
+    }
+}
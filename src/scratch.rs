@@ -0,0 +1,73 @@
+use crate::memory::MemoryBudget;
+
+// Buffer holding raw file content during reads, reused across reads of a WAL
+// It grows as needed while reading a large file, but only up to `budget` bytes are kept
+// allocated between reads
+#[derive(Debug, Default)]
+pub(crate) struct Scratch {
+    buffer: Vec<u8>,
+    budget: usize,
+    // memory budget of the WAL, told how much is kept between reads
+    memory: MemoryBudget,
+}
+
+impl Scratch {
+    pub fn new(budget: usize, memory: MemoryBudget) -> Self {
+        // never keep more than the whole WAL may use
+        let budget = memory.limit().map_or(budget, |limit| budget.min(limit));
+        let buffer = Vec::with_capacity(budget);
+        memory.set_scratch(buffer.capacity());
+        Self {
+            buffer,
+            budget,
+            memory,
+        }
+    }
+
+    // lend the buffer to a reader
+    pub fn take(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
+    }
+
+    // return the buffer once the read is over, releasing memory beyond the budget
+    pub fn restore(&mut self, mut buffer: Vec<u8>) {
+        buffer.clear();
+        buffer.shrink_to(self.budget);
+        self.memory.set_scratch(buffer.capacity());
+        self.buffer = buffer;
+    }
+
+    #[cfg(test)]
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budget() {
+        let memory = MemoryBudget::new(None);
+        let mut scratch = Scratch::new(64, memory.clone());
+        let mut buffer = scratch.take();
+        let ptr = buffer.as_ptr();
+        buffer.extend_from_slice(&[1; 32]);
+        scratch.restore(buffer);
+        // the same allocation is reused
+        let buffer = scratch.take();
+        assert_eq!(buffer.as_ptr(), ptr);
+
+        // memory beyond the budget is released
+        scratch.restore(buffer);
+        scratch.restore(vec![0; 1024]);
+        assert!(scratch.capacity() < 1024);
+        assert!(scratch.capacity() >= 64);
+        assert_eq!(memory.scratch(), scratch.capacity());
+
+        // the scratch buffer stays within the memory budget
+        let scratch = Scratch::new(64, MemoryBudget::new(Some(16)));
+        assert!(scratch.capacity() < 64);
+    }
+}
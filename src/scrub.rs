@@ -0,0 +1,211 @@
+use crate::checksum;
+use crate::layout::Layout;
+use crate::manifest::{Manifest, ManifestKind};
+use crate::segment::SegmentDigest;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread::sleep;
+use std::time::{Duration, SystemTime};
+
+/// Sealed WAL file whose content no longer matches the digest recorded when it was sealed
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Corruption {
+    /// Sequence number of the WAL file, i.e. `N` in `wal_N`
+    pub id: u8,
+    /// CRC-32 recorded when the file was sealed
+    pub expected: u32,
+    /// CRC-32 of the file when it was checked
+    pub found: u32,
+    /// When the corruption was found
+    pub detected_at: SystemTime,
+}
+
+/// Health of the files of a WAL, as reported by [crate::Wal::health]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Health {
+    /// Number of sealed files checked by the scrubber
+    pub scrubbed: u64,
+    /// Corrupted files found by the scrubber, oldest first
+    pub corruptions: Vec<Corruption>,
+}
+
+// Outcome of checking a sealed file
+pub(crate) enum Scrub {
+    // no file could be checked
+    Skipped,
+    Clean,
+    Corrupt(Corruption),
+}
+
+// Shared health of a WAL, along with the scrubber keeping it up to date when enabled
+pub(crate) fn health(
+    location: &Path,
+    layout: &Layout,
+    interval: Option<Duration>,
+) -> Arc<Mutex<Health>> {
+    let health = Arc::new(Mutex::new(Health::default()));
+    if let Some(interval) = interval {
+        // a WAL without a scrubber still works
+        let _ = spawn(
+            location.to_path_buf(),
+            layout.clone(),
+            interval,
+            Arc::downgrade(&health),
+        );
+    }
+    health
+}
+
+// Start a background thread checking a random sealed file against its digest every `interval`
+// The thread stops once all handles of the WAL are dropped, as `health` can't be upgraded
+fn spawn(
+    location: PathBuf,
+    layout: Layout,
+    interval: Duration,
+    health: Weak<Mutex<Health>>,
+) -> io::Result<()> {
+    std::thread::Builder::new()
+        .name("walcraft-scrubber".to_string())
+        .spawn(move || {
+            let random = RandomState::new();
+            let mut round = 0u64;
+            loop {
+                sleep(interval);
+                let health = match health.upgrade() {
+                    Some(h) => h,
+                    None => return,
+                };
+                let mut hasher = random.build_hasher();
+                hasher.write_u64(round);
+                round += 1;
+                let outcome = scrub(&location, &layout, hasher.finish());
+                let mut health = health.lock().unwrap_or_else(|e| e.into_inner());
+                if !matches!(outcome, Scrub::Skipped) {
+                    health.scrubbed += 1;
+                }
+                health.record(outcome);
+            }
+        })
+        .map(|_| ())
+}
+
+impl Health {
+    // keep track of a corrupted file
+    pub(crate) fn record(&mut self, outcome: Scrub) {
+        let corruption = match outcome {
+            Scrub::Corrupt(corruption) => corruption,
+            _ => return,
+        };
+        // a file is reported once for every content it's found with
+        let known = self.corruptions.iter().any(|c| {
+            (c.id, c.expected, c.found) == (corruption.id, corruption.expected, corruption.found)
+        });
+        if !known {
+            self.corruptions.push(corruption);
+        }
+    }
+}
+
+// Check one of the sealed files with a digest, chosen with `pick`
+fn scrub(location: &Path, layout: &Layout, pick: u64) -> Scrub {
+    let sealed = digests(location);
+    if sealed.is_empty() {
+        return Scrub::Skipped;
+    }
+    check(
+        location,
+        layout,
+        &sealed[(pick % sealed.len() as u64) as usize],
+    )
+}
+
+// Check a sealed file against its digest
+// Files rotated or compacted while being read get a new digest, so they are skipped
+pub(crate) fn check(location: &Path, layout: &Layout, digest: &SegmentDigest) -> Scrub {
+    let content = match layout.storage.read(&layout.path(location, digest.id)) {
+        Ok(Some(c)) => c,
+        _ => return Scrub::Skipped,
+    };
+    let found = checksum::crc32(&content);
+    if found == digest.crc32 {
+        return Scrub::Clean;
+    }
+    if !digests(location).contains(digest) {
+        return Scrub::Skipped;
+    }
+    Scrub::Corrupt(Corruption {
+        id: digest.id,
+        expected: digest.crc32,
+        found,
+        detected_at: SystemTime::now(),
+    })
+}
+
+// digests of sealed files, none if the metadata can't be read right now
+pub(crate) fn digests(location: &Path) -> Vec<SegmentDigest> {
+    Manifest::open(location, ManifestKind::default())
+        .load()
+        .map(|meta| meta.digests)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meta::Meta;
+
+    fn clear_storage(name: &str) -> PathBuf {
+        let path = PathBuf::from(format!("./tmp/{}/", name));
+        if path.exists() {
+            std::fs::remove_dir_all(&path).expect("Failed to delete old files");
+        }
+        std::fs::create_dir_all(&path).expect("Failed to create test directory");
+        path
+    }
+
+    #[test]
+    fn corruption() {
+        let dir = clear_storage("scrub_corruption");
+        assert!(matches!(scrub(&dir, &Layout::default(), 0), Scrub::Skipped));
+
+        let content = b"sealed logs".to_vec();
+        std::fs::write(dir.join("wal_1"), &content).unwrap();
+        let meta = Meta {
+            pointer: 2,
+            offset: None,
+            segments: None,
+            format: None,
+            digests: vec![SegmentDigest {
+                id: 1,
+                crc32: checksum::crc32(&content),
+                size: content.len() as u64,
+                entries: 1,
+                verified: true,
+                sha256: None,
+                chain: None,
+            }],
+            spans: vec![],
+            chain: None,
+        };
+        meta.write(&dir).unwrap();
+        assert!(matches!(scrub(&dir, &Layout::default(), 7), Scrub::Clean));
+
+        // flip a bit of the sealed file
+        let mut rotten = content.clone();
+        rotten[3] ^= 0x10;
+        std::fs::write(dir.join("wal_1"), &rotten).unwrap();
+        match scrub(&dir, &Layout::default(), 7) {
+            Scrub::Corrupt(c) => {
+                assert_eq!(c.id, 1);
+                assert_eq!(c.expected, checksum::crc32(&content));
+                assert_eq!(c.found, checksum::crc32(&rotten));
+            }
+            _ => panic!("corruption not found"),
+        }
+    }
+}
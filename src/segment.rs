@@ -0,0 +1,157 @@
+use crate::{checksum, Lsn};
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Logs stored in a single WAL file along with metadata of the file
+///
+/// Returned by [crate::Wal::read_segments] for consumers that process the WAL file by file,
+/// such as archivers and replicators checkpointing their progress per file, and by
+/// [crate::Wal::read_segment] to examine a single file.
+#[derive(Debug)]
+#[non_exhaustive]
+pub struct SegmentEntries<T> {
+    /// Sequence number of the WAL file, i.e. `N` in `wal_N`
+    pub id: u8,
+    /// Location of the WAL file on storage
+    pub path: PathBuf,
+    /// Size of the WAL file in bytes
+    pub size: u64,
+    /// Whether the writer is still appending to this file
+    pub active: bool,
+    /// Sequence numbers of the first and the last log stored in the file, None when it's empty
+    ///
+    /// Logs that can't be deserialized are left out of `entries` but still counted here.
+    pub lsns: Option<RangeInclusive<Lsn>>,
+    /// Digest recorded when the file was sealed, see [crate::WalBuilder::verify_on_rotation]
+    pub digest: Option<SegmentDigest>,
+    /// Logs stored in the file, in the order they were written
+    pub entries: Vec<T>,
+}
+
+/// Digest of a sealed WAL file, computed when the writer moves on to the next file
+///
+/// Recorded only when [crate::WalBuilder::verify_on_rotation] is enabled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SegmentDigest {
+    /// Sequence number of the WAL file, i.e. `N` in `wal_N`
+    pub id: u8,
+    /// CRC-32 of the whole file
+    pub crc32: u32,
+    /// Size of the file in bytes
+    pub size: u64,
+    /// Number of logs in the file
+    pub entries: u64,
+    /// Whether the file read back exactly what the writer wrote
+    pub verified: bool,
+    /// SHA-256 of the whole file, None for digests recorded by older versions
+    pub sha256: Option<[u8; 32]>,
+    /// Link of the file in the hash chain of sealed files, see [SegmentSealed]
+    pub chain: Option<[u8; 32]>,
+}
+
+impl SegmentDigest {
+    // Digest of the file `id` from the fields recorded in meta files:
+    // `<crc32> <size> <entries> <ok|corrupt>`, followed by `<sha256> <link>` when the file is
+    // part of the hash chain
+    pub(crate) fn parse(id: u8, fields: &[&str]) -> Option<Self> {
+        let (crc32, size, entries, status, rest) = match fields {
+            [crc32, size, entries, status, rest @ ..] => (crc32, size, entries, status, rest),
+            _ => return None,
+        };
+        let (sha256, chain) = match rest {
+            [sha256, chain, ..] => (
+                Some(checksum::from_hex(sha256)?),
+                Some(checksum::from_hex(chain)?),
+            ),
+            _ => (None, None),
+        };
+        Some(Self {
+            id,
+            crc32: u32::from_str_radix(crc32, 16).ok()?,
+            size: size.parse().ok()?,
+            entries: entries.parse().ok()?,
+            verified: *status == "ok",
+            sha256,
+            chain,
+        })
+    }
+
+    // fields recorded in meta files, see [SegmentDigest::parse]
+    pub(crate) fn fields(&self) -> String {
+        let status = if self.verified { "ok" } else { "corrupt" };
+        let mut fields = format!(
+            "{:08x} {} {} {}",
+            self.crc32, self.size, self.entries, status
+        );
+        if let (Some(sha256), Some(chain)) = (&self.sha256, &self.chain) {
+            fields.push_str(&format!(
+                " {} {}",
+                checksum::to_hex(sha256),
+                checksum::to_hex(chain)
+            ));
+        }
+        fields
+    }
+}
+
+// Time range in which the logs of a sealed file were written, in milliseconds since the unix
+// epoch, recorded in meta files as `<first> <last>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SegmentSpan {
+    pub id: u8,
+    pub first: u64,
+    pub last: u64,
+}
+
+impl SegmentSpan {
+    pub fn parse(id: u8, fields: &[&str]) -> Option<Self> {
+        match fields {
+            [first, last, ..] => Some(Self {
+                id,
+                first: first.parse().ok()?,
+                last: last.parse().ok()?,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn fields(&self) -> String {
+        format!("{} {}", self.first, self.last)
+    }
+
+    // when the last log of the file was written
+    pub fn last_written(&self) -> SystemTime {
+        Self::time(self.last)
+    }
+
+    // time `millis` milliseconds after the unix epoch
+    pub fn time(millis: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_millis(millis)
+    }
+
+    // milliseconds since the unix epoch of `at`
+    pub fn millis(at: SystemTime) -> u64 {
+        at.duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64)
+    }
+}
+
+/// Event emitted when the writer seals a WAL file and moves on to the next one
+///
+/// Sealed files form a hash chain: the link of a file is the SHA-256 of the link of the file
+/// sealed before it followed by the SHA-256 of its content. Recording the links elsewhere makes
+/// any later change to the sealed files detectable with [crate::Wal::verify_chain].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct SegmentSealed {
+    /// Digest of the sealed file, including its link in the chain
+    pub digest: SegmentDigest,
+    /// Link of the file sealed before, all zeros for the first file of the chain
+    pub previous: [u8; 32],
+}
+
+// Callback notified of every sealed file
+pub(crate) type OnSeal = Arc<dyn Fn(&SegmentSealed) + Send + Sync>;
@@ -0,0 +1,189 @@
+use crate::meta;
+use crate::reader::WalReader;
+use crate::Lsn;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+// Logs per block of an index, reads seek to the start of a block
+pub(crate) const INDEX_INTERVAL: u64 = 64;
+
+// Index of a sealed WAL file, stored in `index_N` next to `wal_N`
+// The logs of the file are split into blocks of about [INDEX_INTERVAL] logs, and the index holds
+// where every block starts along with the sequence numbers and timestamps of its logs. Reads by
+// sequence number, by time or from the end only read the blocks holding the logs they look for.
+// An index describes the file as it was when it was built, and is ignored once the file has
+// another size, e.g. after it was compacted or reused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SegmentIndex {
+    // size of the indexed file
+    pub size: u64,
+    pub blocks: Vec<IndexBlock>,
+}
+
+// Logs stored one after the other in a WAL file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct IndexBlock {
+    // offset of the first frame of the block in the file
+    pub offset: u64,
+    pub lsns: RangeInclusive<Lsn>,
+    pub count: u64,
+    // earliest and latest timestamps of the logs, None when no log is stamped
+    pub times: Option<RangeInclusive<u64>>,
+}
+
+impl SegmentIndex {
+    // index of the logs in the WAL file `id`, None unless the file holds native frames
+    // A block never ends within an atomic batch, so every block is read whole on its own.
+    pub fn build(reader: &WalReader, id: u8) -> Option<Self> {
+        if !reader.indexable() {
+            return None;
+        }
+        let content = reader.raw(id).ok()??;
+        let mut blocks: Vec<IndexBlock> = Vec::new();
+        let mut open = false;
+        for (span, entry) in reader.frames(&content) {
+            let (lsn, at) = (entry.lsn(), entry.timestamp());
+            match blocks.last_mut() {
+                Some(block) if open => {
+                    block.lsns = *block.lsns.start()..=lsn;
+                    block.count += 1;
+                    block.times = match (block.times.take(), at) {
+                        (Some(times), Some(at)) => {
+                            Some(at.min(*times.start())..=at.max(*times.end()))
+                        }
+                        (times, at) => times.or(at.map(|at| at..=at)),
+                    };
+                }
+                _ => blocks.push(IndexBlock {
+                    offset: span.start as u64,
+                    lsns: lsn..=lsn,
+                    count: 1,
+                    times: at.map(|at| at..=at),
+                }),
+            }
+            open = entry.batched() || blocks.last().is_some_and(|b| b.count < INDEX_INTERVAL);
+        }
+        Some(Self {
+            size: content.len() as u64,
+            blocks,
+        })
+    }
+
+    // index of the WAL file `id`, None if it's missing or damaged
+    pub fn load(location: &Path, id: u8) -> Option<Self> {
+        let text = std::fs::read_to_string(Self::path(location, id)).ok()?;
+        Self::parse(meta::unseal(&text)?)
+    }
+
+    pub fn store(&self, location: &Path, id: u8) -> std::io::Result<()> {
+        std::fs::write(Self::path(location, id), meta::seal(&self.to_string()))
+    }
+
+    // drop the index of a file that is written to again
+    pub fn remove(location: &Path, id: u8) {
+        let _ = std::fs::remove_file(Self::path(location, id));
+    }
+
+    // the last blocks of the file, holding its last `n` logs at least
+    pub fn tail(&self, n: u64) -> impl Fn(&IndexBlock) -> bool {
+        let mut remaining = n;
+        let mut from = u64::MAX;
+        for block in self.blocks.iter().rev() {
+            if remaining == 0 {
+                break;
+            }
+            from = block.offset;
+            remaining = remaining.saturating_sub(block.count);
+        }
+        move |block: &IndexBlock| block.offset >= from
+    }
+
+    fn path(location: &Path, id: u8) -> PathBuf {
+        location.join(format!("index_{}", id))
+    }
+
+    // `<size>` on the first line, then every block as
+    // `block <offset> <first lsn> <last lsn> <count> [<earliest> <latest>]`
+    fn parse(text: &str) -> Option<Self> {
+        let mut lines = text.lines();
+        let size = lines.next()?.parse().ok()?;
+        let mut blocks = Vec::new();
+        for line in lines {
+            let parts = line.split_whitespace().collect::<Vec<_>>();
+            let (fields, times) = match parts.as_slice() {
+                ["block", fields @ .., earliest, latest] if fields.len() == 4 => {
+                    (fields, Some(earliest.parse().ok()?..=latest.parse().ok()?))
+                }
+                ["block", fields @ ..] if fields.len() == 4 => (fields, None),
+                _ => return None,
+            };
+            blocks.push(IndexBlock {
+                offset: fields[0].parse().ok()?,
+                lsns: fields[1].parse().ok()?..=fields[2].parse().ok()?,
+                count: fields[3].parse().ok()?,
+                times,
+            });
+        }
+        Some(Self { size, blocks })
+    }
+}
+
+impl std::fmt::Display for SegmentIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.size)?;
+        for block in &self.blocks {
+            write!(
+                f,
+                "\nblock {} {} {} {}",
+                block.offset,
+                block.lsns.start(),
+                block.lsns.end(),
+                block.count
+            )?;
+            if let Some(times) = &block.times {
+                write!(f, " {} {}", times.start(), times.end())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::LogEntry;
+    use std::io::Write;
+
+    #[test]
+    fn index() {
+        let dir = PathBuf::from("./tmp/segment_index/");
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut file = std::fs::File::create(dir.join("wal_1")).unwrap();
+        for lsn in 0..150 {
+            let mut entry = LogEntry::try_new(&(lsn as u32)).unwrap();
+            entry.set_lsn(lsn);
+            entry.set_timestamp(Some(1000 + lsn));
+            // a batch crossing the end of the first block stays within it
+            entry.set_batched((60..70).contains(&lsn));
+            file.write_all(&entry.into_frame(None, false)).unwrap();
+        }
+        let reader = WalReader::new(dir.clone());
+        let index = SegmentIndex::build(&reader, 1).unwrap();
+        let counts = index.blocks.iter().map(|b| b.count).collect::<Vec<_>>();
+        assert_eq!(counts, vec![71, 64, 15]);
+        assert_eq!(index.blocks[1].lsns, 71..=134);
+        assert_eq!(index.blocks[2].lsns, 135..=149);
+        assert_eq!(index.blocks[1].times, Some(1071..=1134));
+        let tail = index.tail(20);
+        let kept = index.blocks.iter().filter(|b| tail(b)).count();
+        assert_eq!(kept, 2);
+
+        index.store(&dir, 1).unwrap();
+        assert_eq!(SegmentIndex::load(&dir, 1), Some(index));
+        SegmentIndex::remove(&dir, 1);
+        assert!(SegmentIndex::load(&dir, 1).is_none());
+    }
+}
@@ -0,0 +1,52 @@
+// Best-effort flush of WALs when the process is asked to terminate, available with the `signals`
+// feature on unix
+//
+// A single thread waits for SIGTERM and SIGINT for the whole process. Once one arrives, every
+// registered WAL is flushed and the default action of the signal is carried out, so the process
+// still terminates with the status expected of the signal.
+
+use crate::WalError;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::sync::{Mutex, OnceLock};
+
+// Flush of a registered WAL
+type Flusher = Box<dyn Fn() -> Result<(), WalError> + Send>;
+
+static FLUSHERS: Mutex<Vec<Flusher>> = Mutex::new(Vec::new());
+// result of starting the thread waiting for signals, started with the first registration
+static LISTENER: OnceLock<Result<(), String>> = OnceLock::new();
+
+// flush the WAL with `flush` when the process is asked to terminate
+pub(crate) fn register(flush: Flusher) -> Result<(), WalError> {
+    LISTENER
+        .get_or_init(|| listen().map_err(|e| e.to_string()))
+        .clone()
+        .map_err(|e| WalError::Unsupported(format!("Failed to handle signals: {}", e)))?;
+    lock().push(flush);
+    Ok(())
+}
+
+fn listen() -> std::io::Result<()> {
+    let mut signals = Signals::new([SIGTERM, SIGINT])?;
+    std::thread::Builder::new()
+        .name("walcraft-signals".to_string())
+        .spawn(move || {
+            if let Some(signal) = signals.forever().next() {
+                flush_all();
+                let _ = signal_hook::low_level::emulate_default_handler(signal);
+            }
+        })?;
+    Ok(())
+}
+
+// flush every registered WAL, even if some fail
+fn flush_all() {
+    for flush in lock().iter() {
+        let _ = flush();
+    }
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Vec<Flusher>> {
+    FLUSHERS.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,113 @@
+use crate::entry::LogEntry;
+use crate::{Lsn, Wal, WalError};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+/// Handle collecting logs of a single task, and adding them to the WAL in batches
+///
+/// Every thread or async task owns its own sink, created with [Wal::sink]. Logs are serialized and
+/// checked when fed, then kept in the sink until [WalSink::flush], or until the sink holds
+/// [WalSink::max_logs] logs or its oldest log waited [WalSink::max_delay] when the next log is
+/// fed. Each flush takes the shared buffer once for the whole batch, which gets a contiguous range
+/// of sequence numbers, instead of once per log. Logs still in the sink are flushed when it's
+/// dropped, ignoring errors.
+///
+/// A sink only flushes when it's used, so a task feeding logs rarely should flush on its own, e.g.
+/// on every tick of a timer.
+///
+/// # Example
+/// ```
+/// use walcraft::Wal;
+///
+/// # std::fs::create_dir_all("./tmp/sink_doc/").unwrap();
+/// let wal: Wal<String> = Wal::new("./tmp/sink_doc/", 500).unwrap();
+/// let mut sink = wal.sink().max_logs(100);
+/// for i in 0..10 {
+///     sink.feed(&format!("request {}", i)).unwrap();
+/// }
+/// let lsn = sink.flush().unwrap();
+/// assert_eq!(lsn.end - lsn.start, 10);
+/// ```
+pub struct WalSink<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    wal: Wal<T>,
+    pending: Vec<LogEntry>,
+    // when the oldest pending log was fed
+    since: Option<Instant>,
+    max_logs: usize,
+    max_delay: Duration,
+}
+
+impl<T> WalSink<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    pub(crate) fn new(wal: Wal<T>) -> Self {
+        Self {
+            wal,
+            pending: Vec::new(),
+            since: None,
+            max_logs: 64,
+            max_delay: Duration::from_millis(5),
+        }
+    }
+
+    /// Flush once this many logs are pending. 64 by default.
+    pub fn max_logs(mut self, logs: usize) -> Self {
+        self.max_logs = logs.max(1);
+        self
+    }
+
+    /// Flush when a log is fed once the oldest pending log waited this long. 5ms by default.
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Add a log to the sink, flushing the pending logs when a limit is reached
+    ///
+    /// A log that can't be serialized or breaks the rules set with [crate::WalBuilder::strict] is
+    /// rejected right away, without affecting the pending logs. An error of the flush leaves the
+    /// logs in the sink.
+    pub fn feed(&mut self, entry: &T) -> Result<(), WalError> {
+        self.wal.check_writable()?;
+        let log = self.wal.encode(entry)?;
+        self.pending.push(log);
+        let since = *self.since.get_or_insert_with(Instant::now);
+        if self.pending.len() >= self.max_logs || since.elapsed() >= self.max_delay {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Add the pending logs to the WAL, returning their sequence numbers
+    ///
+    /// On error, the logs stay in the sink and are added by the next flush.
+    pub fn flush(&mut self) -> Result<Range<Lsn>, WalError> {
+        self.wal.throttle(self.pending.len() as u64)?;
+        let (range, notify) = self.wal.buffer.bulk_add(self.pending.clone())?;
+        if notify {
+            self.wal.notify();
+        }
+        self.pending.clear();
+        self.since = None;
+        Ok(range)
+    }
+
+    /// Number of logs waiting for the next flush
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+impl<T> Drop for WalSink<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    fn drop(&mut self) {
+        let _ = self.flush();
+    }
+}
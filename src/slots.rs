@@ -0,0 +1,347 @@
+use crate::durable;
+use crate::entry::LogEntry;
+use crate::lease::WriterLease;
+use crate::open::OpenDirectory;
+use crate::{Lsn, WalError};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+
+// prefix of the directories of the slots, followed by their id
+const SLOT_PREFIX: &str = "slot_";
+// longest record of the journal, three numbers of up to 20 digits and a slot id with separators
+const RECORD_BYTES: u64 = 3 * 21 + 4;
+// bytes appended to the journal between two drops of the records of logs gone from their files
+const TRIM_BYTES: u64 = 64 * 1024;
+
+// Slot of a WAL written by several processes at once, see [crate::WalBuilder::slots]
+// Every process writes a ring of files of its own in the `slot_<id>` directory it leases, and
+// numbers its logs on its own. The order of the batches of all slots is kept in the `slots`
+// journal of the WAL directory, one `<sequence> <slot> <first lsn> <count>` line per batch,
+// appended under an OS lock on the `slots.lock` file so sequence numbers follow each other
+// whatever the process appending. A handle without a slot of its own only reads them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Slots {
+    root: PathBuf,
+    count: u8,
+    id: Option<u8>,
+}
+
+// Batch of `count` logs of `slot` from `first`, numbered across slots from `sequence`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct SlotRecord {
+    pub sequence: Lsn,
+    pub slot: u8,
+    pub first: Lsn,
+    pub count: u64,
+}
+
+impl SlotRecord {
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.split(' ');
+        let record = Self {
+            sequence: fields.next()?.parse().ok()?,
+            slot: fields.next()?.parse().ok()?,
+            first: fields.next()?.parse().ok()?,
+            count: fields.next()?.parse().ok()?,
+        };
+        fields.next().is_none().then_some(record)
+    }
+
+    fn line(&self) -> String {
+        format!(
+            "{} {} {} {}\n",
+            self.sequence, self.slot, self.first, self.count
+        )
+    }
+
+    // sequence number following the logs of the batch
+    fn next(&self) -> Lsn {
+        self.sequence.saturating_add(self.count)
+    }
+
+    // whether the logs of the batch are older than `oldest`, the oldest log of its slot
+    fn gone(&self, oldest: Option<Lsn>) -> bool {
+        oldest.is_none_or(|oldest| self.first.saturating_add(self.count) <= oldest)
+    }
+}
+
+impl Slots {
+    // Lease the first slot of the WAL at `root` no other handle writes to
+    // The directory of the slot is registered and leased like the directory of any WAL, see
+    // [OpenDirectory] and [WriterLease], and given to the handle opening it.
+    pub fn lease(
+        root: &Path,
+        count: u8,
+    ) -> Result<(Self, PathBuf, OpenDirectory, WriterLease), WalError> {
+        if count == 0 {
+            return Err(WalError::Unsupported(
+                "A WAL shared by processes needs at least 1 slot".to_string(),
+            ));
+        }
+        for id in 1..=count {
+            let location = Self::dir(root, id);
+            std::fs::create_dir_all(&location)
+                .map_err(|e| WalError::io(e, "Failed to create slot directory"))?;
+            let open = match OpenDirectory::register(&location) {
+                Ok(open) => open,
+                Err(WalError::AlreadyOpen(_)) => continue,
+                Err(e) => return Err(e),
+            };
+            match WriterLease::acquire(&location) {
+                Ok(lease) => {
+                    let slots = Self {
+                        root: root.to_path_buf(),
+                        count,
+                        id: Some(id),
+                    };
+                    return Ok((slots, location, open, lease));
+                }
+                Err(WalError::Locked(_)) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(WalError::Locked(format!(
+            "All {} slots of the WAL are written by other handles",
+            count
+        )))
+    }
+
+    // Slots of the WAL at `root` read by a handle writing to none of them
+    pub fn reading(root: &Path, count: u8) -> Self {
+        Self {
+            root: root.to_path_buf(),
+            count,
+            id: None,
+        }
+    }
+
+    // Lease every slot found in the WAL at `root`, failing while a handle writes to one of them
+    pub fn lease_all(root: &Path) -> Result<Vec<(OpenDirectory, WriterLease)>, WalError> {
+        let Ok(entries) = std::fs::read_dir(root) else {
+            return Ok(Vec::new());
+        };
+        let mut leases = Vec::new();
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            if !entry.path().is_dir() || Self::id_of(&name.to_string_lossy()).is_none() {
+                continue;
+            }
+            let open = OpenDirectory::register(&entry.path())?;
+            leases.push((open, WriterLease::acquire(&entry.path())?));
+        }
+        Ok(leases)
+    }
+
+    // directory of the slot `id` of the WAL at `root`
+    pub fn dir(root: &Path, id: u8) -> PathBuf {
+        root.join(format!("{}{}", SLOT_PREFIX, id))
+    }
+
+    // id of the slot written to the directory `name`, None for other directories
+    pub fn id_of(name: &str) -> Option<u8> {
+        name.strip_prefix(SLOT_PREFIX)?.parse().ok()
+    }
+
+    // slot written by the handle, None when it only reads
+    pub fn id(&self) -> Option<u8> {
+        self.id
+    }
+
+    // ids and directories of the slots
+    pub fn dirs(&self) -> impl Iterator<Item = (u8, PathBuf)> + '_ {
+        (1..=self.count).map(|id| (id, Self::dir(&self.root, id)))
+    }
+
+    // Batches of all slots, in the order they were written
+    // A line being appended without the lock is left out.
+    pub fn records(&self) -> Result<Vec<SlotRecord>, WalError> {
+        let journal = match std::fs::read_to_string(self.root.join("slots")) {
+            Ok(journal) => journal,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(WalError::io(e, "Failed to read slots journal")),
+        };
+        let complete = journal.rfind('\n').map_or("", |end| &journal[..end]);
+        Ok(complete.lines().filter_map(SlotRecord::parse).collect())
+    }
+
+    // Number the logs `entries` of every slot across slots, in the order they were written
+    // Records are read after the files, so the files only hold logs already recorded but those
+    // a failure to record kept out, which come last. A slot whose logs were all dropped numbers
+    // them from 0 again, so the newest record of a log wins.
+    pub fn merge(&self, entries: Vec<(u8, LogEntry)>) -> Result<Vec<(Lsn, LogEntry)>, WalError> {
+        let records = self.records()?;
+        let next = records.iter().map(SlotRecord::next).max().unwrap_or(0);
+        let mut recorded: HashMap<u8, BTreeMap<Lsn, SlotRecord>> = HashMap::new();
+        for record in records {
+            recorded
+                .entry(record.slot)
+                .or_default()
+                .insert(record.first, record);
+        }
+        let sequence = |slot: u8, lsn: Lsn| {
+            let (_, record) = recorded.get(&slot)?.range(..=lsn).next_back()?;
+            let offset = lsn - record.first;
+            (offset < record.count).then(|| record.sequence + offset)
+        };
+        let mut numbered = entries
+            .into_iter()
+            .map(|(slot, entry)| (sequence(slot, entry.lsn()), slot, entry))
+            .collect::<Vec<_>>();
+        numbered.sort_by_key(|(sequence, slot, entry)| {
+            (sequence.is_none(), *sequence, *slot, entry.lsn())
+        });
+        let mut unrecorded = next;
+        let merged = numbered
+            .into_iter()
+            .map(|(sequence, _, entry)| {
+                let sequence = sequence.unwrap_or_else(|| {
+                    unrecorded += 1;
+                    unrecorded - 1
+                });
+                (sequence, entry)
+            })
+            .collect();
+        Ok(merged)
+    }
+
+    // Number the logs `lsns` of the slot after the logs of every slot recorded so far
+    // Once enough was appended since, the records of logs gone from the files of their slot are
+    // dropped, `oldest` giving the oldest log still in the directory of a slot.
+    pub fn record(
+        &self,
+        lsns: RangeInclusive<Lsn>,
+        oldest: impl Fn(&Path) -> Option<Lsn>,
+    ) -> Result<(), WalError> {
+        let Some(id) = self.id else {
+            return Ok(());
+        };
+        let _lock = self.lock()?;
+        let path = self.root.join("slots");
+        let mut journal = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&path)
+            .map_err(|e| WalError::io(e, "Failed to open slots journal"))?;
+        let (end, last) = Self::tail(&mut journal)
+            .map_err(|e| WalError::io(e, "Failed to read slots journal"))?;
+        let record = SlotRecord {
+            sequence: last.map_or(0, |last| last.next()),
+            slot: id,
+            first: *lsns.start(),
+            count: lsns.end().saturating_sub(*lsns.start()) + 1,
+        };
+        let line = record.line();
+        // a line torn by a crash is written over
+        journal
+            .set_len(end)
+            .and_then(|_| journal.seek(SeekFrom::Start(end)))
+            .and_then(|_| journal.write_all(line.as_bytes()))
+            .map_err(|e| WalError::io(e, "Failed to write slots journal"))?;
+        if end / TRIM_BYTES != (end + line.len() as u64) / TRIM_BYTES {
+            drop(journal);
+            self.trim(oldest)?;
+        }
+        Ok(())
+    }
+
+    // Drop the records of the logs gone from the files of their slot, but the last one, which
+    // the next sequence number follows
+    fn trim(&self, oldest: impl Fn(&Path) -> Option<Lsn>) -> Result<(), WalError> {
+        let records = self.records()?;
+        let Some((last, records)) = records.split_last() else {
+            return Ok(());
+        };
+        let oldest = self
+            .dirs()
+            .map(|(id, dir)| (id, oldest(&dir)))
+            .collect::<BTreeMap<_, _>>();
+        let mut journal = String::new();
+        for record in records.iter().chain([last]) {
+            let gone = record.gone(oldest.get(&record.slot).copied().flatten());
+            if gone && record != last {
+                continue;
+            }
+            journal.push_str(&record.line());
+        }
+        durable::write_atomic(
+            &self.root.join("slots"),
+            &self.root.join("slots.tmp"),
+            journal.as_bytes(),
+        )
+        .map_err(|e| WalError::io(e, "Failed to trim slots journal"))
+    }
+
+    // Exclusive right to append to the journal, released when dropped
+    fn lock(&self) -> Result<File, WalError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.root.join("slots.lock"))
+            .map_err(|e| WalError::io(e, "Failed to open slots lock file"))?;
+        file.lock()
+            .map_err(|e| WalError::io(e, "Failed to lock slots lock file"))?;
+        Ok(file)
+    }
+
+    // End of the last complete line of the journal, and the record it holds
+    fn tail(journal: &mut File) -> std::io::Result<(u64, Option<SlotRecord>)> {
+        let len = journal.metadata()?.len();
+        let from = len.saturating_sub(2 * RECORD_BYTES);
+        let mut tail = String::new();
+        journal.seek(SeekFrom::Start(from))?;
+        journal.read_to_string(&mut tail)?;
+        let Some(end) = tail.rfind('\n') else {
+            return Ok((from, None));
+        };
+        let line = tail[..end].rsplit('\n').next().unwrap_or_default();
+        Ok((from + end as u64 + 1, SlotRecord::parse(line)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn journal() {
+        let root = Path::new("./tmp/slots_journal/");
+        let _ = std::fs::remove_dir_all(root);
+        let (first, _, _open, _lease) = Slots::lease(root, 2).unwrap();
+        let (second, _, _second_open, _second_lease) = Slots::lease(root, 2).unwrap();
+        assert_eq!((first.id(), second.id()), (Some(1), Some(2)));
+        assert!(matches!(Slots::lease(root, 2), Err(WalError::Locked(_))));
+
+        first.record(0..=2, |_| Some(0)).unwrap();
+        second.record(0..=0, |_| Some(0)).unwrap();
+        // a line torn by a crash is written over by the next one
+        let mut journal = OpenOptions::new()
+            .append(true)
+            .open(root.join("slots"))
+            .unwrap();
+        journal.write_all(b"4 2 1").unwrap();
+        assert_eq!(second.records().unwrap().len(), 2);
+        first.record(3..=3, |_| Some(0)).unwrap();
+        let records = Slots::reading(root, 2).records().unwrap();
+        let numbered = records
+            .iter()
+            .map(|r| (r.sequence, r.slot, r.first, r.count))
+            .collect::<Vec<_>>();
+        assert_eq!(numbered, [(0, 1, 0, 3), (3, 2, 0, 1), (4, 1, 3, 1)]);
+
+        // records of logs gone from their slot are dropped, but the last one
+        first
+            .trim(|dir| (dir == Slots::dir(root, 1)).then_some(3))
+            .unwrap();
+        let records = first.records().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].sequence, 4);
+        second.record(1..=1, |_| None).unwrap();
+        assert_eq!(first.records().unwrap()[1].sequence, 5);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::checksum::crc32;
+use crate::layout::Layout;
+use crate::meta::Meta;
+use crate::segment::SegmentSpan;
+use crate::storage::Opened;
+use crate::{LogEntry, Lsn, WalError};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::time::SystemTime;
+
+// WAL files and pending logs as they were at one instant
+// Files are held open, so the content they had stays readable while the writer appends to them,
+// or replaces them when it recycles, compacts or truncates files. Readers never park the writer.
+// Files of a custom storage are read whole when the snapshot is taken.
+pub(crate) struct Snapshot {
+    // file being written
+    pub pointer: u8,
+    // every existing WAL file, along with its version
+    files: Vec<(u8, Opened, FileVersion)>,
+    // logs accepted but not yet written to a file
+    pub pending: Vec<LogEntry>,
+    // time ranges of the logs of sealed files, as recorded in meta
+    pub spans: Vec<SegmentSpan>,
+}
+
+impl Snapshot {
+    // Must be taken while holding the flush guard, so no log is on its way from the buffer to a
+    // file and every log is either in `pending` or within the size of a file
+    // `meta` is read by the caller, which falls back on the WAL files when it is damaged
+    pub fn take(
+        location: &Path,
+        layout: &Layout,
+        meta: Meta,
+        pending: Vec<LogEntry>,
+    ) -> Result<Self, WalError> {
+        let files = layout
+            .ids()
+            .filter_map(|id| {
+                let path = layout.path(location, id);
+                let opened = layout.storage.open(&path).ok()??;
+                let version = match &opened {
+                    Opened::File(_, size) => (*size, layout.storage.times(&path).1, None),
+                    Opened::Content(content) => {
+                        (opened.size(), None, Some(crc32(content.get_ref())))
+                    }
+                };
+                Some((id, opened, version))
+            })
+            .collect();
+        Ok(Self {
+            pointer: meta.pointer,
+            files,
+            pending,
+            spans: meta.spans,
+        })
+    }
+
+    // file `id` positioned at its start, along with the bytes it held, None if it didn't exist
+    pub fn open(&self, id: u8) -> Result<Option<Opened>, WalError> {
+        let opened = match self.files.iter().find(|(i, ..)| *i == id) {
+            Some((_, opened, _)) => opened,
+            None => return Ok(None),
+        };
+        // clones share their position, so every read starts over
+        let opened = opened
+            .try_clone()
+            .map_err(|e| WalError::io(e, "Failed to read log file"))?;
+        Ok(Some(opened))
+    }
+
+    // What the snapshot holds, equal for two snapshots only if they hold the same logs
+    pub fn version(&self) -> SnapshotVersion {
+        SnapshotVersion {
+            pointer: self.pointer,
+            files: self
+                .files
+                .iter()
+                .map(|(id, _, version)| (*id, *version))
+                .collect(),
+            pending: self
+                .pending
+                .first()
+                .zip(self.pending.last())
+                .map(|(first, last)| first.lsn()..=last.lsn()),
+        }
+    }
+}
+
+// Size of a file, along with when it was last modified, or the checksum of its content when the
+// storage doesn't tell
+type FileVersion = (u64, Option<SystemTime>, Option<u32>);
+
+// Versions of the files of a snapshot, and its pending logs
+// Files only change by growing or being replaced, so the same sizes and times mean the same logs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SnapshotVersion {
+    pointer: u8,
+    files: Vec<(u8, FileVersion)>,
+    pending: Option<RangeInclusive<Lsn>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meta::Meta;
+    use std::io::Write;
+
+    #[test]
+    fn replaced_files() {
+        let dir = Path::new("./tmp/snapshot_replaced/");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+        let layout = Layout::default();
+        let meta = Meta {
+            pointer: 1,
+            offset: None,
+            segments: None,
+            format: None,
+            digests: Vec::new(),
+            spans: Vec::new(),
+            chain: None,
+        };
+        meta.write(dir).unwrap();
+        std::fs::write(dir.join("wal_1"), b"sealed").unwrap();
+        let snapshot = Snapshot::take(dir, &layout, meta, Vec::new()).unwrap();
+        assert!(snapshot.open(2).unwrap().is_none());
+
+        // appended and replaced files keep the content they had
+        std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.join("wal_1"))
+            .and_then(|mut f| f.write_all(b" and more"))
+            .unwrap();
+        std::fs::write(dir.join("wal_1.recycle"), b"").unwrap();
+        std::fs::rename(dir.join("wal_1.recycle"), dir.join("wal_1")).unwrap();
+        for _ in 0..2 {
+            let mut content = Vec::new();
+            snapshot
+                .open(1)
+                .unwrap()
+                .unwrap()
+                .read_into(&mut content)
+                .unwrap();
+            assert_eq!(content, b"sealed");
+        }
+    }
+}
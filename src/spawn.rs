@@ -0,0 +1,151 @@
+use crate::fork::ForkGuard;
+use crate::WalError;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread::Thread;
+
+// Closure running the writer loop on a thread chosen by the application
+pub(crate) type Spawner =
+    Arc<dyn Fn(Box<dyn FnOnce() + Send>) -> std::io::Result<()> + Send + Sync>;
+
+// Handle to the writer thread, however it was spawned, as seen by the handles of one WAL
+// The thread serves the topics of its WAL too, and a WAL is done with the thread once its writer
+// is closed, or once the thread returned.
+#[derive(Clone)]
+pub(crate) struct WriterHandle {
+    thread: Thread,
+    finished: Arc<Finish>,
+    closed: Arc<AtomicBool>,
+    // the writer of this WAL panicked and wasn't restarted, while the thread may go on
+    failed: Arc<AtomicBool>,
+}
+
+// Whether the writer has returned, read without locking by every write, and a condition to wait
+// for it
+#[derive(Default)]
+struct Finish {
+    flag: AtomicBool,
+    lock: Mutex<()>,
+    done: Condvar,
+}
+
+impl WriterHandle {
+    // run `body` with the given spawner, or on a new thread by default
+    // Returns once `body` has started, so its thread is known
+    pub fn spawn<F>(spawner: Option<&Spawner>, body: F) -> Result<Self, WalError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        let finished = Arc::new(Finish::default());
+        let guard = Finished(finished.clone());
+        let task = Box::new(move || {
+            // marks the writer as finished even if it panics
+            let _guard = guard;
+            let _ = tx.send(std::thread::current());
+            body();
+        });
+        let spawned = match spawner {
+            Some(spawn) => spawn(task),
+            None => std::thread::Builder::new().spawn(task).map(|_| ()),
+        };
+        spawned.map_err(|e| WalError::io(e, "Failed to spawn writer thread"))?;
+        let thread = rx
+            .recv()
+            .map_err(|_| WalError::File("Writer thread was never started".to_string()))?;
+        Ok(Self {
+            thread,
+            finished,
+            closed: Arc::default(),
+            failed: Arc::default(),
+        })
+    }
+
+    // handle to the same thread for another WAL served by it
+    pub fn topic(&self) -> Self {
+        Self {
+            thread: self.thread.clone(),
+            finished: self.finished.clone(),
+            closed: Arc::default(),
+            failed: Arc::default(),
+        }
+    }
+
+    // mark the writer of this WAL as done, once it wrote what was left in the buffer
+    pub fn close(&self) {
+        self.finish(&self.closed);
+    }
+
+    // mark the writer of this WAL as dead, after it panicked
+    pub fn fail(&self) {
+        self.finish(&self.failed);
+    }
+
+    fn finish(&self, flag: &AtomicBool) {
+        // set under the lock, so a waiter either sees it or is woken up
+        let lock = self.finished.lock.lock().unwrap_or_else(|e| e.into_inner());
+        flag.store(true, Ordering::Release);
+        drop(lock);
+        self.finished.done.notify_all();
+    }
+
+    // whether the writer of this WAL was closed, while the thread may still serve other WALs
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    // whether the writer of this WAL is closed or panicked, or the thread has returned or
+    // panicked
+    pub fn is_finished(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+            || self.failed.load(Ordering::Acquire)
+            || self.finished.flag.load(Ordering::Acquire)
+    }
+
+    // block until the writer of this WAL is closed or panicked, or the thread has returned or
+    // panicked
+    pub fn wait(&self) {
+        let lock = self.finished.lock.lock().unwrap_or_else(|e| e.into_inner());
+        drop(
+            self.finished
+                .done
+                .wait_while(lock, |_| !self.is_finished())
+                .unwrap_or_else(|e| e.into_inner()),
+        );
+    }
+}
+
+// Waits for the writer to write the logs left in the buffer once all handles are dropped
+// Every handle holds it after the sender of signals, which is dropped first and tells the thread
+// to close the writer of the WAL.
+pub(crate) struct Shutdown {
+    pub writer: Option<WriterHandle>,
+    pub fork: ForkGuard,
+}
+
+impl Drop for Shutdown {
+    fn drop(&mut self) {
+        let writer = match &self.writer {
+            Some(writer) => writer,
+            None => return,
+        };
+        // a forked process has no writer, and the writer can't wait for itself, e.g. when a
+        // callback it runs holds the last handle
+        if self.fork.forked() || std::thread::current().id() == writer.thread.id() {
+            return;
+        }
+        writer.wait();
+    }
+}
+
+struct Finished(Arc<Finish>);
+
+impl Drop for Finished {
+    fn drop(&mut self) {
+        // set under the lock, so a waiter either sees it or is woken up
+        let lock = self.0.lock.lock().unwrap_or_else(|e| e.into_inner());
+        self.0.flag.store(true, Ordering::Release);
+        drop(lock);
+        self.0.done.notify_all();
+    }
+}
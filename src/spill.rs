@@ -0,0 +1,160 @@
+use crate::decode::Decoder;
+use crate::entry::LogEntry;
+use crate::WalError;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// counter to keep names of spill files unique within the process
+static SPILL_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Logs returned by [crate::Wal::read_bounded]
+///
+/// The logs are either held in memory or, when they didn't fit in the configured read memory
+/// cap, stored in a temporary file and decoded one by one while iterating. The temporary file is
+/// deleted when this value is dropped.
+pub struct BoundedRead<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    inner: Inner<T>,
+}
+
+enum Inner<T> {
+    Memory(std::vec::IntoIter<T>),
+    Spilled(SpillFile<T>),
+}
+
+impl<T> BoundedRead<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    pub(crate) fn memory(entries: Vec<LogEntry>, decoder: &Decoder<T>) -> Self {
+        let data = entries
+            .into_iter()
+            .filter_map(|e| decoder.decode(e))
+            .collect::<Vec<_>>();
+        Self {
+            inner: Inner::Memory(data.into_iter()),
+        }
+    }
+
+    pub(crate) fn spilled(writer: SpillWriter, decoder: Decoder<T>) -> Result<Self, WalError> {
+        Ok(Self {
+            inner: Inner::Spilled(writer.finish(decoder)?),
+        })
+    }
+
+    /// Whether the logs were spilled to a temporary file
+    pub fn is_spilled(&self) -> bool {
+        matches!(self.inner, Inner::Spilled(_))
+    }
+}
+
+impl<T> Iterator for BoundedRead<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.inner {
+            Inner::Memory(iter) => iter.next(),
+            Inner::Spilled(file) => file.next(),
+        }
+    }
+}
+
+// Temporary file receiving serialized logs during a bounded read
+pub(crate) struct SpillWriter {
+    path: PathBuf,
+    file: Option<BufWriter<File>>,
+}
+
+impl SpillWriter {
+    pub fn new() -> Result<Self, WalError> {
+        let mut path = std::env::temp_dir();
+        path.push(format!(
+            "walcraft_spill_{}_{}",
+            std::process::id(),
+            SPILL_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file =
+            File::create(&path).map_err(|e| WalError::io(e, "Failed to create spill file"))?;
+        Ok(Self {
+            path,
+            file: Some(BufWriter::new(file)),
+        })
+    }
+
+    pub fn push(&mut self, entry: &LogEntry) -> Result<(), WalError> {
+        let payload = entry.payload();
+        let file = self
+            .file
+            .as_mut()
+            .expect("spill file is open while writing");
+        file.write_all(&(payload.len() as u64).to_ne_bytes())
+            .and_then(|_| file.write_all(payload))
+            .map_err(|e| WalError::io(e, "Failed to write spill file"))
+    }
+
+    fn finish<T>(mut self, decoder: Decoder<T>) -> Result<SpillFile<T>, WalError> {
+        let mut file = self.file.take().expect("spill file is finished only once");
+        file.flush()
+            .map_err(|e| WalError::io(e, "Failed to write spill file"))?;
+        drop(file);
+        let file = OpenOptions::new()
+            .read(true)
+            .open(&self.path)
+            .map_err(|e| WalError::io(e, "Failed to open spill file"))?;
+        Ok(SpillFile {
+            path: std::mem::take(&mut self.path),
+            reader: BufReader::new(file),
+            decoder,
+        })
+    }
+}
+
+impl Drop for SpillWriter {
+    fn drop(&mut self) {
+        // the file was handed over to a reader
+        if self.path.as_os_str().is_empty() {
+            return;
+        }
+        self.file.take();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// Temporary file of serialized logs, decoded lazily
+struct SpillFile<T> {
+    path: PathBuf,
+    reader: BufReader<File>,
+    decoder: Decoder<T>,
+}
+
+impl<T> SpillFile<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    fn next(&mut self) -> Option<T> {
+        loop {
+            let mut size = [0u8; 8];
+            self.reader.read_exact(&mut size).ok()?;
+            let mut payload = vec![0u8; u64::from_ne_bytes(size) as usize];
+            self.reader.read_exact(&mut payload).ok()?;
+            // skip logs that cannot be decoded, same as regular reads
+            if let Some(d) = self.decoder.decode_payload(&payload) {
+                return Some(d);
+            }
+        }
+    }
+}
+
+impl<T> Drop for SpillFile<T> {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,552 @@
+use crate::{BufferDepth, Lsn};
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Statistics of a WAL, as reported by [crate::Wal::stats]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct WalStats {
+    /// Time from adding a log to the buffer until it's written to a file, and synced unless the
+    /// [crate::SyncPolicy] is `Never`
+    pub write_latency: LatencyStats,
+    /// Sealed files checked against their digest since the WAL was opened, see
+    /// [crate::OpenVerification]
+    pub verified_files: u64,
+    /// Sealed files still to be checked
+    pub unverified_files: u64,
+}
+
+/// Move of the writer thread to the next file, as reported by [crate::Wal::rotation_history]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RotationRecord {
+    /// When the writer moved on
+    pub at: SystemTime,
+    /// File left behind, i.e. `N` in `wal_N`
+    pub segment: u8,
+    /// Number of logs in the file left behind
+    pub entries: u64,
+    /// Size of the file left behind in bytes
+    pub bytes: u64,
+    /// Time since the previous rotation, or since the WAL was opened for the first one
+    pub since_previous: Duration,
+}
+
+/// Recent rotations of a WAL and the number of logs of the files left behind, as reported by
+/// [crate::Wal::rotation_history]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RotationHistory {
+    /// The last [RotationHistory::MAX_ROTATIONS] rotations since the WAL was opened, oldest first
+    pub rotations: Vec<RotationRecord>,
+    /// Number of files left behind since the WAL was opened, by the number of logs they hold:
+    /// entry `i` counts the files of at least 2^(i-1) and fewer than 2^i logs, and the first
+    /// entry counts the files left empty
+    pub entries_per_segment: Vec<u64>,
+}
+
+impl RotationHistory {
+    /// Number of rotations kept in the history
+    pub const MAX_ROTATIONS: usize = 64;
+
+    pub(crate) fn record(&mut self, rotation: RotationRecord) {
+        if self.rotations.len() == Self::MAX_ROTATIONS {
+            self.rotations.remove(0);
+        }
+        self.rotations.push(rotation);
+        let bucket = (u64::BITS - rotation.entries.leading_zeros()) as usize;
+        if self.entries_per_segment.len() <= bucket {
+            self.entries_per_segment.resize(bucket + 1, 0);
+        }
+        self.entries_per_segment[bucket] += 1;
+    }
+}
+
+/// Cost of a single read, as reported by [crate::Wal::last_read]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct ReadMetrics {
+    /// WAL files opened by the read
+    pub segments: u64,
+    /// Bytes read from those files
+    pub bytes: u64,
+    /// Frames of logs parsed from those bytes
+    pub frames: u64,
+    /// Frames skipped as they failed their checksum, see [crate::WalBuilder::checksums]
+    pub corrupted: u64,
+    /// Time spent reading the files, waiting for other reads included, but not deserializing logs
+    pub duration: Duration,
+}
+
+// Counters of the files read by a single reader, behind [ReadMetrics]
+#[derive(Debug, Default)]
+pub(crate) struct ReadTally {
+    segments: RefCell<HashSet<PathBuf>>,
+    bytes: Cell<u64>,
+    frames: Cell<u64>,
+    corrupted: Cell<u64>,
+}
+
+impl ReadTally {
+    // the file at `path` was opened, counted once however many times it's opened
+    pub fn opened(&self, path: &Path) {
+        self.segments.borrow_mut().insert(path.to_path_buf());
+    }
+
+    pub fn read(&self, bytes: usize) {
+        self.bytes.set(self.bytes.get() + bytes as u64);
+    }
+
+    pub fn parsed(&self, frames: usize) {
+        self.frames.set(self.frames.get() + frames as u64);
+    }
+
+    // a frame failing its checksum was skipped
+    pub fn corrupted(&self) {
+        self.corrupted.set(self.corrupted.get() + 1);
+    }
+
+    pub fn metrics(&self, duration: Duration) -> ReadMetrics {
+        ReadMetrics {
+            segments: self.segments.borrow().len() as u64,
+            bytes: self.bytes.get(),
+            frames: self.frames.get(),
+            corrupted: self.corrupted.get(),
+            duration,
+        }
+    }
+}
+
+/// Counters of the activity of a WAL since it was opened, as reported by [crate::Wal::metrics]
+///
+/// Counters only grow, so the rate of any of them is the difference between two snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct WalMetrics {
+    /// Logs written to a file by the writer thread
+    pub entries_written: u64,
+    /// Bytes of the frames of those logs
+    pub bytes_written: u64,
+    /// Syncs of the current file to storage, by [crate::Wal::flush] or the [crate::SyncPolicy]
+    pub flushes: u64,
+    /// Moves of the writer thread to the next file
+    pub rotations: u64,
+    /// Logs waiting in the buffer for the writer thread
+    pub buffer_depth: BufferDepth,
+    /// Reads of the WAL through any handle
+    pub reads: u64,
+    /// Time the last sync to storage took, None before the first one
+    pub last_sync_latency: Option<Duration>,
+}
+
+/// State of the WAL files, as reported by [crate::Wal::status]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct WalStatus {
+    /// Id of the file being written
+    pub current_file: u8,
+    /// Every existing WAL file, from the oldest to the current one
+    pub files: Vec<FileStatus>,
+    /// Bytes of all WAL files on storage
+    pub disk_bytes: u64,
+    /// Sequence numbers of the oldest and the newest logs, None when the WAL holds no log
+    pub lsns: Option<RangeInclusive<Lsn>>,
+    /// When the oldest and the newest logs were written, None unless both are stamped, see
+    /// [crate::WalBuilder::timestamps]
+    pub times: Option<RangeInclusive<SystemTime>>,
+    /// Time until the current file is full and the writer moves on to the next one, at the rate
+    /// logs were written recently, None until logs were written through this instance
+    pub until_rotation: Option<Duration>,
+}
+
+/// WAL file as reported by [crate::Wal::status]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct FileStatus {
+    /// Id of the file, as in its name `wal_<id>`
+    pub id: u8,
+    /// Size of the file on storage
+    pub bytes: u64,
+}
+
+// Counters behind [WalMetrics], shared by the handles and the writer thread
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    entries: AtomicU64,
+    bytes: AtomicU64,
+    flushes: AtomicU64,
+    rotations: AtomicU64,
+    reads: AtomicU64,
+    // nanoseconds the last sync took plus one, zero before the first sync
+    last_sync: AtomicU64,
+    // bytes the current file takes before the writer moves on plus one, zero until known
+    room: AtomicU64,
+    // bytes written lately
+    rate: Mutex<WriteRate>,
+}
+
+impl Metrics {
+    pub fn written(&self, entries: u64, bytes: u64) {
+        self.entries.fetch_add(entries, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+        let mut rate = self.rate.lock().unwrap_or_else(|e| e.into_inner());
+        rate.add(bytes, Instant::now());
+    }
+
+    // the current file takes `bytes` more before the writer moves on to the next one
+    pub fn room(&self, bytes: u64) {
+        self.room.store(bytes.saturating_add(1), Ordering::Relaxed);
+    }
+
+    // time until the current file is full at the recent rate of writes
+    pub fn until_rotation(&self) -> Option<Duration> {
+        let room = self.room.load(Ordering::Relaxed).checked_sub(1)?;
+        let rate = self.rate.lock().unwrap_or_else(|e| e.into_inner());
+        let per_sec = rate.per_sec(Instant::now()).filter(|r| *r > 0.0)?;
+        Some(Duration::from_secs_f64(room as f64 / per_sec))
+    }
+
+    pub fn synced(&self, latency: Duration) {
+        self.flushes.fetch_add(1, Ordering::Relaxed);
+        let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX - 1);
+        self.last_sync.store(nanos + 1, Ordering::Relaxed);
+    }
+
+    pub fn rotated(&self) {
+        self.rotations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn read(&self) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self, buffer_depth: BufferDepth) -> WalMetrics {
+        let last_sync = self.last_sync.load(Ordering::Relaxed);
+        WalMetrics {
+            entries_written: self.entries.load(Ordering::Relaxed),
+            bytes_written: self.bytes.load(Ordering::Relaxed),
+            flushes: self.flushes.load(Ordering::Relaxed),
+            rotations: self.rotations.load(Ordering::Relaxed),
+            buffer_depth,
+            reads: self.reads.load(Ordering::Relaxed),
+            last_sync_latency: last_sync.checked_sub(1).map(Duration::from_nanos),
+        }
+    }
+}
+
+// Bytes written per second over about the last [WriteRate::WINDOW]
+// Bytes are counted in consecutive windows, and the rate of the previous window stands in for
+// the part of the last window not elapsed yet.
+#[derive(Debug, Default)]
+struct WriteRate {
+    // start of the current window, None before the first write
+    started: Option<Instant>,
+    // bytes written in the current window
+    bytes: u64,
+    // rate of the previous window
+    previous: Option<f64>,
+}
+
+impl WriteRate {
+    const WINDOW: Duration = Duration::from_secs(10);
+
+    fn add(&mut self, bytes: u64, now: Instant) {
+        let started = *self.started.get_or_insert(now);
+        let elapsed = now.saturating_duration_since(started);
+        if elapsed >= Self::WINDOW {
+            self.previous = Some(self.bytes as f64 / elapsed.as_secs_f64());
+            self.started = Some(now);
+            self.bytes = 0;
+        }
+        self.bytes += bytes;
+    }
+
+    fn per_sec(&self, now: Instant) -> Option<f64> {
+        let elapsed = now.saturating_duration_since(self.started?);
+        let window = Self::WINDOW.as_secs_f64();
+        match (self.previous, elapsed.as_secs_f64()) {
+            (_, elapsed) if elapsed >= window => Some(self.bytes as f64 / elapsed),
+            (Some(previous), elapsed) => {
+                Some((previous * (window - elapsed) + self.bytes as f64) / window)
+            }
+            // the first window started with a write, so it's measured from the next one
+            (None, elapsed) => (elapsed > 0.0).then(|| self.bytes as f64 / elapsed),
+        }
+    }
+}
+
+// Run `sync`, recording how long it took once it succeeded
+pub(crate) fn timed_sync(
+    metrics: &Metrics,
+    sync: impl FnOnce() -> std::io::Result<()>,
+) -> std::io::Result<()> {
+    let _span = span!("walcraft.sync");
+    let started = Instant::now();
+    sync()?;
+    metrics.synced(started.elapsed());
+    Ok(())
+}
+
+/// Percentiles of a latency, measured on a sample of logs
+///
+/// Latencies are bucketed by powers of two of microseconds, so percentiles are upper bounds off
+/// by at most a factor of two. All durations are zero until a log is sampled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub struct LatencyStats {
+    /// Number of sampled logs
+    pub samples: u64,
+    /// Median latency
+    pub p50: Duration,
+    /// Latency of the 90th percentile
+    pub p90: Duration,
+    /// Latency of the 99th percentile
+    pub p99: Duration,
+    /// Highest sampled latency
+    pub max: Duration,
+}
+
+/// Logs written through the handles sharing a label, as reported by [crate::Wal::handle_stats]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct HandleStats {
+    /// Label set with [crate::Wal::labeled], None for the handles without a label
+    pub label: Option<String>,
+    /// Number of logs accepted
+    pub entries: u64,
+    /// Serialized payload of the accepted logs in bytes
+    pub bytes: u64,
+    /// Number of logs rejected, as they couldn't be serialized, broke the rules of the WAL,
+    /// were throttled or didn't fit in the memory budget
+    pub errors: u64,
+}
+
+// Counters of the logs written through the handles sharing a label
+#[derive(Debug, Default)]
+pub(crate) struct HandleCounters {
+    label: Option<String>,
+    entries: AtomicU64,
+    bytes: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl HandleCounters {
+    pub fn accepted(&self, entries: u64, bytes: u64) {
+        self.entries.fetch_add(entries, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn rejected(&self, entries: u64) {
+        self.errors.fetch_add(entries, Ordering::Relaxed);
+    }
+
+    fn stats(&self) -> HandleStats {
+        HandleStats {
+            label: self.label.clone(),
+            entries: self.entries.load(Ordering::Relaxed),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Counters of every label of a WAL, shared by all its handles
+#[derive(Debug, Clone, Default)]
+pub(crate) struct HandleRegistry {
+    labels: Arc<Mutex<Vec<Arc<HandleCounters>>>>,
+}
+
+impl HandleRegistry {
+    // counters of the handles labeled `label`, created on first use
+    pub fn counters(&self, label: Option<&str>) -> Arc<HandleCounters> {
+        let mut labels = self.labels.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(counters) = labels.iter().find(|c| c.label.as_deref() == label) {
+            return counters.clone();
+        }
+        let counters = Arc::new(HandleCounters {
+            label: label.map(String::from),
+            ..HandleCounters::default()
+        });
+        labels.push(counters.clone());
+        counters
+    }
+
+    // stats of every label, in the order labels were first used
+    pub fn stats(&self) -> Vec<HandleStats> {
+        let labels = self.labels.lock().unwrap_or_else(|e| e.into_inner());
+        labels.iter().map(|c| c.stats()).collect()
+    }
+}
+
+// every n-th log is sampled
+pub(crate) const LATENCY_SAMPLING: u64 = 16;
+// bucket `i` holds latencies below 2^i microseconds, the last one holds everything above
+const BUCKETS: usize = 32;
+
+// Histogram of latencies with buckets growing by powers of two
+#[derive(Debug, Default)]
+pub(crate) struct LatencyHistogram {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    max: Duration,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let micros = latency.as_micros() as u64;
+        let bucket = (u64::BITS - micros.leading_zeros()) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)] += 1;
+        self.count += 1;
+        self.max = self.max.max(latency);
+    }
+
+    // upper bound of the latency below which `p` of the samples fall
+    fn percentile(&self, p: f64) -> Duration {
+        let rank = ((self.count as f64 * p).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return Duration::from_micros(1 << i).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    pub fn stats(&self) -> LatencyStats {
+        if self.count == 0 {
+            return LatencyStats::default();
+        }
+        LatencyStats {
+            samples: self.count,
+            p50: self.percentile(0.5),
+            p90: self.percentile(0.9),
+            p99: self.percentile(0.99),
+            max: self.max,
+        }
+    }
+}
+
+// Record the time logs added to the buffer at `sampled` took to be written
+pub(crate) fn record_latency(latency: &Mutex<LatencyHistogram>, sampled: &[Instant]) {
+    if sampled.is_empty() {
+        return;
+    }
+    let mut latency = latency.lock().unwrap_or_else(|e| e.into_inner());
+    for added in sampled {
+        latency.record(added.elapsed());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.stats(), LatencyStats::default());
+        for micros in 1..=100 {
+            histogram.record(Duration::from_micros(micros));
+        }
+        let stats = histogram.stats();
+        assert_eq!(stats.samples, 100);
+        assert_eq!(stats.max, Duration::from_micros(100));
+        // 50us falls in the bucket below 64us, 90us and 99us in the last one
+        assert_eq!(stats.p50, Duration::from_micros(64));
+        assert_eq!(stats.p90, Duration::from_micros(100));
+        assert_eq!(stats.p99, Duration::from_micros(100));
+        assert!(stats.p50 >= Duration::from_micros(50));
+    }
+
+    fn rotation(entries: u64) -> RotationRecord {
+        RotationRecord {
+            at: SystemTime::now(),
+            segment: 1,
+            entries,
+            bytes: entries * 10,
+            since_previous: Duration::ZERO,
+        }
+    }
+
+    #[test]
+    fn rotation_history() {
+        let mut history = RotationHistory::default();
+        for entries in [0, 1, 3, 4, 7, 100] {
+            history.record(rotation(entries));
+        }
+        assert_eq!(history.entries_per_segment, vec![1, 1, 1, 2, 0, 0, 0, 1]);
+        for _ in 0..RotationHistory::MAX_ROTATIONS {
+            history.record(rotation(2));
+        }
+        // only the last rotations are kept, but every file is counted
+        assert_eq!(history.rotations.len(), RotationHistory::MAX_ROTATIONS);
+        assert_eq!(history.entries_per_segment.iter().sum::<u64>(), 70);
+    }
+
+    #[test]
+    fn metrics() {
+        let metrics = Metrics::default();
+        assert_eq!(
+            metrics.snapshot(BufferDepth::default()),
+            WalMetrics::default()
+        );
+        metrics.written(3, 120);
+        metrics.written(1, 40);
+        metrics.synced(Duration::ZERO);
+        metrics.rotated();
+        metrics.read();
+        let snapshot = metrics.snapshot(BufferDepth::default());
+        assert_eq!((snapshot.entries_written, snapshot.bytes_written), (4, 160));
+        assert_eq!(
+            (snapshot.flushes, snapshot.rotations, snapshot.reads),
+            (1, 1, 1)
+        );
+        // a sync too fast to measure is still told apart from no sync
+        assert_eq!(snapshot.last_sync_latency, Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn write_rate() {
+        let start = Instant::now();
+        let mut rate = WriteRate::default();
+        assert_eq!(rate.per_sec(start), None);
+        rate.add(100, start);
+        rate.add(100, start + Duration::from_secs(2));
+        assert_eq!(rate.per_sec(start + Duration::from_secs(4)), Some(50.0));
+        // the previous window covers the start of the next one
+        rate.add(300, start + Duration::from_secs(10));
+        assert_eq!(rate.per_sec(start + Duration::from_secs(15)), Some(40.0));
+        // and the rate drops once writes stop
+        assert_eq!(rate.per_sec(start + Duration::from_secs(40)), Some(10.0));
+
+        let metrics = Metrics::default();
+        assert_eq!(metrics.until_rotation(), None);
+        metrics.room(1000);
+        metrics.written(1, 100);
+        assert!(metrics.until_rotation().is_some());
+    }
+
+    #[test]
+    fn handle_counters() {
+        let registry = HandleRegistry::default();
+        let unlabeled = registry.counters(None);
+        let ingest = registry.counters(Some("ingest"));
+        unlabeled.accepted(1, 10);
+        ingest.accepted(2, 30);
+        registry.counters(Some("ingest")).rejected(1);
+        let stats = registry.stats();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].entries, 1);
+        assert_eq!(stats[1].label.as_deref(), Some("ingest"));
+        assert_eq!(
+            (stats[1].entries, stats[1].bytes, stats[1].errors),
+            (2, 30, 1)
+        );
+    }
+}
@@ -0,0 +1,522 @@
+use crate::durable;
+use crate::flush::SegmentFile;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// Storage of the WAL files holding logs, see [crate::WalBuilder::storage]
+///
+/// Files are named by their path: the directory of the WAL, or the cold location of
+/// [crate::WalBuilder::cold_storage], joined with the name of the file such as `wal_3`. The
+/// writer thread appends frames to one file at a time and syncs it as the [crate::SyncPolicy]
+/// says, replaces files at once when it recycles or rewrites them, and deletes files when logs
+/// are truncated. Readers read whole files.
+///
+/// Only the WAL files go to the storage: the meta file, the lock files and the other files kept
+/// alongside logs, such as key filters and blobs, stay in the WAL directory, which must exist on
+/// the local file system.
+///
+/// # Example
+/// ```
+/// use walcraft::{MemoryStorage, Wal, WalBuilder};
+///
+/// # std::fs::create_dir_all("./tmp/storage_doc/").unwrap();
+/// let storage = MemoryStorage::new();
+/// let builder = WalBuilder::new("./tmp/storage_doc/", 500).storage(storage.clone());
+/// let wal: Wal<String> = Wal::recreate(builder).unwrap();
+/// wal.write("in memory".to_string());
+/// wal.flush().unwrap();
+/// assert!(!std::path::Path::new("./tmp/storage_doc/wal_1").exists());
+/// ```
+pub trait Storage: Send + Sync {
+    /// Open the file at `path` to append to it, creating it when missing
+    fn open_segment(&self, path: &Path) -> io::Result<Box<dyn StorageSegment>>;
+
+    /// Content of the file at `path`, None when there is none
+    fn read(&self, path: &Path) -> io::Result<Option<Vec<u8>>>;
+
+    /// Replace the content of the file at `path` at once, creating it when missing
+    ///
+    /// A crash leaves either the old content or the new one, never part of the new one.
+    fn replace(&self, path: &Path, content: &[u8]) -> io::Result<()>;
+
+    /// Remove the file at `path`, succeeding when there is none
+    fn delete(&self, path: &Path) -> io::Result<()>;
+
+    /// Paths of the files in the directory `dir`, none when there is no such directory
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>>;
+
+    /// Size of the file at `path` in bytes, None when there is none
+    fn size(&self, path: &Path) -> io::Result<Option<u64>> {
+        Ok(self.read(path)?.map(|content| content.len() as u64))
+    }
+}
+
+/// WAL file open for appending, see [Storage::open_segment]
+pub trait StorageSegment: Send {
+    /// Write `data` at the end of the file
+    fn append(&mut self, data: &[u8]) -> io::Result<()>;
+
+    /// Make the data appended so far durable
+    fn fsync(&mut self) -> io::Result<()>;
+
+    /// Size of the file in bytes
+    fn size(&self) -> io::Result<u64>;
+}
+
+/// WAL files on the local file system, the storage of WALs by default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LocalStorage;
+
+impl Storage for LocalStorage {
+    fn open_segment(&self, path: &Path) -> io::Result<Box<dyn StorageSegment>> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(Box::new(file))
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Option<Vec<u8>>> {
+        missing_as_none(std::fs::read(path))
+    }
+
+    fn replace(&self, path: &Path, content: &[u8]) -> io::Result<()> {
+        durable::write_atomic(path, &path.with_extension("replace"), content)
+    }
+
+    fn delete(&self, path: &Path) -> io::Result<()> {
+        missing_as_none(std::fs::remove_file(path)).map(|_| ())
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let entries = match missing_as_none(std::fs::read_dir(dir))? {
+            Some(entries) => entries,
+            None => return Ok(Vec::new()),
+        };
+        let mut paths = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                paths.push(entry.path());
+            }
+        }
+        Ok(paths)
+    }
+
+    fn size(&self, path: &Path) -> io::Result<Option<u64>> {
+        Ok(missing_as_none(std::fs::metadata(path))?.map(|m| m.len()))
+    }
+}
+
+impl StorageSegment for File {
+    fn append(&mut self, data: &[u8]) -> io::Result<()> {
+        self.write_all(data)
+    }
+
+    fn fsync(&mut self) -> io::Result<()> {
+        self.sync_data()
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        self.metadata().map(|m| m.len())
+    }
+}
+
+/// WAL files held in memory and lost with the process, to test applications without writing
+/// logs to disk
+///
+/// Clones share their files, so a WAL opened again with a clone finds the logs written before.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    files: Arc<Mutex<BTreeMap<PathBuf, Vec<u8>>>>,
+}
+
+impl MemoryStorage {
+    /// Storage without any file
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn files(&self) -> std::sync::MutexGuard<'_, BTreeMap<PathBuf, Vec<u8>>> {
+        self.files.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn open_segment(&self, path: &Path) -> io::Result<Box<dyn StorageSegment>> {
+        self.files().entry(path.to_path_buf()).or_default();
+        Ok(Box::new(MemorySegment {
+            storage: self.clone(),
+            path: path.to_path_buf(),
+        }))
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Option<Vec<u8>>> {
+        Ok(self.files().get(path).cloned())
+    }
+
+    fn replace(&self, path: &Path, content: &[u8]) -> io::Result<()> {
+        self.files().insert(path.to_path_buf(), content.to_vec());
+        Ok(())
+    }
+
+    fn delete(&self, path: &Path) -> io::Result<()> {
+        self.files().remove(path);
+        Ok(())
+    }
+
+    fn list(&self, dir: &Path) -> io::Result<Vec<PathBuf>> {
+        let files = self.files();
+        let paths = files.keys().filter(|path| path.parent() == Some(dir));
+        Ok(paths.cloned().collect())
+    }
+
+    fn size(&self, path: &Path) -> io::Result<Option<u64>> {
+        Ok(self.files().get(path).map(|content| content.len() as u64))
+    }
+}
+
+// File of a [MemoryStorage] open for appending
+struct MemorySegment {
+    storage: MemoryStorage,
+    path: PathBuf,
+}
+
+impl StorageSegment for MemorySegment {
+    fn append(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut files = self.storage.files();
+        files.entry(self.path.clone()).or_default().extend(data);
+        Ok(())
+    }
+
+    fn fsync(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn size(&self) -> io::Result<u64> {
+        let files = self.storage.files();
+        Ok(files
+            .get(&self.path)
+            .map_or(0, |content| content.len() as u64))
+    }
+}
+
+// Storage of the WAL files, shared by the builder, the handles, the readers and the writer
+// The local file system is used directly rather than through [LocalStorage], so files stay
+// available for memory maps, io_uring, preallocation and positional writes.
+#[derive(Clone, Default)]
+pub(crate) enum SharedStorage {
+    #[default]
+    Local,
+    Custom(Arc<dyn Storage>),
+}
+
+impl std::fmt::Debug for SharedStorage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Local => f.write_str("Local"),
+            Self::Custom(_) => f.write_str("Custom"),
+        }
+    }
+}
+
+impl PartialEq for SharedStorage {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Local, Self::Local) => true,
+            (Self::Custom(a), Self::Custom(b)) => Arc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Eq for SharedStorage {}
+
+impl SharedStorage {
+    pub fn is_local(&self) -> bool {
+        matches!(self, Self::Local)
+    }
+
+    // content of the file at `path`, None when there is none
+    pub fn read(&self, path: &Path) -> io::Result<Option<Vec<u8>>> {
+        match self {
+            Self::Local => missing_as_none(std::fs::read(path)),
+            Self::Custom(storage) => storage.read(path),
+        }
+    }
+
+    // first `len` bytes of the file at `path` at most, None when there is none
+    pub fn read_head(&self, path: &Path, len: u64) -> io::Result<Option<Vec<u8>>> {
+        match self {
+            Self::Local => {
+                let mut content = Vec::new();
+                let file = missing_as_none(File::open(path))?;
+                file.map(|f| f.take(len).read_to_end(&mut content).map(|_| content))
+                    .transpose()
+            }
+            Self::Custom(storage) => Ok(storage.read(path)?.map(|mut content| {
+                content.truncate(len as usize);
+                content
+            })),
+        }
+    }
+
+    // size of the file at `path` in bytes, None when there is none or it can't be read
+    pub fn size(&self, path: &Path) -> Option<u64> {
+        match self {
+            Self::Local => std::fs::metadata(path).ok().map(|m| m.len()),
+            Self::Custom(storage) => storage.size(path).ok().flatten(),
+        }
+    }
+
+    pub fn exists(&self, path: &Path) -> bool {
+        match self {
+            Self::Local => path.exists(),
+            Self::Custom(_) => self.size(path).is_some(),
+        }
+    }
+
+    // when the file at `path` was created and last modified, when the storage knows
+    pub fn times(&self, path: &Path) -> (Option<SystemTime>, Option<SystemTime>) {
+        match (self, std::fs::metadata(path)) {
+            (Self::Local, Ok(m)) => (m.created().ok(), m.modified().ok()),
+            _ => (None, None),
+        }
+    }
+
+    // paths of the files in the directory `dir`
+    pub fn list(&self, dir: &Path) -> Vec<PathBuf> {
+        let listed = match self {
+            Self::Local => LocalStorage.list(dir),
+            Self::Custom(storage) => storage.list(dir),
+        };
+        listed.unwrap_or_default()
+    }
+
+    // remove the file at `path`, failing with [io::ErrorKind::NotFound] when there is none
+    pub fn delete(&self, path: &Path) -> io::Result<()> {
+        match self {
+            Self::Local => std::fs::remove_file(path),
+            Self::Custom(storage) if self.exists(path) => storage.delete(path),
+            Self::Custom(_) => Err(io::ErrorKind::NotFound.into()),
+        }
+    }
+
+    // Replace the content of the file at `path` at once, going through a temporary file with the
+    // extension `scratch` on the local file system
+    pub fn replace(&self, path: &Path, content: &[u8], scratch: &str) -> io::Result<()> {
+        match self {
+            Self::Local => durable::write_atomic(path, &path.with_extension(scratch), content),
+            Self::Custom(storage) => storage.replace(path, content),
+        }
+    }
+
+    // cut the file at `path` to its first `len` bytes
+    pub fn cut(&self, path: &Path, len: u64) -> io::Result<()> {
+        match self {
+            Self::Local => OpenOptions::new().write(true).open(path).and_then(|file| {
+                file.set_len(len)?;
+                file.sync_all()
+            }),
+            Self::Custom(storage) => {
+                let mut content = storage.read(path)?.unwrap_or_default();
+                content.truncate(len as usize);
+                storage.replace(path, &content)
+            }
+        }
+    }
+
+    // Write `header` over the start of the file at `path`, keeping its size, then release the
+    // bytes past the header up to `head` where the file system punches holes
+    pub fn reclaim(&self, path: &Path, header: &[u8], head: u64) -> io::Result<()> {
+        match self {
+            Self::Local => {
+                let mut file = OpenOptions::new().write(true).open(path)?;
+                file.write_all(header)?;
+                file.sync_data()?;
+                #[cfg(target_os = "linux")]
+                {
+                    use std::os::unix::io::AsRawFd;
+                    let start = header.len() as libc::off_t;
+                    let len = head as libc::off_t - start;
+                    let mode = libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE;
+                    // best effort, as only some file systems punch holes
+                    // SAFETY: the descriptor belongs to `file`, open for as long as the call
+                    unsafe { libc::fallocate(file.as_raw_fd(), mode, start, len) };
+                }
+                #[cfg(not(target_os = "linux"))]
+                let _ = head;
+                Ok(())
+            }
+            Self::Custom(storage) => {
+                let mut content = storage.read(path)?.unwrap_or_default();
+                if content.len() < header.len() {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                content[..header.len()].copy_from_slice(header);
+                storage.replace(path, &content)
+            }
+        }
+    }
+
+    // Open the file at `path` for reading, None when there is none
+    pub fn open(&self, path: &Path) -> io::Result<Option<Opened>> {
+        match self {
+            Self::Local => match missing_as_none(File::open(path))? {
+                Some(file) => {
+                    let size = file.metadata()?.len();
+                    Ok(Some(Opened::File(file, size)))
+                }
+                None => Ok(None),
+            },
+            Self::Custom(storage) => Ok(storage
+                .read(path)?
+                .map(|content| Opened::Content(io::Cursor::new(content.into())))),
+        }
+    }
+
+    // Open the file at `path` to append to it, emptied first when `clear` is set
+    // A local file is emptied at once by renaming an empty file with the extension `scratch` over
+    // it, never leaving a partly cleared file. The directory is synced once the file is created
+    // or replaced, so its name survives a crash along with the logs synced to it.
+    pub fn open_segment(&self, path: &Path, clear: bool, scratch: &str) -> io::Result<Segment> {
+        match self {
+            Self::Local => {
+                let created = clear || !path.exists();
+                if clear {
+                    let tmp = path.with_extension(scratch);
+                    durable::create(&tmp).and_then(|_| durable::replace(&tmp, path))?;
+                }
+                let file = OpenOptions::new().append(true).create(true).open(path)?;
+                if created {
+                    durable::sync_parent(path)?;
+                }
+                Ok(Segment::Local(file))
+            }
+            Self::Custom(storage) => {
+                if clear {
+                    storage.replace(path, &[])?;
+                }
+                storage.open_segment(path).map(Segment::Custom)
+            }
+        }
+    }
+}
+
+// WAL file as opened by a reader, with the bytes it held at that time
+pub(crate) enum Opened {
+    File(File, u64),
+    Content(io::Cursor<Arc<[u8]>>),
+}
+
+impl Opened {
+    pub fn size(&self) -> u64 {
+        match self {
+            Self::File(_, size) => *size,
+            Self::Content(content) => content.get_ref().len() as u64,
+        }
+    }
+
+    // read the whole file into `buffer`
+    pub fn read_into(self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        buffer.clear();
+        match self {
+            Self::File(file, size) => file.take(size).read_to_end(buffer).map(|_| ()),
+            Self::Content(content) => {
+                buffer.extend_from_slice(content.get_ref());
+                Ok(())
+            }
+        }
+    }
+
+    // another handle on the file, reading it from its start
+    pub fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Self::File(file, size) => {
+                let mut file = file.try_clone()?;
+                file.rewind()?;
+                Ok(Self::File(file, *size))
+            }
+            Self::Content(content) => Ok(Self::Content(io::Cursor::new(content.get_ref().clone()))),
+        }
+    }
+}
+
+impl Read for Opened {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::File(file, _) => file.read(buf),
+            Self::Content(content) => content.read(buf),
+        }
+    }
+}
+
+impl Seek for Opened {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        match self {
+            Self::File(file, _) => file.seek(pos),
+            Self::Content(content) => content.seek(pos),
+        }
+    }
+}
+
+// WAL file the writer thread appends to
+pub(crate) enum Segment {
+    Local(File),
+    Custom(Box<dyn StorageSegment>),
+}
+
+impl Segment {
+    // the local file, for the features working on files
+    pub fn file(&self) -> Option<&File> {
+        match self {
+            Self::Local(file) => Some(file),
+            Self::Custom(_) => None,
+        }
+    }
+
+    pub fn size(&self) -> io::Result<u64> {
+        match self {
+            Self::Local(file) => file.metadata().map(|m| m.len()),
+            Self::Custom(segment) => segment.size(),
+        }
+    }
+}
+
+impl SegmentFile for Segment {
+    fn append(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            Self::Local(file) => SegmentFile::append(file, data),
+            Self::Custom(segment) => segment.append(data),
+        }
+    }
+
+    fn write_at(&mut self, data: &[u8], offset: u64) -> io::Result<()> {
+        match self {
+            Self::Local(file) => file.write_at(data, offset),
+            // headers are written at the start of new files, i.e. at their end
+            Self::Custom(segment) if segment.size()? == offset => segment.append(data),
+            Self::Custom(_) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "positional writes need local files",
+            )),
+        }
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        match self {
+            Self::Local(file) => SegmentFile::sync(file),
+            Self::Custom(segment) => segment.fsync(),
+        }
+    }
+}
+
+// None for a missing file, which many operations expect
+fn missing_as_none<T>(result: io::Result<T>) -> io::Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,109 @@
+use crate::decode::Decoder;
+use crate::entry::LogEntry;
+use serde::Deserialize;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Batches of logs written by the writer thread, shared by every subscription
+type Written = Arc<[LogEntry]>;
+
+// Subscriptions of a WAL, shared by its handles and the writer thread
+#[derive(Clone, Default)]
+pub(crate) struct Subscribers {
+    senders: Arc<Mutex<Vec<Sender<Written>>>>,
+}
+
+impl Subscribers {
+    // new subscription, receiving the logs written from now on
+    pub fn add(&self) -> Receiver<Written> {
+        let (sender, receiver) = mpsc::channel();
+        self.lock().push(sender);
+        receiver
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    // send a batch of written logs to every subscription, forgetting the dropped ones
+    pub fn publish(&self, written: Written) {
+        self.lock()
+            .retain(|sender| sender.send(written.clone()).is_ok());
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<Sender<Written>>> {
+        self.senders.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// Logs written to the WAL from the moment of subscribing, in the order they were written, see
+/// [crate::Wal::subscribe]
+///
+/// Iterating blocks until the next log is written, and ends once every handle of the WAL is
+/// dropped and the writer thread has stopped. Logs that can't be deserialized are skipped.
+pub struct Subscription<T> {
+    receiver: Receiver<Written>,
+    decoder: Decoder<T>,
+    // batch being iterated and the position of its next log
+    batch: Option<(Written, usize)>,
+}
+
+impl<T> Subscription<T>
+where
+    T: for<'a> Deserialize<'a>,
+{
+    pub(crate) fn new(receiver: Receiver<Written>, decoder: Decoder<T>) -> Self {
+        Self {
+            receiver,
+            decoder,
+            batch: None,
+        }
+    }
+
+    /// Wait up to `timeout` for the next log
+    ///
+    /// None if no log was written in time, or once the WAL is closed.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Option<T> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(log) = self.next_buffered() {
+                return Some(log);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match self.receiver.recv_timeout(remaining) {
+                Ok(written) => self.batch = Some((written, 0)),
+                Err(_) => return None,
+            }
+        }
+    }
+
+    // next log of the batch being iterated that can be deserialized
+    fn next_buffered(&mut self) -> Option<T> {
+        let (written, next) = self.batch.as_mut()?;
+        while let Some(entry) = written.get(*next) {
+            *next += 1;
+            if let Some(log) = self.decoder.decode_payload(entry.payload()) {
+                return Some(log);
+            }
+        }
+        self.batch = None;
+        None
+    }
+}
+
+impl<T> Iterator for Subscription<T>
+where
+    T: for<'a> Deserialize<'a>,
+{
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        loop {
+            if let Some(log) = self.next_buffered() {
+                return Some(log);
+            }
+            self.batch = Some((self.receiver.recv().ok()?, 0));
+        }
+    }
+}
@@ -0,0 +1,279 @@
+use crate::failure::WriteOperation;
+use crate::spawn::WriterHandle;
+use crate::writer::{Signal, WalWriter, WalWriterProps};
+use crate::{Wal, WalError};
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, SendError, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// Message to the writer thread, which serves a WAL and all its topics
+pub(crate) enum Routed {
+    // serve the writer of a newly opened WAL, restarted with the properties it was created with
+    Attach(usize, Box<WalWriter>, WriterHandle, Option<Restart>),
+    // signal to the writer of a WAL
+    Signal(usize, Signal),
+    // all handles of a WAL were dropped
+    Close(usize),
+}
+
+// Sender of the signals of one WAL to the writer thread it shares with its topics
+// The WAL is closed once the last clone is dropped.
+#[derive(Clone)]
+pub(crate) struct SignalSender {
+    route: Arc<Route>,
+}
+
+struct Route {
+    tx: Sender<Routed>,
+    topic: usize,
+    // number of WALs served by the thread so far, numbering the next one
+    served: Arc<AtomicUsize>,
+}
+
+impl Drop for Route {
+    fn drop(&mut self) {
+        let _ = self.tx.send(Routed::Close(self.topic));
+    }
+}
+
+impl SignalSender {
+    pub fn new(tx: Sender<Routed>) -> Self {
+        Self {
+            route: Arc::new(Route {
+                tx,
+                topic: 0,
+                served: Arc::new(AtomicUsize::new(1)),
+            }),
+        }
+    }
+
+    // sender of another WAL served by the same thread
+    pub fn topic(&self) -> Self {
+        Self {
+            route: Arc::new(Route {
+                tx: self.route.tx.clone(),
+                topic: self.route.served.fetch_add(1, Ordering::Relaxed),
+                served: self.route.served.clone(),
+            }),
+        }
+    }
+
+    pub fn send(&self, signal: Signal) -> Result<(), SendError<Routed>> {
+        self.route.tx.send(Routed::Signal(self.route.topic, signal))
+    }
+
+    // hand the writer of the WAL over to the thread
+    pub fn attach(
+        &self,
+        writer: WalWriter,
+        handle: WriterHandle,
+        restart: Option<Restart>,
+    ) -> Result<(), WalError> {
+        self.route
+            .tx
+            .send(Routed::Attach(
+                self.route.topic,
+                Box::new(writer),
+                handle,
+                restart,
+            ))
+            .map_err(|_| WalError::WriterDead("Writer thread has stopped".to_string()))
+    }
+}
+
+// Properties a writer is created again with after it panics, see
+// [crate::WalBuilder::restart_writer]
+pub(crate) struct Restart {
+    props: Box<WalWriterProps>,
+    // restarts allowed from now on
+    left: usize,
+}
+
+impl Restart {
+    pub fn new(props: WalWriterProps, times: usize) -> Self {
+        Self {
+            props: Box::new(props),
+            left: times,
+        }
+    }
+}
+
+// Writer of a WAL served by the thread
+struct Served {
+    topic: usize,
+    writer: WalWriter,
+    handle: WriterHandle,
+    restart: Option<Restart>,
+}
+
+impl Served {
+    // Run `f` on the writer, containing a panic to this WAL
+    // A writer that panicked is created again from the files, dropping the batch it was
+    // writing, while restarts are left. Returns false once the writer is given up.
+    fn run(&mut self, f: impl FnOnce(&mut WalWriter)) -> bool {
+        let panic = match catch_unwind(AssertUnwindSafe(|| f(&mut self.writer))) {
+            Ok(()) => return true,
+            Err(panic) => panic,
+        };
+        let failures = self.writer.failures().clone();
+        failures.other(
+            WriteOperation::Panic,
+            format!("Writer panicked: {}", panic_message(&panic)),
+        );
+        let restart = match self.restart.as_mut().filter(|r| r.left > 0) {
+            Some(restart) => restart,
+            None => {
+                self.handle.fail();
+                return false;
+            }
+        };
+        restart.left -= 1;
+        match WalWriter::new(*restart.props.clone()) {
+            Ok(writer) => {
+                self.writer = writer;
+                true
+            }
+            Err(e) => {
+                failures.other(WriteOperation::Panic, format!("Restart failed: {}", e));
+                self.handle.fail();
+                false
+            }
+        }
+    }
+}
+
+// text of a panic, when it was raised with one
+fn panic_message(panic: &Box<dyn Any + Send>) -> &str {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => message,
+        (_, Some(message)) => message,
+        _ => "unknown panic",
+    }
+}
+
+// Serve the writers of a WAL and its topics until all their handles are dropped
+// Work due on any writer wakes the thread up, and a writer waiting for its batch to fill holds
+// up the others. A writer panicking is restarted or given up without stopping the others.
+pub(crate) fn run(receiver: Receiver<Routed>) {
+    let mut writers: Vec<Served> = Vec::new();
+    loop {
+        writers.retain_mut(|served| served.run(|writer| writer.tick()));
+        let deadline = writers.iter().filter_map(|s| s.writer.deadline()).min();
+        // Wait for the notification of new logs
+        let message = match deadline {
+            Some(at) => match receiver.recv_timeout(at.saturating_duration_since(Instant::now())) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break,
+            },
+            None => match receiver.recv() {
+                Ok(message) => message,
+                Err(_) => break,
+            },
+        };
+        match message {
+            Routed::Attach(topic, writer, handle, restart) => writers.push(Served {
+                topic,
+                writer: *writer,
+                handle,
+                restart,
+            }),
+            Routed::Signal(topic, signal) => {
+                if let Some(i) = writers.iter().position(|s| s.topic == topic) {
+                    if !writers[i].run(|writer| writer.handle(signal)) {
+                        writers.remove(i);
+                    }
+                }
+            }
+            // write what's left, and let the dropped handles go once the files are closed
+            Routed::Close(topic) => {
+                if let Some(i) = writers.iter().position(|s| s.topic == topic) {
+                    close(writers.remove(i));
+                }
+                if writers.is_empty() {
+                    return;
+                }
+            }
+        }
+    }
+    writers.into_iter().for_each(close);
+}
+
+// Write what's left in the buffer of a WAL and mark its writer as done
+fn close(served: Served) {
+    let Served { writer, handle, .. } = served;
+    // the handles are let go even if the last flush panics
+    let _ = catch_unwind(AssertUnwindSafe(|| writer.close()));
+    handle.close();
+}
+
+// Topics opened from a WAL, see [crate::Wal::topic]
+pub(crate) struct Topics<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    // capacity of the topics without one of their own
+    pub capacity: usize,
+    pub capacities: HashMap<String, usize>,
+    pub read_only: bool,
+    // handles of the topics opened so far, by name
+    pub opened: Mutex<HashMap<String, Wal<T>>>,
+}
+
+impl<T> Topics<T>
+where
+    T: Serialize + for<'a> Deserialize<'a>,
+{
+    pub fn new(capacity: usize, capacities: HashMap<String, usize>, read_only: bool) -> Self {
+        Self {
+            capacity,
+            capacities,
+            read_only,
+            opened: Mutex::default(),
+        }
+    }
+
+    // the same settings, for logs of another type
+    pub fn retype<U>(&self) -> Topics<U>
+    where
+        U: Serialize + for<'a> Deserialize<'a>,
+    {
+        Topics::new(self.capacity, self.capacities.clone(), self.read_only)
+    }
+
+    pub fn capacity(&self, name: &str) -> usize {
+        self.capacities.get(name).copied().unwrap_or(self.capacity)
+    }
+}
+
+// Fail unless `name` is a single directory name
+pub(crate) fn check_name(name: &str) -> Result<(), WalError> {
+    let valid =
+        !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\', '\0']);
+    match valid {
+        true => Ok(()),
+        false => Err(WalError::Unsupported(format!(
+            "Topic name {:?} isn't a valid directory name",
+            name
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert!(check_name("orders").is_ok());
+        assert!(check_name("orders-2024.v1").is_ok());
+        for name in ["", ".", "..", "a/b", "a\\b"] {
+            assert!(check_name(name).is_err(), "{}", name);
+        }
+    }
+}
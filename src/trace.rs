@@ -0,0 +1,22 @@
+// Spans around the work of the writer thread and of reads, enabled by the `tracing` feature
+//
+// The span is entered until the returned guard is dropped. Fields are only evaluated when the
+// feature is enabled, so they cost nothing in regular builds.
+macro_rules! span {
+    ($name:literal $(, $field:ident = $value:expr)* $(,)?) => {{
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!($name $(, $field = $value)*).entered();
+        #[cfg(not(feature = "tracing"))]
+        let span = {
+            let _ = || {
+                $(let _ = &$value;)*
+            };
+            $crate::trace::NoSpan
+        };
+        span
+    }};
+}
+
+// Guard of a span while the `tracing` feature is disabled
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct NoSpan;
@@ -0,0 +1,149 @@
+use crate::entry::LogEntry;
+use crate::{format, Lsn, Rejection, Wal, WalError};
+use serde::{Deserialize, Serialize};
+use std::ops::Range;
+
+/// Logs collected by [Wal::atomic] or staged in [Wal::transaction], written together as an
+/// atomic batch
+///
+/// Every log of the batch but the last is marked in its frame as followed by more logs, so the
+/// last one commits the batch: readers skip a batch cut short by a crash. A transaction dropped
+/// without [Transaction::commit] writes nothing.
+///
+/// Logs written to the streams of the transaction, see [Transaction::stream], belong to the same
+/// batch, so a transaction spanning several streams is written or skipped as a whole too.
+pub struct Transaction<'a, T>
+where
+    T: Serialize + for<'b> Deserialize<'b>,
+{
+    wal: &'a Wal<T>,
+    entries: Vec<LogEntry>,
+    // first log that failed to encode, aborting the whole batch
+    error: Option<WalError>,
+}
+
+impl<'a, T> Transaction<'a, T>
+where
+    T: Serialize + for<'b> Deserialize<'b>,
+{
+    pub(crate) fn new(wal: &'a Wal<T>) -> Self {
+        Self {
+            wal,
+            entries: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Add a log to the batch
+    ///
+    /// A log that can't be serialized or breaks the rules set with [crate::WalBuilder::strict]
+    /// aborts the batch, and [Wal::atomic] or [Transaction::commit] returns the error without
+    /// writing any log.
+    pub fn write(&mut self, entry: T) {
+        self.push(entry, None);
+    }
+
+    /// Logs of the stream `name` in the batch
+    ///
+    /// Streams split the logs of a WAL by name: their logs are tagged with the name, see
+    /// [Wal::try_write_tagged], and read back with [Wal::read_by_tag]. A name longer than
+    /// [format::MAX_TAG_BYTES] aborts the batch with [Rejection::TagTooLarge] once a log is
+    /// written to the stream.
+    ///
+    /// # Example
+    /// ```
+    /// use walcraft::Wal;
+    ///
+    /// # std::fs::create_dir_all("./tmp/stream_doc/").unwrap();
+    /// let wal = Wal::new("./tmp/stream_doc/", 500).unwrap();
+    /// wal.atomic(|txn| {
+    ///     txn.stream("accounts").write("debit 10".to_string());
+    ///     txn.stream("ledger").write("moved 10".to_string());
+    /// })
+    /// .unwrap();
+    /// assert!(wal.read_by_tag(b"ledger").unwrap().contains(&"moved 10".to_string()));
+    /// ```
+    ///
+    pub fn stream(&mut self, name: &str) -> TransactionStream<'_, 'a, T> {
+        TransactionStream {
+            txn: self,
+            tag: name.as_bytes().to_vec(),
+        }
+    }
+
+    // stage a log, tagged with the name of its stream if any
+    fn push(&mut self, entry: T, tag: Option<&[u8]>) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Some(tag) = tag.filter(|tag| tag.len() > format::MAX_TAG_BYTES) {
+            self.wal.counters.rejected(1);
+            self.error = Some(WalError::Rejected(Rejection::TagTooLarge {
+                size: tag.len(),
+                limit: format::MAX_TAG_BYTES,
+            }));
+            return;
+        }
+        match self.wal.encode(&entry) {
+            Ok(mut log) => {
+                log.set_tag(tag.map(<[u8]>::to_vec));
+                self.entries.push(log);
+            }
+            Err(e) => self.error = Some(e),
+        }
+    }
+
+    /// Number of logs staged so far
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether no log was staged yet
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Write the staged logs as an atomic batch, and wait until they are synced to storage
+    ///
+    /// Returns the contiguous range of sequence numbers of the logs. Fails without writing any
+    /// log if staging a log failed, see [Transaction::write].
+    pub fn commit(self) -> Result<Range<Lsn>, WalError> {
+        let wal = self.wal;
+        wal.check_atomic()?;
+        let lsns = wal.enqueue_atomic(self)?;
+        wal.flush()?;
+        Ok(lsns)
+    }
+
+    // logs of the batch, every one but the last marked as followed by more logs
+    pub(crate) fn finish(self) -> Result<Vec<LogEntry>, WalError> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let mut entries = self.entries;
+        let count = entries.len();
+        for entry in entries.iter_mut().take(count.saturating_sub(1)) {
+            entry.set_batched(true);
+        }
+        Ok(entries)
+    }
+}
+
+/// Logs of one stream of a [Transaction], see [Transaction::stream]
+pub struct TransactionStream<'t, 'a, T>
+where
+    T: Serialize + for<'b> Deserialize<'b>,
+{
+    txn: &'t mut Transaction<'a, T>,
+    tag: Vec<u8>,
+}
+
+impl<T> TransactionStream<'_, '_, T>
+where
+    T: Serialize + for<'b> Deserialize<'b>,
+{
+    /// Add a log of the stream to the batch, see [Transaction::write]
+    pub fn write(&mut self, entry: T) {
+        self.txn.push(entry, Some(&self.tag));
+    }
+}
@@ -0,0 +1,281 @@
+use crate::durable;
+use crate::format::{self, FormatVersion};
+use crate::key_filter::KeyFilter;
+use crate::layout::Layout;
+use crate::manifest::{Manifest, ManifestKind};
+use crate::reader::WalReader;
+use crate::segment_index::SegmentIndex;
+use crate::{Lsn, WalError};
+use std::path::{Path, PathBuf};
+
+// Removal of the oldest WAL files, once their logs are covered by a snapshot
+//
+// Truncation is recorded before any file is removed and committed once they are all removed, so
+// a crash at any point never leaves a gap in the logs nor brings removed logs back:
+// 1. the files to remove are recorded in the `truncate` file, replaced atomically
+// 2. the files are removed from the oldest to the newest
+// 3. meta file drops the digests and time ranges of removed files, and the `truncate` file is
+//    removed
+// Readers skip the files of a truncation in progress, and opening the WAL completes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Truncation {
+    // logs up to this sequence number are no longer needed
+    pub through: Lsn,
+    // files to remove, oldest first
+    pub ids: Vec<u8>,
+}
+
+impl Truncation {
+    // Oldest sealed files holding only logs up to `through`, when `pointer` is the current file
+    // Files are taken from the oldest one until a file holds a newer log, so the remaining logs
+    // follow each other without a gap
+    pub fn plan(reader: &WalReader, pointer: u8, through: Lsn) -> Result<Self, WalError> {
+        let mut ids = Vec::new();
+        for id in reader.read_order(pointer).into_iter().skip(1).rev() {
+            match reader.lsn_range(id)? {
+                // nothing to remove from an empty file
+                None => continue,
+                Some(range) if *range.end() <= through => ids.push(id),
+                Some(_) => break,
+            }
+        }
+        Ok(Self { through, ids })
+    }
+
+    fn path(location: &Path) -> PathBuf {
+        location.join("truncate")
+    }
+
+    // truncation in progress, recorded as `<through> <id>...`
+    pub fn pending(location: &Path) -> Option<Self> {
+        let text = std::fs::read_to_string(Self::path(location)).ok()?;
+        let mut parts = text.split_whitespace();
+        let through = parts.next()?.parse().ok()?;
+        let ids = parts.map(|id| id.parse().ok()).collect::<Option<_>>()?;
+        Some(Self { through, ids })
+    }
+
+    // first phase: record the files about to be removed
+    pub fn record(&self, location: &Path) -> Result<(), WalError> {
+        let mut text = self.through.to_string();
+        for id in &self.ids {
+            text.push_str(&format!(" {}", id));
+        }
+        let path = Self::path(location);
+        let tmp = path.with_extension("tmp");
+        durable::write_atomic(&path, &tmp, text.as_bytes())
+            .map_err(|e| WalError::io(e, "Failed to record truncation"))
+    }
+
+    // remove the files, from the oldest to the newest
+    // Files already removed by an interrupted attempt are skipped
+    pub fn remove_files(&self, location: &Path, layout: &Layout) -> Result<(), WalError> {
+        for id in &self.ids {
+            // a file moved to the cold location goes as well
+            let paths = [Some(layout.hot_path(location, *id)), layout.cold_path(*id)];
+            for path in paths.into_iter().flatten() {
+                match layout.storage.delete(&path) {
+                    Ok(_) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(WalError::io(e, "Failed to remove truncated log file")),
+                }
+            }
+            KeyFilter::remove(location, *id);
+            SegmentIndex::remove(location, *id);
+        }
+        // the files stay removed after a crash, before the truncation is cleared
+        durable::sync_parent(&Self::path(location))
+            .map_err(|e| WalError::io(e, "Failed to remove truncated log file"))
+    }
+
+    // last phase: the truncation is complete once meta file no longer describes removed files
+    pub fn clear(location: &Path) -> Result<(), WalError> {
+        match std::fs::remove_file(Self::path(location)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(WalError::io(e, "Failed to complete truncation"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Complete a truncation interrupted by a crash, returning whether there was one
+    pub fn resume(location: &Path, kind: ManifestKind, layout: &Layout) -> Result<bool, WalError> {
+        let truncation = match Self::pending(location) {
+            Some(t) => t,
+            None => return Ok(false),
+        };
+        truncation.remove_files(location, layout)?;
+        let mut manifest = Manifest::open(location, kind);
+        if let Ok(mut meta) = manifest.load() {
+            meta.digests.retain(|d| !truncation.ids.contains(&d.id));
+            meta.spans.retain(|s| !truncation.ids.contains(&s.id));
+            manifest.store(&meta)?;
+        }
+        Self::clear(location)?;
+        Ok(true)
+    }
+}
+
+// Reclaim the logs up to `through` at the start of the sealed file `id`, returning how many
+// logs were reclaimed
+// The file keeps its size and its frames stay where they are: the header records where the
+// first remaining frame starts, and the bytes before it are released where the file system
+// punches holes. Only files with native frames of the current format are reclaimed, and atomic
+// batches are reclaimed whole or not at all. Readers must be kept out while the file changes.
+pub(crate) fn reclaim_head(reader: &WalReader, id: u8, through: Lsn) -> Result<u64, WalError> {
+    if !reader.indexable() {
+        return Ok(0);
+    }
+    let content = match reader.raw(id)? {
+        Some(content) => content,
+        None => return Ok(0),
+    };
+    if FormatVersion::from_header(&content) != Some(FormatVersion::CURRENT) {
+        return Ok(0);
+    }
+    let start = reader.header_len(&content);
+    // end of the last log up to `through` that isn't followed by more logs of its batch
+    let (mut head, mut logs, mut removed) = (start, 0, 0);
+    for (span, entry) in reader.frames(&content) {
+        if entry.lsn() > through {
+            break;
+        }
+        logs += 1;
+        if !entry.batched() {
+            (head, removed) = (span.end, logs);
+        }
+    }
+    let header = format::reclaimed_header(&content, head as u64);
+    if head <= start || head < header.len() {
+        return Ok(0);
+    }
+    let path = reader.segment_path(id);
+    reader
+        .storage()
+        .reclaim(&path, &header, head as u64)
+        .map_err(|e| WalError::io_at(e, "Failed to reclaim log file", &path))?;
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entry::LogEntry;
+    use crate::meta::Meta;
+    use crate::segment::SegmentDigest;
+    use std::fs::File;
+    use std::io::Write;
+
+    // WAL with 4 files of 3 logs each, wal_4 being the current one
+    fn wal(name: &str) -> PathBuf {
+        let dir = PathBuf::from(format!("./tmp/{}/", name));
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+        std::fs::create_dir_all(&dir).unwrap();
+        for id in 1..=4u8 {
+            let mut file = File::create(dir.join(format!("wal_{}", id))).unwrap();
+            for i in 0..3 {
+                let mut entry = LogEntry::try_new(&0u32).unwrap();
+                entry.set_lsn((id as Lsn - 1) * 3 + i);
+                file.write_all(&entry.into_frame(None, false)).unwrap();
+            }
+        }
+        let digest = |id| SegmentDigest {
+            id,
+            crc32: 0,
+            size: 0,
+            entries: 3,
+            verified: true,
+            sha256: None,
+            chain: None,
+        };
+        Meta {
+            pointer: 4,
+            offset: None,
+            segments: None,
+            format: None,
+            digests: vec![digest(1), digest(2), digest(3)],
+            spans: vec![],
+            chain: None,
+        }
+        .write(&dir)
+        .unwrap();
+        dir
+    }
+
+    // sequence numbers of the logs seen by readers, checked to follow each other
+    fn lsns(dir: &Path) -> Vec<Lsn> {
+        let lsns = WalReader::new(dir.to_path_buf())
+            .read()
+            .unwrap()
+            .iter()
+            .map(|e| e.lsn())
+            .collect::<Vec<_>>();
+        assert!(
+            lsns.windows(2).all(|w| w[1] == w[0] + 1),
+            "gap in {:?}",
+            lsns
+        );
+        assert_eq!(lsns.last(), Some(&11));
+        lsns
+    }
+
+    // state of the WAL once the truncation through LSN 6 is complete
+    fn check_complete(dir: &Path) {
+        assert_eq!(lsns(dir).first(), Some(&6));
+        assert!(Truncation::pending(dir).is_none());
+        let meta = Meta::read(dir).unwrap();
+        assert_eq!(meta.digests.iter().map(|d| d.id).collect::<Vec<_>>(), [3]);
+    }
+
+    #[test]
+    fn plan() {
+        let dir = wal("truncation_plan");
+        let reader = WalReader::new(dir.clone());
+        // wal_3 holds LSN 8, so it's kept along with the newer files
+        let truncation = Truncation::plan(&reader, 4, 7).unwrap();
+        assert_eq!(truncation.ids, [1, 2]);
+        assert!(Truncation::plan(&reader, 4, 1).unwrap().ids.is_empty());
+        // the current file is never removed
+        assert_eq!(Truncation::plan(&reader, 4, 100).unwrap().ids, [1, 2, 3]);
+    }
+
+    #[test]
+    fn crash_after_intent() {
+        let dir = wal("truncation_intent");
+        let truncation = Truncation::plan(&WalReader::new(dir.clone()), 4, 6).unwrap();
+        truncation.record(&dir).unwrap();
+        assert_eq!(Truncation::pending(&dir), Some(truncation));
+        // readers already skip the files about to be removed
+        assert_eq!(lsns(&dir).first(), Some(&6));
+        assert!(Truncation::resume(&dir, ManifestKind::Single, &Layout::default()).unwrap());
+        check_complete(&dir);
+    }
+
+    #[test]
+    fn crash_while_removing() {
+        let dir = wal("truncation_removing");
+        let truncation = Truncation::plan(&WalReader::new(dir.clone()), 4, 6).unwrap();
+        truncation.record(&dir).unwrap();
+        std::fs::remove_file(dir.join("wal_1")).unwrap();
+        assert_eq!(lsns(&dir).first(), Some(&6));
+        assert!(Truncation::resume(&dir, ManifestKind::Single, &Layout::default()).unwrap());
+        check_complete(&dir);
+    }
+
+    #[test]
+    fn crash_before_commit() {
+        let dir = wal("truncation_commit");
+        let truncation = Truncation::plan(&WalReader::new(dir.clone()), 4, 6).unwrap();
+        truncation.record(&dir).unwrap();
+        truncation.remove_files(&dir, &Layout::default()).unwrap();
+        // meta file still describes the removed files
+        assert_eq!(Meta::read(&dir).unwrap().digests.len(), 3);
+        assert!(Truncation::resume(&dir, ManifestKind::Single, &Layout::default()).unwrap());
+        check_complete(&dir);
+        // nothing left to resume
+        assert!(!Truncation::resume(&dir, ManifestKind::Single, &Layout::default()).unwrap());
+        check_complete(&dir);
+    }
+}
@@ -0,0 +1,125 @@
+use crate::flush::SegmentFile;
+use io_uring::{opcode, squeue, types, IoUring};
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+
+// Ring of the writer thread, writing and syncing WAL files through io_uring
+// Kernels without io_uring fail to create the ring, and the writer falls back to regular system
+// calls.
+pub(crate) struct Uring {
+    ring: IoUring,
+}
+
+impl Uring {
+    pub fn new() -> Option<Self> {
+        IoUring::new(4).ok().map(|ring| Self { ring })
+    }
+
+    // write all of `data` at `offset` of `file`, or at its position without an offset
+    fn write_all(
+        &mut self,
+        file: &File,
+        mut data: &[u8],
+        mut offset: Option<u64>,
+    ) -> io::Result<()> {
+        while !data.is_empty() {
+            let len = data.len().min(u32::MAX as usize) as u32;
+            // an offset of -1 writes at the position of the file, the end in append mode
+            let entry = opcode::Write::new(types::Fd(file.as_raw_fd()), data.as_ptr(), len)
+                .offset(offset.unwrap_or(u64::MAX))
+                .build();
+            let written = self.submit(&entry)?;
+            if written == 0 {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+            data = &data[written..];
+            offset = offset.map(|o| o + written as u64);
+        }
+        Ok(())
+    }
+
+    pub fn sync(&mut self, file: &File) -> io::Result<()> {
+        let entry = opcode::Fsync::new(types::Fd(file.as_raw_fd()))
+            .flags(types::FsyncFlags::DATASYNC)
+            .build();
+        self.submit(&entry).map(|_| ())
+    }
+
+    // submit `entry` and wait for its completion, returning its result
+    fn submit(&mut self, entry: &squeue::Entry) -> io::Result<usize> {
+        // SAFETY: the buffer and the file of the entry outlive its completion, which is waited
+        // for before returning
+        unsafe { self.ring.submission().push(entry) }
+            .map_err(|_| io::Error::other("io_uring submission queue is full"))?;
+        loop {
+            match self.ring.submit_and_wait(1) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+                Ok(_) => break,
+            }
+        }
+        let completion = self
+            .ring
+            .completion()
+            .next()
+            .ok_or_else(|| io::Error::other("io_uring completion is missing"))?;
+        match completion.result() {
+            result if result < 0 => Err(io::Error::from_raw_os_error(-result)),
+            result => Ok(result as usize),
+        }
+    }
+}
+
+// WAL file written through the ring
+pub(crate) struct RingFile<'a> {
+    pub ring: &'a mut Uring,
+    pub file: &'a File,
+}
+
+impl SegmentFile for RingFile<'_> {
+    fn append(&mut self, data: &[u8]) -> io::Result<()> {
+        self.ring.write_all(self.file, data, None)
+    }
+
+    fn write_at(&mut self, data: &[u8], offset: u64) -> io::Result<()> {
+        self.ring.write_all(self.file, data, Some(offset))
+    }
+
+    fn sync(&mut self) -> io::Result<()> {
+        self.ring.sync(self.file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::OpenOptions;
+
+    #[test]
+    fn ring_file() {
+        let ring = match Uring::new() {
+            Some(ring) => ring,
+            // io_uring is disabled, e.g. in some containers
+            None => return,
+        };
+        let dir = std::path::Path::new("./tmp/uring_ring_file/");
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+        let path = dir.join("wal_1");
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&path)
+            .unwrap();
+        let mut ring = ring;
+        let mut ring_file = RingFile {
+            ring: &mut ring,
+            file: &file,
+        };
+        ring_file.append(b"abc").unwrap();
+        ring_file.append(b"def").unwrap();
+        ring_file.sync().unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"abcdef");
+    }
+}
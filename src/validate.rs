@@ -0,0 +1,556 @@
+use crate::codec::Codec;
+use crate::entry::LogEntry;
+use crate::WalError;
+use serde::ser::{self, Serialize};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+/// Rules enforced on every log before it is accepted by the WAL
+///
+/// Used with [crate::WalBuilder::strict] by teams enforcing contracts on what gets persisted.
+/// Logs breaking a rule are rejected with [WalError::Rejected] describing the violation.
+///
+/// # Example
+/// ```
+/// use walcraft::Validation;
+///
+/// let rules = Validation::new()
+///     .max_payload_bytes(4096)
+///     .reject_non_finite_floats()
+///     .schema_version(2);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Validation {
+    max_payload_bytes: Option<usize>,
+    reject_empty: bool,
+    reject_non_finite: bool,
+    schema_version: Option<u32>,
+}
+
+/// Reason for rejecting a log in strict mode
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Rejection {
+    /// The serialized log is larger than the configured maximum
+    PayloadTooLarge { size: usize, limit: usize },
+    /// The serialized log is empty, as for `()` or unit structs
+    EmptyPayload,
+    /// The log contains a NaN or infinite float at the given field path
+    NonFiniteFloat { path: String },
+    /// The schema version of the WAL directory doesn't match the configured one
+    SchemaMismatch { expected: u32, found: u32 },
+    /// The tag of the log is longer than [crate::format::MAX_TAG_BYTES]
+    TagTooLarge { size: usize, limit: usize },
+}
+
+impl Display for Rejection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::PayloadTooLarge { size, limit } => {
+                write!(
+                    f,
+                    "payload of {} bytes exceeds limit of {} bytes",
+                    size, limit
+                )
+            }
+            Rejection::EmptyPayload => f.write_str("payload is empty"),
+            Rejection::NonFiniteFloat { path } => {
+                write!(f, "non-finite float at `{}`", path)
+            }
+            Rejection::SchemaMismatch { expected, found } => {
+                write!(f, "schema version {} expected, found {}", expected, found)
+            }
+            Rejection::TagTooLarge { size, limit } => {
+                write!(f, "tag of {} bytes exceeds limit of {} bytes", size, limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Rejection {}
+
+impl Validation {
+    /// Create a set of rules that accepts everything
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reject logs whose serialized payload is larger than `bytes`
+    pub fn max_payload_bytes(mut self, bytes: usize) -> Self {
+        self.max_payload_bytes = Some(bytes);
+        self
+    }
+
+    /// Reject logs whose serialized payload is empty
+    ///
+    /// Logs such as `()` or unit structs serialize to nothing with most codecs. They are stored as
+    /// frames holding only a sequence number and read back like any other log, which is what
+    /// markers need, but usually point to a bug when logs are expected to carry data.
+    pub fn reject_empty_payloads(mut self) -> Self {
+        self.reject_empty = true;
+        self
+    }
+
+    /// Reject logs containing NaN or infinite floats
+    pub fn reject_non_finite_floats(mut self) -> Self {
+        self.reject_non_finite = true;
+        self
+    }
+
+    /// Declare the schema version of logs written to the WAL
+    ///
+    /// The version is recorded in the WAL directory when it's first used, and the WAL fails to
+    /// open when a different version was recorded before.
+    pub fn schema_version(mut self, version: u32) -> Self {
+        self.schema_version = Some(version);
+        self
+    }
+
+    // lower the maximum payload size to `bytes`
+    pub(crate) fn limit_payload(&mut self, bytes: usize) {
+        let limit = self.max_payload_bytes.map_or(bytes, |b| b.min(bytes));
+        self.max_payload_bytes = Some(limit);
+    }
+
+    // check the schema version recorded in the WAL directory, recording it if missing
+    pub(crate) fn check_schema(&self, location: &Path, create: bool) -> Result<(), WalError> {
+        let expected = match self.schema_version {
+            None => return Ok(()),
+            Some(v) => v,
+        };
+        let path = location.join("schema");
+        match std::fs::read_to_string(&path) {
+            Ok(text) => {
+                let found = text
+                    .trim()
+                    .parse::<u32>()
+                    .map_err(|_| WalError::Corruption {
+                        path: path.clone(),
+                        segment: None,
+                        offset: None,
+                        reason: "schema version isn't a number".to_string(),
+                    })?;
+                if found != expected {
+                    return Err(WalError::Rejected(Rejection::SchemaMismatch {
+                        expected,
+                        found,
+                    }));
+                }
+                Ok(())
+            }
+            // a read-only WAL is read whatever its version is
+            Err(_) if !create => Ok(()),
+            Err(_) => std::fs::write(&path, expected.to_string())
+                .map_err(|e| WalError::io(e, "Failed to write schema version file")),
+        }
+    }
+
+    // serialize a log into `buffer`, enforcing all the rules
+    pub(crate) fn encode<T: Serialize>(
+        &self,
+        data: &T,
+        codec: Codec,
+        buffer: Vec<u8>,
+    ) -> Result<LogEntry, WalError> {
+        if self.reject_non_finite {
+            let mut check = FloatCheck::default();
+            if let Err(e) = data.serialize(&mut check) {
+                return Err(match e {
+                    CheckError::NonFinite(path) => {
+                        WalError::Rejected(Rejection::NonFiniteFloat { path })
+                    }
+                    CheckError::Custom(msg) => WalError::Serialization(msg),
+                });
+            }
+        }
+        let entry = codec.encode_into(data, buffer)?;
+        self.check_size(&entry)?;
+        Ok(entry)
+    }
+
+    // enforce the payload size rules on an already serialized log
+    pub(crate) fn check_size(&self, entry: &LogEntry) -> Result<(), WalError> {
+        if self.reject_empty && entry.size() == 0 {
+            return Err(WalError::Rejected(Rejection::EmptyPayload));
+        }
+        if let Some(limit) = self.max_payload_bytes {
+            if entry.size() > limit {
+                return Err(WalError::Rejected(Rejection::PayloadTooLarge {
+                    size: entry.size(),
+                    limit,
+                }));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Serializer walking a value only to find non-finite floats, tracking the path to each field
+#[derive(Default)]
+struct FloatCheck {
+    path: Vec<String>,
+}
+
+#[derive(Debug)]
+enum CheckError {
+    NonFinite(String),
+    Custom(String),
+}
+
+impl Display for CheckError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CheckError::NonFinite(path) => write!(f, "non-finite float at `{}`", path),
+            CheckError::Custom(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for CheckError {}
+
+impl ser::Error for CheckError {
+    fn custom<M: Display>(msg: M) -> Self {
+        CheckError::Custom(msg.to_string())
+    }
+}
+
+impl FloatCheck {
+    fn float(&self, finite: bool) -> Result<(), CheckError> {
+        if finite {
+            return Ok(());
+        }
+        let path = if self.path.is_empty() {
+            "<root>".to_string()
+        } else {
+            self.path.join(".")
+        };
+        Err(CheckError::NonFinite(path))
+    }
+
+    fn nested<V: Serialize + ?Sized>(&mut self, key: String, value: &V) -> Result<(), CheckError> {
+        self.path.push(key);
+        let out = value.serialize(&mut *self);
+        self.path.pop();
+        out
+    }
+}
+
+// Compound values keep count of their elements for the field path
+struct Compound<'a> {
+    check: &'a mut FloatCheck,
+    index: usize,
+}
+
+impl Compound<'_> {
+    fn element<V: Serialize + ?Sized>(&mut self, value: &V) -> Result<(), CheckError> {
+        let key = self.index.to_string();
+        self.index += 1;
+        self.check.nested(key, value)
+    }
+}
+
+impl<'a> ser::Serializer for &'a mut FloatCheck {
+    type Ok = ();
+    type Error = CheckError;
+    type SerializeSeq = Compound<'a>;
+    type SerializeTuple = Compound<'a>;
+    type SerializeTupleStruct = Compound<'a>;
+    type SerializeTupleVariant = Compound<'a>;
+    type SerializeMap = Compound<'a>;
+    type SerializeStruct = Compound<'a>;
+    type SerializeStructVariant = Compound<'a>;
+
+    fn serialize_bool(self, _: bool) -> Result<(), CheckError> {
+        Ok(())
+    }
+    fn serialize_i8(self, _: i8) -> Result<(), CheckError> {
+        Ok(())
+    }
+    fn serialize_i16(self, _: i16) -> Result<(), CheckError> {
+        Ok(())
+    }
+    fn serialize_i32(self, _: i32) -> Result<(), CheckError> {
+        Ok(())
+    }
+    fn serialize_i64(self, _: i64) -> Result<(), CheckError> {
+        Ok(())
+    }
+    fn serialize_u8(self, _: u8) -> Result<(), CheckError> {
+        Ok(())
+    }
+    fn serialize_u16(self, _: u16) -> Result<(), CheckError> {
+        Ok(())
+    }
+    fn serialize_u32(self, _: u32) -> Result<(), CheckError> {
+        Ok(())
+    }
+    fn serialize_u64(self, _: u64) -> Result<(), CheckError> {
+        Ok(())
+    }
+    fn serialize_f32(self, v: f32) -> Result<(), CheckError> {
+        self.float(v.is_finite())
+    }
+    fn serialize_f64(self, v: f64) -> Result<(), CheckError> {
+        self.float(v.is_finite())
+    }
+    fn serialize_char(self, _: char) -> Result<(), CheckError> {
+        Ok(())
+    }
+    fn serialize_str(self, _: &str) -> Result<(), CheckError> {
+        Ok(())
+    }
+    fn serialize_bytes(self, _: &[u8]) -> Result<(), CheckError> {
+        Ok(())
+    }
+    fn serialize_none(self) -> Result<(), CheckError> {
+        Ok(())
+    }
+    fn serialize_some<V: Serialize + ?Sized>(self, value: &V) -> Result<(), CheckError> {
+        value.serialize(self)
+    }
+    fn serialize_unit(self) -> Result<(), CheckError> {
+        Ok(())
+    }
+    fn serialize_unit_struct(self, _: &'static str) -> Result<(), CheckError> {
+        Ok(())
+    }
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        _: &'static str,
+    ) -> Result<(), CheckError> {
+        Ok(())
+    }
+    fn serialize_newtype_struct<V: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &V,
+    ) -> Result<(), CheckError> {
+        value.serialize(self)
+    }
+    fn serialize_newtype_variant<V: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        value: &V,
+    ) -> Result<(), CheckError> {
+        self.nested(variant.to_string(), value)
+    }
+    fn serialize_seq(self, _: Option<usize>) -> Result<Compound<'a>, CheckError> {
+        Ok(Compound {
+            check: self,
+            index: 0,
+        })
+    }
+    fn serialize_tuple(self, _: usize) -> Result<Compound<'a>, CheckError> {
+        self.serialize_seq(None)
+    }
+    fn serialize_tuple_struct(self, _: &'static str, _: usize) -> Result<Compound<'a>, CheckError> {
+        self.serialize_seq(None)
+    }
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        _: usize,
+    ) -> Result<Compound<'a>, CheckError> {
+        self.path.push(variant.to_string());
+        self.serialize_seq(None)
+    }
+    fn serialize_map(self, _: Option<usize>) -> Result<Compound<'a>, CheckError> {
+        self.serialize_seq(None)
+    }
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<Compound<'a>, CheckError> {
+        self.serialize_seq(None)
+    }
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        _: usize,
+    ) -> Result<Compound<'a>, CheckError> {
+        self.path.push(variant.to_string());
+        self.serialize_seq(None)
+    }
+}
+
+impl ser::SerializeSeq for Compound<'_> {
+    type Ok = ();
+    type Error = CheckError;
+
+    fn serialize_element<V: Serialize + ?Sized>(&mut self, value: &V) -> Result<(), CheckError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), CheckError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTuple for Compound<'_> {
+    type Ok = ();
+    type Error = CheckError;
+
+    fn serialize_element<V: Serialize + ?Sized>(&mut self, value: &V) -> Result<(), CheckError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), CheckError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleStruct for Compound<'_> {
+    type Ok = ();
+    type Error = CheckError;
+
+    fn serialize_field<V: Serialize + ?Sized>(&mut self, value: &V) -> Result<(), CheckError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), CheckError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeTupleVariant for Compound<'_> {
+    type Ok = ();
+    type Error = CheckError;
+
+    fn serialize_field<V: Serialize + ?Sized>(&mut self, value: &V) -> Result<(), CheckError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), CheckError> {
+        self.check.path.pop();
+        Ok(())
+    }
+}
+
+impl ser::SerializeMap for Compound<'_> {
+    type Ok = ();
+    type Error = CheckError;
+
+    fn serialize_key<V: Serialize + ?Sized>(&mut self, key: &V) -> Result<(), CheckError> {
+        self.check.nested(format!("key{}", self.index), key)
+    }
+    fn serialize_value<V: Serialize + ?Sized>(&mut self, value: &V) -> Result<(), CheckError> {
+        self.element(value)
+    }
+    fn end(self) -> Result<(), CheckError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStruct for Compound<'_> {
+    type Ok = ();
+    type Error = CheckError;
+
+    fn serialize_field<V: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &V,
+    ) -> Result<(), CheckError> {
+        self.check.nested(key.to_string(), value)
+    }
+    fn end(self) -> Result<(), CheckError> {
+        Ok(())
+    }
+}
+
+impl ser::SerializeStructVariant for Compound<'_> {
+    type Ok = ();
+    type Error = CheckError;
+
+    fn serialize_field<V: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &V,
+    ) -> Result<(), CheckError> {
+        self.check.nested(key.to_string(), value)
+    }
+    fn end(self) -> Result<(), CheckError> {
+        self.check.path.pop();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    struct Reading {
+        sensor: u32,
+        values: Vec<f64>,
+        extra: Option<Inner>,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Inner {
+        ratio: f32,
+    }
+
+    fn reading(values: Vec<f64>, ratio: f32) -> Reading {
+        Reading {
+            sensor: 1,
+            values,
+            extra: Some(Inner { ratio }),
+        }
+    }
+
+    #[test]
+    fn non_finite_floats() {
+        let rules = Validation::new().reject_non_finite_floats();
+        assert!(rules
+            .encode(&reading(vec![1.0, 2.5], 0.5), Codec::Bincode, Vec::new())
+            .is_ok());
+        let err = rules
+            .encode(
+                &reading(vec![1.0, f64::NAN], 0.5),
+                Codec::Bincode,
+                Vec::new(),
+            )
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            WalError::Rejected(Rejection::NonFiniteFloat { path }) if path == "values.1"
+        ));
+        let err = rules
+            .encode(&reading(vec![], f32::INFINITY), Codec::Bincode, Vec::new())
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            WalError::Rejected(Rejection::NonFiniteFloat { path }) if path == "extra.ratio"
+        ));
+        // NaN is accepted unless rejected explicitly
+        assert!(Validation::new()
+            .encode(&f64::NAN, Codec::Bincode, Vec::new())
+            .is_ok());
+    }
+
+    #[test]
+    fn payload_size() {
+        let rules = Validation::new().max_payload_bytes(8);
+        assert!(rules.encode(&1u64, Codec::Bincode, Vec::new()).is_ok());
+        let err = rules
+            .encode(&"too long for the limit", Codec::Bincode, Vec::new())
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            WalError::Rejected(Rejection::PayloadTooLarge { limit: 8, .. })
+        ));
+    }
+
+    #[test]
+    fn empty_payload() {
+        assert!(Validation::new()
+            .encode(&(), Codec::Bincode, Vec::new())
+            .is_ok());
+        let rules = Validation::new().reject_empty_payloads();
+        assert!(rules.encode(&0u8, Codec::Bincode, Vec::new()).is_ok());
+        let err = rules.encode(&(), Codec::Bincode, Vec::new()).unwrap_err();
+        assert!(matches!(err, WalError::Rejected(Rejection::EmptyPayload)));
+    }
+}
@@ -0,0 +1,129 @@
+use crate::layout::Layout;
+use crate::scrub::{self, Health};
+use crate::segment::SegmentDigest;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+/// When sealed files are checked against the digests recorded by
+/// [crate::WalBuilder::verify_on_rotation] as the WAL is opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[non_exhaustive]
+pub enum OpenVerification {
+    /// Files are not checked when the WAL is opened
+    #[default]
+    Skip,
+    /// Every file is checked before the WAL is opened
+    Eager,
+    /// Files are checked by a background thread, so logs can be written right away
+    ///
+    /// Reads made before every file is checked wait for the check when `block_reads` is set, and
+    /// are served from files that may not be checked yet otherwise, see [crate::Wal::verified].
+    Deferred {
+        /// Reads wait until every file is checked
+        block_reads: bool,
+    },
+}
+
+// Check of the sealed files started when the WAL was opened
+// Corrupted files are reported in the health of the WAL, like the scrubber does
+pub(crate) struct Verifier {
+    progress: Mutex<Progress>,
+    finished: Condvar,
+    block_reads: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Progress {
+    files: u64,
+    verified: u64,
+    finished: bool,
+}
+
+impl Verifier {
+    pub fn start(
+        location: &Path,
+        layout: &Layout,
+        mode: OpenVerification,
+        health: &Arc<Mutex<Health>>,
+    ) -> Arc<Self> {
+        let digests = match mode {
+            OpenVerification::Skip => Vec::new(),
+            _ => scrub::digests(location),
+        };
+        let verifier = Arc::new(Self {
+            progress: Mutex::new(Progress {
+                files: digests.len() as u64,
+                verified: 0,
+                finished: false,
+            }),
+            finished: Condvar::new(),
+            block_reads: mode == OpenVerification::Deferred { block_reads: true },
+        });
+        let task = {
+            let (verifier, location, layout) =
+                (verifier.clone(), location.to_path_buf(), layout.clone());
+            let health = health.clone();
+            move || verifier.verify(&location, &layout, &digests, &health)
+        };
+        if let OpenVerification::Deferred { .. } = mode {
+            let spawned = std::thread::Builder::new()
+                .name("walcraft-verify".to_string())
+                .spawn(task);
+            // files are still checked when no thread can be started
+            if spawned.is_err() {
+                let digests = scrub::digests(location);
+                verifier.verify(location, layout, &digests, health);
+            }
+        } else {
+            task();
+        }
+        verifier
+    }
+
+    fn verify(
+        &self,
+        location: &Path,
+        layout: &Layout,
+        digests: &[SegmentDigest],
+        health: &Mutex<Health>,
+    ) {
+        for digest in digests {
+            let outcome = scrub::check(location, layout, digest);
+            health
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .record(outcome);
+            self.progress().verified += 1;
+        }
+        self.progress().finished = true;
+        self.finished.notify_all();
+    }
+
+    fn progress(&self) -> MutexGuard<'_, Progress> {
+        self.progress.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Wait until every file is checked, when reads must wait for it
+    pub fn wait_for_reads(&self) {
+        if !self.block_reads {
+            return;
+        }
+        let mut progress = self.progress();
+        while !progress.finished {
+            progress = self
+                .finished
+                .wait(progress)
+                .unwrap_or_else(|e| e.into_inner());
+        }
+    }
+
+    pub fn finished(&self) -> bool {
+        self.progress().finished
+    }
+
+    // files checked so far, and files left to check
+    pub fn counts(&self) -> (u64, u64) {
+        let progress = self.progress();
+        (progress.verified, progress.files - progress.verified)
+    }
+}
@@ -1,20 +1,154 @@
+use crate::ack::Acks;
+use crate::archive::{ArchivedSegment, SharedArchiver};
+use crate::blob::BlobStore;
 use crate::buffer::Buffer;
+use crate::builder::{SyncPolicy, WakeStrategy};
+use crate::chain;
+#[cfg(feature = "chaos")]
+use crate::chaos::ChaosMonkey;
+use crate::checkpoint::SharedCheckpointer;
+use crate::checksum;
+use crate::clock::Monotonic;
+use crate::compaction::{self, KeyFn, KeySketch, MergeFn, RetainFn};
+use crate::compression::{Compression, CompressionCodec};
+use crate::cursor::Consumers;
+use crate::durable;
+use crate::encryption::Cipher;
+use crate::entry::LogEntry;
+use crate::event::{FlushEvent, Listeners, RotationEvent};
+use crate::eviction::{CapacityMonitor, Eviction, OnCapacityWarning, OnEvict};
+use crate::failure::{Failures, WriteOperation};
+use crate::flush::{FlushPolicy, SegmentEncoding, SegmentFile, SegmentWindow};
+use crate::format::{self, FormatVersion, Framing, SEGMENT_HEADER_BYTES};
+use crate::key_filter::KeyFilter;
+use crate::layout::Layout;
 use crate::lock::LockManager;
-use crate::WalError;
+use crate::manifest::{Manifest, ManifestKind};
+use crate::meta::Meta;
+use crate::pacing::{Pacer, Pacing};
+use crate::pipeline::SyncStage;
+use crate::reader::WalReader;
+use crate::scratch::Scratch;
+use crate::segment::{OnSeal, SegmentDigest, SegmentSealed, SegmentSpan};
+use crate::segment_index::SegmentIndex;
+use crate::slots::Slots;
+use crate::stats::{self, LatencyHistogram, Metrics, RotationHistory, RotationRecord};
+use crate::storage::{Segment, SharedStorage};
+use crate::subscribe::Subscribers;
+use crate::truncation::{self, Truncation};
+#[cfg(all(feature = "io-uring", target_os = "linux"))]
+use crate::uring::{RingFile, Uring};
+use crate::{Lsn, WalError};
+use std::collections::HashSet;
 use std::fs::{File, OpenOptions};
-use std::io::Write;
-use std::path::PathBuf;
-use std::sync::mpsc::Receiver;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::SyncSender;
+use std::sync::{Arc, Mutex, TryLockError};
 use std::thread::sleep;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime};
+
+// largest encoding buffer kept from one batch to the next, larger ones are freed after a burst
+const KEPT_FRAMES_BYTES: usize = 4 * 1024 * 1024;
+
+// Message from the Wal interface to the writer thread
+pub(crate) enum Signal {
+    // new logs were added to the buffer
+    Logs,
+    // compact all sealed files, replying with the number of removed logs
+    Compact(SyncSender<Result<u64, WalError>>),
+    // drop the logs of sealed files rejected by the function, replying with the number of
+    // removed logs
+    Retain(RetainFn, SyncSender<Result<u64, WalError>>),
+    // remove the oldest files holding only logs up to the given one, replying with the number
+    // of removed files
+    Truncate(Lsn, SyncSender<Result<usize, WalError>>),
+    // remove all files and start over from the first one, replying once done
+    Clear(SyncSender<Result<(), WalError>>),
+    // cut sealed files to their last valid frame and write the meta file again, replying with
+    // the ids of the files cut
+    Repair(SyncSender<Result<Vec<u8>, WalError>>),
+    // index the sealed files without an index, replying with the number of files indexed
+    Index(SyncSender<Result<usize, WalError>>),
+    // write all logs of the buffer and sync the current file, replying once done
+    Flush(FlushReply),
+    // limit the writer with another pacing from now on
+    Pace(Pacing),
+}
+
+// Where the writer replies once logs are flushed, to a blocked thread or to a task
+pub(crate) enum FlushReply {
+    Blocking(SyncSender<Result<(), WalError>>),
+    #[cfg(feature = "tokio")]
+    Async(tokio::sync::oneshot::Sender<Result<(), WalError>>),
+}
+
+impl FlushReply {
+    fn send(self, result: Result<(), WalError>) {
+        // the waiting side may have given up
+        let _ = match self {
+            Self::Blocking(reply) => reply.send(result).ok(),
+            #[cfg(feature = "tokio")]
+            Self::Async(reply) => reply.send(result).ok(),
+        };
+    }
+}
+
+// Content rewritten over a WAL file by its reader, None to keep the file as is
+type Rewrite = fn(&WalReader, &[u8]) -> Option<Vec<u8>>;
 
 // Arguments or properties needed to create a [WalWriter] instance
+#[derive(Clone)]
 pub(crate) struct WalWriterProps {
     pub buffer: Buffer,
     pub location: PathBuf,
-    pub receiver: Receiver<()>,
     pub lock: LockManager,
+    pub read_lock: Arc<Mutex<Scratch>>,
     pub capacity: usize,
+    pub positional_writes: bool,
+    pub preallocate: bool,
+    pub wake_strategy: WakeStrategy,
+    pub sync_policy: SyncPolicy,
+    pub compress_above: Option<usize>,
+    pub compression_codec: CompressionCodec,
+    pub cipher: Option<Cipher>,
+    pub max_expansion: usize,
+    pub record_size: Option<usize>,
+    pub framing: Framing,
+    pub checksums: bool,
+    pub verify_on_rotation: bool,
+    pub segment_index: bool,
+    pub manifest: ManifestKind,
+    pub on_evict: Option<OnEvict>,
+    pub on_seal: Option<OnSeal>,
+    pub failures: Failures,
+    pub metrics: Arc<Metrics>,
+    pub listeners: Listeners,
+    pub checkpointer: Option<SharedCheckpointer>,
+    pub archiver: Option<SharedArchiver>,
+    pub capacity_warnings: Option<(Vec<f64>, OnCapacityWarning)>,
+    pub consumers: Consumers,
+    pub key: Option<KeyFn>,
+    pub merge: Option<MergeFn>,
+    pub compaction_threshold: Option<f64>,
+    pub latency: Arc<Mutex<LatencyHistogram>>,
+    pub rotations: Arc<Mutex<RotationHistory>>,
+    pub dedup_above: Option<usize>,
+    pub segment_window: Option<SegmentWindow>,
+    pub segment_size: Option<usize>,
+    pub retain_segments: Option<usize>,
+    pub retain_for: Option<Duration>,
+    pub timestamps: bool,
+    pub strict_timestamps: bool,
+    pub subscribers: Subscribers,
+    #[cfg(feature = "chaos")]
+    pub chaos: Option<crate::Chaos>,
+    #[cfg(test)]
+    pub faults: Option<crate::faults::Faults>,
+    pub layout: Layout,
+    pub pacing: Pacing,
+    pub acks: Acks,
+    pub slots: Option<Slots>,
 }
 
 // Writer responsible for saving logs on secondary storage
@@ -24,137 +158,1651 @@ pub(crate) struct WalWriter {
     buffer: Buffer,
     // Location where files are stored
     location: PathBuf,
-    // Notifier from Wal interface about new log addition
-    receiver: Receiver<()>,
     // Handle to current file
-    file: File,
+    file: Segment,
     // Lock manager to switch between read and write mode for file IO
     lock: LockManager,
-    // storage capacity per file
-    capacity_per_file: usize,
+    // Lock held by reads for as long as they read files, kept while files change in place
+    read_lock: Arc<Mutex<Scratch>>,
+    // how batches of logs are encoded and written
+    policy: FlushPolicy,
     // storage capacity filled in the current file
     filled: usize,
+    // number of logs in the current file
+    file_entries: u64,
     // file sequence number for the current file
     pointer: u8,
+    // logical write offset in the current file
+    offset: u64,
+    // how frames are written to the current file
+    encoding: SegmentEncoding,
+    // when the writer started filling the current file
+    opened_at: Instant,
+    // reserve the storage of every file when it's started
+    preallocate: bool,
+    // re-read and digest every file when moving on to the next one
+    verify_on_rotation: bool,
+    // index the logs of every file when moving on to the next one
+    segment_index: bool,
+    // digests of sealed files
+    digests: Vec<SegmentDigest>,
+    // store of the current pointer, write offset and digests
+    manifest: Manifest,
+    // callback notified before a file is reused
+    on_evict: Option<OnEvict>,
+    // callback notified of every sealed file
+    on_seal: Option<OnSeal>,
+    // failures to store logs and metadata, reported to the handles
+    failures: Failures,
+    // counters of writes, syncs and rotations, reported to the handles
+    metrics: Arc<Metrics>,
+    // receivers of flushes and rotations
+    listeners: Listeners,
+    // snapshot saved before logs are dropped
+    checkpointer: Option<SharedCheckpointer>,
+    // destination of files before their logs are dropped
+    archiver: Option<SharedArchiver>,
+    // warnings before logs are dropped to stay within the capacity
+    capacity_monitor: Option<CapacityMonitor>,
+    // read positions of the handles consuming the WAL
+    consumers: Consumers,
+    // bytes stored in the files other than the current one, None once they changed
+    sealed_bytes: Option<u64>,
+    // link of the file sealed last in the hash chain of sealed files
+    chain: [u8; 32],
+    // key of logs in keyed mode
+    key: Option<KeyFn>,
+    // accumulation of logs with the same key by compaction, instead of keeping the last one
+    merge: Option<MergeFn>,
+    // share of dead logs in a sealed file that triggers its compaction
+    compaction_threshold: Option<f64>,
+    // estimated dead logs of every file, indexed by pointer - 1
+    sketches: Vec<KeySketch>,
+    // time sampled logs took from the buffer to a file
+    latency: Arc<Mutex<LatencyHistogram>>,
+    // rotations since the WAL was opened
+    rotations: Arc<Mutex<RotationHistory>>,
+    // acknowledgments of logs once they are durable
+    acks: Acks,
+    // slots of a WAL written by several processes, numbering the batches of this one across them
+    slots: Option<Slots>,
+    // store of large payloads, referenced by the frames of their logs
+    blobs: Option<BlobStore>,
+    // pauses keeping the writer within the resources it's allowed to use
+    pacer: Pacer,
+    // syncs a written batch while the next one is framed, when batches are synced
+    sync_stage: Option<SyncStage<File>>,
+    // bytes written to the current file since it was last synced, and when that was
+    unsynced: usize,
+    synced_at: Instant,
+    // time sampled logs written since the last sync were added to the buffer
+    unsynced_samples: Vec<Instant>,
+    // buffer the frames of every batch are encoded in
+    frames: Vec<u8>,
+    // ring writing and syncing the current file, unless the kernel lacks io_uring
+    #[cfg(all(feature = "io-uring", target_os = "linux"))]
+    ring: Option<Uring>,
+    // number and names of the WAL files
+    layout: Layout,
+    // number of sealed files kept when moving on to the next file
+    retain_segments: Option<usize>,
+    // how long sealed files are kept after their last log was written, and when the oldest
+    // one expires
+    retain_for: Option<Duration>,
+    expiry: Option<Instant>,
+    // time ranges of the logs of sealed files, and of the current file once written to
+    spans: Vec<SegmentSpan>,
+    span: Option<SegmentSpan>,
+    // newest stamp written and monotonic clock of the logs, when stamps are kept in order
+    last_stamp: Option<u64>,
+    monotonic: Option<Monotonic>,
+    // receivers of the logs once written
+    subscribers: Subscribers,
+    // failures and delays injected in storage for chaos tests
+    #[cfg(feature = "chaos")]
+    chaos: Option<ChaosMonkey>,
+    // faults injected in the current file by crash tests
+    #[cfg(test)]
+    faults: Option<crate::faults::Faults>,
 }
 
 impl WalWriter {
     pub fn new(props: WalWriterProps) -> Result<Self, WalError> {
-        let pointer = 1u8;
-        let (file, filled) = Self::set_pointer(props.location.clone(), pointer, false)?;
-        Ok(Self {
+        let storage = props.layout.storage.clone();
+        // files left by a crash while recycling or converting a file replaced nothing yet
+        for id in props.layout.ids() {
+            let path = props.layout.hot_path(&props.location, id);
+            for scratch in ["recycle", "convert", "replace"] {
+                let _ = std::fs::remove_file(path.with_extension(scratch));
+            }
+        }
+        let mut manifest = Manifest::open(&props.location, props.manifest);
+        let mut recorded = manifest.load().ok();
+        // logs are appended to the current file recorded in meta, which was checked against the
+        // WAL files when they were opened, and a new WAL starts with the first file
+        let pointer = recorded
+            .as_ref()
+            .map(|m| m.pointer)
+            .filter(|p| props.layout.ids().contains(p))
+            .unwrap_or(1);
+        // the file written again is brought back from the cold location
+        let path = props.layout.hot_path(&props.location, pointer);
+        if let Some(cold) = props.layout.cold_path(pointer) {
+            if !storage.exists(&path) && storage.exists(&cold) {
+                Self::relocate(&storage, &props.lock, &cold, &path)
+                    .map_err(|e| WalError::io(e, "Failed to bring back log file"))?;
+            }
+        }
+        let reader = WalReader::new(props.location.clone())
+            .fixed(props.record_size)
+            .framing(props.framing)
+            .checksums(props.checksums);
+        // logs are appended in little endian, so a file of another byte order is converted first,
+        // and encrypted with a new nonce when payloads are
+        let reader = reader.cipher(props.cipher.clone());
+        let rewrites: [(Rewrite, &str); 2] = [
+            (
+                |reader, content| reader.to_little_endian(content),
+                "Failed to convert log file to little endian",
+            ),
+            (
+                |reader, content| match reader.needs_reseal(content) {
+                    true => reader.reseal(content),
+                    false => None,
+                },
+                "Failed to encrypt log file",
+            ),
+        ];
+        for (rewrite, failure) in rewrites {
+            let rewritten = Self::rewrite_file(&storage, &path, &reader, rewrite, failure)?;
+            if let Some((before, after)) = rewritten {
+                if let Some(meta) = recorded.as_mut().filter(|m| m.pointer == pointer) {
+                    if meta.offset == Some(before) {
+                        meta.offset = Some(after);
+                    }
+                }
+            }
+        }
+        let encoding = SegmentEncoding {
+            nonce: format::file_nonce(&storage, &path),
+            checksummed: Self::checksummed(
+                &storage,
+                &path,
+                props.checksums && props.record_size.is_none() && props.framing == Framing::Native,
+            ),
+        };
+        let (file, offset) = if props.positional_writes {
+            Self::resume_at_offset(&path, pointer, recorded.as_ref())?
+        } else {
+            Self::trim_torn_tail(&storage, &path, &reader)?;
+            let file = Self::open_file(&storage, &path, false)?;
+            let offset = Self::file_size(&file)?;
+            (file, offset)
+        };
+        // the file being written again outgrows its key filter and its index
+        KeyFilter::remove(&props.location, pointer);
+        SegmentIndex::remove(&props.location, pointer);
+        // the chain goes on from the file sealed last, even when its digest is dropped below
+        let chain = recorded
+            .as_ref()
+            .and_then(|m| m.chain)
+            .unwrap_or(chain::GENESIS);
+        // digests and time ranges of sealed files are kept, except for the file being written again
+        let spans = recorded
+            .as_ref()
+            .map(|m| m.spans.clone())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|s| s.id != pointer)
+            .collect();
+        let digests = recorded
+            .map(|m| m.digests)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|d| d.id != pointer)
+            .collect();
+        // blob references need the flags of native frames
+        let blobs = props
+            .dedup_above
+            .filter(|_| props.record_size.is_none() && props.framing == Framing::Native)
+            .map(|threshold| BlobStore::new(&props.location, threshold));
+        // one file is being overwritten while the others fill the capacity
+        let per_file = props
+            .segment_size
+            .unwrap_or(props.capacity / (props.layout.segments as usize - 1));
+        let mut writer = Self {
             buffer: props.buffer,
             location: props.location,
-            receiver: props.receiver,
             file,
             lock: props.lock,
-            capacity_per_file: props.capacity / 4,
-            filled,
+            read_lock: props.read_lock,
+            policy: FlushPolicy {
+                wake_strategy: props.wake_strategy,
+                compression: props.compress_above.map(|above| Compression {
+                    above,
+                    max_expansion: props.max_expansion,
+                    codec: props.compression_codec,
+                }),
+                cipher: props.cipher,
+                max_expansion: props.max_expansion,
+                record_size: props.record_size,
+                framing: props.framing,
+                checksums: props.checksums,
+                positional_writes: props.positional_writes,
+                capacity_per_file: props
+                    .segment_window
+                    .map_or(per_file, |w| per_file.clamp(w.min, w.max)),
+                sync: props.sync_policy,
+                window: props.segment_window,
+            },
+            // the file written again already holds logs
+            filled: offset as usize,
+            file_entries: 0,
             pointer,
+            offset,
+            encoding,
+            opened_at: Instant::now(),
+            verify_on_rotation: props.verify_on_rotation,
+            segment_index: props.segment_index,
+            preallocate: props.preallocate,
+            digests,
+            manifest,
+            on_evict: props.on_evict,
+            on_seal: props.on_seal,
+            failures: props.failures,
+            metrics: props.metrics,
+            listeners: props.listeners,
+            checkpointer: props.checkpointer,
+            archiver: props.archiver,
+            capacity_monitor: props
+                .capacity_warnings
+                .map(|(thresholds, notify)| CapacityMonitor::new(thresholds, notify)),
+            consumers: props.consumers,
+            sealed_bytes: None,
+            chain,
+            key: props.key,
+            merge: props.merge,
+            compaction_threshold: props.compaction_threshold,
+            sketches: props.layout.ids().map(|_| KeySketch::new()).collect(),
+            latency: props.latency,
+            rotations: props.rotations,
+            acks: props.acks,
+            slots: props.slots,
+            blobs,
+            pacer: Pacer::new(props.pacing, Instant::now()),
+            sync_stage: None,
+            unsynced: 0,
+            synced_at: Instant::now(),
+            unsynced_samples: Vec::new(),
+            frames: Vec::new(),
+            #[cfg(all(feature = "io-uring", target_os = "linux"))]
+            ring: Uring::new(),
+            layout: props.layout,
+            retain_segments: props.retain_segments,
+            // files may have expired while the WAL was closed
+            retain_for: props.retain_for,
+            expiry: props.retain_for.map(|_| Instant::now()),
+            spans,
+            span: None,
+            last_stamp: None,
+            monotonic: None,
+            subscribers: props.subscribers,
+            #[cfg(feature = "chaos")]
+            chaos: props.chaos.map(ChaosMonkey::new),
+            #[cfg(test)]
+            faults: props.faults,
+        };
+        // without the stage, batches are synced by the writer thread itself
+        if writer.policy.syncs() {
+            writer.sync_stage = SyncStage::spawn(
+                writer.latency.clone(),
+                writer.failures.clone(),
+                writer.metrics.clone(),
+                writer.acks.clone(),
+            )
+            .ok();
+        }
+        if writer.offset > 0 {
+            writer.file_entries = writer.digest(writer.pointer, None).entries;
+        }
+        writer.span = writer.resume_span(props.timestamps);
+        // stamps go on from the newest log, whatever the clock says now
+        if props.strict_timestamps {
+            let spans = writer.spans.iter().chain(&writer.span);
+            writer.last_stamp = Some(spans.map(|s| s.last).max().unwrap_or_default());
+            writer.monotonic = Some(Monotonic::after(writer.newest_monotonic()));
+        }
+        writer.reserve_file();
+        // a new file starts with a header
+        if writer.offset == 0 {
+            (writer.offset, writer.encoding) = writer
+                .policy
+                .start_file(&mut writer.file)
+                .map_err(|e| WalError::io(e, "Failed to write log file header"))?;
+            writer.filled = writer.offset as usize;
+        }
+        writer.write_meta()?;
+        Ok(writer)
+    }
+
+    // Do the work due by now: remove expired files, and sync written logs in time even when no
+    // more logs come
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        if self.expiry.is_some_and(|at| at <= now) {
+            self.expire_sealed();
+        }
+        if self.sync_at().is_some_and(|at| at <= now) {
+            self.sync();
+        }
+    }
+
+    // When work is due next without a signal, None to wait for signals only
+    pub fn deadline(&self) -> Option<Instant> {
+        self.sync_at().into_iter().chain(self.expiry).min()
+    }
+
+    // when written logs are due for a sync, None unless synced at intervals
+    fn sync_at(&self) -> Option<Instant> {
+        match (self.policy.sync, self.unsynced) {
+            (SyncPolicy::Interval(interval), 1..) => Some(self.synced_at + interval),
+            _ => None,
+        }
+    }
+
+    // Handle a signal from the handles of the WAL
+    pub fn handle(&mut self, signal: Signal) {
+        match signal {
+            Signal::Compact(reply) => {
+                let _ = reply.send(self.compact_all());
+                self.sealed_bytes = None;
+                return;
+            }
+            Signal::Retain(retain, reply) => {
+                let _ = reply.send(self.retain_all(&retain));
+                self.sealed_bytes = None;
+                return;
+            }
+            Signal::Truncate(through, reply) => {
+                let _ = reply.send(self.truncate(through));
+                self.sealed_bytes = None;
+                return;
+            }
+            Signal::Clear(reply) => {
+                let _ = reply.send(self.clear());
+                self.sealed_bytes = None;
+                return;
+            }
+            Signal::Repair(reply) => {
+                let _ = reply.send(self.repair());
+                self.sealed_bytes = None;
+                return;
+            }
+            Signal::Index(reply) => {
+                let _ = reply.send(self.index_all());
+                return;
+            }
+            Signal::Flush(reply) => {
+                reply.send(self.flush());
+                return;
+            }
+            Signal::Pace(pacing) => {
+                self.pacer.set(pacing);
+                return;
+            }
+            Signal::Logs => {}
+        }
+
+        // give producers a moment to add more logs to the batch
+        if let Some(delay) = self.policy.delay() {
+            sleep(delay);
+        }
+        // or gather a group of logs, written and synced at once
+        if let Some((within, bytes)) = self.policy.group() {
+            self.buffer.wait_for_bytes(bytes, within);
+        }
+
+        let started = Instant::now();
+        let written = self.write_batch();
+        if written == 0 {
+            return;
+        }
+
+        // stay within the allowed bandwidth and CPU time, while logs pile up in the buffer
+        if let Some(pause) = self.pacer.delay(written, started.elapsed(), Instant::now()) {
+            sleep(pause);
+        }
+    }
+
+    // failures of the writer, reported to the handles
+    pub fn failures(&self) -> &Failures {
+        &self.failures
+    }
+
+    // All handles were dropped, write what's left
+    pub fn close(mut self) {
+        let _ = self.flush();
+    }
+
+    // Write all logs of the buffer and sync the current file
+    fn flush(&mut self) -> Result<(), WalError> {
+        let _span = span!("walcraft.flush");
+        self.delay_flush();
+        while self.write_batch() > 0 {}
+        if let Some(stage) = &mut self.sync_stage {
+            stage.wait();
+        }
+        let through = self.acks.last_written();
+        self.inject_sync_failure()
+            .and_then(|_| stats::timed_sync(&self.metrics.clone(), || self.sync_file()))
+            .map_err(|e| {
+                self.failures.io(WriteOperation::Sync, &e, None);
+                self.acks.lost(through);
+                WalError::io(e, "Failed to sync log file")
+            })?;
+        self.acks.durable(through);
+        stats::record_latency(&self.latency, &self.unsynced_samples);
+        self.unsynced_samples.clear();
+        self.unsynced = 0;
+        self.synced_at = Instant::now();
+        Ok(())
+    }
+
+    // Write the logs of the buffer to the current file, moving on to the next file once it's
+    // filled, and return the number of bytes written
+    fn write_batch(&mut self) -> usize {
+        // a batch larger than a whole file is written to a file of its own, instead of
+        // overflowing a file already holding logs
+        let depth = self.buffer.depth();
+        if self.policy.oversized(depth.logs, depth.bytes)
+            && format::holds_frames(
+                &self.layout.storage,
+                &self.layout.hot_path(&self.location, self.pointer),
+            )
+        {
+            self.next_file();
+        }
+        // take all existing logs from buffer
+        // Readers never see logs that left the buffer without being in a file yet
+        let lock = self.lock.clone();
+        let flushing = lock.flush_guard();
+        let mut data = self.buffer.drain();
+        if data.is_empty() {
+            return 0;
+        }
+        if self.key.is_some() {
+            self.track_keys(&data);
+        }
+        self.restamp(&mut data);
+        // copied before payloads move to the blob store
+        let published = match self.subscribers.is_empty() {
+            true => None,
+            false => Some(Arc::<[LogEntry]>::from(data.clone())),
+        };
+        if let Some(blobs) = &self.blobs {
+            data.iter_mut().for_each(|entry| blobs.externalize(entry));
+        }
+
+        // write data to disk
+        let lsns = match (data.first(), data.last()) {
+            (Some(first), Some(last)) => Some(first.lsn()..=last.lsn()),
+            _ => None,
+        };
+        let entries = data.len() as u64;
+        // time range of the batch, from the timestamps of the logs when they are stamped
+        let now = SegmentSpan::millis(SystemTime::now());
+        let stamps = data.iter().filter_map(|e| e.timestamp());
+        let first = stamps.clone().min().unwrap_or(now);
+        let last = stamps.max().unwrap_or(now);
+        // frames are encoded in the buffer kept from the previous batch
+        let mut frames = std::mem::take(&mut self.frames);
+        let payloads = self.policy.encode(&mut data, self.encoding, &mut frames);
+        self.buffer.recycle(data, payloads);
+        let data = frames;
+        let _span = span!("walcraft.write", entries = entries, bytes = data.len());
+        let sampled = self.buffer.take_in_flight();
+        // recorded first, so other processes find every log of the files in the journal
+        if let Some(lsns) = lsns.clone() {
+            self.record_slot(lsns);
+        }
+        let written = self.write(&data, sampled, lsns.clone());
+        if written {
+            self.metrics.written(entries, data.len() as u64);
+            self.offset += data.len() as u64;
+            self.file_entries += entries;
+            let span = self.span.get_or_insert(SegmentSpan {
+                id: self.pointer,
+                first,
+                last,
+            });
+            span.first = span.first.min(first);
+            span.last = span.last.max(last);
+            if self.policy.positional_writes {
+                self.record_meta();
+            }
+            if let Some(published) = published {
+                self.subscribers.publish(published);
+            }
+        }
+        drop(flushing);
+        // listeners don't hold up reads
+        if written {
+            self.listeners.flush(&FlushEvent {
+                segment: self.pointer,
+                entries,
+                bytes: data.len() as u64,
+                lsns,
+            });
+        }
+
+        // handle file logic
+        self.filled += data.len();
+        // the current file outgrows its capacity while the writer can't move on
+        let held = self.policy.rotate(self.filled) && !self.next_file();
+        self.metrics
+            .room(self.policy.capacity_per_file.saturating_sub(self.filled) as u64);
+        self.check_usage();
+        invariant!(
+            held || !self.policy.rotate(self.filled),
+            "file {} is filled {} bytes beyond capacity of {} bytes",
+            self.pointer,
+            self.filled,
+            self.policy.capacity_per_file
+        );
+        invariant!(
+            self.layout.ids().contains(&self.pointer),
+            "file pointer {} is out of range",
+            self.pointer
+        );
+        invariant!(
+            self.file.size().ok() == Some(self.offset),
+            "file {} size doesn't match the write offset {}",
+            self.pointer,
+            self.offset
+        );
+        let bytes = data.len();
+        if data.capacity() <= KEPT_FRAMES_BYTES {
+            self.frames = data;
+        }
+        bytes
+    }
+
+    // Write a batch of frames to the current file, returning whether they were written
+    // The file is synced when the sync policy says so, and the logs `lsns` are acknowledged once
+    // durable, or reported as lost when the write fails
+    fn write(
+        &mut self,
+        frames: &[u8],
+        sampled: Vec<Instant>,
+        lsns: Option<RangeInclusive<Lsn>>,
+    ) -> bool {
+        // the previous batch is durable before the next one is written
+        if let Some(stage) = &mut self.sync_stage {
+            stage.wait();
+        }
+        self.slow_write(frames.len());
+        if let Err(e) = self.write_frames(frames) {
+            self.failures.io(WriteOperation::Write, &e, lsns);
+            return false;
+        }
+        self.unsynced += frames.len();
+        self.unsynced_samples.extend(sampled);
+        if let Some(lsns) = lsns {
+            self.acks.written(lsns);
+        }
+        if !self.policy.syncs() {
+            // logs are as durable as they get once written
+            self.acks.durable(self.acks.last_written());
+            stats::record_latency(&self.latency, &self.unsynced_samples);
+            self.unsynced_samples.clear();
+            self.unsynced = 0;
+        } else if self
+            .policy
+            .sync_due(self.unsynced, self.synced_at.elapsed())
+        {
+            self.sync();
+        }
+        true
+    }
+
+    // Sync the logs written to the current file since the last sync
+    // With the sync stage, the file is synced while the writer moves on to the next batch
+    fn sync(&mut self) {
+        let sampled = std::mem::take(&mut self.unsynced_samples);
+        let through = self.acks.last_written();
+        self.unsynced = 0;
+        self.synced_at = Instant::now();
+        if let Err(e) = self.inject_sync_failure() {
+            self.failures.io(WriteOperation::Sync, &e, None);
+            self.acks.lost(through);
+            return;
+        }
+        // only local files can be synced by another thread
+        let pipelined = self
+            .sync_stage
+            .as_ref()
+            .and(self.file.file().and_then(|file| file.try_clone().ok()));
+        match (&mut self.sync_stage, pipelined) {
+            (Some(stage), Some(file)) => stage.submit(file, sampled, through),
+            _ => match stats::timed_sync(&self.metrics.clone(), || self.sync_file()) {
+                Ok(()) => {
+                    stats::record_latency(&self.latency, &sampled);
+                    self.acks.durable(through);
+                }
+                Err(e) => {
+                    self.failures.io(WriteOperation::Sync, &e, None);
+                    self.acks.lost(through);
+                }
+            },
+        }
+    }
+
+    // Reserve the storage of the current file up to its capacity, keeping its size
+    // Best effort, as only some file systems can reserve space
+    fn reserve_file(&self) {
+        if !self.preallocate {
+            return;
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(file) = self.file.file() {
+            use std::os::unix::io::AsRawFd;
+            let len = self.policy.capacity_per_file as libc::off_t;
+            // SAFETY: the descriptor belongs to the current file, open for as long as the call
+            unsafe { libc::fallocate(file.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, 0, len) };
+        }
+    }
+
+    // Write frames to the current file, through io_uring when available
+    fn write_frames(&mut self, frames: &[u8]) -> std::io::Result<()> {
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if let (Some(ring), Some(file)) = (&mut self.ring, self.file.file()) {
+            let mut file = RingFile { ring, file };
+            return self.policy.write(&mut file, frames, self.offset);
+        }
+        #[cfg(test)]
+        if let Some(faults) = &self.faults {
+            let mut file = faults.wrap(&mut self.file);
+            return self.policy.write(&mut file, frames, self.offset);
+        }
+        self.policy.write(&mut self.file, frames, self.offset)
+    }
+
+    // Sync the current file, through io_uring when available
+    fn sync_file(&mut self) -> std::io::Result<()> {
+        #[cfg(all(feature = "io-uring", target_os = "linux"))]
+        if let (Some(ring), Some(file)) = (&mut self.ring, self.file.file()) {
+            return ring.sync(file);
+        }
+        #[cfg(test)]
+        if let Some(faults) = &self.faults {
+            return faults.wrap(&mut self.file).sync();
+        }
+        self.file.sync()
+    }
+
+    // Pause before a flush, when chaos tests ask for it
+    fn delay_flush(&mut self) {
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = &mut self.chaos {
+            chaos.delay_flush();
+        }
+    }
+
+    // Fail the sync about to happen, when chaos tests ask for it
+    fn inject_sync_failure(&mut self) -> std::io::Result<()> {
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = &mut self.chaos {
+            return chaos.sync();
+        }
+        Ok(())
+    }
+
+    // Pause as long as the slow disk of chaos tests takes to write `bytes`
+    #[cfg_attr(not(feature = "chaos"), allow(unused_variables))]
+    fn slow_write(&self, bytes: usize) {
+        #[cfg(feature = "chaos")]
+        if let Some(chaos) = &self.chaos {
+            chaos.write(bytes);
+        }
+    }
+
+    // Move on to the next file, returning whether the writer did
+    fn next_file(&mut self) -> bool {
+        let next_pointer = self.layout.next(self.pointer);
+        let _span = span!("walcraft.rotate", from = self.pointer, to = next_pointer);
+        // the next file is reused only once its logs are covered by a snapshot
+        if !self.checkpoint(&[next_pointer]) || !self.archive(&[next_pointer]) {
+            return false;
+        }
+        // logs of the file left behind are synced like any other
+        if self.unsynced > 0 {
+            self.sync();
+        }
+        if self.verify_on_rotation {
+            self.seal();
+        }
+        self.filter_keys();
+        self.index(self.pointer);
+        // the next file is about to be overwritten
+        self.digests.retain(|d| d.id != next_pointer);
+        self.spans.retain(|s| s.id != next_pointer);
+        self.spans.extend(self.span.take());
+        self.evict(next_pointer);
+        KeyFilter::remove(&self.location, next_pointer);
+        SegmentIndex::remove(&self.location, next_pointer);
+        if let Some(cold) = self.layout.cold_path(next_pointer) {
+            let _ = self.layout.storage.delete(&cold);
+        }
+        // what the file left behind holds, read only when someone listens
+        let sealed = match self.listeners.is_empty() {
+            true => (None, 0),
+            false => self.describe(self.pointer),
+        };
+        let bytes = self.offset;
+        let file_entries = self.file_entries;
+        let previous = self.pointer;
+        self.pointer = next_pointer;
+        self.offset = 0;
+        // Disk IO for the new pointer & file
+        // The next file is emptied before the meta file points to it. A crash leaves the meta
+        // file pointing to the previous file, with the next one either intact or empty, or points
+        // to the empty next file, so the WAL is consistent at every step.
+        let next_path = self.layout.hot_path(&self.location, next_pointer);
+        let file =
+            match Self::open_file(&self.layout.storage, &next_path, true).and_then(|mut file| {
+                let encoding;
+                (self.offset, encoding) = self
+                    .policy
+                    .start_file(&mut file)
+                    .map_err(|e| WalError::io(e, "Failed to write log file header"))?;
+                self.write_meta().map(|_| (file, encoding))
+            }) {
+                Ok((file, encoding)) => {
+                    self.encoding = encoding;
+                    file
+                }
+                Err(e) => {
+                    self.failures
+                        .other(WriteOperation::Rotate, format!("{:?}", e));
+                    self.pointer = previous;
+                    self.offset = Self::file_size(&self.file).unwrap_or_default();
+                    if self.spans.last().is_some_and(|s| s.id == previous) {
+                        self.span = self.spans.pop();
+                    }
+                    return false;
+                }
+            };
+        // update state
+        self.file = file;
+        let filled_in = self.opened_at.elapsed();
+        self.policy.adapt(self.filled, filled_in);
+        self.reserve_file();
+        self.opened_at = Instant::now();
+        self.filled = self.offset as usize;
+        self.file_entries = 0;
+        self.sketches[next_pointer as usize - 1] = KeySketch::new();
+        self.compact_sealed();
+        self.collect_blobs();
+        self.retain_sealed();
+        self.rotations
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .record(RotationRecord {
+                at: SystemTime::now(),
+                segment: previous,
+                entries: file_entries,
+                bytes,
+                since_previous: filled_in,
+            });
+        self.expire_sealed();
+        self.move_sealed();
+        self.sealed_bytes = None;
+        self.check_unconsumed();
+        self.metrics.rotated();
+        self.listeners.rotate(&RotationEvent {
+            from: previous,
+            to: next_pointer,
+            path: self.layout.path(&self.location, previous),
+            lsns: sealed.0,
+            entries: sealed.1,
+            bytes,
+        });
+        true
+    }
+
+    // sequence numbers of the first and the last log of the file `id`, and its number of logs
+    fn describe(&self, id: u8) -> (Option<RangeInclusive<Lsn>>, u64) {
+        let reader = self.reader();
+        let content = reader.raw(id).ok().flatten().unwrap_or_default();
+        let frames = reader.frames(&content);
+        let lsns = match (frames.first(), frames.last()) {
+            (Some((_, first)), Some((_, last))) => Some(first.lsn()..=last.lsn()),
+            _ => None,
+        };
+        (lsns, frames.len() as u64)
+    }
+
+    // Remove the sealed files beyond the number of files to retain, oldest first
+    fn retain_sealed(&mut self) {
+        let keep = match self.retain_segments {
+            Some(keep) => keep,
+            None => return,
+        };
+        // sealed files holding logs, newest first, with their last log
+        let reader = self.reader();
+        let sealed = self
+            .layout
+            .read_order(self.pointer)
+            .into_iter()
+            .skip(1)
+            .filter_map(|id| Some((id, *reader.lsn_range(id).ok()??.end())))
+            .collect::<Vec<_>>();
+        let through = match sealed.get(keep) {
+            Some((_, lsn)) => *lsn,
+            None => return,
+        };
+        let removed = sealed[keep..].iter().map(|(id, _)| *id).collect::<Vec<_>>();
+        if !self.checkpoint(&removed) || !self.archive(&removed) {
+            return;
+        }
+        for id in removed {
+            self.evict(id);
+        }
+        let _ = self.truncate(through);
+    }
+
+    // Remove the sealed files whose last log was written longer ago than the retention period,
+    // and schedule the next pass for when the oldest remaining file expires
+    fn expire_sealed(&mut self) {
+        let period = match self.retain_for {
+            Some(period) => period,
+            None => return,
+        };
+        self.expiry = None;
+        // sealed files holding logs, newest first, with their last log and when it was written
+        let reader = self.reader();
+        let sealed = self
+            .layout
+            .read_order(self.pointer)
+            .into_iter()
+            .skip(1)
+            .filter_map(|id| {
+                let lsn = *reader.lsn_range(id).ok()??.end();
+                Some((id, lsn, self.last_written(id)?))
+            })
+            .collect::<Vec<_>>();
+        let now = SystemTime::now();
+        let expired = sealed
+            .iter()
+            .position(|(_, _, at)| *at + period <= now)
+            .unwrap_or(sealed.len());
+        if let Some((_, _, at)) = sealed[..expired].last() {
+            let left = (*at + period).duration_since(now).unwrap_or_default();
+            self.expiry = Some(Instant::now() + left);
+        }
+        let through = match sealed.get(expired) {
+            Some((_, lsn, _)) => *lsn,
+            None => return,
+        };
+        // files are sealed in turn, so every file older than an expired one expired too, and
+        // they are evicted oldest first
+        let removed = sealed[expired..]
+            .iter()
+            .rev()
+            .map(|(id, ..)| *id)
+            .collect::<Vec<_>>();
+        if !self.checkpoint(&removed) || !self.archive(&removed) {
+            return;
+        }
+        for id in removed {
+            self.evict(id);
+        }
+        let _ = self.truncate(through);
+    }
+
+    // Stamp every log of `data` no earlier than the log before it, and with a monotonic time
+    // strictly after it, when stamps are kept in order
+    fn restamp(&mut self, data: &mut [LogEntry]) {
+        let (last, monotonic) = match (&mut self.last_stamp, &mut self.monotonic) {
+            (Some(last), Some(monotonic)) => (last, monotonic),
+            _ => return,
+        };
+        for entry in data.iter_mut() {
+            if let Some(at) = entry.timestamp() {
+                *last = at.max(*last);
+                entry.set_timestamp(Some(*last));
+                entry.set_monotonic(Some(monotonic.read()));
+            }
+        }
+    }
+
+    // Monotonic time of the newest log of the WAL, 0 if the newest file holding logs has none
+    fn newest_monotonic(&self) -> u64 {
+        let reader = self.reader();
+        for id in reader.read_order(self.pointer) {
+            let logs = reader.read_files(|i| i == id).unwrap_or_default();
+            if !logs.is_empty() {
+                return logs
+                    .iter()
+                    .filter_map(|e| e.monotonic())
+                    .max()
+                    .unwrap_or_default();
+            }
+        }
+        0
+    }
+
+    // Time range of the logs of the file written again, from their timestamps when logs are
+    // stamped, or else from when the file was created
+    fn resume_span(&self, timestamps: bool) -> Option<SegmentSpan> {
+        let path = self.layout.hot_path(&self.location, self.pointer);
+        if !format::holds_frames(&self.layout.storage, &path) {
+            return None;
+        }
+        let stamped = match timestamps {
+            true => self.reader().read_files(|id| id == self.pointer).ok()?,
+            false => Vec::new(),
+        };
+        let stamps = stamped.iter().filter_map(|e| e.timestamp());
+        let (first, last) = match (stamps.clone().min(), stamps.max()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => {
+                let (created, modified) = self.layout.storage.times(&path);
+                let at = created.or(modified)?;
+                (SegmentSpan::millis(at), SegmentSpan::millis(at))
+            }
+        };
+        Some(SegmentSpan {
+            id: self.pointer,
+            first,
+            last,
         })
     }
 
-    pub fn run(mut self) {
-        loop {
-            // spin lock, until allowed to write
-            if !self.lock.can_write() {
-                sleep(Duration::from_millis(1));
-                continue;
+    // When the last log of the sealed file `id` was written, as recorded when it was sealed or
+    // else when the file was last modified
+    fn last_written(&self, id: u8) -> Option<SystemTime> {
+        match self.spans.iter().find(|s| s.id == id) {
+            Some(span) => Some(span.last_written()),
+            None => {
+                let path = self.layout.path(&self.location, id);
+                self.layout.storage.times(&path).1
+            }
+        }
+    }
+
+    // Move the sealed files older than the newest `after` ones to the cold location
+    fn move_sealed(&self) {
+        let tier = match &self.layout.cold {
+            Some(tier) => tier,
+            None => return,
+        };
+        let moved = self
+            .layout
+            .read_order(self.pointer)
+            .into_iter()
+            .skip(tier.after + 1)
+            .filter(|id| {
+                let hot = self.layout.hot_path(&self.location, *id);
+                self.layout.storage.exists(&hot)
+            });
+        let storage = &self.layout.storage;
+        for id in moved {
+            let hot = self.layout.hot_path(&self.location, id);
+            let cold = self.layout.hot_path(&tier.location, id);
+            // a custom storage has no directories to create
+            let created = match storage.is_local() {
+                true => std::fs::create_dir_all(&tier.location),
+                false => Ok(()),
+            };
+            if let Err(e) = created.and_then(|_| Self::relocate(storage, &self.lock, &hot, &cold)) {
+                // the file is still read where it is, and moved along with the next file
+                self.failures.io(WriteOperation::Move, &e, None);
+                return;
+            }
+        }
+    }
+
+    // Report the thresholds of the capacity crossed by the logs stored in the WAL files
+    fn check_usage(&mut self) {
+        if self.capacity_monitor.is_none() {
+            return;
+        }
+        let sealed = match self.sealed_bytes {
+            Some(bytes) => bytes,
+            None => {
+                let bytes = self
+                    .layout
+                    .ids()
+                    .filter(|id| *id != self.pointer)
+                    .filter_map(|id| {
+                        let path = self.layout.path(&self.location, id);
+                        self.layout.storage.size(&path)
+                    })
+                    .sum();
+                self.sealed_bytes = Some(bytes);
+                bytes
+            }
+        };
+        let capacity = self.policy.capacity_per_file as u64 * (self.layout.segments as u64 - 1);
+        if let Some(monitor) = &mut self.capacity_monitor {
+            monitor.usage(sealed + self.offset, capacity);
+        }
+    }
+
+    // Report logs not yet consumed in the file reused once the current one is filled
+    fn check_unconsumed(&self) {
+        let monitor = match &self.capacity_monitor {
+            Some(monitor) => monitor,
+            None => return,
+        };
+        let consumer = match self.consumers.slowest() {
+            Some(lsn) => lsn,
+            None => return,
+        };
+        let id = self.layout.next(self.pointer);
+        if let Ok(Some(lsns)) = self.reader().lsn_range(id) {
+            if consumer <= *lsns.end() {
+                monitor.unconsumed(id, lsns, consumer);
             }
+        }
+    }
+
+    // Remove blobs no longer referenced once the next file was cleared
+    fn collect_blobs(&self) {
+        let blobs = match &self.blobs {
+            Some(blobs) => blobs,
+            None => return,
+        };
+        let reader = self.reader();
+        let mut referenced = HashSet::new();
+        for id in self.layout.ids() {
+            let content = self.layout.storage.read(&reader.segment_path(id));
+            let content = content.ok().flatten().unwrap_or_default();
+            referenced.extend(reader.blob_references(&content));
+        }
+        // readers never miss the blob of a log they read
+        let lock = self.lock.clone();
+        let _flushing = lock.flush_guard();
+        blobs.collect(&referenced);
+    }
 
-            // Wait for the notification of new logs
-            let _d = self.receiver.recv();
+    // Record the keys of the current file in keyed mode, so lookups by key can skip it
+    fn filter_keys(&self) {
+        let key = match &self.key {
+            Some(key) => key,
+            None => return,
+        };
+        let reader = self.reader();
+        // lookups read a file without a filter
+        let _ = KeyFilter::build(&reader, key, self.pointer).store(&self.location, self.pointer);
+    }
 
-            // take all existing logs from buffer
-            let data = self.buffer.drain();
-            if data.is_empty() {
+    // Index the logs of the file `id` when enabled, so reads can skip to the logs they look for
+    fn index(&self, id: u8) {
+        if !self.segment_index {
+            return;
+        }
+        // reads ignore a missing index, and one that no longer matches its file
+        if let Some(index) = SegmentIndex::build(&self.reader(), id) {
+            let _ = index.store(&self.location, id);
+        }
+    }
+
+    // Index every sealed file without an index matching its content, returning how many were
+    fn index_all(&mut self) -> Result<usize, WalError> {
+        let reader = self.reader();
+        let mut indexed = 0;
+        for id in self.layout.ids().filter(|id| *id != self.pointer) {
+            let path = self.layout.path(&self.location, id);
+            let size = match self.layout.storage.size(&path) {
+                Some(size) => size,
+                None => continue,
+            };
+            let current = SegmentIndex::load(&self.location, id);
+            if current.is_some_and(|index| index.size == size) {
                 continue;
             }
-            dbg!(data.len());
+            if let Some(index) = SegmentIndex::build(&reader, id) {
+                index
+                    .store(&self.location, id)
+                    .map_err(|e| WalError::io(e, "Failed to write segment index"))?;
+                indexed += 1;
+            }
+        }
+        Ok(indexed)
+    }
 
-            // write data to disk
-            let data = data
-                .into_iter()
-                .flat_map(|d| d.to_vec())
-                .collect::<Vec<_>>();
-            let _ = self.file.write_all(&data);
-            // let _ = self.file.sync_all(); // disabling 'fsync' feature
+    // Count the logs superseded by newly written keys
+    fn track_keys(&mut self, data: &[LogEntry]) {
+        for key in data.iter().filter_map(|e| e.key()) {
+            for sketch in self.sketches.iter_mut() {
+                sketch.supersede(key);
+            }
+            self.sketches[self.pointer as usize - 1].insert(key);
+        }
+    }
 
-            // handle file logic
-            self.filled += data.len();
-            if self.filled >= self.capacity_per_file {
-                self.next_file();
+    // Compact sealed files whose estimated share of dead logs exceeds the threshold
+    fn compact_sealed(&mut self) {
+        let threshold = match (&self.key, self.compaction_threshold) {
+            (Some(_), Some(threshold)) => threshold,
+            _ => return,
+        };
+        let order = self.layout.read_order(self.pointer);
+        let mut compacted = false;
+        for i in 1..order.len() {
+            if self.sketches[order[i] as usize - 1].dead_ratio() <= threshold {
+                continue;
             }
+            compacted |= self
+                .compact_file(&order, i)
+                .is_ok_and(|removed| removed > 0);
+        }
+        if compacted {
+            self.record_meta();
+        }
+    }
+
+    // Compact all sealed files, returning the number of removed logs
+    fn compact_all(&mut self) -> Result<u64, WalError> {
+        if self.key.is_none() {
+            return Ok(0);
+        }
+        let order = self.layout.read_order(self.pointer);
+        let mut removed = 0;
+        for i in 1..order.len() {
+            removed += self.compact_file(&order, i)?;
+        }
+        if removed > 0 {
+            self.write_meta()?;
+        }
+        Ok(removed)
+    }
+
+    // Compact the file `order[i]` against the files written after it
+    // The digest of a compacted file is refreshed, meta file is left to the caller
+    fn compact_file(&mut self, order: &[u8], i: usize) -> Result<u64, WalError> {
+        let key = match &self.key {
+            Some(key) => key.clone(),
+            None => return Ok(0),
+        };
+        let reader = self.reader();
+        let id = order[i];
+        let newer = &order[..i];
+        let removed = compaction::compact_segment(&reader, &key, self.merge.as_ref(), id, newer)?;
+        self.compacted(id, removed);
+        Ok(removed)
+    }
 
-            // signal LockManager of parking
-            if !self.lock.can_write() {
-                self.lock.stop();
-                std::thread::park();
+    // Drop the logs rejected by `retain` from all sealed files, returning the number of removed
+    // logs
+    fn retain_all(&mut self, retain: &RetainFn) -> Result<u64, WalError> {
+        let reader = self.reader();
+        let mut removed = 0;
+        for id in self.layout.read_order(self.pointer).into_iter().skip(1) {
+            let dropped = compaction::retain_segment(&reader, retain, id)?;
+            self.compacted(id, dropped);
+            removed += dropped;
+        }
+        if removed > 0 {
+            self.write_meta()?;
+        }
+        Ok(removed)
+    }
+
+    // `removed` logs were dropped from the sealed file `id`, whose index and digest are refreshed
+    fn compacted(&mut self, id: u8, removed: u64) {
+        self.sketches[id as usize - 1].compacted(removed);
+        if removed == 0 {
+            return;
+        }
+        self.index(id);
+        // the digest of the file no longer matches its content
+        if let Some(i) = self.digests.iter().position(|d| d.id == id) {
+            // the link is kept, so the chain reports the file as changed since it was sealed
+            let mut digest = self.digest(id, None);
+            digest.chain = self.digests.remove(i).chain;
+            self.digests.push(digest);
+        }
+    }
+
+    // Cut the bytes past the last valid frame off the sealed files, then write the meta file
+    // again, returning the ids of the files cut
+    // The current file was cut when the writer started. The digest of a cut file is refreshed,
+    // while files corrupted otherwise keep their digest, so they are still reported.
+    fn repair(&mut self) -> Result<Vec<u8>, WalError> {
+        let reader = self.reader();
+        let mut repaired = Vec::new();
+        // readers see the files either before or after they are cut
+        let lock = self.lock.clone();
+        let flushing = lock.flush_guard();
+        for id in self.layout.ids().filter(|id| *id != self.pointer) {
+            let path = self.layout.path(&self.location, id);
+            let content = match self.layout.storage.read(&path) {
+                Ok(Some(content)) => content,
+                _ => continue,
+            };
+            let valid = reader.valid_len(&content);
+            if valid == content.len() {
+                continue;
             }
+            self.layout
+                .storage
+                .cut(&path, valid as u64)
+                .map_err(|e| WalError::io_at(e, "Failed to cut log file", &path))?;
+            self.index(id);
+            if let Some(i) = self.digests.iter().position(|d| d.id == id) {
+                let mut digest = self.digest(id, None);
+                digest.chain = self.digests.remove(i).chain;
+                self.digests.push(digest);
+            }
+            repaired.push(id);
         }
+        drop(flushing);
+        self.write_meta()?;
+        Ok(repaired)
     }
 
-    fn next_file(&mut self) {
-        // calculate next pointer
-        let mut next_pointer = self.pointer + 1;
-        if next_pointer > 5 {
-            next_pointer = 1;
+    // Remove the oldest files holding only logs up to `through`, see [Truncation], then reclaim
+    // the logs up to `through` at the start of the oldest file left
+    fn truncate(&mut self, through: Lsn) -> Result<usize, WalError> {
+        let truncation = Truncation::plan(&self.reader(), self.pointer, through)?;
+        if !truncation.ids.is_empty() {
+            // readers see the files either before or after the truncation
+            let lock = self.lock.clone();
+            let _flushing = lock.flush_guard();
+            truncation.record(&self.location)?;
+            truncation.remove_files(&self.location, &self.layout)?;
+            self.digests.retain(|d| !truncation.ids.contains(&d.id));
+            self.spans.retain(|s| !truncation.ids.contains(&s.id));
+            for id in &truncation.ids {
+                self.sketches[*id as usize - 1] = KeySketch::new();
+            }
+            self.write_meta()?;
+            Truncation::clear(&self.location)?;
         }
-        // Disk IO for the new pointer & file
-        let file = match Self::set_pointer(self.location.clone(), next_pointer, true) {
-            Ok((file, _)) => file,
-            Err(_) => {
-                return;
+        self.reclaim_head(through)?;
+        Ok(truncation.ids.len())
+    }
+
+    // Reclaim the logs up to `through` at the start of the oldest sealed file, see
+    // [truncation::reclaim_head]
+    fn reclaim_head(&mut self, through: Lsn) -> Result<(), WalError> {
+        let reader = self.reader();
+        let mut oldest = None;
+        for id in reader.read_order(self.pointer).into_iter().skip(1).rev() {
+            if let Some(range) = reader.lsn_range(id)? {
+                oldest = Some((id, range));
+                break;
             }
+        }
+        let id = match oldest {
+            Some((id, range)) if *range.start() <= through => id,
+            _ => return Ok(()),
         };
-        // update state
+        // the file changes in place, so no read may be in progress
+        // The writer never waits for reads, as a read may be waiting for the writer, e.g. to
+        // truncate from the filter of [crate::Wal::read_filtered]. The logs are reclaimed by a
+        // later truncation instead.
+        let read_lock = self.read_lock.clone();
+        let _reading = match read_lock.try_lock() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(e)) => e.into_inner(),
+            Err(TryLockError::WouldBlock) => return Ok(()),
+        };
+        let lock = self.lock.clone();
+        let _flushing = lock.flush_guard();
+        let removed = truncation::reclaim_head(&reader, id, through)?;
+        if removed == 0 {
+            return Ok(());
+        }
+        SegmentIndex::remove(&self.location, id);
+        self.compacted(id, removed);
+        self.write_meta()
+    }
+
+    // Remove all files, logs of the buffer included, and start over from the first file
+    // Like a truncation of every file, so a crash at any point completes the removal when the
+    // WAL is opened again
+    fn clear(&mut self) -> Result<(), WalError> {
+        self.flush()?;
+        let ids = self
+            .layout
+            .ids()
+            .filter(|id| {
+                let path = self.layout.path(&self.location, *id);
+                self.layout.storage.exists(&path)
+            })
+            .collect();
+        let truncation = Truncation {
+            through: self.buffer.next_lsn().saturating_sub(1),
+            ids,
+        };
+        let lock = self.lock.clone();
+        let flushing = lock.flush_guard();
+        truncation.record(&self.location)?;
+        truncation.remove_files(&self.location, &self.layout)?;
+        let pointer = 1u8;
+        let path = self.layout.hot_path(&self.location, pointer);
+        let mut file = Self::open_file(&self.layout.storage, &path, true)?;
+        (self.offset, self.encoding) = self
+            .policy
+            .start_file(&mut file)
+            .map_err(|e| WalError::io(e, "Failed to write log file header"))?;
         self.file = file;
-        self.pointer = next_pointer;
-        self.filled = 0;
+        self.reserve_file();
+        self.pointer = pointer;
+        self.filled = self.offset as usize;
+        self.file_entries = 0;
+        self.opened_at = Instant::now();
+        self.digests.clear();
+        self.spans.clear();
+        self.span = None;
+        self.chain = chain::GENESIS;
+        self.sketches.iter_mut().for_each(|s| *s = KeySketch::new());
+        self.write_meta()?;
+        Truncation::clear(&self.location)?;
+        drop(flushing);
+        self.collect_blobs();
+        Ok(())
     }
 
-    fn set_pointer(
-        location: PathBuf,
-        pointer: u8,
-        delete: bool,
-    ) -> Result<(File, usize), WalError> {
-        // write pointer to meta file
-        Self::write_pointer(location.clone(), pointer)?;
-        // open and return pointer WAL file
-        Self::open_file(location, pointer, delete).map(|file| (file, 0))
-    }
-
-    fn write_pointer(mut location: PathBuf, pointer: u8) -> Result<(), WalError> {
-        location.push("meta");
-        // create a new file for writing logs
-        let mut file = match File::create(location) {
-            Ok(f) => f,
-            Err(_) => {
-                return Err(WalError::File("Failed to create pointer file".to_string()));
-            }
-        };
-        // write current pointer
-        let text = pointer.to_string();
-        if let Err(_) = file.write_all(text.as_bytes()) {
-            return Err(WalError::File(
-                "Failed to write to pointer file".to_string(),
-            ));
+    // Save a snapshot covering the logs of the files `ids` before they're dropped, returning
+    // whether the files can go
+    fn checkpoint(&self, ids: &[u8]) -> bool {
+        let checkpointer = match &self.checkpointer {
+            Some(c) => c,
+            None => return true,
+        };
+        let reader = self.reader();
+        let lsns = ids
+            .iter()
+            .filter_map(|id| reader.lsn_range(*id).ok().flatten())
+            .reduce(|a, b| *a.start().min(b.start())..=*a.end().max(b.end()));
+        match lsns {
+            Some(lsns) => checkpointer.checkpoint(lsns).is_ok(),
+            // nothing to lose from new or empty files
+            None => true,
         }
-        Ok(())
     }
 
-    fn open_file(mut location: PathBuf, pointer: u8, delete: bool) -> Result<File, WalError> {
-        let file_name = format!("wal_{}", pointer);
-        location.push(file_name);
-        if delete {
-            if let Err(_) = File::create(location.clone()) {
-                return Err(WalError::File("Failed to clear old log file".to_string()));
+    // Hand the files `ids` holding logs to the archiver before they're dropped, returning whether
+    // the files can go
+    fn archive(&self, ids: &[u8]) -> bool {
+        let archiver = match &self.archiver {
+            Some(a) => a,
+            None => return true,
+        };
+        let reader = self.reader();
+        for &id in ids {
+            let path = self.layout.path(&self.location, id);
+            let lsns = match reader.lsn_range(id) {
+                Ok(Some(lsns)) => lsns,
+                // nothing to lose from new or empty files
+                _ => continue,
+            };
+            let segment = ArchivedSegment {
+                id,
+                bytes: self.layout.storage.size(&path).unwrap_or_default(),
+                path,
+                lsns,
+                storage: self.layout.storage.clone(),
+            };
+            if let Err(e) = archiver.archive(&segment) {
+                self.failures.io(WriteOperation::Archive, &e, None);
+                return false;
             }
         }
-        OpenOptions::new()
-            .append(true)
+        true
+    }
+
+    // Report the logs of a file that is about to be overwritten
+    fn evict(&self, id: u8) {
+        let on_evict = match &self.on_evict {
+            Some(f) => f,
+            None => return,
+        };
+        let path = self.layout.path(&self.location, id);
+        let storage = &self.layout.storage;
+        let bytes = match storage.size(&path) {
+            Some(bytes) if format::holds_frames(storage, &path) => bytes,
+            // nothing to evict from a new or empty file
+            _ => return,
+        };
+        let (first_written, last_written) = storage.times(&path);
+        let reader = self.reader();
+        on_evict(&Eviction {
+            id,
+            lsns: reader.lsn_range(id).ok().flatten(),
+            bytes,
+            first_written,
+            last_written,
+        });
+    }
+
+    // Re-read the current file before moving on, recording its digest and whether it contains
+    // exactly what was written, and link it to the file sealed before
+    fn seal(&mut self) {
+        let mut digest = self.digest(self.pointer, Some(self.offset));
+        let previous = self.chain;
+        if let Some(sha256) = &digest.sha256 {
+            self.chain = chain::link(&previous, sha256);
+            digest.chain = Some(self.chain);
+        }
+        self.digests.push(digest.clone());
+        if let Some(on_seal) = &self.on_seal {
+            on_seal(&SegmentSealed { digest, previous });
+        }
+    }
+
+    // Digest of the WAL file `id`, verified to hold only complete frames and `written` bytes
+    fn digest(&self, id: u8, written: Option<u64>) -> SegmentDigest {
+        let path = self.layout.path(&self.location, id);
+        let content = self.layout.storage.read(&path).ok().flatten();
+        let content = content.unwrap_or_default();
+        let reader = self.reader();
+        let (entries, consumed) = reader.scan(&content);
+        let verified =
+            written.is_none_or(|w| content.len() as u64 == w) && consumed == content.len();
+        SegmentDigest {
+            id,
+            crc32: checksum::crc32(&content),
+            size: content.len() as u64,
+            entries: entries as u64,
+            verified,
+            sha256: Some(checksum::sha256(&content)),
+            chain: None,
+        }
+    }
+
+    // Append the batch `lsns` to the journal of the slots of a WAL written by several processes
+    fn record_slot(&self, lsns: RangeInclusive<Lsn>) {
+        let Some(slots) = &self.slots else {
+            return;
+        };
+        let oldest = |dir: &Path| {
+            let first = self.reader_at(dir.to_path_buf()).read_first();
+            first.ok().flatten().map(|entry| entry.lsn())
+        };
+        if let Err(e) = slots.record(lsns, oldest) {
+            self.failures
+                .other(WriteOperation::Meta, format!("{:?}", e));
+        }
+    }
+
+    // Reader of the WAL files written by this writer
+    fn reader(&self) -> WalReader {
+        self.reader_at(self.location.clone())
+    }
+
+    // Reader of the WAL files at `location`, written with the options of this writer
+    fn reader_at(&self, location: PathBuf) -> WalReader {
+        WalReader::new(location)
+            .layout(self.layout.clone())
+            .fixed(self.policy.record_size)
+            .framing(self.policy.framing)
+            .checksums(self.policy.checksums)
+            .max_expansion(self.policy.max_expansion)
+            .cipher(self.policy.cipher.clone())
+    }
+
+    // Record current pointer, write offset, number of files and digests of sealed files in meta
+    // file
+    fn write_meta(&mut self) -> Result<(), WalError> {
+        let meta = Meta {
+            pointer: self.pointer,
+            offset: self.policy.positional_writes.then_some(self.offset),
+            segments: Some(self.layout.segments),
+            format: Some(FormatVersion::CURRENT),
+            digests: self.digests.clone(),
+            spans: self.spans.clone(),
+            chain: Some(self.chain).filter(|c| *c != chain::GENESIS),
+        };
+        self.manifest.store(&meta)
+    }
+
+    // Record meta like `write_meta`, reporting a failure instead of returning it
+    fn record_meta(&mut self) {
+        if let Err(e) = self.write_meta() {
+            self.failures
+                .other(WriteOperation::Meta, format!("{:?}", e));
+        }
+    }
+
+    // Open the WAL file for positional writes and resume from the offset recorded in meta file
+    // An error is returned when the size of file doesn't match the recorded offset
+    fn resume_at_offset(
+        path: &Path,
+        pointer: u8,
+        recorded: Option<&Meta>,
+    ) -> Result<(Segment, u64), WalError> {
+        let file = OpenOptions::new()
+            .write(true)
             .create(true)
-            .open(&location)
-            .map_err(|_| WalError::File("Failed to open log file".to_string()))
+            .truncate(false)
+            .open(path)
+            .map(Segment::Local)
+            .map_err(|e| WalError::io_at(e, "Failed to open log file", path))?;
+        let size = Self::file_size(&file)?;
+        let offset = match recorded {
+            Some(Meta {
+                pointer: p,
+                offset: Some(offset),
+                ..
+            }) if *p == pointer => {
+                if *offset != size {
+                    return Err(WalError::ExternalModification {
+                        path: path.to_path_buf(),
+                        recorded: *offset,
+                        size,
+                    });
+                }
+                *offset
+            }
+            // no offset was recorded for this file, so trust its size
+            _ => size,
+        };
+        Ok((file, offset))
+    }
+
+    // Whether the frames of the file at `path` carry a checksum, from its header
+    // Files without a header were written with the current setting, `checksums`
+    fn checksummed(storage: &SharedStorage, path: &Path, checksums: bool) -> bool {
+        match storage.read_head(path, SEGMENT_HEADER_BYTES as u64) {
+            Ok(Some(header)) if FormatVersion::from_header(&header).is_some() => {
+                format::checksummed(&header)
+            }
+            _ => checksums,
+        }
+    }
+
+    // Cut the frame a crash left partly written off the end of the file
+    // With positional writes, bytes past the recorded offset are reported instead
+    // Readers stop at the first partial frame, so logs appended after it would never be read
+    fn trim_torn_tail(
+        storage: &SharedStorage,
+        path: &Path,
+        reader: &WalReader,
+    ) -> Result<(), WalError> {
+        let content = match storage.read(path) {
+            Ok(Some(content)) => content,
+            _ => return Ok(()),
+        };
+        let valid = reader.valid_len(&content);
+        if valid == content.len() {
+            return Ok(());
+        }
+        storage
+            .cut(path, valid as u64)
+            .map_err(|e| WalError::io(e, "Failed to truncate torn log file"))
+    }
+
+    // Rewrite the file with the content `rewrite` returns for it, if any, such as its frames in
+    // little endian when they were written in another byte order, returning its size before and
+    // after
+    fn rewrite_file(
+        storage: &SharedStorage,
+        path: &Path,
+        reader: &WalReader,
+        rewrite: Rewrite,
+        failure: &str,
+    ) -> Result<Option<(u64, u64)>, WalError> {
+        let content = match storage.read(path) {
+            Ok(Some(content)) => content,
+            _ => return Ok(None),
+        };
+        let converted = match rewrite(reader, &content) {
+            Some(converted) => converted,
+            None => return Ok(None),
+        };
+        // the converted file replaces the old one at once, never leaving a partly converted file
+        storage
+            .replace(path, &converted, "convert")
+            .map_err(|e| WalError::io(e, failure))?;
+        Ok(Some((content.len() as u64, converted.len() as u64)))
+    }
+
+    // Move the file `from` to `to`, copying it when they're on different disks
+    // Readers find the file at either place at any time, as the copy is complete before the
+    // original is removed, and both steps are taken while no snapshot is being taken
+    fn relocate(
+        storage: &SharedStorage,
+        lock: &LockManager,
+        from: &Path,
+        to: &Path,
+    ) -> std::io::Result<()> {
+        if let SharedStorage::Custom(storage) = storage {
+            let content = storage.read(from)?.unwrap_or_default();
+            storage.replace(to, &content)?;
+            let _flushing = lock.flush_guard();
+            return storage.delete(from);
+        }
+        {
+            let _flushing = lock.flush_guard();
+            if std::fs::rename(from, to).is_ok() {
+                durable::sync_parent(from)?;
+                return durable::sync_parent(to);
+            }
+        }
+        let tmp = to.with_extension("tmp");
+        std::fs::copy(from, &tmp)?;
+        File::open(&tmp)?.sync_all()?;
+        let _flushing = lock.flush_guard();
+        durable::replace(&tmp, to)?;
+        std::fs::remove_file(from)?;
+        durable::sync_parent(from)
+    }
+
+    fn file_size(file: &Segment) -> Result<u64, WalError> {
+        file.size()
+            .map_err(|e| WalError::io(e, "Failed to read log file metadata"))
+    }
+
+    fn open_file(storage: &SharedStorage, path: &Path, delete: bool) -> Result<Segment, WalError> {
+        // the old file is replaced by an empty one at once, never leaving a partly cleared file
+        storage
+            .open_segment(path, delete, "recycle")
+            .map_err(|e| WalError::io(e, "Failed to open log file"))
     }
 }